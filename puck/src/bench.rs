@@ -0,0 +1,133 @@
+// runs the profiled label several times from the same starting state, like `cargo bench`, and
+// compares the estimated cycles with a baseline saved by an earlier run. Estimates don't depend on
+// the host, so every run should agree and any change from the baseline is a change to the guest.
+//
+// Baselines are the profiler's, see `Profiler::save_baseline`, and are also checked after a normal
+// profiled run.
+
+use std::fs;
+
+use anyhow::{bail, ensure, Context, Result};
+use remu::system::{BaselineDiff, Emulator};
+
+// the functions with the largest changes shown from a baseline
+const SHOWN_FUNCTIONS: usize = 8;
+
+pub struct BaselineOptions<'a> {
+    /// Compared with, and failed if the cycles regressed by more than `threshold` percent
+    pub compare: Option<&'a str>,
+    pub save: Option<&'a str>,
+    pub threshold: f64,
+}
+
+pub struct BenchOptions<'a> {
+    pub label: &'a str,
+    pub runs: usize,
+    pub jit: bool,
+    pub baseline: BaselineOptions<'a>,
+}
+
+pub fn run(emulator: Emulator, options: &BenchOptions) -> Result<()> {
+    let label = options.label;
+
+    let mut cycles = Vec::with_capacity(options.runs);
+    let mut last = None;
+    for _ in 0..options.runs.max(1) {
+        let mut run = emulator.clone();
+        run.run(options.jit)?;
+        cycles.push(run.profiler.cycle_count);
+        last = Some(run);
+    }
+    cycles.sort_unstable();
+
+    let mean = cycles.iter().sum::<u64>() as f64 / cycles.len() as f64;
+    let variance = cycles
+        .iter()
+        .map(|&count| (count as f64 - mean).powi(2))
+        .sum::<f64>()
+        / cycles.len() as f64;
+    let (min, median) = (cycles[0], cycles[cycles.len() / 2]);
+
+    println!(
+        "{label:24}cycles: [{min} {median} {}]",
+        cycles[cycles.len() - 1]
+    );
+    println!("{:24}variance: {variance}", "");
+    ensure!(
+        variance == 0.0,
+        "the estimates of {label} differ between runs: {cycles:?}"
+    );
+
+    // every run agrees, so any of them is the baseline
+    check_baseline(&last.expect("there is at least one run"), &options.baseline)
+}
+
+// compares the profile of a finished run with a baseline and saves it as one, failing after
+// saving if the cycles regressed
+pub fn check_baseline(emulator: &Emulator, options: &BaselineOptions) -> Result<()> {
+    let disassembler = &emulator.memory.disassembler;
+
+    let diff = match options.compare {
+        Some(path) => {
+            let text =
+                fs::read_to_string(path).with_context(|| format!("could not read {path}"))?;
+            let diff = emulator
+                .profiler
+                .compare_baseline(disassembler, &text)
+                .with_context(|| format!("could not read {path}"))?;
+            print_diff(&diff, options.threshold);
+            Some(diff)
+        }
+        None => None,
+    };
+
+    if let Some(path) = options.save {
+        let mut out = String::new();
+        emulator.profiler.save_baseline(disassembler, &mut out)?;
+        fs::write(path, out).with_context(|| format!("could not write {path}"))?;
+    }
+
+    match diff {
+        Some(diff) if diff.regressed(options.threshold) => bail!(
+            "the estimated cycles regressed by {:.2}%, more than the {}% threshold",
+            diff.cycles.percent(),
+            options.threshold
+        ),
+        _ => Ok(()),
+    }
+}
+
+fn print_diff(diff: &BaselineDiff, threshold: f64) {
+    eprintln!(
+        "{:24}change: [{:+.2}%] from {} cycles, {:+} cache misses",
+        "",
+        diff.cycles.percent(),
+        diff.cycles.baseline,
+        diff.cache_misses.change()
+    );
+
+    let change = diff.cycles.percent();
+    if change > threshold {
+        eprintln!("{:24}Performance has regressed.", "");
+    } else if change < -threshold {
+        eprintln!("{:24}Performance has improved.", "");
+    } else {
+        eprintln!("{:24}No change in performance detected.", "");
+    }
+
+    let changed = diff
+        .functions
+        .iter()
+        .filter(|function| function.cycles.change() != 0 || function.cache_misses.change() != 0)
+        .take(SHOWN_FUNCTIONS);
+    for function in changed {
+        eprintln!(
+            "{:24}{:+} cycles ({:+.2}%), {:+} cache misses in {}",
+            "",
+            function.cycles.change(),
+            function.cycles.percent(),
+            function.cache_misses.change(),
+            function.name
+        );
+    }
+}
@@ -0,0 +1,105 @@
+// the parts of the debugger's commands shared by the TUI and scripts
+
+use anyhow::Result;
+use remu::{assembler, expr, system::Emulator, time_travel::TimeTravel};
+
+/// Where `:n` stops, set with `:bp`
+pub enum Breakpoint {
+    None,
+    Syscall,
+    Symbol(String),
+    Address(u64),
+    /// The next call into a loaded object, by the start of its name like `libm`
+    Object(String),
+}
+
+impl Breakpoint {
+    /// Parses the argument of `:bp`, which is `syscall`, `obj:` and the name of a loaded object,
+    /// a hex address or a symbol. Without one the breakpoint is cleared.
+    pub fn parse(arg: Option<&str>) -> Breakpoint {
+        match arg {
+            Some("syscall") => Breakpoint::Syscall,
+            Some(arg) if arg.starts_with("obj:") => Breakpoint::Object(arg[4..].to_string()),
+            Some(arg) => match u64::from_str_radix(arg, 16) {
+                Ok(addr) => Breakpoint::Address(addr),
+                Err(_) => Breakpoint::Symbol(arg.to_string()),
+            },
+            None => Breakpoint::None,
+        }
+    }
+
+    /// Runs until the breakpoint is hit. Returns the exit code if the program exited first.
+    pub fn run(&self, time_travel: &mut TimeTravel) -> Option<u64> {
+        match self {
+            Breakpoint::None => time_travel.run(),
+            Breakpoint::Syscall => time_travel.run_to_syscall(),
+            Breakpoint::Symbol(symbol) => {
+                let disassembler = &time_travel.current.memory.disassembler;
+
+                match disassembler.get_symbol_addr(symbol) {
+                    Some(addr) => time_travel.run_to(addr),
+                    None => time_travel.run(),
+                }
+            }
+            Breakpoint::Address(addr) => time_travel.run_to(*addr),
+            Breakpoint::Object(name) => time_travel.run_to_object(name),
+        }
+    }
+}
+
+/// A hex address, with or without a 0x prefix, or an expression without spaces like `sp+16` or
+/// the name of a symbol
+pub fn parse_address(token: &str, emulator: &Emulator) -> Option<u64> {
+    u64::from_str_radix(token.trim_start_matches("0x"), 16)
+        .ok()
+        .or_else(|| expr::evaluate(token, emulator).ok())
+}
+
+/// The bytes given to `:set`, in hex
+pub fn parse_bytes(tokens: &[&str]) -> Option<Vec<u8>> {
+    tokens
+        .iter()
+        .map(|byte| u8::from_str_radix(byte, 16).ok())
+        .collect()
+}
+
+/// Assembles the instruction given to `:patch` and writes it over the code at `addr`, returning
+/// the number of bytes written
+pub fn patch(emulator: &mut Emulator, addr: u64, asm: &str) -> Result<usize> {
+    let bytes = assembler::assemble(asm, addr)?;
+    emulator.memory.write_n(&bytes, addr, bytes.len() as u64)?;
+    Ok(bytes.len())
+}
+
+/// The result of `:objects`, a line for each loaded object and each PLT entry bound so far
+pub fn objects(emulator: &Emulator) -> String {
+    let mut lines = String::new();
+
+    for object in emulator.memory.loaded_objects() {
+        lines += &format!(
+            "{:x}-{:x} {} ({} symbols)\n",
+            object.base, object.end, object.name, object.symbol_count
+        );
+    }
+
+    for binding in emulator.plt_bindings() {
+        lines += &format!(
+            "{}: {} in {} bound to {:x} in {}\n",
+            binding.inst_count,
+            binding.symbol,
+            binding.object,
+            binding.target,
+            binding.target_object.as_deref().unwrap_or("???")
+        );
+    }
+
+    lines
+}
+
+/// The result of `:p`, the value of an expression in hex, decimal and as a signed number
+pub fn print(expr: &str, emulator: &Emulator) -> String {
+    match expr::evaluate(expr, emulator) {
+        Ok(value) => format!("{expr} = {value:#x} ({value}, {})", value as i64),
+        Err(e) => format!("{expr}: {e}"),
+    }
+}
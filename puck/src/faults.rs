@@ -0,0 +1,76 @@
+// reads the faults --faults injects from a TOML file. Every setting is optional, for example:
+//
+// failing_mallocs = [3]
+// failing_mmaps = [1]
+// max_read = 16
+//
+// [[bit_flips]]
+// at = 100000
+// register = "a0"
+// bit = 3
+//
+// [[bit_flips]]
+// at = 200000
+// address = 0x10400
+// bit = 0
+
+use std::fs;
+
+use anyhow::{bail, Context, Result};
+use remu::{
+    register::Reg,
+    system::{BitFlip, FaultPlan, FlipTarget},
+};
+use serde::Deserialize;
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct PlanFile {
+    failing_mallocs: Vec<u64>,
+    failing_mmaps: Vec<u64>,
+    max_read: Option<u64>,
+    bit_flips: Vec<BitFlipEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BitFlipEntry {
+    at: u64,
+    register: Option<String>,
+    address: Option<u64>,
+    bit: u8,
+}
+
+pub fn load(path: &str) -> Result<FaultPlan> {
+    let text = fs::read_to_string(path).with_context(|| format!("could not read {path}"))?;
+    let file: PlanFile = toml::from_str(&text).with_context(|| format!("invalid {path}"))?;
+
+    let bit_flips = file
+        .bit_flips
+        .into_iter()
+        .map(|flip| {
+            let target = match (flip.register, flip.address) {
+                (Some(name), None) => match Reg::parse(&name) {
+                    Some(reg) => FlipTarget::Register(reg),
+                    None => bail!("unknown register {name}"),
+                },
+                (None, Some(addr)) => FlipTarget::Memory(addr),
+                _ => bail!("a bit flip needs either a register or an address"),
+            };
+
+            Ok(BitFlip {
+                at: flip.at,
+                target,
+                bit: flip.bit,
+            })
+        })
+        .collect::<Result<_>>()
+        .with_context(|| format!("invalid {path}"))?;
+
+    Ok(FaultPlan {
+        failing_mallocs: file.failing_mallocs,
+        failing_mmaps: file.failing_mmaps,
+        max_read: file.max_read,
+        bit_flips,
+    })
+}
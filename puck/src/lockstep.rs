@@ -0,0 +1,39 @@
+// runs the program alongside a trace from spike or QEMU, see `Emulator::run_lockstep`, and
+// reports where they first differ
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+};
+
+use anyhow::{bail, Context, Result};
+use remu::system::{Emulator, TraceStep};
+
+pub fn run(mut emulator: Emulator, path: &str) -> Result<()> {
+    let file = File::open(path).with_context(|| format!("could not open {path}"))?;
+    let entry = emulator.pc;
+    let trace = BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| TraceStep::parse(&line));
+
+    let report = emulator.run_lockstep(trace)?;
+    if let Some(divergence) = report.divergence {
+        if let Some((symbol, offset)) = emulator
+            .memory
+            .disassembler
+            .get_symbol_containing(divergence.pc)
+        {
+            eprintln!("in {symbol}+{offset:#x}");
+        }
+        eprint!("{divergence}");
+        bail!("diverged after {} matching instructions", report.steps);
+    }
+
+    if report.steps == 0 {
+        bail!("the trace never reaches the entry point {entry:x}");
+    }
+    println!("Matched all {} instructions of the trace", report.steps);
+
+    Ok(())
+}
@@ -1,28 +1,95 @@
-use std::time::Instant;
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    iter,
+    path::Path,
+    sync::Arc,
+    time::Instant,
+};
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use clap::Parser;
 use elf::{endian::AnyEndian, ElfBytes};
 use log::LevelFilter;
 use simplelog::{ConfigBuilder, SimpleLogger};
 
-use remu::{disassembler::Disassembler, memory::Memory, system::Emulator};
+use remu::{
+    disassembler::Disassembler,
+    error::RVError,
+    memory::{LoadOptions, Memory, MemoryLayout, Uart},
+    system::{
+        CoreDump, CpuModel, Emulator, EmulatorBuilder, EventFilter, ExitReason, MachineIdentity,
+        OutputLimit, Prefetcher, Privilege, ProfileSnapshot, Sysroot, TaintSet,
+    },
+};
 
+// the registers of a 16550 UART span 8 bytes
+const UART_LEN: u64 = 8;
+
+mod bench;
+mod debugger;
+mod faults;
+mod lockstep;
+mod script;
 mod ui;
 
 #[derive(Parser)]
 struct Arguments {
     file: String,
 
+    /// Arguments given to the program, after --. Its argv[0] is FILE when there are any.
+    #[clap(last = true)]
+    program_args: Vec<String>,
+
     /// Path for a file to be treated as standard input
     #[clap(long)]
     stdin: Option<String>,
 
+    /// Keeps at most this many bytes of the program's stdout and stderr each, dropping the rest
+    #[clap(long, value_name = "BYTES")]
+    max_output: Option<usize>,
+
+    /// Fails the program's allocations past this many bytes of memory, and stops it if it uses
+    /// more anyway
+    #[clap(long, value_name = "BYTES")]
+    memory_limit: Option<u64>,
+
+    /// How many instructions the program executes per second of its clock
+    #[clap(long, value_name = "HZ")]
+    clock_rate: Option<u64>,
+
+    /// Slows the program down to run at --clock-rate, and makes its sleeps take real time
+    #[clap(long)]
+    real_time: bool,
+
+    /// Prints each syscall the program makes to stderr, like strace
+    #[clap(long)]
+    strace: bool,
+
+    /// Seeds the bytes the program reads from /dev/random and /dev/urandom
+    #[clap(long, value_name = "SEED")]
+    random_seed: Option<u64>,
+
+    /// The kernel release uname reports to the program
+    #[clap(long, value_name = "RELEASE")]
+    kernel_release: Option<String>,
+
     /// Output the disassembly of the executable, then exit
     #[clap(short, long)]
     disassemble: bool,
 
-    /// Enables the just-in-time recompiler (x86_64 only)
+    /// Adds symbols from an unstripped copy of the executable, or from a map of lines of a hex
+    /// address and a name like the output of `nm`
+    #[clap(long, value_name = "FILE")]
+    symbols: Option<String>,
+
+    /// Writes the executable's symbols to this file as a map --symbols reads, then exits
+    #[clap(long, value_name = "FILE")]
+    export_symbols: Option<String>,
+
+    /// Enables the just-in-time recompiler. It only runs on x86_64 hosts (tested on Linux only),
+    /// and elsewhere the interpreter is used instead.
     #[clap(short, long)]
     jit: bool,
 
@@ -30,14 +97,331 @@ struct Arguments {
     #[clap(short, long)]
     label: Option<String>,
 
+    /// Reports the profile from the first time this label is reached next to the whole run's,
+    /// like main to leave out libc's startup. Profiles the whole run unless --label is given.
+    #[clap(long, value_name = "LABEL")]
+    roi: Option<String>,
+
+    /// Runs the program this many times from the same state and reports the estimated cycles of
+    /// --label, which should be the same every run
+    #[clap(long, value_name = "RUNS", requires = "label", conflicts_with_all = ["interactive", "script"])]
+    bench: Option<usize>,
+
+    /// Compares the estimated cycles of --label with this file saved by --save-baseline, showing
+    /// the functions that changed, and fails if they regressed by more than
+    /// --regression-threshold
+    #[clap(long, value_name = "FILE", requires = "label")]
+    baseline: Option<String>,
+
+    /// Saves the estimated cycles of --label and of each function to this file
+    #[clap(long, value_name = "FILE", requires = "label")]
+    save_baseline: Option<String>,
+
+    /// The percentage the cycles can grow by before --baseline fails
+    #[clap(long, value_name = "PERCENT", default_value_t = 5.0)]
+    regression_threshold: f64,
+
+    /// Counts the instructions of the dynamic linker in the profile, which are left out by
+    /// default
+    #[clap(long)]
+    count_dynamic_linker: bool,
+
+    /// Estimates cycles for a dual issue pipeline like the FU740's, which runs two independent
+    /// adjacent instructions per cycle, instead of a single issue one
+    #[clap(long)]
+    dual_issue: bool,
+
+    /// Estimates cycles with a prefetcher, which is next-line or stride
+    #[clap(long, value_name = "KIND", value_parser = parse_prefetcher)]
+    prefetcher: Option<Prefetcher>,
+
+    /// Store guest memory in a single flat allocation of this many MiB instead of paged buffers.
+    /// Faster, but the whole program including its heap and mmaps has to fit.
+    #[clap(long, value_name = "MIB", conflicts_with = "cow_memory")]
+    flat_memory: Option<u64>,
+
+    /// Store guest memory in copy-on-write pages, which maps the executable without copying it
+    /// and makes time travel snapshots cheap.
+    #[clap(long)]
+    cow_memory: bool,
+
+    /// Loads a position independent executable at this hex address instead of 0
+    #[clap(long, value_name = "ADDR", value_parser = parse_hex)]
+    base: Option<u64>,
+
+    /// Loads the dynamic linker at this hex address
+    #[clap(long, value_name = "ADDR", value_parser = parse_hex)]
+    interp_base: Option<u64>,
+
+    /// Loads the dynamic linker and shared libraries of dynamically linked programs from this
+    /// host directory, like /usr/riscv64-linux-gnu, before the embedded ones
+    #[clap(long, value_name = "DIR")]
+    sysroot: Option<String>,
+
+    /// Randomizes the addresses of the stack, mmaps and position independent code from this
+    /// seed, so the same layout can be reproduced
+    #[clap(long, value_name = "SEED")]
+    aslr: Option<u64>,
+
+    /// Maps a 16550 UART at this hex address, like 10000000 for programs written for QEMU's
+    /// virt board, and prints what the program sends to it. Standard input can be received
+    /// through it. Programs linked far above 0, like at 80000000, need --cow-memory.
+    #[clap(long, value_name = "ADDR", value_parser = parse_hex)]
+    uart: Option<u64>,
+
+    /// Runs a kernel or other bare-metal program in machine or supervisor mode, handling its own
+    /// traps instead of making linux syscalls. In supervisor mode puck answers its SBI calls,
+    /// like OpenSBI does for the console and shutdown.
+    #[clap(long, value_name = "MODE", value_parser = parse_privilege)]
+    system: Option<Privilege>,
+
+    /// Performs calls to memcpy, memset and strlen natively instead of emulating them. Cycle
+    /// counts for these calls are estimated.
+    #[clap(long)]
+    hle: bool,
+
+    /// Replaces calls to a function with returning right away, as SYMBOL or SYMBOL=RET to return
+    /// RET instead of 0, like --stub printf to skip printing. Can be repeated.
+    #[clap(long, value_name = "SYMBOL[=RET]", value_parser = parse_stub)]
+    stub: Vec<(String, u64)>,
+
+    /// Injects the faults in this TOML file, like failing mallocs and mmaps, short reads and
+    /// flipped bits, and reports each one injected after the program exits
+    #[clap(long, value_name = "FILE")]
+    faults: Option<String>,
+
+    /// Stops the program once it has executed this many instructions
+    #[clap(long, value_name = "INSTRUCTIONS")]
+    fuel: Option<u64>,
+
+    /// Makes a host directory visible to the guest, as GUEST_PATH=HOST_DIR. Can be repeated.
+    #[clap(long, value_name = "GUEST_PATH=HOST_DIR", value_parser = parse_mount)]
+    mount: Vec<(String, String)>,
+
     /// Enables an interactive reverse debugger
     #[clap(short, long)]
     interactive: bool,
 
+    /// Runs the reverse debugger's commands from a file instead of the interactive debugger,
+    /// printing their results to standard output
+    #[clap(long, value_name = "FILE", conflicts_with = "interactive")]
+    script: Option<String>,
+
+    /// Records guest activity in a comma separated list of categories (syscall, mmap, fault,
+    /// dynamic-linker) or `all`, and prints it after the program exits
+    #[clap(long, value_name = "CATEGORIES", value_parser = parse_event_filter)]
+    events: Option<EventFilter>,
+
+    /// Prints the guest's memory mappings after the program exits, like /proc/self/maps
+    #[clap(long)]
+    maps: bool,
+
+    /// Prints the objects loaded into the program after it exits, and the PLT entries the dynamic
+    /// linker bound with the instruction count they were bound at
+    #[clap(long, conflicts_with = "jit")]
+    objects: bool,
+
+    /// Writes the registers, memory map, stack and last executed pcs to this file if the program
+    /// crashes
+    #[clap(long, value_name = "FILE")]
+    core_dump: Option<String>,
+
+    /// Keeps the last N instructions executed and prints them if the program crashes. Defaults
+    /// to 64 with --core-dump. The jit isn't used while they're kept.
+    #[clap(long, value_name = "N")]
+    history: Option<usize>,
+
+    /// Opens a dump written by --core-dump in the interactive debugger, to look at the crash
+    /// without running the program again. Execution can't be stepped.
+    #[clap(long, value_name = "FILE", conflicts_with_all = ["interactive", "script"])]
+    open_core: Option<String>,
+
+    /// Samples the guest's call stack while it runs and writes the samples to this file in the
+    /// format of `perf script`, for flamegraph or speedscope
+    #[clap(long, value_name = "FILE")]
+    perf_script: Option<String>,
+
+    /// Samples the guest's call stack while it runs and writes the samples to this file as a
+    /// speedscope profile
+    #[clap(long, value_name = "FILE")]
+    speedscope: Option<String>,
+
+    /// Writes a timeline of function calls, syscalls and jit compilations to this file as a
+    /// chrome trace, for Perfetto or about://tracing. Calls are only seen by the jit when they
+    /// go through a function that isn't compiled yet.
+    #[clap(long, value_name = "FILE")]
+    chrome_trace: Option<String>,
+
+    /// Records every call to malloc, calloc, realloc and free, and writes the allocations made
+    /// from each call stack to this file as a DHAT profile
+    #[clap(long, value_name = "FILE")]
+    dhat: Option<String>,
+
+    /// Reports reads of uninitialized heap memory and accesses to freed blocks or unmapped
+    /// regions, with the stack they were made from
+    #[clap(long, conflicts_with = "jit")]
+    memcheck: bool,
+
+    /// Checks that every call returns to where it was made from with the same stack pointer, and
+    /// reports the store that overwrote a saved return address when one doesn't
+    #[clap(long, conflicts_with = "jit")]
+    check_frames: bool,
+
+    /// Also runs every call to a jit compiled function on the interpreter, and stops at the first
+    /// one where their registers or memory writes differ
+    #[clap(long, requires = "jit")]
+    verify_jit: bool,
+
+    /// Compares each instruction with a trace of the program from spike's --log-commits or QEMU's
+    /// -d exec -one-insn-per-tb, and stops at the first one that differs
+    #[clap(long, value_name = "FILE", conflicts_with_all = ["interactive", "script", "jit"])]
+    lockstep: Option<String>,
+
+    /// Tracks the data read from a comma separated list of sources (stdin, file, getrandom) or
+    /// `all`, and reports the output bytes and branches it influenced
+    #[clap(long, value_name = "SOURCES", value_parser = parse_taint_sources, conflicts_with = "jit")]
+    taint: Option<TaintSet>,
+
+    /// Writes every branch that depended on standard input to this file as lines of JSON, with
+    /// the input offsets involved and the direction taken
+    #[clap(long, value_name = "FILE", conflicts_with = "jit")]
+    branch_log: Option<String>,
+
+    /// The number of instructions between stack samples
+    #[clap(long, value_name = "N", default_value_t = 10_000)]
+    sample_interval: u64,
+
     #[clap(flatten)]
     verbose: clap_verbosity_flag::Verbosity,
 }
 
+fn parse_event_filter(list: &str) -> Result<EventFilter, String> {
+    EventFilter::parse(list).ok_or_else(|| format!("unknown event category in {list}"))
+}
+
+fn parse_hex(addr: &str) -> Result<u64, String> {
+    u64::from_str_radix(addr.trim_start_matches("0x"), 16).map_err(|e| e.to_string())
+}
+
+fn parse_privilege(mode: &str) -> Result<Privilege, String> {
+    match mode {
+        "machine" | "m" => Ok(Privilege::Machine),
+        "supervisor" | "s" => Ok(Privilege::Supervisor),
+        _ => Err(format!("expected machine or supervisor, got {mode}")),
+    }
+}
+
+fn parse_prefetcher(kind: &str) -> Result<Prefetcher, String> {
+    match kind {
+        "next-line" => Ok(Prefetcher::NextLine),
+        "stride" => Ok(Prefetcher::Stride),
+        _ => Err(format!("expected next-line or stride, got {kind}")),
+    }
+}
+
+fn parse_mount(mount: &str) -> Result<(String, String), String> {
+    let (guest, host) = mount
+        .split_once('=')
+        .ok_or_else(|| format!("expected GUEST_PATH=HOST_DIR, got {mount}"))?;
+    Ok((guest.to_string(), host.to_string()))
+}
+
+fn parse_stub(stub: &str) -> Result<(String, u64), String> {
+    match stub.split_once('=') {
+        Some((symbol, ret)) => {
+            let ret = match ret.strip_prefix('-') {
+                Some(negative) => negative.parse::<u64>().map(u64::wrapping_neg),
+                None => ret.parse(),
+            };
+            Ok((symbol.to_string(), ret.map_err(|e| e.to_string())?))
+        }
+        None => Ok((stub.to_string(), 0)),
+    }
+}
+
+fn parse_taint_sources(list: &str) -> Result<TaintSet, String> {
+    TaintSet::parse(list).ok_or_else(|| format!("unknown taint source in {list}"))
+}
+
+// what a core dump holds besides registers, memory and recent instructions, for the Stderr
+// pane
+fn core_dump_report(dump: &CoreDump) -> String {
+    let mut report = format!("{}\n\nmappings:\n", dump.error);
+    for mapping in &dump.mappings {
+        report += &format!("    {mapping}\n");
+    }
+
+    report
+}
+
+// the profile of the whole run next to the profile of the region of interest starting at `label`
+fn region_of_interest_report(
+    label: &str,
+    total: &ProfileSnapshot,
+    region: &ProfileSnapshot,
+) -> String {
+    let rows = |profile: &ProfileSnapshot| {
+        [
+            ("Instructions", profile.inst_count.to_string()),
+            ("Estimated cycles", profile.cycle_count.to_string()),
+            ("IPC", format!("{:.3}", profile.ipc())),
+            (
+                "Cache hit rate",
+                format!("{:.1}%", profile.cache_hit_rate() * 100.0),
+            ),
+            (
+                "Branch prediction rate",
+                format!("{:.1}%", profile.branch_prediction_rate() * 100.0),
+            ),
+        ]
+    };
+
+    let mut report = format!(
+        "{:24}{:>16}{:>16}\n",
+        "",
+        "Whole run",
+        format!("From {label}")
+    );
+    for ((name, total), (_, region)) in rows(total).into_iter().zip(rows(region)) {
+        report += &format!("{name:24}{total:>16}{region:>16}\n");
+    }
+
+    report
+}
+
+// adds the symbols of `path`, an unstripped executable or a symbol map, to the loaded program
+fn load_symbols(memory: &mut Memory, path: &str) -> Result<()> {
+    let data = fs::read(path).with_context(|| format!("could not read {path}"))?;
+    let offset = memory.program_base();
+
+    if data.starts_with(b"\x7fELF") {
+        let elf = ElfBytes::<AnyEndian>::minimal_parse(&data)?;
+        memory.disassembler.add_elf_symbols(&elf, offset);
+    } else {
+        let map = String::from_utf8(data).with_context(|| format!("{path} isn't text"))?;
+        memory.disassembler.import_symbols(&map, offset)?;
+    }
+
+    Ok(())
+}
+
+// a file the emulator writes lines of text to as it runs
+struct LogFile(BufWriter<File>);
+
+impl fmt::Write for LogFile {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write_all(s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+// writes a profile built by `export` to `path`
+fn write_profile(path: &str, export: impl FnOnce(&mut String) -> fmt::Result) -> Result<()> {
+    let mut out = String::new();
+    export(&mut out)?;
+
+    fs::write(path, out).with_context(|| format!("could not write {path}"))
+}
+
 fn main() -> Result<()> {
     let args = Arguments::parse();
     let config = ConfigBuilder::new()
@@ -47,63 +431,456 @@ fn main() -> Result<()> {
 
     SimpleLogger::init(args.verbose.log_level_filter(), config)?;
 
-    let file_data = std::fs::read(args.file).expect("Could not read file.");
-    let slice = file_data.as_slice();
-    let file = ElfBytes::<AnyEndian>::minimal_parse(slice)?;
-
-    match (file.ehdr.class, file.ehdr.e_type, file.ehdr.e_machine) {
-        // (64 bit, executable, risc_v arch)
-        (elf::file::Class::ELF64, 0x03 | 0x02, 0xF3) => log::info!("Parsing executable."),
-        got => {
-            eprintln!(
-                "Error. Invalid executable format. Expects a 64-bit RISC-V Linux binary. Got: {:x?}",
-                got
-            );
-            return Ok(());
-        }
-    }
+    // the executable's pages can be shared with the memory instead of being copied
+    let file_data: Arc<[u8]> = std::fs::read(&args.file)
+        .expect("Could not read file.")
+        .into();
+    let Ok(file) = Memory::parse_elf(&file_data) else {
+        eprintln!("Error. Invalid executable format. Expects a 64-bit RISC-V Linux binary.");
+        return Ok(());
+    };
+    log::info!("Parsing executable.");
 
     if args.disassemble {
         println!("{}", Disassembler::disassemble_elf(&file));
         return Ok(());
     }
 
-    let memory = Memory::load_elf(file);
-    let mut emulator = Emulator::new(memory);
+    let layout = match (args.flat_memory, args.cow_memory) {
+        (Some(mib), _) => MemoryLayout::Flat { size: mib << 20 },
+        (None, true) => MemoryLayout::Cow,
+        (None, false) => MemoryLayout::Paged,
+    };
+
+    let options = LoadOptions {
+        layout,
+        program_base: args.base,
+        interpreter_base: args.interp_base,
+        aslr_seed: args.aslr,
+    };
+    let mut sysroot = Sysroot::default();
+    if let Some(ref dir) = args.sysroot {
+        sysroot.add_dir(dir);
+    }
+    let mut memory = Memory::load_shared_elf_with_sysroot(file_data.clone(), &options, &sysroot)?;
+    let report = memory.load_report();
+    for warning in report.warnings() {
+        eprintln!("warning: {warning}");
+    }
+    if let Some(error) = report.errors().next() {
+        bail!("could not load {}: {error}", args.file);
+    }
+    if let Some(ref path) = args.symbols {
+        load_symbols(&mut memory, path)?;
+    }
+
+    if let Some(ref path) = args.export_symbols {
+        return write_profile(path, |out| memory.disassembler.export_symbols(out));
+    }
+
+    let uart = args.uart.map(|addr| {
+        let uart = Arc::new(Uart::default());
+        memory.map_device(addr, UART_LEN, uart.clone());
+        uart
+    });
+
+    let mut builder = EmulatorBuilder::new(memory)
+        .sysroot(sysroot)
+        .hle(args.hle)
+        .cpu_model(CpuModel {
+            dual_issue: args.dual_issue,
+            prefetcher: args.prefetcher.unwrap_or_default(),
+            ..CpuModel::default()
+        });
+    if let Some(ref label) = args.label {
+        builder = builder.profile_label(label);
+    }
+    if !args.program_args.is_empty() {
+        builder = builder.args(iter::once(&args.file).chain(&args.program_args).cloned());
+    }
 
     if let Some(stdin_file) = args.stdin {
-        let file_data = std::fs::read(stdin_file)
+        let file_data: Arc<[u8]> = std::fs::read(stdin_file)
             .expect("Could not read file.")
-            .leak();
+            .into();
+
+        if let Some(ref uart) = uart {
+            uart.push_input(&file_data);
+        }
+        builder = builder.stdin(file_data);
+    }
 
-        emulator.set_stdin(file_data);
+    if let Some(bytes) = args.memory_limit {
+        builder = builder.memory_limit(bytes);
+    }
+    if args.strace {
+        builder = builder.strace(|line| eprintln!("{line}"));
+    }
+    if let Some(release) = args.kernel_release {
+        builder = builder.machine_identity(MachineIdentity {
+            release,
+            ..MachineIdentity::default()
+        });
+    }
+    if let Some(rate) = args.clock_rate {
+        builder = builder.clock_rate(rate);
+    }
+    builder = builder.real_time_pacing(args.real_time);
+    if let Some(ref path) = args.faults {
+        builder = builder.fault_plan(faults::load(path)?);
+    }
+    if let Some(fuel) = args.fuel {
+        builder = builder.fuel(fuel);
+    }
+    if let Some(max_bytes) = args.max_output {
+        builder = builder.output_limit(OutputLimit::truncate(max_bytes));
+    }
+
+    let mut emulator = builder.build()?;
+    if let Some(privilege) = args.system {
+        emulator.enable_system_mode(privilege);
+    }
+    if let Some(filter) = args.events {
+        emulator.set_event_filter(filter);
     }
 
-    if args.interactive {
+    for (guest, host) in &args.mount {
+        emulator.vfs_mut().mount(guest, host);
+    }
+    if let Some(seed) = args.random_seed {
+        emulator.vfs_mut().set_random_seed(seed);
+    }
+    for (symbol, ret) in args.stub.iter().cloned() {
+        emulator
+            .intercept(&symbol, move |_: &mut Emulator| Ok(ret))
+            .with_context(|| format!("could not stub {symbol}"))?;
+    }
+
+    let baseline_options = bench::BaselineOptions {
+        compare: args.baseline.as_deref(),
+        save: args.save_baseline.as_deref(),
+        threshold: args.regression_threshold,
+    };
+
+    if let Some(ref path) = args.open_core {
+        let data = fs::read(path).with_context(|| format!("could not read {path}"))?;
+        let dump = CoreDump::from_bytes(&data).with_context(|| format!("could not open {path}"))?;
+        emulator.restore_core_dump(&dump)?;
+        emulator.stderr = core_dump_report(&dump);
+
+        let mut app = ui::App::post_mortem(emulator)?;
+        app.main_loop()
+    } else if args.interactive {
+        // the debugger runs one instruction at a time anyway, so it always sees PLT entries bound
+        emulator.set_plt_tracking(true);
         let mut app = ui::App::new(emulator)?;
         app.main_loop()
+    } else if let Some(ref script) = args.script {
+        emulator.set_plt_tracking(true);
+        script::run(emulator, script)
+    } else if let Some(ref trace) = args.lockstep {
+        lockstep::run(emulator, trace)
+    } else if let (Some(runs), Some(label)) = (args.bench, &args.label) {
+        bench::run(
+            emulator,
+            &bench::BenchOptions {
+                label,
+                runs,
+                jit: args.jit,
+                baseline: baseline_options,
+            },
+        )
     } else {
-        if let Some(ref label) = args.label {
-            emulator.profile_label(label)?;
+        if let Some(ref label) = args.roi {
+            emulator.set_region_of_interest(label)?;
+        }
+        emulator
+            .profiler
+            .set_ignore_dynamic_linker_instructions(!args.count_dynamic_linker);
+
+        if args.perf_script.is_some() || args.speedscope.is_some() {
+            emulator.sample_stacks(args.sample_interval);
+        }
+
+        if args.chrome_trace.is_some() {
+            emulator.profiler.start_trace();
+        }
+
+        let history = match args.history {
+            Some(len) => len,
+            None if args.core_dump.is_some() => 64,
+            None => 0,
+        };
+        emulator.set_instruction_history_len(history);
+
+        if args.dhat.is_some() {
+            emulator.set_heap_profiling_enabled(true);
+        }
+
+        emulator.set_memcheck_enabled(args.memcheck);
+        emulator.set_plt_tracking(args.objects);
+        emulator.set_frame_checking_enabled(args.check_frames);
+        // remu only has a jit on x86_64 hosts
+        #[cfg(target_arch = "x86_64")]
+        emulator.set_jit_verification_enabled(args.verify_jit);
+        if let Some(sources) = args.taint {
+            emulator.set_taint_sources(sources);
+        }
+
+        if let Some(ref path) = args.branch_log {
+            let file = File::create(path).with_context(|| format!("could not create {path}"))?;
+            emulator.enable_branch_input_log(LogFile(BufWriter::new(file)));
+        }
+
+        // samples, traces and regions of interest are measured in cycles, which are only counted
+        // while profiling
+        let exporting =
+            args.perf_script.is_some() || args.speedscope.is_some() || args.chrome_trace.is_some();
+        if (exporting || args.roi.is_some()) && args.label.is_none() {
+            emulator.profiler.running = true;
         }
 
         let start = Instant::now();
-        emulator.run(args.jit)?;
+        let result = emulator.run_to_completion(args.jit);
         let end = Instant::now();
+        let fault = match result.exit {
+            ExitReason::Fault(ref e) => Some(e),
+            _ => None,
+        };
+
+        // events and profiles are written before the error, so what led up to a fault can be seen
+        if args.events.is_some() {
+            for record in emulator.events() {
+                eprintln!("{:<14} {record}", record.category.name());
+            }
+        }
+
+        if args.maps {
+            for mapping in emulator.memory.mappings() {
+                eprintln!("{mapping}");
+            }
+        }
 
+        if args.objects {
+            eprint!("{}", debugger::objects(&emulator));
+        }
+
+        if let (Some(path), Some(e)) = (&args.core_dump, fault) {
+            let dump = emulator.core_dump(e);
+            fs::write(path, dump.to_bytes()).with_context(|| format!("could not write {path}"))?;
+        }
+
+        let recent = emulator.recent_instructions();
+        if fault.is_some() && !recent.is_empty() {
+            eprintln!("last {} instructions:", recent.len());
+            for (pc, inst) in recent {
+                match emulator.memory.disassembler.get_symbol_containing(pc) {
+                    Some((symbol, offset)) => {
+                        eprintln!("    {pc:16x} {:<32} {symbol}+{offset:#x}", inst.fmt(pc))
+                    }
+                    None => eprintln!("    {pc:16x} {}", inst.fmt(pc)),
+                }
+            }
+        }
+
+        let name = Path::new(&args.file)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("remu");
+        let (profiler, disassembler) = (&emulator.profiler, &emulator.memory.disassembler);
+
+        if let Some(ref path) = args.perf_script {
+            write_profile(path, |out| {
+                profiler.export_perf_script(name, disassembler, out)
+            })?;
+        }
+
+        if let Some(ref path) = args.speedscope {
+            write_profile(path, |out| {
+                profiler.export_speedscope(name, disassembler, out)
+            })?;
+        }
+
+        if let Some(ref path) = args.chrome_trace {
+            write_profile(path, |out| profiler.export_chrome_trace(disassembler, out))?;
+        }
+
+        if let (Some(path), Some(heap_profile)) = (&args.dhat, emulator.heap_profile()) {
+            write_profile(path, |out| {
+                heap_profile.export_dhat(name, emulator.inst_counter, disassembler, out)
+            })?;
+        }
+
+        for report in emulator.memcheck_reports() {
+            eprintln!(
+                "memcheck: {} of {} bytes at {:#x} ({} times)",
+                report.violation.description(),
+                report.len,
+                report.addr,
+                report.count
+            );
+
+            for &addr in &report.stack {
+                match disassembler.get_symbol_containing(addr) {
+                    Some((symbol, offset)) => eprintln!("    {addr:16x} {symbol}+{offset:#x}"),
+                    None => eprintln!("    {addr:16x} ???"),
+                }
+            }
+        }
+
+        for fault in emulator.injected_faults() {
+            eprintln!("fault: {fault}");
+        }
+
+        if let Some(violation) = emulator.frame_violation() {
+            let symbol = |addr: u64| match disassembler.get_symbol_containing(addr) {
+                Some((symbol, offset)) => format!("{addr:x} {symbol}+{offset:#x}"),
+                None => format!("{addr:x} ???"),
+            };
+
+            eprintln!(
+                "stack corruption: {} returned to {:#x} with sp {:#x}, expected {:#x} with sp {:#x}",
+                symbol(violation.function),
+                violation.return_addr,
+                violation.sp,
+                violation.expected_return_addr,
+                violation.expected_sp
+            );
+            eprintln!("    returning at {}", symbol(violation.return_pc));
+
+            match (violation.ra_slot, violation.overwritten_by) {
+                (Some(slot), Some(pc)) => eprintln!(
+                    "    return address saved at {slot:#x} was overwritten by {}",
+                    symbol(pc)
+                ),
+                (Some(slot), None) => eprintln!("    return address saved at {slot:#x}"),
+                (None, _) => {}
+            }
+
+            for &addr in &violation.stack {
+                eprintln!("    called from {}", symbol(addr));
+            }
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        if let Some(divergence) = emulator.jit_divergence() {
+            if let Some((symbol, offset)) = disassembler.get_symbol_containing(divergence.function)
+            {
+                eprintln!("in {symbol}+{offset:#x}:");
+            }
+            eprintln!("{divergence}");
+        }
+
+        if let Some(taint) = emulator.taint() {
+            let sources = |set: TaintSet| {
+                set.sources()
+                    .map(|source| source.name())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            };
+
+            for output in &taint.outputs {
+                eprintln!(
+                    "taint: output bytes {}..{} influenced by {}",
+                    output.offset,
+                    output.offset + output.len,
+                    sources(output.taint)
+                );
+            }
+
+            for branch in taint.branches.values() {
+                let location = match disassembler.get_symbol_containing(branch.pc) {
+                    Some((symbol, offset)) => format!("{:x} {symbol}+{offset:#x}", branch.pc),
+                    None => format!("{:x} ???", branch.pc),
+                };
+                eprintln!(
+                    "taint: branch at {location} influenced by {} ({} times)",
+                    sources(branch.taint),
+                    branch.count
+                );
+            }
+
+            if let Some(fault) = taint.fault {
+                eprintln!(
+                    "taint: faulting address {:#x} at {:x} influenced by {}",
+                    fault.addr,
+                    fault.pc,
+                    sources(fault.taint)
+                );
+            }
+        }
+        // a segfault is reported in full instead of as a bare error, exiting the way a shell shows
+        // SIGSEGV
+        if let Some(RVError::Segfault(segfault)) = fault {
+            print!("{}", emulator.stdout);
+            eprint!("{}", emulator.stderr);
+            eprint!("{segfault}");
+            std::process::exit(139);
+        }
+        if let ExitReason::Fault(e) = result.exit {
+            return Err(e.into());
+        }
+
+        if let Some(ref uart) = uart {
+            std::io::stdout().write_all(&uart.output())?;
+        }
         print!("{}", emulator.stdout);
+        eprint!("{}", emulator.stderr);
 
         eprintln!("------------------------------");
-        eprintln!("Program exited with code {}", emulator.exit_code.unwrap());
-        eprintln!("Instruction count: {}", emulator.inst_counter);
+        match result.exit {
+            ExitReason::Exited(code) => eprintln!("Program exited with code {code}"),
+            ExitReason::Signaled(sig) => eprintln!("Program was terminated by signal {sig}"),
+            ExitReason::FuelExhausted => eprintln!("Program ran out of fuel at {:x}", emulator.pc),
+            ExitReason::Breakpoint(pc) => eprintln!("Program stopped at a breakpoint at {pc:x}"),
+            ExitReason::Fault(_) => unreachable!("faults are returned above"),
+        }
+        eprintln!("Instruction count: {}", result.inst_count);
+        eprintln!("Peak memory usage: {} bytes", result.peak_memory);
+        eprintln!("Pages written: {}", emulator.memory.dirty_page_count());
+
+        let usage = emulator.memory.usage_by_region();
+        eprintln!(
+            "Memory usage by region: program {}, heap {}, dynamic linker {}, mmap {}, stack {}",
+            usage.program, usage.heap, usage.dynamic_linker, usage.mmap, usage.stack
+        );
+        if emulator.memory.memory_limit_hits() > 0 {
+            eprintln!(
+                "Allocations failed by the memory limit: {}",
+                emulator.memory.memory_limit_hits()
+            );
+        }
 
         if args.label.is_some() {
-            eprintln!("Estimated cycle count: {}", emulator.profiler.cycle_count);
+            eprintln!("Estimated cycle count: {}", result.cycles);
+            if args.dual_issue {
+                eprintln!(
+                    "Dual issued instructions: {}",
+                    emulator.profiler.paired_count
+                );
+            }
             eprintln!(
                 "Cache hit/miss ratio: {}",
                 emulator.profiler.cache_hit_count as f64
                     / emulator.profiler.cache_miss_count as f64
             );
+            eprintln!(
+                "Instruction cache hit/miss ratio: {}",
+                emulator.profiler.l1i_hit_count as f64 / emulator.profiler.l1i_miss_count as f64
+            );
+            eprintln!(
+                "L2 cache hit/miss ratio: {}",
+                emulator.profiler.l2_hit_count as f64 / emulator.profiler.l2_miss_count as f64
+            );
+            if args.prefetcher.is_some() {
+                let snapshot = emulator.profile_snapshot();
+                eprintln!(
+                    "Prefetches: {}, accuracy {:.2}, coverage {:.2}",
+                    snapshot.prefetch_count,
+                    snapshot.prefetch_accuracy(),
+                    snapshot.prefetch_coverage()
+                );
+            }
             eprintln!(
                 "Branch predict/misspredict ratio: {}",
                 emulator.profiler.predicted_branch_count as f64
@@ -114,8 +891,31 @@ fn main() -> Result<()> {
                 emulator.profiler.cycle_count as f64 / 4_000_000_000.0
             );
         }
+        if let Some(ref label) = args.roi {
+            match emulator.region_of_interest_profile() {
+                Some(region) => eprint!(
+                    "{}",
+                    region_of_interest_report(label, &emulator.profile_snapshot(), &region)
+                ),
+                None => eprintln!("The region of interest {label} was never reached"),
+            }
+        }
         eprintln!("Real time: {}s", (end - start).as_secs_f64());
 
+        if args.baseline.is_some() || args.save_baseline.is_some() {
+            bench::check_baseline(&emulator, &baseline_options)?;
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        if args.jit && args.verbose.log_level_filter() > LevelFilter::Error {
+            let stats = emulator.jit_stats();
+            eprintln!("Jit functions compiled: {}", stats.functions_compiled);
+            eprintln!("Jit functions failed: {}", stats.functions_failed);
+            eprintln!("Jit code size: {} bytes", stats.code_bytes);
+            eprintln!("Jit compile time: {}s", stats.compile_time.as_secs_f64());
+            eprintln!("Interpreted function calls: {}", stats.interpreted_calls);
+        }
+
         Ok(())
     }
 }
@@ -0,0 +1,120 @@
+// runs debugger commands from a file without the TUI, like `gdb -x`. Commands are the ones from
+// the TUI's command bar, with or without the `:`, one per line. Blank lines and lines starting
+// with `#` are skipped. `mem <addr|symbol> [lines]` prints a hexdump instead of moving a pane.
+//
+//     bp main
+//     n
+//     p a0
+//     mem sp 4
+
+use std::{
+    fs,
+    io::{self, Write},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use remu::{system::Emulator, time_travel::TimeTravel};
+
+use crate::debugger::{self, Breakpoint};
+
+// the number of lines `mem` prints without a count
+const MEM_LINES: u64 = 4;
+
+pub fn run(emulator: Emulator, path: &str) -> Result<()> {
+    let script = fs::read_to_string(path).with_context(|| format!("could not read {path}"))?;
+
+    let mut time_travel = TimeTravel::new(emulator);
+    let mut breakpoint = Breakpoint::None;
+    let mut stdout = io::stdout().lock();
+
+    for (i, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let tokens: Vec<&str> = line.trim_start_matches(':').split_whitespace().collect();
+        let exit_code = run_command(&tokens, &mut time_travel, &mut breakpoint, &mut stdout)
+            .with_context(|| format!("{path}:{}: {line}", i + 1))?;
+
+        if let Some(exit_code) = exit_code {
+            writeln!(stdout, "Program exited with code {exit_code}")?;
+        }
+    }
+
+    Ok(())
+}
+
+// runs a single command, returning the exit code if it ran the program to the end
+fn run_command(
+    tokens: &[&str],
+    time_travel: &mut TimeTravel,
+    breakpoint: &mut Breakpoint,
+    stdout: &mut impl Write,
+) -> Result<Option<u64>> {
+    let address = |token: &str, emulator: &Emulator| {
+        debugger::parse_address(token, emulator)
+            .ok_or_else(|| anyhow!("unknown address or symbol {token}"))
+    };
+
+    let exit_code = match tokens {
+        ["s" | "step"] => time_travel.step(1),
+        ["s" | "step", amount] => time_travel.step(amount.parse()?),
+        ["so" | "stepover"] => time_travel.step_over(),
+        ["fin" | "finish"] => time_travel.finish(),
+        ["rtc", addr] => {
+            let addr = address(addr, &time_travel.current)?;
+            time_travel.run_to(addr)
+        }
+        ["n" | "next"] => breakpoint.run(time_travel),
+        ["goto", inst_count] => time_travel.goto(inst_count.parse()?),
+        ["bp", args @ ..] if args.len() <= 1 => {
+            *breakpoint = Breakpoint::parse(args.first().copied());
+            None
+        }
+        ["p" | "print", expr @ ..] if !expr.is_empty() => {
+            writeln!(
+                stdout,
+                "{}",
+                debugger::print(&expr.join(" "), &time_travel.current)
+            )?;
+            None
+        }
+        ["objects"] => {
+            write!(stdout, "{}", debugger::objects(&time_travel.current))?;
+            None
+        }
+        ["mem", addr, lines @ ..] if lines.len() <= 1 => {
+            let emulator = &time_travel.current;
+            let lines = match lines.first() {
+                Some(lines) => lines.parse()?,
+                None => MEM_LINES,
+            };
+
+            write!(
+                stdout,
+                "{}",
+                emulator.memory.hexdump(address(addr, emulator)?, lines)
+            )?;
+            None
+        }
+        ["set", addr, bytes @ ..] => {
+            let addr = address(addr, &time_travel.current)?;
+            let bytes = debugger::parse_bytes(bytes).context("expected hex bytes")?;
+
+            time_travel
+                .current
+                .memory
+                .write_n(&bytes, addr, bytes.len() as u64)?;
+            None
+        }
+        ["patch", addr, asm @ ..] if !asm.is_empty() => {
+            let addr = address(addr, &time_travel.current)?;
+            debugger::patch(&mut time_travel.current, addr, &asm.join(" "))?;
+            None
+        }
+        _ => bail!("unknown command, or wrong arguments"),
+    };
+
+    Ok(exit_code)
+}
@@ -4,185 +4,553 @@ use ratatui::{
     prelude::{Constraint, CrosstermBackend, Direction, Layout},
     style::{Modifier, Style},
     text::{Line, Span},
-    widgets::{Block, Borders, List, ListItem, Paragraph},
+    widgets::{Block, Borders, Clear, List, ListItem, Paragraph},
     Terminal,
 };
-use ratatui_textarea::TextArea;
-use std::{io::Stdout, time::Duration};
+use ratatui_textarea::{CursorMove, TextArea};
+use std::{borrow::Cow, collections::BTreeMap, io::Stdout, time::Duration};
 
-use remu::{system::Emulator, time_travel::TimeTravel};
+use remu::{
+    disassembler::Disassembler,
+    memory::HEXDUMP_LINE_WIDTH,
+    register::{FReg, Reg, SP},
+    system::{Emulator, EventFilter, ProfileSnapshot},
+    time_travel::TimeTravel,
+};
+
+use self::{commands::History, config::Config, timeline::Timeline};
+use crate::debugger::{self, Breakpoint};
+
+mod commands;
+mod config;
+mod timeline;
+
+// the number of instructions searched for a match in the disassembly
+const SEARCH_LIMIT: usize = 100_000;
+
+// the number of frames shown in the Backtrace pane
+const BACKTRACE_LIMIT: usize = 64;
+
+// the number of instructions shown in the Recent pane
+const RECENT_LIMIT: usize = 64;
 
 pub struct App {
+    config: Config,
     time_travel: TimeTravel,
     breakpoint: Breakpoint,
+    // start of the Memory pane, which follows the stack pointer if unset
+    memory_addr: Option<u64>,
+    // the cursor of the Disassembly pane, which follows the pc if unset
+    disassembly_addr: Option<u64>,
+    // where the cursor was before following jumps, to go back to
+    jump_history: Vec<u64>,
+    // first line shown in the text panes, which follow the end of the output or show the start
+    // of the text if unset
+    output_scroll: [Option<u16>; PANE_COUNT],
+    // heights of the panes when they were last drawn, for scrolling a page at a time
+    pane_heights: [u16; PANE_COUNT],
+    timeline: Timeline,
+    // the width of the bar when it was last drawn, for scrubbing a cell at a time
+    timeline_width: u16,
+    focus: Pane,
+    last_search: String,
+    // registers before the last command that moved execution, to highlight what it changed
+    previous_registers: Registers,
+    show_float_registers: bool,
     enable_auto: bool,
     auto_delay: u64,
+    // the profiler's counters when auto stepping started, and the cycles spent in each function
+    // since, for the Profiler pane
+    auto_start: ProfileSnapshot,
+    function_cycles: BTreeMap<String, u64>,
     running: bool,
     command_bar: TextArea<'static>,
     command_bar_shown: bool,
+    history: History,
+    // shown until the next key press, at the bottom of the screen or in a popup if it's long
+    message: Option<String>,
     terminal: Terminal<CrosstermBackend<Stdout>>,
 }
 
-enum Breakpoint {
-    None,
-    Syscall,
-    Symbol(String),
-    Address(u64),
+#[derive(Clone, Copy, PartialEq)]
+struct Registers {
+    pc: u64,
+    inst_counter: u64,
+    x: [u64; 32],
+    // compared as bits, so NaNs don't count as changed
+    f: [u64; 32],
+}
+
+impl Registers {
+    fn capture(emulator: &Emulator) -> Registers {
+        Registers {
+            pc: emulator.pc,
+            inst_counter: emulator.inst_counter,
+            x: std::array::from_fn(|i| emulator.reg(Reg(i as u8))),
+            f: std::array::from_fn(|i| emulator.freg(FReg(i as u8)).to_bits()),
+        }
+    }
+}
+
+/// The panes that can be focused with tab, which then receive scrolling and searches. Named in
+/// lowercase in the config file.
+#[derive(Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Pane {
+    Disassembly,
+    Memory,
+    Registers,
+    Stdout,
+    Stderr,
+    Syscalls,
+    /// Everything in the emulator's event log, including syscalls
+    Events,
+    Backtrace,
+    /// The guest's memory mappings
+    Maps,
+    /// The last instructions executed
+    Recent,
+    /// Only shown while auto stepping
+    Profiler,
+    Timeline,
+}
+
+const PANE_COUNT: usize = 12;
+
+// in the order they are focused
+const PANES: [Pane; PANE_COUNT] = [
+    Pane::Disassembly,
+    Pane::Memory,
+    Pane::Registers,
+    Pane::Stdout,
+    Pane::Stderr,
+    Pane::Syscalls,
+    Pane::Events,
+    Pane::Backtrace,
+    Pane::Maps,
+    Pane::Recent,
+    Pane::Profiler,
+    Pane::Timeline,
+];
+
+impl Pane {
+    // whether the pane shows the end of its text until it's scrolled, like a terminal
+    fn follows_end(self) -> bool {
+        matches!(
+            self,
+            Pane::Stdout | Pane::Stderr | Pane::Syscalls | Pane::Events | Pane::Recent
+        )
+    }
+}
+
+// the text of one of the text panes, the registers are only used to scroll and search
+fn output_text(emulator: &Emulator, pane: Pane) -> Cow<'_, str> {
+    match pane {
+        Pane::Stdout => Cow::Borrowed(&emulator.stdout),
+        Pane::Stderr => Cow::Borrowed(&emulator.stderr),
+        Pane::Syscalls => Cow::Owned(
+            emulator
+                .events()
+                .iter()
+                .filter(|record| matches!(record.event, remu::system::Event::Syscall(_)))
+                .map(|record| format!("{record}\n"))
+                .collect(),
+        ),
+        Pane::Events => Cow::Owned(
+            emulator
+                .events()
+                .iter()
+                .map(|record| format!("{:<14} {record}\n", record.category.name()))
+                .collect(),
+        ),
+        Pane::Registers => Cow::Owned(emulator.print_registers()),
+        Pane::Backtrace => {
+            let disassembler = &emulator.memory.disassembler;
+
+            Cow::Owned(
+                emulator
+                    .backtrace(BACKTRACE_LIMIT)
+                    .iter()
+                    .enumerate()
+                    .map(
+                        |(i, &addr)| match disassembler.get_symbol_containing(addr) {
+                            Some((symbol, offset)) => {
+                                format!("#{i:<2} {addr:x} {symbol}+{offset:#x}\n")
+                            }
+                            None => format!("#{i:<2} {addr:x}\n"),
+                        },
+                    )
+                    .collect(),
+            )
+        }
+        Pane::Maps => Cow::Owned(
+            emulator
+                .memory
+                .mappings()
+                .iter()
+                .map(|mapping| format!("{mapping}\n"))
+                .collect(),
+        ),
+        Pane::Recent => Cow::Owned(
+            emulator
+                .recent_instructions()
+                .iter()
+                .map(|&(pc, inst)| format!("{pc:16x} {}\n", inst.fmt(pc)))
+                .collect(),
+        ),
+        Pane::Disassembly | Pane::Memory | Pane::Profiler | Pane::Timeline => Cow::Borrowed(""),
+    }
+}
+
+// the first line shown in a text pane `height` lines tall, when it hasn't been scrolled
+fn default_scroll(output: &str, pane: Pane, height: u16) -> u16 {
+    if pane.follows_end() {
+        bottom_line(output, height)
+    } else {
+        0
+    }
+}
+
+fn command_bar(text: &str) -> TextArea<'static> {
+    let mut command_bar = TextArea::from([text.to_string()]);
+    command_bar.set_cursor_line_style(Style::default());
+    command_bar.move_cursor(CursorMove::End);
+    command_bar
+}
+
+// the scroll offset which shows the last lines of `output` in a pane `height` lines tall
+fn bottom_line(output: &str, height: u16) -> u16 {
+    (output.lines().count() as u16).saturating_sub(height)
 }
 
 impl App {
     pub fn new(emulator: Emulator) -> Result<App> {
+        App::with_time_travel(emulator, TimeTravel::new)
+    }
+
+    /// Looks at a crashed emulator without running it, see [`TimeTravel::post_mortem`]
+    pub fn post_mortem(emulator: Emulator) -> Result<App> {
+        let mut app = App::with_time_travel(emulator, TimeTravel::post_mortem)?;
+        app.message = Some("Post-mortem, the program can't be stepped".to_string());
+        Ok(app)
+    }
+
+    fn with_time_travel(
+        mut emulator: Emulator,
+        time_travel: fn(Emulator) -> TimeTravel,
+    ) -> Result<App> {
+        // before entering the alternate screen, so errors in it can be read
+        let config = Config::load()?;
+
         let mut stdout = std::io::stdout();
         crossterm::terminal::enable_raw_mode()?;
         crossterm::execute!(stdout, crossterm::terminal::EnterAlternateScreen)?;
 
-        let mut command_bar = TextArea::default();
-        command_bar.set_cursor_line_style(Style::default());
+        let command_bar = command_bar("");
+        emulator.set_event_filter(EventFilter::ALL);
+        emulator.profiler.running = true;
+        // a restored core dump comes with its own
+        if emulator.recent_instructions().is_empty() {
+            emulator.set_instruction_history_len(RECENT_LIMIT);
+        }
 
         Ok(App {
-            time_travel: TimeTravel::new(emulator),
+            previous_registers: Registers::capture(&emulator),
+            show_float_registers: false,
+            time_travel: time_travel(emulator),
             breakpoint: Breakpoint::None,
+            memory_addr: None,
+            disassembly_addr: None,
+            jump_history: Vec::new(),
+            output_scroll: [None; PANE_COUNT],
+            pane_heights: [0; PANE_COUNT],
+            timeline: Timeline::default(),
+            timeline_width: 0,
+            focus: config
+                .layout
+                .panes
+                .first()
+                .copied()
+                .unwrap_or(Pane::Disassembly),
+            last_search: String::new(),
             enable_auto: false,
             auto_delay: 16,
+            auto_start: ProfileSnapshot::default(),
+            function_cycles: BTreeMap::new(),
             running: true,
             terminal: Terminal::new(CrosstermBackend::new(stdout))?,
             command_bar,
             command_bar_shown: false,
+            history: History::load(),
+            message: None,
+            config,
         })
     }
 
     fn render_ui(&mut self) -> Result<()> {
-        let disassembler = &self.time_travel.current.memory.disassembler;
-
-        let disassembly = disassembler.disassemble_pc_relative(
-            &self.time_travel.current.memory,
-            self.time_travel.current.pc,
+        let current = &self.time_travel.current;
+        let disassembly_addr = self.disassembly_addr.unwrap_or(current.pc);
+        let disassembly = current.memory.disassembler.disassemble_pc_relative(
+            &current.memory,
+            disassembly_addr,
             30,
         );
 
+        let registers = self.register_lines();
+        let profile = self.profile_lines();
+        let enable_auto = self.enable_auto;
+        let registers_title = if self.show_float_registers {
+            "Registers (f)"
+        } else {
+            "Registers"
+        };
+
+        let config = &self.config;
+        let focus = self.focus;
+        let pane_block = |title: &'static str, pane: Pane| {
+            let border_color = if pane == focus {
+                config.colors.focus
+            } else {
+                config.colors.border
+            };
+
+            Block::default()
+                .title(title)
+                .borders(Borders::ALL)
+                .border_style(Style::default().fg(border_color))
+        };
+
+        let pane_heights = &mut self.pane_heights;
+        let timeline = &self.timeline;
+        let timeline_width = &mut self.timeline_width;
+        let output_scroll = &self.output_scroll;
+
         self.terminal.draw(|f| {
+            let visible = |panes: &[Pane]| -> Vec<Pane> {
+                panes
+                    .iter()
+                    .copied()
+                    .filter(|&pane| config.is_visible(pane))
+                    .collect()
+            };
+
+            let top_panes = visible(&[Pane::Disassembly, Pane::Memory]);
+            let mut bottom_panes = visible(&[
+                Pane::Stdout,
+                Pane::Stderr,
+                Pane::Syscalls,
+                Pane::Events,
+                Pane::Backtrace,
+                Pane::Maps,
+                Pane::Recent,
+                Pane::Profiler,
+            ]);
+            if !enable_auto {
+                bottom_panes.retain(|&pane| pane != Pane::Profiler);
+            }
+
+            let mut columns = vec![Constraint::Min(10)];
+            if config.is_visible(Pane::Registers) {
+                columns.push(Constraint::Length(config.layout.registers_width));
+            }
+
             let chunks = Layout::default()
                 .direction(Direction::Horizontal)
-                .constraints([Constraint::Min(10), Constraint::Length(28)])
+                .constraints(columns)
                 .split(f.size());
 
-            {
-                let vertical_split = Layout::default()
-                    .direction(Direction::Vertical)
-                    .constraints([Constraint::Percentage(70), Constraint::Percentage(30)])
-                    .split(chunks[0]);
+            // either row takes the whole height if the other one is empty
+            let top_height = match (top_panes.is_empty(), bottom_panes.is_empty()) {
+                (true, _) => 0,
+                (false, true) => 100,
+                (false, false) => config.layout.top_height,
+            };
+
+            let timeline_height = if config.is_visible(Pane::Timeline) {
+                4
+            } else {
+                0
+            };
+
+            let vertical_split = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([
+                    Constraint::Percentage(top_height),
+                    Constraint::Min(0),
+                    Constraint::Length(timeline_height),
+                ])
+                .split(chunks[0]);
+
+            if config.is_visible(Pane::Timeline) {
+                let area = vertical_split[2];
+                pane_heights[Pane::Timeline as usize] = area.height;
+                *timeline_width = area.width.saturating_sub(2);
 
-                let pc_start = format!("{:16x}", self.time_travel.current.pc);
+                let lines = timeline.lines(
+                    current.inst_counter,
+                    *timeline_width,
+                    config.colors.highlight_style(),
+                );
+
+                f.render_widget(
+                    Paragraph::new(lines).block(pane_block("Timeline", Pane::Timeline)),
+                    area,
+                );
+            }
+
+            // the hexdump plus borders, without the newline, unless memory is the only pane
+            let memory_width = HEXDUMP_LINE_WIDTH as u16 + 1;
+            let top_split = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints(
+                    top_panes
+                        .iter()
+                        .map(|&pane| match pane {
+                            Pane::Memory if top_panes.len() > 1 => Constraint::Length(memory_width),
+                            _ => Constraint::Min(30),
+                        })
+                        .collect::<Vec<_>>(),
+                )
+                .split(vertical_split[0]);
+
+            let bottom_split = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints(
+                    bottom_panes
+                        .iter()
+                        .map(|_| Constraint::Ratio(1, bottom_panes.len() as u32))
+                        .collect::<Vec<_>>(),
+                )
+                .split(vertical_split[1]);
+
+            for (&pane, &area) in top_panes.iter().zip(top_split.iter()) {
+                pane_heights[pane as usize] = area.height;
+
+                if pane == Pane::Memory {
+                    let memory_addr = self.memory_addr.unwrap_or(current.reg(SP));
+                    let dump = current
+                        .memory
+                        .hexdump(memory_addr, area.height.saturating_sub(2) as u64);
+
+                    f.render_widget(
+                        Paragraph::new(dump).block(pane_block("Memory", Pane::Memory)),
+                        area,
+                    );
+                    continue;
+                }
+
+                let pc_start = format!("{:16x}", current.pc);
+                let view_start = format!("{disassembly_addr:16x}");
 
                 let hl_line = disassembly
                     .lines()
-                    .position(|line| line.starts_with(&pc_start))
-                    .unwrap();
+                    .position(|line| line.starts_with(&pc_start));
+                let view_line = disassembly
+                    .lines()
+                    .position(|line| line.starts_with(&view_start))
+                    .unwrap_or(0);
 
-                let skip_amount = (hl_line as i32 - 8).max(0) as usize;
+                let skip_amount = view_line.saturating_sub(8);
                 let items: Vec<ListItem> = disassembly
                     .lines()
                     .enumerate()
                     .skip(skip_amount)
-                    .take(vertical_split[0].height as usize)
+                    .take(area.height as usize)
                     .map(|(i, line)| {
                         let list_item = ListItem::new(Line::from(Span::raw(line.to_string())));
-                        if i == hl_line {
-                            list_item.style(
-                                Style::default()
-                                    .add_modifier(Modifier::REVERSED)
-                                    .add_modifier(Modifier::BOLD),
-                            )
+                        if Some(i) == hl_line {
+                            list_item.style(config.colors.highlight_style())
+                        } else if i == view_line && disassembly_addr != current.pc {
+                            // the cursor, moved by scrolling, searching and following jumps
+                            list_item.style(Style::default().add_modifier(Modifier::UNDERLINED))
                         } else {
                             list_item
                         }
                     })
                     .collect();
 
-                let disassmebly_memory_split = Layout::default()
-                    .direction(Direction::Horizontal)
-                    .constraints([Constraint::Min(30), Constraint::Length(34)])
-                    .split(vertical_split[0]);
-
                 f.render_widget(
-                    List::new(items).block(
-                        Block::default()
-                            .title("Disassembly")
-                            .borders(Borders::ALL)
-                            .border_style(Style::default()),
-                    ),
-                    disassmebly_memory_split[0],
+                    List::new(items).block(pane_block("Disassembly", Pane::Disassembly)),
+                    area,
                 );
+            }
 
-                // create hexdump
-                let dump = self
-                    .time_travel
-                    .current
-                    .memory
-                    // .hexdump(self.time_travel.current.last_mem_access, 30);
-                    .hexdump(0, 30);
+            for (&pane, &area) in bottom_panes.iter().zip(bottom_split.iter()) {
+                pane_heights[pane as usize] = area.height;
 
-                f.render_widget(
-                    Paragraph::new(dump).block(
-                        Block::default()
-                            .title("Memory")
-                            .borders(Borders::ALL)
-                            .border_style(Style::default()),
-                    ),
-                    disassmebly_memory_split[1],
-                );
+                if pane == Pane::Profiler {
+                    f.render_widget(
+                        Paragraph::new(profile.clone()).block(pane_block("Profiler", pane)),
+                        area,
+                    );
+                    continue;
+                }
 
-                let output_split = Layout::default()
-                    .direction(Direction::Horizontal)
-                    .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
-                    .split(vertical_split[1]);
+                let title = match pane {
+                    Pane::Stdout => "stdout",
+                    Pane::Stderr => "stderr",
+                    Pane::Syscalls => "Syscalls",
+                    Pane::Events => "Events",
+                    Pane::Maps => "Maps",
+                    Pane::Recent => "Recent",
+                    _ => "Backtrace",
+                };
 
-                let output = self.time_travel.current.stdout.clone();
-                let lines = (output.chars().filter(|c| *c == '\n').count() as u16)
-                    .max(output_split[0].height);
+                let output = output_text(current, pane);
+                let height = area.height.saturating_sub(2);
+                let scroll = output_scroll[pane as usize]
+                    .unwrap_or_else(|| default_scroll(&output, pane, height));
 
                 f.render_widget(
-                    Paragraph::new(self.time_travel.current.stdout.clone())
-                        .scroll((lines - output_split[0].height, 0))
-                        .block(
-                            Block::default()
-                                .title("stdout")
-                                .borders(Borders::ALL)
-                                .border_style(Style::default()),
-                        ),
-                    output_split[0],
+                    Paragraph::new(output)
+                        .scroll((scroll, 0))
+                        .block(pane_block(title, pane)),
+                    area,
                 );
+            }
+
+            if let Some(&area) = chunks.get(1) {
+                pane_heights[Pane::Registers as usize] = area.height;
 
                 f.render_widget(
-                    Paragraph::new(self.time_travel.current.stderr.clone()).block(
-                        Block::default()
-                            .title("stderr")
-                            .borders(Borders::ALL)
-                            .border_style(Style::default()),
-                    ),
-                    output_split[1],
+                    Paragraph::new(registers)
+                        .scroll((output_scroll[Pane::Registers as usize].unwrap_or(0), 0))
+                        .block(pane_block(registers_title, Pane::Registers)),
+                    area,
                 );
             }
 
-            f.render_widget(
-                Paragraph::new(self.time_travel.current.print_registers()).block(
-                    Block::default()
-                        .title("Registers")
-                        .borders(Borders::ALL)
-                        .border_style(Style::default()),
-                ),
-                chunks[1],
-            );
+            let floating = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Min(1), Constraint::Length(1)])
+                .split(f.size());
 
             // floating window if command bar shown
             if self.command_bar_shown {
-                let floating = Layout::default()
-                    .direction(Direction::Vertical)
-                    .constraints([Constraint::Min(1), Constraint::Length(1)])
-                    .split(f.size());
-
                 let widget = self.command_bar.widget();
                 f.render_widget(widget, floating[1]);
+            } else if let Some(ref message) = self.message {
+                let lines = message.lines().count() as u16;
+
+                if lines > 1 {
+                    let area = f.size();
+                    let width = message.lines().map(str::len).max().unwrap_or(0) as u16 + 2;
+                    let popup = ratatui::layout::Rect {
+                        x: area.width.saturating_sub(width) / 2,
+                        y: area.height.saturating_sub(lines + 2) / 2,
+                        width: width.min(area.width),
+                        height: (lines + 2).min(area.height),
+                    };
+
+                    f.render_widget(Clear, popup);
+                    f.render_widget(
+                        Paragraph::new(message.as_str())
+                            .block(Block::default().title("Help").borders(Borders::ALL)),
+                        popup,
+                    );
+                } else {
+                    f.render_widget(Paragraph::new(message.as_str()), floating[1]);
+                }
             }
         })?;
 
@@ -197,7 +565,7 @@ impl App {
         };
 
         if !input && self.enable_auto {
-            self.time_travel.step(1);
+            self.auto_step();
         }
 
         if input {
@@ -207,7 +575,8 @@ impl App {
                         code: KeyCode::Esc, ..
                     }) => {
                         self.command_bar_shown = false;
-                        self.command_bar = TextArea::default();
+                        self.command_bar = command_bar("");
+                        self.history.reset();
                     }
                     Event::Key(KeyEvent {
                         code: KeyCode::Enter,
@@ -215,25 +584,108 @@ impl App {
                     }) => {
                         self.command_bar_shown = false;
                         self.do_command();
-                        self.command_bar = TextArea::default();
+                        self.command_bar = command_bar("");
+                    }
+                    Event::Key(KeyEvent {
+                        code: KeyCode::Up, ..
+                    }) => {
+                        if let Some(command) = self.history.previous() {
+                            self.command_bar = command_bar(command);
+                        }
+                    }
+                    Event::Key(KeyEvent {
+                        code: KeyCode::Down,
+                        ..
+                    }) => {
+                        if self.history.is_browsing() {
+                            let command = self.history.next().unwrap_or(":");
+                            self.command_bar = command_bar(command);
+                        }
+                    }
+                    Event::Key(KeyEvent {
+                        code: KeyCode::Tab, ..
+                    }) => {
+                        let symbols = self.time_travel.current.memory.disassembler.symbol_names();
+                        let (line, candidates) =
+                            commands::complete(&self.command_bar.lines()[0], symbols);
+
+                        self.command_bar = command_bar(&line);
+                        if !candidates.is_empty() {
+                            self.message = Some(candidates.join(" "));
+                        }
                     }
                     input => {
                         self.command_bar.input(input);
                     }
                 };
             } else if let Event::Key(key) = crossterm::event::read()? {
+                self.message = None;
+                let keys = self.config.keys;
+
                 match key.code {
-                    KeyCode::Char('j') => {
+                    KeyCode::Char(c) if c == keys.step => {
                         self.time_travel.step(1);
                     }
-                    KeyCode::Char('k') => {
+                    KeyCode::Char(c) if c == keys.step_back => {
                         self.time_travel.step(-1);
                     }
-                    KeyCode::Char('q') => self.running = false,
-                    KeyCode::Char(':') => {
+                    KeyCode::Char(c) if c == keys.float_registers => {
+                        self.show_float_registers = !self.show_float_registers;
+                    }
+                    KeyCode::Char(c) if c == keys.quit => self.running = false,
+                    KeyCode::Char(':') | KeyCode::Char('/') => {
                         self.command_bar_shown = true;
                         self.command_bar.input(key);
                     }
+                    KeyCode::Tab => self.focus_next(),
+                    KeyCode::Up => self.scroll(-1),
+                    KeyCode::Down => self.scroll(1),
+                    KeyCode::PageUp => self.scroll(-self.page_height()),
+                    KeyCode::PageDown => self.scroll(self.page_height()),
+                    KeyCode::Enter => {
+                        if let Some(addr) = self.disassembly_addr {
+                            self.time_travel.run_to(addr);
+                        }
+                    }
+                    // scrub through the timeline a cell at a time
+                    KeyCode::Right | KeyCode::Left if self.focus == Pane::Timeline => {
+                        let cell = self.timeline.cell_size(self.timeline_width);
+                        let position = self.time_travel.current.inst_counter;
+
+                        self.time_travel.goto(if key.code == KeyCode::Right {
+                            position.saturating_add(cell).min(self.timeline.end)
+                        } else {
+                            position.saturating_sub(cell)
+                        });
+                    }
+                    // follow the jump under the cursor, and go back
+                    KeyCode::Right => {
+                        let current = &self.time_travel.current;
+                        let cursor = self.disassembly_addr.unwrap_or(current.pc);
+
+                        if let Some(target) = Disassembler::jump_target(&current.memory, cursor) {
+                            self.jump_history.push(cursor);
+                            self.disassembly_addr = Some(target);
+                            self.focus_disassembly();
+                        }
+                    }
+                    KeyCode::Left => {
+                        if let Some(addr) = self.jump_history.pop() {
+                            self.disassembly_addr = Some(addr);
+                        }
+                    }
+                    // go back to following the pc, stack pointer or end of the output
+                    KeyCode::Home => match self.focus {
+                        Pane::Disassembly => {
+                            self.disassembly_addr = None;
+                            self.jump_history.clear();
+                        }
+                        Pane::Memory => self.memory_addr = None,
+                        Pane::Timeline => {
+                            self.time_travel.goto(self.timeline.end);
+                        }
+                        _ => self.output_scroll[self.focus as usize] = None,
+                    },
                     _ => {}
                 };
             }
@@ -245,14 +697,39 @@ impl App {
     pub fn main_loop(&mut self) -> Result<()> {
         while self.running {
             self.render_ui()?;
+
+            let before = Registers::capture(&self.time_travel.current);
             self.check_input()?;
+            if self.time_travel.current.inst_counter != before.inst_counter {
+                self.previous_registers = before;
+                self.timeline.update(&self.time_travel.current);
+            }
+
+            // the pc stays on the faulting instruction, so following it shows the fault
+            if let Some(fault) = self.time_travel.take_fault() {
+                self.disassembly_addr = None;
+                self.focus_disassembly();
+                self.message = fault.to_string().lines().next().map(String::from);
+            }
         }
 
         Ok(())
     }
 
     fn do_command(&mut self) {
-        let command = self.command_bar.lines()[0].as_str();
+        let command = self.command_bar.lines()[0].clone();
+        self.history.push(&command);
+        self.message = None;
+
+        if let Some(pattern) = command.strip_prefix('/') {
+            // an empty pattern repeats the last search
+            if !pattern.is_empty() {
+                self.last_search = pattern.to_string();
+            }
+
+            self.search();
+            return;
+        }
 
         let tokens = command
             .strip_prefix(':')
@@ -260,75 +737,341 @@ impl App {
             .split_whitespace()
             .collect::<Vec<_>>();
 
-        match tokens[0] {
+        let Some(&name) = tokens.first() else {
+            return;
+        };
+
+        match name {
             "s" | "step" => {
                 let step_amount = tokens.get(1).map(|s| s.parse().unwrap_or(1)).unwrap_or(1);
                 self.time_travel.step(step_amount);
             }
 
+            "so" | "stepover" => {
+                self.time_travel.step_over();
+            }
+
+            // run to an address or symbol, or the disassembly cursor
+            "rtc" => {
+                let addr = match tokens.get(1) {
+                    Some(addr) => debugger::parse_address(addr, &self.time_travel.current),
+                    None => self.disassembly_addr,
+                };
+
+                if let Some(addr) = addr {
+                    self.time_travel.run_to(addr);
+                }
+            }
+
+            "fin" | "finish" => {
+                self.time_travel.finish();
+            }
+
             "sa" | "stopauto" => {
                 self.enable_auto = false;
+                if self.focus == Pane::Profiler {
+                    self.focus_next();
+                }
             }
 
             "a" | "auto" => {
                 self.enable_auto = true;
+                self.auto_start = self.time_travel.current.profile_snapshot();
+                self.function_cycles.clear();
                 let auto_delay = tokens.get(1).map(|s| s.parse().unwrap_or(16)).unwrap_or(16);
                 self.auto_delay = auto_delay;
             }
 
             // advance to next breakpoint, or end of program
-            "n" | "next" => match self.breakpoint {
-                Breakpoint::None => while self.time_travel.step(1).is_none() {},
-                Breakpoint::Syscall => todo!(),
-                Breakpoint::Symbol(ref search_symbol) => {
-                    while self.time_travel.step(1).is_none() {
-                        if let Some(symbol_at_addr) = self
-                            .time_travel
-                            .current
-                            .memory
-                            .disassembler
-                            .get_symbol_at_addr(self.time_travel.current.pc)
-                        {
-                            if &symbol_at_addr == search_symbol {
-                                break;
-                            }
-                        }
-                    }
+            "n" | "next" => {
+                let exited = self.breakpoint.run(&mut self.time_travel).is_some();
+
+                if !exited && !matches!(self.breakpoint, Breakpoint::None) {
+                    let inst_count = self.time_travel.current.inst_counter;
+                    self.timeline.add_breakpoint(inst_count);
                 }
-                Breakpoint::Address(a) => {
-                    while self.time_travel.step(1).is_none() {
-                        if self.time_travel.current.pc == a {
-                            break;
-                        }
-                    }
+            }
+
+            // jump to a point in time by its instruction count
+            "goto" => match tokens.get(1).and_then(|count| count.parse().ok()) {
+                Some(inst_count) => {
+                    self.time_travel.goto(inst_count);
                 }
+                None => self.message = Some("Usage: :goto <n>".to_string()),
             },
 
-            // set breakpoint
-            "bp" => match tokens.get(1) {
-                Some(&"syscall") => {
-                    self.breakpoint = Breakpoint::Syscall;
+            // move the memory pane to an address or symbol, or back to the stack pointer
+            "mem" => match tokens.get(1) {
+                Some(addr) => {
+                    if let Some(addr) = debugger::parse_address(addr, &self.time_travel.current) {
+                        self.memory_addr = Some(addr);
+                    }
                 }
-                Some(&symbol_name) => match u64::from_str_radix(symbol_name, 16) {
-                    Ok(a) => {
-                        self.breakpoint = Breakpoint::Address(a);
+                None => self.memory_addr = None,
+            },
+
+            // overwrite memory at an address with hex bytes, `:set 1000 de ad be ef`. The edit is
+            // lost when stepping backwards past it.
+            "set" => {
+                let addr = tokens
+                    .get(1)
+                    .and_then(|addr| debugger::parse_address(addr, &self.time_travel.current));
+                let bytes = debugger::parse_bytes(tokens.get(2..).unwrap_or_default());
+
+                if let (Some(addr), Some(bytes)) = (addr, bytes) {
+                    let memory = &mut self.time_travel.current.memory;
+                    if let Err(e) = memory.write_n(&bytes, addr, bytes.len() as u64) {
+                        self.time_travel.current.stderr.push_str(&e.to_string());
                     }
-                    Err(_) => {
-                        self.breakpoint = Breakpoint::Symbol(symbol_name.to_string());
+                }
+            }
+
+            // assemble an instruction over the code at an address, `:patch 10a4c li a0, 0`. Like
+            // `:set`, the patch is lost when stepping backwards past it.
+            "patch" => {
+                let addr = tokens
+                    .get(1)
+                    .and_then(|addr| debugger::parse_address(addr, &self.time_travel.current));
+                let asm = tokens.get(2..).unwrap_or_default().join(" ");
+
+                self.message = Some(match addr {
+                    Some(addr) => {
+                        match debugger::patch(&mut self.time_travel.current, addr, &asm) {
+                            Ok(len) => format!("patched {len} bytes at {addr:x}"),
+                            Err(e) => format!(":patch: {e}"),
+                        }
                     }
-                },
-                None => {
-                    self.breakpoint = Breakpoint::None;
+                    None => String::from("usage: :patch <addr|symbol> <instruction>"),
+                });
+            }
+
+            // set breakpoint
+            "bp" => {
+                self.breakpoint = Breakpoint::parse(tokens.get(1).copied());
+            }
+
+            // list the program, the dynamic linker and the shared libraries it loaded
+            "objects" => {
+                self.message = Some(debugger::objects(&self.time_travel.current));
+            }
+
+            // print an expression, `:p *(u64)(sp + 16)`
+            "p" | "print" => {
+                let expr = tokens[1..].join(" ");
+                self.message = Some(debugger::print(&expr, &self.time_travel.current));
+            }
+
+            "help" => {
+                self.message = Some(commands::help(&self.config.keys));
+            }
+
+            _ => {
+                self.message = Some(format!("Unknown command :{name}, see :help"));
+            }
+        }
+    }
+
+    // the Registers pane, with registers that changed in the last step highlighted
+    fn register_lines(&self) -> Vec<Line<'static>> {
+        let current = Registers::capture(&self.time_travel.current);
+        let previous = &self.previous_registers;
+        let changed = Style::default()
+            .add_modifier(Modifier::REVERSED)
+            .add_modifier(Modifier::BOLD);
+
+        let line = |text: String, was_changed: bool| {
+            if was_changed {
+                Line::from(Span::styled(text, changed))
+            } else {
+                Line::from(text)
+            }
+        };
+
+        let mut lines = vec![
+            line(format!("pc: {:22x}", current.pc), false),
+            line(format!("fuel cnt: {:16}", current.inst_counter), false),
+        ];
+
+        for i in 0..32 {
+            let was_changed = if self.show_float_registers {
+                current.f[i] != previous.f[i]
+            } else {
+                current.x[i] != previous.x[i]
+            };
+
+            let text = if self.show_float_registers {
+                let start = format!("f{i} ({}):", FReg(i as u8));
+                format!("{start:12}{:>14.6e}", f64::from_bits(current.f[i]))
+            } else {
+                let start = format!("x{i} ({}):", Reg(i as u8));
+                format!("{start:10}{:16x}", current.x[i])
+            };
+
+            lines.push(line(text, was_changed));
+        }
+
+        lines
+    }
+
+    // focuses the next visible pane
+    fn focus_next(&mut self) {
+        let index = self.focus as usize;
+        let next = (1..=PANE_COUNT)
+            .map(|i| PANES[(index + i) % PANE_COUNT])
+            .find(|&pane| self.is_shown(pane));
+
+        if let Some(pane) = next {
+            self.focus = pane;
+        }
+    }
+
+    fn is_shown(&self, pane: Pane) -> bool {
+        self.config.is_visible(pane) && (pane != Pane::Profiler || self.enable_auto)
+    }
+
+    // focuses the Disassembly pane after moving its cursor, unless it's hidden
+    fn focus_disassembly(&mut self) {
+        if self.config.is_visible(Pane::Disassembly) {
+            self.focus = Pane::Disassembly;
+        }
+    }
+
+    // steps one instruction, counting its cycles towards the function it's in
+    fn auto_step(&mut self) {
+        let current = &self.time_travel.current;
+        let before = current.profile_snapshot();
+        let function = current
+            .memory
+            .disassembler
+            .get_symbol_containing(current.pc)
+            .map_or_else(
+                || format!("{:x}", current.pc),
+                |(symbol, _)| symbol.to_string(),
+            );
+
+        self.time_travel.step(1);
+
+        let cycles = (self.time_travel.current.profile_snapshot() - before).cycle_count;
+        *self.function_cycles.entry(function).or_default() += cycles;
+    }
+
+    // the Profiler pane, measured since auto stepping started
+    fn profile_lines(&self) -> Vec<Line<'static>> {
+        let total = self.time_travel.current.profile_snapshot();
+        let profile = total - self.auto_start;
+
+        let hottest = self
+            .function_cycles
+            .iter()
+            .max_by_key(|(_, &cycles)| cycles)
+            .map_or_else(String::new, |(function, &cycles)| {
+                let share = cycles as f64 / profile.cycle_count.max(1) as f64;
+                format!("{function} ({:.1}%)", share * 100.0)
+            });
+
+        vec![
+            Line::from(format!("cycles:   {}", total.cycle_count)),
+            Line::from(format!("ipc:      {:.3}", profile.ipc())),
+            Line::from(format!(
+                "cache:    {:.1}% hits",
+                profile.cache_hit_rate() * 100.0
+            )),
+            Line::from(format!(
+                "branches: {:.1}% predicted",
+                profile.branch_prediction_rate() * 100.0
+            )),
+            Line::from(format!("hottest:  {hottest}")),
+        ]
+    }
+
+    // the number of lines in the focused pane
+    fn page_height(&self) -> i64 {
+        self.pane_heights[self.focus as usize].saturating_sub(2) as i64
+    }
+
+    // moves the focused pane up (negative) or down by a number of lines
+    fn scroll(&mut self, lines: i64) {
+        let height = self.page_height() as u16;
+        let current = &self.time_travel.current;
+
+        match self.focus {
+            Pane::Disassembly => {
+                let mut addr = self.disassembly_addr.unwrap_or(current.pc);
+                for _ in 0..lines.unsigned_abs() {
+                    addr = if lines < 0 {
+                        Disassembler::previous_pc(&current.memory, addr)
+                    } else {
+                        Disassembler::next_pc(&current.memory, addr)
+                    };
                 }
-            },
 
-            _ => {}
+                self.disassembly_addr = Some(addr);
+            }
+            Pane::Memory => {
+                let addr = self.memory_addr.unwrap_or(current.reg(SP));
+                self.memory_addr = Some(addr.wrapping_add_signed(lines * 16));
+            }
+            _ => {
+                let output = output_text(current, self.focus);
+
+                // unset once it's back where it starts, to follow the output again
+                let bottom = bottom_line(&output, height);
+                let start = default_scroll(&output, self.focus, height);
+                let top = self.output_scroll[self.focus as usize].unwrap_or(start);
+                let top = top.saturating_add_signed(lines as i16).min(bottom);
+
+                self.output_scroll[self.focus as usize] = (top != start).then_some(top);
+            }
+        }
+    }
+
+    // moves the focused pane to the next match of the last search
+    fn search(&mut self) {
+        let pattern = self.last_search.as_str();
+        if pattern.is_empty() {
+            return;
+        }
+
+        let current = &self.time_travel.current;
+
+        match self.focus {
+            Pane::Disassembly | Pane::Memory => {
+                let start = self.disassembly_addr.unwrap_or(current.pc);
+                if let Some(addr) = current.memory.disassembler.search(
+                    &current.memory,
+                    start,
+                    pattern,
+                    SEARCH_LIMIT,
+                ) {
+                    self.focus_disassembly();
+                    self.disassembly_addr = Some(addr);
+                }
+            }
+            _ => {
+                let output = output_text(current, self.focus);
+
+                let height = self.pane_heights[self.focus as usize].saturating_sub(2);
+                let top = self.output_scroll[self.focus as usize]
+                    .unwrap_or_else(|| default_scroll(&output, self.focus, height));
+
+                // searches from the line after the top of the pane, wrapping around
+                let lines: Vec<&str> = output.lines().collect();
+                let found = (1..=lines.len())
+                    .map(|i| (top as usize + i) % lines.len())
+                    .find(|&i| lines[i].contains(pattern));
+
+                if let Some(line) = found {
+                    self.output_scroll[self.focus as usize] = Some(line as u16);
+                }
+            }
         }
     }
 }
 
 impl Drop for App {
     fn drop(&mut self) {
+        self.history.save();
+
         crossterm::terminal::disable_raw_mode().unwrap();
         crossterm::execute!(
             self.terminal.backend_mut(),
@@ -0,0 +1,250 @@
+// the names, help text, history and tab completion of the debugger's command bar
+
+use std::{fs, path::PathBuf};
+
+use super::config::Keys;
+
+pub struct Command {
+    pub names: &'static [&'static str],
+    pub usage: &'static str,
+    pub description: &'static str,
+}
+
+pub const COMMANDS: &[Command] = &[
+    Command {
+        names: &["s", "step"],
+        usage: "[n]",
+        description: "step n instructions, backwards if negative",
+    },
+    Command {
+        names: &["so", "stepover"],
+        usage: "",
+        description: "step, running calls until they return",
+    },
+    Command {
+        names: &["fin", "finish"],
+        usage: "",
+        description: "run until the current function returns",
+    },
+    Command {
+        names: &["rtc"],
+        usage: "[addr|symbol]",
+        description: "run to an address, or the disassembly cursor",
+    },
+    Command {
+        names: &["a", "auto"],
+        usage: "[delay]",
+        description: "step automatically every delay milliseconds",
+    },
+    Command {
+        names: &["sa", "stopauto"],
+        usage: "",
+        description: "stop stepping automatically",
+    },
+    Command {
+        names: &["n", "next"],
+        usage: "",
+        description: "run until the breakpoint, or the end of the program",
+    },
+    Command {
+        names: &["goto"],
+        usage: "<n>",
+        description: "go to the point after n instructions, back or forwards",
+    },
+    Command {
+        names: &["bp"],
+        usage: "[addr|symbol|syscall|obj:name]",
+        description: "set the breakpoint, or clear it without an argument",
+    },
+    Command {
+        names: &["objects"],
+        usage: "",
+        description: "list the loaded objects and the PLT entries bound",
+    },
+    Command {
+        names: &["mem"],
+        usage: "[addr|symbol]",
+        description: "show memory at an address, or follow the stack pointer",
+    },
+    Command {
+        names: &["set"],
+        usage: "<addr|symbol> <bytes...>",
+        description: "overwrite memory with hex bytes",
+    },
+    Command {
+        names: &["patch"],
+        usage: "<addr|symbol> <instruction>",
+        description: "assemble an instruction over the code, like li a0, 0",
+    },
+    Command {
+        names: &["p", "print"],
+        usage: "<expr>",
+        description: "print an expression, like *(u32)(sp + 8) or &main",
+    },
+    Command {
+        names: &["help"],
+        usage: "",
+        description: "show this help",
+    },
+];
+
+pub fn help(keys: &Keys) -> String {
+    let mut help = String::new();
+
+    for command in COMMANDS {
+        let names = command
+            .names
+            .iter()
+            .map(|name| format!(":{name}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let usage = format!("{names} {}", command.usage);
+        help.push_str(&format!("{usage:36} {}\n", command.description));
+    }
+
+    help.push_str(&format!(
+        "{:36} {}\n",
+        "/pattern", "search the focused pane"
+    ));
+    help.push('\n');
+    help.push_str(&format!(
+        "{}/{}: step forwards/backwards   {}: toggle float registers   {}: quit\n",
+        keys.step, keys.step_back, keys.float_registers, keys.quit
+    ));
+    help.push_str("tab: focus the next pane   up/down/page up/page down: scroll\n");
+    help.push_str("home: stop scrolling   enter: run to the disassembly cursor\n");
+    help.push_str("right: follow the jump under the cursor   left: go back\n");
+    help.push_str("left/right on the timeline: move back/forwards in time\n");
+
+    help
+}
+
+/// Completes the last word of `line`, a command name if it is the first word and a symbol name
+/// otherwise. Returns the completed line and, if the completion is ambiguous, the candidates.
+pub fn complete<'a>(line: &str, symbols: impl Iterator<Item = &'a str>) -> (String, Vec<String>) {
+    let Some(body) = line.strip_prefix(':') else {
+        return (line.to_string(), Vec::new());
+    };
+
+    let start = body.rfind(' ').map_or(0, |i| i + 1);
+    let word = &body[start..];
+
+    let mut candidates: Vec<String> = if start == 0 {
+        COMMANDS
+            .iter()
+            .flat_map(|command| command.names.iter())
+            .filter(|name| name.starts_with(word))
+            .map(|name| name.to_string())
+            .collect()
+    } else {
+        symbols
+            .filter(|symbol| symbol.starts_with(word))
+            .map(|symbol| symbol.to_string())
+            .collect()
+    };
+    candidates.sort_unstable();
+    candidates.dedup();
+
+    let completed = match candidates.as_slice() {
+        [] => return (line.to_string(), Vec::new()),
+        [candidate] => format!("{candidate} "),
+        [first, rest @ ..] => rest.iter().fold(first.clone(), |prefix, candidate| {
+            let len = prefix
+                .chars()
+                .zip(candidate.chars())
+                .take_while(|(a, b)| a == b)
+                .count();
+            prefix.chars().take(len).collect()
+        }),
+    };
+
+    if candidates.len() == 1 {
+        candidates.clear();
+    }
+
+    (format!(":{}{completed}", &body[..start]), candidates)
+}
+
+/// Commands entered in earlier sessions are kept in ~/.puck_history
+pub struct History {
+    entries: Vec<String>,
+    // the entry being shown while moving through the history
+    index: Option<usize>,
+    path: Option<PathBuf>,
+}
+
+// the number of commands kept in the history file
+const HISTORY_LIMIT: usize = 1000;
+
+impl History {
+    pub fn load() -> History {
+        let path = std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".puck_history"));
+        let entries = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|history| history.lines().map(str::to_string).collect())
+            .unwrap_or_default();
+
+        History {
+            entries,
+            index: None,
+            path,
+        }
+    }
+
+    pub fn push(&mut self, command: &str) {
+        self.index = None;
+
+        if command.len() > 1 && self.entries.last().map(String::as_str) != Some(command) {
+            self.entries.push(command.to_string());
+        }
+    }
+
+    /// The entry before the one currently shown
+    pub fn previous(&mut self) -> Option<&str> {
+        let index = match self.index {
+            Some(0) => 0,
+            Some(index) => index - 1,
+            None => self.entries.len().checked_sub(1)?,
+        };
+
+        self.index = Some(index);
+        Some(&self.entries[index])
+    }
+
+    /// The entry after the one currently shown, or None once past the newest entry
+    pub fn next(&mut self) -> Option<&str> {
+        let index = self.index? + 1;
+
+        if index < self.entries.len() {
+            self.index = Some(index);
+            Some(&self.entries[index])
+        } else {
+            self.index = None;
+            None
+        }
+    }
+
+    pub fn is_browsing(&self) -> bool {
+        self.index.is_some()
+    }
+
+    pub fn reset(&mut self) {
+        self.index = None;
+    }
+
+    pub fn save(&self) {
+        let Some(ref path) = self.path else {
+            return;
+        };
+
+        let start = self.entries.len().saturating_sub(HISTORY_LIMIT);
+        let mut history = self.entries[start..].join("\n");
+        history.push('\n');
+
+        if let Err(e) = fs::write(path, history) {
+            log::warn!("Failed to save command history: {e}");
+        }
+    }
+}
@@ -0,0 +1,181 @@
+// the layout, colors and keys of the debugger, read from puck.toml in the user's config
+// directory. Every setting is optional, for example:
+//
+// [layout]
+// top_height = 60
+// registers_width = 30
+// panes = ["disassembly", "registers", "stdout", "backtrace"]
+//
+// [colors]
+// focus = "green"
+// highlight = "blue"
+//
+// [keys]
+// step = "n"
+// step_back = "p"
+
+use std::{fs, io, path::PathBuf, str::FromStr};
+
+use anyhow::{bail, Context, Result};
+use ratatui::style::{Color, Modifier, Style};
+use serde::{Deserialize, Deserializer};
+
+use super::Pane;
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub layout: LayoutConfig,
+    pub colors: Colors,
+    pub keys: Keys,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LayoutConfig {
+    /// Percentage of the height given to the disassembly and memory panes, the output panes get
+    /// the rest
+    pub top_height: u16,
+    pub registers_width: u16,
+    /// The panes that are shown, the others are hidden and skipped when changing focus. The
+    /// Profiler pane only appears while auto stepping.
+    pub panes: Vec<Pane>,
+}
+
+impl Default for LayoutConfig {
+    fn default() -> Self {
+        LayoutConfig {
+            top_height: 70,
+            registers_width: 28,
+            panes: vec![
+                Pane::Disassembly,
+                Pane::Memory,
+                Pane::Registers,
+                Pane::Stdout,
+                Pane::Stderr,
+                Pane::Syscalls,
+                Pane::Profiler,
+                Pane::Timeline,
+            ],
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Colors {
+    /// Border of the focused pane
+    #[serde(deserialize_with = "color")]
+    pub focus: Color,
+    #[serde(deserialize_with = "color")]
+    pub border: Color,
+    /// Background of the current instruction and changed registers, which are shown reversed if
+    /// this is unset
+    #[serde(deserialize_with = "optional_color")]
+    pub highlight: Option<Color>,
+}
+
+impl Colors {
+    pub fn highlight_style(&self) -> Style {
+        let style = Style::default().add_modifier(Modifier::BOLD);
+
+        match self.highlight {
+            Some(color) => style.bg(color),
+            None => style.add_modifier(Modifier::REVERSED),
+        }
+    }
+}
+
+impl Default for Colors {
+    fn default() -> Self {
+        Colors {
+            focus: Color::Yellow,
+            border: Color::Reset,
+            highlight: None,
+        }
+    }
+}
+
+// colors are names like "yellow" or "lightblue", hex like "#ff8000", or an indexed color
+fn color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    Color::from_str(&name).map_err(|_| serde::de::Error::custom(format!("unknown color {name}")))
+}
+
+fn optional_color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Color>, D::Error> {
+    color(deserializer).map(Some)
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct Keys {
+    pub step: char,
+    pub step_back: char,
+    pub float_registers: char,
+    pub quit: char,
+}
+
+impl Default for Keys {
+    fn default() -> Self {
+        Keys {
+            step: 'j',
+            step_back: 'k',
+            float_registers: 'f',
+            quit: 'q',
+        }
+    }
+}
+
+impl Config {
+    /// Reads `$XDG_CONFIG_HOME/puck/puck.toml`, or `~/.config/puck/puck.toml`. A missing file
+    /// gives the default config.
+    pub fn load() -> Result<Config> {
+        let Some(path) = config_path() else {
+            return Ok(Config::default());
+        };
+
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(e).with_context(|| format!("could not read {}", path.display())),
+        };
+
+        let config: Config =
+            toml::from_str(&text).with_context(|| format!("invalid config {}", path.display()))?;
+
+        if config.layout.top_height > 100 {
+            bail!(
+                "layout.top_height is a percentage, got {}",
+                config.layout.top_height
+            );
+        }
+
+        let keys = &config.keys;
+        let mut chars = [keys.step, keys.step_back, keys.float_registers, keys.quit];
+        chars.sort_unstable();
+        if chars.windows(2).any(|pair| pair[0] == pair[1])
+            || chars.contains(&':')
+            || chars.contains(&'/')
+        {
+            bail!(
+                "keys in {} have to be different, and not `:` or `/`",
+                path.display()
+            );
+        }
+
+        Ok(config)
+    }
+
+    pub fn is_visible(&self, pane: Pane) -> bool {
+        self.layout.panes.contains(&pane)
+    }
+}
+
+fn config_path() -> Option<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+    Some(config_dir.join("puck").join("puck.toml"))
+}
@@ -0,0 +1,95 @@
+// the Timeline pane, a bar covering every instruction executed so far. Markers are collected as
+// the program runs, so they stay after stepping back to before them.
+
+use std::collections::BTreeSet;
+
+use ratatui::{
+    style::{Color, Style},
+    text::{Line, Span},
+};
+use remu::system::{Emulator, Event, Syscall};
+
+#[derive(Default)]
+pub struct Timeline {
+    /// The furthest the program has run, in instructions
+    pub end: u64,
+    syscalls: BTreeSet<u64>,
+    // writes to stdout and stderr
+    writes: BTreeSet<u64>,
+    breakpoints: BTreeSet<u64>,
+}
+
+impl Timeline {
+    /// Adds the syscalls the emulator has made so far
+    pub fn update(&mut self, emulator: &Emulator) {
+        self.end = self.end.max(emulator.inst_counter);
+
+        for record in emulator.events() {
+            let Event::Syscall(ref syscall) = record.event else {
+                continue;
+            };
+            let is_write = matches!(syscall.syscall, Syscall::Write | Syscall::Writev);
+
+            if is_write && matches!(syscall.args[0], 1 | 2) {
+                self.writes.insert(record.inst_count);
+            } else {
+                self.syscalls.insert(record.inst_count);
+            }
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, inst_count: u64) {
+        self.breakpoints.insert(inst_count);
+    }
+
+    /// The number of instructions each cell of a bar `width` cells wide covers
+    pub fn cell_size(&self, width: u16) -> u64 {
+        (self.end / width.max(1) as u64).max(1)
+    }
+
+    /// The bar, `width` cells wide with the one containing `position` highlighted, and a legend
+    pub fn lines(&self, position: u64, width: u16, highlight: Style) -> Vec<Line<'static>> {
+        let width = width.max(1) as u64;
+        let end = self.end.max(1);
+        // multiplied first, so short programs still spread across the whole bar
+        let cell_start = |cell: u64| (cell as u128 * end as u128 / width as u128) as u64;
+        let position_cell = (position as u128 * width as u128 / end as u128).min(width as u128 - 1);
+
+        let bar = (0..width)
+            .map(|cell| {
+                let range = cell_start(cell)..cell_start(cell + 1).max(cell_start(cell) + 1);
+                let has = |markers: &BTreeSet<u64>| markers.range(range.clone()).next().is_some();
+
+                let (symbol, color) = if has(&self.breakpoints) {
+                    ("●", Color::Red)
+                } else if has(&self.writes) {
+                    ("┃", Color::Green)
+                } else if has(&self.syscalls) {
+                    ("│", Color::Blue)
+                } else {
+                    ("─", Color::Reset)
+                };
+
+                let style = if cell as u128 == position_cell {
+                    highlight
+                } else {
+                    Style::default().fg(color)
+                };
+
+                Span::styled(symbol, style)
+            })
+            .collect::<Vec<_>>();
+
+        let legend = vec![
+            Span::raw(format!("{position} of {} instructions   ", self.end)),
+            Span::styled("│", Style::default().fg(Color::Blue)),
+            Span::raw(" syscall  "),
+            Span::styled("┃", Style::default().fg(Color::Green)),
+            Span::raw(" output  "),
+            Span::styled("●", Style::default().fg(Color::Red)),
+            Span::raw(" breakpoint"),
+        ];
+
+        vec![Line::from(bar), Line::from(legend)]
+    }
+}
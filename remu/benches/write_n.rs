@@ -0,0 +1,43 @@
+// compares Memory::write_n against storing one byte at a time, for every memory backend
+//
+// run with:
+// cargo bench -p remu --bench write_n
+
+use std::time::Instant;
+
+use remu::memory::{Memory, MemoryLayout};
+
+const LEN: u64 = 16 * 1024 * 1024;
+
+fn main() {
+    let data: Vec<u8> = (0..LEN).map(|i| i as u8).collect();
+
+    for layout in [
+        MemoryLayout::Paged,
+        MemoryLayout::Flat { size: 2 * LEN },
+        MemoryLayout::Cow,
+    ] {
+        let mut memory = Memory::new(layout);
+        let addr = memory.mmap(0, LEN) as u64;
+
+        let start = Instant::now();
+        for (i, byte) in data.iter().enumerate() {
+            memory.store(addr + i as u64, *byte).unwrap();
+        }
+        let bytewise = start.elapsed();
+
+        let start = Instant::now();
+        memory.write_n(&data, addr, LEN).unwrap();
+        let write = start.elapsed();
+
+        let start = Instant::now();
+        memory.write_n(&[], addr, LEN).unwrap();
+        let zero = start.elapsed();
+
+        println!(
+            "{layout:?}: {} MiB bytewise: {bytewise:?}, write_n: {write:?}, zero fill: {zero:?} ({:.0}x faster)",
+            LEN >> 20,
+            bytewise.as_secs_f64() / write.as_secs_f64(),
+        );
+    }
+}
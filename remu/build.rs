@@ -0,0 +1,10 @@
+// the jit generates x86_64 code, so the `jit` cfg only turns it on for x86_64 targets. Elsewhere
+// the feature does nothing and `run(true)` falls back to the interpreter.
+fn main() {
+    println!("cargo::rustc-check-cfg=cfg(jit)");
+
+    let x86_64 = std::env::var("CARGO_CFG_TARGET_ARCH").is_ok_and(|arch| arch == "x86_64");
+    if x86_64 && std::env::var_os("CARGO_FEATURE_JIT").is_some() {
+        println!("cargo::rustc-cfg=jit");
+    }
+}
@@ -0,0 +1,566 @@
+// assembles one instruction at a time from the syntax the disassembler prints, for patching a
+// running guest from a debugger, e.g. `nop`, `li a0, 0`, `ld a0, 8(sp)` or `j 10a4c`. Jump and
+// branch targets are absolute addresses in hex, like the disassembler shows them.
+//
+// Everything is encoded uncompressed. Only the instructions the emulator decodes are supported,
+// along with a few common pseudo instructions.
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use crate::{
+    instruction::{
+        b_type, i_type, j_type, r_type, s_type, u_type, OP, OP_32, OP_AMO, OP_AUIPC, OP_FP, OP_IMM,
+        OP_IMM_32, OP_JALR, OP_LOAD, OP_LOAD_FP, OP_LUI, OP_STORE, OP_STORE_FP, OP_SYSTEM,
+    },
+    register::{FReg, Reg, RA},
+};
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum AsmError {
+    #[error("unknown instruction `{0}`")]
+    UnknownInstruction(String),
+
+    #[error("expected {0} operands")]
+    OperandCount(usize),
+
+    #[error("invalid operand `{0}`")]
+    InvalidOperand(String),
+
+    #[error("`{0}` is out of range")]
+    OutOfRange(String),
+}
+
+// how the operands of an instruction are written and encoded
+#[derive(Clone, Copy)]
+enum Format {
+    // (opcode, funct3, funct7) of rd, rs1, rs2
+    R(u32, u32, u32),
+    // (opcode, funct3) of rd, rs1, imm
+    I(u32, u32),
+    // (opcode, funct3, funct6, bits) of rd, rs1, shamt, where the shift amount has up to `bits`
+    // bits and funct6 is above it
+    Shift(u32, u32, u32, u32),
+    // (opcode, funct3) of rd, offset(rs1)
+    Load(u32, u32),
+    // (opcode, funct3) of rs2, offset(rs1)
+    Store(u32, u32),
+    // (funct3) of rs1, rs2, target
+    Branch(u32),
+    // (opcode) of rd, imm, where imm is the upper 20 bits
+    Upper(u32),
+    // [rd,] target
+    Jal,
+    // rd, offset(rs1), or only rs1
+    Jalr,
+    // (funct3, funct5) of rd, rs2, (rs1)
+    Amo(u32, u32),
+    // (funct3) of rd, (rs1)
+    Lr(u32),
+    // (funct3) of rd, csr, rs1
+    Csr(u32),
+    // (funct3) of rd, csr, uimm
+    CsrImm(u32),
+    // (funct3, funct7, int_rd) of float rd, rs1 and rs2, or an integer rd if int_rd
+    Float(u32, u32, bool),
+    // the whole instruction, which has no operands
+    Fixed(u32),
+}
+
+use Format::*;
+
+const INSTRUCTIONS: &[(&str, Format)] = &[
+    ("fence", Fixed(0x0ff0000f)),
+    ("fence.i", Fixed(0x0000100f)),
+    ("ecall", Fixed(0x00000073)),
+    ("ebreak", Fixed(0x00100073)),
+    ("mret", Fixed(0x30200073)),
+    ("sret", Fixed(0x10200073)),
+    ("wfi", Fixed(0x10500073)),
+    ("sfence.vma", Fixed(0x12000073)),
+    ("lui", Upper(OP_LUI)),
+    ("auipc", Upper(OP_AUIPC)),
+    ("jal", Jal),
+    ("jalr", Jalr),
+    ("beq", Branch(0b000)),
+    ("bne", Branch(0b001)),
+    ("blt", Branch(0b100)),
+    ("bge", Branch(0b101)),
+    ("bltu", Branch(0b110)),
+    ("bgeu", Branch(0b111)),
+    ("lb", Load(OP_LOAD, 0b000)),
+    ("lw", Load(OP_LOAD, 0b010)),
+    ("ld", Load(OP_LOAD, 0b011)),
+    ("lbu", Load(OP_LOAD, 0b100)),
+    ("lhu", Load(OP_LOAD, 0b101)),
+    ("lwu", Load(OP_LOAD, 0b110)),
+    ("flw", Load(OP_LOAD_FP, 0b010)),
+    ("fld", Load(OP_LOAD_FP, 0b011)),
+    ("sb", Store(OP_STORE, 0b000)),
+    ("sh", Store(OP_STORE, 0b001)),
+    ("sw", Store(OP_STORE, 0b010)),
+    ("sd", Store(OP_STORE, 0b011)),
+    ("fsw", Store(OP_STORE_FP, 0b010)),
+    ("fsd", Store(OP_STORE_FP, 0b011)),
+    ("addi", I(OP_IMM, 0b000)),
+    ("slti", I(OP_IMM, 0b010)),
+    ("sltiu", I(OP_IMM, 0b011)),
+    ("xori", I(OP_IMM, 0b100)),
+    ("ori", I(OP_IMM, 0b110)),
+    ("andi", I(OP_IMM, 0b111)),
+    ("addiw", I(OP_IMM_32, 0b000)),
+    ("slli", Shift(OP_IMM, 0b001, 0b000000, 6)),
+    ("srli", Shift(OP_IMM, 0b101, 0b000000, 6)),
+    ("srai", Shift(OP_IMM, 0b101, 0b010000, 6)),
+    ("slliw", Shift(OP_IMM_32, 0b001, 0b000000, 5)),
+    ("srliw", Shift(OP_IMM_32, 0b101, 0b000000, 5)),
+    ("sraiw", Shift(OP_IMM_32, 0b101, 0b010000, 5)),
+    ("add", R(OP, 0b000, 0b0000000)),
+    ("sub", R(OP, 0b000, 0b0100000)),
+    ("sll", R(OP, 0b001, 0b0000000)),
+    ("slt", R(OP, 0b010, 0b0000000)),
+    ("sltu", R(OP, 0b011, 0b0000000)),
+    ("xor", R(OP, 0b100, 0b0000000)),
+    ("srl", R(OP, 0b101, 0b0000000)),
+    ("sra", R(OP, 0b101, 0b0100000)),
+    ("or", R(OP, 0b110, 0b0000000)),
+    ("and", R(OP, 0b111, 0b0000000)),
+    ("mul", R(OP, 0b000, 0b0000001)),
+    ("mulhu", R(OP, 0b011, 0b0000001)),
+    ("div", R(OP, 0b100, 0b0000001)),
+    ("divu", R(OP, 0b101, 0b0000001)),
+    ("remu", R(OP, 0b111, 0b0000001)),
+    ("addw", R(OP_32, 0b000, 0b0000000)),
+    ("subw", R(OP_32, 0b000, 0b0100000)),
+    ("sllw", R(OP_32, 0b001, 0b0000000)),
+    ("srlw", R(OP_32, 0b101, 0b0000000)),
+    ("sraw", R(OP_32, 0b101, 0b0100000)),
+    ("divw", R(OP_32, 0b100, 0b0000001)),
+    ("divuw", R(OP_32, 0b101, 0b0000001)),
+    ("remw", R(OP_32, 0b110, 0b0000001)),
+    ("remuw", R(OP_32, 0b111, 0b0000001)),
+    ("amoadd.w", Amo(0b010, 0b00000)),
+    ("amoswap.w", Amo(0b010, 0b00001)),
+    ("sc.w", Amo(0b010, 0b00011)),
+    ("amoor.w", Amo(0b010, 0b01000)),
+    ("amomaxu.w", Amo(0b010, 0b11100)),
+    ("amoadd.d", Amo(0b011, 0b00000)),
+    ("amoswap.d", Amo(0b011, 0b00001)),
+    ("sc.d", Amo(0b011, 0b00011)),
+    ("amomaxu.d", Amo(0b011, 0b11100)),
+    ("lr.w", Lr(0b010)),
+    ("lr.d", Lr(0b011)),
+    ("fle.d", Float(0b000, 0b1010001, true)),
+    ("fdiv.d", Float(0b111, 0b0001101, false)),
+    ("csrrw", Csr(0b001)),
+    ("csrrs", Csr(0b010)),
+    ("csrrc", Csr(0b011)),
+    ("csrrwi", CsrImm(0b101)),
+    ("csrrsi", CsrImm(0b110)),
+    ("csrrci", CsrImm(0b111)),
+];
+
+/// Assembles the instruction `text` to be placed at `pc`, returning its little endian bytes.
+/// Pseudo instructions like `li` can assemble to more than one instruction.
+pub fn assemble(text: &str, pc: u64) -> Result<Vec<u8>, AsmError> {
+    let text = text.trim();
+    let (mnemonic, operands) = match text.split_once(char::is_whitespace) {
+        Some((mnemonic, operands)) => (mnemonic, operands.trim()),
+        None => (text, ""),
+    };
+    let operands: Vec<&str> = match operands {
+        "" => Vec::new(),
+        operands => operands.split(',').map(str::trim).collect(),
+    };
+
+    let instructions = match pseudo(mnemonic, &operands, pc)? {
+        Some(instructions) => instructions,
+        None => {
+            let (_, format) = INSTRUCTIONS
+                .iter()
+                .find(|(name, _)| *name == mnemonic)
+                .ok_or_else(|| AsmError::UnknownInstruction(mnemonic.to_string()))?;
+            Vec::from([encode(*format, &operands, pc)?])
+        }
+    };
+
+    Ok(instructions
+        .into_iter()
+        .flat_map(u32::to_le_bytes)
+        .collect())
+}
+
+// the pseudo instructions, or None if `mnemonic` isn't one
+fn pseudo(mnemonic: &str, operands: &[&str], pc: u64) -> Result<Option<Vec<u32>>, AsmError> {
+    let addi = I(OP_IMM, 0b000);
+    let one =
+        |format, operands: &[&str]| encode(format, operands, pc).map(|inst| Vec::from([inst]));
+
+    let instructions = match (mnemonic, operands) {
+        ("nop", []) => one(addi, &["x0", "x0", "0"])?,
+        ("mv", [rd, rs]) => one(addi, &[rd, rs, "0"])?,
+        ("not", [rd, rs]) => one(I(OP_IMM, 0b100), &[rd, rs, "-1"])?,
+        ("neg", [rd, rs]) => one(R(OP, 0b000, 0b0100000), &[rd, "x0", rs])?,
+        ("j", [target]) => one(Jal, &["x0", target])?,
+        ("jr", [rs]) => one(Jalr, &["x0", &format!("0({rs})")])?,
+        ("ret", []) => one(Jalr, &["x0", "0(ra)"])?,
+        ("beqz", [rs, target]) => one(Branch(0b000), &[rs, "x0", target])?,
+        ("bnez", [rs, target]) => one(Branch(0b001), &[rs, "x0", target])?,
+        ("li", [rd, imm]) => li(reg(rd)?, imm)?,
+        ("nop" | "ret", _) => return Err(AsmError::OperandCount(0)),
+        ("j" | "jr", _) => return Err(AsmError::OperandCount(1)),
+        ("mv" | "not" | "neg" | "beqz" | "bnez" | "li", _) => {
+            return Err(AsmError::OperandCount(2))
+        }
+        _ => return Ok(None),
+    };
+
+    Ok(Some(instructions))
+}
+
+// loads any 32-bit signed immediate, with an addi alone if it fits in 12 bits
+fn li(rd: Reg, imm: &str) -> Result<Vec<u32>, AsmError> {
+    let value = immediate(imm)?;
+    if i32::try_from(value).is_err() {
+        return Err(AsmError::OutOfRange(imm.to_string()));
+    }
+
+    let lo = (value << 52) >> 52;
+    let hi = (value - lo) >> 12;
+    if hi == 0 {
+        return Ok(Vec::from([i_type(OP_IMM, 0b000, rd, Reg(0), lo as u32)]));
+    }
+
+    Ok(Vec::from([
+        u_type(OP_LUI, rd, hi as u32),
+        i_type(OP_IMM_32, 0b000, rd, rd, lo as u32),
+    ]))
+}
+
+fn encode(format: Format, operands: &[&str], pc: u64) -> Result<u32, AsmError> {
+    let count = match format {
+        Fixed(_) => 0,
+        Jal | Jalr => operands.len().clamp(1, 2),
+        Upper(_) | Load(..) | Store(..) | Lr(_) => 2,
+        _ => 3,
+    };
+    if operands.len() != count {
+        return Err(AsmError::OperandCount(count));
+    }
+
+    Ok(match format {
+        R(opcode, funct3, funct7) => r_type(
+            opcode,
+            funct3,
+            funct7,
+            reg(operands[0])?.0,
+            reg(operands[1])?.0,
+            reg(operands[2])?.0,
+        ),
+        I(opcode, funct3) => {
+            let imm = signed(operands[2], 12)?;
+            i_type(opcode, funct3, reg(operands[0])?, reg(operands[1])?, imm)
+        }
+        Shift(opcode, funct3, funct6, bits) => {
+            let shamt = unsigned(operands[2], bits)?;
+            let rd = reg(operands[0])?;
+            i_type(opcode, funct3, rd, reg(operands[1])?, funct6 << 6 | shamt)
+        }
+        Load(opcode, funct3) => {
+            let rd = match opcode {
+                OP_LOAD_FP => freg(operands[0])?.0,
+                _ => reg(operands[0])?.0,
+            };
+            let (offset, rs1) = address(operands[1])?;
+            i_type(opcode, funct3, Reg(rd), rs1, offset)
+        }
+        Store(opcode, funct3) => {
+            let rs2 = match opcode {
+                OP_STORE_FP => freg(operands[0])?.0,
+                _ => reg(operands[0])?.0,
+            };
+            let (offset, rs1) = address(operands[1])?;
+            s_type(opcode, funct3, rs1, Reg(rs2), offset)
+        }
+        Branch(funct3) => {
+            let offset = target(operands[2], pc, 13)?;
+            b_type(funct3, reg(operands[0])?, reg(operands[1])?, offset)
+        }
+        Upper(opcode) => u_type(opcode, reg(operands[0])?, unsigned(operands[1], 20)?),
+        Jal => {
+            let (rd, target_operand) = match operands {
+                [target] => (RA, target),
+                [rd, target] => (reg(rd)?, target),
+                _ => unreachable!(),
+            };
+            j_type(rd, target(target_operand, pc, 21)?)
+        }
+        Jalr => {
+            let (rd, (offset, rs1)) = match operands {
+                [rs1] => (RA, (0, reg(rs1)?)),
+                [rd, address_operand] => (reg(rd)?, address(address_operand)?),
+                _ => unreachable!(),
+            };
+            i_type(OP_JALR, 0b000, rd, rs1, offset)
+        }
+        Amo(funct3, funct5) => {
+            let rs1 = parenthesized(operands[2])?;
+            r_type(
+                OP_AMO,
+                funct3,
+                funct5 << 2,
+                reg(operands[0])?.0,
+                rs1.0,
+                reg(operands[1])?.0,
+            )
+        }
+        Lr(funct3) => {
+            let rs1 = parenthesized(operands[1])?;
+            r_type(OP_AMO, funct3, 0b00010 << 2, reg(operands[0])?.0, rs1.0, 0)
+        }
+        Csr(funct3) => {
+            let csr = unsigned(operands[1], 12)?;
+            i_type(OP_SYSTEM, funct3, reg(operands[0])?, reg(operands[2])?, csr)
+        }
+        CsrImm(funct3) => {
+            let csr = unsigned(operands[1], 12)?;
+            let uimm = unsigned(operands[2], 5)?;
+            i_type(OP_SYSTEM, funct3, reg(operands[0])?, Reg(uimm as u8), csr)
+        }
+        Float(funct3, funct7, int_rd) => {
+            let rd = match int_rd {
+                true => reg(operands[0])?.0,
+                false => freg(operands[0])?.0,
+            };
+            let (rs1, rs2) = (freg(operands[1])?, freg(operands[2])?);
+            r_type(OP_FP, funct3, funct7, rd, rs1.0, rs2.0)
+        }
+        Fixed(inst) => inst,
+    })
+}
+
+fn reg(operand: &str) -> Result<Reg, AsmError> {
+    Reg::parse(operand).ok_or_else(|| AsmError::InvalidOperand(operand.to_string()))
+}
+
+fn freg(operand: &str) -> Result<FReg, AsmError> {
+    FReg::parse(operand).ok_or_else(|| AsmError::InvalidOperand(operand.to_string()))
+}
+
+// a decimal or 0x prefixed hex number, which can be negative
+fn immediate(operand: &str) -> Result<i64, AsmError> {
+    let (negative, digits) = match operand.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, operand),
+    };
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => digits.parse(),
+    }
+    .map_err(|_| AsmError::InvalidOperand(operand.to_string()))?;
+
+    Ok(if negative { -value } else { value })
+}
+
+// an immediate that fits in `bits` bits as a signed number
+fn signed(operand: &str, bits: u32) -> Result<u32, AsmError> {
+    let value = immediate(operand)?;
+    let limit = 1i64 << (bits - 1);
+    if !(-limit..limit).contains(&value) {
+        return Err(AsmError::OutOfRange(operand.to_string()));
+    }
+
+    Ok(value as u32)
+}
+
+// an immediate that fits in `bits` bits as an unsigned number
+fn unsigned(operand: &str, bits: u32) -> Result<u32, AsmError> {
+    let value = immediate(operand)?;
+    if !(0..1i64 << bits).contains(&value) {
+        return Err(AsmError::OutOfRange(operand.to_string()));
+    }
+
+    Ok(value as u32)
+}
+
+// the offset from `pc` to a jump or branch target, an address in hex with or without 0x
+fn target(operand: &str, pc: u64, bits: u32) -> Result<u32, AsmError> {
+    let addr = u64::from_str_radix(operand.trim_start_matches("0x"), 16)
+        .map_err(|_| AsmError::InvalidOperand(operand.to_string()))?;
+    let offset = addr.wrapping_sub(pc) as i64;
+
+    let limit = 1i64 << (bits - 1);
+    if !(-limit..limit).contains(&offset) || offset % 2 != 0 {
+        return Err(AsmError::OutOfRange(operand.to_string()));
+    }
+
+    Ok(offset as u32)
+}
+
+// `offset(rs1)`, where the offset can be left out
+fn address(operand: &str) -> Result<(u32, Reg), AsmError> {
+    let invalid = || AsmError::InvalidOperand(operand.to_string());
+
+    let (offset, rest) = operand.split_once('(').ok_or_else(invalid)?;
+    let rs1 = rest.strip_suffix(')').ok_or_else(invalid)?;
+    let offset = match offset.trim() {
+        "" => 0,
+        offset => signed(offset, 12)?,
+    };
+
+    Ok((offset, reg(rs1.trim())?))
+}
+
+// `(rs1)` or `0(rs1)`, the address of an atomic
+fn parenthesized(operand: &str) -> Result<Reg, AsmError> {
+    match address(operand)? {
+        (0, rs1) => Ok(rs1),
+        _ => Err(AsmError::InvalidOperand(operand.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{instruction::Inst, register::A0};
+
+    fn decode(text: &str, pc: u64) -> Vec<Inst> {
+        assemble(text, pc)
+            .unwrap()
+            .chunks(4)
+            .map(|bytes| Inst::decode(u32::from_le_bytes(bytes.try_into().unwrap())).0)
+            .collect()
+    }
+
+    #[test]
+    fn assembles() {
+        assert_eq!(assemble("nop", 0), Ok(Vec::from(0x13u32.to_le_bytes())));
+        assert_eq!(
+            decode("li a0, 0", 0),
+            [Inst::Addi {
+                rd: A0,
+                rs1: Reg(0),
+                imm: 0
+            }]
+        );
+        assert_eq!(
+            decode("li a0, 0x12345fff", 0),
+            [
+                Inst::Lui {
+                    rd: A0,
+                    imm: 0x12346000
+                },
+                Inst::Addiw {
+                    rd: A0,
+                    rs1: A0,
+                    imm: -1
+                }
+            ]
+        );
+        assert_eq!(
+            decode("j 1000", 0x1ff0),
+            [Inst::Jal {
+                rd: Reg(0),
+                offset: -0xff0
+            }]
+        );
+        assert_eq!(
+            decode("bne a0, x0, 0x10800", 0x10000),
+            [Inst::Bne {
+                rs1: A0,
+                rs2: Reg(0),
+                offset: 0x800
+            }]
+        );
+        assert_eq!(
+            decode("sd ra, -8(sp)", 0),
+            [Inst::Sd {
+                rs1: Reg(2),
+                rs2: RA,
+                offset: -8
+            }]
+        );
+        assert_eq!(
+            decode("srai a0, a0, 63", 0),
+            [Inst::Srai {
+                rd: A0,
+                rs1: A0,
+                shamt: 63
+            }]
+        );
+        assert_eq!(
+            decode("amoswap.d a0, a1, (a2)", 0),
+            [Inst::Amoswapd {
+                rd: A0,
+                rs1: Reg(12),
+                rs2: Reg(11)
+            }]
+        );
+        assert_eq!(
+            decode("csrrs a0, 0xc00, x0", 0),
+            [Inst::Csrrs {
+                rd: A0,
+                rs1: Reg(0),
+                csr: 0xc00
+            }]
+        );
+        assert_eq!(
+            decode("ret", 0),
+            [Inst::Jalr {
+                rd: Reg(0),
+                rs1: RA,
+                offset: 0
+            }]
+        );
+    }
+
+    #[test]
+    fn disassembly_round_trip() {
+        let pc = 0x10000;
+        let round_trip = |inst: Inst| {
+            // the assembler has no conversions
+            if !matches!(
+                inst,
+                Inst::Error(_) | Inst::Fcvtdlu { .. } | Inst::Fcvtds { .. }
+            ) {
+                assert_eq!(decode(&inst.fmt(pc), pc), [inst], "{}", inst.fmt(pc));
+            }
+        };
+
+        for inst in 0..=u16::MAX {
+            round_trip(Inst::decode(inst as u32).0);
+        }
+
+        let mut inst: u32 = 0x87654321;
+        for _ in 0..1 << 16 {
+            inst ^= inst << 13;
+            inst ^= inst >> 17;
+            inst ^= inst << 5;
+            round_trip(Inst::decode(inst | 0b11).0);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            assemble("frob a0", 0),
+            Err(AsmError::UnknownInstruction("frob".into()))
+        );
+        assert_eq!(assemble("add a0, a1", 0), Err(AsmError::OperandCount(3)));
+        assert_eq!(
+            assemble("addi a0, q1, 1", 0),
+            Err(AsmError::InvalidOperand("q1".into()))
+        );
+        assert_eq!(
+            assemble("addi a0, a0, 2048", 0),
+            Err(AsmError::OutOfRange("2048".into()))
+        );
+        assert_eq!(
+            assemble("beq a0, a1, 1001", 0),
+            Err(AsmError::OutOfRange("1001".into()))
+        );
+    }
+}
@@ -1,6 +1,8 @@
 // source:
 // https://android.googlesource.com/platform/bionic/+/android-7.1.1_r11/libc/kernel/uapi/linux/auxvec.h
 
+use alloc::string::String;
+
 #[derive(Debug, Clone, Copy)]
 #[allow(unused)]
 pub enum Auxv {
@@ -31,3 +33,49 @@ pub enum Auxv {
 
 pub const RANDOM_BYTES: u64 = 16;
 pub struct AuxPair(pub Auxv, pub u64);
+
+/// What the guest finds in its auxiliary vector, see [`Emulator::new_with_auxv`]
+///
+/// [`Emulator::new_with_auxv`]: crate::system::Emulator::new_with_auxv
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuxvConfig {
+    /// AT_HWCAP, the ISA extensions the guest believes exist, see [`AuxvConfig::hwcap`]
+    pub hwcap: u64,
+    pub uid: u64,
+    pub euid: u64,
+    pub gid: u64,
+    pub egid: u64,
+    /// AT_CLKTCK, the frequency `times` counts in
+    pub clock_tick: u64,
+    /// AT_PLATFORM, which Linux leaves out on RISC-V
+    pub platform: Option<String>,
+    /// The bytes AT_RANDOM points to, which glibc seeds stack protectors and pointer mangling with
+    pub random: [u8; RANDOM_BYTES as usize],
+}
+
+impl AuxvConfig {
+    /// The hwcap bits of the single letter extensions in `extensions`, like `"imafdc"`. Linux
+    /// sets bit `n` for the `n`th letter of the alphabet.
+    pub fn hwcap(extensions: &str) -> u64 {
+        extensions
+            .bytes()
+            .filter(u8::is_ascii_alphabetic)
+            .map(|extension| 1 << (extension.to_ascii_lowercase() - b'a'))
+            .fold(0, |hwcap, bit| hwcap | bit)
+    }
+}
+
+impl Default for AuxvConfig {
+    fn default() -> Self {
+        AuxvConfig {
+            hwcap: AuxvConfig::hwcap("imafdc"),
+            uid: 0,
+            euid: 0,
+            gid: 0,
+            egid: 0,
+            clock_tick: 100,
+            platform: None,
+            random: core::array::from_fn(|i| i as u8),
+        }
+    }
+}
@@ -0,0 +1,159 @@
+// running one program on many inputs at once, like a fuzzing corpus or the cases of a test
+// suite.
+//
+// Emulators can be sent between threads but not shared by them, so each worker thread builds its
+// own emulator once with a setup function, which can load the program and run it up to the point
+// every input starts from, then runs each input on a clone of it. Runs don't
+// share anything: each has its own memory, file descriptors and profiler.
+
+use alloc::{string::String, vec::Vec};
+
+use rayon::prelude::*;
+
+use crate::{
+    error::RVError,
+    system::{Emulator, ProfileSnapshot},
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum BatchError {
+    #[error("could not start the batch's threads")]
+    ThreadPool(#[from] rayon::ThreadPoolBuildError),
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BatchOptions {
+    pub jit: bool,
+    /// The worker threads to run inputs on, or 0 for rayon's global pool of one per core
+    pub threads: usize,
+}
+
+/// What the guest did with one input
+#[derive(Debug)]
+pub struct BatchRun {
+    /// The exit code, or the error that stopped the guest
+    pub result: Result<u64, RVError>,
+    pub stdout: String,
+    pub stderr: String,
+    /// The profile of the whole run, which is only counted if the profiler was running or set up
+    /// to start at a label after setup
+    pub profile: ProfileSnapshot,
+}
+
+/// The runs of a batch in the order of their inputs, see [`run_batch`]
+#[derive(Debug)]
+pub struct Batch {
+    pub runs: Vec<BatchRun>,
+}
+
+impl Batch {
+    /// The profiles of every run added together
+    pub fn profile(&self) -> ProfileSnapshot {
+        self.runs
+            .iter()
+            .fold(ProfileSnapshot::default(), |total, run| total + run.profile)
+    }
+
+    /// The indices of the inputs that stopped the guest with an error, and their errors
+    pub fn errors(&self) -> impl Iterator<Item = (usize, &RVError)> {
+        self.runs
+            .iter()
+            .enumerate()
+            .filter_map(|(i, run)| Some((i, run.result.as_ref().err()?)))
+    }
+}
+
+/// Runs a clone of the emulator built by `setup` with each input as its stdin, on as many
+/// threads as [`BatchOptions::threads`]. `setup` is called once by each thread that runs an
+/// input.
+pub fn run_batch<I>(
+    setup: impl Fn() -> Emulator + Send + Sync,
+    inputs: &[I],
+    options: &BatchOptions,
+) -> Result<Batch, BatchError>
+where
+    I: AsRef<[u8]> + Sync,
+{
+    let run = || {
+        inputs
+            .par_iter()
+            .map_init(&setup, |emulator, input| {
+                let mut emulator = emulator.clone();
+                // the emulator is already built, so its stdin can only be swapped out
+                #[allow(deprecated)]
+                emulator.set_stdin(input.as_ref());
+                let result = emulator.run(options.jit);
+
+                BatchRun {
+                    result,
+                    profile: emulator.profile_snapshot(),
+                    stdout: emulator.stdout,
+                    stderr: emulator.stderr,
+                }
+            })
+            .collect()
+    };
+
+    let runs = match options.threads {
+        0 => run(),
+        threads => rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()?
+            .install(run),
+    };
+
+    Ok(Batch { runs })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::Memory;
+
+    // exits with the first byte of stdin
+    fn exit_with_input() -> Emulator {
+        let mut data = [0u8; 0x200];
+        let program: [u32; 8] = [
+            0x00000513, // li a0, 0
+            0x10000593, // li a1, 0x100
+            0x00100613, // li a2, 1
+            0x03f00893, // li a7, 63
+            0x00000073, // ecall
+            0x10004503, // lbu a0, 0x100(zero)
+            0x05d00893, // li a7, 93
+            0x00000073, // ecall
+        ];
+        for (i, inst) in program.iter().enumerate() {
+            data[i * 4..i * 4 + 4].copy_from_slice(&inst.to_le_bytes());
+        }
+
+        let mut emulator = Emulator::new(Memory::from_raw(&data));
+        emulator.profiler.running = true;
+        emulator
+    }
+
+    #[test]
+    fn batch() -> Result<(), BatchError> {
+        let inputs: Vec<[u8; 1]> = (0..32).map(|i| [i]).collect();
+        let options = BatchOptions {
+            jit: false,
+            threads: 4,
+        };
+
+        let batch = run_batch(exit_with_input, &inputs, &options)?;
+        let exit_codes: Vec<u64> = batch
+            .runs
+            .iter()
+            .map(|run| *run.result.as_ref().unwrap())
+            .collect();
+        assert_eq!(exit_codes, (0..32).collect::<Vec<u64>>());
+        assert_eq!(batch.errors().count(), 0);
+
+        let one = batch.runs[0].profile;
+        assert_eq!(one.inst_count, 8);
+        assert_eq!(batch.profile().inst_count, 32 * one.inst_count);
+        assert_eq!(batch.profile().cycle_count, 32 * one.cycle_count);
+
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use std::mem;
+use core::mem;
 
 #[derive(Clone, Debug)]
 pub struct Cache<K: Eq, V: Eq + Clone, const SIZE: usize> {
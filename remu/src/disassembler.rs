@@ -1,4 +1,10 @@
-use std::collections::HashMap;
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt::{self, Write};
 
 use elf::{
     abi::{STT_FILE, STT_FUNC, STT_NOTYPE},
@@ -8,6 +14,12 @@ use elf::{
 
 use crate::{instruction::Inst, memory::Memory};
 
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum SymbolMapError {
+    #[error("line {0} of the symbol map isn't an address followed by a name")]
+    InvalidLine(usize),
+}
+
 #[derive(Clone)]
 pub struct Disassembler {
     symbols: Vec<(u64, String)>,
@@ -20,11 +32,14 @@ impl Disassembler {
         }
     }
 
-    // offset: the address offset in memory
-    pub fn add_elf_symbols<T: EndianParse>(&mut self, elf: &ElfBytes<T>, offset: u64) {
-        // add symbols
-        let (symbol_table, string_table) = elf.symbol_table().unwrap().unwrap();
+    // offset: the address offset in memory. Returns the number of symbols added.
+    pub fn add_elf_symbols<T: EndianParse>(&mut self, elf: &ElfBytes<T>, offset: u64) -> usize {
+        // add symbols, unless the executable was stripped
+        let Ok(Some((symbol_table, string_table))) = elf.symbol_table() else {
+            return 0;
+        };
 
+        let len = self.symbols.len();
         for symbol in symbol_table.iter() {
             let symtype = symbol.st_symtype();
             if symtype == STT_FUNC || symtype == STT_NOTYPE {
@@ -34,6 +49,8 @@ impl Disassembler {
             }
         }
 
+        let count = self.symbols.len() - len;
+
         // also push .text and .plt start sections
         if let Some(plt_header) = elf.section_header_by_name(".plt").unwrap() {
             self.symbols
@@ -48,6 +65,48 @@ impl Disassembler {
         //     .push((text_header.sh_addr + offset, ".text".to_string()));
 
         self.symbols.sort_unstable_by_key(|a| a.0);
+        count
+    }
+
+    /// Writes every named symbol as a line of its address in hex and its name, which
+    /// [`Disassembler::import_symbols`] reads back
+    pub fn export_symbols(&self, out: &mut impl Write) -> fmt::Result {
+        // the unnamed symbol elf symbol tables start with can't be read back
+        for (addr, name) in self.symbols.iter().filter(|(_, name)| !name.is_empty()) {
+            writeln!(out, "{addr:016x} {name}")?;
+        }
+
+        Ok(())
+    }
+
+    /// Adds the symbols of a map of lines of a hex address and a name, moved by `offset`, and
+    /// returns how many there were. The output of `nm`, with a symbol type between them, is
+    /// read too. Empty lines and lines starting with `#` are skipped.
+    pub fn import_symbols(&mut self, map: &str, offset: u64) -> Result<usize, SymbolMapError> {
+        // nothing is added if any line is invalid
+        let mut symbols = Vec::new();
+
+        for (i, line) in map.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (addr, name) = match fields[..] {
+                [addr, name] | [addr, _, name] => (addr, name),
+                _ => return Err(SymbolMapError::InvalidLine(i + 1)),
+            };
+            let addr = u64::from_str_radix(addr.trim_start_matches("0x"), 16)
+                .map_err(|_| SymbolMapError::InvalidLine(i + 1))?;
+
+            symbols.push((addr.wrapping_add(offset), name.to_string()));
+        }
+
+        let count = symbols.len();
+        self.symbols.extend(symbols);
+        self.symbols.sort_unstable_by_key(|a| a.0);
+        Ok(count)
     }
 
     pub fn disassemble_elf<T: EndianParse>(elf: &ElfBytes<T>) -> String {
@@ -55,7 +114,7 @@ impl Disassembler {
         dias.add_elf_symbols(elf, 0);
 
         let mut text_regions = Vec::new();
-        let mut instructions = HashMap::new();
+        let mut instructions = BTreeMap::new();
 
         for section_name in [".text", ".plt"] {
             // add instructions
@@ -127,6 +186,63 @@ impl Disassembler {
         writer
     }
 
+    /// Finds the first of the `limit` instructions after `start_pc` whose disassembly, including
+    /// any symbol labels, contains `pattern`. Stops early at unmapped memory.
+    pub fn search(
+        &self,
+        memory: &Memory,
+        start_pc: u64,
+        pattern: &str,
+        limit: usize,
+    ) -> Option<u64> {
+        let (_, size) = Inst::decode(memory.load(start_pc).ok()?);
+        let mut pc = start_pc + size as u64;
+
+        for _ in 0..limit {
+            let (inst, size) = Inst::decode(memory.load(pc).ok()?);
+
+            if self.disassemble_inst(inst, pc).contains(pattern) {
+                return Some(pc);
+            }
+
+            pc += size as u64;
+        }
+
+        None
+    }
+
+    /// The address of the instruction after the one at `pc`
+    pub fn next_pc(memory: &Memory, pc: u64) -> u64 {
+        let (_, size) = Inst::decode(memory.load(pc).unwrap_or(0));
+        pc + size as u64
+    }
+
+    /// The address of the instruction before the one at `pc`. Instructions can't be decoded
+    /// backwards, so this assumes a 4 byte instruction unless the bytes 4 before `pc` start a
+    /// compressed one.
+    pub fn previous_pc(memory: &Memory, pc: u64) -> u64 {
+        match Inst::decode(memory.load(pc.wrapping_sub(4)).unwrap_or(0)) {
+            (_, 4) => pc.wrapping_sub(4),
+            _ => pc.wrapping_sub(2),
+        }
+    }
+
+    /// Where the jump or branch at `pc` goes when taken, if it has a fixed target
+    pub fn jump_target(memory: &Memory, pc: u64) -> Option<u64> {
+        let (inst, _) = Inst::decode(memory.load(pc).ok()?);
+
+        match inst {
+            Inst::Jal { offset, .. }
+            | Inst::Beq { offset, .. }
+            | Inst::Bne { offset, .. }
+            | Inst::Blt { offset, .. }
+            | Inst::Bltu { offset, .. }
+            | Inst::Bge { offset, .. }
+            | Inst::Bgeu { offset, .. } => Some(pc.wrapping_add(offset as i64 as u64)),
+            _ => None,
+        }
+    }
+
     pub fn get_symbol_at_addr(&self, addr: u64) -> Option<String> {
         self.symbols
             .binary_search_by_key(&addr, |a| a.0)
@@ -134,6 +250,20 @@ impl Disassembler {
             .ok()
     }
 
+    /// The closest symbol at or before `addr`, and how far past it `addr` is
+    pub fn get_symbol_containing(&self, addr: u64) -> Option<(&str, u64)> {
+        let idx = self
+            .symbols
+            .partition_point(|a| a.0 <= addr)
+            .checked_sub(1)?;
+        let (start, name) = &self.symbols[idx];
+        Some((name, addr - start))
+    }
+
+    pub fn symbol_names(&self) -> impl Iterator<Item = &str> {
+        self.symbols.iter().map(|symbol| symbol.1.as_str())
+    }
+
     pub fn get_symbol_addr(&self, symbol: &str) -> Option<u64> {
         self.symbols.iter().find(|x| x.1 == symbol).map(|x| x.0)
     }
@@ -141,14 +271,12 @@ impl Disassembler {
     fn disassemble_inst(&self, inst: Inst, pc: u64) -> String {
         let mut writer = String::new();
 
-        let mut idx = self.symbols.partition_point(|a| a.0 < pc);
-        if let Some(mut symbol) = self.symbols.get(idx) {
-            while symbol.0 == pc {
-                writer.push_str(&format!("{}:\n", symbol.1));
-
-                idx += 1;
-                symbol = &self.symbols[idx];
-            }
+        let idx = self.symbols.partition_point(|a| a.0 < pc);
+        for symbol in self.symbols[idx..]
+            .iter()
+            .take_while(|symbol| symbol.0 == pc)
+        {
+            writer.push_str(&format!("{}:\n", symbol.1));
         }
 
         writer.push_str(&format!("{pc:16x} {}", inst.fmt(pc)));
@@ -178,3 +306,68 @@ impl Disassembler {
         writer
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search() {
+        let mut data = [0u8; 16];
+        data[0..4].copy_from_slice(&0x00100593u32.to_le_bytes()); // li a1, 1
+        data[4..8].copy_from_slice(&0x00b50533u32.to_le_bytes()); // add a0, a0, a1
+        data[8..12].copy_from_slice(&0x00008067u32.to_le_bytes()); // ret
+        let memory = Memory::from_raw(&data);
+
+        let disassembler = &memory.disassembler;
+        assert_eq!(disassembler.search(&memory, 0, "add", 10), Some(4));
+        assert_eq!(disassembler.search(&memory, 4, "add", 10), None);
+        assert_eq!(disassembler.search(&memory, 0, "ret", 1), None);
+    }
+
+    #[test]
+    fn symbol_maps() {
+        let mut disassembler = Disassembler::new();
+        let map = "# from nm\n\n0000000000010078 T main\n10000 _start\n";
+        assert_eq!(disassembler.import_symbols(map, 0x1000), Ok(2));
+        assert_eq!(
+            disassembler.get_symbol_containing(0x11004),
+            Some(("_start", 4))
+        );
+        assert_eq!(disassembler.get_symbol_addr("main"), Some(0x11078));
+
+        let mut out = String::new();
+        disassembler.export_symbols(&mut out).unwrap();
+        assert_eq!(out, "0000000000011000 _start\n0000000000011078 main\n");
+
+        let mut imported = Disassembler::new();
+        assert_eq!(imported.import_symbols(&out, 0), Ok(2));
+        assert_eq!(imported.symbols, disassembler.symbols);
+
+        assert_eq!(
+            imported.import_symbols("10000 _start\nmain", 0),
+            Err(SymbolMapError::InvalidLine(2))
+        );
+    }
+
+    #[test]
+    fn navigation() {
+        let mut data = [0u8; 16];
+        data[0..4].copy_from_slice(&0xfe0008e3u32.to_le_bytes()); // beqz zero, -16
+        data[4..6].copy_from_slice(&0x0505u16.to_le_bytes()); // addi a0, a0, 1
+        data[6..10].copy_from_slice(&0x00c000efu32.to_le_bytes()); // jal ra, 12
+        let memory = Memory::from_raw(&data);
+
+        assert_eq!(Disassembler::next_pc(&memory, 0), 4);
+        assert_eq!(Disassembler::next_pc(&memory, 4), 6);
+        assert_eq!(Disassembler::previous_pc(&memory, 6), 4);
+        assert_eq!(Disassembler::previous_pc(&memory, 4), 0);
+
+        assert_eq!(
+            Disassembler::jump_target(&memory, 0),
+            Some(0u64.wrapping_sub(16))
+        );
+        assert_eq!(Disassembler::jump_target(&memory, 4), None);
+        assert_eq!(Disassembler::jump_target(&memory, 6), Some(18));
+    }
+}
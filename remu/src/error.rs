@@ -1,8 +1,49 @@
+use alloc::boxed::Box;
+
+use crate::system::{AccessKind, SandboxViolation, Segfault};
+
 #[derive(thiserror::Error, Debug)]
 pub enum RVError {
     #[error("segmentation fault")]
     SegmentationFault,
 
+    /// A [`RVError::SegmentationFault`] with where and how the guest faulted
+    #[error("segmentation fault at pc {:#x}", .0.pc)]
+    Segfault(Box<Segfault>),
+
+    /// An exception in system mode without a handler for it, see
+    /// [`Emulator::enable_system_mode`](crate::system::Emulator::enable_system_mode)
+    #[error("unhandled trap with cause {cause} at pc {pc:#x}")]
+    UnhandledTrap { cause: u64, pc: u64 },
+
+    /// A virtual address that the page table doesn't allow the access to, with paging enabled
+    /// in system mode
+    #[error("page fault: {kind} at {addr:#x}")]
+    PageFault { addr: u64, kind: AccessKind },
+
+    #[error("a function returned to the wrong address or with the wrong stack pointer")]
+    StackCorruption,
+
+    /// The jit and the interpreter disagreed about a function, see
+    /// [`Emulator::jit_divergence`](crate::system::Emulator::jit_divergence)
+    #[error("the jit diverged from the interpreter in the function at {pc:#x}")]
+    JitDivergence { pc: u64 },
+
+    /// The guest used more memory than it's limited to, see
+    /// [`Memory::set_memory_limit`](crate::memory::Memory::set_memory_limit)
+    #[error("the guest is using {usage} bytes of memory, past its limit of {limit}")]
+    MemoryLimitExceeded { usage: u64, limit: u64 },
+
+    /// The guest tried to do something its sandbox policy doesn't allow, see
+    /// [`Emulator::set_sandbox_policy`](crate::system::Emulator::set_sandbox_policy)
+    #[error("the sandbox policy doesn't allow {0}")]
+    SandboxViolation(Box<SandboxViolation>),
+
+    /// The guest executed all the instructions it was allowed to, see
+    /// [`Emulator::set_fuel`](crate::system::Emulator::set_fuel)
+    #[error("the guest ran out of fuel")]
+    FuelExhausted,
+
     #[error("the requested function label does not exist")]
     InvalidLabel,
 
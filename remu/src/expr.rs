@@ -0,0 +1,337 @@
+// a small expression language for inspecting the emulator from a debugger, e.g.
+// `*(u64)(sp + 16)`, `&main + 4` or `a0 * 2`
+
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use crate::{error::RVError, register::Reg, system::Emulator};
+
+#[derive(thiserror::Error, Debug)]
+pub enum ExprError {
+    #[error("unexpected end of expression")]
+    UnexpectedEnd,
+
+    #[error("unexpected `{0}`")]
+    Unexpected(String),
+
+    #[error("unknown register or symbol `{0}`")]
+    UnknownName(String),
+
+    #[error("division by zero")]
+    DivisionByZero,
+
+    #[error("{0}")]
+    Memory(#[from] RVError),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Number(u64),
+    Name(String),
+    // operators and parentheses, `<<` and `>>` are the only two character ones
+    Op(&'static str),
+}
+
+const OPS: [&str; 13] = [
+    "<<", ">>", "+", "-", "*", "/", "%", "&", "|", "^", "~", "(", ")",
+];
+
+fn tokenize(expr: &str) -> Result<Vec<Token>, ExprError> {
+    let mut tokens = Vec::new();
+    let mut rest = expr.trim_start();
+
+    while let Some(c) = rest.chars().next() {
+        if c.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(rest.len());
+            let number = &rest[..len];
+
+            let value = match number.strip_prefix("0x") {
+                Some(hex) => u64::from_str_radix(hex, 16),
+                None => number.parse(),
+            }
+            .map_err(|_| ExprError::Unexpected(number.to_string()))?;
+
+            tokens.push(Token::Number(value));
+            rest = &rest[len..];
+        } else if c.is_ascii_alphabetic() || c == '_' || c == '.' {
+            // symbols can contain dots and versions, like `memcpy@GLIBC_2.27`
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || "_.@$".contains(c)))
+                .unwrap_or(rest.len());
+
+            tokens.push(Token::Name(rest[..len].to_string()));
+            rest = &rest[len..];
+        } else {
+            let op = OPS
+                .iter()
+                .find(|op| rest.starts_with(*op))
+                .ok_or_else(|| ExprError::Unexpected(c.to_string()))?;
+
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        }
+
+        rest = rest.trim_start();
+    }
+
+    Ok(tokens)
+}
+
+// the width and signedness of a cast or dereference
+#[derive(Clone, Copy)]
+struct Type {
+    bytes: u8,
+    signed: bool,
+}
+
+impl Type {
+    const U64: Type = Type {
+        bytes: 8,
+        signed: false,
+    };
+
+    fn parse(name: &str) -> Option<Type> {
+        let (signed, bits) = match name.split_at_checked(1)? {
+            ("u", bits) => (false, bits),
+            ("i", bits) => (true, bits),
+            _ => return None,
+        };
+
+        let bytes = match bits {
+            "8" => 1,
+            "16" => 2,
+            "32" => 4,
+            "64" => 8,
+            _ => return None,
+        };
+
+        Some(Type { bytes, signed })
+    }
+
+    fn convert(self, value: u64) -> u64 {
+        let shift = 64 - 8 * self.bytes as u32;
+
+        if self.signed {
+            (((value << shift) as i64) >> shift) as u64
+        } else {
+            (value << shift) >> shift
+        }
+    }
+
+    fn load(self, emulator: &Emulator, addr: u64) -> Result<u64, RVError> {
+        let memory = &emulator.memory;
+
+        let value = match self.bytes {
+            1 => memory.load::<u8>(addr)? as u64,
+            2 => memory.load::<u16>(addr)? as u64,
+            4 => memory.load::<u32>(addr)? as u64,
+            _ => memory.load::<u64>(addr)?,
+        };
+
+        Ok(self.convert(value))
+    }
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    emulator: &'a Emulator,
+}
+
+// binary operators from the loosest binding to the tightest
+const PRECEDENCE: [&[&str]; 6] = [
+    &["|"],
+    &["^"],
+    &["&"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<Token, ExprError> {
+        let token = self.peek().cloned().ok_or(ExprError::UnexpectedEnd)?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), ExprError> {
+        match self.next()? {
+            Token::Op(found) if found == op => Ok(()),
+            token => Err(unexpected(token)),
+        }
+    }
+
+    // a parenthesized type name, consumed only if it is there
+    fn cast(&mut self) -> Option<Type> {
+        match self.tokens.get(self.position..self.position + 3) {
+            Some([Token::Op("("), Token::Name(name), Token::Op(")")]) => {
+                let ty = Type::parse(name)?;
+                self.position += 3;
+                Some(ty)
+            }
+            _ => None,
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<u64, ExprError> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+
+        let mut lhs = self.binary(level + 1)?;
+
+        while let Some(&Token::Op(op)) = self.peek() {
+            if !PRECEDENCE[level].contains(&op) {
+                break;
+            }
+
+            self.position += 1;
+            let rhs = self.binary(level + 1)?;
+
+            lhs = match op {
+                "|" => lhs | rhs,
+                "^" => lhs ^ rhs,
+                "&" => lhs & rhs,
+                "<<" => lhs.wrapping_shl(rhs as u32),
+                ">>" => lhs.wrapping_shr(rhs as u32),
+                "+" => lhs.wrapping_add(rhs),
+                "-" => lhs.wrapping_sub(rhs),
+                "*" => lhs.wrapping_mul(rhs),
+                "/" => lhs.checked_div(rhs).ok_or(ExprError::DivisionByZero)?,
+                _ => lhs.checked_rem(rhs).ok_or(ExprError::DivisionByZero)?,
+            };
+        }
+
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<u64, ExprError> {
+        if let Some(ty) = self.cast() {
+            return Ok(ty.convert(self.unary()?));
+        }
+
+        match self.next()? {
+            Token::Op("-") => Ok(self.unary()?.wrapping_neg()),
+            Token::Op("~") => Ok(!self.unary()?),
+            Token::Op("*") => {
+                let ty = self.cast().unwrap_or(Type::U64);
+                let addr = self.unary()?;
+                Ok(ty.load(self.emulator, addr)?)
+            }
+            Token::Op("&") => match self.next()? {
+                Token::Name(name) => self.symbol(&name),
+                token => Err(unexpected(token)),
+            },
+            Token::Op("(") => {
+                let value = self.binary(0)?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            Token::Number(value) => Ok(value),
+            Token::Name(name) => match register(&name) {
+                Some(reg) => Ok(reg
+                    .map(|reg| self.emulator.reg(reg))
+                    .unwrap_or(self.emulator.pc)),
+                None => self.symbol(&name),
+            },
+            token => Err(unexpected(token)),
+        }
+    }
+
+    fn symbol(&self, name: &str) -> Result<u64, ExprError> {
+        self.emulator
+            .memory
+            .disassembler
+            .get_symbol_addr(name)
+            .ok_or_else(|| ExprError::UnknownName(name.to_string()))
+    }
+}
+
+fn unexpected(token: Token) -> ExprError {
+    ExprError::Unexpected(match token {
+        Token::Number(value) => value.to_string(),
+        Token::Name(name) => name,
+        Token::Op(op) => op.to_string(),
+    })
+}
+
+// an integer register by its abi or x name, or None for the pc
+fn register(name: &str) -> Option<Option<Reg>> {
+    match name {
+        "pc" => Some(None),
+        _ => Reg::parse(name).map(Some),
+    }
+}
+
+/// Evaluates `expr` against the current state of `emulator`. Names are registers, or the address
+/// of a symbol, which can also be written `&symbol`. `*expr` loads a u64 from memory, and a cast
+/// like `*(i32)expr` changes the size and sign extension of the load.
+pub fn evaluate(expr: &str, emulator: &Emulator) -> Result<u64, ExprError> {
+    let mut parser = Parser {
+        tokens: tokenize(expr)?,
+        position: 0,
+        emulator,
+    };
+
+    let value = parser.binary(0)?;
+
+    match parser.peek() {
+        Some(token) => Err(unexpected(token.clone())),
+        None => Ok(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{memory::Memory, register::SP};
+
+    #[test]
+    fn evaluate_expressions() -> Result<(), ExprError> {
+        let mut emulator = Emulator::new(Memory::from_raw(&[0; 0x100]));
+        let sp = emulator.reg(SP);
+        emulator.memory.store(sp - 16, 0xfffffffe_u32)?;
+        emulator.memory.store(sp - 12, 7u32)?;
+        emulator.set_reg(Reg(10), 5);
+
+        assert_eq!(evaluate("1 + 2 * 3", &emulator)?, 7);
+        assert_eq!(evaluate("(1 + 2) * 3", &emulator)?, 9);
+        assert_eq!(evaluate("0x10 | 1 << 2", &emulator)?, 0x14);
+        assert_eq!(evaluate("a0 * 2 - x10", &emulator)?, 5);
+        assert_eq!(evaluate("-1", &emulator)?, u64::MAX);
+        assert_eq!(evaluate("(u8)0x1234", &emulator)?, 0x34);
+
+        assert_eq!(evaluate("*(u32)(sp - 16)", &emulator)?, 0xfffffffe);
+        assert_eq!(evaluate("*(i32)(sp - 16)", &emulator)?, -2i64 as u64);
+        assert_eq!(evaluate("*(u64)(sp-16)", &emulator)?, 0x7_fffffffe);
+        assert_eq!(evaluate("*(u32)(sp - 16 + 4)", &emulator)?, 7);
+
+        assert!(matches!(
+            evaluate("nope", &emulator),
+            Err(ExprError::UnknownName(_))
+        ));
+        assert!(matches!(
+            evaluate("1 / 0", &emulator),
+            Err(ExprError::DivisionByZero)
+        ));
+        assert!(matches!(
+            evaluate("(1 + 2", &emulator),
+            Err(ExprError::UnexpectedEnd)
+        ));
+        assert!(matches!(
+            evaluate("1 2", &emulator),
+            Err(ExprError::Unexpected(_))
+        ));
+
+        Ok(())
+    }
+}
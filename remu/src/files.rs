@@ -1,17 +1,596 @@
-pub const LD_LINUX_DATA: &'static [u8] = include_bytes!("../../res/ld-linux-riscv64-lp64d.so.1");
-pub const LIBC_DATA: &'static [u8] = include_bytes!("../../res/libc.so.6");
-pub const LIBCPP_DATA: &'static [u8] = include_bytes!("../../res/libstdc++.so");
-pub const LIBM_DATA: &'static [u8] = include_bytes!("../../res/libm.so.6");
-pub const LIBGCCS_DATA: &'static [u8] = include_bytes!("../../res/libgcc_s.so.1");
+// the dynamic linker and shared libraries programs are linked against, so dynamically linked
+// programs run without a riscv sysroot installed. They add several megabytes to the library, so
+// they're behind the `sysroot` feature, see `Sysroot`.
+#[cfg(feature = "sysroot")]
+pub use self::sysroot::*;
 
-pub const LIBC_FILE_DESCRIPTOR: i64 = 10;
-pub const LIBCPP_FILE_DESCRIPTOR: i64 = 11;
-pub const LIBM_FILE_DESCRIPTOR: i64 = 12;
-pub const LIBGCCS_FILE_DESCRIPTOR: i64 = 13;
+#[cfg(feature = "sysroot")]
+mod sysroot {
+    pub const LD_LINUX_DATA: &'static [u8] =
+        include_bytes!("../../res/ld-linux-riscv64-lp64d.so.1");
+    pub const LIBC_DATA: &'static [u8] = include_bytes!("../../res/libc.so.6");
+    pub const LIBCPP_DATA: &'static [u8] = include_bytes!("../../res/libstdc++.so");
+    pub const LIBM_DATA: &'static [u8] = include_bytes!("../../res/libm.so.6");
+    pub const LIBGCCS_DATA: &'static [u8] = include_bytes!("../../res/libgcc_s.so.1");
 
+    // where the embedded dynamic linker looks for the libraries
+    pub(super) fn embedded_file(path: &str) -> Option<&'static [u8]> {
+        match path {
+            super::DEFAULT_INTERPRETER => Some(LD_LINUX_DATA),
+            "/lib/tls/libc.so.6" => Some(LIBC_DATA),
+            "/lib/tls/libstdc++.so.6" => Some(LIBCPP_DATA),
+            "/lib/tls/libm.so.6" => Some(LIBM_DATA),
+            "/lib/tls/libgcc_s.so.1" => Some(LIBGCCS_DATA),
+            _ => None,
+        }
+    }
+}
+
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+#[cfg(feature = "std")]
+use std::path::PathBuf;
+
+/// A file the guest can read, seek in and map
 #[derive(Clone)]
 pub struct FileDescriptor {
     // current file read location
     pub offset: u64,
-    pub data: Box<[u8]>,
+    // shared between forks of the emulator, which each keep their own offset
+    pub data: Arc<[u8]>,
+}
+
+impl FileDescriptor {
+    /// A file of `data`, which isn't copied if it's already an `Arc`
+    pub fn new(data: impl Into<Arc<[u8]>>) -> Self {
+        FileDescriptor {
+            offset: 0,
+            data: data.into(),
+        }
+    }
+}
+
+/// What a file descriptor in an [`FdTable`] refers to
+#[derive(Clone)]
+pub enum OpenFile {
+    File(FileDescriptor),
+    /// Collects everything written to it
+    Sink(Vec<u8>),
+    /// Writes are appended to [`Emulator::stdout`](crate::system::Emulator::stdout)
+    Stdout,
+    /// Writes are appended to [`Emulator::stderr`](crate::system::Emulator::stderr)
+    Stderr,
+    /// A device from the [`Vfs`], which discards writes
+    Device(Device),
+    /// A directory read with getdents64
+    Directory {
+        path: String,
+        entries: Vec<DirEntry>,
+        // the index of the next entry, which is also the cookie lseek takes
+        position: u64,
+    },
+}
+
+/// The file descriptors open in the guest. By default only stdout and stderr are, see
+/// [`Emulator::with_fds`](crate::system::Emulator::with_fds) to start with others.
+#[derive(Clone)]
+pub struct FdTable {
+    fds: BTreeMap<i64, OpenFile>,
+}
+
+impl Default for FdTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FdTable {
+    pub fn new() -> FdTable {
+        let mut table = FdTable::empty();
+        table.insert(1, OpenFile::Stdout);
+        table.insert(2, OpenFile::Stderr);
+        table
+    }
+
+    /// A table without even stdout and stderr
+    pub fn empty() -> FdTable {
+        FdTable {
+            fds: BTreeMap::new(),
+        }
+    }
+
+    /// Opens `file` as `fd`, closing whatever it referred to before
+    pub fn insert(&mut self, fd: i64, file: OpenFile) {
+        self.fds.insert(fd, file);
+    }
+
+    /// Opens `file` as the lowest fd not in use, like open does
+    pub fn open(&mut self, file: OpenFile) -> i64 {
+        let fd = (0..).find(|fd| !self.fds.contains_key(fd)).unwrap();
+        self.fds.insert(fd, file);
+        fd
+    }
+
+    pub fn remove(&mut self, fd: i64) -> Option<OpenFile> {
+        self.fds.remove(&fd)
+    }
+
+    pub fn get(&self, fd: i64) -> Option<&OpenFile> {
+        self.fds.get(&fd)
+    }
+
+    pub fn get_mut(&mut self, fd: i64) -> Option<&mut OpenFile> {
+        self.fds.get_mut(&fd)
+    }
+
+    /// The readable file open as `fd`, if it is one
+    pub fn file(&self, fd: i64) -> Option<&FileDescriptor> {
+        match self.fds.get(&fd) {
+            Some(OpenFile::File(file)) => Some(file),
+            _ => None,
+        }
+    }
+
+    pub fn file_mut(&mut self, fd: i64) -> Option<&mut FileDescriptor> {
+        match self.fds.get_mut(&fd) {
+            Some(OpenFile::File(file)) => Some(file),
+            _ => None,
+        }
+    }
+
+    /// Everything written to the sink open as `fd`, if it is one
+    pub fn sink(&self, fd: i64) -> Option<&[u8]> {
+        match self.fds.get(&fd) {
+            Some(OpenFile::Sink(data)) => Some(data),
+            _ => None,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (i64, &OpenFile)> {
+        self.fds.iter().map(|(&fd, file)| (fd, file))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileKind {
+    File,
+    Directory,
+    CharDevice,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub kind: FileKind,
+}
+
+/// The seed of the random devices in a new [`Vfs`]
+pub const DEFAULT_RANDOM_SEED: u64 = 0;
+
+/// A character device in the [`Vfs`]. Writes to any of them are discarded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Device {
+    /// Reads are empty, like /dev/null
+    Null,
+    /// Reads are zeros, like /dev/zero
+    Zero,
+    /// Reads are an endless stream of bytes generated from the seed, so they're the same every
+    /// run, like a deterministic /dev/urandom. Each open file has its own place in the stream.
+    Random(u64),
+}
+
+impl Device {
+    /// Fills `buf` with what's read from the device, returning how many bytes were read
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        match self {
+            Device::Null => return 0,
+            Device::Zero => buf.fill(0),
+            // splitmix64
+            Device::Random(state) => {
+                for chunk in buf.chunks_mut(8) {
+                    *state = state.wrapping_add(0x9e3779b97f4a7c15);
+                    let mut z = *state;
+                    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+                    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+                    z ^= z >> 31;
+                    chunk.copy_from_slice(&z.to_le_bytes()[..chunk.len()]);
+                }
+            }
+        }
+
+        buf.len()
+    }
+}
+
+/// What a path in the [`Vfs`] refers to
+#[derive(Clone)]
+pub enum VfsNode {
+    File(Arc<[u8]>),
+    Device(Device),
+    /// The entries of the directory, not including `.` and `..`
+    Directory(Vec<DirEntry>),
+}
+
+/// The files the guest can open and execute. Files are either added in memory or read from host
+/// directories mounted into it, and directories are implied by the paths of what's in them.
+///
+/// /dev/null, /dev/zero, /dev/random and /dev/urandom are there from the start, see [`Device`].
+#[derive(Clone)]
+pub struct Vfs {
+    files: BTreeMap<String, Arc<[u8]>>,
+    // take precedence over mounts, since reading the host's would never end
+    devices: BTreeMap<String, Device>,
+    // guest path -> host directory
+    #[cfg(feature = "std")]
+    mounts: Vec<(String, PathBuf)>,
+}
+
+impl Default for Vfs {
+    fn default() -> Self {
+        let mut vfs = Vfs {
+            files: BTreeMap::new(),
+            devices: BTreeMap::new(),
+            #[cfg(feature = "std")]
+            mounts: Vec::new(),
+        };
+
+        vfs.add_device("/dev/null", Device::Null);
+        vfs.add_device("/dev/zero", Device::Zero);
+        vfs.add_device("/dev/random", Device::Random(DEFAULT_RANDOM_SEED));
+        vfs.add_device("/dev/urandom", Device::Random(DEFAULT_RANDOM_SEED));
+        vfs
+    }
+}
+
+impl Vfs {
+    /// Makes `device` available at the absolute `path`
+    pub fn add_device(&mut self, path: &str, device: Device) {
+        self.devices.insert(normalize(path), device);
+    }
+
+    /// Restarts every [`Device::Random`] from `seed`, for a different but still reproducible
+    /// stream of bytes
+    pub fn set_random_seed(&mut self, seed: u64) {
+        for device in self.devices.values_mut() {
+            if let Device::Random(state) = device {
+                *state = seed;
+            }
+        }
+    }
+
+    /// Makes `data` available at the absolute `path`. It isn't copied if it's already an `Arc`.
+    pub fn add_file(&mut self, path: &str, data: impl Into<Arc<[u8]>>) {
+        self.files.insert(normalize(path), data.into());
+    }
+
+    /// Makes the host directory `host` visible at the absolute `path`. Its files are read when
+    /// they are opened, and shadow files added with [`Vfs::add_file`].
+    #[cfg(feature = "std")]
+    pub fn mount(&mut self, path: &str, host: impl Into<PathBuf>) {
+        self.mounts.push((normalize(path), host.into()));
+    }
+
+    /// The file or directory at the absolute `path`
+    pub fn lookup(&self, path: &str) -> Option<VfsNode> {
+        let path = normalize(path);
+        if let Some(&device) = self.devices.get(&path) {
+            return Some(VfsNode::Device(device));
+        }
+
+        #[cfg(feature = "std")]
+        let host = self.lookup_host(&path);
+        #[cfg(not(feature = "std"))]
+        let host: Option<VfsNode> = None;
+
+        let mut entries = match host {
+            Some(VfsNode::Directory(entries)) => Some(entries),
+            Some(node) => return Some(node),
+            None => None,
+        };
+
+        if let Some(data) = self.files.get(&path) {
+            return Some(VfsNode::File(data.clone()));
+        }
+
+        for entry in self.implied_entries(&path) {
+            let entries = entries.get_or_insert_with(Vec::new);
+            if !entries.iter().any(|other| other.name == entry.name) {
+                entries.push(entry);
+            }
+        }
+
+        match entries {
+            Some(entries) => Some(VfsNode::Directory(entries)),
+            None if path == "/" => Some(VfsNode::Directory(Vec::new())),
+            None => None,
+        }
+    }
+
+    /// The kind and size of what's at the absolute `path`, without reading it
+    pub fn metadata(&self, path: &str) -> Option<(FileKind, u64)> {
+        let path = normalize(path);
+        if self.devices.contains_key(&path) {
+            return Some((FileKind::CharDevice, 0));
+        }
+
+        #[cfg(feature = "std")]
+        if let Some(host) = self.host_path(&path) {
+            if let Ok(metadata) = std::fs::metadata(host) {
+                let kind = match metadata.is_dir() {
+                    true => FileKind::Directory,
+                    false => FileKind::File,
+                };
+                return Some((kind, metadata.len()));
+            }
+        }
+
+        if let Some(data) = self.files.get(&path) {
+            return Some((FileKind::File, data.len() as u64));
+        }
+
+        let is_dir = path == "/" || self.implied_entries(&path).next().is_some();
+        is_dir.then_some((FileKind::Directory, 0))
+    }
+
+    /// Whether the absolute `path` is read from a mounted host directory
+    pub fn is_host_path(&self, path: &str) -> bool {
+        let path = normalize(path);
+        if self.devices.contains_key(&path) {
+            return false;
+        }
+
+        #[cfg(feature = "std")]
+        if let Some(host) = self.host_path(&path) {
+            return host.exists();
+        }
+
+        false
+    }
+
+    // the children of `dir` implied by the paths of added files, devices and mount points
+
+    fn implied_entries<'a>(&'a self, dir: &str) -> impl Iterator<Item = DirEntry> + 'a {
+        let prefix = match dir {
+            "/" => String::from("/"),
+            dir => format!("{dir}/"),
+        };
+
+        let files = self.files.keys().map(|path| (path, FileKind::File));
+        let files = files.chain(self.devices.keys().map(|path| (path, FileKind::CharDevice)));
+        #[cfg(feature = "std")]
+        let files = files.chain(
+            self.mounts
+                .iter()
+                .map(|(path, _)| (path, FileKind::Directory)),
+        );
+
+        files.filter_map(move |(path, kind)| {
+            let rest = path.strip_prefix(&prefix)?;
+            Some(match rest.split_once('/') {
+                Some((name, _)) => DirEntry {
+                    name: name.to_string(),
+                    kind: FileKind::Directory,
+                },
+                None => DirEntry {
+                    name: rest.to_string(),
+                    kind,
+                },
+            })
+        })
+    }
+
+    // where `path` is on the host, if it's under a mount point. Later mounts take precedence.
+    #[cfg(feature = "std")]
+    fn host_path(&self, path: &str) -> Option<PathBuf> {
+        self.mounts.iter().rev().find_map(|(mount, host)| {
+            let rest = match path.strip_prefix(mount.as_str())? {
+                "" => "",
+                rest if mount == "/" => rest,
+                rest => rest.strip_prefix('/')?,
+            };
+            Some(host.join(rest))
+        })
+    }
+
+    #[cfg(feature = "std")]
+    fn lookup_host(&self, path: &str) -> Option<VfsNode> {
+        let host = self.host_path(path)?;
+        let metadata = std::fs::metadata(&host).ok()?;
+
+        if !metadata.is_dir() {
+            let data = std::fs::read(&host).ok()?;
+            return Some(VfsNode::File(data.into()));
+        }
+
+        let mut entries = Vec::new();
+        for entry in std::fs::read_dir(&host).ok()?.flatten() {
+            // symlinks are followed
+            let Ok(metadata) = std::fs::metadata(entry.path()) else {
+                continue;
+            };
+
+            entries.push(DirEntry {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                kind: match metadata.is_dir() {
+                    true => FileKind::Directory,
+                    false => FileKind::File,
+                },
+            });
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Some(VfsNode::Directory(entries))
+    }
+}
+
+/// Where the dynamic linker of an executable that doesn't name one is looked up in the [`Sysroot`]
+pub const DEFAULT_INTERPRETER: &str = "/lib/ld-linux-riscv64-lp64d.so.1";
+
+/// Where dynamically linked programs get their dynamic linker and shared libraries from. Paths
+/// are the guest's, and files the guest opens are looked up here when they aren't in the [`Vfs`].
+///
+/// By default it has the files embedded with the `sysroot` feature, if it's enabled.
+#[derive(Clone)]
+pub struct Sysroot {
+    files: BTreeMap<String, Arc<[u8]>>,
+    // host directories standing in for the guest's /
+    #[cfg(feature = "std")]
+    dirs: Vec<PathBuf>,
+    #[cfg(feature = "sysroot")]
+    embedded: bool,
+}
+
+impl Default for Sysroot {
+    fn default() -> Self {
+        Sysroot {
+            #[cfg(feature = "sysroot")]
+            embedded: true,
+            ..Sysroot::empty()
+        }
+    }
+}
+
+impl Sysroot {
+    /// A sysroot with nothing in it, not even the embedded files
+    pub fn empty() -> Self {
+        Sysroot {
+            files: BTreeMap::new(),
+            #[cfg(feature = "std")]
+            dirs: Vec::new(),
+            #[cfg(feature = "sysroot")]
+            embedded: false,
+        }
+    }
+
+    /// Makes `data` available at the absolute `path`, like the dynamic linker at
+    /// [`DEFAULT_INTERPRETER`] or `/lib/libc.so.6`
+    pub fn add_file(&mut self, path: &str, data: impl Into<Arc<[u8]>>) {
+        self.files.insert(normalize(path), data.into());
+    }
+
+    /// Looks up files in the host directory `dir`, such as `/usr/riscv64-linux-gnu`, as if it were
+    /// the guest's /. Files are read when they are looked up, and files added with
+    /// [`Sysroot::add_file`] shadow them.
+    #[cfg(feature = "std")]
+    pub fn add_dir(&mut self, dir: impl Into<PathBuf>) {
+        self.dirs.push(dir.into());
+    }
+
+    /// The file at the absolute `path`
+    pub fn file(&self, path: &str) -> Option<Arc<[u8]>> {
+        let path = normalize(path);
+        if let Some(data) = self.files.get(&path) {
+            return Some(data.clone());
+        }
+
+        #[cfg(feature = "std")]
+        for dir in &self.dirs {
+            let host = dir.join(path.trim_start_matches('/'));
+            if let Ok(data) = std::fs::read(host) {
+                return Some(data.into());
+            }
+        }
+
+        #[cfg(feature = "sysroot")]
+        if self.embedded {
+            return embedded_file(&path).map(Arc::from);
+        }
+
+        None
+    }
+}
+
+/// Removes `.`, `..`, empty components and trailing slashes from the absolute `path`
+pub fn normalize(path: &str) -> String {
+    let mut components: Vec<&str> = Vec::new();
+
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            component => components.push(component),
+        }
+    }
+
+    format!("/{}", components.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vfs() {
+        let mut vfs = Vfs::default();
+        vfs.add_file("/bin/child", *b"elf");
+        vfs.add_file("/etc/passwd", *b"root");
+        vfs.add_file("/etc/ssl/cert.pem", *b"");
+
+        assert_eq!(normalize("/etc/./ssl/../passwd/"), "/etc/passwd");
+        assert!(
+            matches!(vfs.lookup("/etc/../bin/child"), Some(VfsNode::File(data)) if &*data == b"elf")
+        );
+        assert_eq!(vfs.metadata("/etc/passwd"), Some((FileKind::File, 4)));
+        assert_eq!(vfs.metadata("/etc"), Some((FileKind::Directory, 0)));
+        assert_eq!(vfs.metadata("/usr"), None);
+
+        let Some(VfsNode::Directory(entries)) = vfs.lookup("/etc") else {
+            panic!("/etc isn't a directory");
+        };
+        let names: Vec<(&str, FileKind)> = entries
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.kind))
+            .collect();
+        assert_eq!(
+            names,
+            [("passwd", FileKind::File), ("ssl", FileKind::Directory)]
+        );
+
+        let Some(VfsNode::Directory(root)) = vfs.lookup("/") else {
+            panic!("/ isn't a directory");
+        };
+        // bin, etc and dev
+        assert_eq!(root.len(), 3);
+
+        let Some(VfsNode::Device(mut urandom)) = vfs.lookup("/dev/urandom") else {
+            panic!("/dev/urandom isn't a device");
+        };
+        let (mut first, mut again) = ([0; 12], [0; 12]);
+        urandom.read(&mut first);
+        let Some(VfsNode::Device(mut random)) = vfs.lookup("/dev/random") else {
+            panic!("/dev/random isn't a device");
+        };
+        random.read(&mut again);
+        assert_eq!(first, again);
+        assert_ne!(first, [0; 12]);
+
+        vfs.set_random_seed(1);
+        let Some(VfsNode::Device(mut reseeded)) = vfs.lookup("/dev/urandom") else {
+            panic!("/dev/urandom isn't a device");
+        };
+        reseeded.read(&mut again);
+        assert_ne!(first, again);
+        assert_eq!(Device::Null.read(&mut again), 0);
+    }
+
+    #[test]
+    fn sysroot() {
+        let mut sysroot = Sysroot::empty();
+        sysroot.add_file("/lib/libc.so.6", *b"libc");
+
+        assert!(matches!(sysroot.file("/lib/../lib/libc.so.6"), Some(data) if &*data == b"libc"));
+        assert!(sysroot.file("/lib/libm.so.6").is_none());
+        assert!(sysroot.file(DEFAULT_INTERPRETER).is_none());
+        assert_eq!(
+            Sysroot::default().file(DEFAULT_INTERPRETER).is_some(),
+            cfg!(feature = "sysroot")
+        );
+    }
 }
@@ -1,3 +1,7 @@
+// decoding, encoding and printing the instructions of RV64GC the emulator supports
+
+use alloc::{format, string::String, vec::Vec};
+
 use crate::register::{FReg, Reg, RA, SP};
 
 const TABLE_SIZE: usize = u16::MAX as usize;
@@ -20,197 +24,1102 @@ const COMPRESSED_INSTRUCTIONS: [Inst; TABLE_SIZE] = generate_compressed_instruct
 pub enum Inst {
     // MISC.
     Fence,
+    /// Makes stores to instruction memory visible to instruction fetches that follow it
+    FenceI,
     Ecall,
     Ebreak,
+    /// Returns from a trap taken into machine mode
+    Mret,
+    /// Returns from a trap taken into supervisor mode
+    Sret,
+    /// Waits for an interrupt, which is a no-op here
+    Wfi,
+    /// Orders page table updates, a no-op without paging
+    SfenceVma,
     Error(u32),
-    Lui { rd: Reg, imm: i32 },
+    Lui {
+        rd: Reg,
+        imm: i32,
+    },
 
     // LOADS/STORES
-    Ld { rd: Reg, rs1: Reg, offset: i32 },
-    Lw { rd: Reg, rs1: Reg, offset: i32 },
-    Lwu { rd: Reg, rs1: Reg, offset: i32 },
-    Lhu { rd: Reg, rs1: Reg, offset: i32 },
-    Lb { rd: Reg, rs1: Reg, offset: i32 },
-    Lbu { rd: Reg, rs1: Reg, offset: i32 },
-    Sd { rs1: Reg, rs2: Reg, offset: i32 },
-    Sw { rs1: Reg, rs2: Reg, offset: i32 },
-    Sh { rs1: Reg, rs2: Reg, offset: i32 },
-    Sb { rs1: Reg, rs2: Reg, offset: i32 },
+    Ld {
+        rd: Reg,
+        rs1: Reg,
+        offset: i32,
+    },
+    Lw {
+        rd: Reg,
+        rs1: Reg,
+        offset: i32,
+    },
+    Lwu {
+        rd: Reg,
+        rs1: Reg,
+        offset: i32,
+    },
+    Lhu {
+        rd: Reg,
+        rs1: Reg,
+        offset: i32,
+    },
+    Lb {
+        rd: Reg,
+        rs1: Reg,
+        offset: i32,
+    },
+    Lbu {
+        rd: Reg,
+        rs1: Reg,
+        offset: i32,
+    },
+    Sd {
+        rs1: Reg,
+        rs2: Reg,
+        offset: i32,
+    },
+    Sw {
+        rs1: Reg,
+        rs2: Reg,
+        offset: i32,
+    },
+    Sh {
+        rs1: Reg,
+        rs2: Reg,
+        offset: i32,
+    },
+    Sb {
+        rs1: Reg,
+        rs2: Reg,
+        offset: i32,
+    },
 
     // MATH OPERATIONS
-    Add { rd: Reg, rs1: Reg, rs2: Reg },
-    Addw { rd: Reg, rs1: Reg, rs2: Reg },
-    Addi { rd: Reg, rs1: Reg, imm: i32 },
-    Addiw { rd: Reg, rs1: Reg, imm: i32 },
-    Div { rd: Reg, rs1: Reg, rs2: Reg },
-    Divw { rd: Reg, rs1: Reg, rs2: Reg },
-    Divu { rd: Reg, rs1: Reg, rs2: Reg },
-    Divuw { rd: Reg, rs1: Reg, rs2: Reg },
-    And { rd: Reg, rs1: Reg, rs2: Reg },
-    Andi { rd: Reg, rs1: Reg, imm: i32 },
-    Sub { rd: Reg, rs1: Reg, rs2: Reg },
-    Subw { rd: Reg, rs1: Reg, rs2: Reg },
-    Sll { rd: Reg, rs1: Reg, rs2: Reg },
-    Sllw { rd: Reg, rs1: Reg, rs2: Reg },
-    Slli { rd: Reg, rs1: Reg, shamt: u32 },
-    Slliw { rd: Reg, rs1: Reg, shamt: u32 },
-    Srl { rd: Reg, rs1: Reg, rs2: Reg },
-    Srlw { rd: Reg, rs1: Reg, rs2: Reg },
-    Srli { rd: Reg, rs1: Reg, shamt: u32 },
-    Srliw { rd: Reg, rs1: Reg, shamt: u32 },
-    Sra { rd: Reg, rs1: Reg, rs2: Reg },
-    Sraw { rd: Reg, rs1: Reg, rs2: Reg },
-    Srai { rd: Reg, rs1: Reg, shamt: u32 },
-    Sraiw { rd: Reg, rs1: Reg, shamt: u32 },
-    Or { rd: Reg, rs1: Reg, rs2: Reg },
-    Ori { rd: Reg, rs1: Reg, imm: i32 },
-    Xor { rd: Reg, rs1: Reg, rs2: Reg },
-    Xori { rd: Reg, rs1: Reg, imm: i32 },
+    Add {
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    Addw {
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    Addi {
+        rd: Reg,
+        rs1: Reg,
+        imm: i32,
+    },
+    Addiw {
+        rd: Reg,
+        rs1: Reg,
+        imm: i32,
+    },
+    Div {
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    Divw {
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    Divu {
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    Divuw {
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    And {
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    Andi {
+        rd: Reg,
+        rs1: Reg,
+        imm: i32,
+    },
+    Sub {
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    Subw {
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    Sll {
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    Sllw {
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    Slli {
+        rd: Reg,
+        rs1: Reg,
+        shamt: u32,
+    },
+    Slliw {
+        rd: Reg,
+        rs1: Reg,
+        shamt: u32,
+    },
+    Srl {
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    Srlw {
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    Srli {
+        rd: Reg,
+        rs1: Reg,
+        shamt: u32,
+    },
+    Srliw {
+        rd: Reg,
+        rs1: Reg,
+        shamt: u32,
+    },
+    Sra {
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    Sraw {
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    Srai {
+        rd: Reg,
+        rs1: Reg,
+        shamt: u32,
+    },
+    Sraiw {
+        rd: Reg,
+        rs1: Reg,
+        shamt: u32,
+    },
+    Or {
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    Ori {
+        rd: Reg,
+        rs1: Reg,
+        imm: i32,
+    },
+    Xor {
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    Xori {
+        rd: Reg,
+        rs1: Reg,
+        imm: i32,
+    },
 
     // JUMPING
-    Auipc { rd: Reg, imm: i32 },
-    Jal { rd: Reg, offset: i32 },
-    Jalr { rd: Reg, rs1: Reg, offset: i32 },
+    Auipc {
+        rd: Reg,
+        imm: i32,
+    },
+    Jal {
+        rd: Reg,
+        offset: i32,
+    },
+    Jalr {
+        rd: Reg,
+        rs1: Reg,
+        offset: i32,
+    },
 
     // BRANCHES
-    Beq { rs1: Reg, rs2: Reg, offset: i32 },
-    Bne { rs1: Reg, rs2: Reg, offset: i32 },
-    Blt { rs1: Reg, rs2: Reg, offset: i32 },
-    Bltu { rs1: Reg, rs2: Reg, offset: i32 },
-    Bge { rs1: Reg, rs2: Reg, offset: i32 },
-    Bgeu { rs1: Reg, rs2: Reg, offset: i32 },
-    Mul { rd: Reg, rs1: Reg, rs2: Reg },
-    Mulhu { rd: Reg, rs1: Reg, rs2: Reg },
-    Remw { rd: Reg, rs1: Reg, rs2: Reg },
-    Remu { rd: Reg, rs1: Reg, rs2: Reg },
-    Remuw { rd: Reg, rs1: Reg, rs2: Reg },
-    Slt { rd: Reg, rs1: Reg, rs2: Reg },
-    Sltu { rd: Reg, rs1: Reg, rs2: Reg },
-    Slti { rd: Reg, rs1: Reg, imm: i32 },
-    Sltiu { rd: Reg, rs1: Reg, imm: u32 },
+    Beq {
+        rs1: Reg,
+        rs2: Reg,
+        offset: i32,
+    },
+    Bne {
+        rs1: Reg,
+        rs2: Reg,
+        offset: i32,
+    },
+    Blt {
+        rs1: Reg,
+        rs2: Reg,
+        offset: i32,
+    },
+    Bltu {
+        rs1: Reg,
+        rs2: Reg,
+        offset: i32,
+    },
+    Bge {
+        rs1: Reg,
+        rs2: Reg,
+        offset: i32,
+    },
+    Bgeu {
+        rs1: Reg,
+        rs2: Reg,
+        offset: i32,
+    },
+    Mul {
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    Mulhu {
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    Remw {
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    Remu {
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    Remuw {
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    Slt {
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    Sltu {
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    Slti {
+        rd: Reg,
+        rs1: Reg,
+        imm: i32,
+    },
+    Sltiu {
+        rd: Reg,
+        rs1: Reg,
+        imm: u32,
+    },
 
     // ATOMICS
-    Amoswapw { rd: Reg, rs1: Reg, rs2: Reg },
-    Amoswapd { rd: Reg, rs1: Reg, rs2: Reg },
-    Amoaddw { rd: Reg, rs1: Reg, rs2: Reg },
-    Amoaddd { rd: Reg, rs1: Reg, rs2: Reg },
-    Amoorw { rd: Reg, rs1: Reg, rs2: Reg },
-    Amomaxuw { rd: Reg, rs1: Reg, rs2: Reg },
-    Amomaxud { rd: Reg, rs1: Reg, rs2: Reg },
-    Lrw { rd: Reg, rs1: Reg },
-    Lrd { rd: Reg, rs1: Reg },
-    Scw { rd: Reg, rs1: Reg, rs2: Reg },
-    Scd { rd: Reg, rs1: Reg, rs2: Reg },
+    Amoswapw {
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    Amoswapd {
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    Amoaddw {
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    Amoaddd {
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    Amoorw {
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    Amomaxuw {
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    Amomaxud {
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    Lrw {
+        rd: Reg,
+        rs1: Reg,
+    },
+    Lrd {
+        rd: Reg,
+        rs1: Reg,
+    },
+    Scw {
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    Scd {
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
 
     // FLOATING POINT
-    Fsd { rs1: Reg, rs2: FReg, offset: i32 },
-    Fsw { rs1: Reg, rs2: FReg, offset: i32 },
-    Fld { rd: FReg, rs1: Reg, offset: i32 },
-    Flw { rd: FReg, rs1: Reg, offset: i32 },
-    Fcvtdlu { rd: Reg, rs1: FReg, rm: u8 },
-    Fcvtds { rd: Reg, rs1: FReg, rm: u8 },
-    Fled { rd: Reg, rs1: FReg, rs2: FReg },
-    Fdivd { rd: FReg, rs1: FReg, rs2: FReg },
+    Fsd {
+        rs1: Reg,
+        rs2: FReg,
+        offset: i32,
+    },
+    Fsw {
+        rs1: Reg,
+        rs2: FReg,
+        offset: i32,
+    },
+    Fld {
+        rd: FReg,
+        rs1: Reg,
+        offset: i32,
+    },
+    Flw {
+        rd: FReg,
+        rs1: Reg,
+        offset: i32,
+    },
+    Fcvtdlu {
+        rd: Reg,
+        rs1: FReg,
+        rm: u8,
+    },
+    Fcvtds {
+        rd: Reg,
+        rs1: FReg,
+        rm: u8,
+    },
+    Fled {
+        rd: Reg,
+        rs1: FReg,
+        rs2: FReg,
+    },
+    Fdivd {
+        rd: FReg,
+        rs1: FReg,
+        rs2: FReg,
+    },
+
+    // CONTROL AND STATUS REGISTERS
+    Csrrw {
+        rd: Reg,
+        rs1: Reg,
+        csr: u16,
+    },
+    Csrrs {
+        rd: Reg,
+        rs1: Reg,
+        csr: u16,
+    },
+    Csrrc {
+        rd: Reg,
+        rs1: Reg,
+        csr: u16,
+    },
+    Csrrwi {
+        rd: Reg,
+        uimm: u8,
+        csr: u16,
+    },
+    Csrrsi {
+        rd: Reg,
+        uimm: u8,
+        csr: u16,
+    },
+    Csrrci {
+        rd: Reg,
+        uimm: u8,
+        csr: u16,
+    },
+}
+
+/// An operand of an instruction, as it's written in assembly
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Operand {
+    Reg(Reg),
+    FReg(FReg),
+    /// An immediate or shift amount, written in decimal
+    Imm(i64),
+    /// The upper 20 bits of `lui` and `auipc`, written in hex
+    Upper(u32),
+    /// A control and status register, written in hex
+    Csr(u16),
+    /// `offset(base)`, the address of a load, store, atomic or `jalr`
+    Mem {
+        base: Reg,
+        offset: i32,
+    },
+    /// A jump or branch target, relative to the instruction. It's written as the address it
+    /// refers to, in hex.
+    Target(i32),
+    /// A static rounding mode
+    RoundingMode(u8),
+}
+
+impl Operand {
+    /// The operand as it's written for an instruction at `pc`
+    pub fn fmt(self, pc: u64) -> String {
+        match self {
+            Operand::Reg(reg) => format!("{reg}"),
+            Operand::FReg(reg) => format!("{reg}"),
+            Operand::Imm(imm) => format!("{imm}"),
+            Operand::Upper(imm) => format!("{imm:#x}"),
+            Operand::Csr(csr) => format!("{csr:#x}"),
+            Operand::Mem { base, offset } => format!("{offset}({base})"),
+            Operand::Target(offset) => format!("{:x}", pc.wrapping_add(offset as u64)),
+            Operand::RoundingMode(rm) => match rm {
+                0b000 => "rne".into(),
+                0b001 => "rtz".into(),
+                0b010 => "rdn".into(),
+                0b011 => "rup".into(),
+                0b100 => "rmm".into(),
+                0b111 => "dyn".into(),
+                rm => format!("{rm}"),
+            },
+        }
+    }
 }
 
 impl Inst {
+    /// The instruction as it's written in assembly, for an instruction at `pc`. The assembler
+    /// accepts it back.
     pub fn fmt(&self, pc: u64) -> String {
-        match *self {
-            Inst::Fence => format!("fence"),
-            Inst::Ecall => format!("ecall"),
-            Inst::Ebreak => format!("break"),
-            Inst::Error(ref e) => format!("error: {e:08x}"),
-            Inst::Lui { rd, imm } => format!("lui   {}, {:x}", rd, imm >> 12),
-            Inst::Ld { rd, rs1, offset } => format!("ld    {}, {}({})", rd, offset, rs1),
-            Inst::Lw { rd, rs1, offset } => format!("lw    {}, {}({})", rd, offset, rs1),
-            Inst::Lwu { rd, rs1, offset } => format!("lwu    {}, {}({})", rd, offset, rs1),
-            Inst::Lhu { rd, rs1, offset } => format!("lhu   {}, {}({})", rd, offset, rs1),
-            Inst::Lb { rd, rs1, offset } => format!("lbu   {}, {}({})", rd, offset, rs1),
-            Inst::Lbu { rd, rs1, offset } => format!("lbu   {}, {}({})", rd, offset, rs1),
-            Inst::Sd { rs1, rs2, offset } => format!("sd    {}, {}({})", rs2, offset, rs1),
-            Inst::Sw { rs1, rs2, offset } => format!("sw    {}, {}({})", rs2, offset, rs1),
-            Inst::Sh { rs1, rs2, offset } => format!("sh    {}, {}({})", rs2, offset, rs1),
-            Inst::Sb { rs1, rs2, offset } => format!("sb    {}, {}({})", rs2, offset, rs1),
-            Inst::Add { rd, rs1, rs2 } => format!("add   {rd}, {rs1}, {rs2}"),
-            Inst::Addw { rd, rs1, rs2 } => format!("addw  {rd}, {rs1}, {rs2}"),
-            Inst::Addi { rd, rs1, imm } => format!("addi  {rd}, {rs1}, {}", imm as i64),
-            Inst::Addiw { rd, rs1, imm } => format!("addiw {rd}, {rs1}, {}", imm as i32),
-            Inst::And { rd, rs1, rs2 } => format!("and   {rd}, {rs1}, {rs2}"),
-            Inst::Andi { rd, rs1, imm } => format!("andi  {rd}, {rs1}, {}", imm as i64),
-            Inst::Sub { rd, rs1, rs2 } => format!("sub   {rd}, {rs1}, {rs2}"),
-            Inst::Subw { rd, rs1, rs2 } => format!("subw  {rd}, {rs1}, {rs2}"),
-            Inst::Sll { rd, rs1, rs2 } => format!("sll  {rd}, {rs1}, {rs2}"),
-            Inst::Sllw { rd, rs1, rs2 } => format!("sllw  {rd}, {rs1}, {rs2}"),
-            Inst::Slli { rd, rs1, shamt } => format!("slli  {rd}, {rs1}, {shamt}"),
-            Inst::Slliw { rd, rs1, shamt } => format!("slliw {rd}, {rs1}, {shamt}"),
-            Inst::Srl { rd, rs1, rs2 } => format!("srl  {rd}, {rs1}, {rs2}"),
-            Inst::Srlw { rd, rs1, rs2 } => format!("srl  {rd}, {rs1}, {rs2}"),
-            Inst::Srli { rd, rs1, shamt } => format!("srli  {rd}, {rs1}, {shamt}"),
-            Inst::Srliw { rd, rs1, shamt } => format!("srliw {rd}, {rs1}, {shamt}"),
-            Inst::Sra { rd, rs1, rs2 } => format!("sra  {rd}, {rs1}, {rs2}"),
-            Inst::Sraw { rd, rs1, rs2 } => format!("sraw {rd}, {rs1}, {rs2}"),
-            Inst::Srai { rd, rs1, shamt } => format!("srai  {rd}, {rs1}, {shamt}"),
-            Inst::Sraiw { rd, rs1, shamt } => format!("sraiw {rd}, {rs1}, {shamt}"),
-            Inst::Or { rd, rs1, rs2 } => format!("or    {rd}, {rs1}, {rs2}"),
-            Inst::Ori { rd, rs1, imm } => format!("ori   {rd}, {rs1}, {imm}"),
-            Inst::Xor { rd, rs1, rs2 } => format!("xor   {rd}, {rs1}, {rs2}"),
-            Inst::Xori { rd, rs1, imm } => format!("xori  {rd}, {rs1}, {imm}"),
-            Inst::Auipc { rd, imm } => format!("auipc {rd}, 0x{:x}", imm as u64 >> 12),
-            Inst::Jal { rd, offset } => format!("jal   {rd}, {:x}", pc.wrapping_add(offset as u64)),
-            Inst::Jalr { rd, rs1, offset } => format!("jalr  {rd}, {offset}({rs1})"),
-            Inst::Beq { rs1, rs2, offset } => {
-                format!("beq   {rs1}, {rs2}, {:x}", pc.wrapping_add(offset as u64))
-            }
-            Inst::Bne { rs1, rs2, offset } => {
-                format!("bne   {rs1}, {rs2}, {:x}", pc.wrapping_add(offset as u64))
-            }
-            Inst::Blt { rs1, rs2, offset } => {
-                format!("blt   {rs1}, {rs2}, {:x}", pc.wrapping_add(offset as u64))
-            }
-            Inst::Bltu { rs1, rs2, offset } => {
-                format!("bltu  {rs1}, {rs2}, {:x}", pc.wrapping_add(offset as u64))
-            }
-            Inst::Bge { rs1, rs2, offset } => {
-                format!("bge   {rs1}, {rs2}, {:x}", pc.wrapping_add(offset as u64))
-            }
-            Inst::Bgeu { rs1, rs2, offset } => {
-                format!("bgeu  {rs1}, {rs2}, {:x}", pc.wrapping_add(offset as u64))
-            }
-            Inst::Div { rd, rs1, rs2 } => format!("div   {rd}, {rs1}, {rs2}"),
-            Inst::Divw { rd, rs1, rs2 } => format!("divw  {rd}, {rs1}, {rs2}"),
-            Inst::Divu { rd, rs1, rs2 } => format!("divu  {rd}, {rs1}, {rs2}"),
-            Inst::Divuw { rd, rs1, rs2 } => format!("divuw {rd}, {rs1}, {rs2}"),
-            Inst::Mul { rd, rs1, rs2 } => format!("mul   {rd}, {rs1}, {rs2}"),
-            Inst::Mulhu { rd, rs1, rs2 } => format!("mul   {rd}, {rs1}, {rs2}"),
-            Inst::Remw { rd, rs1, rs2 } => format!("remw  {rd}, {rs1}, {rs2}"),
-            Inst::Remu { rd, rs1, rs2 } => format!("remu  {rd}, {rs1}, {rs2}"),
-            Inst::Remuw { rd, rs1, rs2 } => format!("remuw  {rd}, {rs1}, {rs2}"),
-            Inst::Amoswapw { rd, rs1, rs2 } => format!("amoswap.w {rd}, {rs1}, {rs2}"),
-            Inst::Amoswapd { rd, rs1, rs2 } => format!("amoswap.d {rd}, {rs1}, {rs2}"),
-            Inst::Amoaddw { rd, rs1, rs2 } => format!("amoadd.w {rd}, {rs1}, {rs2}"),
-            Inst::Amoaddd { rd, rs1, rs2 } => format!("amoadd.d {rd}, {rs1}, {rs2}"),
-            Inst::Amoorw { rd, rs1, rs2 } => format!("amoor.w {rd}, {rs1}, {rs2}"),
-            Inst::Amomaxuw { rd, rs1, rs2 } => format!("amomaxu.w {rd}, {rs1}, {rs2}"),
-            Inst::Amomaxud { rd, rs1, rs2 } => format!("amomaxu.d {rd}, {rs1}, {rs2}"),
-            Inst::Slt { rd, rs1, rs2 } => format!("slt   {rd}, {rs1}, {rs2}"),
-            Inst::Sltu { rd, rs1, rs2 } => format!("sltu  {rd}, {rs1}, {rs2}"),
-            Inst::Slti { rd, rs1, imm } => format!("slti  {rd}, {rs1}, {imm}"),
-            Inst::Sltiu { rd, rs1, imm } => format!("sltiu {rd}, {rs1}, {imm}"),
-            Inst::Lrw { rd, rs1 } => format!("lr.w  {rd}, ({rs1})"),
-            Inst::Lrd { rd, rs1 } => format!("lr.d  {rd}, ({rs1})"),
-            Inst::Scw { rd, rs1, rs2 } => format!("sc.w  {rd}, {rs2},({rs1})"),
-            Inst::Scd { rd, rs1, rs2 } => format!("sc.d  {rd}, {rs2},({rs1})"),
-            Inst::Fsd { rs1, rs2, offset } => format!("fsd   {rs2}, {offset}({rs1})"),
-            Inst::Fsw { rs1, rs2, offset } => format!("fsw   {rs2}, {offset}({rs1})"),
-            Inst::Fld { rs1, rd, offset } => format!("fld   {rd}, {offset}({rs1})"),
-            Inst::Flw { rs1, rd, offset } => format!("flw   {rd}, {offset}({rs1})"),
-            Inst::Fcvtdlu { rs1, rd, rm } => format!("fcvt.d.lu {rd}, {rs1} rm={rm:03b}"),
-            Inst::Fcvtds { rs1, rd, rm } => format!("fcvt.d.s {rd}, {rs1} rm={rm:03b}"),
-            Inst::Fled { rd, rs1, rs2 } => format!("fle.d  {rd}, {rs1} {rs2}"),
-            Inst::Fdivd { rd, rs1, rs2 } => format!("fdiv.d {rd}, {rs1} {rs2}"),
+        if let Inst::Error(inst) = *self {
+            return format!("error: {inst:08x}");
+        }
+
+        let operands: Vec<String> = self
+            .operands()
+            .into_iter()
+            .map(|operand| operand.fmt(pc))
+            .collect();
+
+        match operands.is_empty() {
+            true => self.mnemonic().into(),
+            false => format!("{:<5} {}", self.mnemonic(), operands.join(", ")),
+        }
+    }
+
+    /// The name of the instruction in assembly
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Inst::Fence => "fence",
+            Inst::FenceI => "fence.i",
+            Inst::Ecall => "ecall",
+            Inst::Ebreak => "ebreak",
+            Inst::Mret => "mret",
+            Inst::Sret => "sret",
+            Inst::Wfi => "wfi",
+            Inst::SfenceVma => "sfence.vma",
+            Inst::Error(_) => "error",
+            Inst::Lui { .. } => "lui",
+            Inst::Ld { .. } => "ld",
+            Inst::Lw { .. } => "lw",
+            Inst::Lwu { .. } => "lwu",
+            Inst::Lhu { .. } => "lhu",
+            Inst::Lb { .. } => "lb",
+            Inst::Lbu { .. } => "lbu",
+            Inst::Sd { .. } => "sd",
+            Inst::Sw { .. } => "sw",
+            Inst::Sh { .. } => "sh",
+            Inst::Sb { .. } => "sb",
+            Inst::Add { .. } => "add",
+            Inst::Addw { .. } => "addw",
+            Inst::Addi { .. } => "addi",
+            Inst::Addiw { .. } => "addiw",
+            Inst::Div { .. } => "div",
+            Inst::Divw { .. } => "divw",
+            Inst::Divu { .. } => "divu",
+            Inst::Divuw { .. } => "divuw",
+            Inst::And { .. } => "and",
+            Inst::Andi { .. } => "andi",
+            Inst::Sub { .. } => "sub",
+            Inst::Subw { .. } => "subw",
+            Inst::Sll { .. } => "sll",
+            Inst::Sllw { .. } => "sllw",
+            Inst::Slli { .. } => "slli",
+            Inst::Slliw { .. } => "slliw",
+            Inst::Srl { .. } => "srl",
+            Inst::Srlw { .. } => "srlw",
+            Inst::Srli { .. } => "srli",
+            Inst::Srliw { .. } => "srliw",
+            Inst::Sra { .. } => "sra",
+            Inst::Sraw { .. } => "sraw",
+            Inst::Srai { .. } => "srai",
+            Inst::Sraiw { .. } => "sraiw",
+            Inst::Or { .. } => "or",
+            Inst::Ori { .. } => "ori",
+            Inst::Xor { .. } => "xor",
+            Inst::Xori { .. } => "xori",
+            Inst::Auipc { .. } => "auipc",
+            Inst::Jal { .. } => "jal",
+            Inst::Jalr { .. } => "jalr",
+            Inst::Beq { .. } => "beq",
+            Inst::Bne { .. } => "bne",
+            Inst::Blt { .. } => "blt",
+            Inst::Bltu { .. } => "bltu",
+            Inst::Bge { .. } => "bge",
+            Inst::Bgeu { .. } => "bgeu",
+            Inst::Mul { .. } => "mul",
+            Inst::Mulhu { .. } => "mulhu",
+            Inst::Remw { .. } => "remw",
+            Inst::Remu { .. } => "remu",
+            Inst::Remuw { .. } => "remuw",
+            Inst::Slt { .. } => "slt",
+            Inst::Sltu { .. } => "sltu",
+            Inst::Slti { .. } => "slti",
+            Inst::Sltiu { .. } => "sltiu",
+            Inst::Amoswapw { .. } => "amoswap.w",
+            Inst::Amoswapd { .. } => "amoswap.d",
+            Inst::Amoaddw { .. } => "amoadd.w",
+            Inst::Amoaddd { .. } => "amoadd.d",
+            Inst::Amoorw { .. } => "amoor.w",
+            Inst::Amomaxuw { .. } => "amomaxu.w",
+            Inst::Amomaxud { .. } => "amomaxu.d",
+            Inst::Lrw { .. } => "lr.w",
+            Inst::Lrd { .. } => "lr.d",
+            Inst::Scw { .. } => "sc.w",
+            Inst::Scd { .. } => "sc.d",
+            Inst::Fsd { .. } => "fsd",
+            Inst::Fsw { .. } => "fsw",
+            Inst::Fld { .. } => "fld",
+            Inst::Flw { .. } => "flw",
+            Inst::Fcvtdlu { .. } => "fcvt.d.lu",
+            Inst::Fcvtds { .. } => "fcvt.d.s",
+            Inst::Fled { .. } => "fle.d",
+            Inst::Fdivd { .. } => "fdiv.d",
+            Inst::Csrrw { .. } => "csrrw",
+            Inst::Csrrs { .. } => "csrrs",
+            Inst::Csrrc { .. } => "csrrc",
+            Inst::Csrrwi { .. } => "csrrwi",
+            Inst::Csrrsi { .. } => "csrrsi",
+            Inst::Csrrci { .. } => "csrrci",
+        }
+    }
+
+    /// The operands of the instruction, in the order they're written in assembly. A rounding
+    /// mode is only included when it isn't dynamic.
+    pub fn operands(self) -> Vec<Operand> {
+        use Operand::{Csr, FReg as F, Imm, Reg as R, Target, Upper};
+        let mem = |base, offset| Operand::Mem { base, offset };
+        let rm = |rm| (rm != 0b111).then_some(Operand::RoundingMode(rm));
+
+        match self {
+            Inst::Fence
+            | Inst::FenceI
+            | Inst::Ecall
+            | Inst::Ebreak
+            | Inst::Mret
+            | Inst::Sret
+            | Inst::Wfi
+            | Inst::SfenceVma
+            | Inst::Error(_) => Vec::new(),
+            Inst::Lui { rd, imm } | Inst::Auipc { rd, imm } => {
+                Vec::from([R(rd), Upper(imm as u32 >> 12)])
+            }
+            Inst::Ld { rd, rs1, offset }
+            | Inst::Lw { rd, rs1, offset }
+            | Inst::Lwu { rd, rs1, offset }
+            | Inst::Lhu { rd, rs1, offset }
+            | Inst::Lb { rd, rs1, offset }
+            | Inst::Lbu { rd, rs1, offset }
+            | Inst::Jalr { rd, rs1, offset } => Vec::from([R(rd), mem(rs1, offset)]),
+            Inst::Sd { rs1, rs2, offset }
+            | Inst::Sw { rs1, rs2, offset }
+            | Inst::Sh { rs1, rs2, offset }
+            | Inst::Sb { rs1, rs2, offset } => Vec::from([R(rs2), mem(rs1, offset)]),
+            Inst::Add { rd, rs1, rs2 }
+            | Inst::Addw { rd, rs1, rs2 }
+            | Inst::Div { rd, rs1, rs2 }
+            | Inst::Divw { rd, rs1, rs2 }
+            | Inst::Divu { rd, rs1, rs2 }
+            | Inst::Divuw { rd, rs1, rs2 }
+            | Inst::And { rd, rs1, rs2 }
+            | Inst::Sub { rd, rs1, rs2 }
+            | Inst::Subw { rd, rs1, rs2 }
+            | Inst::Sll { rd, rs1, rs2 }
+            | Inst::Sllw { rd, rs1, rs2 }
+            | Inst::Srl { rd, rs1, rs2 }
+            | Inst::Srlw { rd, rs1, rs2 }
+            | Inst::Sra { rd, rs1, rs2 }
+            | Inst::Sraw { rd, rs1, rs2 }
+            | Inst::Or { rd, rs1, rs2 }
+            | Inst::Xor { rd, rs1, rs2 }
+            | Inst::Mul { rd, rs1, rs2 }
+            | Inst::Mulhu { rd, rs1, rs2 }
+            | Inst::Remw { rd, rs1, rs2 }
+            | Inst::Remu { rd, rs1, rs2 }
+            | Inst::Remuw { rd, rs1, rs2 }
+            | Inst::Slt { rd, rs1, rs2 }
+            | Inst::Sltu { rd, rs1, rs2 } => Vec::from([R(rd), R(rs1), R(rs2)]),
+            Inst::Addi { rd, rs1, imm }
+            | Inst::Addiw { rd, rs1, imm }
+            | Inst::Andi { rd, rs1, imm }
+            | Inst::Ori { rd, rs1, imm }
+            | Inst::Xori { rd, rs1, imm }
+            | Inst::Slti { rd, rs1, imm } => Vec::from([R(rd), R(rs1), Imm(imm as i64)]),
+            Inst::Sltiu { rd, rs1, imm } => Vec::from([R(rd), R(rs1), Imm(imm as i32 as i64)]),
+            Inst::Slli { rd, rs1, shamt }
+            | Inst::Slliw { rd, rs1, shamt }
+            | Inst::Srli { rd, rs1, shamt }
+            | Inst::Srliw { rd, rs1, shamt }
+            | Inst::Srai { rd, rs1, shamt }
+            | Inst::Sraiw { rd, rs1, shamt } => Vec::from([R(rd), R(rs1), Imm(shamt as i64)]),
+            Inst::Jal { rd, offset } => Vec::from([R(rd), Target(offset)]),
+            Inst::Beq { rs1, rs2, offset }
+            | Inst::Bne { rs1, rs2, offset }
+            | Inst::Blt { rs1, rs2, offset }
+            | Inst::Bltu { rs1, rs2, offset }
+            | Inst::Bge { rs1, rs2, offset }
+            | Inst::Bgeu { rs1, rs2, offset } => Vec::from([R(rs1), R(rs2), Target(offset)]),
+            Inst::Amoswapw { rd, rs1, rs2 }
+            | Inst::Amoswapd { rd, rs1, rs2 }
+            | Inst::Amoaddw { rd, rs1, rs2 }
+            | Inst::Amoaddd { rd, rs1, rs2 }
+            | Inst::Amoorw { rd, rs1, rs2 }
+            | Inst::Amomaxuw { rd, rs1, rs2 }
+            | Inst::Amomaxud { rd, rs1, rs2 }
+            | Inst::Scw { rd, rs1, rs2 }
+            | Inst::Scd { rd, rs1, rs2 } => Vec::from([R(rd), R(rs2), mem(rs1, 0)]),
+            Inst::Lrw { rd, rs1 } | Inst::Lrd { rd, rs1 } => Vec::from([R(rd), mem(rs1, 0)]),
+            Inst::Fsd { rs1, rs2, offset } | Inst::Fsw { rs1, rs2, offset } => {
+                Vec::from([F(rs2), mem(rs1, offset)])
+            }
+            Inst::Fld { rd, rs1, offset } | Inst::Flw { rd, rs1, offset } => {
+                Vec::from([F(rd), mem(rs1, offset)])
+            }
+            Inst::Fcvtdlu { rd, rs1, rm: mode } | Inst::Fcvtds { rd, rs1, rm: mode } => {
+                [R(rd), F(rs1)].into_iter().chain(rm(mode)).collect()
+            }
+            Inst::Fled { rd, rs1, rs2 } => Vec::from([R(rd), F(rs1), F(rs2)]),
+            Inst::Fdivd { rd, rs1, rs2 } => Vec::from([F(rd), F(rs1), F(rs2)]),
+            Inst::Csrrw { rd, rs1, csr }
+            | Inst::Csrrs { rd, rs1, csr }
+            | Inst::Csrrc { rd, rs1, csr } => Vec::from([R(rd), Csr(csr), R(rs1)]),
+            Inst::Csrrwi { rd, uimm, csr }
+            | Inst::Csrrsi { rd, uimm, csr }
+            | Inst::Csrrci { rd, uimm, csr } => Vec::from([R(rd), Csr(csr), Imm(uimm as i64)]),
+        }
+    }
+
+    /// The integer register the instruction writes, if any
+    pub fn written_reg(self) -> Option<Reg> {
+        match self {
+            Inst::Lui { rd, .. }
+            | Inst::Ld { rd, .. }
+            | Inst::Lw { rd, .. }
+            | Inst::Lwu { rd, .. }
+            | Inst::Lhu { rd, .. }
+            | Inst::Lb { rd, .. }
+            | Inst::Lbu { rd, .. }
+            | Inst::Add { rd, .. }
+            | Inst::Addw { rd, .. }
+            | Inst::Addi { rd, .. }
+            | Inst::Addiw { rd, .. }
+            | Inst::Div { rd, .. }
+            | Inst::Divw { rd, .. }
+            | Inst::Divu { rd, .. }
+            | Inst::Divuw { rd, .. }
+            | Inst::And { rd, .. }
+            | Inst::Andi { rd, .. }
+            | Inst::Sub { rd, .. }
+            | Inst::Subw { rd, .. }
+            | Inst::Sll { rd, .. }
+            | Inst::Sllw { rd, .. }
+            | Inst::Slli { rd, .. }
+            | Inst::Slliw { rd, .. }
+            | Inst::Srl { rd, .. }
+            | Inst::Srlw { rd, .. }
+            | Inst::Srli { rd, .. }
+            | Inst::Srliw { rd, .. }
+            | Inst::Sra { rd, .. }
+            | Inst::Sraw { rd, .. }
+            | Inst::Srai { rd, .. }
+            | Inst::Sraiw { rd, .. }
+            | Inst::Or { rd, .. }
+            | Inst::Ori { rd, .. }
+            | Inst::Xor { rd, .. }
+            | Inst::Xori { rd, .. }
+            | Inst::Auipc { rd, .. }
+            | Inst::Jal { rd, .. }
+            | Inst::Jalr { rd, .. }
+            | Inst::Mul { rd, .. }
+            | Inst::Mulhu { rd, .. }
+            | Inst::Remw { rd, .. }
+            | Inst::Remu { rd, .. }
+            | Inst::Remuw { rd, .. }
+            | Inst::Slt { rd, .. }
+            | Inst::Sltu { rd, .. }
+            | Inst::Slti { rd, .. }
+            | Inst::Sltiu { rd, .. }
+            | Inst::Amoswapw { rd, .. }
+            | Inst::Amoswapd { rd, .. }
+            | Inst::Amoaddw { rd, .. }
+            | Inst::Amoaddd { rd, .. }
+            | Inst::Amoorw { rd, .. }
+            | Inst::Amomaxuw { rd, .. }
+            | Inst::Amomaxud { rd, .. }
+            | Inst::Lrw { rd, .. }
+            | Inst::Lrd { rd, .. }
+            | Inst::Scw { rd, .. }
+            | Inst::Scd { rd, .. }
+            | Inst::Fcvtdlu { rd, .. }
+            | Inst::Fcvtds { rd, .. }
+            | Inst::Fled { rd, .. }
+            | Inst::Csrrw { rd, .. }
+            | Inst::Csrrs { rd, .. }
+            | Inst::Csrrc { rd, .. }
+            | Inst::Csrrwi { rd, .. }
+            | Inst::Csrrsi { rd, .. }
+            | Inst::Csrrci { rd, .. } => Some(rd),
+            _ => None,
         }
     }
 
+    /// The canonical 32-bit encoding of the instruction, which [`Inst::decode`] decodes back to
+    /// the same instruction. [`Inst::Error`] encodes to the word it was decoded from.
+    pub fn encode(self) -> u32 {
+        let r = |opcode, funct3, funct7, rd: Reg, rs1: Reg, rs2: Reg| {
+            r_type(opcode, funct3, funct7, rd.0, rs1.0, rs2.0)
+        };
+        let amo = |funct3, funct5: u32, rd: Reg, rs1: Reg, rs2: Reg| {
+            r_type(OP_AMO, funct3, funct5 << 2, rd.0, rs1.0, rs2.0)
+        };
+
+        match self {
+            Inst::Fence => 0x0ff0000f,
+            Inst::FenceI => 0x0000100f,
+            Inst::Ecall => 0x00000073,
+            Inst::Ebreak => 0x00100073,
+            Inst::Mret => 0x30200073,
+            Inst::Sret => 0x10200073,
+            Inst::Wfi => 0x10500073,
+            Inst::SfenceVma => 0x12000073,
+            Inst::Error(inst) => inst,
+            Inst::Lui { rd, imm } => u_type(OP_LUI, rd, imm as u32 >> 12),
+            Inst::Auipc { rd, imm } => u_type(OP_AUIPC, rd, imm as u32 >> 12),
+
+            Inst::Lb { rd, rs1, offset } => i_type(OP_LOAD, 0b000, rd, rs1, offset as u32),
+            Inst::Lw { rd, rs1, offset } => i_type(OP_LOAD, 0b010, rd, rs1, offset as u32),
+            Inst::Ld { rd, rs1, offset } => i_type(OP_LOAD, 0b011, rd, rs1, offset as u32),
+            Inst::Lbu { rd, rs1, offset } => i_type(OP_LOAD, 0b100, rd, rs1, offset as u32),
+            Inst::Lhu { rd, rs1, offset } => i_type(OP_LOAD, 0b101, rd, rs1, offset as u32),
+            Inst::Lwu { rd, rs1, offset } => i_type(OP_LOAD, 0b110, rd, rs1, offset as u32),
+            Inst::Sb { rs1, rs2, offset } => s_type(OP_STORE, 0b000, rs1, rs2, offset as u32),
+            Inst::Sh { rs1, rs2, offset } => s_type(OP_STORE, 0b001, rs1, rs2, offset as u32),
+            Inst::Sw { rs1, rs2, offset } => s_type(OP_STORE, 0b010, rs1, rs2, offset as u32),
+            Inst::Sd { rs1, rs2, offset } => s_type(OP_STORE, 0b011, rs1, rs2, offset as u32),
+
+            Inst::Addi { rd, rs1, imm } => i_type(OP_IMM, 0b000, rd, rs1, imm as u32),
+            Inst::Slti { rd, rs1, imm } => i_type(OP_IMM, 0b010, rd, rs1, imm as u32),
+            Inst::Sltiu { rd, rs1, imm } => i_type(OP_IMM, 0b011, rd, rs1, imm),
+            Inst::Xori { rd, rs1, imm } => i_type(OP_IMM, 0b100, rd, rs1, imm as u32),
+            Inst::Ori { rd, rs1, imm } => i_type(OP_IMM, 0b110, rd, rs1, imm as u32),
+            Inst::Andi { rd, rs1, imm } => i_type(OP_IMM, 0b111, rd, rs1, imm as u32),
+            Inst::Addiw { rd, rs1, imm } => i_type(OP_IMM_32, 0b000, rd, rs1, imm as u32),
+            Inst::Slli { rd, rs1, shamt } => i_type(OP_IMM, 0b001, rd, rs1, shamt),
+            Inst::Srli { rd, rs1, shamt } => i_type(OP_IMM, 0b101, rd, rs1, shamt),
+            Inst::Srai { rd, rs1, shamt } => i_type(OP_IMM, 0b101, rd, rs1, 0x400 | shamt),
+            Inst::Slliw { rd, rs1, shamt } => i_type(OP_IMM_32, 0b001, rd, rs1, shamt),
+            Inst::Srliw { rd, rs1, shamt } => i_type(OP_IMM_32, 0b101, rd, rs1, shamt),
+            Inst::Sraiw { rd, rs1, shamt } => i_type(OP_IMM_32, 0b101, rd, rs1, 0x400 | shamt),
+
+            Inst::Add { rd, rs1, rs2 } => r(OP, 0b000, 0b0000000, rd, rs1, rs2),
+            Inst::Sub { rd, rs1, rs2 } => r(OP, 0b000, 0b0100000, rd, rs1, rs2),
+            Inst::Sll { rd, rs1, rs2 } => r(OP, 0b001, 0b0000000, rd, rs1, rs2),
+            Inst::Slt { rd, rs1, rs2 } => r(OP, 0b010, 0b0000000, rd, rs1, rs2),
+            Inst::Sltu { rd, rs1, rs2 } => r(OP, 0b011, 0b0000000, rd, rs1, rs2),
+            Inst::Xor { rd, rs1, rs2 } => r(OP, 0b100, 0b0000000, rd, rs1, rs2),
+            Inst::Srl { rd, rs1, rs2 } => r(OP, 0b101, 0b0000000, rd, rs1, rs2),
+            Inst::Sra { rd, rs1, rs2 } => r(OP, 0b101, 0b0100000, rd, rs1, rs2),
+            Inst::Or { rd, rs1, rs2 } => r(OP, 0b110, 0b0000000, rd, rs1, rs2),
+            Inst::And { rd, rs1, rs2 } => r(OP, 0b111, 0b0000000, rd, rs1, rs2),
+            Inst::Mul { rd, rs1, rs2 } => r(OP, 0b000, 0b0000001, rd, rs1, rs2),
+            Inst::Mulhu { rd, rs1, rs2 } => r(OP, 0b011, 0b0000001, rd, rs1, rs2),
+            Inst::Div { rd, rs1, rs2 } => r(OP, 0b100, 0b0000001, rd, rs1, rs2),
+            Inst::Divu { rd, rs1, rs2 } => r(OP, 0b101, 0b0000001, rd, rs1, rs2),
+            Inst::Remu { rd, rs1, rs2 } => r(OP, 0b111, 0b0000001, rd, rs1, rs2),
+            Inst::Addw { rd, rs1, rs2 } => r(OP_32, 0b000, 0b0000000, rd, rs1, rs2),
+            Inst::Subw { rd, rs1, rs2 } => r(OP_32, 0b000, 0b0100000, rd, rs1, rs2),
+            Inst::Sllw { rd, rs1, rs2 } => r(OP_32, 0b001, 0b0000000, rd, rs1, rs2),
+            Inst::Srlw { rd, rs1, rs2 } => r(OP_32, 0b101, 0b0000000, rd, rs1, rs2),
+            Inst::Sraw { rd, rs1, rs2 } => r(OP_32, 0b101, 0b0100000, rd, rs1, rs2),
+            Inst::Divw { rd, rs1, rs2 } => r(OP_32, 0b100, 0b0000001, rd, rs1, rs2),
+            Inst::Divuw { rd, rs1, rs2 } => r(OP_32, 0b101, 0b0000001, rd, rs1, rs2),
+            Inst::Remw { rd, rs1, rs2 } => r(OP_32, 0b110, 0b0000001, rd, rs1, rs2),
+            Inst::Remuw { rd, rs1, rs2 } => r(OP_32, 0b111, 0b0000001, rd, rs1, rs2),
+
+            Inst::Jal { rd, offset } => j_type(rd, offset as u32),
+            Inst::Jalr { rd, rs1, offset } => i_type(OP_JALR, 0b000, rd, rs1, offset as u32),
+            Inst::Beq { rs1, rs2, offset } => b_type(0b000, rs1, rs2, offset as u32),
+            Inst::Bne { rs1, rs2, offset } => b_type(0b001, rs1, rs2, offset as u32),
+            Inst::Blt { rs1, rs2, offset } => b_type(0b100, rs1, rs2, offset as u32),
+            Inst::Bge { rs1, rs2, offset } => b_type(0b101, rs1, rs2, offset as u32),
+            Inst::Bltu { rs1, rs2, offset } => b_type(0b110, rs1, rs2, offset as u32),
+            Inst::Bgeu { rs1, rs2, offset } => b_type(0b111, rs1, rs2, offset as u32),
+
+            Inst::Amoaddw { rd, rs1, rs2 } => amo(0b010, 0b00000, rd, rs1, rs2),
+            Inst::Amoswapw { rd, rs1, rs2 } => amo(0b010, 0b00001, rd, rs1, rs2),
+            Inst::Lrw { rd, rs1 } => amo(0b010, 0b00010, rd, rs1, Reg(0)),
+            Inst::Scw { rd, rs1, rs2 } => amo(0b010, 0b00011, rd, rs1, rs2),
+            Inst::Amoorw { rd, rs1, rs2 } => amo(0b010, 0b01000, rd, rs1, rs2),
+            Inst::Amomaxuw { rd, rs1, rs2 } => amo(0b010, 0b11100, rd, rs1, rs2),
+            Inst::Amoaddd { rd, rs1, rs2 } => amo(0b011, 0b00000, rd, rs1, rs2),
+            Inst::Amoswapd { rd, rs1, rs2 } => amo(0b011, 0b00001, rd, rs1, rs2),
+            Inst::Lrd { rd, rs1 } => amo(0b011, 0b00010, rd, rs1, Reg(0)),
+            Inst::Scd { rd, rs1, rs2 } => amo(0b011, 0b00011, rd, rs1, rs2),
+            Inst::Amomaxud { rd, rs1, rs2 } => amo(0b011, 0b11100, rd, rs1, rs2),
+
+            Inst::Flw { rd, rs1, offset } => {
+                i_type(OP_LOAD_FP, 0b010, Reg(rd.0), rs1, offset as u32)
+            }
+            Inst::Fld { rd, rs1, offset } => {
+                i_type(OP_LOAD_FP, 0b011, Reg(rd.0), rs1, offset as u32)
+            }
+            Inst::Fsw { rs1, rs2, offset } => {
+                s_type(OP_STORE_FP, 0b010, rs1, Reg(rs2.0), offset as u32)
+            }
+            Inst::Fsd { rs1, rs2, offset } => {
+                s_type(OP_STORE_FP, 0b011, rs1, Reg(rs2.0), offset as u32)
+            }
+            Inst::Fcvtdlu { rd, rs1, rm } => {
+                r_type(OP_FP, rm as u32, 0b1101001, rd.0, rs1.0, 0b00011)
+            }
+            Inst::Fcvtds { rd, rs1, rm } => r_type(OP_FP, rm as u32, 0b0100001, rd.0, rs1.0, 0),
+            Inst::Fled { rd, rs1, rs2 } => r_type(OP_FP, 0b000, 0b1010001, rd.0, rs1.0, rs2.0),
+            // with the rounding mode set by the fcsr
+            Inst::Fdivd { rd, rs1, rs2 } => r_type(OP_FP, 0b111, 0b0001101, rd.0, rs1.0, rs2.0),
+
+            Inst::Csrrw { rd, rs1, csr } => i_type(OP_SYSTEM, 0b001, rd, rs1, csr as u32),
+            Inst::Csrrs { rd, rs1, csr } => i_type(OP_SYSTEM, 0b010, rd, rs1, csr as u32),
+            Inst::Csrrc { rd, rs1, csr } => i_type(OP_SYSTEM, 0b011, rd, rs1, csr as u32),
+            Inst::Csrrwi { rd, uimm, csr } => i_type(OP_SYSTEM, 0b101, rd, Reg(uimm), csr as u32),
+            Inst::Csrrsi { rd, uimm, csr } => i_type(OP_SYSTEM, 0b110, rd, Reg(uimm), csr as u32),
+            Inst::Csrrci { rd, uimm, csr } => i_type(OP_SYSTEM, 0b111, rd, Reg(uimm), csr as u32),
+        }
+    }
+
+    /// The 16-bit encoding of the instruction, if it has one in the C extension, which
+    /// [`Inst::decode`] decodes back to the same instruction
+    pub fn encode_compressed(self) -> Option<u16> {
+        // x8-x15, the registers the 3-bit register fields can hold
+        let c = |reg: u8| (8..16).contains(&reg);
+        let simm = |imm: i32, bits: u32| (-(1 << (bits - 1))..1 << (bits - 1)).contains(&imm);
+        let uimm =
+            |imm: i32, bits: u32, align: i32| (0..1 << bits).contains(&imm) && imm % align == 0;
+
+        // quadrant, funct3, and the rest of the instruction
+        let (quadrant, funct3, rest) = match self {
+            Inst::Addi { rd, rs1, imm } if rd == rs1 && simm(imm, 6) => {
+                (0b01, 0b000, ci(rd.0, imm))
+            }
+            Inst::Addi {
+                rd: SP,
+                rs1: SP,
+                imm,
+            } if imm != 0 && imm % 16 == 0 && simm(imm, 10) => {
+                let imm = imm as u32;
+                let rest = bits(imm, 9, 9, 12)
+                    | 2 << 7
+                    | bits(imm, 4, 4, 6)
+                    | bits(imm, 6, 6, 5)
+                    | bits(imm, 8, 7, 3)
+                    | bits(imm, 5, 5, 2);
+                (0b01, 0b011, rest)
+            }
+            Inst::Addi { rd, rs1: SP, imm } if c(rd.0) && imm != 0 && uimm(imm, 10, 4) => {
+                let imm = imm as u32;
+                let rest = bits(imm, 5, 4, 11)
+                    | bits(imm, 9, 6, 7)
+                    | bits(imm, 2, 2, 6)
+                    | bits(imm, 3, 3, 5)
+                    | creg(rd.0) << 2;
+                (0b00, 0b000, rest)
+            }
+            Inst::Addi {
+                rd,
+                rs1: Reg(0),
+                imm,
+            } if simm(imm, 6) => (0b01, 0b010, ci(rd.0, imm)),
+            Inst::Addiw { rd, rs1, imm } if rd == rs1 && rd.0 != 0 && simm(imm, 6) => {
+                (0b01, 0b001, ci(rd.0, imm))
+            }
+            Inst::Lui { rd, imm } if rd != SP && imm != 0 && imm & 0xfff == 0 && simm(imm, 18) => {
+                let imm = imm as u32;
+                (
+                    0b01,
+                    0b011,
+                    bits(imm, 17, 17, 12) | (rd.0 as u16) << 7 | bits(imm, 16, 12, 2),
+                )
+            }
+            Inst::Srli { rd, rs1, shamt } if rd == rs1 && c(rd.0) => {
+                (0b01, 0b100, ci(rd.0 - 8, shamt as i32))
+            }
+            Inst::Srai { rd, rs1, shamt } if rd == rs1 && c(rd.0) => {
+                (0b01, 0b100, 0b01 << 10 | ci(rd.0 - 8, shamt as i32))
+            }
+            Inst::Andi { rd, rs1, imm } if rd == rs1 && c(rd.0) && simm(imm, 6) => {
+                (0b01, 0b100, 0b10 << 10 | ci(rd.0 - 8, imm))
+            }
+            Inst::Sub { rd, rs1, rs2 } if rd == rs1 && c(rd.0) && c(rs2.0) => {
+                (0b01, 0b100, ca(0b0, rd, 0b00, rs2))
+            }
+            Inst::Xor { rd, rs1, rs2 } if rd == rs1 && c(rd.0) && c(rs2.0) => {
+                (0b01, 0b100, ca(0b0, rd, 0b01, rs2))
+            }
+            Inst::Or { rd, rs1, rs2 } if rd == rs1 && c(rd.0) && c(rs2.0) => {
+                (0b01, 0b100, ca(0b0, rd, 0b10, rs2))
+            }
+            Inst::And { rd, rs1, rs2 } if rd == rs1 && c(rd.0) && c(rs2.0) => {
+                (0b01, 0b100, ca(0b0, rd, 0b11, rs2))
+            }
+            Inst::Subw { rd, rs1, rs2 } if rd == rs1 && c(rd.0) && c(rs2.0) => {
+                (0b01, 0b100, ca(0b1, rd, 0b00, rs2))
+            }
+            Inst::Addw { rd, rs1, rs2 } if rd == rs1 && c(rd.0) && c(rs2.0) => {
+                (0b01, 0b100, ca(0b1, rd, 0b01, rs2))
+            }
+            Inst::Jal { rd: Reg(0), offset } if offset % 2 == 0 && simm(offset, 12) => {
+                let offset = offset as u32;
+                let rest = bits(offset, 11, 11, 12)
+                    | bits(offset, 4, 4, 11)
+                    | bits(offset, 9, 8, 9)
+                    | bits(offset, 10, 10, 8)
+                    | bits(offset, 6, 6, 7)
+                    | bits(offset, 7, 7, 6)
+                    | bits(offset, 3, 1, 3)
+                    | bits(offset, 5, 5, 2);
+                (0b01, 0b101, rest)
+            }
+            Inst::Beq {
+                rs1,
+                rs2: Reg(0),
+                offset,
+            } if c(rs1.0) && offset % 2 == 0 && simm(offset, 9) => (0b01, 0b110, cb(rs1, offset)),
+            Inst::Bne {
+                rs1,
+                rs2: Reg(0),
+                offset,
+            } if c(rs1.0) && offset % 2 == 0 && simm(offset, 9) => (0b01, 0b111, cb(rs1, offset)),
+
+            Inst::Slli { rd, rs1, shamt } if rd == rs1 => (0b10, 0b000, ci(rd.0, shamt as i32)),
+            Inst::Jalr {
+                rd: Reg(0),
+                rs1,
+                offset: 0,
+            } if rs1.0 != 0 => (0b10, 0b100, (rs1.0 as u16) << 7),
+            Inst::Jalr {
+                rd: RA,
+                rs1,
+                offset: 0,
+            } if rs1.0 != 0 => (0b10, 0b100, 1 << 12 | (rs1.0 as u16) << 7),
+            Inst::Add {
+                rd,
+                rs1: Reg(0),
+                rs2,
+            } if rs2.0 != 0 => (0b10, 0b100, cr(rd.0, rs2.0)),
+            Inst::Add { rd, rs1, rs2 } if rd == rs1 && rd.0 != 0 && rs2.0 != 0 => {
+                (0b10, 0b100, 1 << 12 | cr(rd.0, rs2.0))
+            }
+            Inst::Ebreak => (0b10, 0b100, 1 << 12),
+
+            // loads and stores relative to the stack pointer
+            Inst::Lw {
+                rd,
+                rs1: SP,
+                offset,
+            } if rd.0 != 0 && uimm(offset, 8, 4) => {
+                let offset = offset as u32;
+                let imm = bits(offset, 5, 5, 12) | bits(offset, 4, 2, 4) | bits(offset, 7, 6, 2);
+                (0b10, 0b010, imm | (rd.0 as u16) << 7)
+            }
+            Inst::Ld {
+                rd,
+                rs1: SP,
+                offset,
+            } if rd.0 != 0 && uimm(offset, 9, 8) => {
+                (0b10, 0b011, load_sp_d(offset) | (rd.0 as u16) << 7)
+            }
+            Inst::Fld {
+                rd,
+                rs1: SP,
+                offset,
+            } if uimm(offset, 9, 8) => (0b10, 0b001, load_sp_d(offset) | (rd.0 as u16) << 7),
+            Inst::Sw {
+                rs1: SP,
+                rs2,
+                offset,
+            } if uimm(offset, 8, 4) => {
+                let offset = offset as u32;
+                let imm = bits(offset, 5, 2, 9) | bits(offset, 7, 6, 7);
+                (0b10, 0b110, imm | (rs2.0 as u16) << 2)
+            }
+            Inst::Sd {
+                rs1: SP,
+                rs2,
+                offset,
+            } if uimm(offset, 9, 8) => (0b10, 0b111, store_sp_d(offset) | (rs2.0 as u16) << 2),
+            Inst::Fsd {
+                rs1: SP,
+                rs2,
+                offset,
+            } if uimm(offset, 9, 8) => (0b10, 0b101, store_sp_d(offset) | (rs2.0 as u16) << 2),
+
+            // loads and stores between x8-x15
+            Inst::Lw { rd, rs1, offset } if c(rd.0) && c(rs1.0) && uimm(offset, 7, 4) => {
+                let offset = offset as u32;
+                let imm = bits(offset, 5, 3, 10) | bits(offset, 2, 2, 6) | bits(offset, 6, 6, 5);
+                (0b00, 0b010, imm | cl(rs1.0, rd.0))
+            }
+            Inst::Ld { rd, rs1, offset } if c(rd.0) && c(rs1.0) && uimm(offset, 8, 8) => {
+                (0b00, 0b011, mem_d(offset) | cl(rs1.0, rd.0))
+            }
+            Inst::Fld { rd, rs1, offset } if c(rd.0) && c(rs1.0) && uimm(offset, 8, 8) => {
+                (0b00, 0b001, mem_d(offset) | cl(rs1.0, rd.0))
+            }
+            Inst::Sw { rs1, rs2, offset } if c(rs1.0) && c(rs2.0) && uimm(offset, 7, 4) => {
+                let offset = offset as u32;
+                let imm = bits(offset, 5, 3, 10) | bits(offset, 2, 2, 6) | bits(offset, 6, 6, 5);
+                (0b00, 0b110, imm | cl(rs1.0, rs2.0))
+            }
+            Inst::Sd { rs1, rs2, offset } if c(rs1.0) && c(rs2.0) && uimm(offset, 8, 8) => {
+                (0b00, 0b111, mem_d(offset) | cl(rs1.0, rs2.0))
+            }
+            Inst::Fsd { rs1, rs2, offset } if c(rs1.0) && c(rs2.0) && uimm(offset, 8, 8) => {
+                (0b00, 0b101, mem_d(offset) | cl(rs1.0, rs2.0))
+            }
+
+            _ => return None,
+        };
+
+        Some(funct3 << 13 | rest | quadrant)
+    }
+
     // returns the instruction along with the number of bytes read
     pub fn decode(inst: u32) -> (Inst, u8) {
         match inst & 0b11 {
@@ -260,7 +1169,10 @@ impl Inst {
                     _ => Inst::Error(inst),
                 }
             }
-            0b0001111 => Inst::Fence,
+            0b0001111 => match funct3 {
+                0b001 => Inst::FenceI,
+                _ => Inst::Fence,
+            },
             0b0010011 => {
                 let imm = (inst & 0xFFF00000) as i32 >> 20;
                 match funct3 {
@@ -460,7 +1372,7 @@ impl Inst {
 
             // floating point operations
             0b1010011 => {
-                let rm = ((inst >> 12) & 0b11) as u8;
+                let rm = ((inst >> 12) & 0b111) as u8;
                 match (funct7, rs2.0, rm) {
                     (0b001101, rs2, _rm) => Inst::Fdivd {
                         rd: FReg(rd.0),
@@ -499,7 +1411,7 @@ impl Inst {
             }
 
             0b1100111 => {
-                let offset = (inst & 0xFFF00000) as i32 >> 12;
+                let offset = (inst & 0xFFF00000) as i32 >> 20;
                 match funct3 {
                     0b000 => Inst::Jalr { rd, rs1, offset },
                     _ => Inst::Error(inst),
@@ -515,11 +1427,26 @@ impl Inst {
                 Inst::Jal { rd, offset }
             }
 
-            0b1110011 => match (funct7, rs2.0, rs1.0, funct3, rd.0) {
-                (0, 0, 0, 0, 0) => Inst::Ecall,
-                (1, 0, 0, 0, 0) => Inst::Ebreak,
-                _ => Inst::Error(inst),
-            },
+            0b1110011 => {
+                let csr = (inst >> 20) as u16;
+                let uimm = rs1.0;
+
+                match (funct7, rs2.0, rs1.0, funct3, rd.0) {
+                    (0, 0, 0, 0, 0) => Inst::Ecall,
+                    (0, 1, 0, 0, 0) => Inst::Ebreak,
+                    (0b0011000, 0b00010, 0, 0, 0) => Inst::Mret,
+                    (0b0001000, 0b00010, 0, 0, 0) => Inst::Sret,
+                    (0b0001000, 0b00101, 0, 0, 0) => Inst::Wfi,
+                    (0b0001001, _, _, 0, 0) => Inst::SfenceVma,
+                    (_, _, _, 0b001, _) => Inst::Csrrw { rd, rs1, csr },
+                    (_, _, _, 0b010, _) => Inst::Csrrs { rd, rs1, csr },
+                    (_, _, _, 0b011, _) => Inst::Csrrc { rd, rs1, csr },
+                    (_, _, _, 0b101, _) => Inst::Csrrwi { rd, uimm, csr },
+                    (_, _, _, 0b110, _) => Inst::Csrrsi { rd, uimm, csr },
+                    (_, _, _, 0b111, _) => Inst::Csrrci { rd, uimm, csr },
+                    _ => Inst::Error(inst),
+                }
+            }
 
             _ => Inst::Error(inst),
         }
@@ -582,7 +1509,7 @@ impl Inst {
                         }
                     }
                     0b011 => {
-                        // C.LD
+                        // C.LD - C.FLW on RV32C
                         let rd = Reg((((inst >> 2) & 0b111) + 8) as u8);
                         let rs1 = Reg((((inst >> 7) & 0b111) + 8) as u8);
                         let offset = (inst & 0b1100000) << 1 // imm[7:6]
@@ -622,7 +1549,7 @@ impl Inst {
                         }
                     }
                     0b111 => {
-                        // C.SD
+                        // C.SD - C.FSW on RV32C
                         let rs1 = Reg((((inst >> 7) & 0b111) + 8) as u8);
                         let rs2 = Reg((((inst >> 2) & 0b111) + 8) as u8);
                         let imm = (inst & 0b1110000000000) >> 7 // imm[5:3]
@@ -653,16 +1580,20 @@ impl Inst {
                         }
                     }
                     0b001 => {
-                        // C.ADDIW
+                        // C.ADDIW - C.JAL on RV32C
 
                         let imm = (((inst & 0b1000000000000) << 3) as i16 >> 10) // imm[5]
                                 | (inst & 0b1111100) as i16 >> 2; // imm[4:0]
                         let rd = Reg(((inst >> 7) & 0b11111) as u8);
 
-                        Inst::Addiw {
-                            rd,
-                            rs1: rd,
-                            imm: imm as i32,
+                        if rd.0 == 0 {
+                            Inst::Error(inst as u32)
+                        } else {
+                            Inst::Addiw {
+                                rd,
+                                rs1: rd,
+                                imm: imm as i32,
+                            }
                         }
                     }
                     0b010 => {
@@ -689,17 +1620,25 @@ impl Inst {
                                     | ((inst & 0b100000) << 1) as i32 // imm[6]
                                     | ((inst & 0b1000000) >> 2) as i32; // imm[4]
 
-                            Inst::Addi {
-                                rd: SP,
-                                rs1: SP,
-                                imm,
+                            if imm == 0 {
+                                Inst::Error(inst as u32)
+                            } else {
+                                Inst::Addi {
+                                    rd: SP,
+                                    rs1: SP,
+                                    imm,
+                                }
                             }
                         } else {
                             // C.LUI
                             let imm = ((((inst & 0b1000000000000) << 3) as i16 as i32) << 2)  // imm[17]
                                     | ((inst as u32 & 0b1111100) << 10) as i32; // imm[16:12]
 
-                            Inst::Lui { rd, imm }
+                            if imm == 0 {
+                                Inst::Error(inst as u32)
+                            } else {
+                                Inst::Lui { rd, imm }
+                            }
                         }
                     }
                     0b100 => {
@@ -712,14 +1651,11 @@ impl Inst {
                                 let shamt = (inst & 0b1000000000000) >> 7 // imm[5]
                                           | (inst & 0b1111100) >> 2; // imm[4:0]
 
-                                if shamt == 0 {
-                                    Inst::Error(inst as u32)
-                                } else {
-                                    Inst::Srli {
-                                        rd,
-                                        rs1: rd,
-                                        shamt: shamt as u32,
-                                    }
+                                // a shift by 0 is a hint
+                                Inst::Srli {
+                                    rd,
+                                    rs1: rd,
+                                    shamt: shamt as u32,
                                 }
                             }
 
@@ -728,14 +1664,10 @@ impl Inst {
                                 let shamt = (inst & 0b1000000000000) >> 7 // imm[5]
                                           | (inst & 0b1111100) >> 2; // imm[4:0]
 
-                                if shamt == 0 {
-                                    Inst::Error(inst as u32)
-                                } else {
-                                    Inst::Srai {
-                                        rd,
-                                        rs1: rd,
-                                        shamt: shamt as u32,
-                                    }
+                                Inst::Srai {
+                                    rd,
+                                    rs1: rd,
+                                    shamt: shamt as u32,
                                 }
                             }
 
@@ -821,19 +1753,15 @@ impl Inst {
             0b10 => {
                 match funct3 {
                     0b000 => {
-                        // C.SLLI
+                        // C.SLLI, a hint when rd is x0 or the shift is by 0
                         let rd = Reg(((inst >> 7) & 0b11111) as u8);
                         let shamt = (inst & 0b1000000000000) >> 7 // imm[5]
                                   | (inst & 0b1111100) >> 2; // imm[4:0]
 
-                        if shamt != 0 {
-                            Inst::Slli {
-                                rd,
-                                rs1: rd,
-                                shamt: shamt as u32,
-                            }
-                        } else {
-                            Inst::Error(inst as u32)
+                        Inst::Slli {
+                            rd,
+                            rs1: rd,
+                            shamt: shamt as u32,
                         }
                     }
                     0b001 => {
@@ -889,37 +1817,31 @@ impl Inst {
                         let rs1 = Reg(((inst >> 7) & 0b11111) as u8);
                         let rs2 = Reg(((inst >> 2) & 0b11111) as u8);
 
-                        // C.JR - ret
-                        if imm == 0 && rs1.0 != 0 && rs2.0 == 0 {
-                            Inst::Jalr {
+                        // C.MV and C.ADD with rd = x0 are hints
+                        match (imm, rs1.0, rs2.0) {
+                            (0, 0, 0) => Inst::Error(inst as u32),
+                            // C.JR - ret
+                            (0, _, 0) => Inst::Jalr {
                                 rd: Reg(0),
                                 rs1,
                                 offset: 0,
-                            }
-                        }
-                        // C.MV - Move
-                        else if imm == 0 && rs1.0 != 0 && rs2.0 != 0 {
-                            Inst::Add {
+                            },
+                            // C.MV - Move
+                            (0, _, _) => Inst::Add {
                                 rd: rs1,
                                 rs1: Reg(0),
                                 rs2,
-                            }
-                        }
-                        // C.ADD - Add
-                        else if imm == 1 && rs1.0 != 0 && rs2.0 != 0 {
-                            Inst::Add { rd: rs1, rs1, rs2 }
-                        }
-                        // C.JALR
-                        else if imm == 1 && rs1.0 != 0 && rs2.0 == 0 {
-                            Inst::Jalr {
+                            },
+                            // C.EBREAK
+                            (_, 0, 0) => Inst::Ebreak,
+                            // C.JALR
+                            (_, _, 0) => Inst::Jalr {
                                 rd: RA,
                                 rs1,
                                 offset: 0,
-                            }
-                        }
-                        // C.EBREAK
-                        else {
-                            Inst::Ebreak
+                            },
+                            // C.ADD - Add
+                            _ => Inst::Add { rd: rs1, rs1, rs2 },
                         }
                     }
                     0b101 => {
@@ -970,6 +1892,132 @@ impl Inst {
     }
 }
 
+// the major opcodes, the lowest 7 bits of uncompressed instructions
+pub(crate) const OP_LOAD: u32 = 0b0000011;
+pub(crate) const OP_LOAD_FP: u32 = 0b0000111;
+pub(crate) const OP_IMM: u32 = 0b0010011;
+pub(crate) const OP_AUIPC: u32 = 0b0010111;
+pub(crate) const OP_IMM_32: u32 = 0b0011011;
+pub(crate) const OP_STORE: u32 = 0b0100011;
+pub(crate) const OP_STORE_FP: u32 = 0b0100111;
+pub(crate) const OP_AMO: u32 = 0b0101111;
+pub(crate) const OP: u32 = 0b0110011;
+pub(crate) const OP_LUI: u32 = 0b0110111;
+pub(crate) const OP_32: u32 = 0b0111011;
+pub(crate) const OP_FP: u32 = 0b1010011;
+pub(crate) const OP_BRANCH: u32 = 0b1100011;
+pub(crate) const OP_JALR: u32 = 0b1100111;
+pub(crate) const OP_JAL: u32 = 0b1101111;
+pub(crate) const OP_SYSTEM: u32 = 0b1110011;
+
+// the uncompressed instruction formats, which take immediates as the bits of a signed number and
+// ignore the bits that don't fit
+
+pub(crate) fn r_type(opcode: u32, funct3: u32, funct7: u32, rd: u8, rs1: u8, rs2: u8) -> u32 {
+    funct7 << 25
+        | (rs2 as u32) << 20
+        | (rs1 as u32) << 15
+        | funct3 << 12
+        | (rd as u32) << 7
+        | opcode
+}
+
+pub(crate) fn i_type(opcode: u32, funct3: u32, rd: Reg, rs1: Reg, imm: u32) -> u32 {
+    (imm & 0xfff) << 20 | (rs1.0 as u32) << 15 | funct3 << 12 | (rd.0 as u32) << 7 | opcode
+}
+
+pub(crate) fn s_type(opcode: u32, funct3: u32, rs1: Reg, rs2: Reg, imm: u32) -> u32 {
+    (imm >> 5 & 0x7f) << 25
+        | (rs2.0 as u32) << 20
+        | (rs1.0 as u32) << 15
+        | funct3 << 12
+        | (imm & 0x1f) << 7
+        | opcode
+}
+
+pub(crate) fn b_type(funct3: u32, rs1: Reg, rs2: Reg, offset: u32) -> u32 {
+    (offset >> 12 & 1) << 31
+        | (offset >> 5 & 0x3f) << 25
+        | (rs2.0 as u32) << 20
+        | (rs1.0 as u32) << 15
+        | funct3 << 12
+        | (offset >> 1 & 0xf) << 8
+        | (offset >> 11 & 1) << 7
+        | OP_BRANCH
+}
+
+pub(crate) fn u_type(opcode: u32, rd: Reg, imm: u32) -> u32 {
+    (imm & 0xfffff) << 12 | (rd.0 as u32) << 7 | opcode
+}
+
+pub(crate) fn j_type(rd: Reg, offset: u32) -> u32 {
+    (offset >> 20 & 1) << 31
+        | (offset >> 1 & 0x3ff) << 21
+        | (offset >> 11 & 1) << 20
+        | (offset >> 12 & 0xff) << 12
+        | (rd.0 as u32) << 7
+        | OP_JAL
+}
+
+// the parts of compressed instructions, without the quadrant and funct3
+
+// bits `high` to `low` of `value`, moved to start at bit `at`
+fn bits(value: u32, high: u32, low: u32, at: u32) -> u16 {
+    ((value >> low & ((1 << (high - low + 1)) - 1)) << at) as u16
+}
+
+// the 3-bit field of one of x8-x15
+fn creg(reg: u8) -> u16 {
+    reg as u16 - 8
+}
+
+// a 6-bit immediate split around a 5-bit register, like c.addi
+fn ci(reg: u8, imm: i32) -> u16 {
+    bits(imm as u32, 5, 5, 12) | (reg as u16) << 7 | bits(imm as u32, 4, 0, 2)
+}
+
+// two full registers, like c.mv
+fn cr(rd: u8, rs2: u8) -> u16 {
+    (rd as u16) << 7 | (rs2 as u16) << 2
+}
+
+// arithmetic between x8-x15, like c.sub
+fn ca(word: u16, rd: Reg, funct2: u16, rs2: Reg) -> u16 {
+    word << 12 | 0b11 << 10 | creg(rd.0) << 7 | funct2 << 5 | creg(rs2.0) << 2
+}
+
+// c.beqz and c.bnez
+fn cb(rs1: Reg, offset: i32) -> u16 {
+    let offset = offset as u32;
+    bits(offset, 8, 8, 12)
+        | bits(offset, 4, 3, 10)
+        | creg(rs1.0) << 7
+        | bits(offset, 7, 6, 5)
+        | bits(offset, 2, 1, 3)
+        | bits(offset, 5, 5, 2)
+}
+
+// the registers of a load or store between x8-x15
+fn cl(rs1: u8, reg: u8) -> u16 {
+    creg(rs1) << 7 | creg(reg) << 2
+}
+
+// the offset of a doubleword load or store between x8-x15
+fn mem_d(offset: i32) -> u16 {
+    bits(offset as u32, 5, 3, 10) | bits(offset as u32, 7, 6, 5)
+}
+
+// the offset of a doubleword load from the stack pointer
+fn load_sp_d(offset: i32) -> u16 {
+    let offset = offset as u32;
+    bits(offset, 5, 5, 12) | bits(offset, 4, 3, 5) | bits(offset, 8, 6, 2)
+}
+
+// the offset of a doubleword store to the stack pointer
+fn store_sp_d(offset: i32) -> u16 {
+    bits(offset as u32, 5, 3, 10) | bits(offset as u32, 8, 6, 7)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1120,4 +2168,250 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn csr_decoding() {
+        // csrr a0, mhartid
+        let (inst, _) = Inst::decode(0xf1402573);
+        assert_eq!(
+            inst,
+            Inst::Csrrs {
+                rd: A0,
+                rs1: Reg(0),
+                csr: 0xf14
+            }
+        );
+
+        // csrwi fflags, 1
+        let (inst, _) = Inst::decode(0x0010d073);
+        assert_eq!(
+            inst,
+            Inst::Csrrwi {
+                rd: Reg(0),
+                uimm: 1,
+                csr: 0x001
+            }
+        );
+    }
+
+    #[test]
+    fn encode_round_trip() {
+        // every compressed instruction, which also all have an uncompressed encoding
+        for inst in 0..=u16::MAX {
+            let decoded = match Inst::decode(inst as u32) {
+                (Inst::Error(_), _) | (_, 4) => continue,
+                (decoded, _) => decoded,
+            };
+
+            let compressed = decoded.encode_compressed();
+            assert_eq!(
+                compressed.map(|inst| Inst::decode(inst as u32).0),
+                Some(decoded),
+                "{inst:04x}"
+            );
+            assert_eq!(Inst::decode(decoded.encode()).0, decoded, "{inst:04x}");
+        }
+
+        // and a spread of uncompressed ones
+        let mut inst: u32 = 0x12345678;
+        for _ in 0..1 << 18 {
+            inst ^= inst << 13;
+            inst ^= inst >> 17;
+            inst ^= inst << 5;
+
+            let decoded = Inst::decode(inst | 0b11).0;
+            if !matches!(decoded, Inst::Error(_)) {
+                assert_eq!(Inst::decode(decoded.encode()).0, decoded, "{inst:08x}");
+            }
+        }
+    }
+
+    // the 32-bit instruction a compressed one expands to, following the tables of the C
+    // extension, or None if it's reserved
+    fn expand(c: u16) -> Option<u32> {
+        let c = c as u32;
+        let bits = |hi: u32, lo: u32| (c >> lo) & ((1 << (hi - lo + 1)) - 1);
+        let sext = |value: u32, width: u32| ((value << (32 - width)) as i32 >> (32 - width)) as u32;
+        let i = |imm: u32, rs1: u32, f3: u32, rd: u32, op: u32| {
+            (imm & 0xfff) << 20 | rs1 << 15 | f3 << 12 | rd << 7 | op
+        };
+        let s = |imm: u32, rs2: u32, rs1: u32, f3: u32, op: u32| {
+            (imm >> 5 & 0x7f) << 25 | rs2 << 20 | rs1 << 15 | f3 << 12 | (imm & 0x1f) << 7 | op
+        };
+        let r = |f7: u32, rs2: u32, rs1: u32, f3: u32, rd: u32, op: u32| {
+            f7 << 25 | rs2 << 20 | rs1 << 15 | f3 << 12 | rd << 7 | op
+        };
+        let b = |imm: u32, rs1: u32, f3: u32| {
+            (imm >> 12 & 1) << 31
+                | (imm >> 5 & 0x3f) << 25
+                | rs1 << 15
+                | f3 << 12
+                | (imm >> 1 & 0xf) << 8
+                | (imm >> 11 & 1) << 7
+                | 0x63
+        };
+
+        // the 3-bit registers of the CIW, CL, CS, CA and CB formats
+        let rd_ = bits(4, 2) + 8;
+        let rs1_ = bits(9, 7) + 8;
+        let rs2_ = rd_;
+        let rd = bits(11, 7);
+        let rs2 = bits(6, 2);
+        let imm6 = sext(bits(12, 12) << 5 | bits(6, 2), 6);
+        let shamt = bits(12, 12) << 5 | bits(6, 2);
+        // the offsets of C.FLD/C.LD and C.LW
+        let uimm_d = bits(12, 10) << 3 | bits(6, 5) << 6;
+        let uimm_w = bits(12, 10) << 3 | bits(6, 6) << 2 | bits(5, 5) << 6;
+
+        Some(match (c & 0b11, c >> 13) {
+            (0b00, 0b000) => {
+                let imm = bits(12, 11) << 4 | bits(10, 7) << 6 | bits(6, 6) << 2 | bits(5, 5) << 3;
+                if imm == 0 {
+                    return None;
+                }
+                i(imm, 2, 0, rd_, 0x13)
+            }
+            (0b00, 0b001) => i(uimm_d, rs1_, 3, rd_, 0x07),
+            (0b00, 0b010) => i(uimm_w, rs1_, 2, rd_, 0x03),
+            (0b00, 0b011) => i(uimm_d, rs1_, 3, rd_, 0x03),
+            (0b00, 0b100) => return None,
+            (0b00, 0b101) => s(uimm_d, rs2_, rs1_, 3, 0x27),
+            (0b00, 0b110) => s(uimm_w, rs2_, rs1_, 2, 0x23),
+            (0b00, 0b111) => s(uimm_d, rs2_, rs1_, 3, 0x23),
+
+            (0b01, 0b000) => i(imm6, rd, 0, rd, 0x13),
+            (0b01, 0b001) if rd == 0 => return None,
+            (0b01, 0b001) => i(imm6, rd, 0, rd, 0x1b),
+            (0b01, 0b010) => i(imm6, 0, 0, rd, 0x13),
+            (0b01, 0b011) if rd == 2 => {
+                let imm = bits(12, 12) << 9
+                    | bits(6, 6) << 4
+                    | bits(5, 5) << 6
+                    | bits(4, 3) << 7
+                    | bits(2, 2) << 5;
+                if imm == 0 {
+                    return None;
+                }
+                i(sext(imm, 10), 2, 0, 2, 0x13)
+            }
+            (0b01, 0b011) => {
+                if imm6 == 0 {
+                    return None;
+                }
+                imm6 << 12 | rd << 7 | 0x37
+            }
+            (0b01, 0b100) => {
+                let rd = rs1_;
+                match (bits(11, 10), bits(12, 12), bits(6, 5)) {
+                    (0b00, _, _) => i(shamt, rd, 5, rd, 0x13),
+                    (0b01, _, _) => i(shamt | 0x400, rd, 5, rd, 0x13),
+                    (0b10, _, _) => i(imm6, rd, 7, rd, 0x13),
+                    (_, 0, 0b00) => r(0x20, rs2_, rd, 0, rd, 0x33),
+                    (_, 0, 0b01) => r(0, rs2_, rd, 4, rd, 0x33),
+                    (_, 0, 0b10) => r(0, rs2_, rd, 6, rd, 0x33),
+                    (_, 0, _) => r(0, rs2_, rd, 7, rd, 0x33),
+                    (_, _, 0b00) => r(0x20, rs2_, rd, 0, rd, 0x3b),
+                    (_, _, 0b01) => r(0, rs2_, rd, 0, rd, 0x3b),
+                    _ => return None,
+                }
+            }
+            (0b01, 0b101) => {
+                let imm = bits(12, 12) << 11
+                    | bits(11, 11) << 4
+                    | bits(10, 9) << 8
+                    | bits(8, 8) << 10
+                    | bits(7, 7) << 6
+                    | bits(6, 6) << 7
+                    | bits(5, 3) << 1
+                    | bits(2, 2) << 5;
+                let imm = sext(imm, 12);
+                (imm >> 20 & 1) << 31
+                    | (imm >> 1 & 0x3ff) << 21
+                    | (imm >> 11 & 1) << 20
+                    | (imm >> 12 & 0xff) << 12
+                    | 0x6f
+            }
+            (0b01, funct3) => {
+                let imm = bits(12, 12) << 8
+                    | bits(11, 10) << 3
+                    | bits(6, 5) << 6
+                    | bits(4, 3) << 1
+                    | bits(2, 2) << 5;
+                b(sext(imm, 9), rs1_, funct3 & 1)
+            }
+
+            (0b10, 0b000) => i(shamt, rd, 1, rd, 0x13),
+            (0b10, 0b001) => {
+                let imm = bits(12, 12) << 5 | bits(6, 5) << 3 | bits(4, 2) << 6;
+                i(imm, 2, 3, rd, 0x07)
+            }
+            (0b10, 0b010 | 0b011) if rd == 0 => return None,
+            (0b10, 0b010) => {
+                let imm = bits(12, 12) << 5 | bits(6, 4) << 2 | bits(3, 2) << 6;
+                i(imm, 2, 2, rd, 0x03)
+            }
+            (0b10, 0b011) => {
+                let imm = bits(12, 12) << 5 | bits(6, 5) << 3 | bits(4, 2) << 6;
+                i(imm, 2, 3, rd, 0x03)
+            }
+            (0b10, 0b100) => match (bits(12, 12), rd, rs2) {
+                (0, 0, 0) => return None,
+                (0, _, 0) => i(0, rd, 0, 0, 0x67),
+                (0, _, _) => r(0, rs2, 0, 0, rd, 0x33),
+                (_, 0, 0) => 0x00100073,
+                (_, _, 0) => i(0, rd, 0, 1, 0x67),
+                _ => r(0, rs2, rd, 0, rd, 0x33),
+            },
+            (0b10, 0b101) => s(bits(12, 10) << 3 | bits(9, 7) << 6, rs2, 2, 3, 0x27),
+            (0b10, 0b110) => s(bits(12, 9) << 2 | bits(8, 7) << 6, rs2, 2, 2, 0x23),
+            (0b10, _) => s(bits(12, 10) << 3 | bits(9, 7) << 6, rs2, 2, 3, 0x23),
+
+            _ => unreachable!(),
+        })
+    }
+
+    #[test]
+    fn compressed_decoding_is_complete() {
+        for c in (0..=u16::MAX).filter(|c| c & 0b11 != 0b11) {
+            let expected = match expand(c) {
+                Some(inst) => Inst::decode(inst).0,
+                None => Inst::Error(c as u32),
+            };
+            assert_eq!(Inst::decode(c as u32), (expected, 2), "{c:04x}");
+        }
+    }
+
+    #[test]
+    fn formatting() {
+        let fmt = |inst: u32, pc| Inst::decode(inst).0.fmt(pc);
+
+        assert_eq!(fmt(0xff5ff0ef, 0x1010), "jal   ra, 1004"); // jal ra, -12
+        assert_eq!(fmt(0xfeb54ce3, 0x1010), "blt   a0, a1, 1008");
+        assert_eq!(fmt(0xff810503, 0), "lb    a0, -8(sp)");
+        assert_eq!(fmt(0x02b53533, 0), "mulhu a0, a0, a1");
+        assert_eq!(fmt(0x00b5553b, 0), "srlw  a0, a0, a1");
+        assert_eq!(fmt(0x12345517, 0), "auipc a0, 0x12345");
+        assert_eq!(fmt(0x18b5352f, 0), "sc.d  a0, a1, 0(a0)");
+        assert_eq!(fmt(0xa2b50553, 0), "fle.d a0, fa0, fa1");
+        assert_eq!(fmt(0xd2351553, 0), "fcvt.d.lu a0, fa0, rtz");
+        assert_eq!(fmt(0xc0002573, 0), "csrrs a0, 0xc00, x0");
+        assert_eq!(fmt(0x00100073, 0), "ebreak");
+        assert_eq!(fmt(0xffffffff, 0), "error: ffffffff");
+    }
+
+    #[test]
+    fn jalr_decoding() {
+        // jalr ra, 8(a0)
+        let (inst, _) = Inst::decode(0x008500e7);
+        assert_eq!(
+            inst,
+            Inst::Jalr {
+                rd: RA,
+                rs1: A0,
+                offset: 8
+            }
+        );
+        assert_eq!(inst.encode(), 0x008500e7);
+        assert_eq!(inst.encode_compressed(), None);
+    }
 }
@@ -0,0 +1,363 @@
+// A thin layer between decoding and execution, which both the interpreter's blocks and the jit
+// run on. Decoded instructions are turned into ops, and a few common patterns are combined into
+// cheaper ones along the way.
+
+use alloc::vec::Vec;
+
+use crate::{instruction::Inst, register::Reg};
+
+/// The right hand side of a comparison
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    Reg(Reg),
+    Imm(u64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    /// A single instruction of `len` bytes, executed as is
+    Inst(Inst, u8),
+
+    /// An instruction of `len` bytes which only writes to x0, and so does nothing
+    Nop(u8),
+
+    /// `lui` or `auipc` followed by an `addi` or `addiw` of the same register
+    Li { rd: Reg, imm: u64, len: u8 },
+
+    /// `auipc` followed by an `ld` relative to it, as used to load from the global offset table.
+    /// `base` is the register the `auipc` sets to `upper`, kept for when the load faults.
+    LoadGlobal {
+        base: Reg,
+        upper: u64,
+        rd: Reg,
+        addr: u64,
+        auipc_len: u8,
+        len: u8,
+    },
+
+    /// `slli` followed by an `srli` by the same amount, keeping only the bits in `mask`
+    ZeroExtend {
+        rd: Reg,
+        rs1: Reg,
+        mask: u64,
+        len: u8,
+    },
+
+    /// `slt`, `sltu`, `slti` or `sltiu` followed by a `bnez` (`branch_if` true) or `beqz` on its
+    /// result. `offset` is relative to the branch, which starts `cmp_len` bytes into the op.
+    CmpBranch {
+        rd: Reg,
+        rs1: Reg,
+        rs2: Operand,
+        signed: bool,
+        branch_if: bool,
+        offset: i32,
+        cmp_len: u8,
+        len: u8,
+    },
+}
+
+impl Op {
+    /// The number of bytes of instructions the op replaces
+    pub fn len(&self) -> u8 {
+        match *self {
+            Op::Inst(_, len)
+            | Op::Nop(len)
+            | Op::Li { len, .. }
+            | Op::LoadGlobal { len, .. }
+            | Op::ZeroExtend { len, .. }
+            | Op::CmpBranch { len, .. } => len,
+        }
+    }
+
+    /// The number of instructions the op replaces
+    pub fn inst_count(&self) -> u64 {
+        match self {
+            Op::Inst(..) | Op::Nop(_) => 1,
+            Op::Li { .. }
+            | Op::LoadGlobal { .. }
+            | Op::ZeroExtend { .. }
+            | Op::CmpBranch { .. } => 2,
+        }
+    }
+}
+
+// whether inst does nothing but write a value to rd, so it can be dropped when rd is x0
+fn nop_if_x0(inst: Inst) -> Option<Reg> {
+    match inst {
+        Inst::Lui { rd, .. }
+        | Inst::Auipc { rd, .. }
+        | Inst::Add { rd, .. }
+        | Inst::Addw { rd, .. }
+        | Inst::Addi { rd, .. }
+        | Inst::Addiw { rd, .. }
+        | Inst::And { rd, .. }
+        | Inst::Andi { rd, .. }
+        | Inst::Sub { rd, .. }
+        | Inst::Subw { rd, .. }
+        | Inst::Sll { rd, .. }
+        | Inst::Sllw { rd, .. }
+        | Inst::Slli { rd, .. }
+        | Inst::Slliw { rd, .. }
+        | Inst::Srl { rd, .. }
+        | Inst::Srlw { rd, .. }
+        | Inst::Srli { rd, .. }
+        | Inst::Srliw { rd, .. }
+        | Inst::Sra { rd, .. }
+        | Inst::Sraw { rd, .. }
+        | Inst::Srai { rd, .. }
+        | Inst::Sraiw { rd, .. }
+        | Inst::Or { rd, .. }
+        | Inst::Ori { rd, .. }
+        | Inst::Xor { rd, .. }
+        | Inst::Xori { rd, .. }
+        | Inst::Slt { rd, .. }
+        | Inst::Sltu { rd, .. }
+        | Inst::Slti { rd, .. }
+        | Inst::Sltiu { rd, .. }
+        | Inst::Mul { rd, .. }
+        | Inst::Mulhu { rd, .. } => Some(rd),
+        _ => None,
+    }
+}
+
+// folds `first` at `pc` and `second` into a single constant load
+fn fold_constant(pc: u64, first: Inst, second: Inst) -> Option<(Reg, u64)> {
+    let (rd, upper) = match first {
+        Inst::Lui { rd, imm } => (rd, imm as u64),
+        Inst::Auipc { rd, imm } => (rd, pc.wrapping_add(imm as i64 as u64)),
+        _ => return None,
+    };
+
+    let value = match second {
+        Inst::Addi { rd: rd2, rs1, imm } if rd2 == rd && rs1 == rd => {
+            upper.wrapping_add(imm as u64)
+        }
+        Inst::Addiw { rd: rd2, rs1, imm } if rd2 == rd && rs1 == rd => {
+            (upper as i32).wrapping_add(imm) as u64
+        }
+        _ => return None,
+    };
+
+    Some((rd, value))
+}
+
+// merges an `auipc` into the `ld` using it as a base. a load into x0 is left
+// alone, since `LoadGlobal` writes `rd` directly
+fn fuse_load(pc: u64, first: (Inst, u8), second: (Inst, u8)) -> Option<Op> {
+    match (first.0, second.0) {
+        (Inst::Auipc { rd: base, imm }, Inst::Ld { rd, rs1, offset })
+            if rs1 == base && rd != Reg(0) =>
+        {
+            let upper = pc.wrapping_add(imm as i64 as u64);
+
+            Some(Op::LoadGlobal {
+                base,
+                upper,
+                rd,
+                addr: upper.wrapping_add(offset as u64),
+                auipc_len: first.1,
+                len: first.1 + second.1,
+            })
+        }
+        _ => None,
+    }
+}
+
+// merges a left shift and a right shift back by the same amount into a mask
+fn fuse_zero_extend(first: (Inst, u8), second: (Inst, u8)) -> Option<Op> {
+    match (first.0, second.0) {
+        (
+            Inst::Slli { rd, rs1, shamt },
+            Inst::Srli {
+                rd: rd2,
+                rs1: rs1_2,
+                shamt: shamt2,
+            },
+        ) if rd2 == rd && rs1_2 == rd && shamt2 == shamt => Some(Op::ZeroExtend {
+            rd,
+            rs1,
+            mask: u64::MAX >> shamt,
+            len: first.1 + second.1,
+        }),
+        _ => None,
+    }
+}
+
+// merges a comparison into the branch on its result that follows it
+fn fuse_branch(first: (Inst, u8), second: (Inst, u8)) -> Option<Op> {
+    let (rd, rs1, rs2, signed) = match first.0 {
+        Inst::Slt { rd, rs1, rs2 } => (rd, rs1, Operand::Reg(rs2), true),
+        Inst::Sltu { rd, rs1, rs2 } => (rd, rs1, Operand::Reg(rs2), false),
+        Inst::Slti { rd, rs1, imm } => (rd, rs1, Operand::Imm(imm as i64 as u64), true),
+        Inst::Sltiu { rd, rs1, imm } => (rd, rs1, Operand::Imm(imm as u64), false),
+        _ => return None,
+    };
+
+    let zero = Reg(0);
+    let (branch_if, offset) = match second.0 {
+        Inst::Bne {
+            rs1: a,
+            rs2: b,
+            offset,
+        } if (a, b) == (rd, zero) || (a, b) == (zero, rd) => (true, offset),
+        Inst::Beq {
+            rs1: a,
+            rs2: b,
+            offset,
+        } if (a, b) == (rd, zero) || (a, b) == (zero, rd) => (false, offset),
+        _ => return None,
+    };
+
+    Some(Op::CmpBranch {
+        rd,
+        rs1,
+        rs2,
+        signed,
+        branch_if,
+        offset,
+        cmp_len: first.1,
+        len: first.1 + second.1,
+    })
+}
+
+/// Turns the instructions starting at `pc` into ops. Instructions for which `is_target` is true
+/// are never merged into the previous one, so they can still be jumped to.
+pub fn optimize(pc: u64, insts: &[(Inst, u8)], is_target: impl Fn(u64) -> bool) -> Vec<Op> {
+    let mut ops = Vec::with_capacity(insts.len());
+    let mut pc = pc;
+    let mut i = 0;
+
+    while i < insts.len() {
+        let (inst, len) = insts[i];
+
+        if nop_if_x0(inst) == Some(Reg(0)) {
+            ops.push(Op::Nop(len));
+            pc += len as u64;
+            i += 1;
+            continue;
+        }
+
+        if let Some(&(next, next_len)) = insts.get(i + 1) {
+            if !is_target(pc + len as u64) {
+                let (first, second) = ((inst, len), (next, next_len));
+
+                let fused = match fold_constant(pc, inst, next) {
+                    Some((rd, imm)) if rd != Reg(0) => Some(Op::Li {
+                        rd,
+                        imm,
+                        len: len + next_len,
+                    }),
+                    _ => fuse_load(pc, first, second)
+                        .or_else(|| fuse_zero_extend(first, second))
+                        .or_else(|| fuse_branch(first, second)),
+                };
+
+                if let Some(op) = fused {
+                    ops.push(op);
+                    pc += op.len() as u64;
+                    i += 2;
+                    continue;
+                }
+            }
+        }
+
+        ops.push(Op::Inst(inst, len));
+        pc += len as u64;
+        i += 1;
+    }
+
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::register::{A0, A1};
+
+    const T0: Reg = Reg(5);
+
+    fn decode(insts: &[u32]) -> Vec<(Inst, u8)> {
+        insts.iter().map(|inst| Inst::decode(*inst)).collect()
+    }
+
+    #[test]
+    fn peephole() {
+        let insts = decode(&[
+            0x12345537, // lui a0, 0x12345
+            0x67850513, // addi a0, a0, 0x678
+            0x00000013, // nop
+            0x00b522b3, // slt t0, a0, a1
+            0x00029463, // bnez t0, 8
+            0x00000297, // auipc t0, 0
+            0x0102829b, // addiw t0, t0, 16
+        ]);
+
+        assert_eq!(
+            optimize(0x1000, &insts, |_| false),
+            [
+                Op::Li {
+                    rd: A0,
+                    imm: 0x12345678,
+                    len: 8
+                },
+                Op::Nop(4),
+                Op::CmpBranch {
+                    rd: T0,
+                    rs1: A0,
+                    rs2: Operand::Reg(A1),
+                    signed: true,
+                    branch_if: true,
+                    offset: 8,
+                    cmp_len: 4,
+                    len: 8
+                },
+                Op::Li {
+                    rd: T0,
+                    imm: 0x1024,
+                    len: 8
+                },
+            ]
+        );
+
+        let insts = decode(&[
+            0x00000517, // auipc a0, 0
+            0x01053583, // ld a1, 16(a0)
+            0x02051513, // slli a0, a0, 32
+            0x02055513, // srli a0, a0, 32
+        ]);
+
+        assert_eq!(
+            optimize(0x1000, &insts, |_| false),
+            [
+                Op::LoadGlobal {
+                    base: A0,
+                    upper: 0x1000,
+                    rd: A1,
+                    addr: 0x1010,
+                    auipc_len: 4,
+                    len: 8
+                },
+                Op::ZeroExtend {
+                    rd: A0,
+                    rs1: A0,
+                    mask: 0xffffffff,
+                    len: 8
+                },
+            ]
+        );
+
+        // loads into x0 still have to fault, but can't be allowed to write it
+        let insts = decode(&[
+            0x00000517, // auipc a0, 0
+            0x01053003, // ld zero, 16(a0)
+        ]);
+        let ops = optimize(0x1000, &insts, |_| false);
+        assert_eq!(ops, [Op::Inst(insts[0].0, 4), Op::Inst(insts[1].0, 4)]);
+
+        // the addi is jumped to, so it has to stay on its own
+        let insts = decode(&[0x12345537, 0x67850513]);
+        let ops = optimize(0x1000, &insts, |pc| pc == 0x1004);
+        assert_eq!(ops, [Op::Inst(insts[0].0, 4), Op::Inst(insts[1].0, 4)]);
+    }
+}
@@ -1,11 +1,23 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod assembler;
 mod auxvec;
+#[cfg(feature = "batch")]
+pub mod batch;
 mod cache;
 pub mod disassembler;
 pub mod error;
+pub mod expr;
 mod files;
-mod instruction;
+pub mod instruction;
+mod ir;
 pub mod memory;
 mod profiler;
-mod register;
+pub mod register;
+mod sync;
 pub mod system;
 pub mod time_travel;
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -0,0 +1,517 @@
+use alloc::{sync::Arc, vec, vec::Vec};
+use core::{
+    mem::{self, MaybeUninit},
+    ops::Range,
+};
+
+use crate::{error::RVError, system::STACK_START};
+
+use super::{page_align, MemoryBackend, MemoryUsage, PAGE_BITS, PAGE_MASK, PAGE_SIZE};
+
+type Page = [u8; PAGE_SIZE as usize];
+
+static ZERO_PAGE: Page = [0; PAGE_SIZE as usize];
+
+#[derive(Clone)]
+enum PageData {
+    Owned(Arc<Page>),
+    // the page at an offset into an executable image, which is only copied once it is written to
+    Image(Arc<[u8]>, usize),
+}
+
+impl PageData {
+    #[inline]
+    fn bytes(&self) -> &Page {
+        match self {
+            PageData::Owned(page) => page,
+            PageData::Image(image, offset) => image[*offset..*offset + PAGE_SIZE as usize]
+                .try_into()
+                .expect("slice is one page long"),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+struct Region {
+    // pages that were never written are None and read as zero
+    pages: Vec<Option<PageData>>,
+    // the number of accessible bytes. For the stack this is counted down from STACK_START.
+    len: u64,
+}
+
+/// A copy-on-write memory backend with the same address layout as [`PagedMemory`], but split into
+/// reference counted pages. Cloning only copies page pointers, and a page is copied the first time
+/// either clone writes to it, which makes frequent snapshots cheap.
+///
+/// Segments of executables loaded from a shared image are mapped without copying them, see
+/// [`Memory::load_shared_elf`](super::Memory::load_shared_elf).
+///
+/// [`PagedMemory`]: super::PagedMemory
+#[derive(Clone)]
+pub struct CowMemory {
+    regions: Vec<Region>,
+
+    // the number of times mmap has been called
+    mmap_count: u64,
+
+    // the bytes of owned pages this snapshot references, some of which may be shared with clones
+    usage: MemoryUsage,
+}
+
+impl Default for CowMemory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CowMemory {
+    pub fn new() -> CowMemory {
+        let mut memory = CowMemory {
+            regions: vec![Region::default(); 256],
+            mmap_count: 3,
+            usage: MemoryUsage::default(),
+        };
+
+        // add an initial page to the stack
+        memory.regions[255].len = PAGE_SIZE;
+
+        memory
+    }
+
+    // (region, page index, offset into the page) of an address
+    #[inline]
+    fn locate(addr: u64) -> (usize, usize, usize) {
+        let region = (addr >> 56) as usize;
+        let offset = (addr & PAGE_MASK) as usize;
+
+        // the stack's pages are numbered from the top
+        let page = if region == 255 {
+            (STACK_START >> PAGE_BITS) - (addr >> PAGE_BITS)
+        } else {
+            (addr & 0x00FFFFFFFFFFFFFF) >> PAGE_BITS
+        };
+
+        (region, page as usize, offset)
+    }
+
+    #[inline]
+    fn accessible(&self, addr: u64, len: u64) -> bool {
+        let region = &self.regions[(addr >> 56) as usize];
+
+        if addr >> 56 == 0xFF {
+            addr >= STACK_START - region.len
+        } else {
+            (addr & 0x00FFFFFFFFFFFFFF)
+                .checked_add(len)
+                .is_some_and(|end| end <= region.len)
+        }
+    }
+
+    // grows the stack to contain addr like the paged backend does, if addr is on the stack
+    #[inline]
+    fn grow_stack(&mut self, addr: u64) -> Result<(), RVError> {
+        if addr >> 56 == 0xFF {
+            let region = &mut self.regions[255];
+            while STACK_START - region.len > addr {
+                if STACK_START - region.len - addr > PAGE_SIZE {
+                    return Err(RVError::SegmentationFault);
+                }
+
+                region.len *= 2;
+            }
+        }
+
+        Ok(())
+    }
+
+    // the page at (region, page), allocating it if it was never written and copying it if shared
+    fn page_mut(&mut self, region: usize, page: usize) -> &mut Page {
+        let CowMemory { regions, usage, .. } = self;
+        let usage = usage.region_mut(region as u8);
+
+        let pages = &mut regions[region].pages;
+        if page >= pages.len() {
+            pages.resize(page + 1, None);
+        }
+
+        let slot = &mut pages[page];
+        match slot {
+            Some(PageData::Owned(_)) => {}
+            Some(image @ PageData::Image(..)) => {
+                *usage += PAGE_SIZE;
+                *slot = Some(PageData::Owned(Arc::new(*image.bytes())));
+            }
+            None => {
+                *usage += PAGE_SIZE;
+                *slot = Some(PageData::Owned(Arc::new(ZERO_PAGE)));
+            }
+        }
+
+        match slot {
+            Some(PageData::Owned(page)) => Arc::make_mut(page),
+            _ => unreachable!(),
+        }
+    }
+
+    // sets the length of a region, dropping pages past the new end
+    fn resize(&mut self, region: usize, len: u64) {
+        if region != 255 && len < self.regions[region].len {
+            self.clear(
+                ((region as u64) << 56) + len,
+                self.regions[region].len - len,
+            );
+
+            let pages = &mut self.regions[region].pages;
+            pages.truncate(((len + PAGE_MASK) >> PAGE_BITS) as usize);
+        }
+
+        self.regions[region].len = len;
+    }
+
+    // zeroes [addr, addr + len), releasing whole pages instead of writing to them
+    fn clear(&mut self, mut addr: u64, len: u64) {
+        let end = addr + len;
+
+        while addr < end {
+            let (region, page, offset) = Self::locate(addr);
+            let n = (PAGE_SIZE - offset as u64).min(end - addr);
+
+            if n == PAGE_SIZE {
+                if let Some(page) = self.regions[region].pages.get_mut(page) {
+                    if let Some(PageData::Owned(_)) = page.take() {
+                        *self.usage.region_mut(region as u8) -= PAGE_SIZE;
+                    }
+                }
+            } else if let Some(Some(_)) = self.regions[region].pages.get(page) {
+                self.page_mut(region, page)[offset..offset + n as usize].fill(0);
+            }
+
+            addr += n;
+        }
+    }
+}
+
+impl MemoryBackend for CowMemory {
+    #[inline]
+    fn load<T>(&self, addr: u64) -> Result<T, RVError> {
+        let size = mem::size_of::<T>();
+
+        if !self.accessible(addr, size as u64) {
+            return Err(RVError::SegmentationFault);
+        }
+
+        let (region, page, offset) = Self::locate(addr);
+
+        if offset + size <= PAGE_SIZE as usize {
+            let page = match self.regions[region].pages.get(page) {
+                Some(Some(page)) => page.bytes(),
+                _ => &ZERO_PAGE,
+            };
+
+            // SAFETY: the read is within the page
+            unsafe { Ok(page.as_ptr().add(offset).cast::<T>().read_unaligned()) }
+        } else {
+            // straddles two pages
+            let mut value = MaybeUninit::<T>::uninit();
+            let bytes = value.as_mut_ptr().cast::<u8>();
+
+            for i in 0..size {
+                // SAFETY: i is within the size of T
+                unsafe { bytes.add(i).write(self.load(addr + i as u64)?) };
+            }
+
+            // SAFETY: every byte was initialized above
+            unsafe { Ok(value.assume_init()) }
+        }
+    }
+
+    #[inline]
+    fn store<T>(&mut self, addr: u64, data: T) -> Result<(), RVError> {
+        let size = mem::size_of::<T>();
+
+        self.grow_stack(addr)?;
+        if !self.accessible(addr, size as u64) {
+            return Err(RVError::SegmentationFault);
+        }
+
+        let (region, page, offset) = Self::locate(addr);
+
+        if offset + size <= PAGE_SIZE as usize {
+            let page = self.page_mut(region, page);
+
+            // SAFETY: the write is within the page
+            unsafe {
+                page.as_mut_ptr()
+                    .add(offset)
+                    .cast::<T>()
+                    .write_unaligned(data)
+            };
+        } else {
+            // straddles two pages
+            let bytes = (&data as *const T).cast::<u8>();
+
+            for i in 0..size {
+                // SAFETY: i is within the size of T
+                self.store(addr + i as u64, unsafe { bytes.add(i).read() })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write(&mut self, mut addr: u64, mut data: &[u8]) -> Result<(), RVError> {
+        self.grow_stack(addr)?;
+        if !self.accessible(addr, data.len() as u64) {
+            return Err(RVError::SegmentationFault);
+        }
+
+        while !data.is_empty() {
+            let (region, page, offset) = Self::locate(addr);
+            let n = (PAGE_SIZE as usize - offset).min(data.len());
+
+            self.page_mut(region, page)[offset..offset + n].copy_from_slice(&data[..n]);
+
+            addr += n as u64;
+            data = &data[n..];
+        }
+
+        Ok(())
+    }
+
+    fn zero(&mut self, addr: u64, len: u64) -> Result<(), RVError> {
+        self.grow_stack(addr)?;
+        if !self.accessible(addr, len) {
+            return Err(RVError::SegmentationFault);
+        }
+
+        self.clear(addr, len);
+        Ok(())
+    }
+
+    fn fill(&mut self, mut addr: u64, len: u64, byte: u8) -> Result<(), RVError> {
+        if byte == 0 {
+            return self.zero(addr, len);
+        }
+
+        self.grow_stack(addr)?;
+        if !self.accessible(addr, len) {
+            return Err(RVError::SegmentationFault);
+        }
+
+        let end = addr + len;
+        while addr < end {
+            let (region, page, offset) = Self::locate(addr);
+            let n = (PAGE_SIZE - offset as u64).min(end - addr);
+
+            self.page_mut(region, page)[offset..offset + n as usize].fill(byte);
+
+            addr += n;
+        }
+
+        Ok(())
+    }
+
+    fn map(&mut self, addr: u64, size: u64) -> i64 {
+        log::info!("MMAP REGION: 0x{:x}-0x{:x}", addr, addr.wrapping_add(size));
+
+        // we can only have a maximum of 254 memory mapped regions
+        if self.mmap_count > 254 {
+            return -1;
+        }
+
+        // if the user does not ask for an address, we start a new region
+        if addr == 0 {
+            let Some(len) = page_align(size) else {
+                return -1;
+            };
+
+            let region = self.mmap_count as usize;
+            self.mmap_count += 1;
+
+            self.resize(region, len);
+
+            ((region as u64) << 56) as i64
+        } else {
+            let (region, _, _) = Self::locate(addr);
+            if region == 255 {
+                return -1;
+            }
+
+            let start = addr & 0x00FFFFFFFFFFFFFF;
+            let Some(end) = start.checked_add(size).and_then(page_align) else {
+                return -1;
+            };
+            if self.regions[region].len < end {
+                self.resize(region, end);
+            }
+
+            // mapping over an existing mapping replaces it
+            self.clear(addr, end - start);
+
+            addr as i64
+        }
+    }
+
+    // an mmap region shrinks when its end is unmapped
+    fn unmap(&mut self, addr: u64, len: u64) -> Result<(), RVError> {
+        let (region, _, _) = Self::locate(addr);
+        let start = addr & 0x00FFFFFFFFFFFFFF;
+
+        if (3..=254).contains(&region)
+            && start
+                .checked_add(len)
+                .and_then(page_align)
+                .is_some_and(|end| end >= self.regions[region].len)
+        {
+            self.resize(region, start);
+            return Ok(());
+        }
+
+        self.zero(addr, len)
+    }
+
+    // an mmap region can grow if the mapping is at its end
+    fn grow_mapping(&mut self, addr: u64, old_len: u64, new_len: u64) -> bool {
+        let (region, _, _) = Self::locate(addr);
+        let start = addr & 0x00FFFFFFFFFFFFFF;
+
+        let end = start.checked_add(old_len).and_then(page_align);
+        let Some(new_end) = start.checked_add(new_len).and_then(page_align) else {
+            return false;
+        };
+
+        if !(3..=254).contains(&region)
+            || end.is_none_or(|end| end < self.regions[region].len)
+            || new_end > 0x0100000000000000
+        {
+            return false;
+        }
+
+        self.resize(region, new_end);
+        true
+    }
+
+    fn reserve(&mut self, addr: u64, len: u64) -> Result<(), RVError> {
+        let (region, _, _) = Self::locate(addr);
+        if region == 255 {
+            return Err(RVError::SegmentationFault);
+        }
+
+        let end = (addr & 0x00FFFFFFFFFFFFFF)
+            .checked_add(len)
+            .and_then(page_align)
+            .ok_or(RVError::SegmentationFault)?;
+        if self.regions[region].len < end {
+            self.resize(region, end);
+        }
+
+        Ok(())
+    }
+
+    fn map_image(
+        &mut self,
+        addr: u64,
+        image: &Arc<[u8]>,
+        data: Range<usize>,
+        len: u64,
+    ) -> Result<bool, RVError> {
+        self.reserve(addr, len)?;
+
+        let end = addr + len;
+        let data_end = addr + data.len() as u64;
+
+        let mut page_addr = addr & !PAGE_MASK;
+        while page_addr < end {
+            let page_end = page_addr + PAGE_SIZE;
+
+            if page_addr >= addr && page_end <= data_end {
+                let offset = data.start + (page_addr - addr) as usize;
+                let page_data = PageData::Image(image.clone(), offset);
+
+                let (region, page, _) = Self::locate(page_addr);
+                let pages = &mut self.regions[region].pages;
+                if page >= pages.len() {
+                    pages.resize(page + 1, None);
+                }
+
+                if let Some(PageData::Owned(_)) = pages[page].replace(page_data) {
+                    *self.usage.region_mut(region as u8) -= PAGE_SIZE;
+                }
+            } else {
+                // pages only partially covered by file data are copied, and zero filled up to len
+                let start = page_addr.max(addr);
+                let stop = page_end.min(end);
+
+                if start < data_end {
+                    let offset = data.start + (start - addr) as usize;
+                    let n = (stop.min(data_end) - start) as usize;
+                    self.write(start, &image[offset..offset + n])?;
+                }
+
+                let zero_start = start.max(data_end);
+                if zero_start < stop {
+                    self.clear(zero_start, stop - zero_start);
+                }
+            }
+
+            page_addr = page_end;
+        }
+
+        Ok(true)
+    }
+
+    fn brk(&mut self, new_end: u64) -> u64 {
+        if new_end >> 56 == 1 {
+            self.resize(1, new_end & 0x00FFFFFFFFFFFFFF);
+        }
+
+        0x0100000000000000 + self.regions[1].len
+    }
+
+    fn usage(&self) -> MemoryUsage {
+        self.usage
+    }
+
+    fn dynamic_linker_base(&self, _image_end: u64) -> u64 {
+        0x0200000000000000 + self.regions[2].len
+    }
+
+    fn heap(&self) -> Range<u64> {
+        0x0100000000000000..0x0100000000000000 + self.regions[1].len
+    }
+
+    fn stack(&self) -> Range<u64> {
+        STACK_START - (self.regions[255].len - 1)..STACK_START
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lazy_image() -> Result<(), RVError> {
+        let image: Arc<[u8]> = (0..PAGE_SIZE + PAGE_SIZE / 2).map(|i| i as u8).collect();
+        let mut memory = CowMemory::new();
+
+        // one full page referenced from the image, then half a page of data and half of bss
+        assert!(memory.map_image(PAGE_SIZE, &image, 0..image.len(), 2 * PAGE_SIZE)?);
+        assert_eq!(memory.usage().program, PAGE_SIZE);
+
+        assert_eq!(memory.load::<u8>(PAGE_SIZE + 5)?, 5);
+        assert_eq!(
+            memory.load::<u32>(2 * PAGE_SIZE)?,
+            u32::from_le_bytes([0, 1, 2, 3])
+        );
+        assert_eq!(memory.load::<u64>(3 * PAGE_SIZE - 8)?, 0);
+
+        // writing copies the page, leaving the image alone
+        let snapshot = memory.clone();
+        memory.store(PAGE_SIZE + 5, 0xffu8)?;
+        assert_eq!(memory.load::<u8>(PAGE_SIZE + 5)?, 0xff);
+        assert_eq!(snapshot.load::<u8>(PAGE_SIZE + 5)?, 5);
+        assert_eq!(memory.usage().total(), 2 * PAGE_SIZE);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,205 @@
+// the pages written since a snapshot of memory was taken, so it can be restored by copying only
+// those, see `Memory::restore`. Memories cloned from the same snapshot share its id, and their
+// pages are dirty relative to it.
+
+use alloc::{vec, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use super::{paged::PagedMemory, Memory, MemoryBackend, PAGE_BITS, PAGE_MASK, PAGE_SIZE};
+use crate::system::STACK_START;
+
+// 0 is never handed out, it's the id of memory that hasn't had a snapshot taken
+static NEXT_SNAPSHOT: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Clone, Debug)]
+pub(super) struct DirtyPages {
+    snapshot: u64,
+    // one bit per page of each region, with the stack's counted down from STACK_START
+    bits: Vec<Vec<u64>>,
+    count: u64,
+    // the page last marked, plus one, which most writes hit again
+    last: u64,
+}
+
+impl Default for DirtyPages {
+    fn default() -> Self {
+        DirtyPages {
+            snapshot: 0,
+            bits: vec![vec![]; 256],
+            count: 0,
+            last: 0,
+        }
+    }
+}
+
+impl DirtyPages {
+    // the index of the page containing `addr` in its region
+    fn page_index(addr: u64) -> u64 {
+        match PagedMemory::heap_index(addr).0 {
+            255 => (STACK_START - addr) >> PAGE_BITS,
+            _ => PagedMemory::heap_addr(addr) >> PAGE_BITS,
+        }
+    }
+
+    #[inline]
+    pub fn mark(&mut self, addr: u64, len: u64) {
+        let last_byte = addr.saturating_add(len.max(1) - 1);
+        if addr >> PAGE_BITS == last_byte >> PAGE_BITS && self.last == (addr >> PAGE_BITS) + 1 {
+            return;
+        }
+        self.last = (addr >> PAGE_BITS) + 1;
+
+        let bits = &mut self.bits[PagedMemory::heap_index(addr).0 as usize];
+        let (first, last) = (Self::page_index(addr), Self::page_index(last_byte));
+
+        for page in first.min(last)..=first.max(last) {
+            let word = (page / 64) as usize;
+            if word >= bits.len() {
+                bits.resize(word + 1, 0);
+            }
+
+            if bits[word] & (1 << (page % 64)) == 0 {
+                bits[word] |= 1 << (page % 64);
+                self.count += 1;
+            }
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    // the id of the snapshot the pages are dirty relative to
+    pub fn snapshot(&self) -> u64 {
+        self.snapshot
+    }
+
+    // forgets the dirty pages, starting over from a new snapshot
+    pub fn clear(&mut self) {
+        *self = DirtyPages {
+            snapshot: NEXT_SNAPSHOT.fetch_add(1, Ordering::Relaxed),
+            ..DirtyPages::default()
+        };
+    }
+
+    // the address of each dirty page
+    pub fn pages(&self) -> impl Iterator<Item = u64> + '_ {
+        self.bits.iter().enumerate().flat_map(|(region, bits)| {
+            bits.iter().enumerate().flat_map(move |(word, &bits)| {
+                (0..64)
+                    .filter(move |bit| bits & (1 << bit) != 0)
+                    .map(move |bit| {
+                        let page = word as u64 * 64 + bit;
+                        match region {
+                            255 => STACK_START - (page << PAGE_BITS) - PAGE_MASK,
+                            _ => (region as u64) << 56 | page << PAGE_BITS,
+                        }
+                    })
+            })
+        })
+    }
+}
+
+impl Memory {
+    /// The number of pages written since [`Memory::clear_dirty_pages`], or since the memory was
+    /// created. Counted over a whole run it approximates the guest's working set.
+    pub fn dirty_page_count(&self) -> u64 {
+        self.dirty.count()
+    }
+
+    /// The address of each page written since [`Memory::clear_dirty_pages`]
+    pub fn dirty_pages(&self) -> impl Iterator<Item = u64> + '_ {
+        self.dirty.pages()
+    }
+
+    /// Forgets which pages were written. A clone taken right after is a snapshot that
+    /// [`Memory::restore`] goes back to by copying only the pages written since.
+    pub fn clear_dirty_pages(&mut self) {
+        self.dirty.clear();
+    }
+
+    /// Makes memory a copy of `snapshot`. If `snapshot` was cloned from it right after
+    /// [`Memory::clear_dirty_pages`] and nothing was mapped, unmapped or grown since, only the
+    /// pages written since are copied back, otherwise all of it is. Memcheck needs a full copy.
+    pub fn restore(&mut self, snapshot: &Memory) {
+        if !self.restores_pages_from(snapshot) {
+            *self = snapshot.clone();
+            return;
+        }
+
+        let pages: Vec<u64> = self.dirty.pages().collect();
+        let mut page = vec![0; PAGE_SIZE as usize];
+        for addr in pages {
+            if snapshot.backend.read(addr, &mut page).is_ok() {
+                let _ = self.backend.write(addr, &page);
+                continue;
+            }
+
+            // the end of a region that doesn't fill its last page
+            for addr in (0..PAGE_SIZE).map(|offset| addr + offset) {
+                if let Ok(byte) = snapshot.backend.load::<u8>(addr) {
+                    let _ = self.backend.store(addr, byte);
+                }
+            }
+        }
+
+        self.code_generation = self.code_generation.max(snapshot.code_generation) + 1;
+        self.memory_limit_hits = snapshot.memory_limit_hits;
+        #[cfg(feature = "mmu")]
+        {
+            self.mmu = snapshot.mmu.clone();
+        }
+        self.dirty = snapshot.dirty.clone();
+    }
+
+    fn restores_pages_from(&self, snapshot: &Memory) -> bool {
+        self.dirty.snapshot() != 0
+            && self.dirty.snapshot() == snapshot.dirty.snapshot()
+            && snapshot.dirty.count() == 0
+            && self.shadow.is_none()
+            && snapshot.shadow.is_none()
+            && self.layout() == snapshot.layout()
+            && self.usage_by_region() == snapshot.usage_by_region()
+            && self.mappings() == snapshot.mappings()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::RVError;
+
+    #[test]
+    fn dirty_pages() -> Result<(), RVError> {
+        let mut memory = Memory::from_raw(&vec![0; 3 * PAGE_SIZE as usize]);
+        assert_eq!(memory.dirty_page_count(), 3);
+
+        memory.clear_dirty_pages();
+        let snapshot = memory.clone();
+        let stack = STACK_START - 0x100;
+        memory.store::<u64>(2 * PAGE_SIZE - 4, u64::MAX)?;
+        memory.store::<u8>(PAGE_SIZE, 1)?;
+        memory.store::<u8>(stack, 2)?;
+
+        assert_eq!(memory.dirty_page_count(), 3);
+        let mut pages: Vec<u64> = memory.dirty_pages().collect();
+        pages.sort_unstable();
+        assert_eq!(pages, [PAGE_SIZE, 2 * PAGE_SIZE, STACK_START - PAGE_MASK]);
+
+        memory.restore(&snapshot);
+        assert_eq!(memory.dirty_page_count(), 0);
+        assert_eq!(memory.load::<u64>(2 * PAGE_SIZE - 8)?, 0);
+        assert_eq!(memory.load::<u64>(2 * PAGE_SIZE)?, 0);
+        assert_eq!(memory.load::<u8>(stack)?, 0);
+
+        // after a mapping the whole snapshot is copied back
+        let addr = memory.mmap(0, PAGE_SIZE) as u64;
+        memory.store::<u8>(addr, 3)?;
+        memory.store::<u8>(0x10, 4)?;
+        memory.restore(&snapshot);
+        assert_eq!(memory.load::<u8>(0x10)?, 0);
+        assert!(memory.load::<u8>(addr).is_err());
+
+        Ok(())
+    }
+}
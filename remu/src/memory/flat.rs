@@ -0,0 +1,225 @@
+use alloc::{vec, vec::Vec};
+use core::{mem, ops::Range};
+
+use crate::{error::RVError, system::STACK_START};
+
+use super::{page_align, MemoryBackend, MemoryUsage, PAGE_SIZE};
+
+/// Size of the fixed stack at the top of the address space
+pub const FLAT_STACK_SIZE: u64 = 8 * 1024 * 1024;
+
+/// A memory backend made of one contiguous allocation, which makes every access a single
+/// subtraction and bounds check. Only suitable for programs that fit in a modest address range.
+///
+/// Layout of the guest address space:
+///
+/// ```text
+/// 0                                                             size
+/// | elf segments | dynamic linker | brk heap ->   <- mmap regions | unmapped ... | <- stack |
+///                                                                 STACK_START - FLAT_STACK_SIZE
+/// ```
+///
+/// The stack is stored at the start of the allocation, directly followed by the low region, so a
+/// wrapping subtraction of the stack's base address maps both into the buffer.
+#[derive(Clone)]
+pub struct FlatMemory {
+    data: Vec<u8>,
+    // address of the lowest byte of the stack
+    stack_base: u64,
+
+    brk_start: u64,
+    brk_end: u64,
+    // mmap regions are handed out downwards from the end of the low region
+    mmap_bottom: u64,
+}
+
+impl FlatMemory {
+    /// `size` is the number of bytes of the low region, excluding the stack
+    pub fn new(size: u64) -> FlatMemory {
+        let size = page_align(size).expect("flat memory fits in the address space");
+
+        FlatMemory {
+            data: vec![0; (size + FLAT_STACK_SIZE) as usize],
+            stack_base: STACK_START - FLAT_STACK_SIZE + 1,
+            brk_start: 0,
+            brk_end: 0,
+            mmap_bottom: size,
+        }
+    }
+
+    /// The bytes allocated for everything except the stack
+    pub fn size(&self) -> u64 {
+        self.data.len() as u64 - FLAT_STACK_SIZE
+    }
+
+    // the part of the buffer backing [addr, addr + len)
+    fn span(&self, addr: u64, len: u64) -> Result<&[u8], RVError> {
+        let start = self.offset(addr);
+
+        match start.checked_add(len) {
+            Some(end) if end <= self.data.len() as u64 => {
+                Ok(&self.data[start as usize..end as usize])
+            }
+            _ => Err(RVError::SegmentationFault),
+        }
+    }
+
+    fn span_mut(&mut self, addr: u64, len: u64) -> Result<&mut [u8], RVError> {
+        let start = self.offset(addr);
+
+        match start.checked_add(len) {
+            Some(end) if end <= self.data.len() as u64 => {
+                Ok(&mut self.data[start as usize..end as usize])
+            }
+            _ => Err(RVError::SegmentationFault),
+        }
+    }
+
+    // kept as a u64 until bounds checked, so it can't be truncated on 32 bit hosts
+    #[inline]
+    fn offset(&self, addr: u64) -> u64 {
+        addr.wrapping_sub(self.stack_base)
+    }
+}
+
+impl MemoryBackend for FlatMemory {
+    // the whole buffer is allocated up front, but the host only backs the pages that are touched,
+    // so only the parts of each region in use are counted
+    fn usage(&self) -> MemoryUsage {
+        let size = self.data.len() as u64 - FLAT_STACK_SIZE;
+
+        MemoryUsage {
+            program: self.brk_start,
+            heap: self.brk_end - self.brk_start,
+            dynamic_linker: 0,
+            mmap: size - self.mmap_bottom,
+            stack: FLAT_STACK_SIZE,
+        }
+    }
+
+    fn brk(&mut self, new_end: u64) -> u64 {
+        if (self.brk_start..=self.mmap_bottom).contains(&new_end) {
+            // memory past the break could be stale from an earlier, larger break
+            if new_end > self.brk_end {
+                self.zero(self.brk_end, new_end - self.brk_end)
+                    .expect("the break is within the low region");
+            }
+
+            self.brk_end = new_end;
+        }
+
+        self.brk_end
+    }
+
+    fn reserve(&mut self, addr: u64, len: u64) -> Result<(), RVError> {
+        let end = addr.checked_add(len).ok_or(RVError::SegmentationFault)?;
+        if end > self.mmap_bottom {
+            log::error!("segment {addr:x}-{end:x} does not fit in flat memory");
+            return Err(RVError::SegmentationFault);
+        }
+
+        // the heap starts after the last segment
+        self.brk_start = self.brk_start.max(page_align(end).expect("checked above"));
+        self.brk_end = self.brk_start;
+
+        Ok(())
+    }
+
+    fn map(&mut self, addr: u64, size: u64) -> i64 {
+        log::info!("MMAP REGION: 0x{:x}-0x{:x}", addr, addr.wrapping_add(size));
+
+        let Some(size) = page_align(size) else {
+            return -1;
+        };
+
+        if addr == 0 {
+            if self.mmap_bottom - self.brk_end < size {
+                return -1;
+            }
+
+            self.mmap_bottom -= size;
+            self.mmap_bottom as i64
+        } else {
+            if addr.saturating_add(size) > self.data.len() as u64 - FLAT_STACK_SIZE {
+                return -1;
+            }
+
+            // overwrites existing mappings, just like the paged backend
+            self.zero(addr, size).expect("checked above");
+
+            addr as i64
+        }
+    }
+
+    #[inline]
+    fn store<T>(&mut self, addr: u64, data: T) -> Result<(), RVError> {
+        let offset = self.offset(addr);
+
+        if offset <= (self.data.len() - mem::size_of::<T>()) as u64 {
+            unsafe {
+                // SAFETY: Write is guaranteed to be within buffer bounds
+                self.data
+                    .as_mut_ptr()
+                    .add(offset as usize)
+                    .cast::<T>()
+                    .write_unaligned(data);
+            }
+
+            Ok(())
+        } else {
+            Err(RVError::SegmentationFault)
+        }
+    }
+
+    #[inline]
+    fn load<T>(&self, addr: u64) -> Result<T, RVError> {
+        let offset = self.offset(addr);
+
+        if offset <= (self.data.len() - mem::size_of::<T>()) as u64 {
+            unsafe {
+                // SAFETY: Read is guaranteed to be within buffer bounds
+                Ok(self
+                    .data
+                    .as_ptr()
+                    .add(offset as usize)
+                    .cast::<T>()
+                    .read_unaligned())
+            }
+        } else {
+            Err(RVError::SegmentationFault)
+        }
+    }
+
+    fn read(&self, addr: u64, buf: &mut [u8]) -> Result<(), RVError> {
+        buf.copy_from_slice(self.span(addr, buf.len() as u64)?);
+        Ok(())
+    }
+
+    fn write(&mut self, addr: u64, data: &[u8]) -> Result<(), RVError> {
+        self.span_mut(addr, data.len() as u64)?
+            .copy_from_slice(data);
+        Ok(())
+    }
+
+    fn zero(&mut self, addr: u64, len: u64) -> Result<(), RVError> {
+        self.fill(addr, len, 0)
+    }
+
+    fn fill(&mut self, addr: u64, len: u64, byte: u8) -> Result<(), RVError> {
+        self.span_mut(addr, len)?.fill(byte);
+        Ok(())
+    }
+
+    // right after the program, since everything has to fit in one region
+    fn dynamic_linker_base(&self, image_end: u64) -> u64 {
+        page_align(image_end).expect("the image fits in flat memory") + PAGE_SIZE
+    }
+
+    fn heap(&self) -> Range<u64> {
+        self.brk_start..self.brk_end
+    }
+
+    fn stack(&self) -> Range<u64> {
+        self.stack_base..STACK_START
+    }
+}
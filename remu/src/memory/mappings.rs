@@ -0,0 +1,174 @@
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use core::fmt::{self, Display};
+
+// linux PROT_* flags
+const PROT_READ: u64 = 1;
+const PROT_WRITE: u64 = 2;
+const PROT_EXEC: u64 = 4;
+
+/// What a [`Mapping`] holds
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MappingKind {
+    /// A segment of the executable
+    Program,
+    /// A segment of the dynamic linker
+    DynamicLinker,
+    /// Memory allocated through brk
+    Heap,
+    Mmap,
+    Stack,
+    /// A device mapped with [`Memory::map_device`](super::Memory::map_device)
+    Device,
+}
+
+impl MappingKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            MappingKind::Program => "program",
+            MappingKind::DynamicLinker => "ld.so",
+            MappingKind::Heap => "[heap]",
+            MappingKind::Mmap => "mmap",
+            MappingKind::Stack => "[stack]",
+            MappingKind::Device => "[device]",
+        }
+    }
+}
+
+/// A range of guest memory, see [`Memory::mappings`](super::Memory::mappings)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Mapping {
+    pub start: u64,
+    /// The first address past the mapping
+    pub end: u64,
+    /// The linux PROT_* flags of the mapping
+    pub prot: u64,
+    pub kind: MappingKind,
+}
+
+impl Mapping {
+    pub fn contains(&self, addr: u64) -> bool {
+        (self.start..self.end).contains(&addr)
+    }
+
+    /// The permissions the way /proc/self/maps shows them, like `r-xp`
+    pub fn perms(&self) -> [char; 4] {
+        let flag = |bit, c| if self.prot & bit != 0 { c } else { '-' };
+        [
+            flag(PROT_READ, 'r'),
+            flag(PROT_WRITE, 'w'),
+            flag(PROT_EXEC, 'x'),
+            'p',
+        ]
+    }
+}
+
+impl Display for Mapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let perms: String = self.perms().iter().collect();
+        write!(
+            f,
+            "{:016x}-{:016x} {perms} {}",
+            self.start,
+            self.end,
+            self.kind.name()
+        )
+    }
+}
+
+// the prot of an elf segment with the PF_* flags `p_flags`
+pub(super) fn segment_prot(p_flags: u32) -> u64 {
+    let mut prot = 0;
+    if p_flags & 4 != 0 {
+        prot |= PROT_READ;
+    }
+    if p_flags & 2 != 0 {
+        prot |= PROT_WRITE;
+    }
+    if p_flags & 1 != 0 {
+        prot |= PROT_EXEC;
+    }
+    prot
+}
+
+pub(super) const PROT_READ_WRITE: u64 = PROT_READ | PROT_WRITE;
+
+// the mappings made by loading and mmap, which don't overlap. The heap and stack are tracked by
+// the backends.
+#[derive(Clone, Debug, Default)]
+pub(super) struct Mappings {
+    // start -> mapping
+    mappings: BTreeMap<u64, Mapping>,
+}
+
+impl Mappings {
+    // replaces whatever was mapped in [start, end)
+    pub fn insert(&mut self, start: u64, end: u64, prot: u64, kind: MappingKind) {
+        self.remove(start, end);
+        self.mappings.insert(
+            start,
+            Mapping {
+                start,
+                end,
+                prot,
+                kind,
+            },
+        );
+    }
+
+    // unmaps [start, end), splitting the mappings it partially covers
+    pub fn remove(&mut self, start: u64, end: u64) {
+        for mapping in self.split(start, end) {
+            self.mappings.remove(&mapping.start);
+        }
+    }
+
+    // changes the prot of everything mapped in [start, end)
+    pub fn protect(&mut self, start: u64, end: u64, prot: u64) {
+        for mapping in self.split(start, end) {
+            if let Some(mapping) = self.mappings.get_mut(&mapping.start) {
+                mapping.prot = prot;
+            }
+        }
+    }
+
+    // extends the mapping ending at `old_end` to `new_end`
+    pub fn grow(&mut self, old_end: u64, new_end: u64) {
+        if let Some((_, mapping)) = self.mappings.range_mut(..old_end).next_back() {
+            if mapping.end == old_end {
+                mapping.end = new_end;
+            }
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Mapping> {
+        self.mappings.values()
+    }
+
+    // splits the mappings overlapping [start, end) at its ends, and returns the ones inside it
+    fn split(&mut self, start: u64, end: u64) -> Vec<Mapping> {
+        for at in [start, end] {
+            let Some((_, &mapping)) = self.mappings.range(..at).next_back() else {
+                continue;
+            };
+
+            if mapping.end > at {
+                self.mappings
+                    .get_mut(&mapping.start)
+                    .expect("found above")
+                    .end = at;
+                self.mappings.insert(
+                    at,
+                    Mapping {
+                        start: at,
+                        ..mapping
+                    },
+                );
+            }
+        }
+
+        self.mappings
+            .range(start..end)
+            .map(|(_, &mapping)| mapping)
+            .collect()
+    }
+}
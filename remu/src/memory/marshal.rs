@@ -0,0 +1,169 @@
+// reading and writing whole strings, structs and arrays of guest memory at once, instead of a
+// load per byte. Reads are split at page boundaries, so each page is translated and checked once.
+
+use alloc::vec::Vec;
+use core::{mem, mem::MaybeUninit, slice};
+
+use super::{Memory, MemoryBackend, PAGE_MASK, PAGE_SIZE};
+use crate::{error::RVError, system::AccessKind};
+
+/// Types that can be copied to and from guest memory as they are, like the `#[repr(C)]` structs
+/// syscalls take, see [`Memory::read_struct`]
+///
+/// # Safety
+///
+/// Every bit pattern has to be a valid value of the type, and it can't have any padding.
+pub unsafe trait FromBytes: Copy {}
+
+macro_rules! from_bytes {
+    ($($ty:ty),*) => {
+        $(unsafe impl FromBytes for $ty {})*
+    };
+}
+
+from_bytes!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+unsafe impl<T: FromBytes, const N: usize> FromBytes for [T; N] {}
+
+impl Memory {
+    /// Reads the nul terminated string at `addr`, without the nul. Stops after `max_len` bytes
+    /// if it's longer.
+    pub fn read_cstr(&self, mut addr: u64, max_len: u64) -> Result<Vec<u8>, RVError> {
+        let mut s = Vec::new();
+        let mut chunk = [0; PAGE_SIZE as usize];
+
+        while (s.len() as u64) < max_len {
+            let len = (PAGE_SIZE - (addr & PAGE_MASK)).min(max_len - s.len() as u64);
+            let chunk = &mut chunk[..len as usize];
+
+            if self.read_into(addr, chunk).is_err() {
+                // the string can end right before the end of its mapping, so only the bytes
+                // before its nul have to be there
+                for i in 0..len {
+                    match self.load(addr + i)? {
+                        b'\0' => return Ok(s),
+                        c => s.push(c),
+                    }
+                }
+            } else if let Some(end) = chunk.iter().position(|&c| c == b'\0') {
+                s.extend_from_slice(&chunk[..end]);
+                return Ok(s);
+            } else {
+                s.extend_from_slice(chunk);
+            }
+
+            addr += len;
+        }
+
+        Ok(s)
+    }
+
+    /// Reads the `T` at `addr`, which doesn't have to be aligned
+    pub fn read_struct<T: FromBytes>(&self, addr: u64) -> Result<T, RVError> {
+        let mut value = MaybeUninit::<T>::zeroed();
+        // SAFETY: zeroed, so every byte is initialized
+        let bytes = unsafe {
+            slice::from_raw_parts_mut(value.as_mut_ptr().cast::<u8>(), mem::size_of::<T>())
+        };
+        self.read_into(addr, bytes)?;
+
+        // SAFETY: any bytes are a valid `T`
+        Ok(unsafe { value.assume_init() })
+    }
+
+    /// Writes `value` to `addr`, which doesn't have to be aligned
+    pub fn write_struct<T: FromBytes>(&mut self, addr: u64, value: &T) -> Result<(), RVError> {
+        // SAFETY: `T` has no padding, so every byte of it is initialized
+        let bytes =
+            unsafe { slice::from_raw_parts((value as *const T).cast::<u8>(), mem::size_of::<T>()) };
+
+        self.write_n(bytes, addr, bytes.len() as u64)
+    }
+
+    /// Reads `count` little endian u64s starting at `addr`, like an array of pointers or of
+    /// iovecs
+    pub fn read_u64_array(&self, mut addr: u64, count: u64) -> Result<Vec<u64>, RVError> {
+        let mut remaining = count.checked_mul(8).ok_or(RVError::SegmentationFault)?;
+        let mut values = Vec::new();
+        let mut chunk = [0; PAGE_SIZE as usize];
+
+        // grown a page at a time, so a huge count fails once it reaches unmapped memory instead
+        // of allocating it all up front
+        while remaining > 0 {
+            let len = remaining.min(PAGE_SIZE);
+            let chunk = &mut chunk[..len as usize];
+            self.read_into(addr, chunk)?;
+
+            values.extend(
+                chunk
+                    .chunks_exact(8)
+                    .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap())),
+            );
+            addr += len;
+            remaining -= len;
+        }
+
+        Ok(values)
+    }
+
+    // fills `buf` with the bytes at `addr`, translating each page it spans
+    pub(super) fn read_into(&self, mut addr: u64, mut buf: &mut [u8]) -> Result<(), RVError> {
+        if addr.checked_add(buf.len() as u64).is_none() {
+            return Err(RVError::SegmentationFault);
+        }
+
+        while !buf.is_empty() {
+            let n = self.contiguous_len(addr, buf.len() as u64);
+            let (chunk, rest) = buf.split_at_mut(n as usize);
+
+            let physical = self.translate(addr, n, AccessKind::Read)?;
+            self.backend.read(physical, chunk)?;
+
+            addr += n;
+            buf = rest;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    struct Timespec {
+        sec: u64,
+        nsec: u64,
+    }
+
+    unsafe impl FromBytes for Timespec {}
+
+    #[test]
+    fn marshalling() -> Result<(), RVError> {
+        let mut data = alloc::vec![0; 0x1010];
+        data[0x100..0x106].copy_from_slice(b"hello\0");
+        // crosses a page boundary
+        data[0xffe..0x100f].copy_from_slice(b"0123456789abcdef\0");
+        let mut memory = Memory::from_raw(&data);
+
+        assert_eq!(memory.read_cstr(0x100, 64)?, b"hello");
+        assert_eq!(memory.read_cstr(0x100, 3)?, b"hel");
+        assert_eq!(memory.read_cstr(0xffe, 64)?, b"0123456789abcdef");
+        assert_eq!(memory.read_string_n(0xffe, 4)?, "0123");
+        assert!(memory.read_cstr(0x100_0000, 64).is_err());
+
+        let time = Timespec { sec: 3, nsec: 7 };
+        memory.write_struct(0x203, &time)?;
+        assert_eq!(memory.read_struct::<Timespec>(0x203)?, time);
+        assert_eq!(memory.read_u64_array(0x203, 2)?, [3, 7]);
+        assert_eq!(memory.read_struct::<[u32; 2]>(0x203)?, [3, 0]);
+
+        assert!(memory.read_u64_array(0x100_0000, 4).is_err());
+        assert!(memory.read_u64_array(0x1000, u64::MAX).is_err());
+        assert!(memory.read_struct::<u64>(u64::MAX - 2).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+// devices mapped into guest memory, whose loads and stores run callbacks instead of reading or
+// writing memory, like the UART bare-metal programs print through
+
+use alloc::{collections::BTreeMap, collections::VecDeque, sync::Arc, vec::Vec};
+use core::mem;
+
+use crate::sync::Lock;
+
+/// A device mapped at a range of guest addresses, see [`Memory::map_device`](super::Memory::map_device).
+/// Clones of the memory share their devices, which can be on other threads, so handlers keep
+/// their state behind locks or atomics.
+pub trait MemoryHandler: Send + Sync {
+    /// Reads `size` bytes, which is 1, 2, 4 or 8, at `offset` into the device's range. Reading
+    /// may change the device's state, like taking a byte from a receive buffer.
+    fn load(&self, offset: u64, size: u64) -> u64;
+
+    /// Writes the low `size` bytes of `value` at `offset` into the device's range
+    fn store(&self, offset: u64, size: u64, value: u64);
+}
+
+// start -> (end, handler)
+#[derive(Clone, Default)]
+pub(super) struct Devices(BTreeMap<u64, (u64, Arc<dyn MemoryHandler>)>);
+
+impl Devices {
+    pub fn insert(&mut self, start: u64, end: u64, handler: Arc<dyn MemoryHandler>) {
+        self.0.insert(start, (end, handler));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // the device `addr` is in and the offset into it
+    fn get(&self, addr: u64) -> Option<(&dyn MemoryHandler, u64)> {
+        let (&start, (end, handler)) = self.0.range(..=addr).next_back()?;
+        (addr < *end).then(|| (handler.as_ref(), addr - start))
+    }
+
+    // loads a `T` from the device at `addr`, if there is one
+    pub fn load<T>(&self, addr: u64) -> Option<T> {
+        let size = mem::size_of::<T>();
+        if size > 8 {
+            return None;
+        }
+
+        let (handler, offset) = self.get(addr)?;
+        let bytes = handler.load(offset, size as u64).to_le_bytes();
+
+        // SAFETY: T is at most 8 bytes, and loads are only made of plain integers and floats
+        Some(unsafe { bytes.as_ptr().cast::<T>().read_unaligned() })
+    }
+
+    // stores `data` to the device at `addr`, returning it back if there isn't one
+    pub fn store<T>(&self, addr: u64, data: T) -> Result<(), T> {
+        let size = mem::size_of::<T>();
+        let Some((handler, offset)) = self.get(addr).filter(|_| size <= 8) else {
+            return Err(data);
+        };
+
+        let mut bytes = [0u8; 8];
+        // SAFETY: T is at most 8 bytes, and stores are only made of plain integers and floats
+        unsafe { bytes.as_mut_ptr().cast::<T>().write_unaligned(data) };
+
+        handler.store(offset, size as u64, u64::from_le_bytes(bytes));
+        Ok(())
+    }
+}
+
+// the registers of a 16550 UART this emulates
+const RBR_THR: u64 = 0;
+const LSR: u64 = 5;
+
+// line status bits
+const LSR_DATA_READY: u64 = 0x01;
+const LSR_THR_EMPTY: u64 = 0x20;
+const LSR_TRANSMITTER_EMPTY: u64 = 0x40;
+
+/// The registers of a 16550 UART that bare-metal programs use to print, like the one of QEMU's
+/// `virt` board at 0x10000000. Sending never waits, and everything else is ignored.
+#[derive(Debug, Default)]
+pub struct Uart {
+    output: Lock<Vec<u8>>,
+    input: Lock<VecDeque<u8>>,
+}
+
+impl Uart {
+    /// Everything the guest has sent so far
+    pub fn output(&self) -> Vec<u8> {
+        self.output.lock().clone()
+    }
+
+    /// Makes `data` available to the guest to receive
+    pub fn push_input(&self, data: &[u8]) {
+        self.input.lock().extend(data);
+    }
+}
+
+impl MemoryHandler for Uart {
+    fn load(&self, offset: u64, _size: u64) -> u64 {
+        match offset {
+            RBR_THR => self.input.lock().pop_front().unwrap_or(0) as u64,
+            LSR => {
+                let ready = !self.input.lock().is_empty();
+                LSR_THR_EMPTY | LSR_TRANSMITTER_EMPTY | if ready { LSR_DATA_READY } else { 0 }
+            }
+            _ => 0,
+        }
+    }
+
+    fn store(&self, offset: u64, _size: u64, value: u64) {
+        if offset == RBR_THR {
+            self.output.lock().push(value as u8);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::{MappingKind, Memory};
+
+    #[test]
+    fn uart() {
+        let mut memory = Memory::from_raw(&[0; 16]);
+        let uart = Arc::new(Uart::default());
+        memory.map_device(0x1000_0000, 8, uart.clone());
+
+        for &byte in b"hi" {
+            assert_eq!(
+                memory.load::<u8>(0x1000_0005).unwrap() as u64 & LSR_THR_EMPTY,
+                LSR_THR_EMPTY
+            );
+            memory.store(0x1000_0000, byte).unwrap();
+        }
+        assert_eq!(uart.output(), b"hi");
+
+        uart.push_input(b"x");
+        assert_eq!(
+            memory.load::<u8>(0x1000_0005).unwrap() as u64 & LSR_DATA_READY,
+            LSR_DATA_READY
+        );
+        assert_eq!(memory.load::<u8>(0x1000_0000).unwrap(), b'x');
+        assert_eq!(
+            memory.load::<u8>(0x1000_0005).unwrap() as u64 & LSR_DATA_READY,
+            0
+        );
+
+        // memory around the device is untouched
+        memory.store::<u32>(4, 0x1234).unwrap();
+        assert_eq!(memory.load::<u32>(4).unwrap(), 0x1234);
+        assert_eq!(
+            memory.mapping_at(0x1000_0004).map(|mapping| mapping.kind),
+            Some(MappingKind::Device)
+        );
+    }
+}
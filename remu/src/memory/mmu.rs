@@ -0,0 +1,300 @@
+// sv39 address translation, for kernels that turn on paging through satp. Translations are
+// cached in a software tlb in front of the page table walk.
+// https://five-embeddev.com/riscv-priv-isa-manual/Priv-v1.12/supervisor.html#sv39-page-based-39-bit-virtual-memory-system
+//
+// Accessed and dirty bits are treated as always set and never written back, which is enough for
+// kernels that don't look at them, like xv6.
+
+use core::cell::{Cell, RefCell};
+
+use super::{Backend, MemoryBackend};
+use crate::{error::RVError, system::AccessKind};
+
+const PAGE_BITS: u64 = 12;
+pub(super) const PAGE_MASK: u64 = (1 << PAGE_BITS) - 1;
+const LEVELS: u64 = 3;
+const VPN_BITS: u64 = 9;
+
+const SATP_PPN_MASK: u64 = (1 << 44) - 1;
+
+// page table entry bits
+const PTE_V: u64 = 1 << 0;
+const PTE_R: u64 = 1 << 1;
+const PTE_W: u64 = 1 << 2;
+const PTE_X: u64 = 1 << 3;
+const PTE_U: u64 = 1 << 4;
+const PTE_PPN_SHIFT: u64 = 10;
+const PTE_PPN_MASK: u64 = (1 << 44) - 1;
+
+// entries in the direct mapped tlb
+const TLB_ENTRIES: usize = 64;
+
+/// What addresses are translated with, see [`Memory::set_translation`](super::Memory::set_translation)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Translation {
+    /// The satp csr, whose mode is sv39
+    pub satp: u64,
+    /// Whether the hart is in user mode, which can only access user pages
+    pub user: bool,
+    /// mstatus.SUM, which lets supervisor mode access user pages
+    pub sum: bool,
+    /// mstatus.MXR, which makes executable pages readable
+    pub mxr: bool,
+}
+
+/// How often translations were found in the tlb, see [`Memory::tlb_stats`](super::Memory::tlb_stats)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TlbStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+// a 4KiB page of a translation, which superpages are split into
+#[derive(Clone, Copy, Debug)]
+struct TlbEntry {
+    vpn: u64,
+    ppn: u64,
+    // the pte's permission bits
+    flags: u64,
+}
+
+const EMPTY_ENTRY: TlbEntry = TlbEntry {
+    vpn: u64::MAX,
+    ppn: 0,
+    flags: 0,
+};
+
+#[derive(Clone, Debug)]
+pub(super) struct Mmu {
+    // `None` while addresses are physical
+    translation: Option<Translation>,
+    tlb: RefCell<[TlbEntry; TLB_ENTRIES]>,
+    stats: Cell<TlbStats>,
+}
+
+impl Mmu {
+    pub fn new() -> Mmu {
+        Mmu {
+            translation: None,
+            tlb: RefCell::new([EMPTY_ENTRY; TLB_ENTRIES]),
+            stats: Cell::new(TlbStats::default()),
+        }
+    }
+
+    pub fn is_translating(&self) -> bool {
+        self.translation.is_some()
+    }
+
+    // flushes the tlb if the page table changed
+    pub fn set_translation(&mut self, translation: Option<Translation>) {
+        let satp = |translation: Option<Translation>| translation.map(|t| t.satp);
+        if satp(translation) != satp(self.translation) {
+            self.flush();
+        }
+        self.translation = translation;
+    }
+
+    pub fn flush(&self) {
+        *self.tlb.borrow_mut() = [EMPTY_ENTRY; TLB_ENTRIES];
+    }
+
+    pub fn stats(&self) -> TlbStats {
+        self.stats.get()
+    }
+
+    // the physical address of the `size` bytes at `addr`. Accesses that cross into another page
+    // fault, which the spec allows for misaligned accesses.
+    #[inline]
+    pub fn translate(
+        &self,
+        backend: &Backend,
+        addr: u64,
+        size: u64,
+        kind: AccessKind,
+    ) -> Result<u64, RVError> {
+        let Some(translation) = self.translation else {
+            return Ok(addr);
+        };
+
+        if (addr & PAGE_MASK) + size > 1 << PAGE_BITS {
+            return Err(RVError::SegmentationFault);
+        }
+
+        let vpn = addr >> PAGE_BITS;
+        let slot = vpn as usize % TLB_ENTRIES;
+        let mut stats = self.stats.get();
+
+        let cached = self.tlb.borrow()[slot];
+        let entry = if cached.vpn == vpn {
+            stats.hits += 1;
+            cached
+        } else {
+            stats.misses += 1;
+            let entry = walk(backend, translation.satp, addr, kind)?;
+            self.tlb.borrow_mut()[slot] = entry;
+            entry
+        };
+        self.stats.set(stats);
+
+        if !allowed(translation, entry.flags, kind) {
+            return Err(page_fault(addr, kind));
+        }
+
+        Ok(entry.ppn << PAGE_BITS | addr & PAGE_MASK)
+    }
+}
+
+fn allowed(translation: Translation, flags: u64, kind: AccessKind) -> bool {
+    let Translation { user, sum, mxr, .. } = translation;
+
+    let privilege_ok = match (user, flags & PTE_U != 0) {
+        (true, user_page) => user_page,
+        // supervisor mode can't execute user pages even with sum
+        (false, true) => sum && kind != AccessKind::Execute,
+        (false, false) => true,
+    };
+
+    let permission_ok = match kind {
+        AccessKind::Read => flags & PTE_R != 0 || mxr && flags & PTE_X != 0,
+        AccessKind::Write => flags & PTE_W != 0,
+        AccessKind::Execute => flags & PTE_X != 0,
+    };
+
+    privilege_ok && permission_ok
+}
+
+// walks the page table from satp to the leaf that maps `addr`
+fn walk(backend: &Backend, satp: u64, addr: u64, kind: AccessKind) -> Result<TlbEntry, RVError> {
+    // bits 63..39 have to be copies of bit 38
+    if ((addr as i64) << 25 >> 25) as u64 != addr {
+        return Err(page_fault(addr, kind));
+    }
+
+    let mut table = (satp & SATP_PPN_MASK) << PAGE_BITS;
+
+    for level in (0..LEVELS).rev() {
+        let shift = PAGE_BITS + level * VPN_BITS;
+        let index = (addr >> shift) & ((1 << VPN_BITS) - 1);
+        let pte: u64 = backend.load(table + index * 8)?;
+        let ppn = (pte >> PTE_PPN_SHIFT) & PTE_PPN_MASK;
+
+        if pte & PTE_V == 0 || pte & (PTE_R | PTE_W) == PTE_W {
+            return Err(page_fault(addr, kind));
+        }
+
+        if pte & (PTE_R | PTE_X) == 0 {
+            table = ppn << PAGE_BITS;
+            continue;
+        }
+
+        // the ppn of a superpage has to be aligned to its size
+        let low_ppn = (1 << (level * VPN_BITS)) - 1;
+        if ppn & low_ppn != 0 {
+            return Err(page_fault(addr, kind));
+        }
+
+        return Ok(TlbEntry {
+            vpn: addr >> PAGE_BITS,
+            ppn: ppn | (addr >> PAGE_BITS) & low_ppn,
+            flags: pte & (PTE_R | PTE_W | PTE_X | PTE_U),
+        });
+    }
+
+    Err(page_fault(addr, kind))
+}
+
+fn page_fault(addr: u64, kind: AccessKind) -> RVError {
+    RVError::PageFault { addr, kind }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::Memory;
+
+    #[test]
+    fn sv39() {
+        let mut memory = Memory::from_raw(&[0; 0x5000]);
+        // tables at 0x1000, 0x2000 and 0x3000 mapping 0 to a user page at 0x4000
+        memory
+            .store::<u64>(0x1000, 0x2 << PTE_PPN_SHIFT | PTE_V)
+            .unwrap();
+        memory
+            .store::<u64>(0x2000, 0x3 << PTE_PPN_SHIFT | PTE_V)
+            .unwrap();
+        let pte = 0x4 << PTE_PPN_SHIFT | PTE_V | PTE_R | PTE_W | PTE_U;
+        memory.store::<u64>(0x3000, pte).unwrap();
+        // and a 1GiB page mapping 0x4000_0000 to 0, for the supervisor only
+        memory.store::<u64>(0x1008, PTE_V | PTE_R | PTE_X).unwrap();
+        memory.store::<u64>(0x4010, 0xabcd).unwrap();
+
+        let mut translation = Translation {
+            satp: 8 << 60 | 1, // sv39
+            user: true,
+            sum: false,
+            mxr: false,
+        };
+        memory.set_translation(Some(translation));
+
+        assert_eq!(memory.load::<u64>(0x10).ok(), Some(0xabcd));
+        memory.store::<u64>(0x18, 7).unwrap();
+        assert_eq!(memory.tlb_stats(), TlbStats { hits: 1, misses: 1 });
+
+        // user mode can't touch supervisor pages
+        assert!(matches!(
+            memory.load::<u64>(0x4000_4018),
+            Err(RVError::PageFault {
+                addr: 0x4000_4018,
+                kind: AccessKind::Read,
+            })
+        ));
+
+        translation.user = false;
+        memory.set_translation(Some(translation));
+        assert_eq!(memory.load::<u64>(0x4000_4018).ok(), Some(7));
+        // and supervisor mode can't touch user pages without sum, or write read-only ones
+        assert!(memory.load::<u64>(0x10).is_err());
+        assert!(memory.store::<u64>(0x4000_4018, 0).is_err());
+
+        // unmapped and non-canonical addresses fault
+        assert!(memory.load::<u8>(0x8000_0000).is_err());
+        assert!(memory.load::<u8>(1 << 40).is_err());
+
+        memory.set_translation(None);
+        assert_eq!(memory.load::<u64>(0x4018).ok(), Some(7));
+    }
+
+    #[test]
+    fn marshalling() -> Result<(), RVError> {
+        let mut memory = Memory::from_raw(&[0; 0x7000]);
+        // virtual pages 0 and 1 map to the physical pages at 0x4000 and 0x6000, which aren't next
+        // to each other
+        memory.store::<u64>(0x1000, 0x2 << PTE_PPN_SHIFT | PTE_V)?;
+        memory.store::<u64>(0x2000, 0x3 << PTE_PPN_SHIFT | PTE_V)?;
+        let leaf = PTE_V | PTE_R | PTE_W | PTE_U;
+        memory.store::<u64>(0x3000, 0x4 << PTE_PPN_SHIFT | leaf)?;
+        memory.store::<u64>(0x3008, 0x6 << PTE_PPN_SHIFT | leaf)?;
+        memory.set_translation(Some(Translation {
+            satp: 8 << 60 | 1, // sv39
+            user: true,
+            sum: false,
+            mxr: false,
+        }));
+
+        // straddles the two pages
+        let value = [0x0123456789abcdefu64, 0xfedcba9876543210];
+        memory.write_struct(0xffc, &value)?;
+        assert_eq!(memory.read_struct::<[u64; 2]>(0xffc)?, value);
+        assert_eq!(memory.read_n(0xffc, 4)?, [0xef, 0xcd, 0xab, 0x89]);
+        memory.write_n(b"hi", 0x1ffe, 2)?;
+        assert!(memory.write_n(b"hi", 0x2000, 2).is_err());
+
+        memory.set_translation(None);
+        assert_eq!(memory.load::<u32>(0x4ffc)?, 0x89abcdef);
+        assert_eq!(memory.load::<u32>(0x6000)?, 0x01234567);
+        assert_eq!(memory.load::<u16>(0x6ffe)?, u16::from_le_bytes(*b"hi"));
+        assert_eq!(memory.load::<u64>(0x5000)?, 0);
+
+        Ok(())
+    }
+}
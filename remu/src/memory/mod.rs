@@ -0,0 +1,1754 @@
+use alloc::{format, string::String, sync::Arc, vec, vec::Vec};
+use core::{mem, ops::Range};
+
+use elf::{
+    abi::{DT_NEEDED, EM_RISCV, ET_DYN, ET_EXEC, PT_DYNAMIC, PT_INTERP, PT_LOAD, PT_PHDR},
+    endian::{AnyEndian, EndianParse},
+    file::Class,
+    ElfBytes,
+};
+use log::{debug, warn};
+
+use crate::{
+    disassembler::Disassembler,
+    error::RVError,
+    files::{FileDescriptor, Sysroot, DEFAULT_INTERPRETER},
+    system::{AccessKind, STACK_START},
+};
+
+#[cfg(feature = "mmu")]
+pub use self::mmu::{TlbStats, Translation};
+pub use self::{
+    cow::CowMemory,
+    flat::{FlatMemory, FLAT_STACK_SIZE},
+    mappings::{Mapping, MappingKind},
+    marshal::FromBytes,
+    mmio::{MemoryHandler, Uart},
+    objects::LoadedObject,
+    paged::PagedMemory,
+    report::{LoadDiagnostic, LoadReport},
+    shadow::Violation,
+};
+use self::{
+    dirty::DirtyPages,
+    mappings::{segment_prot, Mappings, PROT_READ_WRITE},
+    mmio::Devices,
+    paged::HeapIndex,
+    shadow::Shadow,
+};
+
+mod cow;
+mod dirty;
+mod flat;
+mod mappings;
+mod marshal;
+mod mmio;
+#[cfg(feature = "mmu")]
+mod mmu;
+mod objects;
+mod paged;
+mod report;
+mod shadow;
+
+// the guest's page size, which the `pages-16k` and `pages-64k` features raise from 4 KiB. It's
+// what mmap aligns to and what AT_PAGESZ tells the guest, while sv39 pages are always 4 KiB.
+#[cfg(feature = "pages-64k")]
+const PAGE_BITS: u64 = 16;
+#[cfg(all(feature = "pages-16k", not(feature = "pages-64k")))]
+const PAGE_BITS: u64 = 14;
+#[cfg(not(any(feature = "pages-16k", feature = "pages-64k")))]
+const PAGE_BITS: u64 = 12;
+pub const PAGE_SIZE: u64 = 1 << PAGE_BITS;
+pub const PAGE_MASK: u64 = (1 << PAGE_BITS) - 1;
+
+// the dynamic linker named by the executable's PT_INTERP segment
+fn interpreter_path<T: EndianParse>(elf: &ElfBytes<T>) -> Option<String> {
+    let segment = elf
+        .segments()?
+        .iter()
+        .find(|segment| segment.p_type == PT_INTERP)?;
+    let path = elf.segment_data(&segment).ok()?;
+    let path = path.split(|&byte| byte == 0).next()?;
+
+    Some(String::from_utf8_lossy(path).into_owned())
+}
+
+// rounds `addr` up to the next page boundary, or None if that's past the end of the address space
+pub(crate) const fn page_align(addr: u64) -> Option<u64> {
+    match addr.checked_add(PAGE_MASK) {
+        Some(addr) => Some(addr & !PAGE_MASK),
+        None => None,
+    }
+}
+
+// the end of the pages [addr, addr + len) covers, or None if they wrap around the address space
+pub(crate) fn range_end(addr: u64, len: u64) -> Option<u64> {
+    addr.checked_add(page_align(len)?)
+}
+
+/// The number of characters in each line of [`Memory::hexdump`], including the newline
+pub const HEXDUMP_LINE_WIDTH: usize = 87;
+
+#[derive(Default, Clone)]
+pub struct ProgramHeaderInfo {
+    pub entry: u64,
+    pub address: u64,
+    pub size: u64,
+    pub number: u64,
+}
+
+/// Bytes of host memory used to store each region of guest memory. The regions follow the layout
+/// of [`PagedMemory`], where the top byte of an address selects the region.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// Segments of the executable
+    pub program: u64,
+    /// Memory allocated through brk
+    pub heap: u64,
+    pub dynamic_linker: u64,
+    /// Every region allocated through mmap
+    pub mmap: u64,
+    pub stack: u64,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> u64 {
+        self.program + self.heap + self.dynamic_linker + self.mmap + self.stack
+    }
+
+    // the counter for the region selected by the top byte of an address
+    fn region_mut(&mut self, region: u8) -> &mut u64 {
+        match region {
+            0 => &mut self.program,
+            1 => &mut self.heap,
+            2 => &mut self.dynamic_linker,
+            255 => &mut self.stack,
+            _ => &mut self.mmap,
+        }
+    }
+}
+
+/// Selects the backend a [`Memory`] stores guest memory in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MemoryLayout {
+    /// See [`PagedMemory`]
+    #[default]
+    Paged,
+    /// See [`FlatMemory`]. `size` bytes are allocated up front for everything except the stack.
+    Flat { size: u64 },
+    /// See [`CowMemory`]
+    Cow,
+}
+
+/// Where [`Memory::load_elf_with_options`] puts things in the guest's address space
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LoadOptions {
+    pub layout: MemoryLayout,
+    /// The page aligned address a position independent executable is loaded at, instead of 0.
+    /// Ignored for other executables, which can only run where they were linked.
+    pub program_base: Option<u64>,
+    /// The page aligned address the dynamic linker is loaded at, instead of where the backend
+    /// puts it
+    pub interpreter_base: Option<u64>,
+    /// Shifts the stack, mmaps, and the program and dynamic linker unless they have a base, by a
+    /// random number of pages. The same seed always gives the same addresses.
+    pub aslr_seed: Option<u64>,
+}
+
+impl From<MemoryLayout> for LoadOptions {
+    fn from(layout: MemoryLayout) -> Self {
+        LoadOptions {
+            layout,
+            ..LoadOptions::default()
+        }
+    }
+}
+
+// how far ASLR can shift the program, the dynamic linker and the stack. mmaps are only shifted by
+// a few pages, since the paged backend allocates the pages skipped at the start of each region.
+const ASLR_PAGES: u64 = 256;
+const ASLR_MMAP_PAGES: u64 = 16;
+
+// splitmix64, which is plenty for picking addresses
+#[derive(Clone, Debug)]
+struct Aslr(u64);
+
+impl Aslr {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    // a random multiple of `align` below `max`
+    fn offset(&mut self, max: u64, align: u64) -> u64 {
+        self.next() % (max / align) * align
+    }
+}
+
+/// Storage for guest memory. [`Memory`] handles elf loading, symbols, and code tracking on top of
+/// one of the backends implementing this.
+///
+/// `load` and `store` are generic over the accessed type, so backends are dispatched through a
+/// private enum instead of a trait object, which also keeps [`Emulator`](crate::system::Emulator)
+/// free of type parameters.
+pub trait MemoryBackend: Clone {
+    fn load<T>(&self, addr: u64) -> Result<T, RVError>;
+
+    fn store<T>(&mut self, addr: u64, data: T) -> Result<(), RVError>;
+
+    /// Fills `buf` with the bytes at `addr`. The default implementation loads one byte at a time.
+    fn read(&self, addr: u64, buf: &mut [u8]) -> Result<(), RVError> {
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = self.load(addr + i as u64)?;
+        }
+
+        Ok(())
+    }
+
+    /// Copies `data` to `addr`. The default implementation stores one byte at a time.
+    fn write(&mut self, addr: u64, data: &[u8]) -> Result<(), RVError> {
+        for (i, byte) in data.iter().enumerate() {
+            self.store(addr + i as u64, *byte)?;
+        }
+
+        Ok(())
+    }
+
+    /// Zeroes [addr, addr + len). The default implementation stores one byte at a time.
+    fn zero(&mut self, addr: u64, len: u64) -> Result<(), RVError> {
+        self.fill(addr, len, 0)
+    }
+
+    /// Sets every byte of [addr, addr + len) to `byte`. The default implementation stores one
+    /// byte at a time.
+    fn fill(&mut self, addr: u64, len: u64, byte: u8) -> Result<(), RVError> {
+        for i in 0..len {
+            self.store(addr + i, byte)?;
+        }
+
+        Ok(())
+    }
+
+    /// Maps `len` zeroed bytes at `addr`, or wherever the backend sees fit if `addr` is 0. Returns
+    /// the address of the mapping, or -1 if it failed.
+    fn map(&mut self, addr: u64, len: u64) -> i64;
+
+    /// Unmaps [addr, addr + len), giving the host memory behind it back where the backend can.
+    /// Backends that can't unmap part of a region may leave it accessible, and by default it's
+    /// only zeroed.
+    fn unmap(&mut self, addr: u64, len: u64) -> Result<(), RVError> {
+        self.zero(addr, len)
+    }
+
+    /// Grows the mapping of `old_len` bytes at `addr` to `new_len` bytes without moving it,
+    /// zeroing the new part. Returns false if that isn't possible, in which case nothing changed.
+    /// By default mappings can't grow.
+    fn grow_mapping(&mut self, _addr: u64, _old_len: u64, _new_len: u64) -> bool {
+        false
+    }
+
+    /// Makes [addr, addr + len) accessible without clearing it. Used to map elf segments.
+    fn reserve(&mut self, addr: u64, len: u64) -> Result<(), RVError>;
+
+    /// Maps `len` bytes at `addr` whose start is backed by `image[data]`, without copying it if
+    /// the backend supports that. The rest is zero filled. Returns false if the backend doesn't
+    /// support it, in which case nothing was mapped.
+    fn map_image(
+        &mut self,
+        _addr: u64,
+        _image: &Arc<[u8]>,
+        _data: Range<usize>,
+        _len: u64,
+    ) -> Result<bool, RVError> {
+        Ok(false)
+    }
+
+    /// Moves the program break to `new_end` if possible, returning the current break.
+    fn brk(&mut self, new_end: u64) -> u64;
+
+    /// Changes the protection of [addr, addr + len) to the linux PROT_* flags in `prot`. Backends
+    /// don't have to enforce protections, and by default they are ignored.
+    fn protect(&mut self, _addr: u64, _len: u64, _prot: u64) -> Result<(), RVError> {
+        Ok(())
+    }
+
+    /// The number of bytes of host memory used to store guest memory. This is called after every
+    /// block, so it has to be cheap to compute.
+    fn usage(&self) -> MemoryUsage;
+
+    /// Where the dynamic linker gets loaded, given the end of the executable's segments.
+    fn dynamic_linker_base(&self, image_end: u64) -> u64;
+
+    /// The memory currently allocated through brk
+    fn heap(&self) -> Range<u64>;
+
+    /// The part of the stack that has been allocated, up to [`STACK_START`]
+    fn stack(&self) -> Range<u64>;
+}
+
+// boxing the paged backend would add an indirection to every access
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone)]
+enum Backend {
+    Paged(PagedMemory),
+    Flat(FlatMemory),
+    Cow(CowMemory),
+}
+
+// forwards a method call to whichever backend is in use
+macro_rules! dispatch {
+    ($self:ident.$method:ident($($arg:expr),*)) => {
+        match $self {
+            Backend::Paged(backend) => backend.$method($($arg),*),
+            Backend::Flat(backend) => backend.$method($($arg),*),
+            Backend::Cow(backend) => backend.$method($($arg),*),
+        }
+    };
+}
+
+impl MemoryBackend for Backend {
+    #[inline]
+    fn load<T>(&self, addr: u64) -> Result<T, RVError> {
+        dispatch!(self.load(addr))
+    }
+
+    #[inline]
+    fn store<T>(&mut self, addr: u64, data: T) -> Result<(), RVError> {
+        dispatch!(self.store(addr, data))
+    }
+
+    fn read(&self, addr: u64, buf: &mut [u8]) -> Result<(), RVError> {
+        dispatch!(self.read(addr, buf))
+    }
+
+    fn write(&mut self, addr: u64, data: &[u8]) -> Result<(), RVError> {
+        dispatch!(self.write(addr, data))
+    }
+
+    fn zero(&mut self, addr: u64, len: u64) -> Result<(), RVError> {
+        dispatch!(self.zero(addr, len))
+    }
+
+    fn fill(&mut self, addr: u64, len: u64, byte: u8) -> Result<(), RVError> {
+        dispatch!(self.fill(addr, len, byte))
+    }
+
+    fn map(&mut self, addr: u64, len: u64) -> i64 {
+        dispatch!(self.map(addr, len))
+    }
+
+    fn reserve(&mut self, addr: u64, len: u64) -> Result<(), RVError> {
+        dispatch!(self.reserve(addr, len))
+    }
+
+    fn map_image(
+        &mut self,
+        addr: u64,
+        image: &Arc<[u8]>,
+        data: Range<usize>,
+        len: u64,
+    ) -> Result<bool, RVError> {
+        dispatch!(self.map_image(addr, image, data, len))
+    }
+
+    fn brk(&mut self, new_end: u64) -> u64 {
+        dispatch!(self.brk(new_end))
+    }
+
+    fn unmap(&mut self, addr: u64, len: u64) -> Result<(), RVError> {
+        dispatch!(self.unmap(addr, len))
+    }
+
+    fn grow_mapping(&mut self, addr: u64, old_len: u64, new_len: u64) -> bool {
+        dispatch!(self.grow_mapping(addr, old_len, new_len))
+    }
+
+    fn protect(&mut self, addr: u64, len: u64, prot: u64) -> Result<(), RVError> {
+        dispatch!(self.protect(addr, len, prot))
+    }
+
+    fn usage(&self) -> MemoryUsage {
+        dispatch!(self.usage())
+    }
+
+    fn dynamic_linker_base(&self, image_end: u64) -> u64 {
+        dispatch!(self.dynamic_linker_base(image_end))
+    }
+
+    fn heap(&self) -> Range<u64> {
+        dispatch!(self.heap())
+    }
+
+    fn stack(&self) -> Range<u64> {
+        dispatch!(self.stack())
+    }
+}
+
+#[derive(Clone)]
+pub struct Memory {
+    backend: Backend,
+
+    // the address of entry to the program
+    pub entry: u64,
+
+    pub program_header: ProgramHeaderInfo,
+
+    pub disassembler: Disassembler,
+
+    /// Where the dynamic linker is mapped, for dynamically linked executables
+    pub dynamic_linker: Option<Range<u64>>,
+
+    // see `program_base`
+    program_base: u64,
+    // see `loaded_objects`
+    objects: Vec<LoadedObject>,
+
+    // one bit per page of each buffer, set for pages instructions have been decoded from
+    code_pages: Vec<Vec<u64>>,
+
+    /// Incremented whenever a page marked with [`Memory::mark_code`] is written to, so decoded
+    /// instruction caches know to flush themselves.
+    pub code_generation: u64,
+
+    // see `set_memcheck_enabled`
+    shadow: Option<Shadow>,
+    // see `start_write_log`
+    write_log: Option<Vec<Range<u64>>>,
+    // see `dirty_page_count`
+    dirty: DirtyPages,
+
+    // see `mappings`
+    mappings: Mappings,
+    // see `map_device`
+    devices: Devices,
+    // see `set_translation`
+    #[cfg(feature = "mmu")]
+    mmu: mmu::Mmu,
+    load_options: LoadOptions,
+    // see `load_report`
+    load_report: LoadReport,
+    aslr: Option<Aslr>,
+    // see `stack_top`
+    stack_top: u64,
+    // see `set_memory_limit`
+    memory_limit: Option<u64>,
+    memory_limit_hits: u64,
+}
+
+impl Memory {
+    /// Checks that `elf` is a 64-bit RISC-V executable, position independent or not, which is
+    /// all the loaders support
+    pub fn check_elf<T: EndianParse>(elf: &ElfBytes<T>) -> Result<(), RVError> {
+        match (elf.ehdr.class, elf.ehdr.e_type, elf.ehdr.e_machine) {
+            (Class::ELF64, ET_EXEC | ET_DYN, EM_RISCV) => Ok(()),
+            _ => Err(RVError::InvalidFileType),
+        }
+    }
+
+    /// Parses the executable `data` and checks it with [`Memory::check_elf`]
+    pub fn parse_elf(data: &[u8]) -> Result<ElfBytes<'_, AnyEndian>, RVError> {
+        let elf = ElfBytes::minimal_parse(data).map_err(|_| RVError::InvalidFileType)?;
+        Memory::check_elf(&elf)?;
+        Ok(elf)
+    }
+
+    /// Loads the executable `data`, failing with [`RVError::InvalidFileType`] if it isn't one
+    /// the emulator can run
+    pub fn load_elf_bytes(data: &[u8], options: &LoadOptions) -> Result<Self, RVError> {
+        Ok(Memory::load_elf_with_options(
+            Memory::parse_elf(data)?,
+            options,
+        ))
+    }
+
+    pub fn load_elf<T: EndianParse>(elf: ElfBytes<T>) -> Self {
+        Self::load_elf_with_layout(elf, MemoryLayout::Paged)
+    }
+
+    pub fn load_elf_with_layout<T: EndianParse>(elf: ElfBytes<T>, layout: MemoryLayout) -> Self {
+        Self::load_elf_with_options(elf, &LoadOptions::from(layout))
+    }
+
+    pub fn load_elf_with_options<T: EndianParse>(elf: ElfBytes<T>, options: &LoadOptions) -> Self {
+        Self::load_elf_with_sysroot(elf, options, &Sysroot::default())
+    }
+
+    /// Like [`Memory::load_elf_with_options`], but a dynamically linked executable's dynamic
+    /// linker comes from `sysroot`
+    pub fn load_elf_with_sysroot<T: EndianParse>(
+        elf: ElfBytes<T>,
+        options: &LoadOptions,
+        sysroot: &Sysroot,
+    ) -> Self {
+        Self::load_with(elf, options, sysroot, |memory, offset, elf| {
+            memory.map_segments(offset, elf, MappingKind::Program)
+        })
+    }
+
+    /// Like [`Memory::load_elf_bytes`], but backends that support it (currently only
+    /// [`CowMemory`]) reference the pages of `image` instead of copying them, until they are
+    /// written to.
+    pub fn load_shared_elf(image: Arc<[u8]>, options: &LoadOptions) -> Result<Self, RVError> {
+        Self::load_shared_elf_with_sysroot(image, options, &Sysroot::default())
+    }
+
+    pub fn load_shared_elf_with_sysroot(
+        image: Arc<[u8]>,
+        options: &LoadOptions,
+        sysroot: &Sysroot,
+    ) -> Result<Self, RVError> {
+        let elf = Memory::parse_elf(&image)?;
+        Ok(Self::load_with(
+            elf,
+            options,
+            sysroot,
+            |memory, offset, elf| {
+                memory.map_shared_segments(offset, elf, &image, MappingKind::Program)
+            },
+        ))
+    }
+
+    fn load_with<'data, T, F>(
+        elf: ElfBytes<'data, T>,
+        options: &LoadOptions,
+        sysroot: &Sysroot,
+        map_program: F,
+    ) -> Self
+    where
+        T: EndianParse,
+        F: FnOnce(&mut Memory, u64, &ElfBytes<'data, T>),
+    {
+        let mut memory = Memory::new(options.layout);
+        memory.load_options = *options;
+        memory.aslr = options.aslr_seed.map(Aslr);
+
+        let offset = match (elf.ehdr.e_type, options.program_base) {
+            (ET_DYN, Some(base)) => base,
+            (ET_DYN, None) => memory.aslr_offset(ASLR_PAGES),
+            (_, Some(_)) => {
+                warn!("The executable isn't position independent, ignoring its base address");
+                0
+            }
+            (_, None) => 0,
+        };
+
+        memory.program_base = offset;
+        memory.add_object(&elf, offset, "program");
+
+        let mut report = LoadReport::default();
+        let program = report.check_image(MappingKind::Program, offset, &elf);
+
+        // load dynamic libraries, if they exist
+        // https://blog.k3170makan.com/2018/11/introduction-to-elf-format-part-vii.html
+        // https://www.youtube.com/watch?v=Ss2e6JauS0Y
+        if let Some((_dynamic_symbol_table, string_table)) = elf.dynamic_symbol_table().unwrap() {
+            if let Some(dynamic) = elf.dynamic().unwrap() {
+                for x in dynamic {
+                    if x.d_tag == DT_NEEDED {
+                        let obj = string_table.get(x.d_val() as usize).unwrap();
+                        log::info!("requires shared object: {}", obj);
+                    }
+                }
+
+                let ld_linux = interpreter_path(&elf)
+                    .and_then(|path| sysroot.file(&path))
+                    .or_else(|| sysroot.file(DEFAULT_INTERPRETER));
+
+                if let Some(ld_linux) = ld_linux {
+                    let ld_elf = ElfBytes::<AnyEndian>::minimal_parse(&ld_linux).unwrap();
+                    log::info!("Loading dynamically linked executable.");
+
+                    let ld_offset = match options.interpreter_base {
+                        Some(base) => base,
+                        None => {
+                            memory.dynamic_linker_base(offset + image_end(&elf))
+                                + memory.aslr_offset(ASLR_PAGES)
+                        }
+                    };
+
+                    let linker = report.check_image(MappingKind::DynamicLinker, ld_offset, &ld_elf);
+                    report.check_dynamic_linker(&program, &linker);
+
+                    memory.map_segments(ld_offset, &ld_elf, MappingKind::DynamicLinker);
+                    map_program(&mut memory, offset, &elf);
+
+                    memory.add_object(&ld_elf, ld_offset, "ld.so");
+
+                    memory.entry = ld_offset + ld_elf.ehdr.e_entry;
+                    memory.dynamic_linker = Some(ld_offset..ld_offset + image_end(&ld_elf));
+                } else {
+                    // the program is still mapped so it can be inspected, but it won't get far
+                    report.diagnostics.push(LoadDiagnostic::NoDynamicLinker);
+                    map_program(&mut memory, offset, &elf);
+                    memory.entry = offset + elf.ehdr.e_entry;
+                }
+            }
+        } else {
+            log::info!("Loading statically linked executable.");
+            map_program(&mut memory, offset, &elf);
+            memory.entry = offset + elf.ehdr.e_entry;
+        }
+
+        for diagnostic in &report.diagnostics {
+            if diagnostic.is_error() {
+                log::error!("{diagnostic}");
+            } else {
+                warn!("{diagnostic}");
+            }
+        }
+        memory.load_report = report;
+
+        // the pages in between are touched so backends that only grow the stack a page at a
+        // time can reach the top
+        if let Some(ref mut aslr) = memory.aslr {
+            memory.stack_top = STACK_START - aslr.offset(ASLR_PAGES << PAGE_BITS, 16);
+        }
+        let mut addr = STACK_START;
+        while addr > memory.stack_top {
+            addr = addr.saturating_sub(PAGE_SIZE).max(memory.stack_top);
+            memory
+                .backend
+                .store(addr, 0u8)
+                .expect("Failed to grow the stack");
+        }
+
+        memory
+    }
+
+    /// Creates an empty address space, with only a stack
+    pub fn new(layout: MemoryLayout) -> Self {
+        let backend = match layout {
+            MemoryLayout::Paged => Backend::Paged(PagedMemory::new()),
+            MemoryLayout::Flat { size } => Backend::Flat(FlatMemory::new(size)),
+            MemoryLayout::Cow => Backend::Cow(CowMemory::new()),
+        };
+
+        Memory {
+            backend,
+            entry: 0,
+            program_header: ProgramHeaderInfo::default(),
+            disassembler: Disassembler::new(),
+            dynamic_linker: None,
+            program_base: 0,
+            objects: Vec::new(),
+            code_pages: vec![vec![]; 256],
+            code_generation: 0,
+            shadow: None,
+            write_log: None,
+            dirty: DirtyPages::default(),
+            mappings: Mappings::default(),
+            devices: Devices::default(),
+            #[cfg(feature = "mmu")]
+            mmu: mmu::Mmu::new(),
+            load_options: LoadOptions::from(layout),
+            load_report: LoadReport::default(),
+            aslr: None,
+            stack_top: STACK_START,
+            memory_limit: None,
+            memory_limit_hits: 0,
+        }
+    }
+
+    /// The options the program was loaded with, see [`Memory::load_elf_with_options`]
+    pub fn load_options(&self) -> LoadOptions {
+        self.load_options
+    }
+
+    /// What was wrong with the executable's segments, if anything, found when it was loaded
+    pub fn load_report(&self) -> &LoadReport {
+        &self.load_report
+    }
+
+    /// Where the stack pointer starts, below [`STACK_START`] if ASLR is enabled
+    pub fn stack_top(&self) -> u64 {
+        self.stack_top
+    }
+
+    /// How far the executable was moved from the addresses it was linked at, which is 0 unless
+    /// it's position independent. Symbols from elsewhere are moved by the same amount.
+    pub fn program_base(&self) -> u64 {
+        self.program_base
+    }
+
+    // a random page aligned offset below `max` pages, or 0 if ASLR is disabled
+    fn aslr_offset(&mut self, max: u64) -> u64 {
+        match self.aslr {
+            Some(ref mut aslr) => aslr.offset(max << PAGE_BITS, PAGE_SIZE),
+            None => 0,
+        }
+    }
+
+    // where the dynamic linker gets loaded, after a program ending at `image_end`
+    fn dynamic_linker_base(&self, image_end: u64) -> u64 {
+        self.backend.dynamic_linker_base(image_end)
+    }
+
+    // records the program header and the mappings of the loaded segments, and returns the
+    // (address, file data, size) of every segment
+    fn segments<'data, E: EndianParse>(
+        &mut self,
+        offset: u64,
+        elf: &ElfBytes<'data, E>,
+        kind: MappingKind,
+    ) -> Vec<(u64, &'data [u8], u64)> {
+        let mut mapped = Vec::new();
+
+        let segments = elf.segments().unwrap();
+        for segment in segments {
+            match segment.p_type {
+                PT_LOAD | PT_PHDR | PT_DYNAMIC => {
+                    let addr_start = offset + segment.p_vaddr;
+
+                    if segment.p_type == PT_PHDR {
+                        self.program_header.size = segment.p_memsz;
+                        self.program_header.address = addr_start;
+                        self.program_header.number = elf.ehdr.e_phnum as u64;
+                        self.program_header.entry = offset + elf.ehdr.e_entry;
+                    }
+
+                    // anything past p_memsz isn't mapped, and the rest is zero filled by the
+                    // backend, however large the bss is
+                    let mut data = elf.segment_data(&segment).unwrap();
+                    if data.len() as u64 > segment.p_memsz {
+                        warn!("segment at {addr_start:x} has more file data than memory");
+                        data = &data[..segment.p_memsz as usize];
+                    }
+
+                    if segment.p_type == PT_LOAD {
+                        self.mappings.insert(
+                            addr_start & !PAGE_MASK,
+                            page_align(addr_start + segment.p_memsz)
+                                .expect("segment wraps around the address space"),
+                            segment_prot(segment.p_flags),
+                            kind,
+                        );
+                    }
+
+                    debug!(
+                        "Mapping {} bytes onto offset {:x}. p_type = {}",
+                        segment.p_memsz, addr_start, segment.p_type
+                    );
+
+                    mapped.push((addr_start, data, segment.p_memsz));
+                }
+                PT_INTERP => {
+                    log::debug!("interp: {segment:x?}");
+                }
+                _ => {
+                    warn!("Unknown p_type: {segment:x?}");
+                }
+            }
+        }
+
+        mapped
+    }
+
+    fn map_segments<'data, E: EndianParse>(
+        &mut self,
+        offset: u64,
+        elf: &ElfBytes<'data, E>,
+        kind: MappingKind,
+    ) {
+        for (addr, data, len) in self.segments(offset, elf, kind) {
+            self.backend
+                .reserve(addr, len)
+                .expect("Failed to map executable segment");
+
+            self.write_n(data, addr, len)
+                .expect("Failed to load executable into memory");
+        }
+    }
+
+    // lets the backend reference the segment data instead of copying it, if it can. `elf` is
+    // parsed from `image`.
+    fn map_shared_segments<E: EndianParse>(
+        &mut self,
+        offset: u64,
+        elf: &ElfBytes<E>,
+        image: &Arc<[u8]>,
+        kind: MappingKind,
+    ) {
+        for (addr, data, len) in self.segments(offset, elf, kind) {
+            let start = data.as_ptr() as usize - image.as_ptr() as usize;
+            let mapped = self
+                .backend
+                .map_image(addr, image, start..start + data.len(), len)
+                .expect("Failed to map executable segment");
+
+            if !mapped {
+                self.backend
+                    .reserve(addr, len)
+                    .expect("Failed to map executable segment");
+
+                self.write_n(data, addr, len)
+                    .expect("Failed to load executable into memory");
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub fn from_raw(data: &[u8]) -> Self {
+        Self::from_raw_with_layout(data, MemoryLayout::Paged)
+    }
+
+    #[cfg(test)]
+    pub fn from_raw_with_layout(data: &[u8], layout: MemoryLayout) -> Self {
+        let mut memory = Memory::new(layout);
+
+        memory
+            .backend
+            .reserve(0, data.len() as u64)
+            .expect("Failed to reserve test data");
+        memory
+            .write_n(data, 0, data.len() as u64)
+            .expect("Failed to write data for test");
+
+        memory
+    }
+
+    // returns the number of bytes of memory allocated
+    /// The layout the memory was created with
+    pub fn layout(&self) -> MemoryLayout {
+        match self.backend {
+            Backend::Paged(_) => MemoryLayout::Paged,
+            Backend::Flat(ref flat) => MemoryLayout::Flat { size: flat.size() },
+            Backend::Cow(_) => MemoryLayout::Cow,
+        }
+    }
+
+    pub fn usage(&self) -> u64 {
+        self.backend.usage().total()
+    }
+
+    /// The number of bytes of memory allocated, split up by region
+    pub fn usage_by_region(&self) -> MemoryUsage {
+        self.backend.usage()
+    }
+
+    /// Fails brk, mmap and mremap with ENOMEM when they would take [`Memory::usage`] past
+    /// `limit` bytes. Memory the guest uses without asking, like the stack as it grows, can still
+    /// go past it, which the emulator stops at with [`RVError::MemoryLimitExceeded`]. Unlimited
+    /// by default.
+    pub fn set_memory_limit(&mut self, limit: Option<u64>) {
+        self.memory_limit = limit;
+    }
+
+    pub fn memory_limit(&self) -> Option<u64> {
+        self.memory_limit
+    }
+
+    /// The number of allocations that failed because of [`Memory::set_memory_limit`]
+    pub fn memory_limit_hits(&self) -> u64 {
+        self.memory_limit_hits
+    }
+
+    // whether `len` more bytes fit under the memory limit, counting it as hit if they don't
+    fn reserve_usage(&mut self, len: u64) -> bool {
+        let Some(limit) = self.memory_limit else {
+            return true;
+        };
+
+        let usage = self.usage();
+        if usage.saturating_add(len) <= limit {
+            return true;
+        }
+
+        log::warn!(
+            "Allocating {len} bytes would take memory usage from {usage} past the limit of {limit}"
+        );
+        self.memory_limit_hits += 1;
+        false
+    }
+
+    pub fn brk(&mut self, new_end: u64) -> u64 {
+        let end = self.backend.brk(0);
+        if new_end > end && !self.reserve_usage(new_end - end) {
+            return end;
+        }
+
+        self.backend.brk(new_end)
+    }
+
+    pub fn mmap(&mut self, addr: u64, size: u64) -> i64 {
+        let Some(len) = page_align(size).filter(|_| range_end(addr, size).is_some()) else {
+            return -1;
+        };
+        if !self.reserve_usage(len) {
+            return -1;
+        }
+
+        let addr = match addr {
+            // the start of the mapping is skipped
+            0 if self.aslr.is_some() => {
+                let skipped = self.aslr_offset(ASLR_MMAP_PAGES);
+                match self.backend.map(0, skipped.saturating_add(size)) {
+                    -1 => -1,
+                    addr => addr + skipped as i64,
+                }
+            }
+            addr => self.backend.map(addr, size),
+        };
+
+        if addr >= 0 {
+            let start = addr as u64;
+            let end = start + len;
+            self.mappings
+                .insert(start, end, PROT_READ_WRITE, MappingKind::Mmap);
+
+            if let Some(ref mut shadow) = self.shadow {
+                shadow.map(start, size);
+            }
+        }
+
+        addr
+    }
+
+    /// The pages aren't actually released, but later accesses to them are reported by memcheck.
+    /// Returns false if the range wraps around the address space, in which case nothing is
+    /// unmapped.
+    pub fn munmap(&mut self, addr: u64, len: u64) -> bool {
+        let Some(end) = range_end(addr, len) else {
+            return false;
+        };
+
+        if let Some(ref mut shadow) = self.shadow {
+            shadow.unmap(addr, len);
+        }
+        self.mappings.remove(addr, end);
+
+        // unmapping memory that isn't mapped isn't an error
+        let _ = self.backend.unmap(addr, len);
+        true
+    }
+
+    /// Zeroes [addr, addr + len) like `madvise(MADV_DONTNEED)`, releasing the host memory behind
+    /// whole pages where the backend can
+    pub fn discard(&mut self, addr: u64, len: u64) -> Result<(), RVError> {
+        self.backend.zero(addr, len)?;
+
+        if let Some(ref mut shadow) = self.shadow {
+            shadow.write(addr, len);
+        }
+        self.log_write(addr, len);
+        self.dirty.mark(addr, len);
+
+        Ok(())
+    }
+
+    /// Starts recording every write, to be read back with [`Memory::take_write_log`]
+    pub fn start_write_log(&mut self) {
+        self.write_log = Some(Vec::new());
+    }
+
+    pub fn is_logging_writes(&self) -> bool {
+        self.write_log.is_some()
+    }
+
+    /// The addresses written since [`Memory::start_write_log`], in the order they were written,
+    /// and stops recording them
+    pub fn take_write_log(&mut self) -> Vec<Range<u64>> {
+        self.write_log.take().unwrap_or_default()
+    }
+
+    #[inline]
+    fn log_write(&mut self, addr: u64, len: u64) {
+        if let Some(ref mut log) = self.write_log {
+            log.push(addr..addr + len);
+        }
+    }
+
+    /// Resizes the mapping of `old_len` bytes at `addr` to `new_len` bytes like mremap, moving it
+    /// if it can't grow in place and `may_move` is set. Returns the address of the mapping, or -1
+    /// if it couldn't be resized.
+    pub fn mremap(&mut self, addr: u64, old_len: u64, new_len: u64, may_move: bool) -> i64 {
+        let (Some(old_len), Some(new_len)) = (page_align(old_len), page_align(new_len)) else {
+            return -1;
+        };
+        if addr.checked_add(old_len.max(new_len)).is_none() {
+            return -1;
+        }
+
+        if new_len <= old_len {
+            self.munmap(addr + new_len, old_len - new_len);
+            return addr as i64;
+        }
+
+        if !self.reserve_usage(new_len - old_len) {
+            return -1;
+        }
+
+        if self.backend.grow_mapping(addr, old_len, new_len) {
+            self.mappings.grow(addr + old_len, addr + new_len);
+            if let Some(ref mut shadow) = self.shadow {
+                shadow.map(addr + old_len, new_len - old_len);
+            }
+            return addr as i64;
+        }
+
+        if !may_move {
+            return -1;
+        }
+
+        let Ok(data) = self.read_n(addr, old_len) else {
+            return -1;
+        };
+        let new_addr = self.mmap(0, new_len);
+        if new_addr >= 0 && self.write_n(&data, new_addr as u64, old_len).is_ok() {
+            self.munmap(addr, old_len);
+        }
+
+        new_addr
+    }
+    pub fn protect(&mut self, addr: u64, len: u64, prot: u64) -> Result<(), RVError> {
+        let end = range_end(addr, len).ok_or(RVError::SegmentationFault)?;
+        self.backend.protect(addr, len, prot)?;
+        self.mappings.protect(addr, end, prot);
+
+        Ok(())
+    }
+
+    /// Everything mapped in the guest's address space by start address, like /proc/self/maps
+    pub fn mappings(&self) -> Vec<Mapping> {
+        let heap = self.backend.heap();
+        let stack = self.backend.stack();
+
+        let mut mappings: Vec<Mapping> = self.mappings.iter().copied().collect();
+        if !heap.is_empty() {
+            mappings.push(Mapping {
+                start: heap.start,
+                end: heap.end,
+                prot: PROT_READ_WRITE,
+                kind: MappingKind::Heap,
+            });
+        }
+        mappings.push(Mapping {
+            start: stack.start,
+            end: stack.end,
+            prot: PROT_READ_WRITE,
+            kind: MappingKind::Stack,
+        });
+
+        mappings.sort_by_key(|mapping| mapping.start);
+        mappings
+    }
+
+    /// Makes loads and stores in [addr, addr + len) go to `handler` instead of memory, for the
+    /// devices bare-metal programs expect, like a [`Uart`]. Only the guest's own loads and stores
+    /// reach it, syscalls reading or writing the range don't.
+    pub fn map_device(&mut self, addr: u64, len: u64, handler: Arc<dyn MemoryHandler>) {
+        self.devices.insert(addr, addr + len, handler);
+        self.mappings
+            .insert(addr, addr + len, PROT_READ_WRITE, MappingKind::Device);
+    }
+
+    /// Whether anything is mapped in the pages of [addr, addr + len)
+    pub fn is_mapped(&self, addr: u64, len: u64) -> bool {
+        let end = range_end(addr, len).unwrap_or(u64::MAX);
+        self.mappings()
+            .iter()
+            .any(|mapping| mapping.start < end && addr < mapping.end)
+    }
+
+    /// The mapping `addr` belongs to, if any
+    pub fn mapping_at(&self, addr: u64) -> Option<Mapping> {
+        self.mappings()
+            .into_iter()
+            .find(|mapping| mapping.contains(addr))
+    }
+
+    pub fn mmap_file(
+        &mut self,
+        descriptor: &FileDescriptor,
+        addr: u64,
+        offset: u64,
+        len: u64,
+    ) -> Result<i64, RVError> {
+        // TODO: assert offset is multiple of pagesize
+        // the part of the mapping past the end of the file is zero filled
+        let end = (offset.saturating_add(len) as usize).min(descriptor.data.len());
+        let data = &descriptor.data[(offset as usize).min(end)..end];
+
+        let addr_start = self.mmap(addr, len);
+
+        if addr_start >= 0 {
+            self.write_n(data, addr_start as u64, data.len() as u64)?;
+
+            if offset == 0 {
+                self.add_mapped_object(&descriptor.data, addr_start as u64);
+            }
+        }
+
+        Ok(addr_start)
+    }
+
+    /// Marks the page(s) containing the instruction at `addr` as code. Stack pages are never marked.
+    pub fn mark_code(&mut self, addr: u64) {
+        let heap_index = PagedMemory::heap_index(addr);
+        if heap_index == HeapIndex(255) {
+            return;
+        }
+
+        let bits = &mut self.code_pages[heap_index.0 as usize];
+        for page in [
+            PagedMemory::heap_addr(addr) >> PAGE_BITS,
+            PagedMemory::heap_addr(addr + 3) >> PAGE_BITS,
+        ] {
+            let word = (page / 64) as usize;
+            if word >= bits.len() {
+                bits.resize(word + 1, 0);
+            }
+
+            bits[word] |= 1 << (page % 64);
+        }
+    }
+
+    /// Forgets everything decoded from memory, so instructions are fetched again. Stores to code
+    /// pages already do this, fence.i does it for code the caches couldn't see being written.
+    pub fn flush_code(&mut self) {
+        self.code_generation += 1;
+    }
+
+    // bumps code_generation if the write to [addr, addr + len) touches a code page
+    #[inline]
+    fn invalidate_code(&mut self, heap_index: HeapIndex, heap_addr: u64, len: u64) {
+        let bits = &mut self.code_pages[heap_index.0 as usize];
+        if bits.is_empty() {
+            return;
+        }
+
+        for page in (heap_addr >> PAGE_BITS)..=((heap_addr + len.max(1) - 1) >> PAGE_BITS) {
+            if let Some(word) = bits.get_mut((page / 64) as usize) {
+                if *word & (1 << (page % 64)) != 0 {
+                    *word &= !(1 << (page % 64));
+                    self.code_generation += 1;
+                }
+            }
+        }
+    }
+
+    /// Translates addresses through the sv39 page table in `translation.satp` from now on, or
+    /// makes them physical again if `None`. Changing satp flushes the tlb.
+    #[cfg(feature = "mmu")]
+    pub fn set_translation(&mut self, translation: Option<Translation>) {
+        self.mmu.set_translation(translation);
+    }
+
+    #[cfg(feature = "mmu")]
+    pub fn is_translating(&self) -> bool {
+        self.mmu.is_translating()
+    }
+
+    /// Forgets cached translations, for sfence.vma after the page table changed
+    #[cfg(feature = "mmu")]
+    pub fn flush_tlb(&self) {
+        self.mmu.flush();
+    }
+
+    #[cfg(feature = "mmu")]
+    pub fn tlb_stats(&self) -> TlbStats {
+        self.mmu.stats()
+    }
+
+    // the physical address of a `size` byte access at `addr`, which is `addr` unless paging is
+    // enabled
+    #[inline(always)]
+    #[cfg_attr(not(feature = "mmu"), allow(unused_variables))]
+    fn translate(&self, addr: u64, size: u64, kind: AccessKind) -> Result<u64, RVError> {
+        #[cfg(feature = "mmu")]
+        return self.mmu.translate(&self.backend, addr, size, kind);
+
+        #[cfg(not(feature = "mmu"))]
+        Ok(addr)
+    }
+
+    #[inline]
+    pub fn store<T>(&mut self, addr: u64, mut data: T) -> Result<(), RVError> {
+        let addr = self.translate(addr, mem::size_of::<T>() as u64, AccessKind::Write)?;
+
+        if !self.devices.is_empty() {
+            match self.devices.store(addr, data) {
+                Ok(()) => return Ok(()),
+                Err(not_stored) => data = not_stored,
+            }
+        }
+
+        let heap_index = PagedMemory::heap_index(addr);
+
+        if heap_index != HeapIndex(255) {
+            let heap_addr = PagedMemory::heap_addr(addr);
+            self.invalidate_code(heap_index, heap_addr, mem::size_of::<T>() as u64);
+        }
+
+        if let Some(ref mut shadow) = self.shadow {
+            shadow.write(addr, mem::size_of::<T>() as u64);
+        }
+        self.log_write(addr, mem::size_of::<T>() as u64);
+        self.dirty.mark(addr, mem::size_of::<T>() as u64);
+
+        self.backend.store(addr, data)
+    }
+
+    #[inline]
+    pub fn load<T>(&self, addr: u64) -> Result<T, RVError> {
+        let addr = self.translate(addr, mem::size_of::<T>() as u64, AccessKind::Read)?;
+        self.load_physical(addr)
+    }
+
+    /// Loads the instruction at `addr`, whose second half can be on the next page
+    #[cfg(feature = "mmu")]
+    pub fn fetch(&self, addr: u64) -> Result<u32, RVError> {
+        let low: u16 = self.load_physical(self.translate(addr, 2, AccessKind::Execute)?)?;
+        if low & 0b11 != 0b11 {
+            return Ok(low as u32);
+        }
+
+        let high: u16 = self.load_physical(self.translate(addr + 2, 2, AccessKind::Execute)?)?;
+        Ok(low as u32 | (high as u32) << 16)
+    }
+
+    #[inline]
+    fn load_physical<T>(&self, addr: u64) -> Result<T, RVError> {
+        if !self.devices.is_empty() {
+            if let Some(data) = self.devices.load(addr) {
+                return Ok(data);
+            }
+        }
+
+        self.backend.load(addr)
+    }
+
+    /// Writes `s` to `addr`, and zero fills the rest of the `len` bytes. Any bytes of `s` past
+    /// `len` are ignored.
+    pub fn write_n(&mut self, s: &[u8], mut addr: u64, len: u64) -> Result<(), RVError> {
+        let mut data = &s[..s.len().min(len as usize)];
+        let end = addr.checked_add(len).ok_or(RVError::SegmentationFault)?;
+
+        while addr < end {
+            let n = self.contiguous_len(addr, end - addr);
+            let physical = self.translate(addr, n, AccessKind::Write)?;
+            let (chunk, rest) = data.split_at(data.len().min(n as usize));
+
+            self.mark_written(physical, n);
+            self.backend.write(physical, chunk)?;
+            self.backend
+                .zero(physical + chunk.len() as u64, n - chunk.len() as u64)?;
+
+            addr += n;
+            data = rest;
+        }
+
+        Ok(())
+    }
+
+    /// Sets the `len` bytes at `addr` to `byte`, like memset
+    pub fn fill(&mut self, mut addr: u64, len: u64, byte: u8) -> Result<(), RVError> {
+        let end = addr.checked_add(len).ok_or(RVError::SegmentationFault)?;
+
+        while addr < end {
+            let n = self.contiguous_len(addr, end - addr);
+            let physical = self.translate(addr, n, AccessKind::Write)?;
+
+            // the backend checks the range before anything else, which would take forever to
+            // mark if it was huge
+            self.backend.fill(physical, n, byte)?;
+            self.mark_written(physical, n);
+
+            addr += n;
+        }
+
+        Ok(())
+    }
+
+    /// Copies `len` bytes from `src` to `dst` a page at a time, like memcpy
+    pub fn copy(&mut self, mut dst: u64, mut src: u64, len: u64) -> Result<(), RVError> {
+        let mut chunk = [0; PAGE_SIZE as usize];
+        let mut remaining = len;
+
+        while remaining > 0 {
+            let n = remaining.min(PAGE_SIZE);
+            let chunk = &mut chunk[..n as usize];
+            self.read_into(src, chunk)?;
+            self.write_n(chunk, dst, n)?;
+
+            dst = dst.checked_add(n).ok_or(RVError::SegmentationFault)?;
+            src = src.checked_add(n).ok_or(RVError::SegmentationFault)?;
+            remaining -= n;
+        }
+
+        Ok(())
+    }
+
+    /// Reads `len` bytes starting at `addr`.
+    pub fn read_n(&self, mut addr: u64, len: u64) -> Result<Vec<u8>, RVError> {
+        let mut data = Vec::new();
+        let mut remaining = len;
+
+        // grown a page at a time, so a huge len fails once it reaches unmapped memory instead of
+        // allocating it all up front
+        while remaining > 0 {
+            let n = remaining.min(PAGE_SIZE);
+            let start = data.len();
+            data.resize(start + n as usize, 0);
+            self.read_into(addr, &mut data[start..])?;
+
+            addr = addr.checked_add(n).ok_or(RVError::SegmentationFault)?;
+            remaining -= n;
+        }
+
+        Ok(data)
+    }
+
+    // how much of [addr, addr + len) is contiguous in physical memory, which is all of it unless
+    // addresses are translated a page at a time. Sv39 pages are always 4KiB, whatever PAGE_SIZE is.
+    #[cfg_attr(not(feature = "mmu"), allow(unused_variables))]
+    pub(super) fn contiguous_len(&self, addr: u64, len: u64) -> u64 {
+        #[cfg(feature = "mmu")]
+        if self.mmu.is_translating() {
+            return (mmu::PAGE_MASK - (addr & mmu::PAGE_MASK) + 1).min(len);
+        }
+
+        len
+    }
+
+    // keeps code, shadow memory, the write log and dirty pages up to date with a write of `len`
+    // bytes at the physical address `addr`
+    fn mark_written(&mut self, addr: u64, len: u64) {
+        let heap_index = PagedMemory::heap_index(addr);
+        if heap_index != HeapIndex(255) {
+            self.invalidate_code(heap_index, PagedMemory::heap_addr(addr), len);
+        }
+
+        if let Some(ref mut shadow) = self.shadow {
+            shadow.write(addr, len);
+        }
+        self.log_write(addr, len);
+        self.dirty.mark(addr, len);
+    }
+
+    /// [`Memory::read_cstr`], with any bytes that aren't UTF-8 replaced
+    pub fn read_string_n(&mut self, addr: u64, len: u64) -> Result<String, RVError> {
+        let s = self.read_cstr(addr, len)?;
+        Ok(String::from_utf8_lossy(&s).into())
+    }
+
+    pub fn read_file(
+        &mut self,
+        file_descriptor: &mut FileDescriptor,
+        buf: u64,
+        count: u64,
+    ) -> Result<i64, RVError> {
+        let o = file_descriptor.offset as usize;
+        let max = (o + count as usize).min(file_descriptor.data.len());
+
+        let data = &file_descriptor.data[o..max];
+
+        self.write_n(data, buf, data.len() as u64)?;
+
+        file_descriptor.offset += data.len() as u64;
+
+        Ok(data.len() as i64)
+    }
+
+    /// Dumps `lines` lines of 16 bytes, starting at the line containing `addr`, in the format of
+    /// `hexdump -C`. Bytes that aren't mapped are shown as `--`.
+    pub fn hexdump(&self, addr: u64, lines: u64) -> String {
+        let mut writer = String::with_capacity(HEXDUMP_LINE_WIDTH * lines as usize);
+        let mut addr = addr & !0xf;
+
+        for _ in 0..lines {
+            let bytes: Vec<Option<u8>> = (0..16)
+                .map(|i| self.load(addr.wrapping_add(i)).ok())
+                .collect();
+
+            writer.push_str(&format!("{addr:16x} "));
+            for (i, byte) in bytes.iter().enumerate() {
+                if i % 8 == 0 {
+                    writer.push(' ');
+                }
+
+                match byte {
+                    Some(byte) => writer.push_str(&format!("{byte:02x} ")),
+                    None => writer.push_str("-- "),
+                }
+            }
+
+            writer.push_str(" |");
+            for byte in bytes {
+                writer.push(match byte {
+                    Some(c) if c.is_ascii_graphic() || c == b' ' => c as char,
+                    _ => '.',
+                });
+            }
+            writer.push_str("|\n");
+
+            addr = addr.wrapping_add(16);
+        }
+
+        writer
+    }
+}
+
+// the end of the highest loadable segment, relative to where the image is loaded
+fn image_end<'data, E: EndianParse>(elf: &ElfBytes<'data, E>) -> u64 {
+    elf.segments()
+        .unwrap()
+        .iter()
+        .filter(|segment| segment.p_type == PT_LOAD)
+        .map(|segment| segment.p_vaddr + segment.p_memsz)
+        .max()
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::STACK_START;
+
+    const LAYOUTS: [MemoryLayout; 3] = [
+        MemoryLayout::Paged,
+        MemoryLayout::Flat {
+            size: 16 * PAGE_SIZE,
+        },
+        MemoryLayout::Cow,
+    ];
+
+    #[test]
+    fn write_n() -> Result<(), RVError> {
+        let data: Vec<u8> = (0..0x1800).map(|i| i as u8).collect();
+
+        for layout in LAYOUTS {
+            let mut memory = Memory::new(layout);
+            let addr = memory.mmap(0, 0x4000) as u64;
+
+            memory.write_n(&[0xff; 0x4000], addr, 0x4000)?;
+
+            // spans a page boundary, then zero fills past the data
+            memory.write_n(&data, addr + 0x800, 0x3000)?;
+            assert_eq!(memory.load::<u8>(addr + 0x7ff)?, 0xff);
+            assert_eq!(memory.load::<u8>(addr + 0x800)?, 0);
+            assert_eq!(memory.load::<u8>(addr + 0x1805)?, 5);
+            assert_eq!(memory.load::<u8>(addr + 0x2000)?, 0);
+            assert_eq!(memory.load::<u8>(addr + 0x37ff)?, 0);
+            assert_eq!(memory.load::<u8>(addr + 0x3800)?, 0xff);
+
+            // the stack grows to fit
+            memory.write_n(b"hello", STACK_START - 0x1100, 5)?;
+            assert_eq!(memory.read_string_n(STACK_START - 0x1100, 5)?, "hello");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn mremap() -> Result<(), RVError> {
+        const PAGE: usize = PAGE_SIZE as usize;
+
+        for layout in LAYOUTS {
+            let mut memory = Memory::new(layout);
+            let addr = memory.mmap(0, 2 * PAGE_SIZE) as u64;
+            memory.write_n(&[1; 2 * PAGE], addr, 2 * PAGE_SIZE)?;
+
+            // grows in place where the backend can, and moves otherwise
+            let grown = memory.mremap(addr, 2 * PAGE_SIZE, 4 * PAGE_SIZE, true) as u64;
+            assert_eq!(memory.read_n(grown, 2 * PAGE_SIZE)?, [1; 2 * PAGE]);
+            assert_eq!(memory.load::<u8>(grown + 4 * PAGE_SIZE - 1)?, 0);
+
+            // shrinking keeps the mapping where it is
+            assert_eq!(
+                memory.mremap(grown, 4 * PAGE_SIZE, PAGE_SIZE, false),
+                grown as i64
+            );
+            assert_eq!(memory.load::<u8>(grown + PAGE_SIZE - 1)?, 1);
+
+            memory.discard(grown, PAGE_SIZE)?;
+            assert_eq!(memory.load::<u8>(grown)?, 0);
+        }
+
+        // mappings grow in place to exactly the pages asked for
+        for layout in [MemoryLayout::Paged, MemoryLayout::Cow] {
+            let mut memory = Memory::new(layout);
+            let addr = memory.mmap(0, PAGE_SIZE + 1) as u64;
+            assert_eq!(
+                memory.mremap(addr, PAGE_SIZE + 1, 3 * PAGE_SIZE, false),
+                addr as i64
+            );
+            assert_eq!(memory.load::<u8>(addr + 3 * PAGE_SIZE - 1)?, 0);
+            assert!(memory.load::<u8>(addr + 3 * PAGE_SIZE).is_err());
+        }
+
+        // unmapping the end of a region gives its memory back
+        let mut memory = Memory::new(MemoryLayout::Cow);
+        let addr = memory.mmap(0, 4 * PAGE_SIZE) as u64;
+        memory.write_n(&[1; 4 * PAGE], addr, 4 * PAGE_SIZE)?;
+        let before = memory.usage_by_region().mmap;
+        memory.munmap(addr + 2 * PAGE_SIZE, 2 * PAGE_SIZE);
+        assert!(memory.usage_by_region().mmap <= before - 2 * PAGE_SIZE);
+        assert!(memory.load::<u8>(addr + 3 * PAGE_SIZE).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn mappings() -> Result<(), RVError> {
+        for layout in LAYOUTS {
+            let mut memory = Memory::new(layout);
+            let addr = memory.mmap(0, 3 * PAGE_SIZE) as u64;
+            memory.protect(addr, PAGE_SIZE, 1)?;
+            memory.munmap(addr + PAGE_SIZE, PAGE_SIZE);
+
+            let mappings = memory.mappings();
+            let lines: Vec<String> = mappings.iter().map(|m| m.to_string()).collect();
+            assert_eq!(lines.len(), 3, "{lines:?}");
+            assert!(lines[0].ends_with(" r--p mmap"));
+            assert!(lines[1].ends_with(" rw-p mmap"));
+            assert!(lines[2].ends_with(" rw-p [stack]"));
+
+            assert_eq!(
+                (mappings[1].start, mappings[1].end),
+                (addr + 2 * PAGE_SIZE, addr + 3 * PAGE_SIZE)
+            );
+            assert_eq!(
+                memory.mapping_at(STACK_START - 8).map(|m| m.kind),
+                Some(MappingKind::Stack)
+            );
+            assert_eq!(memory.mapping_at(addr + PAGE_SIZE), None);
+            assert!(memory.is_mapped(addr + PAGE_SIZE / 2, PAGE_SIZE));
+            assert!(!memory.is_mapped(addr + PAGE_SIZE, PAGE_SIZE));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn hexdump() {
+        let memory = Memory::from_raw(b"Hello, world!\n\0\x01");
+
+        let dump = memory.hexdump(0x4, 2);
+        let lines: Vec<&str> = dump.lines().collect();
+
+        assert_eq!(
+            lines[0],
+            "               0  48 65 6c 6c 6f 2c 20 77  6f 72 6c 64 21 0a 00 01  |Hello, world!...|"
+        );
+        assert_eq!(lines[0].len(), HEXDUMP_LINE_WIDTH - 1);
+        assert!(lines[1].starts_with("              10  00"));
+
+        assert!(memory
+            .hexdump(0x0500000000000000, 1)
+            .starts_with(" 500000000000000  -- -- -- --"));
+
+        // the last line wraps around instead of overflowing
+        assert_eq!(memory.hexdump(u64::MAX, 2).lines().count(), 2);
+    }
+
+    #[test]
+    fn usage_by_region() -> Result<(), RVError> {
+        const PAGE: usize = PAGE_SIZE as usize;
+
+        for layout in LAYOUTS {
+            let mut memory = Memory::new(layout);
+            let before = memory.usage_by_region();
+
+            let addr = memory.mmap(0, 4 * PAGE_SIZE) as u64;
+            memory.write_n(&[1; 4 * PAGE], addr, 4 * PAGE_SIZE)?;
+
+            let heap = memory.brk(0);
+            memory.brk(heap + 2 * PAGE_SIZE);
+            memory.write_n(&[1; 2 * PAGE], heap, 2 * PAGE_SIZE)?;
+
+            let usage = memory.usage_by_region();
+            assert!(usage.mmap >= before.mmap + 4 * PAGE_SIZE);
+            assert!(usage.heap >= before.heap + 2 * PAGE_SIZE);
+            assert_eq!(usage.program, before.program);
+            assert_eq!(usage.total(), memory.usage());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn memory_limit() {
+        let mut memory = Memory::new(MemoryLayout::Paged);
+        memory.set_memory_limit(Some(memory.usage() + 3 * PAGE_SIZE));
+
+        let heap = memory.brk(0);
+        assert_eq!(memory.brk(heap + 2 * PAGE_SIZE), heap + 2 * PAGE_SIZE);
+        // a failed brk leaves the break where it was
+        assert_eq!(memory.brk(heap + 4 * PAGE_SIZE), heap + 2 * PAGE_SIZE);
+        assert_eq!(memory.mmap(0, 2 * PAGE_SIZE), -1);
+        let addr = memory.mmap(0, PAGE_SIZE);
+        assert!(addr >= 0);
+        assert_eq!(
+            memory.mremap(addr as u64, PAGE_SIZE, 2 * PAGE_SIZE, true),
+            -1
+        );
+
+        assert_eq!(memory.memory_limit_hits(), 3);
+    }
+
+    #[test]
+    fn large_bss() -> Result<(), RVError> {
+        // 4 MiB of bss after a few bytes of data, starting partway through a page
+        const ADDR: u64 = 0x10000 + 64 + 56;
+        const DATA: [u8; 0x100] = [0xaa; 0x100];
+        const MEMSZ: u64 = 0x400000;
+        let elf = data_elf(&DATA, MEMSZ);
+        let image: Arc<[u8]> = elf.clone().into();
+
+        let check = |mut memory: Memory| -> Result<(), RVError> {
+            let end = ADDR + MEMSZ;
+            assert_eq!(memory.load::<u8>(ADDR)?, 0xaa);
+            assert_eq!(memory.load::<u8>(ADDR + 0xff)?, 0xaa);
+            for addr in [
+                ADDR + 0x100,
+                page_align(ADDR).unwrap(),
+                ADDR + MEMSZ / 2,
+                end - 8,
+            ] {
+                assert_eq!(memory.load::<u64>(addr)?, 0);
+            }
+
+            memory.store::<u64>(end - 8, 0x1234)?;
+            assert_eq!(memory.load::<u64>(end - 8)?, 0x1234);
+            let mapping = memory
+                .mappings()
+                .into_iter()
+                .find(|m| m.start == ADDR & !PAGE_MASK);
+            assert_eq!(mapping.map(|m| m.end), page_align(end));
+            Ok(())
+        };
+
+        let layouts = [
+            MemoryLayout::Paged,
+            MemoryLayout::Flat { size: 0x800000 },
+            MemoryLayout::Cow,
+        ];
+        for layout in layouts {
+            check(Memory::load_elf_bytes(&elf, &layout.into())?)?;
+            check(Memory::load_shared_elf(image.clone(), &layout.into())?)?;
+        }
+
+        // file data past p_memsz is ignored
+        let truncated = Memory::load_elf_bytes(&data_elf(&DATA, 0x80), &LoadOptions::default())?;
+        assert_eq!(truncated.load::<u8>(ADDR + 0x7f)?, 0xaa);
+        assert_eq!(truncated.load::<u8>(ADDR + 0x80)?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn load_report() -> Result<(), RVError> {
+        let load = |segments| {
+            Memory::load_elf_bytes(&segments_elf(&[1; 8], segments), &LoadOptions::default())
+        };
+
+        let memory = load(&[(5, 0x10000, 0x1000), (6, 0x11000, 0x1000)])?;
+        assert_eq!(memory.load_report(), &LoadReport::default());
+
+        // the second segment overwrites the end of the first, and is W+X
+        let memory = load(&[(5, 0x10000, 0x2000), (7, 0x11000, 0x1000)])?;
+        let report = memory.load_report();
+        assert_eq!(
+            report.diagnostics,
+            [
+                LoadDiagnostic::WritableExecutable {
+                    kind: MappingKind::Program,
+                    segment: 0x11000..0x12000,
+                },
+                LoadDiagnostic::OverlappingSegments {
+                    kind: MappingKind::Program,
+                    first: 0x10000..0x12000,
+                    second: 0x11000..0x12000,
+                },
+            ]
+        );
+        assert!(report.has_errors());
+        assert_eq!(report.warnings().count(), 1);
+
+        let mut report = LoadReport::default();
+        report.check_dynamic_linker(
+            &[0x8000..0x9000, 0x10000..0x12000],
+            &[0x20000..0x21000, 0x11000..0x13000],
+        );
+        assert_eq!(
+            report.diagnostics,
+            [LoadDiagnostic::OverlapsDynamicLinker {
+                segment: 0x10000..0x12000,
+                linker: 0x11000..0x13000,
+            }]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "sysroot")]
+    fn sysroot() -> Result<(), RVError> {
+        use crate::files::{LD_LINUX_DATA, LIBM_DATA};
+
+        // libm is dynamically linked, and as good a program as any for loading
+        let load = |sysroot: &Sysroot| -> Result<Memory, RVError> {
+            let elf = Memory::parse_elf(LIBM_DATA)?;
+            Ok(Memory::load_elf_with_sysroot(
+                elf,
+                &LoadOptions::default(),
+                sysroot,
+            ))
+        };
+
+        let memory = load(&Sysroot::empty())?;
+        assert_eq!(memory.dynamic_linker, None);
+        assert!(memory
+            .load_report()
+            .diagnostics
+            .contains(&LoadDiagnostic::NoDynamicLinker));
+
+        let mut sysroot = Sysroot::empty();
+        sysroot.add_file(DEFAULT_INTERPRETER, LD_LINUX_DATA);
+        let memory = load(&sysroot)?;
+        assert!(memory.dynamic_linker.is_some());
+        assert!(!memory.load_report().has_errors());
+
+        Ok(())
+    }
+
+    // an executable with a writable segment of `data` followed by zeros up to `memsz` bytes
+    fn data_elf(data: &[u8], memsz: u64) -> Vec<u8> {
+        segments_elf(data, &[(6, 0x10000 + 64 + 56, memsz)])
+    }
+
+    // an executable with a segment of (p_flags, p_vaddr, p_memsz) for each of `segments`, which
+    // all start with `data`
+    fn segments_elf(data: &[u8], segments: &[(u32, u64, u64)]) -> Vec<u8> {
+        let offset = 64 + 56 * segments.len() as u64;
+
+        let mut elf = Vec::new();
+        elf.extend(b"\x7fELF\x02\x01\x01");
+        elf.resize(16, 0);
+        elf.extend(2u16.to_le_bytes()); // executable
+        elf.extend(0xf3u16.to_le_bytes()); // risc-v
+        elf.extend(1u32.to_le_bytes());
+        elf.extend(segments[0].1.to_le_bytes()); // entry
+        elf.extend(64u64.to_le_bytes()); // program headers
+        elf.extend(0u64.to_le_bytes()); // section headers
+        elf.extend(0u32.to_le_bytes());
+        for size in [64u16, 56, segments.len() as u16, 64, 0, 0] {
+            elf.extend(size.to_le_bytes());
+        }
+
+        for &(flags, addr, memsz) in segments {
+            elf.extend(1u32.to_le_bytes()); // PT_LOAD
+            elf.extend(flags.to_le_bytes());
+            let filesz = data.len() as u64;
+            for field in [offset, addr, addr, filesz, memsz, 0x1000] {
+                elf.extend(field.to_le_bytes());
+            }
+        }
+
+        elf.extend(data);
+        elf
+    }
+}
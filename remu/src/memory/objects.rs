@@ -0,0 +1,169 @@
+// the objects in the guest's address space: the program, the dynamic linker, and the shared
+// libraries the dynamic linker maps, with where each was put and the GOT slots of its PLT entries
+
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::ops::Range;
+
+use elf::{
+    abi::{DT_SONAME, ET_DYN, R_RISCV_JUMP_SLOT},
+    endian::{AnyEndian, EndianParse},
+    ElfBytes,
+};
+
+use super::{image_end, Memory};
+
+/// An object mapped into the guest, see [`Memory::loaded_objects`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LoadedObject {
+    /// Its soname, like `libm.so.6`, or `program` for an executable without one
+    pub name: String,
+    pub base: u64,
+    /// The end of its highest segment
+    pub end: u64,
+    /// The symbols it added to the disassembler, 0 if it was stripped
+    pub symbol_count: usize,
+    // where its .plt is, which the GOT slots point to until they're bound
+    pub(crate) plt: Option<Range<u64>>,
+    // the GOT slot of each PLT entry, and the symbol it's bound to
+    pub(crate) plt_slots: Vec<(u64, String)>,
+}
+
+impl LoadedObject {
+    pub fn contains(&self, addr: u64) -> bool {
+        (self.base..self.end).contains(&addr)
+    }
+}
+
+impl Memory {
+    /// The objects mapped into the guest, in the order they were loaded: the program, the dynamic
+    /// linker for dynamically linked programs, then each shared library as the dynamic linker maps
+    /// it
+    pub fn loaded_objects(&self) -> &[LoadedObject] {
+        &self.objects
+    }
+
+    /// The loaded object `addr` is in
+    pub fn object_at(&self, addr: u64) -> Option<&LoadedObject> {
+        self.objects.iter().find(|object| object.contains(addr))
+    }
+
+    // the object whose GOT has a PLT entry's slot at `addr`, and the symbol the slot is for
+    pub(crate) fn plt_slot(&self, addr: u64) -> Option<(&LoadedObject, &str)> {
+        self.objects.iter().find_map(|object| {
+            let slot = object.plt_slots.iter().find(|(slot, _)| *slot == addr)?;
+            Some((object, slot.1.as_str()))
+        })
+    }
+
+    // records `elf` as loaded at `base` and adds its symbols, calling it `fallback_name` if it has
+    // no soname
+    pub(super) fn add_object<T: EndianParse>(
+        &mut self,
+        elf: &ElfBytes<T>,
+        base: u64,
+        fallback_name: &str,
+    ) {
+        let symbol_count = self.disassembler.add_elf_symbols(elf, base);
+        let name = soname(elf).unwrap_or_else(|| fallback_name.to_string());
+        let plt = elf
+            .section_header_by_name(".plt")
+            .ok()
+            .flatten()
+            .map(|plt| base + plt.sh_addr..base + plt.sh_addr + plt.sh_size);
+
+        self.objects.push(LoadedObject {
+            name,
+            base,
+            end: base + image_end(elf),
+            symbol_count,
+            plt,
+            plt_slots: plt_slots(elf, base),
+        });
+    }
+
+    // mapping the start of a shared library is how the dynamic linker loads one, before it maps
+    // the rest of its segments over it
+    pub(super) fn add_mapped_object(&mut self, data: &[u8], base: u64) {
+        if self.objects.iter().any(|object| object.base == base) {
+            return;
+        }
+
+        let Ok(elf) = ElfBytes::<AnyEndian>::minimal_parse(data) else {
+            return;
+        };
+        if elf.ehdr.e_type == ET_DYN {
+            self.add_object(&elf, base, "shared object");
+        }
+    }
+}
+
+fn soname<T: EndianParse>(elf: &ElfBytes<T>) -> Option<String> {
+    let (_, strings) = elf.dynamic_symbol_table().ok()??;
+    let soname = elf
+        .dynamic()
+        .ok()??
+        .iter()
+        .find(|entry| entry.d_tag == DT_SONAME)?;
+
+    strings
+        .get(soname.d_val() as usize)
+        .ok()
+        .map(str::to_string)
+}
+
+// the GOT slots the .rela.plt jump slot relocations fill in, with their symbols
+fn plt_slots<T: EndianParse>(elf: &ElfBytes<T>, base: u64) -> Vec<(u64, String)> {
+    let Ok(Some((symbols, strings))) = elf.dynamic_symbol_table() else {
+        return Vec::new();
+    };
+    let Ok(Some(rela_plt)) = elf.section_header_by_name(".rela.plt") else {
+        return Vec::new();
+    };
+    let Ok(relas) = elf.section_data_as_relas(&rela_plt) else {
+        return Vec::new();
+    };
+
+    relas
+        .filter(|rela| rela.r_type == R_RISCV_JUMP_SLOT)
+        .filter_map(|rela| {
+            let symbol = symbols.get(rela.r_sym as usize).ok()?;
+            let name = strings.get(symbol.st_name as usize).ok()?;
+            Some((base + rela.r_offset, name.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(all(test, feature = "sysroot"))]
+mod tests {
+    use super::*;
+    use crate::files::{FileDescriptor, LIBM_DATA};
+
+    #[test]
+    fn loaded_objects() {
+        let mut memory = Memory::from_raw(&[0; 0x100]);
+        let libm = FileDescriptor::new(LIBM_DATA);
+        let base = memory.mmap_file(&libm, 0, 0, 0x2000).unwrap() as u64;
+        // the dynamic linker maps the later segments over the first mapping
+        memory.mmap_file(&libm, base, 0, 0x1000).unwrap();
+
+        let [object] = memory.loaded_objects() else {
+            panic!("expected libm, found {:?}", memory.loaded_objects());
+        };
+        assert_eq!(object.name, "libm.so.6");
+        assert_eq!(object.base, base);
+        assert!(object.end > base && object.symbol_count > 0);
+        assert_eq!(memory.object_at(object.end - 1), Some(object));
+        assert_eq!(memory.object_at(object.end), None);
+
+        let cos = memory.disassembler.get_symbol_addr("cos").unwrap();
+        assert!(object.contains(cos));
+
+        let (slot, symbol) = object.plt_slots[0].clone();
+        let (found, found_symbol) = memory.plt_slot(slot).unwrap();
+        assert_eq!((found.name.as_str(), found_symbol), ("libm.so.6", &*symbol));
+        assert!(memory.plt_slot(base).is_none());
+    }
+}
@@ -0,0 +1,426 @@
+use alloc::{vec, vec::Vec};
+use core::{
+    mem,
+    ops::{Index, IndexMut, Range},
+};
+
+use crate::{error::RVError, system::STACK_START};
+
+use super::{page_align, MemoryBackend, MemoryUsage, PAGE_SIZE};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct HeapIndex(pub u8);
+
+impl Index<HeapIndex> for [Vec<u8>] {
+    type Output = Vec<u8>;
+    fn index(&self, index: HeapIndex) -> &Self::Output {
+        &self[index.0 as usize]
+    }
+}
+
+impl IndexMut<HeapIndex> for [Vec<u8>] {
+    fn index_mut(&mut self, index: HeapIndex) -> &mut Self::Output {
+        &mut self[index.0 as usize]
+    }
+}
+
+/// The default memory backend. The top byte of an address selects one of 256 independently
+/// growable buffers.
+#[derive(Clone)]
+pub struct PagedMemory {
+    // buffer 0:     program data
+    // buffer 1:     heap
+    // buffer 2:     dynamic linker (if available)
+    // buffer 3-245: mmap regions
+    // buffer 255:   stack
+    buffers: [Vec<u8>; 256],
+
+    // the number of times mmap has been called
+    mmap_count: u64,
+
+    // the sizes of buffers 0-254, kept up to date as they grow. The stack is measured directly.
+    usage: MemoryUsage,
+}
+
+impl Default for PagedMemory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PagedMemory {
+    pub fn new() -> PagedMemory {
+        let mut memory = PagedMemory {
+            buffers: vec![vec![]; 256].try_into().expect("static"),
+            mmap_count: 3,
+            usage: MemoryUsage::default(),
+        };
+
+        // add an initial page to the stack
+        memory.buffers[255].resize(PAGE_SIZE as usize, 0);
+
+        memory
+    }
+
+    // sets a heap size to new_end
+    fn grow_heap(&mut self, new_addr: u64) {
+        let heap_index = Self::heap_index(new_addr);
+        let heap_size = new_addr & 0x00FFFFFFFFFFFFFF;
+        match heap_index.0 {
+            0..=254 => {
+                log::debug!("Growing heap {} to size = {:x}", heap_index.0, heap_size);
+                let old_size = self.buffers[heap_index].len() as u64;
+                self.buffers[heap_index].resize(heap_size as usize, 0);
+                #[cfg(feature = "huge-pages")]
+                advise_huge_pages(&self.buffers[heap_index]);
+
+                let usage = self.usage.region_mut(heap_index.0);
+                *usage = *usage - old_size + heap_size;
+                log::debug!("heap size: {:x}", self.buffers[heap_index].len());
+            }
+            255 => {
+                unimplemented!();
+            }
+        }
+    }
+
+    // grows the stack to contain addr, returning the new end of the stack
+    #[inline]
+    fn grow_stack(buffer: &mut Vec<u8>, addr: u64) -> Result<u64, RVError> {
+        let mut stack_end = STACK_START - buffer.len() as u64;
+
+        while stack_end > addr {
+            // don't resize of bigger than a page
+            if stack_end - addr > PAGE_SIZE {
+                return Err(RVError::SegmentationFault);
+            }
+
+            // resize and shift
+            // manual vec implementation here
+            buffer.extend_from_within(0..buffer.len());
+            #[cfg(feature = "huge-pages")]
+            advise_huge_pages(buffer);
+
+            stack_end = STACK_START - buffer.len() as u64;
+        }
+
+        Ok(stack_end)
+    }
+
+    // the part of a buffer backing [addr, addr + len)
+    fn span_mut(&mut self, addr: u64, len: u64) -> Result<&mut [u8], RVError> {
+        let heap_index = Self::heap_index(addr);
+        let buffer = &mut self.buffers[heap_index];
+
+        let start = if heap_index == HeapIndex(255) {
+            addr - Self::grow_stack(buffer, addr)?
+        } else {
+            Self::heap_addr(addr)
+        };
+
+        match start.checked_add(len) {
+            Some(end) if end <= buffer.len() as u64 => {
+                Ok(&mut buffer[start as usize..end as usize])
+            }
+            _ => Err(RVError::SegmentationFault),
+        }
+    }
+
+    // the part of a buffer backing [addr, addr + len), without growing the stack
+    fn span(&self, addr: u64, len: u64) -> Result<&[u8], RVError> {
+        let heap_index = Self::heap_index(addr);
+        let buffer = &self.buffers[heap_index];
+
+        let start = if heap_index == HeapIndex(255) {
+            addr.checked_sub(STACK_START - buffer.len() as u64)
+                .ok_or(RVError::SegmentationFault)?
+        } else {
+            Self::heap_addr(addr)
+        };
+
+        match start.checked_add(len) {
+            Some(end) if end <= buffer.len() as u64 => Ok(&buffer[start as usize..end as usize]),
+            _ => Err(RVError::SegmentationFault),
+        }
+    }
+
+    /// gets the heap index of a given address
+    pub fn heap_index(addr: u64) -> HeapIndex {
+        HeapIndex((addr >> 56) as u8)
+    }
+
+    /// gets the index into the heap
+    pub fn heap_addr(addr: u64) -> u64 {
+        0x00FFFFFFFFFFFFFF & addr
+    }
+
+    /// returns the end of a heap with a given index
+    fn heap_end(&self, index: HeapIndex) -> u64 {
+        0x0100000000000000 * index.0 as u64 + self.buffers[index].len() as u64
+    }
+}
+
+// asks the host to back a buffer's allocation with huge pages where it covers whole ones, which
+// saves the TLB misses of guests that use a lot of memory. The advice stays with the allocation,
+// but growing a buffer can move it to a new one.
+#[cfg(feature = "huge-pages")]
+fn advise_huge_pages(buffer: &Vec<u8>) {
+    #[cfg(target_os = "linux")]
+    {
+        const HUGE_PAGE: usize = 2 << 20;
+
+        let start = (buffer.as_ptr() as usize).next_multiple_of(HUGE_PAGE);
+        let end = (buffer.as_ptr() as usize + buffer.capacity()) & !(HUGE_PAGE - 1);
+        if start < end {
+            // SAFETY: the range is within the buffer's allocation, and the advice doesn't change
+            // its contents
+            unsafe { libc::madvise(start as *mut libc::c_void, end - start, libc::MADV_HUGEPAGE) };
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = buffer;
+}
+
+impl MemoryBackend for PagedMemory {
+    // returns the number of bytes of memory allocated
+    fn usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            stack: self.buffers[255].len() as u64,
+            ..self.usage
+        }
+    }
+
+    fn brk(&mut self, new_end: u64) -> u64 {
+        // ensure address is within heap bounds
+        let val = new_end >> 56;
+        if val == 1 {
+            self.grow_heap(new_end);
+        }
+
+        return 0x0100000000000000 + self.buffers[1].len() as u64;
+    }
+
+    // an mmap region shrinks when its end is unmapped
+    fn unmap(&mut self, addr: u64, len: u64) -> Result<(), RVError> {
+        let heap_index = Self::heap_index(addr);
+        let end = Self::heap_addr(addr).checked_add(len).and_then(page_align);
+
+        if (3..=254).contains(&heap_index.0)
+            && end.is_some_and(|end| end >= self.buffers[heap_index].len() as u64)
+        {
+            self.grow_heap(addr);
+            return Ok(());
+        }
+
+        self.zero(addr, len)
+    }
+
+    // an mmap region can grow if the mapping is at its end
+    fn grow_mapping(&mut self, addr: u64, old_len: u64, new_len: u64) -> bool {
+        let heap_index = Self::heap_index(addr);
+        let end = Self::heap_addr(addr)
+            .checked_add(old_len)
+            .and_then(page_align);
+        let Some(new_end) = addr.checked_add(new_len).and_then(page_align) else {
+            return false;
+        };
+
+        if !(3..=254).contains(&heap_index.0)
+            || end.is_none_or(|end| end < self.buffers[heap_index].len() as u64)
+            || Self::heap_index(new_end - 1) != heap_index
+        {
+            return false;
+        }
+
+        self.grow_heap(new_end);
+        true
+    }
+
+    fn reserve(&mut self, addr: u64, len: u64) -> Result<(), RVError> {
+        // grows a heap to contain address, if necessary
+        let end = addr.checked_add(len).ok_or(RVError::SegmentationFault)?;
+        let aligned_end = page_align(end).ok_or(RVError::SegmentationFault)?;
+        if self.heap_end(Self::heap_index(end)) < aligned_end {
+            self.grow_heap(aligned_end);
+        }
+
+        Ok(())
+    }
+
+    fn read(&self, addr: u64, buf: &mut [u8]) -> Result<(), RVError> {
+        if !buf.is_empty() {
+            buf.copy_from_slice(self.span(addr, buf.len() as u64)?);
+        }
+
+        Ok(())
+    }
+
+    fn write(&mut self, addr: u64, data: &[u8]) -> Result<(), RVError> {
+        if !data.is_empty() {
+            self.span_mut(addr, data.len() as u64)?
+                .copy_from_slice(data);
+        }
+
+        Ok(())
+    }
+
+    fn zero(&mut self, addr: u64, len: u64) -> Result<(), RVError> {
+        self.fill(addr, len, 0)
+    }
+
+    fn fill(&mut self, addr: u64, len: u64, byte: u8) -> Result<(), RVError> {
+        if len != 0 {
+            self.span_mut(addr, len)?.fill(byte);
+        }
+
+        Ok(())
+    }
+
+    fn dynamic_linker_base(&self, _image_end: u64) -> u64 {
+        self.heap_end(HeapIndex(2))
+    }
+
+    fn heap(&self) -> Range<u64> {
+        0x0100000000000000..self.heap_end(HeapIndex(1))
+    }
+
+    fn stack(&self) -> Range<u64> {
+        STACK_START - (self.buffers[255].len() as u64 - 1)..STACK_START
+    }
+
+    fn map(&mut self, addr: u64, size: u64) -> i64 {
+        log::info!("MMAP REGION: 0x{:x}-0x{:x}", addr, addr.wrapping_add(size));
+
+        // we can only have a maximum of 254 memory mapped regions
+        if self.mmap_count > 254 {
+            return -1;
+        }
+
+        // if the user does not ask for an address, we start a new buffer
+        let new_buffer = addr == 0;
+        let addr = match addr {
+            0 => 0x0100000000000000 * self.mmap_count,
+            addr => addr,
+        };
+        // take note to align to page boundary
+        let Some(end) = addr.checked_add(size).and_then(page_align) else {
+            return -1;
+        };
+
+        if new_buffer {
+            self.mmap_count += 1;
+            self.grow_heap(end);
+
+            addr as i64
+        }
+        // if the user asks for a specific block of memory
+        else {
+            let heap_index = Self::heap_index(addr);
+
+            // only grow the heap of the memory region extends past the current heap end
+            if self.heap_end(heap_index) < end {
+                self.grow_heap(end);
+            }
+
+            // This overwrites the data if the addr specified happens to overlap with an existing
+            // mapping. But this is the _correct_ behavior according to `man 2 mmap`
+            self.zero(addr, end - addr).expect("This shoudl not fail");
+
+            addr as i64
+        }
+    }
+
+    #[inline]
+    fn store<T>(&mut self, addr: u64, data: T) -> Result<(), RVError> {
+        let heap_index = Self::heap_index(addr);
+        let heap_addr = Self::heap_addr(addr);
+
+        let buffer = &mut self.buffers[heap_index];
+
+        if heap_index == HeapIndex(255) {
+            let stack_end = Self::grow_stack(buffer, addr)?;
+
+            unsafe {
+                // SAFETY: if we got to this point the stack has been resized to the proper size already
+                buffer
+                    .as_mut_ptr()
+                    .add((addr - stack_end) as usize)
+                    .cast::<T>()
+                    .write_unaligned(data);
+            }
+
+            Ok(())
+        } else if heap_addr as usize + mem::size_of::<T>() <= buffer.len() {
+            unsafe {
+                // SAFETY: Write is guaranteed to be within buffer bounds
+                buffer
+                    .as_mut_ptr()
+                    .add(heap_addr as usize)
+                    .cast::<T>()
+                    .write_unaligned(data);
+
+                Ok(())
+            }
+        } else {
+            return Err(RVError::SegmentationFault);
+        }
+    }
+
+    #[inline]
+    fn load<T>(&self, addr: u64) -> Result<T, RVError> {
+        let heap_index = Self::heap_index(addr);
+        let heap_addr = Self::heap_addr(addr);
+
+        let buffer = &self.buffers[heap_index];
+
+        if heap_index == HeapIndex(255) {
+            let stack_end = STACK_START - buffer.len() as u64;
+
+            if addr > stack_end {
+                // SAFETY: guaranteed to be on stack
+                unsafe {
+                    return Ok(buffer
+                        .as_ptr()
+                        .add((addr - stack_end) as usize)
+                        .cast::<T>()
+                        .read_unaligned());
+                }
+            } else {
+                return Err(RVError::SegmentationFault);
+            }
+        } else if heap_addr as usize + mem::size_of::<T>() <= buffer.len() {
+            unsafe {
+                // SAFETY: Read is guaranteed to be within buffer bounds
+                return Ok(buffer
+                    .as_ptr()
+                    .add(heap_addr as usize)
+                    .cast::<T>()
+                    .read_unaligned());
+            }
+        } else {
+            return Err(RVError::SegmentationFault);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn large_regions() -> Result<(), RVError> {
+        let mut memory = PagedMemory::new();
+        let addr = memory.map(0, 8 << 20) as u64;
+        memory.store(addr + (6 << 20), 0xabu8)?;
+        memory.brk(0x0100000000000000 + (4 << 20));
+        memory.store(0x0100000000000000 + (3 << 20), 1u8)?;
+        assert_eq!(memory.load::<u8>(addr + (6 << 20))?, 0xab);
+
+        // the stack grows by at most a page past its end
+        memory.store(STACK_START - PAGE_SIZE - 1, 1u8)?;
+        assert!(memory.store(STACK_START - 4 * PAGE_SIZE, 1u8).is_err());
+        memory.store(STACK_START - 3 * PAGE_SIZE, 1u8)?;
+
+        Ok(())
+    }
+}
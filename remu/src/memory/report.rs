@@ -0,0 +1,158 @@
+// problems with the segments of an executable found while loading it, which would otherwise only
+// show up later as the guest crashing or overwriting itself. Overlaps are errors, since whichever
+// segment is mapped last wins, and W+X segments are only warnings.
+
+use alloc::vec::Vec;
+use core::{
+    fmt::{self, Display},
+    ops::Range,
+};
+
+use elf::{
+    abi::{PF_W, PF_X, PT_LOAD},
+    endian::EndianParse,
+    ElfBytes,
+};
+
+use super::MappingKind;
+
+/// Something wrong with the segments of a loaded executable, see [`LoadReport`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LoadDiagnostic {
+    /// Two PT_LOAD segments of the same image share addresses
+    OverlappingSegments {
+        kind: MappingKind,
+        first: Range<u64>,
+        second: Range<u64>,
+    },
+    /// A segment of the program shares addresses with one of the dynamic linker, which can happen
+    /// when its base is chosen with
+    /// [`LoadOptions::interpreter_base`](super::LoadOptions::interpreter_base)
+    OverlapsDynamicLinker {
+        segment: Range<u64>,
+        linker: Range<u64>,
+    },
+    /// A segment is both writable and executable
+    WritableExecutable {
+        kind: MappingKind,
+        segment: Range<u64>,
+    },
+    /// The executable is dynamically linked, but its dynamic linker isn't in the
+    /// [`Sysroot`](crate::system::Sysroot)
+    NoDynamicLinker,
+}
+
+impl LoadDiagnostic {
+    /// Whether the program was loaded wrong, rather than only loaded in a way worth knowing about
+    pub fn is_error(&self) -> bool {
+        !matches!(self, LoadDiagnostic::WritableExecutable { .. })
+    }
+}
+
+impl Display for LoadDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadDiagnostic::OverlappingSegments {
+                kind,
+                first,
+                second,
+            } => write!(
+                f,
+                "{} segments {:x}-{:x} and {:x}-{:x} overlap",
+                kind.name(),
+                first.start,
+                first.end,
+                second.start,
+                second.end
+            ),
+            LoadDiagnostic::OverlapsDynamicLinker { segment, linker } => write!(
+                f,
+                "program segment {:x}-{:x} overlaps the ld.so segment {:x}-{:x}",
+                segment.start, segment.end, linker.start, linker.end
+            ),
+            LoadDiagnostic::WritableExecutable { kind, segment } => write!(
+                f,
+                "{} segment {:x}-{:x} is writable and executable",
+                kind.name(),
+                segment.start,
+                segment.end
+            ),
+            LoadDiagnostic::NoDynamicLinker => write!(
+                f,
+                "the executable is dynamically linked, but the sysroot has no dynamic linker"
+            ),
+        }
+    }
+}
+
+/// What was found while loading an executable, see
+/// [`Memory::load_report`](super::Memory::load_report)
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LoadReport {
+    pub diagnostics: Vec<LoadDiagnostic>,
+}
+
+impl LoadReport {
+    pub fn errors(&self) -> impl Iterator<Item = &LoadDiagnostic> {
+        self.diagnostics.iter().filter(|d| d.is_error())
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &LoadDiagnostic> {
+        self.diagnostics.iter().filter(|d| !d.is_error())
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.errors().next().is_some()
+    }
+
+    // checks the segments of an image loaded at `offset`, returning where they are
+    pub(super) fn check_image<E: EndianParse>(
+        &mut self,
+        kind: MappingKind,
+        offset: u64,
+        elf: &ElfBytes<E>,
+    ) -> Vec<Range<u64>> {
+        let mut segments: Vec<Range<u64>> = Vec::new();
+
+        let loaded = elf.segments().into_iter().flatten();
+        for segment in loaded.filter(|segment| segment.p_type == PT_LOAD && segment.p_memsz > 0) {
+            let start = offset.wrapping_add(segment.p_vaddr);
+            let range = start..start.saturating_add(segment.p_memsz);
+
+            if segment.p_flags & PF_W != 0 && segment.p_flags & PF_X != 0 {
+                self.diagnostics.push(LoadDiagnostic::WritableExecutable {
+                    kind,
+                    segment: range.clone(),
+                });
+            }
+
+            for first in segments.iter().filter(|first| overlap(first, &range)) {
+                self.diagnostics.push(LoadDiagnostic::OverlappingSegments {
+                    kind,
+                    first: first.clone(),
+                    second: range.clone(),
+                });
+            }
+
+            segments.push(range);
+        }
+
+        segments
+    }
+
+    pub(super) fn check_dynamic_linker(&mut self, program: &[Range<u64>], linker: &[Range<u64>]) {
+        for segment in program {
+            for linker in linker.iter().filter(|linker| overlap(segment, linker)) {
+                self.diagnostics
+                    .push(LoadDiagnostic::OverlapsDynamicLinker {
+                        segment: segment.clone(),
+                        linker: linker.clone(),
+                    });
+            }
+        }
+    }
+}
+
+fn overlap(a: &Range<u64>, b: &Range<u64>) -> bool {
+    a.start < b.end && b.start < a.end
+}
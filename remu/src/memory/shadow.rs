@@ -0,0 +1,252 @@
+// the shadow memory behind memcheck. Everything is assumed to be defined, except blocks returned
+// by malloc that haven't been written to yet, and everything is accessible except blocks that
+// were freed and regions that were unmapped.
+
+use alloc::collections::BTreeMap;
+
+use super::{Memory, PAGE_BITS, PAGE_MASK};
+
+/// A memory access memcheck reports, see [`Memory::check_access`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Violation {
+    /// A load of bytes allocated with malloc that were never written
+    UninitializedRead,
+    /// An access to a freed block or an unmapped region
+    UseAfterFree,
+}
+
+impl Violation {
+    pub fn description(self) -> &'static str {
+        match self {
+            Violation::UninitializedRead => "uninitialized read",
+            Violation::UseAfterFree => "use after free",
+        }
+    }
+}
+
+// a bit per byte of a page
+const PAGE_WORDS: usize = (1 << PAGE_BITS) / 64;
+
+#[derive(Clone, Debug, Default)]
+pub(super) struct Shadow {
+    // undefined bytes, by page
+    undefined: BTreeMap<u64, [u64; PAGE_WORDS]>,
+    // live blocks returned by the allocator, start -> len
+    blocks: BTreeMap<u64, u64>,
+    // freed blocks and unmapped regions, start -> end
+    freed: BTreeMap<u64, u64>,
+}
+
+impl Shadow {
+    // failed allocations, which return null, are ignored
+    pub fn allocate(&mut self, addr: u64, len: u64, defined: bool) {
+        if addr == 0 {
+            return;
+        }
+
+        self.blocks.insert(addr, len);
+        self.unfree(addr, len);
+
+        if !defined {
+            self.set_undefined(addr, len, true);
+        }
+    }
+
+    // the bytes copied from the old block were written by realloc itself, so only a grown tail
+    // is left undefined
+    pub fn reallocate(&mut self, old: u64, new: u64, len: u64) {
+        let old_len = self.blocks.get(&old).copied().unwrap_or(0);
+
+        if new != old {
+            self.free(old);
+        }
+
+        self.allocate(new, len, true);
+        if new != 0 && len > old_len {
+            self.set_undefined(new + old_len, len - old_len, true);
+        }
+    }
+
+    /// Marks the block allocated at `addr` as freed. Pointers that weren't allocated are ignored.
+    pub fn free(&mut self, addr: u64) {
+        if let Some(len) = self.blocks.remove(&addr) {
+            self.set_undefined(addr, len, false);
+            self.freed.insert(addr, addr + len);
+        }
+    }
+
+    pub fn map(&mut self, addr: u64, len: u64) {
+        self.unfree(addr, len);
+    }
+
+    pub fn unmap(&mut self, addr: u64, len: u64) {
+        self.unfree(addr, len);
+        self.freed.insert(addr, addr + len);
+    }
+
+    #[inline]
+    pub fn write(&mut self, addr: u64, len: u64) {
+        if !self.undefined.is_empty() {
+            self.set_undefined(addr, len, false);
+        }
+    }
+
+    pub fn check(&self, addr: u64, len: u64, is_load: bool) -> Option<Violation> {
+        let end = addr.saturating_add(len);
+
+        let freed = self
+            .freed
+            .range(..end)
+            .next_back()
+            .is_some_and(|(_, &freed_end)| freed_end > addr);
+        if freed {
+            return Some(Violation::UseAfterFree);
+        }
+
+        let undefined = (addr..end).any(|addr| {
+            self.undefined
+                .get(&(addr >> PAGE_BITS))
+                .is_some_and(|bits| {
+                    let offset = addr & PAGE_MASK;
+                    bits[offset as usize / 64] & 1 << (offset % 64) != 0
+                })
+        });
+        if is_load && undefined {
+            return Some(Violation::UninitializedRead);
+        }
+
+        None
+    }
+
+    fn set_undefined(&mut self, addr: u64, len: u64, undefined: bool) {
+        for addr in addr..addr.saturating_add(len) {
+            let page = addr >> PAGE_BITS;
+            let offset = addr & PAGE_MASK;
+            let bit = 1 << (offset % 64);
+
+            if undefined {
+                let bits = self.undefined.entry(page).or_insert([0; PAGE_WORDS]);
+                bits[offset as usize / 64] |= bit;
+            } else if let Some(bits) = self.undefined.get_mut(&page) {
+                bits[offset as usize / 64] &= !bit;
+            }
+        }
+    }
+
+    // removes [addr, addr + len) from the freed regions, keeping the parts of them around it
+    fn unfree(&mut self, addr: u64, len: u64) {
+        let end = addr.saturating_add(len);
+
+        let overlapping: alloc::vec::Vec<(u64, u64)> = self
+            .freed
+            .range(..end)
+            .rev()
+            .take_while(|(_, &freed_end)| freed_end > addr)
+            .map(|(&start, &freed_end)| (start, freed_end))
+            .collect();
+
+        for (start, freed_end) in overlapping {
+            self.freed.remove(&start);
+
+            if start < addr {
+                self.freed.insert(start, addr);
+            }
+            if freed_end > end {
+                self.freed.insert(end, freed_end);
+            }
+        }
+    }
+}
+
+impl Memory {
+    /// Tracks which bytes are defined and which were freed, so accesses can be checked with
+    /// [`Memory::check_access`]. Enabling it again clears what was tracked. Disabled by default.
+    pub fn set_memcheck_enabled(&mut self, enabled: bool) {
+        self.shadow = enabled.then(Shadow::default);
+    }
+
+    pub fn is_memcheck_enabled(&self) -> bool {
+        self.shadow.is_some()
+    }
+
+    /// Records a block returned by the guest's allocator. Unless `defined`, its bytes are
+    /// undefined until they are written.
+    pub fn allocate(&mut self, addr: u64, len: u64, defined: bool) {
+        if let Some(ref mut shadow) = self.shadow {
+            shadow.allocate(addr, len, defined);
+        }
+    }
+
+    /// Records a successful realloc of the block at `old` to `len` bytes at `new`
+    pub fn reallocate(&mut self, old: u64, new: u64, len: u64) {
+        if let Some(ref mut shadow) = self.shadow {
+            shadow.reallocate(old, new, len);
+        }
+    }
+
+    /// Records that the block allocated at `addr` was freed, see [`Memory::allocate`]
+    pub fn free(&mut self, addr: u64) {
+        if let Some(ref mut shadow) = self.shadow {
+            shadow.free(addr);
+        }
+    }
+
+    /// The violation an access of `len` bytes at `addr` would be, if any. Always `None` when
+    /// memcheck is disabled.
+    pub fn check_access(&self, addr: u64, len: u64, is_load: bool) -> Option<Violation> {
+        self.shadow
+            .as_ref()
+            .and_then(|shadow| shadow.check(addr, len, is_load))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shadow() {
+        let mut shadow = Shadow::default();
+        shadow.allocate(0x1000, 16, false);
+        shadow.allocate(0x2000, 16, true);
+
+        assert_eq!(
+            shadow.check(0x1000, 8, true),
+            Some(Violation::UninitializedRead)
+        );
+        assert_eq!(shadow.check(0x1000, 8, false), None);
+        shadow.write(0x1000, 8);
+        assert_eq!(shadow.check(0x1000, 8, true), None);
+        assert_eq!(
+            shadow.check(0x1004, 8, true),
+            Some(Violation::UninitializedRead)
+        );
+        assert_eq!(shadow.check(0x2000, 16, true), None);
+
+        shadow.free(0x1000);
+        assert_eq!(
+            shadow.check(0x100c, 8, false),
+            Some(Violation::UseAfterFree)
+        );
+        assert_eq!(shadow.check(0x1010, 8, true), None);
+
+        // reusing part of a freed block leaves the rest freed
+        shadow.allocate(0x1000, 8, true);
+        assert_eq!(shadow.check(0x1000, 8, true), None);
+        assert_eq!(shadow.check(0x1008, 1, true), Some(Violation::UseAfterFree));
+
+        shadow.unmap(0x3000, 0x1000);
+        assert_eq!(shadow.check(0x3ff8, 8, true), Some(Violation::UseAfterFree));
+        shadow.map(0x3000, 0x1000);
+        assert_eq!(shadow.check(0x3ff8, 8, true), None);
+
+        // moving a block frees the old one, and a grown tail is undefined
+        shadow.reallocate(0x2000, 0x4000, 32);
+        assert_eq!(shadow.check(0x2000, 1, true), Some(Violation::UseAfterFree));
+        assert_eq!(shadow.check(0x4000, 16, true), None);
+        assert_eq!(
+            shadow.check(0x4010, 1, true),
+            Some(Violation::UninitializedRead)
+        );
+    }
+}
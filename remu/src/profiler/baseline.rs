@@ -0,0 +1,251 @@
+// estimated cycles saved from one run, to catch later runs of the same program getting slower.
+// Estimates don't depend on the host, so any change is a change to the program. Functions are
+// kept by name, since their addresses move between builds.
+//
+// Saved as text, a header and the totals followed by a line per function:
+//
+//     remu baseline 1
+//     total 2020 3
+//     2014 3 1 fib
+
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt::{self, Write};
+
+use super::{FunctionCounts, Profiler};
+use crate::disassembler::Disassembler;
+
+const HEADER: &str = "remu baseline 1";
+
+#[derive(thiserror::Error, Clone, Debug, PartialEq, Eq)]
+pub enum BaselineError {
+    #[error("not a remu baseline")]
+    NotABaseline,
+
+    #[error("line {0} of the baseline is invalid")]
+    InvalidLine(usize),
+}
+
+/// The estimated cycles and cache misses of a run, see [`Profiler::baseline`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Baseline {
+    pub cycles: u64,
+    pub cache_misses: u64,
+    pub functions: BTreeMap<String, FunctionCounts>,
+}
+
+/// A count in a baseline and in the run compared with it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CountDelta {
+    pub baseline: u64,
+    pub current: u64,
+}
+
+impl CountDelta {
+    pub fn change(&self) -> i64 {
+        self.current as i64 - self.baseline as i64
+    }
+
+    /// The change as a percentage of the baseline, which is infinite for something new
+    pub fn percent(&self) -> f64 {
+        match self.baseline {
+            0 if self.current == 0 => 0.0,
+            0 => f64::INFINITY,
+            baseline => self.change() as f64 / baseline as f64 * 100.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FunctionDiff {
+    pub name: String,
+    pub cycles: CountDelta,
+    pub cache_misses: CountDelta,
+}
+
+/// How a run differs from a baseline, see [`Profiler::compare_baseline`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BaselineDiff {
+    pub cycles: CountDelta,
+    pub cache_misses: CountDelta,
+    /// Every function in either run, the largest change in cycles first
+    pub functions: Vec<FunctionDiff>,
+}
+
+impl BaselineDiff {
+    /// Whether the estimated cycles grew by more than `tolerance` percent
+    pub fn regressed(&self, tolerance: f64) -> bool {
+        self.cycles.percent() > tolerance
+    }
+}
+
+impl Baseline {
+    pub fn parse(text: &str) -> Result<Baseline, BaselineError> {
+        let mut lines = text.lines().enumerate();
+        if lines.next().map(|(_, line)| line.trim()) != Some(HEADER) {
+            return Err(BaselineError::NotABaseline);
+        }
+
+        let mut baseline = Baseline::default();
+        for (i, line) in lines {
+            let invalid = BaselineError::InvalidLine(i + 1);
+            let fields: Vec<&str> = line.trim().splitn(4, ' ').collect();
+            let number = |field: &str| field.parse::<u64>().map_err(|_| invalid.clone());
+
+            match fields[..] {
+                [""] => {}
+                ["total", cycles, cache_misses] => {
+                    baseline.cycles = number(cycles)?;
+                    baseline.cache_misses = number(cache_misses)?;
+                }
+                [cycles, cache_misses, calls, name] => {
+                    let counts = FunctionCounts {
+                        calls: number(calls)?,
+                        cycles: number(cycles)?,
+                        cache_misses: number(cache_misses)?,
+                    };
+                    baseline.functions.insert(name.to_string(), counts);
+                }
+                _ => return Err(invalid),
+            }
+        }
+
+        Ok(baseline)
+    }
+
+    /// Writes the baseline to be read back by [`Baseline::parse`]
+    pub fn write(&self, out: &mut impl Write) -> fmt::Result {
+        writeln!(out, "{HEADER}")?;
+        writeln!(out, "total {} {}", self.cycles, self.cache_misses)?;
+        for (name, counts) in &self.functions {
+            writeln!(
+                out,
+                "{} {} {} {name}",
+                counts.cycles, counts.cache_misses, counts.calls
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// How `current` differs from this baseline
+    pub fn compare(&self, current: &Baseline) -> BaselineDiff {
+        let delta = |baseline, current| CountDelta { baseline, current };
+
+        let mut names: Vec<&String> = self.functions.keys().collect();
+        names.extend(current.functions.keys());
+        names.sort_unstable();
+        names.dedup();
+
+        let mut functions: Vec<FunctionDiff> = names
+            .into_iter()
+            .map(|name| {
+                let before = self.functions.get(name).copied().unwrap_or_default();
+                let after = current.functions.get(name).copied().unwrap_or_default();
+                FunctionDiff {
+                    name: name.clone(),
+                    cycles: delta(before.cycles, after.cycles),
+                    cache_misses: delta(before.cache_misses, after.cache_misses),
+                }
+            })
+            .collect();
+        functions.sort_by_key(|function| core::cmp::Reverse(function.cycles.change().abs()));
+
+        BaselineDiff {
+            cycles: delta(self.cycles, current.cycles),
+            cache_misses: delta(self.cache_misses, current.cache_misses),
+            functions,
+        }
+    }
+}
+
+impl Profiler {
+    /// The counts so far, with functions named by the symbols containing them
+    pub fn baseline(&self, disassembler: &Disassembler) -> Baseline {
+        let mut functions: BTreeMap<String, FunctionCounts> = BTreeMap::new();
+        for (addr, counts) in self.functions() {
+            let name = disassembler
+                .get_symbol_containing(addr)
+                .map_or("[unknown]", |(symbol, _)| symbol);
+
+            let total = functions.entry(name.to_string()).or_default();
+            total.calls += counts.calls;
+            total.cycles += counts.cycles;
+            total.cache_misses += counts.cache_misses;
+        }
+
+        Baseline {
+            cycles: self.cycle_count,
+            cache_misses: self.cache_miss_count,
+            functions,
+        }
+    }
+
+    /// Writes [`Profiler::baseline`], to be compared with by a later run
+    pub fn save_baseline(&self, disassembler: &Disassembler, out: &mut impl Write) -> fmt::Result {
+        self.baseline(disassembler).write(out)
+    }
+
+    /// How the counts so far differ from a baseline written by [`Profiler::save_baseline`]
+    pub fn compare_baseline(
+        &self,
+        disassembler: &Disassembler,
+        baseline: &str,
+    ) -> Result<BaselineDiff, BaselineError> {
+        Ok(Baseline::parse(baseline)?.compare(&self.baseline(disassembler)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn baselines() {
+        let mut disassembler = Disassembler::new();
+        disassembler
+            .import_symbols("1000 main\n2000 work\n", 0)
+            .unwrap();
+
+        let mut profiler = Profiler::new();
+        profiler.running = true;
+        profiler.start_function(0x1000);
+        profiler.cycle_count = 10;
+        profiler.trace_call(0x2000, false);
+        profiler.cycle_count = 30;
+        profiler.cache_miss_count = 2;
+
+        let mut saved = String::new();
+        profiler.save_baseline(&disassembler, &mut saved).unwrap();
+        assert_eq!(
+            saved,
+            "remu baseline 1\ntotal 30 2\n10 0 1 main\n20 2 1 work\n"
+        );
+        assert_eq!(
+            Baseline::parse(&saved),
+            Ok(profiler.baseline(&disassembler))
+        );
+
+        // work gets slower
+        profiler.cycle_count = 36;
+        let diff = profiler.compare_baseline(&disassembler, &saved).unwrap();
+        assert_eq!(diff.cycles.percent(), 20.0);
+        assert!(diff.regressed(5.0));
+        assert!(!diff.regressed(25.0));
+        assert_eq!(diff.functions[0].name, "work");
+        assert_eq!(diff.functions[0].cycles.change(), 6);
+        assert_eq!(diff.functions[1].cycles.change(), 0);
+
+        assert_eq!(
+            Baseline::parse("total 1 2"),
+            Err(BaselineError::NotABaseline)
+        );
+        assert_eq!(
+            Baseline::parse("remu baseline 1\ntotal x 2"),
+            Err(BaselineError::InvalidLine(2))
+        );
+    }
+}
@@ -0,0 +1,103 @@
+// the caches the profiler simulates, which only track which lines they hold and not their data
+
+use alloc::{vec, vec::Vec};
+
+// the granularity caches and prefetchers hold memory in
+pub(super) const LINE_SIZE: u64 = 64;
+
+/// The shape of one level of cache, see [`CpuModel`](super::CpuModel)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// In bytes
+    pub size: u64,
+    /// The lines each set holds, which are replaced least recently used first
+    pub ways: u64,
+}
+
+#[derive(Clone, Debug)]
+pub(super) struct CacheLevel {
+    sets: u64,
+    ways: usize,
+    // the line held by each way of each set and when it was last used, allocated on first use
+    // since most emulators never profile
+    lines: Vec<(u64, u64)>,
+    clock: u64,
+}
+
+impl CacheLevel {
+    pub fn new(config: CacheConfig) -> CacheLevel {
+        let ways = config.ways.max(1);
+        CacheLevel {
+            sets: (config.size / LINE_SIZE / ways).max(1),
+            ways: ways as usize,
+            lines: Vec::new(),
+            clock: 0,
+        }
+    }
+
+    pub fn contains(&self, addr: u64) -> bool {
+        let line = addr / LINE_SIZE;
+        !self.lines.is_empty() && self.set(line).iter().any(|&(held, _)| held == line)
+    }
+
+    // whether the line `addr` is in was held, which it is afterwards either way
+    pub fn access(&mut self, addr: u64) -> bool {
+        if self.lines.is_empty() {
+            self.lines = vec![(u64::MAX, 0); self.sets as usize * self.ways];
+        }
+
+        let line = addr / LINE_SIZE;
+        self.clock += 1;
+        let clock = self.clock;
+        let set = self.set_mut(line);
+
+        if let Some(way) = set.iter_mut().find(|(held, _)| *held == line) {
+            way.1 = clock;
+            return true;
+        }
+
+        let lru = set
+            .iter_mut()
+            .min_by_key(|(_, used)| *used)
+            .expect("sets have at least one way");
+        *lru = (line, clock);
+        false
+    }
+
+    fn set(&self, line: u64) -> &[(u64, u64)] {
+        let start = (line % self.sets) as usize * self.ways;
+        &self.lines[start..start + self.ways]
+    }
+
+    fn set_mut(&mut self, line: u64) -> &mut [(u64, u64)] {
+        let start = (line % self.sets) as usize * self.ways;
+        &mut self.lines[start..start + self.ways]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lru() {
+        // two sets of two ways
+        let mut cache = CacheLevel::new(CacheConfig {
+            size: 4 * LINE_SIZE,
+            ways: 2,
+        });
+        let line = |n: u64| n * LINE_SIZE;
+
+        assert!(!cache.contains(0));
+        assert!(!cache.access(line(0)));
+        assert!(cache.access(line(0) + 8));
+        assert!(!cache.access(line(2)));
+        assert!(cache.access(line(0)));
+
+        // line 2 is the least recently used of the first set, so line 4 replaces it
+        assert!(!cache.access(line(4)));
+        assert!(cache.contains(line(0)));
+        assert!(!cache.contains(line(2)));
+        assert!(!cache.contains(line(1)));
+    }
+}
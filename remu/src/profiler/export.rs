@@ -0,0 +1,199 @@
+// writes the profiler's data in the formats of other tools, so their viewers can be used on
+// emulated programs without a converter
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::fmt::{self, Write};
+
+use super::{Profiler, StackSample};
+use crate::disassembler::Disassembler;
+
+// the clock speed virtual cycles are converted to seconds at, the same as the estimate puck prints
+pub(super) const CLOCK_HZ: f64 = 4_000_000_000.0;
+
+impl Profiler {
+    /// Writes the stack samples in the text format of `perf script`, which flamegraph's
+    /// stackcollapse-perf.pl, speedscope and the Firefox profiler can read. `comm` is used as
+    /// both the command and the object name. Times are in virtual cycles, so the profiler has to
+    /// be running while sampling.
+    pub fn export_perf_script(
+        &self,
+        comm: &str,
+        disassembler: &Disassembler,
+        out: &mut impl Write,
+    ) -> fmt::Result {
+        for (sample, period) in self.sample_periods() {
+            let time = sample.cycle_count as f64 / CLOCK_HZ;
+
+            writeln!(out, "{comm} 1/1 [000] {time:.9}: {period} cycles:u:")?;
+
+            for &addr in &sample.stack {
+                match disassembler.get_symbol_containing(addr) {
+                    Some((symbol, offset)) => {
+                        writeln!(out, "\t{addr:16x} {symbol}+{offset:#x} ({comm})")?
+                    }
+                    None => writeln!(out, "\t{addr:16x} [unknown] ({comm})")?,
+                }
+            }
+
+            writeln!(out)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the stack samples as a sampled profile in speedscope's JSON format, which
+    /// <https://www.speedscope.app> opens directly. Frames are whole functions and samples are
+    /// weighted by the virtual cycles since the previous one.
+    pub fn export_speedscope(
+        &self,
+        name: &str,
+        disassembler: &Disassembler,
+        out: &mut impl Write,
+    ) -> fmt::Result {
+        let mut frames: Vec<&str> = Vec::new();
+        let mut frame_indices: BTreeMap<&str, usize> = BTreeMap::new();
+        let mut samples: Vec<Vec<usize>> = Vec::new();
+        let mut weights: Vec<u64> = Vec::new();
+
+        for (sample, period) in self.sample_periods() {
+            // speedscope wants the outermost frame first
+            let stack = sample.stack.iter().rev().map(|&addr| {
+                let function = disassembler
+                    .get_symbol_containing(addr)
+                    .map_or("[unknown]", |(symbol, _)| symbol);
+
+                *frame_indices.entry(function).or_insert_with(|| {
+                    frames.push(function);
+                    frames.len() - 1
+                })
+            });
+
+            samples.push(stack.collect());
+            weights.push(period);
+        }
+
+        write!(
+            out,
+            "{{\"$schema\":\"https://www.speedscope.app/file-format-schema.json\",\
+             \"exporter\":\"remu\",\"name\":"
+        )?;
+        write_json_string(name, out)?;
+
+        write!(out, ",\"shared\":{{\"frames\":[")?;
+        for (i, frame) in frames.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            write!(out, "{separator}{{\"name\":")?;
+            write_json_string(frame, out)?;
+            write!(out, "}}")?;
+        }
+
+        write!(out, "]}},\"profiles\":[{{\"type\":\"sampled\",\"name\":")?;
+        write_json_string(name, out)?;
+        write!(
+            out,
+            ",\"unit\":\"none\",\"startValue\":0,\"endValue\":{},\"samples\":[",
+            weights.iter().sum::<u64>()
+        )?;
+        for (i, stack) in samples.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            write!(out, "{separator}")?;
+            write_json_array(stack, out)?;
+        }
+        write!(out, "],\"weights\":")?;
+        write_json_array(&weights, out)?;
+
+        writeln!(out, "}}]}}")
+    }
+
+    // each sample with the number of cycles since the one before it, at least one so samples
+    // taken while the profiler was stopped still count
+    fn sample_periods(&self) -> impl Iterator<Item = (&StackSample, u64)> {
+        let mut previous_cycles = 0;
+
+        self.samples.iter().map(move |sample| {
+            let period = sample.cycle_count.saturating_sub(previous_cycles).max(1);
+            previous_cycles = sample.cycle_count;
+            (sample, period)
+        })
+    }
+}
+
+pub(crate) fn write_json_string(string: &str, out: &mut impl Write) -> fmt::Result {
+    out.write_char('"')?;
+
+    for c in string.chars() {
+        match c {
+            '"' => out.write_str("\\\"")?,
+            '\\' => out.write_str("\\\\")?,
+            c if c.is_control() => write!(out, "\\u{:04x}", c as u32)?,
+            c => out.write_char(c)?,
+        }
+    }
+
+    out.write_char('"')
+}
+
+fn write_json_array(values: &[impl fmt::Display], out: &mut impl Write) -> fmt::Result {
+    out.write_char('[')?;
+
+    for (i, value) in values.iter().enumerate() {
+        let separator = if i == 0 { "" } else { "," };
+        write!(out, "{separator}{value}")?;
+    }
+
+    out.write_char(']')
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{string::String, vec};
+
+    use super::*;
+    use crate::profiler::StackSample;
+
+    #[test]
+    fn exports() {
+        let mut profiler = Profiler::new();
+        profiler.samples = vec![
+            StackSample {
+                inst_count: 10,
+                cycle_count: 4_000,
+                stack: vec![0x1010, 0x2000],
+            },
+            StackSample {
+                inst_count: 20,
+                cycle_count: 6_000,
+                stack: vec![0x1020],
+            },
+        ];
+
+        let mut out = String::new();
+        profiler
+            .export_perf_script("fib", &Disassembler::new(), &mut out)
+            .unwrap();
+
+        assert_eq!(
+            out,
+            "fib 1/1 [000] 0.000001000: 4000 cycles:u:\n\
+             \t            1010 [unknown] (fib)\n\
+             \t            2000 [unknown] (fib)\n\
+             \n\
+             fib 1/1 [000] 0.000001500: 2000 cycles:u:\n\
+             \t            1020 [unknown] (fib)\n\
+             \n"
+        );
+
+        let mut out = String::new();
+        profiler
+            .export_speedscope("fib \"1\"", &Disassembler::new(), &mut out)
+            .unwrap();
+
+        assert_eq!(
+            out,
+            "{\"$schema\":\"https://www.speedscope.app/file-format-schema.json\",\"exporter\":\"remu\",\
+             \"name\":\"fib \\\"1\\\"\",\"shared\":{\"frames\":[{\"name\":\"[unknown]\"}]},\
+             \"profiles\":[{\"type\":\"sampled\",\"name\":\"fib \\\"1\\\"\",\"unit\":\"none\",\
+             \"startValue\":0,\"endValue\":6000,\"samples\":[[0,0],[0]],\"weights\":[4000,2000]}]}\n"
+        );
+    }
+}
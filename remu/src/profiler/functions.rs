@@ -0,0 +1,127 @@
+// the cycles and cache misses spent in each function, found from the same calls and returns as
+// the trace. A function is only charged for its own instructions, not the ones of the functions
+// it calls.
+
+use alloc::{collections::BTreeMap, vec::Vec};
+
+use super::Profiler;
+
+/// What was spent in a function itself while profiling, see [`Profiler::functions`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FunctionCounts {
+    pub calls: u64,
+    pub cycles: u64,
+    pub cache_misses: u64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub(super) struct CallStack {
+    // the functions that were called into, innermost last
+    callers: Vec<u64>,
+    // the function running now, and the cycle and cache miss counts when it last started running
+    current: u64,
+    since: (u64, u64),
+    counts: BTreeMap<u64, FunctionCounts>,
+}
+
+impl CallStack {
+    // charges the function running now for what was spent since it started running, where `now`
+    // is the current cycle and cache miss counts
+    fn charge(&mut self, now: (u64, u64)) {
+        if now == self.since {
+            return;
+        }
+
+        let counts = self.counts.entry(self.current).or_default();
+        counts.cycles += now.0 - self.since.0;
+        counts.cache_misses += now.1 - self.since.1;
+        self.since = now;
+    }
+
+    fn switch_to(&mut self, addr: u64, now: (u64, u64)) {
+        self.charge(now);
+        self.current = addr;
+        self.counts.entry(addr).or_default().calls += 1;
+    }
+}
+
+impl Profiler {
+    /// The counts of each function by its address. Instructions run before the profiler saw a
+    /// call are counted under 0.
+    pub fn functions(&self) -> BTreeMap<u64, FunctionCounts> {
+        let mut stack = self.call_stack.clone();
+        stack.charge(self.function_counters());
+        stack.counts
+    }
+
+    // makes `addr` the function running now, for when profiling starts in the middle of it
+    pub(crate) fn start_function(&mut self, addr: u64) {
+        let now = self.function_counters();
+        self.call_stack.switch_to(addr, now);
+    }
+
+    pub(super) fn enter_function(&mut self, addr: u64) {
+        if self.running {
+            let now = self.function_counters();
+            let stack = &mut self.call_stack;
+            stack.callers.push(stack.current);
+            stack.switch_to(addr, now);
+        }
+    }
+
+    pub(super) fn exit_function(&mut self) {
+        if self.running {
+            let now = self.function_counters();
+            let stack = &mut self.call_stack;
+            stack.charge(now);
+            // returning from the function profiling started in keeps counting it
+            if let Some(caller) = stack.callers.pop() {
+                stack.current = caller;
+            }
+        }
+    }
+
+    fn function_counters(&self) -> (u64, u64) {
+        (self.cycle_count, self.cache_miss_count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn functions() {
+        let mut profiler = Profiler::new();
+        profiler.running = true;
+        profiler.start_function(0x1000);
+
+        profiler.cycle_count = 10;
+        profiler.trace_call(0x2000, false);
+        profiler.cycle_count = 15;
+        profiler.cache_miss_count = 1;
+        profiler.trace_call(0x2000, false);
+        profiler.cycle_count = 40;
+        profiler.trace_return(false);
+        profiler.trace_return(false);
+        profiler.cycle_count = 42;
+
+        let functions = profiler.functions();
+        assert_eq!(
+            functions[&0x1000],
+            FunctionCounts {
+                calls: 1,
+                cycles: 12,
+                cache_misses: 0,
+            }
+        );
+        assert_eq!(
+            functions[&0x2000],
+            FunctionCounts {
+                calls: 2,
+                cycles: 30,
+                cache_misses: 1,
+            }
+        );
+    }
+}
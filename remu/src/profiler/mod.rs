@@ -0,0 +1,939 @@
+use alloc::{collections::VecDeque, vec::Vec};
+use core::ops::{Add, Range, Sub};
+
+use crate::{
+    cache::Cache,
+    instruction::Inst,
+    register::{FReg, Reg},
+};
+
+mod baseline;
+mod caches;
+mod export;
+mod functions;
+mod trace;
+
+pub use self::baseline::{Baseline, BaselineDiff, BaselineError, CountDelta, FunctionDiff};
+pub use self::caches::CacheConfig;
+pub(crate) use self::export::write_json_string;
+pub use self::functions::FunctionCounts;
+pub use self::trace::{Trace, TraceEvent};
+
+use self::{
+    caches::{CacheLevel, LINE_SIZE},
+    functions::CallStack,
+};
+// prefetched lines are kept until this many newer ones replace them
+const PREFETCH_BUFFER_LINES: usize = 16;
+// entries of the stride prefetcher's table of loads, indexed by pc
+const STRIDE_TABLE_ENTRIES: usize = 16;
+
+/// The guest's call stack at one point in time, see [`Emulator::sample_stacks`]
+///
+/// [`Emulator::sample_stacks`]: crate::system::Emulator::sample_stacks
+#[derive(Clone, Debug)]
+pub struct StackSample {
+    pub inst_count: u64,
+    pub cycle_count: u64,
+    /// The pc followed by the return address of each caller, innermost first
+    pub stack: Vec<u64>,
+}
+
+/// Events a hardware performance monitor counter can be set to count, see the mhpmevent csrs
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HpmEvent {
+    #[default]
+    None,
+    CacheHit,
+    CacheMiss,
+    BranchPredicted,
+    BranchMispredicted,
+}
+
+impl HpmEvent {
+    pub fn from_id(id: u64) -> HpmEvent {
+        match id {
+            1 => HpmEvent::CacheHit,
+            2 => HpmEvent::CacheMiss,
+            3 => HpmEvent::BranchPredicted,
+            4 => HpmEvent::BranchMispredicted,
+            _ => HpmEvent::None,
+        }
+    }
+
+    pub fn id(self) -> u64 {
+        self as u64
+    }
+}
+
+/// The profiler's counters at one point in time, see [`Emulator::profile_snapshot`]. Subtracting
+/// an earlier snapshot gives the counts for the instructions run in between.
+///
+/// [`Emulator::profile_snapshot`]: crate::system::Emulator::profile_snapshot
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProfileSnapshot {
+    pub inst_count: u64,
+    pub cycle_count: u64,
+    pub cache_hit_count: u64,
+    pub cache_miss_count: u64,
+    pub predicted_branch_count: u64,
+    pub mispredicted_branch_count: u64,
+    pub l1i_hit_count: u64,
+    pub l1i_miss_count: u64,
+    pub l2_hit_count: u64,
+    pub l2_miss_count: u64,
+    pub prefetch_count: u64,
+    pub useful_prefetch_count: u64,
+}
+
+impl ProfileSnapshot {
+    /// Instructions per cycle
+    pub fn ipc(&self) -> f64 {
+        ratio(self.inst_count, self.cycle_count)
+    }
+
+    /// The fraction of loads that hit the L1 data cache
+    pub fn cache_hit_rate(&self) -> f64 {
+        ratio(
+            self.cache_hit_count,
+            self.cache_hit_count + self.cache_miss_count,
+        )
+    }
+
+    /// The fraction of instruction fetches that hit the L1 instruction cache
+    pub fn l1i_hit_rate(&self) -> f64 {
+        ratio(self.l1i_hit_count, self.l1i_hit_count + self.l1i_miss_count)
+    }
+
+    /// The fraction of accesses that missed an L1 cache which hit the L2
+    pub fn l2_hit_rate(&self) -> f64 {
+        ratio(self.l2_hit_count, self.l2_hit_count + self.l2_miss_count)
+    }
+
+    /// The fraction of branches that were predicted correctly
+    pub fn branch_prediction_rate(&self) -> f64 {
+        ratio(
+            self.predicted_branch_count,
+            self.predicted_branch_count + self.mispredicted_branch_count,
+        )
+    }
+
+    /// The fraction of prefetched lines that were loaded before being replaced
+    pub fn prefetch_accuracy(&self) -> f64 {
+        ratio(self.useful_prefetch_count, self.prefetch_count)
+    }
+
+    /// The fraction of lines that would have missed the cache which were prefetched instead
+    pub fn prefetch_coverage(&self) -> f64 {
+        ratio(
+            self.useful_prefetch_count,
+            self.useful_prefetch_count + self.cache_miss_count,
+        )
+    }
+}
+
+// zero rather than NaN when nothing was counted
+fn ratio(count: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 / total as f64
+    }
+}
+
+impl Sub for ProfileSnapshot {
+    type Output = ProfileSnapshot;
+
+    // wrapping, since stepping backwards in time goes back to smaller counts
+    fn sub(self, earlier: ProfileSnapshot) -> ProfileSnapshot {
+        ProfileSnapshot {
+            inst_count: self.inst_count.wrapping_sub(earlier.inst_count),
+            cycle_count: self.cycle_count.wrapping_sub(earlier.cycle_count),
+            cache_hit_count: self.cache_hit_count.wrapping_sub(earlier.cache_hit_count),
+            cache_miss_count: self.cache_miss_count.wrapping_sub(earlier.cache_miss_count),
+            predicted_branch_count: self
+                .predicted_branch_count
+                .wrapping_sub(earlier.predicted_branch_count),
+            mispredicted_branch_count: self
+                .mispredicted_branch_count
+                .wrapping_sub(earlier.mispredicted_branch_count),
+            l1i_hit_count: self.l1i_hit_count.wrapping_sub(earlier.l1i_hit_count),
+            l1i_miss_count: self.l1i_miss_count.wrapping_sub(earlier.l1i_miss_count),
+            l2_hit_count: self.l2_hit_count.wrapping_sub(earlier.l2_hit_count),
+            l2_miss_count: self.l2_miss_count.wrapping_sub(earlier.l2_miss_count),
+            prefetch_count: self.prefetch_count.wrapping_sub(earlier.prefetch_count),
+            useful_prefetch_count: self
+                .useful_prefetch_count
+                .wrapping_sub(earlier.useful_prefetch_count),
+        }
+    }
+}
+
+impl Add for ProfileSnapshot {
+    type Output = ProfileSnapshot;
+
+    // the counts of two separate runs together
+    fn add(self, other: ProfileSnapshot) -> ProfileSnapshot {
+        ProfileSnapshot {
+            inst_count: self.inst_count + other.inst_count,
+            cycle_count: self.cycle_count + other.cycle_count,
+            cache_hit_count: self.cache_hit_count + other.cache_hit_count,
+            cache_miss_count: self.cache_miss_count + other.cache_miss_count,
+            predicted_branch_count: self.predicted_branch_count + other.predicted_branch_count,
+            mispredicted_branch_count: self.mispredicted_branch_count
+                + other.mispredicted_branch_count,
+            l1i_hit_count: self.l1i_hit_count + other.l1i_hit_count,
+            l1i_miss_count: self.l1i_miss_count + other.l1i_miss_count,
+            l2_hit_count: self.l2_hit_count + other.l2_hit_count,
+            l2_miss_count: self.l2_miss_count + other.l2_miss_count,
+            prefetch_count: self.prefetch_count + other.prefetch_count,
+            useful_prefetch_count: self.useful_prefetch_count + other.useful_prefetch_count,
+        }
+    }
+}
+
+/// How the profiler predicts which memory is loaded next, see [`CpuModel::prefetcher`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Prefetcher {
+    #[default]
+    None,
+    /// Fetches the line after each one that's loaded
+    NextLine,
+    /// Fetches the next address of each load whose address changed by the same amount twice in
+    /// a row, like the index of a loop over an array
+    Stride,
+}
+
+/// The pipeline the profiler estimates cycles for, see [`Profiler::set_model`]. The defaults
+/// are roughly the U74 cores of the FU740.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CpuModel {
+    /// Issue two independent adjacent instructions per cycle instead of one. The second
+    /// instruction can't read the register the first writes, and only one of them can access
+    /// memory.
+    pub dual_issue: bool,
+    pub l1i: CacheConfig,
+    pub l1d: CacheConfig,
+    /// Shared by both L1s
+    pub l2: CacheConfig,
+    /// Cycles until the value of a load that hits the L1 data cache can be used
+    pub l1d_latency: u64,
+    /// Cycles until the value of a load that misses the L1 but hits the L2 can be used. Fetching
+    /// an instruction that misses the L1 instruction cache stalls the pipeline as long.
+    pub l2_latency: u64,
+    /// The same, for loads and fetches that miss the L2 too
+    pub memory_latency: u64,
+    /// Misses that can be in flight at once. A load that misses while all of them are waits for
+    /// the oldest to finish, so 1 serializes every miss.
+    pub outstanding_misses: usize,
+    /// Stores that can wait to be written to the cache before the pipeline stalls on the next
+    /// one. Loads of an address in the buffer are forwarded from it.
+    pub store_buffer_entries: usize,
+    /// Loads of lines that were prefetched hit the L1 once the prefetch finishes, which takes
+    /// as long as an L1 miss
+    pub prefetcher: Prefetcher,
+}
+
+impl Default for CpuModel {
+    fn default() -> CpuModel {
+        CpuModel {
+            dual_issue: false,
+            l1i: CacheConfig {
+                size: 32 * 1024,
+                ways: 4,
+            },
+            l1d: CacheConfig {
+                size: 32 * 1024,
+                ways: 8,
+            },
+            l2: CacheConfig {
+                size: 2 * 1024 * 1024,
+                ways: 16,
+            },
+            l1d_latency: 3,
+            l2_latency: 20,
+            memory_latency: 200,
+            outstanding_misses: 4,
+            store_buffer_entries: 8,
+            prefetcher: Prefetcher::None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Profiler {
+    x_pipeline_delay: [u64; 32],
+    f_pipeline_delay: [u64; 32],
+
+    pub cycle_count: u64,
+    pub cache_hit_count: u64,
+    pub cache_miss_count: u64,
+    pub mispredicted_branch_count: u64,
+    pub predicted_branch_count: u64,
+    pub l1i_hit_count: u64,
+    pub l1i_miss_count: u64,
+    /// Accesses that missed an L1 cache and hit the L2, and that missed both
+    pub l2_hit_count: u64,
+    pub l2_miss_count: u64,
+    /// Lines fetched by the prefetcher
+    pub prefetch_count: u64,
+    /// Prefetched lines that were loaded before being replaced
+    pub useful_prefetch_count: u64,
+
+    // by default, we assume the branch is not taken.
+    // if the address of the branch instruction is inside
+    // this hashmap, we take the branch
+    branch_predictor: Cache<u64, bool, 100>,
+
+    l1i: CacheLevel,
+    l1d: CacheLevel,
+    l2: CacheLevel,
+    // the cycles the cache misses in flight finish at
+    outstanding_misses: Vec<u64>,
+    // the address of each store waiting to be written and the cycle it's written at, oldest
+    // first
+    store_buffer: VecDeque<(u64, u64)>,
+    prefetched: VecDeque<PrefetchedLine>,
+    // the pc, last address and the difference from the address before it of recent loads
+    stride_table: [(u64, u64, u64); STRIDE_TABLE_ENTRIES],
+
+    pub running: bool,
+    // see `set_ignore_dynamic_linker_instructions`
+    ignore_dynamic_linker_instructions: bool,
+    dynamic_linker: Option<Range<u64>>,
+
+    // see `set_model`
+    model: CpuModel,
+    /// Instructions that issued in the same cycle as the one before them, with dual issue
+    pub paired_count: u64,
+    // whether the cycle the last instruction issued in has a slot left
+    slot_free: bool,
+    // whether the instruction being executed can take that slot, until a stall or dependency
+    // rules it out
+    pairable: bool,
+    // the register written by and whether memory is accessed by the last instruction, and by the
+    // one being executed
+    last_issued: (Option<Reg>, bool),
+    issuing: (Option<Reg>, bool),
+
+    /// Stacks recorded while sampling, oldest first
+    pub samples: Vec<StackSample>,
+
+    /// See [`Profiler::start_trace`]
+    pub trace: Option<Trace>,
+    /// Whether calls are seen per jit block instead of per jal and jalr, set while running with
+    /// the jit, which doesn't execute calls one instruction at a time
+    pub(crate) calls_by_block: bool,
+    // see `functions`
+    call_stack: CallStack,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        let model = CpuModel::default();
+        Profiler {
+            x_pipeline_delay: [0; 32],
+            f_pipeline_delay: [0; 32],
+
+            cycle_count: 0,
+            cache_hit_count: 0,
+            cache_miss_count: 0,
+            mispredicted_branch_count: 0,
+            predicted_branch_count: 0,
+            l1i_hit_count: 0,
+            l1i_miss_count: 0,
+            l2_hit_count: 0,
+            l2_miss_count: 0,
+            prefetch_count: 0,
+            useful_prefetch_count: 0,
+            branch_predictor: Cache::new(),
+            l1i: CacheLevel::new(model.l1i),
+            l1d: CacheLevel::new(model.l1d),
+            l2: CacheLevel::new(model.l2),
+            outstanding_misses: Vec::new(),
+            store_buffer: VecDeque::new(),
+            prefetched: VecDeque::new(),
+            stride_table: [(0, 0, 0); STRIDE_TABLE_ENTRIES],
+            running: false,
+            ignore_dynamic_linker_instructions: true,
+            dynamic_linker: None,
+            model,
+            paired_count: 0,
+            slot_free: false,
+            pairable: false,
+            last_issued: (None, false),
+            issuing: (None, false),
+            samples: Vec::new(),
+            trace: None,
+            calls_by_block: false,
+            call_stack: CallStack::default(),
+        }
+    }
+
+    /// The number of times `event` happened while profiling
+    pub fn event_count(&self, event: HpmEvent) -> u64 {
+        match event {
+            HpmEvent::None => 0,
+            HpmEvent::CacheHit => self.cache_hit_count,
+            HpmEvent::CacheMiss => self.cache_miss_count,
+            HpmEvent::BranchPredicted => self.predicted_branch_count,
+            HpmEvent::BranchMispredicted => self.mispredicted_branch_count,
+        }
+    }
+
+    /// The counters as of now, where `inst_count` is the number of instructions executed so far
+    pub fn snapshot(&self, inst_count: u64) -> ProfileSnapshot {
+        ProfileSnapshot {
+            inst_count,
+            cycle_count: self.cycle_count,
+            cache_hit_count: self.cache_hit_count,
+            cache_miss_count: self.cache_miss_count,
+            predicted_branch_count: self.predicted_branch_count,
+            mispredicted_branch_count: self.mispredicted_branch_count,
+            l1i_hit_count: self.l1i_hit_count,
+            l1i_miss_count: self.l1i_miss_count,
+            l2_hit_count: self.l2_hit_count,
+            l2_miss_count: self.l2_miss_count,
+            prefetch_count: self.prefetch_count,
+            useful_prefetch_count: self.useful_prefetch_count,
+        }
+    }
+
+    /// Estimates cycles for `model` from now on, starting with empty caches if their shape
+    /// changed
+    pub fn set_model(&mut self, model: CpuModel) {
+        if (model.l1i, model.l1d, model.l2) != (self.model.l1i, self.model.l1d, self.model.l2) {
+            self.l1i = CacheLevel::new(model.l1i);
+            self.l1d = CacheLevel::new(model.l1d);
+            self.l2 = CacheLevel::new(model.l2);
+        }
+        self.model = model;
+        self.slot_free = false;
+    }
+
+    pub fn model(&self) -> CpuModel {
+        self.model
+    }
+
+    /// Called before `inst` executes, so it can be paired with the instruction before it
+    #[inline]
+    pub fn issue(&mut self, inst: Inst, pc: u64) {
+        if self.model.dual_issue && self.is_counted(pc) {
+            let memory = accesses_memory(inst);
+            self.issuing = (inst.written_reg().filter(|rd| rd.0 != 0), memory);
+            self.pairable = self.slot_free && !(memory && self.last_issued.1);
+        }
+    }
+
+    pub fn tick(&mut self, pc: u64) {
+        if self.is_counted(pc) {
+            self.fetch(pc);
+
+            if self.pairable {
+                self.paired_count += 1;
+                self.slot_free = false;
+            } else {
+                self.cycle_count += 1;
+                self.slot_free = self.model.dual_issue;
+            }
+
+            self.pairable = false;
+            self.last_issued = self.issuing;
+            self.issuing = (None, false);
+        }
+    }
+
+    /// Charges `cycles` for work done outside of the instruction stream
+    pub fn add_cycles(&mut self, pc: u64, cycles: u64) {
+        if self.is_counted(pc) {
+            self.cycle_count += cycles;
+            self.slot_free = false;
+        }
+    }
+
+    /// Leaves the instructions of the dynamic linker out of the counts, which it does by default
+    pub fn set_ignore_dynamic_linker_instructions(&mut self, ignore: bool) {
+        self.ignore_dynamic_linker_instructions = ignore;
+    }
+
+    // where the dynamic linker is mapped, see `Memory::dynamic_linker`
+    pub(crate) fn set_dynamic_linker(&mut self, range: Option<Range<u64>>) {
+        self.dynamic_linker = range;
+    }
+
+    #[inline]
+    fn is_counted(&self, pc: u64) -> bool {
+        self.running
+            && !(self.ignore_dynamic_linker_instructions
+                && self
+                    .dynamic_linker
+                    .as_ref()
+                    .is_some_and(|range| range.contains(&pc)))
+    }
+
+    #[inline]
+    pub fn pipeline_stall_xx(&mut self, reg1: Reg, reg2: Reg, pc: u64) {
+        if self.is_counted(pc) {
+            self.read_x(reg1);
+            self.read_x(reg2);
+        }
+    }
+
+    #[inline]
+    pub fn pipeline_stall_xf(&mut self, reg1: Reg, reg2: FReg, pc: u64) {
+        if self.is_counted(pc) {
+            self.read_x(reg1);
+            self.stall_until(self.f_pipeline_delay[reg2.0 as usize]);
+        }
+    }
+
+    #[inline]
+    pub fn pipeline_stall_x(&mut self, reg1: Reg, pc: u64) {
+        if self.is_counted(pc) {
+            self.read_x(reg1);
+        }
+    }
+
+    // waits for `reg` to be written, which rules out pairing with the instruction writing it
+    #[inline]
+    fn read_x(&mut self, reg: Reg) {
+        if self.last_issued.0 == Some(reg) {
+            self.pairable = false;
+        }
+        self.stall_until(self.x_pipeline_delay[reg]);
+    }
+
+    #[inline]
+    fn stall_until(&mut self, cycle: u64) {
+        if cycle > self.cycle_count {
+            self.cycle_count = cycle;
+            self.pairable = false;
+            self.slot_free = false;
+        }
+    }
+
+    #[inline]
+    pub fn branch_taken(&mut self, pc: u64) {
+        if self.is_counted(pc) {
+            match self.branch_predictor.update(pc, true) {
+                None | Some(false) => {
+                    // mispredicted branch incurs a 4 cycle penalty
+                    self.mispredicted_branch_count += 1;
+                    self.cycle_count += 4;
+                    self.slot_free = false;
+                }
+                Some(true) => {
+                    self.predicted_branch_count += 1;
+                }
+            }
+        }
+    }
+
+    #[inline]
+    pub fn branch_not_taken(&mut self, pc: u64) {
+        if self.is_counted(pc) {
+            match self.branch_predictor.update(pc, false) {
+                Some(true) => {
+                    // mispredicted branch incurs a 4 cycle penalty
+                    self.mispredicted_branch_count += 1;
+                    self.cycle_count += 4;
+                    self.slot_free = false;
+                }
+                None | Some(false) => {
+                    self.predicted_branch_count += 1;
+                }
+            }
+        }
+    }
+
+    #[inline]
+    pub fn add_delay_x(&mut self, reg: Reg, amount: u64) {
+        self.x_pipeline_delay[reg] = self.cycle_count + amount;
+    }
+
+    pub fn add_load_delay_f(&mut self, rd: FReg, addr: u64, pc: u64) {
+        if self.is_counted(pc) {
+            self.f_pipeline_delay[rd.0 as usize] = self.load(addr, pc);
+        }
+    }
+
+    pub fn add_load_delay_x(&mut self, rd: Reg, addr: u64, pc: u64) {
+        if self.is_counted(pc) {
+            self.x_pipeline_delay[rd] = self.load(addr, pc);
+        }
+    }
+
+    /// Called when a store to `addr` executes, which waits for room in the store buffer
+    pub fn add_store(&mut self, addr: u64, pc: u64) {
+        if !self.is_counted(pc) {
+            return;
+        }
+
+        let now = self.cycle_count;
+        self.store_buffer.retain(|&(_, written)| written > now);
+        if self.store_buffer.len() >= self.model.store_buffer_entries.max(1) {
+            let (_, oldest) = self.store_buffer.pop_front().expect("the buffer is full");
+            self.stall_until(oldest);
+        }
+
+        let written = if self.l1d.access(addr) {
+            self.cycle_count + self.model.l1d_latency
+        } else {
+            let latency = self.l2_access(addr);
+            self.miss(latency)
+        };
+
+        // the buffer is written to the cache in order
+        let previous = self.store_buffer.back().map_or(0, |&(_, written)| written);
+        self.store_buffer.push_back((addr, written.max(previous)));
+    }
+
+    // the cycle the value loaded from `addr` by the instruction at `pc` can be used at
+    fn load(&mut self, addr: u64, pc: u64) -> u64 {
+        let forwarded = self.store_buffer.iter().any(|&(stored, _)| stored == addr);
+        let hit = self.l1d.access(addr) || forwarded;
+
+        let prefetched = if hit { None } else { self.use_prefetched(addr) };
+        self.train_prefetcher(addr, pc);
+
+        if hit {
+            self.cache_hit_count += 1;
+            return self.cycle_count + self.model.l1d_latency;
+        }
+
+        if let Some(ready) = prefetched {
+            self.cache_hit_count += 1;
+            return ready.max(self.cycle_count) + self.model.l1d_latency;
+        }
+
+        self.cache_miss_count += 1;
+        let latency = self.l2_access(addr);
+        let served = self.miss(latency);
+        // the load can't start until there's a free slot for its miss
+        self.stall_until(served - latency);
+        served
+    }
+
+    // stalls the pipeline while the instruction at `pc` is fetched, if it isn't in the L1
+    fn fetch(&mut self, pc: u64) {
+        if self.l1i.access(pc) {
+            self.l1i_hit_count += 1;
+        } else {
+            self.l1i_miss_count += 1;
+            let latency = self.l2_access(pc);
+            self.cycle_count += latency;
+            self.slot_free = false;
+            self.pairable = false;
+        }
+    }
+
+    // the cycles until the line `addr` is in arrives from the L2 or memory, after missing an L1
+    fn l2_access(&mut self, addr: u64) -> u64 {
+        if self.l2.access(addr) {
+            self.l2_hit_count += 1;
+            self.model.l2_latency
+        } else {
+            self.l2_miss_count += 1;
+            self.model.memory_latency
+        }
+    }
+
+    // the cycle the prefetch of the line `addr` is in finishes, if it was prefetched
+    fn use_prefetched(&mut self, addr: u64) -> Option<u64> {
+        let line = self
+            .prefetched
+            .iter_mut()
+            .find(|line| line.line == addr / LINE_SIZE)?;
+
+        if !line.used {
+            line.used = true;
+            self.useful_prefetch_count += 1;
+        }
+        Some(line.ready)
+    }
+
+    fn train_prefetcher(&mut self, addr: u64, pc: u64) {
+        match self.model.prefetcher {
+            Prefetcher::None => {}
+            Prefetcher::NextLine => self.prefetch(addr + LINE_SIZE),
+            Prefetcher::Stride => {
+                let entry = &mut self.stride_table[(pc >> 1) as usize % STRIDE_TABLE_ENTRIES];
+                let (last_pc, last_addr, last_stride) = *entry;
+                let stride = addr.wrapping_sub(last_addr);
+                *entry = (pc, addr, stride);
+
+                if last_pc == pc && stride == last_stride && stride != 0 {
+                    self.prefetch(addr.wrapping_add(stride));
+                }
+            }
+        }
+    }
+
+    // fetches the line `addr` is in, unless it already was or is in the L1
+    fn prefetch(&mut self, addr: u64) {
+        let line = addr / LINE_SIZE;
+        if self.l1d.contains(addr)
+            || self
+                .prefetched
+                .iter()
+                .any(|prefetched| prefetched.line == line)
+        {
+            return;
+        }
+
+        let latency = if self.l2.access(addr) {
+            self.model.l2_latency
+        } else {
+            self.model.memory_latency
+        };
+
+        if self.prefetched.len() >= PREFETCH_BUFFER_LINES {
+            self.prefetched.pop_front();
+        }
+        self.prefetched.push_back(PrefetchedLine {
+            line,
+            ready: self.cycle_count + latency,
+            used: false,
+        });
+        self.prefetch_count += 1;
+    }
+
+    // takes a slot for an L1 miss taking `latency` cycles, which starts now or when the oldest
+    // miss finishes if there are no free ones. Returns the cycle it's served at.
+    fn miss(&mut self, latency: u64) -> u64 {
+        let now = self.cycle_count;
+        self.outstanding_misses.retain(|&finished| finished > now);
+
+        let start = if self.outstanding_misses.len() >= self.model.outstanding_misses.max(1) {
+            let (oldest, &finished) = self
+                .outstanding_misses
+                .iter()
+                .enumerate()
+                .min_by_key(|&(_, &finished)| finished)
+                .expect("every slot is taken");
+            self.outstanding_misses.swap_remove(oldest);
+            finished
+        } else {
+            now
+        };
+
+        let served = start + latency;
+        self.outstanding_misses.push(served);
+        served
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct PrefetchedLine {
+    line: u64,
+    // the cycle the prefetch finishes at
+    ready: u64,
+    // whether it was loaded since, which makes it a useful prefetch
+    used: bool,
+}
+
+// only one instruction per cycle can use the load/store unit
+fn accesses_memory(inst: Inst) -> bool {
+    matches!(
+        inst,
+        Inst::Ld { .. }
+            | Inst::Lw { .. }
+            | Inst::Lwu { .. }
+            | Inst::Lhu { .. }
+            | Inst::Lb { .. }
+            | Inst::Lbu { .. }
+            | Inst::Fld { .. }
+            | Inst::Flw { .. }
+            | Inst::Sd { .. }
+            | Inst::Sw { .. }
+            | Inst::Sh { .. }
+            | Inst::Sb { .. }
+            | Inst::Fsd { .. }
+            | Inst::Fsw { .. }
+            | Inst::Lrw { .. }
+            | Inst::Lrd { .. }
+            | Inst::Scw { .. }
+            | Inst::Scd { .. }
+            | Inst::Amoswapw { .. }
+            | Inst::Amoswapd { .. }
+            | Inst::Amoaddw { .. }
+            | Inst::Amoaddd { .. }
+            | Inst::Amoorw { .. }
+            | Inst::Amomaxuw { .. }
+            | Inst::Amomaxud { .. }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{memory::Memory, system::Emulator};
+
+    #[test]
+    fn snapshots() {
+        let mut profiler = Profiler::new();
+        profiler.cycle_count = 10;
+        profiler.cache_hit_count = 1;
+        let start = profiler.snapshot(5);
+
+        profiler.cycle_count = 30;
+        profiler.cache_hit_count = 4;
+        profiler.cache_miss_count = 1;
+        profiler.predicted_branch_count = 2;
+        profiler.mispredicted_branch_count = 2;
+        let delta = profiler.snapshot(15) - start;
+
+        assert_eq!(delta.inst_count, 10);
+        assert_eq!(delta.cycle_count, 20);
+        assert_eq!(delta.ipc(), 0.5);
+        assert_eq!(delta.cache_hit_rate(), 0.75);
+        assert_eq!(delta.branch_prediction_rate(), 0.5);
+        assert_eq!(ProfileSnapshot::default().ipc(), 0.0);
+    }
+
+    #[test]
+    fn dual_issue() {
+        let mut data = [0u8; 16];
+        data[0..4].copy_from_slice(&0x00100513u32.to_le_bytes()); // li a0, 1
+        data[4..8].copy_from_slice(&0x00200593u32.to_le_bytes()); // li a1, 2
+        data[8..12].copy_from_slice(&0x00b50633u32.to_le_bytes()); // add a2, a0, a1
+        data[12..16].copy_from_slice(&0x00300693u32.to_le_bytes()); // li a3, 3
+
+        let cycles = |dual_issue| {
+            let mut emulator = Emulator::new(Memory::from_raw(&data));
+            emulator.profiler.running = true;
+            emulator.profiler.set_model(CpuModel {
+                dual_issue,
+                ..CpuModel::default()
+            });
+            for _ in 0..4 {
+                emulator.fetch_and_execute().unwrap();
+            }
+            (
+                emulator.profiler.cycle_count,
+                emulator.profiler.paired_count,
+            )
+        };
+
+        // after fetching the line the code is in
+        let fetch = CpuModel::default().memory_latency;
+        assert_eq!(cycles(false), (fetch + 4, 0));
+        // the add depends on the li before it, so it starts a new cycle
+        assert_eq!(cycles(true), (fetch + 2, 2));
+    }
+
+    // a profiler that's running, with the instruction at pc 0 already fetched
+    fn running(model: CpuModel) -> Profiler {
+        let mut profiler = Profiler::new();
+        profiler.running = true;
+        profiler.set_model(model);
+        profiler.tick(0);
+        profiler.cycle_count = 0;
+        profiler.l1i_miss_count = 0;
+        profiler.l2_miss_count = 0;
+        profiler
+    }
+
+    #[test]
+    fn memory_level_parallelism() {
+        let model = CpuModel {
+            memory_latency: 100,
+            outstanding_misses: 2,
+            store_buffer_entries: 2,
+            ..CpuModel::default()
+        };
+
+        // streaming loads into different registers overlap, two at a time
+        let mut profiler = running(model);
+        for (i, addr) in [0x10000, 0x20000, 0x30000, 0x40000].into_iter().enumerate() {
+            profiler.add_load_delay_x(Reg(10 + i as u8), addr, 0);
+            profiler.tick(0);
+        }
+        assert_eq!(profiler.cycle_count, 102);
+        assert_eq!(profiler.cache_miss_count, 4);
+
+        // while each load of a linked list waits for the one before it
+        let mut profiler = running(model);
+        for addr in [0x10000, 0x20000, 0x30000, 0x40000] {
+            profiler.pipeline_stall_x(Reg(10), 0);
+            profiler.add_load_delay_x(Reg(10), addr, 0);
+            profiler.tick(0);
+        }
+        assert_eq!(profiler.cycle_count, 301);
+
+        // stores only stall once the buffer is full, and loads of them are forwarded
+        let mut profiler = running(model);
+        for addr in [0x10000, 0x20000, 0x30000] {
+            profiler.add_store(addr, 0);
+            profiler.tick(0);
+        }
+        assert_eq!(profiler.cycle_count, 101);
+        profiler.add_load_delay_x(Reg(10), 0x30000, 0);
+        assert_eq!(profiler.cache_hit_count, 1);
+    }
+
+    #[test]
+    fn cache_hierarchy() {
+        let model = CpuModel {
+            l1d: CacheConfig {
+                size: 2 * LINE_SIZE,
+                ways: 2,
+            },
+            ..CpuModel::default()
+        };
+        let mut profiler = running(model);
+
+        // three lines don't fit in the L1, so the first is fetched from the L2 again
+        for addr in [0x1000, 0x2000, 0x3000, 0x1000] {
+            profiler.add_load_delay_x(Reg(10), addr, 0);
+        }
+        assert_eq!(profiler.x_pipeline_delay[10], model.l2_latency);
+        let snapshot = profiler.snapshot(0);
+        assert_eq!(
+            (snapshot.cache_hit_count, snapshot.cache_miss_count),
+            (0, 4)
+        );
+        assert_eq!((snapshot.l2_hit_count, snapshot.l2_miss_count), (1, 3));
+
+        // instructions are fetched through their own L1
+        profiler.tick(0x100);
+        profiler.tick(0x104);
+        assert_eq!(profiler.cycle_count, 2 + model.memory_latency);
+        assert_eq!((profiler.l1i_hit_count, profiler.l1i_miss_count), (1, 1));
+    }
+
+    #[test]
+    fn prefetchers() {
+        // loads alternating between two arrays `stride` bytes at a time
+        let run = |prefetcher, stride: u64| {
+            let mut profiler = running(CpuModel {
+                prefetcher,
+                ..CpuModel::default()
+            });
+            for i in 0..64 {
+                profiler.add_load_delay_x(Reg(10), 0x10000 + i * stride, 0x100);
+                profiler.add_load_delay_x(Reg(11), 0x80000 + i * stride, 0x104);
+                profiler.tick(0);
+            }
+            profiler.snapshot(64)
+        };
+
+        // each of the 16 lines misses without a prefetcher, and only the first line of each array
+        // with one
+        assert_eq!(run(Prefetcher::None, 8).cache_miss_count, 16);
+        let next_line = run(Prefetcher::NextLine, 8);
+        assert_eq!(next_line.cache_miss_count, 2);
+        assert_eq!(next_line.useful_prefetch_count, 14);
+        assert_eq!(next_line.prefetch_accuracy(), 14.0 / 16.0);
+        assert_eq!(run(Prefetcher::Stride, 8).cache_miss_count, 2);
+
+        // skipping lines defeats the next line prefetcher, while the stride prefetcher only
+        // misses until the stride of each load is known
+        let next_line = run(Prefetcher::NextLine, 128);
+        assert_eq!(next_line.cache_miss_count, 128);
+        assert_eq!(next_line.prefetch_accuracy(), 0.0);
+        let stride = run(Prefetcher::Stride, 128);
+        assert_eq!(stride.cache_miss_count, 6);
+        assert_eq!(stride.prefetch_coverage(), 122.0 / 128.0);
+    }
+}
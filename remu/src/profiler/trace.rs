@@ -0,0 +1,219 @@
+// a timeline of function calls, syscalls and jit compilations, on the same virtual cycles as the
+// rest of the profiler. Written out as a chrome trace, which Perfetto and about://tracing open.
+
+use alloc::{collections::BTreeMap, format, vec::Vec};
+use core::fmt::{self, Write};
+
+use super::{
+    export::{write_json_string, CLOCK_HZ},
+    Profiler,
+};
+use crate::{disassembler::Disassembler, system::Syscall};
+
+// the trace's threads, guest code and the jit compiler
+const GUEST_TID: u8 = 1;
+const JIT_TID: u8 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceEvent {
+    /// A call to the function at `addr`
+    Enter { cycle: u64, addr: u64 },
+    /// A return from the innermost function
+    Exit { cycle: u64 },
+    /// Syscalls take no virtual time, so they are instants rather than spans
+    Syscall { cycle: u64, syscall: Syscall },
+    /// The function at `addr` being compiled by the jit, from when it was queued until it was
+    /// first run compiled
+    JitCompile { start: u64, end: u64, addr: u64 },
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Trace {
+    pub events: Vec<TraceEvent>,
+    // functions queued for compilation, and the cycle they were queued at
+    pending_compiles: BTreeMap<u64, u64>,
+}
+
+impl Profiler {
+    /// Starts recording a [`Trace`], replacing any earlier one. Cycles are only counted while
+    /// the profiler is running, so it should be running too.
+    pub fn start_trace(&mut self) {
+        self.trace = Some(Trace::default());
+    }
+
+    // a call, which is ignored unless it was seen the way `calls_by_block` says calls are
+    pub(crate) fn trace_call(&mut self, addr: u64, by_block: bool) {
+        if by_block != self.calls_by_block {
+            return;
+        }
+
+        let cycle = self.cycle_count;
+        if let Some(ref mut trace) = self.trace {
+            trace.events.push(TraceEvent::Enter { cycle, addr });
+        }
+        self.enter_function(addr);
+    }
+
+    pub(crate) fn trace_return(&mut self, by_block: bool) {
+        if by_block != self.calls_by_block {
+            return;
+        }
+
+        let cycle = self.cycle_count;
+        if let Some(ref mut trace) = self.trace {
+            trace.events.push(TraceEvent::Exit { cycle });
+        }
+        self.exit_function();
+    }
+
+    pub(crate) fn trace_syscall(&mut self, syscall: Syscall) {
+        let cycle = self.cycle_count;
+
+        if let Some(ref mut trace) = self.trace {
+            trace.events.push(TraceEvent::Syscall { cycle, syscall });
+        }
+    }
+
+    pub(crate) fn trace_jit_queued(&mut self, addr: u64) {
+        let cycle = self.cycle_count;
+
+        if let Some(ref mut trace) = self.trace {
+            trace.pending_compiles.insert(addr, cycle);
+        }
+    }
+
+    pub(crate) fn trace_jit_ready(&mut self, addr: u64) {
+        let end = self.cycle_count;
+
+        if let Some(ref mut trace) = self.trace {
+            if let Some(start) = trace.pending_compiles.remove(&addr) {
+                trace
+                    .events
+                    .push(TraceEvent::JitCompile { start, end, addr });
+            }
+        }
+    }
+
+    /// Writes the trace in chrome's trace event format. Functions and syscalls are on one
+    /// thread and jit compilations on another, timed like
+    /// [`Profiler::export_perf_script`].
+    pub fn export_chrome_trace(
+        &self,
+        disassembler: &Disassembler,
+        out: &mut impl Write,
+    ) -> fmt::Result {
+        let events = self.trace.as_ref().map_or(&[][..], |trace| &trace.events);
+        let function = |addr: u64| {
+            disassembler
+                .get_symbol_containing(addr)
+                .map_or("[unknown]", |(symbol, _)| symbol)
+        };
+        // in microseconds
+        let ts = |cycle: u64| cycle as f64 * 1_000_000.0 / CLOCK_HZ;
+
+        write!(
+            out,
+            "{{\"displayTimeUnit\":\"ns\",\"traceEvents\":[\
+             {{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":{GUEST_TID},\"args\":{{\"name\":\"guest\"}}}},\
+             {{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":{JIT_TID},\"args\":{{\"name\":\"jit\"}}}}"
+        )?;
+
+        for event in events {
+            match *event {
+                TraceEvent::Enter { cycle, addr } => {
+                    write!(out, ",\n{{\"name\":")?;
+                    write_json_string(function(addr), out)?;
+                    write!(
+                        out,
+                        ",\"cat\":\"function\",\"ph\":\"B\",\"ts\":{},\"pid\":1,\"tid\":{GUEST_TID}}}",
+                        ts(cycle)
+                    )?;
+                }
+                TraceEvent::Exit { cycle } => write!(
+                    out,
+                    ",\n{{\"ph\":\"E\",\"ts\":{},\"pid\":1,\"tid\":{GUEST_TID}}}",
+                    ts(cycle)
+                )?,
+                TraceEvent::Syscall { cycle, syscall } => {
+                    let name = format!("{syscall:?}").to_lowercase();
+                    write!(
+                        out,
+                        ",\n{{\"name\":\"{name}\",\"cat\":\"syscall\",\"ph\":\"i\",\"s\":\"t\",\"ts\":{},\"pid\":1,\"tid\":{GUEST_TID}}}",
+                        ts(cycle)
+                    )?;
+                }
+                TraceEvent::JitCompile { start, end, addr } => {
+                    write!(out, ",\n{{\"name\":")?;
+                    write_json_string(function(addr), out)?;
+                    write!(
+                        out,
+                        ",\"cat\":\"jit\",\"ph\":\"X\",\"ts\":{},\"dur\":{},\"pid\":1,\"tid\":{JIT_TID}}}",
+                        ts(start),
+                        ts(end - start)
+                    )?;
+                }
+            }
+        }
+
+        writeln!(out, "\n]}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::String;
+
+    use super::*;
+
+    #[test]
+    fn chrome_trace() {
+        let mut profiler = Profiler::new();
+        profiler.start_trace();
+
+        profiler.trace_call(0x1000, false);
+        profiler.cycle_count = 4_000;
+        // calls made through blocks aren't recorded without the jit
+        profiler.trace_call(0x2000, true);
+        profiler.trace_syscall(Syscall::Write);
+        profiler.trace_jit_queued(0x1000);
+        profiler.cycle_count = 6_000;
+        profiler.trace_jit_ready(0x1000);
+        profiler.trace_jit_ready(0x1000);
+        profiler.trace_return(false);
+
+        let trace = profiler.trace.as_ref().unwrap();
+        assert_eq!(
+            trace.events,
+            [
+                TraceEvent::Enter {
+                    cycle: 0,
+                    addr: 0x1000
+                },
+                TraceEvent::Syscall {
+                    cycle: 4_000,
+                    syscall: Syscall::Write
+                },
+                TraceEvent::JitCompile {
+                    start: 4_000,
+                    end: 6_000,
+                    addr: 0x1000
+                },
+                TraceEvent::Exit { cycle: 6_000 },
+            ]
+        );
+
+        let mut out = String::new();
+        profiler
+            .export_chrome_trace(&Disassembler::new(), &mut out)
+            .unwrap();
+
+        assert!(out.contains(
+            "{\"name\":\"[unknown]\",\"cat\":\"function\",\"ph\":\"B\",\"ts\":0,\"pid\":1,\"tid\":1}"
+        ));
+        assert!(out.contains("\"name\":\"write\",\"cat\":\"syscall\",\"ph\":\"i\""));
+        assert!(
+            out.contains("\"cat\":\"jit\",\"ph\":\"X\",\"ts\":1,\"dur\":0.5,\"pid\":1,\"tid\":2")
+        );
+        assert!(out.ends_with("{\"ph\":\"E\",\"ts\":1.5,\"pid\":1,\"tid\":1}\n]}\n"));
+    }
+}
@@ -1,6 +1,7 @@
 #![allow(unused)]
 
-use std::{
+use alloc::format;
+use core::{
     fmt::Display,
     ops::{Index, IndexMut},
 };
@@ -52,7 +53,7 @@ impl IndexMut<FReg> for [f64] {
 }
 
 impl Display for Reg {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         let s = match self.0 {
             0 => "x0",
             1 => "ra",
@@ -94,7 +95,7 @@ impl Display for Reg {
 }
 
 impl Display for FReg {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         let s = match self.0 {
             0 => "ft0",
             1 => "ft1",
@@ -135,6 +136,30 @@ impl Display for FReg {
     }
 }
 
+impl Reg {
+    /// The register with an abi name like `a0`, `zero` or `fp`, or an x name like `x10`
+    pub fn parse(name: &str) -> Option<Reg> {
+        match name {
+            "zero" => return Some(Reg(0)),
+            "fp" => return Some(S0),
+            _ => {}
+        }
+
+        (0..32)
+            .map(Reg)
+            .find(|reg| format!("{reg}") == name || format!("x{}", reg.0) == name)
+    }
+}
+
+impl FReg {
+    /// The register with an abi name like `fa0`, or an f name like `f10`
+    pub fn parse(name: &str) -> Option<FReg> {
+        (0..32)
+            .map(FReg)
+            .find(|reg| format!("{reg}") == name || format!("f{}", reg.0) == name)
+    }
+}
+
 pub const RA: Reg = Reg(1);
 pub const SP: Reg = Reg(2);
 pub const S0: Reg = Reg(8);
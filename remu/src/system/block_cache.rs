@@ -0,0 +1,81 @@
+use alloc::{sync::Arc, vec, vec::Vec};
+
+use crate::{ir::Op, memory::Memory};
+
+const BLOCK_CACHE_BITS: u64 = 12;
+const BLOCK_CACHE_SIZE: usize = 1 << BLOCK_CACHE_BITS;
+
+// blocks are cut off after this many instructions even without a branch, so a straight line of
+// code doesn't get decoded all at once
+pub const MAX_BLOCK_LEN: usize = 64;
+
+/// A pre-decoded basic block. Only the last op can transfer control.
+pub type Block = Arc<[Op]>;
+
+/// Direct-mapped cache of pre-decoded basic blocks, indexed by their starting pc.
+///
+/// Invalidation works exactly like [`InstCache`](super::inst_cache::InstCache): every page a block
+/// was decoded from is marked as code, and the whole cache is flushed when one of them is written.
+pub struct BlockCache {
+    // (start pc, block), allocated on first use
+    entries: Vec<Option<(u64, Block)>>,
+    generation: u64,
+}
+
+impl BlockCache {
+    pub fn new() -> BlockCache {
+        BlockCache {
+            entries: Vec::new(),
+            generation: 0,
+        }
+    }
+
+    #[inline]
+    fn index(pc: u64) -> usize {
+        ((pc >> 1) & (BLOCK_CACHE_SIZE as u64 - 1)) as usize
+    }
+
+    #[inline]
+    pub fn get(&mut self, pc: u64, memory: &Memory) -> Option<Block> {
+        if self.generation != memory.code_generation {
+            self.invalidate();
+            self.generation = memory.code_generation;
+            return None;
+        }
+
+        match self.entries.get(Self::index(pc)) {
+            Some(Some((start, block))) if *start == pc => Some(block.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn insert(&mut self, pc: u64, block: Block, memory: &mut Memory) {
+        if self.entries.is_empty() {
+            self.entries = vec![None; BLOCK_CACHE_SIZE];
+        }
+
+        let mut inst_pc = pc;
+        for op in block.iter() {
+            // ops can span two instructions, and so two pages
+            memory.mark_code(inst_pc);
+            memory.mark_code(inst_pc + op.len() as u64 - 1);
+            inst_pc += op.len() as u64;
+        }
+
+        self.generation = memory.code_generation;
+        self.entries[Self::index(pc)] = Some((pc, block));
+    }
+
+    pub fn invalidate(&mut self) {
+        for entry in &mut self.entries {
+            *entry = None;
+        }
+    }
+}
+
+// see the Clone impl of InstCache
+impl Clone for BlockCache {
+    fn clone(&self) -> Self {
+        BlockCache::new()
+    }
+}
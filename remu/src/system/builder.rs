@@ -0,0 +1,427 @@
+// configuring an emulator before it starts, for what has to be known when the stack is set up or
+// that depends on the program being loaded first
+
+use alloc::{
+    boxed::Box,
+    string::{String, ToString},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+#[cfg(feature = "std")]
+use std::path::Path;
+
+use super::{
+    Emulator, ExitHook, FaultPlan, InterceptHandler, MachineIdentity, OutputLimit, SandboxPolicy,
+    StraceSink, SyscallHandler, DEFAULT_PROGRAM_NAME,
+};
+use crate::{
+    auxvec::AuxvConfig,
+    error::RVError,
+    files::{FdTable, FileDescriptor, OpenFile, Sysroot},
+    memory::{LoadOptions, Memory},
+    profiler::CpuModel,
+};
+
+// where the program comes from
+enum Program {
+    Memory(Box<Memory>),
+    Elf(Arc<[u8]>),
+}
+
+/// Builds an [`Emulator`] with everything set up before its first instruction, in whatever order
+/// it's configured in
+pub struct EmulatorBuilder {
+    program: Program,
+    load_options: LoadOptions,
+    sysroot: Sysroot,
+    auxv: AuxvConfig,
+    identity: MachineIdentity,
+    args: Vec<String>,
+    env: Vec<String>,
+    fds: Option<FdTable>,
+    stdin: Option<Arc<[u8]>>,
+    model: CpuModel,
+    hle: bool,
+    memcheck: bool,
+    frame_checking: bool,
+    heap_profiling: bool,
+    exit_summary: bool,
+    output_limit: Option<OutputLimit>,
+    memory_limit: Option<u64>,
+    sandbox: Option<SandboxPolicy>,
+    strace: Option<StraceSink>,
+    clock_rate: Option<u64>,
+    #[cfg(feature = "std")]
+    real_time_pacing: bool,
+    profile_label: Option<String>,
+    exit_hooks: Vec<ExitHook>,
+    syscall_handlers: Vec<(u64, Arc<dyn SyscallHandler>)>,
+    intercepts: Vec<(String, Arc<dyn InterceptHandler>)>,
+    fault_plan: Option<FaultPlan>,
+    fuel: Option<u64>,
+}
+
+impl EmulatorBuilder {
+    /// Runs the program already loaded into `memory`, which ignores [`EmulatorBuilder::load_options`]
+    pub fn new(memory: Memory) -> EmulatorBuilder {
+        EmulatorBuilder::with_program(Program::Memory(Box::new(memory)))
+    }
+
+    /// Runs the 64-bit RISC-V executable `data`, which is loaded when the emulator is built
+    pub fn from_elf(data: impl Into<Arc<[u8]>>) -> EmulatorBuilder {
+        EmulatorBuilder::with_program(Program::Elf(data.into()))
+    }
+
+    /// Runs the executable at `path` on the host
+    #[cfg(feature = "std")]
+    pub fn from_path(path: impl AsRef<Path>) -> std::io::Result<EmulatorBuilder> {
+        Ok(EmulatorBuilder::from_elf(std::fs::read(path)?))
+    }
+
+    fn with_program(program: Program) -> EmulatorBuilder {
+        EmulatorBuilder {
+            program,
+            load_options: LoadOptions::default(),
+            sysroot: Sysroot::default(),
+            auxv: AuxvConfig::default(),
+            identity: MachineIdentity::default(),
+            args: vec![DEFAULT_PROGRAM_NAME.to_string()],
+            env: Vec::new(),
+            fds: None,
+            stdin: None,
+            model: CpuModel::default(),
+            hle: false,
+            memcheck: false,
+            frame_checking: false,
+            heap_profiling: false,
+            exit_summary: false,
+            output_limit: None,
+            memory_limit: None,
+            sandbox: None,
+            strace: None,
+            clock_rate: None,
+            #[cfg(feature = "std")]
+            real_time_pacing: false,
+            profile_label: None,
+            exit_hooks: Vec::new(),
+            syscall_handlers: Vec::new(),
+            intercepts: Vec::new(),
+            fault_plan: None,
+            fuel: None,
+        }
+    }
+
+    /// Where the executable is loaded, see [`Memory::load_elf_with_options`]
+    pub fn load_options(mut self, options: LoadOptions) -> Self {
+        self.load_options = options;
+        self
+    }
+
+    /// Where a dynamically linked executable gets its dynamic linker and shared libraries. See
+    /// [`Emulator::sysroot`]
+    pub fn sysroot(mut self, sysroot: Sysroot) -> Self {
+        self.sysroot = sysroot;
+        self
+    }
+
+    /// The auxiliary vector, so guests that detect features at runtime can be shown a different
+    /// machine
+    pub fn auxv(mut self, auxv: AuxvConfig) -> Self {
+        self.auxv = auxv;
+        self
+    }
+
+    /// See [`Emulator::set_machine_identity`]
+    pub fn machine_identity(mut self, identity: MachineIdentity) -> Self {
+        self.identity = identity;
+        self
+    }
+
+    /// The guest's argv, starting with the program's name. By default it's only `/prog`.
+    pub fn args<S: Into<String>>(mut self, args: impl IntoIterator<Item = S>) -> Self {
+        self.args = args.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// The guest's environment, of `NAME=value` strings. Empty by default.
+    pub fn env<S: Into<String>>(mut self, env: impl IntoIterator<Item = S>) -> Self {
+        self.env = env.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Starts the guest with the file descriptors in `fds` open, instead of only stdout and
+    /// stderr, like a test harness passing extra descriptors to a child process
+    pub fn fds(mut self, fds: FdTable) -> Self {
+        self.fds = Some(fds);
+        self
+    }
+
+    /// Makes `data` the guest's standard input, replacing file descriptor 0 of
+    /// [`EmulatorBuilder::fds`]. It isn't copied if it's already an `Arc`, so the same input can
+    /// be given to many emulators without copying it for each.
+    pub fn stdin(mut self, data: impl Into<Arc<[u8]>>) -> Self {
+        self.stdin = Some(data.into());
+        self
+    }
+
+    /// See [`Profiler::set_model`](crate::profiler::Profiler::set_model)
+    pub fn cpu_model(mut self, model: CpuModel) -> Self {
+        self.model = model;
+        self
+    }
+
+    /// See [`Emulator::set_hle_enabled`]
+    pub fn hle(mut self, enabled: bool) -> Self {
+        self.hle = enabled;
+        self
+    }
+
+    /// See [`Emulator::set_memcheck_enabled`]
+    pub fn memcheck(mut self, enabled: bool) -> Self {
+        self.memcheck = enabled;
+        self
+    }
+
+    /// See [`Emulator::set_frame_checking_enabled`]
+    pub fn frame_checking(mut self, enabled: bool) -> Self {
+        self.frame_checking = enabled;
+        self
+    }
+
+    /// See [`Emulator::set_heap_profiling_enabled`]
+    pub fn heap_profiling(mut self, enabled: bool) -> Self {
+        self.heap_profiling = enabled;
+        self
+    }
+
+    /// See [`Emulator::set_exit_summary_enabled`]
+    pub fn exit_summary(mut self, enabled: bool) -> Self {
+        self.exit_summary = enabled;
+        self
+    }
+
+    /// See [`Emulator::set_output_limit`]
+    pub fn output_limit(mut self, limit: OutputLimit) -> Self {
+        self.output_limit = Some(limit);
+        self
+    }
+
+    /// See [`Memory::set_memory_limit`]
+    pub fn memory_limit(mut self, bytes: u64) -> Self {
+        self.memory_limit = Some(bytes);
+        self
+    }
+
+    /// See [`Emulator::set_sandbox_policy`]
+    pub fn sandbox(mut self, policy: SandboxPolicy) -> Self {
+        self.sandbox = Some(policy);
+        self
+    }
+
+    /// See [`Emulator::set_strace`]
+    pub fn strace<F>(mut self, sink: F) -> Self
+    where
+        F: Fn(&str) + Send + Sync + 'static,
+    {
+        self.strace = Some(Arc::new(sink));
+        self
+    }
+
+    /// See [`Emulator::set_clock_rate`]
+    pub fn clock_rate(mut self, instructions_per_sec: u64) -> Self {
+        self.clock_rate = Some(instructions_per_sec);
+        self
+    }
+
+    /// See [`Emulator::set_real_time_pacing`]
+    #[cfg(feature = "std")]
+    pub fn real_time_pacing(mut self, enabled: bool) -> Self {
+        self.real_time_pacing = enabled;
+        self
+    }
+
+    /// Only counts cycles from when the function `label` is reached until it returns. Building
+    /// fails with [`RVError::InvalidLabel`] if the program doesn't have it.
+    pub fn profile_label(mut self, label: &str) -> Self {
+        self.profile_label = Some(label.to_string());
+        self
+    }
+
+    /// See [`Emulator::on_exit`]
+    pub fn on_exit<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&Emulator, u64) + Send + Sync + 'static,
+    {
+        self.exit_hooks.push(Arc::new(hook));
+        self
+    }
+
+    /// See [`Emulator::register_syscall`]
+    pub fn syscall(mut self, nr: u64, handler: impl SyscallHandler + 'static) -> Self {
+        self.syscall_handlers.push((nr, Arc::new(handler)));
+        self
+    }
+
+    /// See [`Emulator::intercept`]
+    pub fn intercept(mut self, symbol: &str, handler: impl InterceptHandler + 'static) -> Self {
+        self.intercepts
+            .push((symbol.to_string(), Arc::new(handler)));
+        self
+    }
+
+    /// See [`Emulator::set_fault_plan`]
+    pub fn fault_plan(mut self, plan: FaultPlan) -> Self {
+        self.fault_plan = Some(plan);
+        self
+    }
+
+    /// See [`Emulator::set_fuel`]
+    pub fn fuel(mut self, fuel: u64) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    /// Loads the program and sets up the emulator to start at its entry point. Fails with
+    /// [`RVError::InvalidFileType`] if the program isn't a 64-bit RISC-V executable, and with
+    /// [`RVError::InvalidLabel`] if a profiled or intercepted symbol doesn't exist.
+    pub fn build(self) -> Result<Emulator, RVError> {
+        let mut memory = match self.program {
+            Program::Memory(memory) => *memory,
+            Program::Elf(ref data) => {
+                let elf = Memory::parse_elf(data)?;
+                Memory::load_elf_with_sysroot(elf, &self.load_options, &self.sysroot)
+            }
+        };
+
+        memory.set_memory_limit(self.memory_limit);
+
+        let mut emulator = Emulator::with_stack(memory, self.auxv, self.args, self.env)?;
+        emulator.sysroot = self.sysroot;
+        if let Some(fds) = self.fds {
+            emulator.fds = fds;
+        }
+        if let Some(stdin) = self.stdin {
+            emulator
+                .fds
+                .insert(0, OpenFile::File(FileDescriptor::new(stdin)));
+        }
+
+        emulator.set_machine_identity(self.identity);
+        emulator.profiler.set_model(self.model);
+        emulator.set_hle_enabled(self.hle);
+        emulator.set_memcheck_enabled(self.memcheck);
+        emulator.set_frame_checking_enabled(self.frame_checking);
+        emulator.set_heap_profiling_enabled(self.heap_profiling);
+        emulator.set_exit_summary_enabled(self.exit_summary);
+        emulator.set_output_limit(self.output_limit);
+        emulator.set_sandbox_policy(self.sandbox);
+        emulator.set_strace(self.strace);
+        emulator.set_fault_plan(self.fault_plan);
+        emulator.set_fuel(self.fuel);
+        if let Some(rate) = self.clock_rate {
+            emulator.set_clock_rate(rate);
+        }
+        #[cfg(feature = "std")]
+        emulator.set_real_time_pacing(self.real_time_pacing);
+        if let Some(ref label) = self.profile_label {
+            #[allow(deprecated)]
+            emulator.profile_label(label)?;
+        }
+
+        emulator.exit_hooks = self.exit_hooks;
+        emulator.syscall_handlers.extend(self.syscall_handlers);
+        for (symbol, handler) in self.intercepts {
+            emulator.add_intercept(&symbol, handler)?;
+        }
+        emulator.save_start();
+
+        Ok(emulator)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::register::SP;
+
+    // the argv and envp the guest starts with, read off its stack
+    fn strings(emulator: &Emulator) -> Result<[Vec<String>; 2], RVError> {
+        let memory = &emulator.memory;
+        let argc = memory.load::<u64>(emulator.x[SP])?;
+        let mut addr = emulator.x[SP] + 8;
+
+        // both are null terminated arrays of pointers
+        let mut read = || {
+            let mut strings = Vec::new();
+            loop {
+                let ptr = memory.load::<u64>(addr)?;
+                addr += 8;
+                if ptr == 0 {
+                    return Ok::<_, RVError>(strings);
+                }
+                strings.push(String::from_utf8_lossy(&memory.read_cstr(ptr, 64)?).into());
+            }
+        };
+
+        let args = read()?;
+        assert_eq!(args.len() as u64, argc);
+        Ok([args, read()?])
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn setters() -> Result<(), RVError> {
+        let memory = Memory::from_raw(&[0; 0x100]);
+
+        // the same emulator as setting stdin after it's made
+        let built = EmulatorBuilder::new(memory.clone())
+            .stdin(*b"input")
+            .build()?;
+        let mut set = Emulator::new(memory.clone());
+        set.set_stdin(*b"input");
+        assert_eq!((built.pc, built.x), (set.pc, set.x));
+        assert_eq!(
+            built.fds().file(0).map(|file| &file.data),
+            set.fds().file(0).map(|file| &file.data)
+        );
+
+        let emulator = EmulatorBuilder::new(memory)
+            .stdin(*b"input")
+            .env(["HOME=/", "TERM=dumb"])
+            .args(["prog", "-v"])
+            .build()?;
+        assert_eq!(
+            strings(&emulator)?,
+            [vec!["prog", "-v"], vec!["HOME=/", "TERM=dumb"]]
+        );
+        assert_eq!(
+            emulator.fds().file(0).map(|file| &*file.data),
+            Some(&b"input"[..])
+        );
+        assert_eq!(strings(&set)?, [vec!["/prog"], vec![]]);
+        assert!(matches!(
+            set.profile_label("missing"),
+            Err(RVError::InvalidLabel)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn errors() {
+        let memory = Memory::from_raw(&[0; 0x100]);
+
+        let unknown_label = EmulatorBuilder::new(memory.clone())
+            .profile_label("missing")
+            .build();
+        assert!(matches!(unknown_label, Err(RVError::InvalidLabel)));
+        let unknown_intercept = EmulatorBuilder::new(memory)
+            .intercept("missing", |_: &mut Emulator| Ok(0))
+            .build();
+        assert!(matches!(unknown_intercept, Err(RVError::InvalidLabel)));
+
+        let script = EmulatorBuilder::from_elf(*b"#!/bin/sh").build();
+        assert!(matches!(script, Err(RVError::InvalidFileType)));
+    }
+}
@@ -0,0 +1,235 @@
+// the guest's clock. Time passes as instructions are executed at the modeled clock rate, and jumps
+// forward when the guest sleeps, so a program sleeping for a second finishes at once but still
+// sees the second go by. Real-time pacing ties it back to the host's clock for interactive
+// programs, by waiting whenever the guest gets ahead.
+
+use core::time::Duration;
+
+use super::Emulator;
+use crate::{error::RVError, register::*};
+
+/// The instructions executed per second of the guest's clock by default
+pub const DEFAULT_CLOCK_RATE: u64 = 1_000_000_000;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+const EINVAL: u64 = -22i64 as u64;
+// clock_nanosleep's flag for sleeping until an absolute time rather than for a duration
+const TIMER_ABSTIME: u64 = 1;
+
+// how much of the guest's time passes between checks that it isn't ahead of the host
+#[cfg(feature = "std")]
+const PACING_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Clone, Debug)]
+pub(super) struct GuestClock {
+    // instructions per second
+    rate: u64,
+    // the time at `base_inst`, and the instruction count the rate is counted from since. Moved
+    // up when the rate changes, so changing it doesn't change the time that already passed.
+    base: Duration,
+    base_inst: u64,
+    // see `set_real_time_pacing`
+    #[cfg(feature = "std")]
+    pacing: Option<Pacing>,
+}
+
+// the host and guest times pacing started at
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug)]
+struct Pacing {
+    host: std::time::Instant,
+    guest: Duration,
+}
+
+impl Default for GuestClock {
+    fn default() -> Self {
+        GuestClock {
+            rate: DEFAULT_CLOCK_RATE,
+            base: Duration::ZERO,
+            base_inst: 0,
+            #[cfg(feature = "std")]
+            pacing: None,
+        }
+    }
+}
+
+impl Emulator {
+    /// How many instructions the guest executes per second of its clock, which is what
+    /// clock_gettime reports. Defaults to [`DEFAULT_CLOCK_RATE`].
+    ///
+    /// # Panics
+    ///
+    /// If `instructions_per_sec` is 0.
+    pub fn set_clock_rate(&mut self, instructions_per_sec: u64) {
+        assert!(instructions_per_sec > 0, "the clock rate can't be 0");
+
+        self.clock.base = self.guest_time();
+        self.clock.base_inst = self.inst_counter;
+        self.clock.rate = instructions_per_sec;
+    }
+
+    pub fn clock_rate(&self) -> u64 {
+        self.clock.rate
+    }
+
+    /// The time on the guest's clock: the instructions executed at [`Emulator::clock_rate`],
+    /// and the time the guest spent sleeping
+    pub fn guest_time(&self) -> Duration {
+        let insts = self.inst_counter.saturating_sub(self.clock.base_inst) as u128;
+        let nanos = insts * NANOS_PER_SEC as u128 / self.clock.rate as u128;
+
+        self.clock.base
+            + Duration::new(
+                (nanos / NANOS_PER_SEC as u128) as u64,
+                (nanos % NANOS_PER_SEC as u128) as u32,
+            )
+    }
+
+    /// Moves the guest's clock forward by `duration`, like the guest sleeping for it
+    pub fn advance_guest_time(&mut self, duration: Duration) {
+        self.clock.base += duration;
+
+        #[cfg(feature = "std")]
+        self.pace();
+    }
+
+    /// Throttles execution so the guest's clock doesn't run ahead of the host's, and sleeps take
+    /// as long as they would on real hardware, for interactive programs. Only slows the guest
+    /// down, so one that can't keep up with [`Emulator::clock_rate`] falls behind. Disabled by
+    /// default, so the guest runs as fast as it can and sleeps return at once.
+    #[cfg(feature = "std")]
+    pub fn set_real_time_pacing(&mut self, enabled: bool) {
+        let was_enabled = self.clock.pacing.is_some();
+        self.clock.pacing = enabled.then(|| Pacing {
+            host: std::time::Instant::now(),
+            guest: self.guest_time(),
+        });
+
+        if enabled && !was_enabled {
+            self.schedule_pacing();
+        }
+    }
+
+    #[cfg(feature = "std")]
+    pub fn real_time_pacing(&self) -> bool {
+        self.clock.pacing.is_some()
+    }
+
+    // checks the guest isn't ahead of the host every `PACING_INTERVAL`, until pacing is disabled
+    #[cfg(feature = "std")]
+    fn schedule_pacing(&mut self) {
+        let interval = PACING_INTERVAL.as_nanos() * self.clock.rate as u128 / NANOS_PER_SEC as u128;
+
+        self.schedule_interrupt(interval.max(1) as u64, |emulator| {
+            if emulator.clock.pacing.is_some() {
+                emulator.pace();
+                emulator.schedule_pacing();
+            }
+        });
+    }
+
+    // waits for the host's clock to catch up with the guest's
+    #[cfg(feature = "std")]
+    fn pace(&self) {
+        let Some(pacing) = self.clock.pacing else {
+            return;
+        };
+
+        let guest_elapsed = self.guest_time().saturating_sub(pacing.guest);
+        if let Some(ahead) = guest_elapsed.checked_sub(pacing.host.elapsed()) {
+            std::thread::sleep(ahead);
+        }
+    }
+
+    // clock_gettime(clockid, tp). Every clock reads the guest's clock, so the realtime clock
+    // starts at the epoch.
+    pub(super) fn clock_gettime(&mut self) -> Result<u64, RVError> {
+        let time = self.guest_time();
+        let tp = self.x[A1];
+
+        self.memory
+            .write_struct(tp, &[time.as_secs(), time.subsec_nanos() as u64])?;
+
+        Ok(0)
+    }
+
+    // nanosleep(req, rem) and clock_nanosleep(clockid, flags, req, rem). Sleeps are never
+    // interrupted, so rem is left alone.
+    pub(super) fn nanosleep(&mut self, flags: u64, req: u64) -> Result<u64, RVError> {
+        let [secs, nanos] = self.memory.read_struct::<[u64; 2]>(req)?;
+        if (secs as i64) < 0 || nanos >= NANOS_PER_SEC {
+            return Ok(EINVAL);
+        }
+
+        let requested = Duration::new(secs, nanos as u32);
+        let duration = if flags & TIMER_ABSTIME != 0 {
+            requested.saturating_sub(self.guest_time())
+        } else {
+            requested
+        };
+        self.advance_guest_time(duration);
+
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::{assembler::assemble, memory::Memory};
+
+    // sleeps for 2.5 seconds, then until 10 seconds, then reads the clock into 0x80
+    fn program() -> Vec<u8> {
+        let mut data = Vec::new();
+        for line in [
+            "li a0, 0xa0",
+            "li a1, 0",
+            "li a7, 101",
+            "ecall",
+            "li a0, 1",
+            "li a1, 1",
+            "li a2, 0xb0",
+            "li a3, 0",
+            "li a7, 115",
+            "ecall",
+            "li a0, 1",
+            "li a1, 0x80",
+            "li a7, 113",
+            "ecall",
+            "li a0, 0",
+            "li a7, 93",
+            "ecall",
+        ] {
+            data.extend(assemble(line, data.len() as u64).unwrap());
+        }
+
+        data.resize(0x100, 0);
+        data[0xa0..0xa8].copy_from_slice(&2u64.to_le_bytes());
+        data[0xa8..0xb0].copy_from_slice(&500_000_000u64.to_le_bytes());
+        data[0xb0..0xb8].copy_from_slice(&10u64.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn guest_clock() -> Result<(), RVError> {
+        let mut emulator = Emulator::new(Memory::from_raw(&program()));
+        emulator.set_clock_rate(1_000);
+        emulator.run(false)?;
+
+        // the clock was read 4 instructions after waking up at 10 seconds, at 1ms each
+        let secs: u64 = emulator.memory.load(0x80)?;
+        let nanos: u64 = emulator.memory.load(0x88)?;
+        assert_eq!((secs, nanos), (10, 4_000_000));
+
+        // changing the rate leaves the time that passed alone
+        let time = emulator.guest_time();
+        emulator.set_clock_rate(1);
+        assert_eq!(emulator.guest_time(), time);
+        emulator.inst_counter += 2;
+        assert_eq!(emulator.guest_time(), time + Duration::from_secs(2));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,349 @@
+// running the riscv-tests ISA suite (https://github.com/riscv-software-src/riscv-tests), to check
+// the interpreter and the jit against the specification rather than against hand-encoded
+// instructions.
+//
+// The suite's "p" environment starts every test in machine mode, which sets up the csrs and
+// enters the test with mret. The setup is interpreted in system mode, and the test itself runs
+// as a normal program on the chosen engine. Tests end with an ecall that the environment turns
+// into a write to tohost, a0 being 0 on success or the number of the failing test shifted left
+// with the low bit set, which is the same value the emulator sees as the exit code.
+//
+// Only the user level tests (rv64u*-p-*) can be run like this, the machine and supervisor ones
+// expect their ecalls and exceptions to be handled by the environment.
+
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use super::{Emulator, Privilege};
+use crate::{error::RVError, instruction::Inst};
+
+/// How a test of the riscv-tests suite ended, see [`run_isa_test`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IsaTestResult {
+    Pass,
+    /// The number of the first failing test case, the `TEST_CASE` number in the test's source
+    Fail(u64),
+    /// The test didn't end within the instruction limit
+    Timeout,
+}
+
+/// Runs the riscv-tests executable `elf` on the interpreter or jit, stopping after `max_insts`
+/// instructions
+pub fn run_isa_test(elf: &[u8], jit: bool, max_insts: u64) -> Result<IsaTestResult, RVError> {
+    run_loaded(Emulator::from_elf_bytes(elf)?, jit, max_insts)
+}
+
+fn run_loaded(mut emulator: Emulator, jit: bool, max_insts: u64) -> Result<IsaTestResult, RVError> {
+    emulator.enable_system_mode(Privilege::Machine);
+
+    loop {
+        if emulator.inst_counter >= max_insts {
+            return Ok(IsaTestResult::Timeout);
+        }
+
+        let (inst, _) = emulator.fetch()?;
+        if let Some(exit_code) = emulator.fetch_and_execute()? {
+            return Ok(result(exit_code));
+        }
+
+        if inst == Inst::Mret {
+            break;
+        }
+    }
+
+    emulator.disable_system_mode();
+
+    let timed_out = Arc::new(AtomicBool::new(false));
+    let timer = timed_out.clone();
+    emulator.schedule_interrupt(max_insts - emulator.inst_counter, move |emulator| {
+        timer.store(true, Ordering::Relaxed);
+        emulator.exit(0);
+    });
+
+    let exit_code = emulator.run(jit)?;
+    if timed_out.load(Ordering::Relaxed) {
+        return Ok(IsaTestResult::Timeout);
+    }
+
+    Ok(result(exit_code))
+}
+
+fn result(exit_code: u64) -> IsaTestResult {
+    match exit_code {
+        0 => IsaTestResult::Pass,
+        code => IsaTestResult::Fail(code >> 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{format, string::String, vec::Vec};
+
+    use super::*;
+    use crate::{assembler::assemble, memory::Memory, register::*};
+
+    fn program(lines: &[&str]) -> Emulator {
+        let mut data = Vec::new();
+        for line in lines {
+            data.extend(assemble(line, data.len() as u64).unwrap());
+        }
+
+        Emulator::new(Memory::from_raw(&data))
+    }
+
+    // the shape of a riscv-tests program, checking that a0 is 5
+    fn isa_test(a0: i32) -> Emulator {
+        let li = format!("li a0, {a0}");
+        program(&[
+            "li t0, 16",
+            "csrrw zero, 0x341, t0", // mepc
+            "mret",
+            "nop",
+            // test 2
+            "li gp, 2",
+            &li,
+            "li a1, 5",
+            "bne a0, a1, 0x30",
+            // pass
+            "li a7, 93",
+            "li a0, 0",
+            "ecall",
+            "nop",
+            // fail
+            "slli a0, gp, 1",
+            "ori a0, a0, 1",
+            "li a7, 93",
+            "ecall",
+        ])
+    }
+
+    #[test]
+    fn isa_tests() -> Result<(), RVError> {
+        for jit in [false, true] {
+            assert_eq!(run_loaded(isa_test(5), jit, 100)?, IsaTestResult::Pass);
+            assert_eq!(run_loaded(isa_test(4), jit, 100)?, IsaTestResult::Fail(2));
+        }
+
+        let looping = program(&["li t0, 8", "csrrw zero, 0x341, t0", "mret", "j 0xc"]);
+        assert_eq!(run_loaded(looping, false, 100)?, IsaTestResult::Timeout);
+
+        Ok(())
+    }
+
+    // runs the suite when REMU_RISCV_TESTS is the isa directory of a riscv-tests build
+    #[cfg(feature = "std")]
+    #[test]
+    fn riscv_tests() {
+        let Some(dir) = std::env::var_os("REMU_RISCV_TESTS") else {
+            return;
+        };
+
+        let mut failures = Vec::new();
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            if !name.starts_with("rv64u") || !name.contains("-p-") || name.ends_with(".dump") {
+                continue;
+            }
+
+            let elf = std::fs::read(&path).unwrap();
+            for jit in [false, cfg!(jit)] {
+                match run_isa_test(&elf, jit, 1_000_000) {
+                    Ok(IsaTestResult::Pass) => {}
+                    result => failures.push(format!("{name} (jit: {jit}): {result:?}")),
+                }
+            }
+        }
+
+        failures.dedup();
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+
+    // instructions with what they should result in, given the values of their two source
+    // registers. Shifts by an immediate ignore the second one.
+    type Reference = fn(u64, u64) -> u64;
+
+    fn references() -> Vec<(Inst, Reference)> {
+        let (rd, rs1, rs2) = (A0, A0, A1);
+
+        let references: [(Inst, Reference); 32] = [
+            (Inst::Add { rd, rs1, rs2 }, |a, b| a.wrapping_add(b)),
+            (Inst::Addw { rd, rs1, rs2 }, |a, b| {
+                (a as u32).wrapping_add(b as u32) as i32 as u64
+            }),
+            (Inst::Sub { rd, rs1, rs2 }, |a, b| a.wrapping_sub(b)),
+            (Inst::Subw { rd, rs1, rs2 }, |a, b| {
+                (a as u32).wrapping_sub(b as u32) as i32 as u64
+            }),
+            (Inst::And { rd, rs1, rs2 }, |a, b| a & b),
+            (Inst::Or { rd, rs1, rs2 }, |a, b| a | b),
+            (Inst::Xor { rd, rs1, rs2 }, |a, b| a ^ b),
+            (Inst::Sll { rd, rs1, rs2 }, |a, b| a << (b & 63)),
+            (Inst::Sllw { rd, rs1, rs2 }, |a, b| {
+                ((a as u32) << (b & 31)) as i32 as u64
+            }),
+            (Inst::Slli { rd, rs1, shamt: 63 }, |a, _| a << 63),
+            (Inst::Slliw { rd, rs1, shamt: 31 }, |a, _| {
+                ((a as u32) << 31) as i32 as u64
+            }),
+            (Inst::Srl { rd, rs1, rs2 }, |a, b| a >> (b & 63)),
+            (Inst::Srlw { rd, rs1, rs2 }, |a, b| {
+                ((a as u32) >> (b & 31)) as i32 as u64
+            }),
+            (Inst::Srli { rd, rs1, shamt: 1 }, |a, _| a >> 1),
+            (Inst::Srliw { rd, rs1, shamt: 0 }, |a, _| {
+                a as u32 as i32 as u64
+            }),
+            (Inst::Sra { rd, rs1, rs2 }, |a, b| {
+                ((a as i64) >> (b & 63)) as u64
+            }),
+            (Inst::Sraw { rd, rs1, rs2 }, |a, b| {
+                ((a as i32) >> (b & 31)) as u64
+            }),
+            (Inst::Srai { rd, rs1, shamt: 63 }, |a, _| {
+                ((a as i64) >> 63) as u64
+            }),
+            (Inst::Sraiw { rd, rs1, shamt: 31 }, |a, _| {
+                ((a as i32) >> 31) as u64
+            }),
+            (Inst::Slt { rd, rs1, rs2 }, |a, b| {
+                ((a as i64) < (b as i64)) as u64
+            }),
+            (Inst::Sltu { rd, rs1, rs2 }, |a, b| (a < b) as u64),
+            (Inst::Mul { rd, rs1, rs2 }, |a, b| a.wrapping_mul(b)),
+            (Inst::Mulhu { rd, rs1, rs2 }, |a, b| {
+                ((a as u128 * b as u128) >> 64) as u64
+            }),
+            (Inst::Div { rd, rs1, rs2 }, |a, b| match b {
+                0 => u64::MAX,
+                b => (a as i64).wrapping_div(b as i64) as u64,
+            }),
+            (Inst::Divu { rd, rs1, rs2 }, |a, b| {
+                a.checked_div(b).unwrap_or(u64::MAX)
+            }),
+            (Inst::Divw { rd, rs1, rs2 }, |a, b| match b as u32 {
+                0 => u64::MAX,
+                b => (a as i32).wrapping_div(b as i32) as u64,
+            }),
+            (Inst::Divuw { rd, rs1, rs2 }, |a, b| {
+                (a as u32)
+                    .checked_div(b as u32)
+                    .map_or(u64::MAX, |q| q as i32 as u64)
+            }),
+            (Inst::Remu { rd, rs1, rs2 }, |a, b| {
+                a.checked_rem(b).unwrap_or(a)
+            }),
+            (Inst::Remw { rd, rs1, rs2 }, |a, b| match b as u32 {
+                0 => a as i32 as u64,
+                b => (a as i32).wrapping_rem(b as i32) as u64,
+            }),
+            (Inst::Remuw { rd, rs1, rs2 }, |a, b| {
+                let a = a as u32;
+                a.checked_rem(b as u32).unwrap_or(a) as i32 as u64
+            }),
+            (
+                Inst::Fled {
+                    rd,
+                    rs1: FReg(10),
+                    rs2: FReg(11),
+                },
+                |a, b| (f64::from_bits(a) <= f64::from_bits(b)) as u64,
+            ),
+            (
+                Inst::Fdivd {
+                    rd: FReg(10),
+                    rs1: FReg(10),
+                    rs2: FReg(11),
+                },
+                |a, b| (f64::from_bits(a) / f64::from_bits(b)).to_bits(),
+            ),
+        ];
+
+        references.into()
+    }
+
+    // runs `inst` with a and b in a0 and a1, or fa0 and fa1, returning a0 or fa0
+    fn execute(emulator: &mut Emulator, inst: Inst, a: u64, b: u64) -> u64 {
+        emulator.pc = 0;
+        emulator.x[A0] = a;
+        emulator.x[A1] = b;
+        emulator.f[FReg(10)] = f64::from_bits(a);
+        emulator.f[FReg(11)] = f64::from_bits(b);
+
+        emulator.fetch_and_execute().unwrap();
+        match inst {
+            Inst::Fdivd { .. } => emulator.f[FReg(10)].to_bits(),
+            _ => emulator.x[A0],
+        }
+    }
+
+    // executes instructions on random and edge case operands, comparing both their normal and
+    // compressed encodings against a reference implementation
+    #[test]
+    fn differential() {
+        const EDGES: [u64; 10] = [
+            0,
+            1,
+            2,
+            u64::MAX,
+            i64::MIN as u64,
+            i64::MAX as u64,
+            i32::MIN as u64,
+            i32::MAX as u64,
+            u32::MAX as u64,
+            0x8000_0000,
+        ];
+
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut operands: Vec<(u64, u64)> = EDGES
+            .iter()
+            .flat_map(|&a| EDGES.iter().map(move |&b| (a, b)))
+            .collect();
+        operands.extend((0..1000).map(|_| {
+            let mut next = || {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state
+            };
+            (next(), next())
+        }));
+        // floating point operands that aren't NaN
+        operands.extend(
+            [1.0f64, -0.0, 0.5, f64::INFINITY, 3.25]
+                .iter()
+                .flat_map(|&a| {
+                    [0.0f64, -2.0, 1.0, 3.25]
+                        .iter()
+                        .map(move |&b| (a.to_bits(), b.to_bits()))
+                }),
+        );
+
+        let mut failures: Vec<String> = Vec::new();
+        for (inst, reference) in references() {
+            let mut encodings = Vec::from([inst.encode().to_le_bytes().to_vec()]);
+            if let Some(compressed) = inst.encode_compressed() {
+                encodings.push(compressed.to_le_bytes().to_vec());
+            }
+
+            for encoding in &encodings {
+                let mut emulator = Emulator::new(Memory::from_raw(encoding));
+                for &(a, b) in &operands {
+                    let expected = reference(a, b);
+                    let actual = execute(&mut emulator, inst, a, b);
+                    let nans = f64::from_bits(expected).is_nan() && f64::from_bits(actual).is_nan();
+                    if expected != actual && !(matches!(inst, Inst::Fdivd { .. }) && nans) {
+                        failures.push(format!(
+                            "{} ({} bytes) of {a:#x}, {b:#x}: expected {expected:#x}, got {actual:#x}",
+                            inst.fmt(0),
+                            encoding.len()
+                        ));
+                        break;
+                    }
+                }
+            }
+        }
+
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+}
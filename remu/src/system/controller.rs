@@ -0,0 +1,328 @@
+// a single owner that drives the emulator and is told whenever it stops, in the style of ptrace.
+// Debugger frontends are built on this instead of stepping the emulator themselves.
+
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    string::{String, ToString},
+};
+
+use num_traits::FromPrimitive;
+
+use super::{memcheck::memory_access, Emulator, Syscall};
+use crate::{error::RVError, instruction::Inst, register::A7};
+
+/// Why [`Emulator::run_controlled`] stopped
+#[derive(Debug)]
+pub enum StopReason {
+    /// Before the first instruction, so the controller can set up breakpoints
+    Attached,
+    /// A single instruction was executed after [`Resume::Step`]
+    Step,
+    /// The pc reached a breakpoint, before the instruction there was executed
+    Breakpoint(u64),
+    /// The store at `pc` wrote to the watched bytes at `addr`, and has been executed
+    Watchpoint { pc: u64, addr: u64 },
+    /// The syscall is about to be made, after [`Resume::Syscall`]
+    SyscallEntry(Syscall),
+    /// The syscall was made, and its result is in a0
+    SyscallExit(Syscall),
+    /// The instruction at the pc failed. Resuming retries it, so the controller should fix the
+    /// cause or detach.
+    Signal(RVError),
+    /// The program exited with this code. The controller's answer is ignored.
+    Exited(u64),
+}
+
+/// How [`Emulator::run_controlled`] continues after a stop
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resume {
+    /// Runs until the next breakpoint, watchpoint, signal or exit
+    Continue,
+    /// Executes a single instruction
+    Step,
+    /// Like [`Resume::Continue`], but also stops on entry to and exit from the next syscall
+    Syscall,
+    /// Stops controlling the emulator, leaving it where it is
+    Detach,
+}
+
+/// Receives every stop of [`Emulator::run_controlled`] and decides how to continue. The emulator
+/// can be inspected and modified freely in between, including its breakpoints and watchpoints.
+pub trait Controller {
+    fn on_stop(&mut self, emulator: &mut Emulator, reason: StopReason) -> Resume;
+}
+
+impl<F: FnMut(&mut Emulator, StopReason) -> Resume> Controller for F {
+    fn on_stop(&mut self, emulator: &mut Emulator, reason: StopReason) -> Resume {
+        self(emulator, reason)
+    }
+}
+
+/// The breakpoints and watchpoints [`Emulator::run_controlled`] stops at
+#[derive(Clone, Debug, Default)]
+pub(super) struct StopPoints {
+    breakpoints: BTreeSet<u64>,
+    // start -> len
+    watchpoints: BTreeMap<u64, u64>,
+    // see `add_object_breakpoint`
+    object_breakpoints: BTreeSet<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Resumed {
+    breakpoint: bool,
+    syscall_entry: bool,
+}
+
+impl StopPoints {
+    pub(super) fn is_empty(&self) -> bool {
+        self.breakpoints.is_empty()
+            && self.watchpoints.is_empty()
+            && self.object_breakpoints.is_empty()
+    }
+}
+
+impl Emulator {
+    pub fn add_breakpoint(&mut self, addr: u64) {
+        self.stop_points.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u64) {
+        self.stop_points.breakpoints.remove(&addr);
+    }
+
+    /// Stops after any store that writes to the `len` bytes at `addr`
+    pub fn add_watchpoint(&mut self, addr: u64, len: u64) {
+        self.stop_points.watchpoints.insert(addr, len);
+    }
+
+    pub fn remove_watchpoint(&mut self, addr: u64) {
+        self.stop_points.watchpoints.remove(&addr);
+    }
+
+    /// Stops at [`StopReason::Breakpoint`] the first time the pc reaches a loaded object whose
+    /// name starts with `name`, like `libm` for the first call into libm.so.6, which doesn't have
+    /// to be loaded yet. Removed once it's hit. See [`Memory::loaded_objects`].
+    ///
+    /// [`Memory::loaded_objects`]: crate::memory::Memory::loaded_objects
+    pub fn add_object_breakpoint(&mut self, name: &str) {
+        self.stop_points.object_breakpoints.insert(name.to_string());
+    }
+
+    pub fn remove_object_breakpoint(&mut self, name: &str) {
+        self.stop_points.object_breakpoints.remove(name);
+    }
+
+    /// Runs one instruction at a time, handing control to `controller` at every stop until it
+    /// detaches or the program exits. Returns the exit code, or `None` if the controller
+    /// detached first.
+    pub fn run_controlled(&mut self, controller: &mut impl Controller) -> Option<u64> {
+        let mut resume = controller.on_stop(self, StopReason::Attached);
+        // set after stopping before an instruction, so resuming executes it instead of stopping
+        // there again
+        let mut resumed = Resumed::default();
+
+        loop {
+            if let Some(exit_code) = self.exit_code {
+                controller.on_stop(self, StopReason::Exited(exit_code));
+                return Some(exit_code);
+            }
+
+            if resume == Resume::Detach {
+                return None;
+            }
+
+            let Some(reason) = self.step_controlled(resume, resumed) else {
+                resumed = Resumed::default();
+                continue;
+            };
+
+            resumed = Resumed {
+                breakpoint: matches!(
+                    reason,
+                    StopReason::Breakpoint(_) | StopReason::SyscallEntry(_) | StopReason::Signal(_)
+                ),
+                syscall_entry: matches!(reason, StopReason::SyscallEntry(_)),
+            };
+            resume = controller.on_stop(self, reason);
+        }
+    }
+
+    // executes the next instruction, returning why it stopped, if it did
+    fn step_controlled(&mut self, resume: Resume, resumed: Resumed) -> Option<StopReason> {
+        if !resumed.breakpoint
+            && (self.stop_points.breakpoints.contains(&self.pc) || self.entered_object_breakpoint())
+        {
+            return Some(StopReason::Breakpoint(self.pc));
+        }
+
+        let inst = match self.fetch() {
+            Ok((inst, _)) => inst,
+            Err(e) => return Some(StopReason::Signal(self.diagnose(e))),
+        };
+
+        let syscall = match inst {
+            Inst::Ecall if resume == Resume::Syscall => Syscall::from_u64(self.x[A7]),
+            _ => None,
+        };
+        if let Some(syscall) = syscall {
+            if !resumed.syscall_entry {
+                return Some(StopReason::SyscallEntry(syscall));
+            }
+        }
+
+        let pc = self.pc;
+        let watched = self.watched_store(inst);
+
+        if let Err(e) = self.execute_next() {
+            return Some(StopReason::Signal(self.diagnose(e)));
+        }
+
+        if let Some(syscall) = syscall {
+            Some(StopReason::SyscallExit(syscall))
+        } else if let Some(addr) = watched {
+            Some(StopReason::Watchpoint { pc, addr })
+        } else if resume == Resume::Step {
+            Some(StopReason::Step)
+        } else {
+            None
+        }
+    }
+
+    // whether the pc is in an object with a breakpoint, removing the breakpoint if it is
+    fn entered_object_breakpoint(&mut self) -> bool {
+        let breakpoints = &mut self.stop_points.object_breakpoints;
+        if breakpoints.is_empty() {
+            return false;
+        }
+
+        let Some(object) = self.memory.object_at(self.pc) else {
+            return false;
+        };
+        let Some(name) = breakpoints
+            .iter()
+            .find(|&name| object.name.starts_with(name.as_str()))
+            .cloned()
+        else {
+            return false;
+        };
+
+        breakpoints.remove(&name)
+    }
+
+    // the watched address `inst` is about to store to, if any
+    fn watched_store(&self, inst: Inst) -> Option<u64> {
+        let watchpoints = &self.stop_points.watchpoints;
+        if watchpoints.is_empty() {
+            return None;
+        }
+
+        let (base, offset, len, false) = memory_access(inst)? else {
+            return None;
+        };
+        let start = self.x[base].wrapping_add(offset as u64);
+        let end = start.wrapping_add(len);
+
+        watchpoints
+            .range(..end)
+            .find(|&(&addr, &watch_len)| addr.wrapping_add(watch_len) > start)
+            .map(|(&addr, _)| addr.max(start))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::memory::Memory;
+
+    #[test]
+    fn stops() {
+        let mut data = [0u8; 0x40];
+        data[0..4].copy_from_slice(&0x00100513u32.to_le_bytes()); // li a0, 1
+        data[4..8].copy_from_slice(&0x02a03023u32.to_le_bytes()); // sd a0, 32(zero)
+        data[8..12].copy_from_slice(&0x0ac00893u32.to_le_bytes()); // li a7, 172
+        data[12..16].copy_from_slice(&0x00000073u32.to_le_bytes()); // ecall
+        data[16..20].copy_from_slice(&0x05d00893u32.to_le_bytes()); // li a7, 93
+        data[20..24].copy_from_slice(&0x00000073u32.to_le_bytes()); // ecall
+
+        let mut emulator = Emulator::new(Memory::from_raw(&data));
+        let mut stops = Vec::new();
+        let exit_code = emulator.run_controlled(&mut |emulator: &mut Emulator, reason| {
+            let resume = match reason {
+                StopReason::Attached => {
+                    emulator.add_breakpoint(8);
+                    emulator.add_watchpoint(0x24, 4);
+                    Resume::Continue
+                }
+                StopReason::Breakpoint(_) | StopReason::SyscallEntry(_) => Resume::Syscall,
+                StopReason::SyscallExit(_) => Resume::Step,
+                _ => Resume::Continue,
+            };
+
+            stops.push((format_stop(&reason), emulator.pc));
+            resume
+        });
+
+        assert_eq!(exit_code, Some(1));
+        assert_eq!(
+            stops,
+            [
+                ("attached", 0),
+                ("watchpoint 0x4 0x24", 8),
+                ("breakpoint", 8),
+                ("entry getpid", 12),
+                ("exit getpid", 16),
+                ("step", 20),
+                ("exited", 24),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "sysroot")]
+    fn object_breakpoint() {
+        use crate::{
+            files::{FileDescriptor, LIBM_DATA},
+            register::A0,
+        };
+
+        let mut data = [0u8; 0x40];
+        data[0..4].copy_from_slice(&0x000500e7u32.to_le_bytes()); // jalr a0
+
+        let mut emulator = Emulator::new(Memory::from_raw(&data));
+        let libm = FileDescriptor::new(LIBM_DATA);
+        emulator.memory.mmap_file(&libm, 0, 0, 0x80000).unwrap();
+        let cos = emulator.memory.disassembler.get_symbol_addr("cos").unwrap();
+        emulator.x[A0] = cos;
+
+        let mut stops = Vec::new();
+        emulator.run_controlled(&mut |emulator: &mut Emulator, reason| match reason {
+            StopReason::Attached => {
+                emulator.add_object_breakpoint("libm");
+                Resume::Continue
+            }
+            _ => {
+                stops.push((format_stop(&reason), emulator.pc));
+                Resume::Detach
+            }
+        });
+
+        assert_eq!(stops, [("breakpoint", cos)]);
+        assert!(emulator.stop_points.object_breakpoints.is_empty());
+    }
+
+    fn format_stop(reason: &StopReason) -> &'static str {
+        match reason {
+            StopReason::Attached => "attached",
+            StopReason::Step => "step",
+            StopReason::Breakpoint(_) => "breakpoint",
+            StopReason::Watchpoint { pc: 4, addr: 0x24 } => "watchpoint 0x4 0x24",
+            StopReason::SyscallEntry(Syscall::Getpid) => "entry getpid",
+            StopReason::SyscallExit(Syscall::Getpid) => "exit getpid",
+            StopReason::Exited(_) => "exited",
+            _ => "unexpected",
+        }
+    }
+}
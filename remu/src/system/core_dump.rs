@@ -0,0 +1,301 @@
+// the state of a guest when it crashed, written to a file so the crash can be looked at later
+// without running the program again
+
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use super::Emulator;
+use crate::{
+    error::RVError,
+    instruction::Inst,
+    memory::{Mapping, MappingKind},
+    register::SP,
+};
+
+const MAGIC: &[u8; 8] = b"REMUCORE";
+const VERSION: u32 = 1;
+
+// the most stack, in bytes above the stack pointer, that's kept
+const STACK_LIMIT: u64 = 64 * 1024;
+
+// in the order they're numbered in the file
+const MAPPING_KINDS: [MappingKind; 6] = [
+    MappingKind::Program,
+    MappingKind::DynamicLinker,
+    MappingKind::Heap,
+    MappingKind::Mmap,
+    MappingKind::Stack,
+    MappingKind::Device,
+];
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum CoreDumpError {
+    #[error("not a remu core dump")]
+    NotACoreDump,
+
+    #[error("unsupported core dump version {0}")]
+    UnsupportedVersion(u32),
+
+    #[error("the core dump is truncated")]
+    Truncated,
+
+    #[error("the core dump is corrupted")]
+    Corrupted,
+}
+
+/// The guest's state when it crashed, see [`Emulator::core_dump`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct CoreDump {
+    /// Why the guest crashed
+    pub error: String,
+    pub pc: u64,
+    pub inst_count: u64,
+    pub x: [u64; 32],
+    pub f: [f64; 32],
+    pub mappings: Vec<Mapping>,
+    /// The address of the first byte of `stack`, which is the stack pointer
+    pub stack_addr: u64,
+    pub stack: Vec<u8>,
+    /// The pcs executed before the crash, oldest first, see
+    /// [`Emulator::set_instruction_history_len`]
+    pub recent_pcs: Vec<u64>,
+}
+
+impl Emulator {
+    /// The registers, memory map, stack and recent pcs after `error` stopped the guest
+    pub fn core_dump(&self, error: &RVError) -> CoreDump {
+        let stack_addr = self.x[SP];
+        let stack = match self.memory.mapping_at(stack_addr) {
+            Some(mapping) => {
+                let len = (mapping.end - stack_addr).min(STACK_LIMIT);
+                self.memory.read_n(stack_addr, len).unwrap_or_default()
+            }
+            None => Vec::new(),
+        };
+
+        CoreDump {
+            error: error.to_string(),
+            pc: self.pc,
+            inst_count: self.inst_counter,
+            x: self.x,
+            f: self.f,
+            mappings: self.memory.mappings(),
+            stack_addr,
+            stack,
+            recent_pcs: self
+                .recent_instructions()
+                .iter()
+                .map(|&(pc, _)| pc)
+                .collect(),
+        }
+    }
+
+    /// Puts the guest back into the state of `dump`, for looking at it after the fact. The
+    /// emulator should have loaded the program that crashed, whose code and data aren't in the
+    /// dump. Only the stack is restored, the rest of memory is as the program was loaded. The
+    /// recent pcs become the [recent instructions](Emulator::recent_instructions).
+    pub fn restore_core_dump(&mut self, dump: &CoreDump) -> Result<(), RVError> {
+        let recent_instructions: Vec<(u64, Inst)> = dump
+            .recent_pcs
+            .iter()
+            .filter_map(|&pc| Some((pc, Inst::decode(self.memory.load(pc).ok()?).0)))
+            .collect();
+        self.set_instruction_history_len(recent_instructions.len());
+        self.set_recent_instructions(recent_instructions);
+
+        self.pc = dump.pc;
+        self.inst_counter = dump.inst_count;
+        self.x = dump.x;
+        self.f = dump.f;
+
+        self.memory
+            .write_n(&dump.stack, dump.stack_addr, dump.stack.len() as u64)
+    }
+}
+
+impl CoreDump {
+    /// Serializes the dump, to be read back with [`CoreDump::from_bytes`]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+
+        let mut put = |value: u64| out.extend_from_slice(&value.to_le_bytes());
+        put(self.pc);
+        put(self.inst_count);
+        for &value in &self.x {
+            put(value);
+        }
+        for &value in &self.f {
+            put(value.to_bits());
+        }
+
+        put(self.mappings.len() as u64);
+        for mapping in &self.mappings {
+            put(mapping.start);
+            put(mapping.end);
+            put(mapping.prot);
+            let kind = MAPPING_KINDS.iter().position(|&kind| kind == mapping.kind);
+            put(kind.expect("every kind is numbered") as u64);
+        }
+
+        put(self.recent_pcs.len() as u64);
+        for &pc in &self.recent_pcs {
+            put(pc);
+        }
+
+        put(self.stack_addr);
+        for bytes in [self.stack.as_slice(), self.error.as_bytes()] {
+            out.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+            out.extend_from_slice(bytes);
+        }
+
+        out
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<CoreDump, CoreDumpError> {
+        let (magic, rest) = data
+            .split_at_checked(MAGIC.len())
+            .ok_or(CoreDumpError::NotACoreDump)?;
+        if magic != MAGIC {
+            return Err(CoreDumpError::NotACoreDump);
+        }
+
+        let mut reader = Reader(rest);
+        let version = u32::from_le_bytes(reader.bytes(4)?.try_into().expect("4 bytes"));
+        if version != VERSION {
+            return Err(CoreDumpError::UnsupportedVersion(version));
+        }
+
+        let pc = reader.u64()?;
+        let inst_count = reader.u64()?;
+        let mut x = [0; 32];
+        for reg in &mut x {
+            *reg = reader.u64()?;
+        }
+        let mut f = [0.0; 32];
+        for reg in &mut f {
+            *reg = f64::from_bits(reader.u64()?);
+        }
+
+        let mappings = (0..reader.u64()?)
+            .map(|_| {
+                Ok(Mapping {
+                    start: reader.u64()?,
+                    end: reader.u64()?,
+                    prot: reader.u64()?,
+                    kind: *MAPPING_KINDS
+                        .get(reader.u64()? as usize)
+                        .ok_or(CoreDumpError::Corrupted)?,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let recent_pcs = (0..reader.u64()?)
+            .map(|_| reader.u64())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let stack_addr = reader.u64()?;
+        let len = reader.u64()?;
+        let stack = reader.bytes(len)?.to_vec();
+        let len = reader.u64()?;
+        let error =
+            String::from_utf8(reader.bytes(len)?.to_vec()).map_err(|_| CoreDumpError::Corrupted)?;
+
+        Ok(CoreDump {
+            error,
+            pc,
+            inst_count,
+            x,
+            f,
+            mappings,
+            stack_addr,
+            stack,
+            recent_pcs,
+        })
+    }
+}
+
+// reads the little endian fields of a core dump
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn bytes(&mut self, len: u64) -> Result<&[u8], CoreDumpError> {
+        let len = usize::try_from(len).map_err(|_| CoreDumpError::Truncated)?;
+        let (bytes, rest) = self
+            .0
+            .split_at_checked(len)
+            .ok_or(CoreDumpError::Truncated)?;
+
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn u64(&mut self) -> Result<u64, CoreDumpError> {
+        Ok(u64::from_le_bytes(
+            self.bytes(8)?.try_into().expect("8 bytes"),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{memory::Memory, register::A0};
+
+    #[test]
+    fn core_dump() {
+        let mut data = [0u8; 8];
+        data[0..4].copy_from_slice(&0x00010537u32.to_le_bytes()); // lui a0, 0x10
+        data[4..8].copy_from_slice(&0x00053503u32.to_le_bytes()); // ld a0, 0(a0)
+
+        let mut emulator = Emulator::new(Memory::from_raw(&data));
+        emulator.set_instruction_history_len(4);
+        emulator
+            .memory
+            .store::<u64>(emulator.x[SP], 0xdead)
+            .unwrap();
+
+        emulator.fetch_and_execute().unwrap();
+        let e = emulator.fetch_and_execute().unwrap_err();
+
+        let dump = emulator.core_dump(&e);
+        assert_eq!(dump.pc, 4);
+        assert_eq!(dump.x[A0], 0x10000);
+        assert_eq!(dump.recent_pcs, [0, 4]);
+        assert_eq!(dump.stack_addr, emulator.x[SP]);
+        assert_eq!(dump.stack[..8], 0xdeadu64.to_le_bytes());
+        assert!(dump.error.starts_with("segmentation fault"));
+        assert!(dump
+            .mappings
+            .iter()
+            .any(|mapping| mapping.kind == MappingKind::Stack));
+
+        let bytes = dump.to_bytes();
+        assert_eq!(CoreDump::from_bytes(&bytes), Ok(dump.clone()));
+        assert_eq!(
+            CoreDump::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(CoreDumpError::Truncated)
+        );
+        assert_eq!(
+            CoreDump::from_bytes(b"ELF"),
+            Err(CoreDumpError::NotACoreDump)
+        );
+
+        // a fresh emulator of the same program is put back where it crashed
+        let mut restored = Emulator::new(Memory::from_raw(&data));
+        restored.restore_core_dump(&dump).unwrap();
+        assert_eq!(restored.pc, 4);
+        assert_eq!(
+            restored.recent_instructions(),
+            emulator.recent_instructions()
+        );
+        assert_eq!(restored.x[A0], 0x10000);
+        assert_eq!(
+            restored.memory.load::<u64>(dump.stack_addr).unwrap(),
+            0xdead
+        );
+    }
+}
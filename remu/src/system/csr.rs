@@ -0,0 +1,112 @@
+// control and status registers
+// https://five-embeddev.com/riscv-priv-isa-manual/Priv-v1.12/priv-csrs.html
+
+use crate::profiler::HpmEvent;
+
+use super::Emulator;
+
+pub const MHARTID: u16 = 0xF14;
+
+// unprivileged counters, and the machine mode counters they shadow. The cycle counter and the
+// hardware performance monitor counters come from the profiler, so they only advance while
+// profiling and match the numbers puck reports.
+pub const CYCLE: u16 = 0xC00;
+pub const TIME: u16 = 0xC01;
+pub const INSTRET: u16 = 0xC02;
+pub const HPMCOUNTER3: u16 = 0xC03;
+pub const HPMCOUNTER31: u16 = 0xC1F;
+pub const MCYCLE: u16 = 0xB00;
+pub const MINSTRET: u16 = 0xB02;
+pub const MHPMCOUNTER3: u16 = 0xB03;
+pub const MHPMCOUNTER31: u16 = 0xB1F;
+pub const MHPMEVENT3: u16 = 0x323;
+pub const MHPMEVENT31: u16 = 0x33F;
+
+/// The number of configurable counters, hpmcounter3 through hpmcounter31
+pub const HPM_COUNTERS: usize = 29;
+
+// hpmcounter3 counts cache misses and hpmcounter4 branch mispredictions, until reconfigured
+pub const DEFAULT_HPM_EVENTS: [HpmEvent; HPM_COUNTERS] = {
+    let mut events = [HpmEvent::None; HPM_COUNTERS];
+    events[0] = HpmEvent::CacheMiss;
+    events[1] = HpmEvent::BranchMispredicted;
+    events
+};
+
+impl Emulator {
+    pub(super) fn read_csr(&self, csr: u16) -> u64 {
+        if let Some(value) = self.read_system_csr(csr) {
+            return value;
+        }
+
+        match csr {
+            MHARTID => self.hart_id,
+            CYCLE | MCYCLE => self.profiler.cycle_count,
+            // there is no wall clock in the emulator, so time is measured in instructions
+            TIME | INSTRET | MINSTRET => self.inst_counter,
+            HPMCOUNTER3..=HPMCOUNTER31 => self.hpm_counter(csr - HPMCOUNTER3),
+            MHPMCOUNTER3..=MHPMCOUNTER31 => self.hpm_counter(csr - MHPMCOUNTER3),
+            MHPMEVENT3..=MHPMEVENT31 => self.hpm_events[(csr - MHPMEVENT3) as usize].id(),
+            _ => {
+                log::warn!("{:16x} read from unimplemented csr {csr:#x}", self.pc);
+                0
+            }
+        }
+    }
+
+    pub(super) fn write_csr(&mut self, csr: u16, value: u64) {
+        if self.write_system_csr(csr, value) {
+            return;
+        }
+
+        match csr {
+            // read-only
+            MHARTID | CYCLE | TIME | INSTRET | HPMCOUNTER3..=HPMCOUNTER31 => {}
+            // the counters are derived from the profiler, so writes to them are ignored
+            MCYCLE | MINSTRET | MHPMCOUNTER3..=MHPMCOUNTER31 => {}
+            MHPMEVENT3..=MHPMEVENT31 => {
+                self.hpm_events[(csr - MHPMEVENT3) as usize] = HpmEvent::from_id(value);
+            }
+            _ => {
+                log::warn!(
+                    "{:16x} write of {value:x} to unimplemented csr {csr:#x}",
+                    self.pc
+                );
+            }
+        }
+    }
+
+    fn hpm_counter(&self, index: u16) -> u64 {
+        self.profiler.event_count(self.hpm_events[index as usize])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::Memory;
+
+    #[test]
+    fn performance_counters() {
+        let mut emulator = Emulator::new(Memory::from_raw(&[0; 4]));
+        emulator.profiler.cycle_count = 100;
+        emulator.profiler.cache_hit_count = 7;
+        emulator.profiler.cache_miss_count = 3;
+        emulator.profiler.mispredicted_branch_count = 2;
+        emulator.inst_counter = 50;
+
+        assert_eq!(emulator.read_csr(CYCLE), 100);
+        assert_eq!(emulator.read_csr(INSTRET), 50);
+        assert_eq!(emulator.read_csr(HPMCOUNTER3), 3);
+        assert_eq!(emulator.read_csr(HPMCOUNTER3 + 1), 2);
+        assert_eq!(emulator.read_csr(HPMCOUNTER3 + 2), 0);
+
+        emulator.write_csr(MHPMEVENT3 + 2, HpmEvent::CacheHit.id());
+        assert_eq!(emulator.read_csr(MHPMEVENT3 + 2), 1);
+        assert_eq!(emulator.read_csr(HPMCOUNTER3 + 2), 7);
+        assert_eq!(emulator.read_csr(MHPMCOUNTER3 + 2), 7);
+
+        emulator.write_csr(CYCLE, 0);
+        assert_eq!(emulator.read_csr(CYCLE), 100);
+    }
+}
@@ -0,0 +1,191 @@
+// a log of what the guest does that's visible outside of its registers and memory. Unlike the
+// `log` crate's output it's kept in memory, so the debugger can show it, and each category is
+// enabled separately so the ones that aren't wanted cost a single check.
+
+use alloc::string::{String, ToString};
+use core::fmt::{self, Display};
+
+use super::{syscall::SyscallRecord, Emulator};
+use crate::error::RVError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventCategory {
+    Syscall,
+    /// mmap, munmap and brk
+    Mmap,
+    Fault,
+    /// Syscalls and mappings made by the dynamic linker while it loads the program
+    DynamicLinker,
+}
+
+impl EventCategory {
+    pub const ALL: [EventCategory; 4] = [
+        EventCategory::Syscall,
+        EventCategory::Mmap,
+        EventCategory::Fault,
+        EventCategory::DynamicLinker,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            EventCategory::Syscall => "syscall",
+            EventCategory::Mmap => "mmap",
+            EventCategory::Fault => "fault",
+            EventCategory::DynamicLinker => "dynamic-linker",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<EventCategory> {
+        EventCategory::ALL
+            .into_iter()
+            .find(|category| category.name() == name)
+    }
+}
+
+/// The categories of events that are recorded, see [`Emulator::set_event_filter`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EventFilter(u8);
+
+impl EventFilter {
+    pub const NONE: EventFilter = EventFilter(0);
+    pub const ALL: EventFilter = EventFilter(0b1111);
+
+    pub fn with(self, category: EventCategory) -> EventFilter {
+        EventFilter(self.0 | 1 << category as u8)
+    }
+
+    pub fn contains(self, category: EventCategory) -> bool {
+        self.0 & 1 << category as u8 != 0
+    }
+
+    /// Parses a comma separated list of category names, like `syscall,fault`, or `all`
+    pub fn parse(list: &str) -> Option<EventFilter> {
+        if list == "all" {
+            return Some(EventFilter::ALL);
+        }
+
+        list.split(',').try_fold(EventFilter::NONE, |filter, name| {
+            Some(filter.with(EventCategory::from_name(name.trim())?))
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Event {
+    Syscall(SyscallRecord),
+    /// A successful mmap of `len` bytes at `addr`
+    Mmap {
+        addr: u64,
+        len: u64,
+    },
+    Munmap {
+        addr: u64,
+        len: u64,
+    },
+    /// The program break moved to `end`
+    Brk {
+        end: u64,
+    },
+    /// An error that stopped execution
+    Fault(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct EventRecord {
+    /// The number of instructions executed before the event
+    pub inst_count: u64,
+    pub pc: u64,
+    pub category: EventCategory,
+    pub event: Event,
+}
+
+impl Display for EventRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:x} ", self.pc)?;
+
+        match self.event {
+            Event::Syscall(ref record) => write!(f, "{record}"),
+            Event::Mmap { addr, len } => write!(f, "mmap {addr:#x}-{:#x}", addr + len),
+            Event::Munmap { addr, len } => write!(f, "munmap {addr:#x}-{:#x}", addr + len),
+            Event::Brk { end } => write!(f, "brk {end:#x}"),
+            Event::Fault(ref error) => write!(f, "fault: {error}"),
+        }
+    }
+}
+
+impl Emulator {
+    /// Chooses which categories of events are recorded, see [`Emulator::events`]. Nothing is
+    /// recorded by default.
+    pub fn set_event_filter(&mut self, filter: EventFilter) {
+        self.event_filter = filter;
+    }
+
+    /// The events recorded so far, oldest first
+    pub fn events(&self) -> &[EventRecord] {
+        &self.events
+    }
+
+    /// Whether an event of `category` made at the current pc would be recorded. Checked before
+    /// building an event, so disabled categories cost nothing more.
+    pub(super) fn is_event_enabled(&self, category: EventCategory) -> bool {
+        self.event_filter.contains(self.event_category(category))
+    }
+
+    pub(super) fn record_event(&mut self, category: EventCategory, event: Event) {
+        let category = self.event_category(category);
+
+        if self.event_filter.contains(category) {
+            self.events.push(EventRecord {
+                inst_count: self.inst_counter,
+                pc: self.pc,
+                category,
+                event,
+            });
+        }
+    }
+
+    // records the error that stopped execution, if there was one, with its diagnosis
+    pub(super) fn record_fault<T>(&mut self, result: Result<T, RVError>) -> Result<T, RVError> {
+        let result = result.map_err(|e| self.diagnose(e));
+        if let Err(ref e) = result {
+            self.record_event(EventCategory::Fault, Event::Fault(e.to_string()));
+        }
+
+        result
+    }
+
+    // syscalls and mappings are counted as the dynamic linker's if it made them
+    fn event_category(&self, category: EventCategory) -> EventCategory {
+        let in_dynamic_linker = self
+            .memory
+            .dynamic_linker
+            .as_ref()
+            .is_some_and(|range| range.contains(&self.pc));
+
+        match category {
+            EventCategory::Syscall | EventCategory::Mmap if in_dynamic_linker => {
+                EventCategory::DynamicLinker
+            }
+            category => category,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters() {
+        let filter = EventFilter::parse("syscall, fault").unwrap();
+        assert!(filter.contains(EventCategory::Syscall));
+        assert!(filter.contains(EventCategory::Fault));
+        assert!(!filter.contains(EventCategory::Mmap));
+
+        assert_eq!(EventFilter::parse("all"), Some(EventFilter::ALL));
+        assert_eq!(EventFilter::parse("syscall,nope"), None);
+        assert!(EventCategory::ALL
+            .into_iter()
+            .all(|category| EventFilter::ALL.contains(category)));
+    }
+}
@@ -0,0 +1,307 @@
+use alloc::{string::String, sync::Arc};
+
+use super::{
+    controller::{Resume, StopReason},
+    heap::HeapSummary,
+    Emulator,
+};
+use crate::error::RVError;
+
+// the signals whose default action isn't to terminate: SIGCHLD, SIGURG and SIGWINCH are ignored,
+// and the rest stop or continue the process, which does nothing without a parent to notice
+const NON_TERMINATING_SIGNALS: [u64; 8] = [17, 18, 19, 20, 21, 22, 23, 28];
+
+/// A callback run once the guest exits, given the machine as it was left and the exit code
+pub type ExitHook = Arc<dyn Fn(&Emulator, u64) + Send + Sync>;
+
+/// The state of the guest when it exited, see [`Emulator::set_exit_summary_enabled`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExitSummary {
+    pub exit_code: u64,
+    pub inst_counter: u64,
+    pub stdout: String,
+    pub stderr: String,
+    /// What was left on the heap, if heap profiling is enabled
+    pub heap: Option<HeapSummary>,
+}
+
+/// How a run ended and what it used, see [`Emulator::run_to_completion`]
+#[derive(Debug)]
+pub struct RunResult {
+    pub exit: ExitReason,
+    /// The instructions executed over the lifetime of the emulator, like
+    /// [`Emulator::inst_counter`]
+    pub inst_count: u64,
+    /// The estimated cycle count, which is only counted while profiling
+    pub cycles: u64,
+    /// The most memory the guest used at once, in bytes
+    pub peak_memory: u64,
+}
+
+#[derive(Debug)]
+pub enum ExitReason {
+    /// The program called exit or exit_group with this code
+    Exited(u64),
+    /// The program sent itself this signal, like abort does, and was terminated by it
+    Signaled(u64),
+    /// An instruction failed, and running again retries it
+    Fault(RVError),
+    /// The program executed all the instructions it was allowed to, see [`Emulator::set_fuel`]
+    FuelExhausted,
+    /// The pc reached a breakpoint, or the store at the pc wrote to a watched address, see
+    /// [`Emulator::add_breakpoint`]
+    Breakpoint(u64),
+}
+
+impl ExitReason {
+    /// The exit status a shell would report, 128 plus the signal for a program that was
+    /// terminated by one. `None` if the program is still running.
+    pub fn exit_code(&self) -> Option<u64> {
+        match *self {
+            ExitReason::Exited(code) => Some(code),
+            ExitReason::Signaled(sig) => Some(128 + sig),
+            _ => None,
+        }
+    }
+}
+
+impl Emulator {
+    /// Runs until the program exits, faults, runs out of fuel or reaches a breakpoint or
+    /// watchpoint. With any breakpoints or watchpoints set it only interprets, like
+    /// [`Emulator::run_controlled`].
+    pub fn run_to_completion(&mut self, jit: bool) -> RunResult {
+        let exit = if self.stop_points.is_empty() {
+            match self.run(jit) {
+                Ok(code) => self.exit_reason(code),
+                Err(e) => fault_reason(e),
+            }
+        } else {
+            self.run_to_stop_point()
+        };
+
+        RunResult {
+            exit,
+            inst_count: self.inst_counter,
+            cycles: self.profiler.cycle_count,
+            peak_memory: self.max_memory,
+        }
+    }
+
+    fn run_to_stop_point(&mut self) -> ExitReason {
+        // a breakpoint at the pc is the one the last run stopped at, so it's stepped over
+        let start = self.pc;
+        let mut resuming = true;
+        let mut stopped = None;
+
+        let exit_code = self.run_controlled(&mut |_: &mut Emulator, reason| {
+            let resumed = core::mem::replace(&mut resuming, false);
+            match reason {
+                StopReason::Attached => resuming = true,
+                StopReason::Breakpoint(pc) if resumed && pc == start => {}
+                StopReason::Breakpoint(pc) | StopReason::Watchpoint { pc, .. } => {
+                    stopped = Some(ExitReason::Breakpoint(pc));
+                    return Resume::Detach;
+                }
+                StopReason::Signal(e) => {
+                    stopped = Some(fault_reason(e));
+                    return Resume::Detach;
+                }
+                _ => {}
+            }
+
+            Resume::Continue
+        });
+
+        match exit_code {
+            Some(code) => self.exit_reason(code),
+            None => stopped.expect("the controller only detaches when it stops"),
+        }
+    }
+
+    fn exit_reason(&self, exit_code: u64) -> ExitReason {
+        match self.term_signal {
+            Some(sig) => ExitReason::Signaled(sig),
+            None => ExitReason::Exited(exit_code),
+        }
+    }
+
+    /// The signal that terminated the guest, if one did
+    pub fn term_signal(&self) -> Option<u64> {
+        self.term_signal
+    }
+
+    /// Stops the guest with [`RVError::FuelExhausted`] once it has executed `fuel` more
+    /// instructions, or never with `None`, the default. The jit only checks between blocks, so
+    /// it can run a little past it.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel_limit = fuel.map(|fuel| self.inst_counter.saturating_add(fuel));
+        self.update_next_interrupt();
+    }
+
+    /// The instructions the guest can still execute, if it's limited by [`Emulator::set_fuel`]
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel_limit
+            .map(|limit| limit.saturating_sub(self.inst_counter))
+    }
+
+    pub(super) fn check_fuel(&self) -> Result<(), RVError> {
+        match self.fuel_limit {
+            Some(limit) if self.inst_counter >= limit => Err(RVError::FuelExhausted),
+            _ => Ok(()),
+        }
+    }
+
+    // takes the default action of `sig`, sent by the guest to itself. Handlers are never run, so
+    // a signal that terminates by default always does, exiting the way a shell reports it.
+    pub(super) fn raise(&mut self, sig: u64) {
+        if sig == 0 || NON_TERMINATING_SIGNALS.contains(&sig) {
+            return;
+        }
+
+        log::info!("Terminated by signal {sig}");
+        self.term_signal = Some(sig);
+        self.exit(128 + sig);
+    }
+
+    /// Runs `hook` when the guest calls exit or exit_group, or when every hart of a [`Machine`]
+    /// has exited. Hooks run in the order they were added, after the exit summary is captured.
+    ///
+    /// [`Machine`]: super::Machine
+    pub fn on_exit<F>(&mut self, hook: F)
+    where
+        F: Fn(&Emulator, u64) + Send + Sync + 'static,
+    {
+        self.exit_hooks.push(Arc::new(hook));
+    }
+
+    /// Captures the output and heap usage of the guest when it exits, see
+    /// [`Emulator::exit_summary`]. Disabled by default.
+    pub fn set_exit_summary_enabled(&mut self, enabled: bool) {
+        self.exit_summary_enabled = enabled;
+        self.exit_summary = None;
+    }
+
+    pub fn exit_summary(&self) -> Option<&ExitSummary> {
+        self.exit_summary.as_ref()
+    }
+
+    // ends the program with `exit_code`
+    pub(super) fn exit(&mut self, exit_code: u64) {
+        self.exit_code = Some(exit_code);
+
+        if self.exit_summary_enabled {
+            self.exit_summary = Some(ExitSummary {
+                exit_code,
+                inst_counter: self.inst_counter,
+                stdout: self.stdout.clone(),
+                stderr: self.stderr.clone(),
+                heap: self.heap_profile.as_ref().map(|profile| profile.summary()),
+            });
+        }
+
+        for hook in self.exit_hooks.clone() {
+            hook(self, exit_code);
+        }
+    }
+}
+
+fn fault_reason(e: RVError) -> ExitReason {
+    match e {
+        RVError::FuelExhausted => ExitReason::FuelExhausted,
+        e => ExitReason::Fault(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::{assembler::assemble, memory::Memory, sync::Lock};
+
+    #[test]
+    fn exit_hooks() {
+        let mut data = [0u8; 12];
+        data[0..4].copy_from_slice(&0x00700513u32.to_le_bytes()); // li a0, 7
+        data[4..8].copy_from_slice(&0x05d00893u32.to_le_bytes()); // li a7, 93
+        data[8..12].copy_from_slice(&0x00000073u32.to_le_bytes()); // ecall
+
+        let mut emulator = Emulator::new(Memory::from_raw(&data));
+        emulator.stdout.push_str("done");
+        emulator.set_exit_summary_enabled(true);
+
+        let exited = Arc::new(Lock::new(None));
+        let hook_exited = exited.clone();
+        emulator.on_exit(move |emulator, exit_code| {
+            let summary = emulator.exit_summary().unwrap();
+            *hook_exited.lock() = Some((exit_code, summary.inst_counter));
+        });
+
+        assert_eq!(emulator.run(false).unwrap(), 7);
+        assert_eq!(*exited.lock(), Some((7, 2)));
+
+        let summary = emulator.exit_summary().unwrap();
+        assert_eq!(summary.stdout, "done");
+        assert_eq!(summary.heap, None);
+    }
+
+    // sends itself `sig`, then exits with 1 if that didn't terminate it
+    fn raise_program(sig: u64) -> Vec<u8> {
+        let mut data = Vec::new();
+        for line in [
+            "li a7, 172",
+            "ecall",
+            &format!("li a1, {sig}"),
+            "li a7, 129",
+            "ecall",
+            "li a0, 1",
+            "li a7, 93",
+            "ecall",
+        ] {
+            data.extend(assemble(line, data.len() as u64).unwrap());
+        }
+
+        data.resize(0x100, 0);
+        data
+    }
+
+    #[test]
+    fn run_to_completion() {
+        let mut emulator = Emulator::new(Memory::from_raw(&raise_program(6)));
+        emulator.add_breakpoint(0xc);
+
+        let result = emulator.run_to_completion(false);
+        assert!(matches!(result.exit, ExitReason::Breakpoint(0xc)));
+        assert_eq!(result.inst_count, 3);
+
+        let result = emulator.run_to_completion(false);
+        assert!(matches!(result.exit, ExitReason::Signaled(6)));
+        assert_eq!(result.exit.exit_code(), Some(134));
+        assert_eq!(result.inst_count, 5);
+        assert_eq!(emulator.term_signal(), Some(6));
+
+        // SIGCHLD is ignored
+        let mut emulator = Emulator::new(Memory::from_raw(&raise_program(17)));
+        let result = emulator.run_to_completion(false);
+        assert!(matches!(result.exit, ExitReason::Exited(1)));
+
+        let mut emulator = Emulator::new(Memory::from_raw(&raise_program(6)));
+        emulator.set_fuel(Some(2));
+        let result = emulator.run_to_completion(false);
+        assert!(matches!(result.exit, ExitReason::FuelExhausted));
+        assert_eq!((result.inst_count, emulator.fuel()), (2, Some(0)));
+
+        emulator.set_fuel(Some(1));
+        assert!(matches!(
+            emulator.run_to_completion(false).exit,
+            ExitReason::FuelExhausted
+        ));
+        assert_eq!(emulator.inst_counter, 3);
+
+        emulator.set_fuel(None);
+        assert!(matches!(
+            emulator.run_to_completion(false).exit,
+            ExitReason::Signaled(6)
+        ));
+    }
+}
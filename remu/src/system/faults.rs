@@ -0,0 +1,337 @@
+// injecting faults into the guest to exercise its error handling: allocations that fail, reads
+// that come up short, and bits flipped in registers or memory, as if by a cosmic ray
+
+use alloc::{boxed::Box, vec::Vec};
+use core::fmt;
+
+use super::Emulator;
+use crate::{
+    error::RVError,
+    register::{Reg, A0, RA},
+};
+
+const ENOMEM: u64 = -12i64 as u64;
+
+/// The faults to inject into the guest, see [`Emulator::set_fault_plan`]. Calls are counted
+/// from 1, from when the plan is set.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FaultPlan {
+    /// The calls to malloc that return null. Like [`Emulator::set_hle_enabled`], malloc is
+    /// looked up by symbol, so it has to be linked into the executable.
+    pub failing_mallocs: Vec<u64>,
+    /// The mmap syscalls that fail with ENOMEM, including those the dynamic linker makes
+    pub failing_mmaps: Vec<u64>,
+    /// The most bytes a read returns, however many were asked for and are there
+    pub max_read: Option<u64>,
+    pub bit_flips: Vec<BitFlip>,
+}
+
+/// Flips a bit once the guest has executed `at` instructions. With the jit it's flipped at the
+/// end of the block that reaches `at`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BitFlip {
+    pub at: u64,
+    pub target: FlipTarget,
+    /// The bit of the register, or of the byte of memory
+    pub bit: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlipTarget {
+    Register(Reg),
+    Memory(u64),
+}
+
+/// A fault that was injected, see [`Emulator::injected_faults`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InjectedFault {
+    pub inst_count: u64,
+    pub pc: u64,
+    pub kind: FaultKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FaultKind {
+    /// The numbered call to malloc returned null
+    Malloc { call: u64 },
+    /// The numbered mmap syscall failed
+    Mmap { call: u64 },
+    /// A read asked for more than the plan's `max_read`, and was cut down to it
+    ShortRead { fd: i64, requested: u64, max: u64 },
+    /// The bit was flipped. Flips of memory that isn't mapped are skipped.
+    BitFlip(BitFlip),
+}
+
+impl fmt::Display for InjectedFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            FaultKind::Malloc { call } => write!(f, "malloc call {call} returned null")?,
+            FaultKind::Mmap { call } => write!(f, "mmap call {call} failed with ENOMEM")?,
+            FaultKind::ShortRead { fd, requested, max } => {
+                write!(f, "read of {requested} bytes from fd {fd} cut to {max}")?
+            }
+            FaultKind::BitFlip(flip) => match flip.target {
+                FlipTarget::Register(reg) => write!(f, "flipped bit {} of {reg}", flip.bit)?,
+                FlipTarget::Memory(addr) => {
+                    write!(f, "flipped bit {} of the byte at {addr:#x}", flip.bit)?
+                }
+            },
+        }
+
+        write!(
+            f,
+            " at {:#x} after {} instructions",
+            self.pc, self.inst_count
+        )
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub(super) struct FaultState {
+    plan: FaultPlan,
+    malloc: Option<u64>,
+    mallocs: u64,
+    mmaps: u64,
+    injected: Vec<InjectedFault>,
+}
+
+impl Emulator {
+    /// Injects the faults in `plan` as the guest runs, to test how it handles them. Replaces the
+    /// last plan, and `None` stops injecting faults.
+    pub fn set_fault_plan(&mut self, plan: Option<FaultPlan>) {
+        let Some(plan) = plan else {
+            self.faults = None;
+            return;
+        };
+
+        for flip in plan.bit_flips.iter().copied() {
+            let after = flip.at.saturating_sub(self.inst_counter);
+            self.schedule_interrupt(after, move |emulator| {
+                // a plan set after this one doesn't have the flip
+                if emulator
+                    .faults
+                    .as_ref()
+                    .is_some_and(|faults| faults.plan.bit_flips.contains(&flip))
+                {
+                    emulator.flip_bit(flip);
+                }
+            });
+        }
+
+        self.faults = Some(Box::new(FaultState {
+            malloc: self.memory.disassembler.get_symbol_addr("malloc"),
+            plan,
+            ..FaultState::default()
+        }));
+    }
+
+    pub fn fault_plan(&self) -> Option<&FaultPlan> {
+        self.faults.as_ref().map(|faults| &faults.plan)
+    }
+
+    /// The faults injected so far, in order
+    pub fn injected_faults(&self) -> &[InjectedFault] {
+        self.faults
+            .as_ref()
+            .map_or(&[], |faults| faults.injected.as_slice())
+    }
+
+    // looks malloc up again in a new program
+    pub(super) fn find_malloc_fault(&mut self) {
+        if let Some(ref mut faults) = self.faults {
+            faults.malloc = self.memory.disassembler.get_symbol_addr("malloc");
+        }
+    }
+
+    fn record_fault_injection(&mut self, kind: FaultKind) {
+        let fault = InjectedFault {
+            inst_count: self.inst_counter,
+            pc: self.pc,
+            kind,
+        };
+        log::info!("Injected fault: {fault}");
+
+        if let Some(ref mut faults) = self.faults {
+            faults.injected.push(fault);
+        }
+    }
+
+    // returns null to ra from a call to malloc that's planned to fail. Returns false if pc isn't
+    // malloc or the call should succeed.
+    pub(super) fn try_malloc_fault(&mut self) -> Result<bool, RVError> {
+        let Some(ref mut faults) = self.faults else {
+            return Ok(false);
+        };
+        if faults.malloc != Some(self.pc) {
+            return Ok(false);
+        }
+
+        faults.mallocs += 1;
+        let call = faults.mallocs;
+        if !faults.plan.failing_mallocs.contains(&call) {
+            return Ok(false);
+        }
+
+        self.record_fault_injection(FaultKind::Malloc { call });
+        self.x[A0] = 0;
+        self.check_return(self.x[RA])?;
+        self.pc = self.x[RA];
+
+        Ok(true)
+    }
+
+    // ENOMEM if this mmap syscall is planned to fail
+    pub(super) fn mmap_fault(&mut self) -> Option<u64> {
+        let faults = self.faults.as_mut()?;
+        faults.mmaps += 1;
+        let call = faults.mmaps;
+        if !faults.plan.failing_mmaps.contains(&call) {
+            return None;
+        }
+
+        self.record_fault_injection(FaultKind::Mmap { call });
+        Some(ENOMEM)
+    }
+
+    // the bytes a read of `count` bytes from `fd` is cut down to
+    pub(super) fn short_read(&mut self, fd: i64, count: u64) -> u64 {
+        let Some(max) = self.faults.as_ref().and_then(|faults| faults.plan.max_read) else {
+            return count;
+        };
+        if count <= max {
+            return count;
+        }
+
+        self.record_fault_injection(FaultKind::ShortRead {
+            fd,
+            requested: count,
+            max,
+        });
+        max
+    }
+
+    fn flip_bit(&mut self, flip: BitFlip) {
+        match flip.target {
+            FlipTarget::Register(reg) if reg.0 != 0 => self.x[reg] ^= 1 << (flip.bit % 64),
+            FlipTarget::Register(_) => return,
+            FlipTarget::Memory(addr) => {
+                let Ok(byte) = self.memory.load::<u8>(addr) else {
+                    return;
+                };
+                let flipped = byte ^ (1 << (flip.bit % 8));
+                if self.memory.write_n(&[flipped], addr, 1).is_err() {
+                    return;
+                }
+            }
+        }
+
+        self.record_fault_injection(FaultKind::BitFlip(flip));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::{assembler::assemble, memory::Memory, register::*};
+
+    // calls malloc three times, then exits with whether each call returned null as a bit
+    fn program() -> Vec<u8> {
+        let mut data = Vec::new();
+        for line in [
+            "jal 40",
+            "sltiu s0, a0, 1",
+            "jal 40",
+            "sltiu a0, a0, 1",
+            "slli a0, a0, 1",
+            "or s0, s0, a0",
+            "jal 40",
+            "sltiu a0, a0, 1",
+            "slli a0, a0, 2",
+            "or a0, s0, a0",
+            "li a7, 93",
+            "ecall",
+            "nop",
+            "nop",
+            "nop",
+            "nop",
+            "li a0, 0x80",
+            "ret",
+        ] {
+            data.extend(assemble(line, data.len() as u64).unwrap());
+        }
+
+        data.resize(0x100, 0);
+        data
+    }
+
+    #[test]
+    fn fault_injection() -> Result<(), RVError> {
+        let mut emulator = Emulator::new(Memory::from_raw(&program()));
+        emulator
+            .memory
+            .disassembler
+            .import_symbols("40 malloc", 0)
+            .unwrap();
+        emulator.memory.write_n(&[0x0f], 0xf0, 1)?;
+        emulator.set_fault_plan(Some(FaultPlan {
+            failing_mallocs: vec![2],
+            failing_mmaps: vec![1],
+            max_read: Some(4),
+            bit_flips: vec![BitFlip {
+                at: 2,
+                target: FlipTarget::Memory(0xf0),
+                bit: 7,
+            }],
+        }));
+
+        // only the second call failed
+        assert_eq!(emulator.run(false)?, 0b010);
+        assert_eq!(emulator.memory.load::<u8>(0xf0)?, 0x8f);
+
+        assert_eq!(emulator.mmap_fault(), Some(ENOMEM));
+        assert_eq!(emulator.mmap_fault(), None);
+        assert_eq!(emulator.short_read(0, 3), 3);
+        assert_eq!(emulator.short_read(0, 10), 4);
+
+        let kinds: Vec<FaultKind> = emulator
+            .injected_faults()
+            .iter()
+            .map(|fault| fault.kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                FaultKind::BitFlip(BitFlip {
+                    at: 2,
+                    target: FlipTarget::Memory(0xf0),
+                    bit: 7,
+                }),
+                FaultKind::Malloc { call: 2 },
+                FaultKind::Mmap { call: 1 },
+                FaultKind::ShortRead {
+                    fd: 0,
+                    requested: 10,
+                    max: 4,
+                },
+            ]
+        );
+        assert_eq!(emulator.injected_faults()[1].pc, 0x40);
+
+        // flipping a register
+        let mut emulator = Emulator::new(Memory::from_raw(&program()));
+        emulator.set_fault_plan(Some(FaultPlan {
+            bit_flips: vec![BitFlip {
+                at: 0,
+                target: FlipTarget::Register(S1),
+                bit: 3,
+            }],
+            ..FaultPlan::default()
+        }));
+        emulator.fetch_and_execute()?;
+        assert_eq!(emulator.x[S1], 8);
+
+        Ok(())
+    }
+}
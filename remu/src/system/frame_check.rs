@@ -0,0 +1,247 @@
+// a shadow stack of every call, checked on each return. The return address and stack pointer of a
+// call are recorded when it's made, and the stack slot the callee saves its return address to is
+// watched, so when a return doesn't match its call the store that smashed the slot is known.
+
+use alloc::{collections::BTreeMap, vec::Vec};
+
+use super::{memcheck::memory_access, Emulator};
+use crate::{error::RVError, instruction::Inst, register::*};
+
+#[derive(Clone, Copy, Debug)]
+struct Frame {
+    function: u64,
+    return_addr: u64,
+    sp: u64,
+    ra_slot: Option<u64>,
+    // the first store to ra_slot after it was saved
+    overwritten_by: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub(super) struct FrameCheck {
+    frames: Vec<Frame>,
+    // saved return address slots, and the index of the frame each belongs to
+    slots: BTreeMap<u64, usize>,
+}
+
+impl FrameCheck {
+    fn pop(&mut self) -> Option<Frame> {
+        let frame = self.frames.pop()?;
+        if let Some(slot) = frame.ra_slot {
+            self.slots.remove(&slot);
+        }
+
+        Some(frame)
+    }
+}
+
+/// A return that didn't match its call, see [`Emulator::set_frame_checking_enabled`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FrameViolation {
+    /// The function that returned
+    pub function: u64,
+    pub return_pc: u64,
+    pub expected_return_addr: u64,
+    pub return_addr: u64,
+    pub expected_sp: u64,
+    pub sp: u64,
+    /// Where the function saved its return address, if it did
+    pub ra_slot: Option<u64>,
+    /// The pc of the first store to overwrite the saved return address
+    pub overwritten_by: Option<u64>,
+    /// The return address of each call that was still active, innermost first
+    pub stack: Vec<u64>,
+}
+
+impl Emulator {
+    /// Records the return address and stack pointer of every call, and checks them when the call
+    /// returns. A return to anywhere else, or with a different stack pointer, stops execution with
+    /// [`RVError::StackCorruption`] and is described by [`Emulator::frame_violation`]. Every
+    /// instruction has to be checked, so the jit isn't used while it's enabled. Disabled by
+    /// default.
+    pub fn set_frame_checking_enabled(&mut self, enabled: bool) {
+        self.frame_check = enabled.then(FrameCheck::default);
+        self.frame_violation = None;
+    }
+
+    /// The return that stopped execution, if frame checking caught one
+    pub fn frame_violation(&self) -> Option<&FrameViolation> {
+        self.frame_violation.as_ref()
+    }
+
+    // checks the call, return or store `inst` is about to make, before it's executed
+    pub(super) fn check_frame(&mut self, inst: Inst, incr: u64) -> Result<(), RVError> {
+        let pc = self.pc;
+        let Some(ref mut check) = self.frame_check else {
+            return Ok(());
+        };
+
+        match inst {
+            Inst::Jal { rd: RA, offset } => check.frames.push(Frame {
+                function: pc.wrapping_add(offset as u64),
+                return_addr: pc + incr,
+                sp: self.x[SP],
+                ra_slot: None,
+                overwritten_by: None,
+            }),
+            Inst::Jalr {
+                rd: RA,
+                rs1,
+                offset,
+            } => check.frames.push(Frame {
+                function: self.x[rs1].wrapping_add(offset as u64),
+                return_addr: pc + incr,
+                sp: self.x[SP],
+                ra_slot: None,
+                overwritten_by: None,
+            }),
+            Inst::Jalr {
+                rd: Reg(0),
+                rs1: RA,
+                offset: 0,
+            } => return self.check_return(self.x[RA]),
+            _ => {
+                let Some((base, offset, len, false)) = memory_access(inst) else {
+                    return Ok(());
+                };
+                let addr = self.x[base].wrapping_add(offset as u64);
+
+                // the innermost function saving its return address
+                let saves_ra = matches!(inst, Inst::Sd { rs2: RA, .. });
+                if let Some(frame) = check.frames.last_mut() {
+                    if saves_ra && frame.ra_slot.is_none() && self.x[RA] == frame.return_addr {
+                        if !check.slots.contains_key(&addr) {
+                            frame.ra_slot = Some(addr);
+                            check.slots.insert(addr, check.frames.len() - 1);
+                        }
+                        return Ok(());
+                    }
+                }
+
+                let overwritten = check
+                    .slots
+                    .range(addr.saturating_sub(7)..addr.saturating_add(len))
+                    .map(|(_, &frame)| frame);
+                for frame in overwritten.collect::<Vec<_>>() {
+                    check.frames[frame].overwritten_by.get_or_insert(pc);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // pops the frame of the call returning to `return_addr`. Frames skipped over by a longjmp
+    // are popped along with it, as long as the return address matches an outer call.
+    pub(super) fn check_return(&mut self, return_addr: u64) -> Result<(), RVError> {
+        let sp = self.x[SP];
+        let Some(ref mut check) = self.frame_check else {
+            return Ok(());
+        };
+        // returns with no call recorded, like from the entry point, can't be checked
+        let Some(&frame) = check.frames.last() else {
+            return Ok(());
+        };
+
+        if frame.return_addr == return_addr && frame.sp == sp {
+            check.pop();
+            return Ok(());
+        }
+
+        if frame.return_addr != return_addr {
+            if let Some(depth) = check
+                .frames
+                .iter()
+                .rposition(|frame| frame.return_addr == return_addr)
+            {
+                while check.frames.len() > depth {
+                    check.pop();
+                }
+                return Ok(());
+            }
+        }
+
+        let stack = check
+            .frames
+            .iter()
+            .rev()
+            .map(|frame| frame.return_addr)
+            .collect();
+        self.frame_violation = Some(FrameViolation {
+            function: frame.function,
+            return_pc: self.pc,
+            expected_return_addr: frame.return_addr,
+            return_addr,
+            expected_sp: frame.sp,
+            sp,
+            ra_slot: frame.ra_slot,
+            overwritten_by: frame.overwritten_by,
+            stack,
+        });
+
+        Err(RVError::StackCorruption)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::Memory;
+
+    #[test]
+    fn smashed_return_address() {
+        let mut emulator = Emulator::new(Memory::from_raw(&[0; 0x100]));
+        emulator.set_frame_checking_enabled(true);
+        emulator.pc = 0x1000;
+        emulator.x[SP] = 0x8000;
+
+        // jal ra, 0x100
+        emulator
+            .check_frame(
+                Inst::Jal {
+                    rd: RA,
+                    offset: 0x100,
+                },
+                4,
+            )
+            .unwrap();
+        emulator.pc = 0x1100;
+        emulator.x[RA] = 0x1004;
+        emulator.x[SP] = 0x7ff0;
+
+        // sd ra, 8(sp), then a byte store over it
+        let save = Inst::Sd {
+            rs1: SP,
+            rs2: RA,
+            offset: 8,
+        };
+        emulator.check_frame(save, 4).unwrap();
+        emulator.pc = 0x1108;
+        let smash = Inst::Sb {
+            rs1: SP,
+            rs2: A0,
+            offset: 12,
+        };
+        emulator.check_frame(smash, 4).unwrap();
+
+        emulator.pc = 0x1110;
+        emulator.x[RA] = 0x4141_4141;
+        emulator.x[SP] = 0x8000;
+        let ret = Inst::Jalr {
+            rd: Reg(0),
+            rs1: RA,
+            offset: 0,
+        };
+        assert!(matches!(
+            emulator.check_frame(ret, 4),
+            Err(RVError::StackCorruption)
+        ));
+
+        let violation = emulator.frame_violation().unwrap();
+        assert_eq!(violation.function, 0x1100);
+        assert_eq!(violation.expected_return_addr, 0x1004);
+        assert_eq!(violation.return_addr, 0x4141_4141);
+        assert_eq!(violation.ra_slot, Some(0x7ff8));
+        assert_eq!(violation.overwritten_by, Some(0x1108));
+    }
+}
@@ -0,0 +1,359 @@
+// heap profiling in the style of valgrind's DHAT: calls to malloc, calloc, realloc and free are
+// run as usual, but their arguments and results are recorded along with the stack they were
+// called from, then summarized per call stack. Memcheck learns which blocks are live the same way.
+
+use alloc::{collections::BTreeMap, format, vec::Vec};
+use core::fmt::{self, Write};
+
+use crate::{disassembler::Disassembler, error::RVError, profiler::write_json_string, register::*};
+
+use super::Emulator;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum HeapRoutine {
+    Malloc,
+    Calloc,
+    Realloc,
+    Free,
+}
+
+const ROUTINES: [(&str, HeapRoutine); 4] = [
+    ("malloc", HeapRoutine::Malloc),
+    ("calloc", HeapRoutine::Calloc),
+    ("realloc", HeapRoutine::Realloc),
+    ("free", HeapRoutine::Free),
+];
+
+// the deepest call stack an allocation is attributed to
+const HEAP_FRAME_LIMIT: usize = 16;
+
+/// The allocations made from one call stack
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AllocationSite {
+    /// The call site followed by the return address of each caller, innermost first
+    pub stack: Vec<u64>,
+    pub total_bytes: u64,
+    pub total_blocks: u64,
+    /// The summed lifetimes of every block, in instructions
+    pub total_lifetimes: u64,
+    pub live_bytes: u64,
+    pub live_blocks: u64,
+    pub max_bytes: u64,
+    pub max_blocks: u64,
+    /// Live when the whole heap was at its largest
+    pub peak_bytes: u64,
+    pub peak_blocks: u64,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct LiveBlock {
+    site: usize,
+    size: u64,
+    allocated_at: u64,
+}
+
+/// See [`Emulator::set_heap_profiling_enabled`]
+#[derive(Clone, Debug, Default)]
+pub struct HeapProfile {
+    pub sites: Vec<AllocationSite>,
+    site_indices: BTreeMap<Vec<u64>, usize>,
+    // keyed by address
+    live: BTreeMap<u64, LiveBlock>,
+    pub live_bytes: u64,
+    pub max_live_bytes: u64,
+    /// When the heap was at its largest, in instructions
+    pub peak_time: u64,
+}
+
+/// The totals of a [`HeapProfile`], see [`HeapProfile::summary`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HeapSummary {
+    pub total_bytes: u64,
+    pub total_blocks: u64,
+    pub live_bytes: u64,
+    pub live_blocks: u64,
+    pub max_live_bytes: u64,
+}
+
+impl HeapProfile {
+    pub fn summary(&self) -> HeapSummary {
+        HeapSummary {
+            total_bytes: self.sites.iter().map(|site| site.total_bytes).sum(),
+            total_blocks: self.sites.iter().map(|site| site.total_blocks).sum(),
+            live_bytes: self.live_bytes,
+            live_blocks: self.live.len() as u64,
+            max_live_bytes: self.max_live_bytes,
+        }
+    }
+
+    fn allocate(&mut self, addr: u64, size: u64, stack: Vec<u64>, now: u64) {
+        if addr == 0 {
+            return;
+        }
+
+        let sites = &mut self.sites;
+        let site = *self.site_indices.entry(stack).or_insert_with_key(|stack| {
+            sites.push(AllocationSite {
+                stack: stack.clone(),
+                ..AllocationSite::default()
+            });
+            sites.len() - 1
+        });
+
+        let stats = &mut self.sites[site];
+        stats.total_bytes += size;
+        stats.total_blocks += 1;
+        stats.live_bytes += size;
+        stats.live_blocks += 1;
+        stats.max_bytes = stats.max_bytes.max(stats.live_bytes);
+        stats.max_blocks = stats.max_blocks.max(stats.live_blocks);
+
+        self.live.insert(
+            addr,
+            LiveBlock {
+                site,
+                size,
+                allocated_at: now,
+            },
+        );
+        self.live_bytes += size;
+
+        if self.live_bytes > self.max_live_bytes {
+            self.max_live_bytes = self.live_bytes;
+            self.peak_time = now;
+
+            for site in &mut self.sites {
+                site.peak_bytes = site.live_bytes;
+                site.peak_blocks = site.live_blocks;
+            }
+        }
+    }
+
+    // frees of pointers that weren't allocated, including null, are ignored
+    fn free(&mut self, addr: u64, now: u64) {
+        let Some(block) = self.live.remove(&addr) else {
+            return;
+        };
+
+        let site = &mut self.sites[block.site];
+        site.live_bytes -= block.size;
+        site.live_blocks -= 1;
+        site.total_lifetimes += now - block.allocated_at;
+        self.live_bytes -= block.size;
+    }
+
+    /// Writes the profile as DHAT's JSON, which the DHAT viewer and the Firefox profiler open.
+    /// Times are in instructions, `now` being the end of the run.
+    pub fn export_dhat(
+        &self,
+        cmd: &str,
+        now: u64,
+        disassembler: &Disassembler,
+        out: &mut impl Write,
+    ) -> fmt::Result {
+        // blocks still live at the end have lived until now
+        let mut lifetimes: Vec<u64> = self.sites.iter().map(|site| site.total_lifetimes).collect();
+        for block in self.live.values() {
+            lifetimes[block.site] += now - block.allocated_at;
+        }
+
+        // frame 0 is the root every stack hangs off
+        let mut frames: Vec<u64> = Vec::new();
+        let mut frame_indices: BTreeMap<u64, usize> = BTreeMap::new();
+
+        write!(
+            out,
+            "{{\"dhatFileVersion\":2,\"mode\":\"heap\",\"verb\":\"Allocated\",\"bklt\":true,\
+             \"bkacc\":false,\"tu\":\"instrs\",\"Mtu\":\"instr\",\"cmd\":"
+        )?;
+        write_json_string(cmd, out)?;
+        write!(
+            out,
+            ",\"pid\":1,\"te\":{now},\"tg\":{},\"pps\":[",
+            self.peak_time
+        )?;
+
+        for (i, site) in self.sites.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            write!(
+                out,
+                "{separator}\n{{\"tb\":{},\"tbk\":{},\"tl\":{},\"mb\":{},\"mbk\":{},\"gb\":{},\
+                 \"gbk\":{},\"eb\":{},\"ebk\":{},\"fs\":[",
+                site.total_bytes,
+                site.total_blocks,
+                lifetimes[i],
+                site.max_bytes,
+                site.max_blocks,
+                site.peak_bytes,
+                site.peak_blocks,
+                site.live_bytes,
+                site.live_blocks,
+            )?;
+
+            for (j, &addr) in site.stack.iter().enumerate() {
+                let frame = *frame_indices.entry(addr).or_insert_with(|| {
+                    frames.push(addr);
+                    frames.len()
+                });
+
+                let separator = if j == 0 { "" } else { "," };
+                write!(out, "{separator}{frame}")?;
+            }
+
+            write!(out, "]}}")?;
+        }
+
+        write!(out, "\n],\"ftbl\":[\"[root]\"")?;
+        for addr in frames {
+            let frame = match disassembler.get_symbol_containing(addr) {
+                Some((symbol, offset)) => format!("{addr:#x}: {symbol}+{offset:#x}"),
+                None => format!("{addr:#x}: ???"),
+            };
+
+            writeln!(out, ",")?;
+            write_json_string(&frame, out)?;
+        }
+
+        writeln!(out, "\n]}}")
+    }
+}
+
+impl Emulator {
+    /// Records every call to `malloc`, `calloc`, `realloc` and `free` in a [`HeapProfile`],
+    /// attributed to the stack they were called from. The routines are looked up by symbol name,
+    /// so they have to be linked into the executable. Disabled by default.
+    pub fn set_heap_profiling_enabled(&mut self, enabled: bool) {
+        self.heap_profile = enabled.then(HeapProfile::default);
+        self.find_heap_routines();
+    }
+
+    pub fn heap_profile(&self) -> Option<&HeapProfile> {
+        self.heap_profile.as_ref()
+    }
+
+    // the heap routines are only looked up while something needs to know about their calls
+    pub(super) fn find_heap_routines(&mut self) {
+        self.heap_routines.clear();
+
+        if self.heap_profile.is_some() || self.memory.is_memcheck_enabled() {
+            for (name, routine) in ROUTINES {
+                if let Some(addr) = self.memory.disassembler.get_symbol_addr(name) {
+                    log::info!("Tracking calls to {name} at {addr:x}");
+                    self.heap_routines.insert(addr, routine);
+                }
+            }
+        }
+    }
+
+    // runs the heap routine starting at pc until it returns, and records what it did. Returns
+    // false if there is no routine at pc.
+    pub(super) fn try_heap_call(&mut self) -> Result<bool, RVError> {
+        if self.in_heap_call {
+            return Ok(false);
+        }
+        let Some(&routine) = self.heap_routines.get(&self.pc) else {
+            return Ok(false);
+        };
+
+        let (arg0, arg1) = (self.x[A0], self.x[A1]);
+        let (return_addr, sp) = (self.x[RA], self.x[SP]);
+        // the frame pointer is still the caller's, so this misses only the call site itself
+        let mut stack = self.backtrace(HEAP_FRAME_LIMIT);
+        stack[0] = return_addr;
+
+        // set while the routine is running, so the calls it makes aren't counted twice and its
+        // bookkeeping isn't checked by memcheck
+        self.in_heap_call = true;
+        let result = loop {
+            match self.execute_next() {
+                Ok(None) if self.pc != return_addr || self.x[SP] != sp => {}
+                result => break result,
+            }
+        };
+        self.in_heap_call = false;
+
+        if result?.is_some() {
+            return Ok(true);
+        }
+
+        let now = self.inst_counter;
+        let ret = self.x[A0];
+        // realloc fails without freeing anything, unless it was asked to shrink to nothing
+        let resized = ret != 0 || arg1 == 0;
+
+        match routine {
+            HeapRoutine::Malloc => self.memory.allocate(ret, arg0, false),
+            HeapRoutine::Calloc => self.memory.allocate(ret, arg0.saturating_mul(arg1), true),
+            HeapRoutine::Realloc if resized => self.memory.reallocate(arg0, ret, arg1),
+            HeapRoutine::Realloc => {}
+            HeapRoutine::Free => self.memory.free(arg0),
+        }
+
+        let Some(ref mut profile) = self.heap_profile else {
+            return Ok(true);
+        };
+
+        match routine {
+            HeapRoutine::Malloc => profile.allocate(ret, arg0, stack, now),
+            HeapRoutine::Calloc => profile.allocate(ret, arg0.saturating_mul(arg1), stack, now),
+            HeapRoutine::Realloc => {
+                // like DHAT, a resize counts as a free and a new allocation
+                if resized {
+                    profile.free(arg0, now);
+                }
+                profile.allocate(ret, arg1, stack, now);
+            }
+            HeapRoutine::Free => profile.free(arg0, now),
+        }
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{string::String, vec};
+
+    use super::*;
+
+    #[test]
+    fn heap_profile() {
+        let mut profile = HeapProfile::default();
+        let (a, b) = (vec![0x1000, 0x2000], vec![0x1100, 0x2000]);
+
+        profile.allocate(0x10, 32, a.clone(), 0);
+        profile.allocate(0x40, 16, b.clone(), 10);
+        profile.free(0x10, 20);
+        profile.allocate(0x10, 8, a.clone(), 30);
+        profile.free(0x99, 40);
+        profile.allocate(0, 100, a, 50);
+
+        assert_eq!(profile.sites.len(), 2);
+        assert_eq!(profile.max_live_bytes, 48);
+        assert_eq!(profile.peak_time, 10);
+        assert_eq!(profile.live_bytes, 24);
+
+        let site = &profile.sites[0];
+        assert_eq!((site.total_bytes, site.total_blocks), (40, 2));
+        assert_eq!(
+            (site.live_bytes, site.max_bytes, site.peak_bytes),
+            (8, 32, 32)
+        );
+        assert_eq!(site.total_lifetimes, 20);
+
+        let mut out = String::new();
+        profile
+            .export_dhat("prog", 100, &Disassembler::new(), &mut out)
+            .unwrap();
+
+        assert!(out.contains("\"te\":100,\"tg\":10,"));
+        assert!(out.contains(
+            "{\"tb\":40,\"tbk\":2,\"tl\":90,\"mb\":32,\"mbk\":1,\"gb\":32,\"gbk\":1,\"eb\":8,\
+             \"ebk\":1,\"fs\":[1,2]}"
+        ));
+        assert!(out.contains("\"fs\":[3,2]}"));
+        assert!(
+            out.ends_with("\"[root]\",\n\"0x1000: ???\",\n\"0x2000: ???\",\n\"0x1100: ???\"\n]}\n")
+        );
+    }
+}
@@ -0,0 +1,106 @@
+// the last instructions executed, which often explain a crash without tracing the whole run
+
+use alloc::vec::Vec;
+
+use super::Emulator;
+use crate::instruction::Inst;
+
+// a ring buffer of (pc, inst), overwriting the oldest once it's full
+#[derive(Clone, Debug)]
+pub(super) struct InstHistory {
+    records: Vec<(u64, Inst)>,
+    // where the next record goes once the buffer is full
+    next: usize,
+    len: usize,
+}
+
+impl InstHistory {
+    fn new(len: usize) -> InstHistory {
+        InstHistory {
+            records: Vec::with_capacity(len),
+            next: 0,
+            len,
+        }
+    }
+
+    pub fn push(&mut self, pc: u64, inst: Inst) {
+        if self.records.len() < self.len {
+            self.records.push((pc, inst));
+        } else {
+            self.records[self.next] = (pc, inst);
+            self.next = (self.next + 1) % self.len;
+        }
+    }
+
+    // oldest first
+    fn iter(&self) -> impl Iterator<Item = &(u64, Inst)> {
+        let (newer, older) = self.records.split_at(self.next);
+        older.iter().chain(newer)
+    }
+}
+
+impl Emulator {
+    /// Keeps the last `len` instructions executed, see [`Emulator::recent_instructions`]. They
+    /// are only seen one at a time, so the jit isn't used while this is enabled. 0 disables it.
+    pub fn set_instruction_history_len(&mut self, len: usize) {
+        self.inst_history = (len > 0).then(|| InstHistory::new(len));
+    }
+
+    /// The pc and instruction of the last instructions executed, oldest first. Empty unless
+    /// enabled with [`Emulator::set_instruction_history_len`].
+    pub fn recent_instructions(&self) -> Vec<(u64, Inst)> {
+        self.inst_history
+            .as_ref()
+            .map_or_else(Vec::new, |history| history.iter().copied().collect())
+    }
+
+    // replaces the history with `records`, keeping its length
+    pub(super) fn set_recent_instructions(
+        &mut self,
+        records: impl IntoIterator<Item = (u64, Inst)>,
+    ) {
+        if let Some(ref mut history) = self.inst_history {
+            *history = InstHistory::new(history.len);
+            for (pc, inst) in records {
+                history.push(pc, inst);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::Memory;
+
+    #[test]
+    fn recent_instructions() {
+        let mut data = [0u8; 16];
+        data[0..4].copy_from_slice(&0x00100513u32.to_le_bytes()); // li a0, 1
+        data[4..8].copy_from_slice(&0x00150513u32.to_le_bytes()); // addi a0, a0, 1
+        data[8..12].copy_from_slice(&0x00150513u32.to_le_bytes()); // addi a0, a0, 1
+        data[12..16].copy_from_slice(&0x00053503u32.to_le_bytes()); // ld a0, 0(a0)
+
+        let mut emulator = Emulator::new(Memory::from_raw(&data));
+        assert!(emulator.recent_instructions().is_empty());
+
+        emulator.set_instruction_history_len(2);
+        emulator.fetch_and_execute().unwrap();
+        assert_eq!(
+            emulator.recent_instructions(),
+            [(0, Inst::decode(0x00100513).0)]
+        );
+
+        for _ in 0..3 {
+            emulator.fetch_and_execute().unwrap();
+        }
+
+        // the oldest are overwritten
+        let pcs: Vec<u64> = emulator
+            .recent_instructions()
+            .iter()
+            .map(|&(pc, _)| pc)
+            .collect();
+        assert_eq!(pcs, [8, 12]);
+    }
+}
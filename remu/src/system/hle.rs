@@ -0,0 +1,134 @@
+// high level emulation of common libc routines: calls to them are performed natively on guest
+// memory instead of being executed one instruction at a time
+
+use crate::{error::RVError, register::*};
+
+use super::Emulator;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Routine {
+    Memcpy,
+    Memset,
+    Strlen,
+}
+
+const ROUTINES: [(&str, Routine); 3] = [
+    ("memcpy", Routine::Memcpy),
+    ("memset", Routine::Memset),
+    ("strlen", Routine::Strlen),
+];
+
+// the modeled cost of the call, return and setup of a routine, on top of the per word cost
+const CALL_CYCLES: u64 = 8;
+
+impl Emulator {
+    /// Intercepts calls to `memcpy`, `memset` and `strlen`, which are looked up by symbol name,
+    /// and performs them natively. The profiler is charged a modeled number of cycles for each
+    /// call, roughly that of a word at a time implementation. Disabled by default.
+    pub fn set_hle_enabled(&mut self, enabled: bool) {
+        self.hle_routines.clear();
+
+        if enabled {
+            for (name, routine) in ROUTINES {
+                if let Some(addr) = self.memory.disassembler.get_symbol_addr(name) {
+                    log::info!("Intercepting {name} at {addr:x}");
+                    self.hle_routines.insert(addr, routine);
+                }
+            }
+        }
+    }
+
+    // performs the routine starting at pc and returns to ra, if there is one
+    pub(super) fn try_hle(&mut self) -> Result<bool, RVError> {
+        if self.try_intercept()? || self.try_malloc_fault()? || self.try_heap_call()? {
+            return Ok(true);
+        }
+
+        let Some(&routine) = self.hle_routines.get(&self.pc) else {
+            return Ok(false);
+        };
+
+        let (dst, arg, len) = (self.x[A0], self.x[A1], self.x[A2]);
+
+        let (ret, cycles) = match routine {
+            Routine::Memcpy => {
+                self.memory.copy(dst, arg, len)?;
+
+                // a load and a store per word
+                (dst, 2 * len.div_ceil(8))
+            }
+            Routine::Memset => {
+                self.memory.fill(dst, len, arg as u8)?;
+
+                (dst, len.div_ceil(8))
+            }
+            Routine::Strlen => {
+                let mut len = 0;
+                while self.memory.load::<u8>(dst + len)? != 0 {
+                    len += 1;
+                }
+
+                // a load and a zero byte check per word
+                (len, 2 * (len + 1).div_ceil(8))
+            }
+        };
+
+        let len = if routine == Routine::Strlen { ret } else { len };
+        self.taint_routine(routine, dst, arg, len);
+
+        let cycles = CALL_CYCLES + cycles;
+        self.profiler.add_cycles(self.pc, cycles);
+        // count the modeled instructions too, so timers and instruction counts stay comparable
+        self.inst_counter += cycles;
+
+        self.x[A0] = ret;
+        self.check_return(self.x[RA])?;
+        self.pc = self.x[RA];
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::Memory;
+
+    #[test]
+    fn routines() -> Result<(), RVError> {
+        let mut emulator = Emulator::new(Memory::from_raw(&[0; 0x100]));
+        emulator.hle_routines.insert(0x1000, Routine::Memcpy);
+        emulator.hle_routines.insert(0x1004, Routine::Memset);
+        emulator.hle_routines.insert(0x1008, Routine::Strlen);
+
+        let call = |emulator: &mut Emulator, addr, args: [u64; 3]| {
+            emulator.pc = addr;
+            emulator.x[RA] = 0x40;
+            emulator.x[A0] = args[0];
+            emulator.x[A1] = args[1];
+            emulator.x[A2] = args[2];
+
+            emulator.fetch_and_execute()?;
+            assert_eq!(emulator.pc, 0x40);
+            Ok::<u64, RVError>(emulator.x[A0])
+        };
+
+        assert_eq!(call(&mut emulator, 0x1004, [0x10, b'a' as u64, 5])?, 0x10);
+        assert_eq!(call(&mut emulator, 0x1000, [0x20, 0x10, 3])?, 0x20);
+        assert_eq!(call(&mut emulator, 0x1008, [0x10, 0, 0])?, 5);
+        assert_eq!(call(&mut emulator, 0x1008, [0x20, 0, 0])?, 3);
+
+        // memset with zero clears the string
+        call(&mut emulator, 0x1004, [0x10, 0, 2])?;
+        assert_eq!(emulator.memory.read_string_n(0x10, 16)?, "");
+        assert_eq!(emulator.memory.read_string_n(0x12, 16)?, "aaa");
+
+        // huge lengths fail at the end of memory instead of allocating a buffer that big first
+        assert!(call(&mut emulator, 0x1000, [0x20, 0x10, 1 << 48]).is_err());
+        assert!(call(&mut emulator, 0x1004, [0x10, b'b' as u64, u64::MAX]).is_err());
+        assert_eq!(emulator.memory.read_string_n(0x12, 16)?, "aaa");
+        assert!(emulator.memory.read_n(0x10, 1 << 48).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,127 @@
+// what the guest learns about the machine it's running on from uname and sysinfo
+
+use alloc::string::String;
+
+use super::Emulator;
+use crate::{error::RVError, register::*};
+
+/// The memory sysinfo reports when there's no memory limit
+pub const DEFAULT_TOTAL_RAM: u64 = 4 << 30;
+
+// the size of each field of struct utsname, with its nul
+const UTSNAME_FIELD_LEN: usize = 65;
+
+/// The strings uname reports, see [`Emulator::set_machine_identity`]. The kernel is always Linux
+/// and the machine riscv64, since those are what's emulated.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MachineIdentity {
+    pub nodename: String,
+    /// The kernel version, which glibc and some runtimes check a minimum of
+    pub release: String,
+    pub version: String,
+    pub domainname: String,
+}
+
+impl Default for MachineIdentity {
+    fn default() -> Self {
+        MachineIdentity {
+            nodename: "remu".into(),
+            release: "6.6.0".into(),
+            version: "#1 SMP".into(),
+            domainname: "(none)".into(),
+        }
+    }
+}
+
+impl Emulator {
+    /// What uname tells the guest about the machine
+    pub fn set_machine_identity(&mut self, identity: MachineIdentity) {
+        self.identity = identity;
+    }
+
+    pub fn machine_identity(&self) -> &MachineIdentity {
+        &self.identity
+    }
+
+    // uname(buf), filling in struct utsname. Fields longer than it has room for are cut short.
+    pub(super) fn uname(&mut self) -> Result<u64, RVError> {
+        let buf = self.x[A0];
+        let identity = &self.identity;
+        let fields = [
+            "Linux",
+            &identity.nodename,
+            &identity.release,
+            &identity.version,
+            "riscv64",
+            &identity.domainname,
+        ];
+
+        let mut utsname = [0; UTSNAME_FIELD_LEN * 6];
+        for (field, value) in utsname.chunks_mut(UTSNAME_FIELD_LEN).zip(fields) {
+            let len = value.len().min(UTSNAME_FIELD_LEN - 1);
+            field[..len].copy_from_slice(&value.as_bytes()[..len]);
+        }
+        self.memory.write_n(&utsname, buf, utsname.len() as u64)?;
+
+        Ok(0)
+    }
+
+    // sysinfo(info), filling in struct sysinfo. The memory is the memory limit, or
+    // `DEFAULT_TOTAL_RAM` without one, and what's free is what the guest hasn't used of it.
+    pub(super) fn sysinfo(&mut self) -> Result<u64, RVError> {
+        let info = self.x[A0];
+        let total = self.memory.memory_limit().unwrap_or(DEFAULT_TOTAL_RAM);
+        let free = total.saturating_sub(self.memory.usage());
+
+        let mut sysinfo = [0u8; 112];
+        let mut put = |offset: usize, bytes: &[u8]| {
+            sysinfo[offset..offset + bytes.len()].copy_from_slice(bytes);
+        };
+        put(0, &self.guest_time().as_secs().to_le_bytes()); // uptime
+        put(32, &total.to_le_bytes()); // totalram
+        put(40, &free.to_le_bytes()); // freeram
+        put(80, &1u16.to_le_bytes()); // procs
+        put(104, &1u32.to_le_bytes()); // mem_unit
+        self.memory.write_n(&sysinfo, info, sysinfo.len() as u64)?;
+
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{memory::Memory, system::Syscall};
+
+    #[test]
+    fn machine_identity() -> Result<(), RVError> {
+        let mut emulator = Emulator::new(Memory::from_raw(&[0; 0x400]));
+        emulator.set_machine_identity(MachineIdentity {
+            release: "5.15.0".into(),
+            ..MachineIdentity::default()
+        });
+
+        emulator.x[A0] = 0x100;
+        emulator.x[A7] = Syscall::Uname as u64;
+        emulator.syscall()?;
+        assert_eq!(emulator.x[A0], 0);
+        assert_eq!(emulator.memory.read_string_n(0x100, 65)?, "Linux");
+        assert_eq!(emulator.memory.read_string_n(0x100 + 2 * 65, 65)?, "5.15.0");
+        assert_eq!(
+            emulator.memory.read_string_n(0x100 + 4 * 65, 65)?,
+            "riscv64"
+        );
+
+        emulator.memory.set_memory_limit(Some(1 << 20));
+        emulator.x[A0] = 0x300;
+        emulator.x[A7] = Syscall::Sysinfo as u64;
+        emulator.syscall()?;
+        let total: u64 = emulator.memory.load(0x300 + 32)?;
+        let free: u64 = emulator.memory.load(0x300 + 40)?;
+        assert_eq!(total, 1 << 20);
+        assert_eq!(free, total - emulator.memory.usage());
+        assert_eq!(emulator.memory.load::<u32>(0x300 + 104)?, 1);
+
+        Ok(())
+    }
+}
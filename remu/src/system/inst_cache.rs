@@ -0,0 +1,81 @@
+use alloc::{vec, vec::Vec};
+
+use crate::{instruction::Inst, memory::Memory};
+
+const INST_CACHE_BITS: u64 = 12;
+const INST_CACHE_SIZE: usize = 1 << INST_CACHE_BITS;
+
+// marks an unused slot, no instruction can live at this address since it is not 2-byte aligned
+const EMPTY: u64 = u64::MAX;
+
+/// Direct-mapped cache of decoded instructions, indexed by pc.
+///
+/// Pages instructions are decoded from get marked as code in [`Memory`], and the whole cache is
+/// flushed whenever one of them is written to, so self-modifying code never runs stale decodes.
+pub struct InstCache {
+    // (pc, inst, length), allocated on first use
+    entries: Vec<(u64, Inst, u8)>,
+    generation: u64,
+    pub enabled: bool,
+}
+
+impl InstCache {
+    pub fn new() -> InstCache {
+        InstCache {
+            entries: Vec::new(),
+            generation: 0,
+            enabled: true,
+        }
+    }
+
+    #[inline]
+    fn index(pc: u64) -> usize {
+        ((pc >> 1) & (INST_CACHE_SIZE as u64 - 1)) as usize
+    }
+
+    #[inline]
+    pub fn get(&mut self, pc: u64, memory: &Memory) -> Option<(Inst, u8)> {
+        if self.generation != memory.code_generation {
+            self.invalidate();
+            self.generation = memory.code_generation;
+            return None;
+        }
+
+        match self.entries.get(Self::index(pc)) {
+            Some(&(entry_pc, inst, len)) if entry_pc == pc => Some((inst, len)),
+            _ => None,
+        }
+    }
+
+    pub fn insert(&mut self, pc: u64, decoded: (Inst, u8), memory: &mut Memory) {
+        // code on the stack can't be tracked, so never cache it
+        if !self.enabled || pc >> 56 == 0xFF {
+            return;
+        }
+
+        if self.entries.is_empty() {
+            self.entries = vec![(EMPTY, Inst::Error(0), 0); INST_CACHE_SIZE];
+        }
+
+        memory.mark_code(pc);
+        self.generation = memory.code_generation;
+        self.entries[Self::index(pc)] = (pc, decoded.0, decoded.1);
+    }
+
+    pub fn invalidate(&mut self) {
+        for entry in &mut self.entries {
+            entry.0 = EMPTY;
+        }
+    }
+}
+
+// the cache only ever holds derived state, so clones (like time travel snapshots) start empty
+// instead of copying it
+impl Clone for InstCache {
+    fn clone(&self) -> Self {
+        InstCache {
+            enabled: self.enabled,
+            ..InstCache::new()
+        }
+    }
+}
@@ -0,0 +1,182 @@
+// guest functions replaced by rust closures, to stub out printing, make rand return a fixed
+// sequence or mock a slow routine. A call to one runs the closure instead and returns to ra.
+
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+};
+
+use super::Emulator;
+use crate::{error::RVError, register::*};
+
+/// Runs in place of a guest function, see [`Emulator::intercept`]. Clones of the emulator share
+/// their handlers, which can be on other threads.
+pub trait InterceptHandler: Send + Sync {
+    /// Runs the function, whose arguments are in a0 through a7 and on the stack, and returns the
+    /// value for a0. Errors stop execution the way a failed instruction does.
+    fn call(&self, emulator: &mut Emulator) -> Result<u64, RVError>;
+}
+
+impl<F: Fn(&mut Emulator) -> Result<u64, RVError> + Send + Sync> InterceptHandler for F {
+    fn call(&self, emulator: &mut Emulator) -> Result<u64, RVError> {
+        self(emulator)
+    }
+}
+
+#[derive(Clone)]
+pub(super) struct Intercept {
+    // looked up again when the program is replaced, if it's a symbol
+    symbol: Option<String>,
+    handler: Arc<dyn InterceptHandler>,
+}
+
+impl Emulator {
+    /// Runs `handler` whenever the guest calls the function `symbol`, instead of the function,
+    /// then returns to the caller. Fails with [`RVError::InvalidLabel`] if there's no such
+    /// symbol, like for a function in a shared library that isn't loaded yet.
+    pub fn intercept(
+        &mut self,
+        symbol: &str,
+        handler: impl InterceptHandler + 'static,
+    ) -> Result<(), RVError> {
+        self.add_intercept(symbol, Arc::new(handler))
+    }
+
+    pub(super) fn add_intercept(
+        &mut self,
+        symbol: &str,
+        handler: Arc<dyn InterceptHandler>,
+    ) -> Result<(), RVError> {
+        let addr = self
+            .memory
+            .disassembler
+            .get_symbol_addr(symbol)
+            .ok_or(RVError::InvalidLabel)?;
+
+        self.intercepts.insert(
+            addr,
+            Intercept {
+                symbol: Some(symbol.to_string()),
+                handler,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Like [`Emulator::intercept`], for the function at `addr`, which doesn't need a symbol
+    pub fn intercept_addr(&mut self, addr: u64, handler: impl InterceptHandler + 'static) {
+        self.intercepts.insert(
+            addr,
+            Intercept {
+                symbol: None,
+                handler: Arc::new(handler),
+            },
+        );
+    }
+
+    /// Lets the function `symbol` run again
+    pub fn remove_intercept(&mut self, symbol: &str) {
+        self.intercepts
+            .retain(|_, intercept| intercept.symbol.as_deref() != Some(symbol));
+    }
+
+    // moves the intercepted symbols to where they are in a new program, dropping those it
+    // doesn't have and those intercepted by address
+    pub(super) fn find_intercepts(&mut self) {
+        let intercepts = core::mem::take(&mut self.intercepts);
+
+        self.intercepts = intercepts
+            .into_values()
+            .filter_map(|intercept| {
+                let addr = self
+                    .memory
+                    .disassembler
+                    .get_symbol_addr(intercept.symbol.as_deref()?)?;
+                Some((addr, intercept))
+            })
+            .collect::<BTreeMap<_, _>>();
+    }
+
+    // runs the handler of the function starting at pc and returns to ra, if there is one
+    pub(super) fn try_intercept(&mut self) -> Result<bool, RVError> {
+        let Some(intercept) = self.intercepts.get(&self.pc) else {
+            return Ok(false);
+        };
+
+        let handler = intercept.handler.clone();
+        self.x[A0] = handler.call(self)?;
+
+        self.check_return(self.x[RA])?;
+        self.pc = self.x[RA];
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use core::sync::atomic::{AtomicU64, Ordering};
+
+    use super::*;
+    use crate::{assembler::assemble, memory::Memory};
+
+    // calls rand twice and exits with the sum, rand itself returning 99
+    fn program() -> Vec<u8> {
+        let mut data = Vec::new();
+        for line in [
+            "jal 1c",
+            "mv s0, a0",
+            "jal 1c",
+            "add a0, a0, s0",
+            "li a7, 93",
+            "ecall",
+            "nop",
+            "li a0, 99",
+            "ret",
+        ] {
+            data.extend(assemble(line, data.len() as u64).unwrap());
+        }
+
+        data.resize(0x100, 0);
+        data
+    }
+
+    fn emulator() -> Emulator {
+        let mut emulator = Emulator::new(Memory::from_raw(&program()));
+        emulator
+            .memory
+            .disassembler
+            .import_symbols("1c rand", 0)
+            .unwrap();
+        emulator
+    }
+
+    #[test]
+    fn intercept() -> Result<(), RVError> {
+        assert_eq!(emulator().run(false)?, 198);
+
+        let mut emulator = emulator();
+        let next = Arc::new(AtomicU64::new(3));
+        let sequence = next.clone();
+        emulator.intercept("rand", move |_: &mut Emulator| {
+            Ok(sequence.fetch_add(1, Ordering::Relaxed))
+        })?;
+        assert_eq!(emulator.run(false)?, 7);
+        assert_eq!(next.load(Ordering::Relaxed), 5);
+
+        let mut emulator = self::emulator();
+        emulator.intercept("rand", |_: &mut Emulator| Ok(1))?;
+        emulator.remove_intercept("rand");
+        assert_eq!(emulator.run(false)?, 198);
+
+        assert!(matches!(
+            emulator.intercept("srand", |_: &mut Emulator| Ok(0)),
+            Err(RVError::InvalidLabel)
+        ));
+
+        Ok(())
+    }
+}
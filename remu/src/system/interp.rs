@@ -0,0 +1,189 @@
+// threaded interpreter: executes whole pre-decoded basic blocks instead of fetching and decoding
+// every instruction individually
+
+use alloc::vec::Vec;
+
+use crate::{
+    error::RVError,
+    instruction::Inst,
+    ir::{self, Op, Operand},
+};
+
+use super::{
+    block_cache::{Block, MAX_BLOCK_LEN},
+    Emulator,
+};
+
+// whether `inst` can change pc to anything but the next instruction, or exit the program
+fn ends_block(inst: Inst) -> bool {
+    matches!(
+        inst,
+        Inst::Jal { .. }
+            | Inst::Jalr { .. }
+            | Inst::Beq { .. }
+            | Inst::Bne { .. }
+            | Inst::Blt { .. }
+            | Inst::Bltu { .. }
+            | Inst::Bge { .. }
+            | Inst::Bgeu { .. }
+            | Inst::FenceI
+            | Inst::Ecall
+            | Inst::Ebreak
+            | Inst::Mret
+            | Inst::Sret
+            | Inst::Error(_)
+    )
+}
+
+impl Emulator {
+    fn decode_block(&mut self) -> Result<Block, RVError> {
+        let mut insts = Vec::new();
+        let mut pc = self.pc;
+
+        while insts.len() < MAX_BLOCK_LEN {
+            let inst_data = match self.memory.load::<u32>(pc) {
+                Ok(inst_data) => inst_data,
+                // the fault belongs to the instruction at pc, which will report it when reached
+                Err(_) if !insts.is_empty() => break,
+                Err(e) => return Err(e),
+            };
+
+            let (inst, incr) = Inst::decode(inst_data);
+            insts.push((inst, incr));
+            pc += incr as u64;
+
+            if ends_block(inst) {
+                break;
+            }
+        }
+
+        // blocks are only ever entered at the start, so nothing inside is a jump target
+        Ok(ir::optimize(self.pc, &insts, |_| false).into())
+    }
+
+    // executes an op made up of several instructions, see `ir::Op`
+    fn execute_op(&mut self, op: Op) -> Result<(), RVError> {
+        match op {
+            Op::Inst(..) => unreachable!("single instructions go through execute"),
+            Op::Nop(_) => {}
+            Op::Li { rd, imm, .. } => {
+                self.x[rd] = imm;
+            }
+            Op::LoadGlobal {
+                base,
+                upper,
+                rd,
+                addr,
+                auipc_len,
+                ..
+            } => {
+                self.x[base] = upper;
+
+                match self.memory.load(addr) {
+                    Ok(value) => self.x[rd] = value,
+                    Err(e) => {
+                        // fault at the ld, as if the auipc ran on its own
+                        self.pc += auipc_len as u64;
+                        self.inst_counter += 1;
+                        return Err(e);
+                    }
+                }
+            }
+            Op::ZeroExtend { rd, rs1, mask, .. } => {
+                self.x[rd] = self.x[rs1] & mask;
+            }
+            Op::CmpBranch {
+                rd,
+                rs1,
+                rs2,
+                signed,
+                branch_if,
+                offset,
+                cmp_len,
+                ..
+            } => {
+                let rhs = match rs2 {
+                    Operand::Reg(rs2) => self.x[rs2],
+                    Operand::Imm(imm) => imm,
+                };
+
+                let less = if signed {
+                    (self.x[rs1] as i64) < rhs as i64
+                } else {
+                    self.x[rs1] < rhs
+                };
+                self.x[rd] = less as u64;
+
+                if less == branch_if {
+                    let branch_pc = self.pc + cmp_len as u64;
+                    // the increment below moves past the op
+                    self.pc = branch_pc
+                        .wrapping_add(offset as u64)
+                        .wrapping_sub(op.len() as u64);
+                }
+            }
+        }
+
+        self.pc = self.pc.wrapping_add(op.len() as u64);
+        self.inst_counter += op.inst_count();
+        self.profiler.tick(self.pc);
+
+        Ok(())
+    }
+
+    /// Executes instructions up to and including the next branch. This is what `run(false)` uses,
+    /// and behaves exactly like calling [`Emulator::fetch_and_execute`] in a loop.
+    pub fn execute_interp_block(&mut self) -> Result<Option<u64>, RVError> {
+        // the slow path handles everything that has to be checked before every instruction. Ops can
+        // span two instructions, so interrupts due after the next one are stepped into exactly.
+        if self.exit_code.is_some()
+            || self.next_interrupt <= self.inst_counter + 1
+            || self.profile_start_point.is_some()
+            || self.roi_start_point.is_some()
+            || self.profiler.running
+            || self.checks_every_instruction()
+            || !self.inst_cache.enabled
+            || self.pc >> 56 == 0xFF
+        {
+            return self.execute_next();
+        }
+
+        if self.try_hle()? {
+            return Ok(self.exit_code);
+        }
+
+        let block = match self.block_cache.get(self.pc, &self.memory) {
+            Some(block) => block,
+            None => {
+                let block = self.decode_block()?;
+                self.block_cache
+                    .insert(self.pc, block.clone(), &mut self.memory);
+                block
+            }
+        };
+
+        let generation = self.memory.code_generation;
+
+        for &op in block.iter() {
+            // stop early if an interrupt is due before the op is done
+            if self.inst_counter + op.inst_count() > self.next_interrupt {
+                break;
+            }
+
+            match op {
+                // blocks never run while profiling
+                Op::Inst(inst, incr) => self.execute::<false>(inst, incr as u64)?,
+                op => self.execute_op(op)?,
+            }
+
+            // the rest of the block may have been overwritten
+            if self.memory.code_generation != generation {
+                break;
+            }
+        }
+
+        self.update_memory_usage()?;
+
+        Ok(self.exit_code)
+    }
+}
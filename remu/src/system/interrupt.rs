@@ -0,0 +1,58 @@
+use alloc::sync::Arc;
+
+use super::Emulator;
+
+/// A callback run between two instructions. It can freely modify registers and memory, or set `pc`
+/// to force a jump.
+pub type InterruptHandler = Arc<dyn Fn(&mut Emulator) + Send + Sync>;
+
+impl Emulator {
+    /// Schedules `handler` to run once `after_n_insts` more instructions have been executed.
+    /// Handlers scheduled for the same instruction run in the order they were scheduled.
+    pub fn schedule_interrupt<F>(&mut self, after_n_insts: u64, handler: F)
+    where
+        F: Fn(&mut Emulator) + Send + Sync + 'static,
+    {
+        let at = self.inst_counter.saturating_add(after_n_insts);
+
+        self.interrupts
+            .entry(at)
+            .or_default()
+            .push(Arc::new(handler));
+
+        self.next_interrupt = self.next_interrupt.min(at);
+    }
+
+    /// Runs every handler that has become due. Handlers may schedule further interrupts.
+    pub(super) fn deliver_interrupts(&mut self) {
+        while let Some(entry) = self.interrupts.first_entry() {
+            if *entry.key() > self.inst_counter {
+                break;
+            }
+
+            for handler in entry.remove() {
+                handler(self);
+            }
+        }
+
+        self.update_next_interrupt();
+    }
+
+    // the fuel limit is checked along with the interrupts, so it costs nothing until it's due
+    pub(super) fn update_next_interrupt(&mut self) {
+        let next = self.interrupts.keys().next().copied().unwrap_or(u64::MAX);
+        self.next_interrupt = next.min(self.fuel_limit.unwrap_or(u64::MAX));
+    }
+
+    pub fn pending_interrupts(&self) -> usize {
+        self.interrupts
+            .values()
+            .map(|handlers| handlers.len())
+            .sum()
+    }
+
+    pub fn clear_interrupts(&mut self) {
+        self.interrupts = Default::default();
+        self.update_next_interrupt();
+    }
+}
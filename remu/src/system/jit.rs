@@ -1,15 +1,28 @@
-use std::{collections::HashMap, mem, num::NonZeroU64};
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    mem,
+    num::NonZeroU64,
+};
 
 use dynasm::dynasm;
-use dynasmrt::{x64::Assembler, AssemblyOffset, DynasmApi, DynasmLabelApi, ExecutableBuffer};
+use dynasmrt::{
+    x64::Assembler, AssemblyOffset, DynamicLabel, DynasmApi, DynasmLabelApi, ExecutableBuffer,
+};
 
 use crate::{
     instruction::Inst,
+    ir::{self, Op, Operand},
     profiler::Profiler,
-    register::{Reg, RA},
+    register::{Reg, A0, A1, A2, A3, A4, A5, RA, S0, S1, SP},
     system::Emulator,
 };
 
+// compiled code and the helpers it calls use the System V calling convention on every host. Rust
+// can declare sysv64 functions on any x86_64 target, Windows included, so the code generator
+// never has to know the host's own convention.
+type CompiledFn = extern "sysv64" fn(*mut Emulator, *mut u64, *mut u64);
+
 macro_rules! my_dynasm {
     ($ops:ident $($t:tt)*) => {
         dynasm!($ops
@@ -23,24 +36,44 @@ macro_rules! my_dynasm {
 }
 
 macro_rules! load_reg {
-    ($ops:ident, $store_loc:ident <= $reg:expr) => {
-        my_dynasm!($ops
-            ; mov $store_loc, QWORD [a_registers + (8 * $reg.0 as i32)]
-        )
+    ($ops:ident, $regs:expr, $store_loc:ident <= $reg:expr) => {
+        match $regs.host($reg) {
+            Some(host) => my_dynasm!($ops
+                ; mov $store_loc, Rq(host)
+            ),
+            None => my_dynasm!($ops
+                ; mov $store_loc, QWORD [a_registers + (8 * $reg.0 as i32)]
+            ),
+        }
     };
 }
 
 macro_rules! store_reg {
-    ($ops:ident, $out_reg:ident => $reg:expr) => {
-        my_dynasm!($ops
-            ; mov QWORD [a_registers + (8 * $reg.0 as i32)], $out_reg
-        )
+    ($ops:ident, $regs:expr, $out_reg:ident => $reg:expr) => {
+        match $regs.host($reg) {
+            Some(host) => my_dynasm!($ops
+                ; mov Rq(host), $out_reg
+            ),
+            None => my_dynasm!($ops
+                ; mov QWORD [a_registers + (8 * $reg.0 as i32)], $out_reg
+            ),
+        }
+    };
+}
+
+/// calls an extern function that reads or writes guest registers, so the allocated ones are
+/// written back before and reloaded after
+macro_rules! call_extern_spilled {
+    ($ops:ident, $regs:expr, $addr:expr) => {
+        $regs.spill(&mut $ops);
+        call_extern!($ops, $addr);
+        $regs.reload(&mut $ops);
     };
 }
 
 macro_rules! call_extern {
     ($ops:ident, $addr:expr) => {my_dynasm!($ops
-        ; mov rax, QWORD $addr as _
+        ; mov rax, QWORD $addr as usize as _
         ; call rax
 
         ; mov rdi, [rsp + 0x8]
@@ -67,13 +100,13 @@ macro_rules! pipeline_stall {
 }
 
 macro_rules! branch_impl {
-    ($btype:ident : $ops:ident, $profile:expr, $dynamic_labels:expr, $pc:expr, $rs1:expr, $rs2:expr, $offset:expr) => {
+    ($btype:ident : $ops:ident, $regs:expr, $profile:expr, $dynamic_labels:expr, $pc:expr, $rs1:expr, $rs2:expr, $offset:expr) => {
         let branch_not_taken_label = $ops.new_dynamic_label();
         my_dynasm!($ops
             ;; if $profile { pipeline_stall!($ops, x.$rs1, x.$rs2); }
 
-            ;; load_reg!($ops, r9 <= $rs1)
-            ;; load_reg!($ops, r10 <= $rs2)
+            ;; load_reg!($ops, $regs, r9 <= $rs1)
+            ;; load_reg!($ops, $regs, r10 <= $rs2)
             ; cmp r9, r10
             ; $btype =>branch_not_taken_label
             ;; if $profile { call_extern!($ops, branch_taken); }
@@ -107,6 +140,11 @@ unsafe extern "sysv64" fn add_load_delay_x(emu: *mut Emulator, addr: u64, rd: Re
     emulator.profiler.add_load_delay_x(rd, addr, emulator.pc);
 }
 
+unsafe extern "sysv64" fn add_store(emu: *mut Emulator, addr: u64) {
+    let emulator = unsafe { &mut *emu };
+    emulator.profiler.add_store(addr, emulator.pc);
+}
+
 unsafe extern "sysv64" fn profiler_tick(emu: *mut Emulator) {
     let emulator = unsafe { &mut *emu };
     emulator.profiler.tick(emulator.pc);
@@ -129,15 +167,21 @@ unsafe extern "sysv64" fn profiler_pipeline_stall_x(emu: *mut Emulator, reg1: Re
     emulator.profiler.tick(emulator.pc);
 }
 
-/// returns false if the syscall fails, otherwise true
+/// returns true once the guest has exited or execve replaced the program, so the function stops
+/// instead of running on
 unsafe extern "sysv64" fn syscall(emu: *mut Emulator) -> bool {
     let emulator = unsafe { &mut *emu };
-    emulator.syscall().is_ok()
+    let exec_count = emulator.exec_count;
+    let _ = emulator.syscall();
+    emulator.exit_code.is_some() || emulator.exec_count != exec_count
 }
 
-unsafe extern "sysv64" fn execute_block(emu: *mut Emulator) {
+/// returns true if the guest exited or replaced the program inside the call, like `syscall`
+unsafe extern "sysv64" fn execute_block(emu: *mut Emulator) -> bool {
     let emulator = unsafe { &mut *emu };
-    emulator.execute_block().expect("Failed to execute block");
+    let exec_count = emulator.exec_count;
+    let exit_code = emulator.execute_block().expect("Failed to execute block");
+    exit_code.is_some() || emulator.exec_count != exec_count
 }
 
 unsafe extern "sysv64" fn branch_not_taken(emu: *mut Emulator) {
@@ -166,6 +210,12 @@ unsafe extern "sysv64" fn load_u64(emu: *mut Emulator, offset: u64) -> u64 {
 unsafe extern "sysv64" fn start_profile(emu: *mut Emulator) {
     let emulator = unsafe { &mut *emu };
     emulator.profiler.running = true;
+    emulator.profiler.start_function(emulator.pc);
+}
+
+unsafe extern "sysv64" fn start_region_of_interest(emu: *mut Emulator) {
+    let emulator = unsafe { &mut *emu };
+    emulator.start_region_of_interest();
 }
 
 unsafe extern "sysv64" fn end_profile(emu: *mut Emulator) {
@@ -191,22 +241,253 @@ unsafe extern "sysv64" fn log_inst(emu: *mut Emulator) {
 
 const ZERO: i32 = 0;
 
+// the ops a function is compiled from. Instructions are combined unless profiling, which counts
+// cycles per instruction. Branch targets have to stay at the start of an op, so they can be
+// jumped to.
+fn function_body(pc: u64, instructions: &[(Inst, u8)], profile: bool) -> Vec<Op> {
+    if profile {
+        instructions
+            .iter()
+            .map(|&(inst, step)| Op::Inst(inst, step))
+            .collect()
+    } else {
+        let targets = branch_targets(pc, instructions);
+        ir::optimize(pc, instructions, |pc| targets.contains(&pc))
+    }
+}
+
+// the targets of every branch in a function
+fn branch_targets(mut pc: u64, instructions: &[(Inst, u8)]) -> HashSet<u64> {
+    let mut targets = HashSet::new();
+
+    for &(inst, step) in instructions {
+        if let Inst::Beq { offset, .. }
+        | Inst::Bne { offset, .. }
+        | Inst::Blt { offset, .. }
+        | Inst::Bltu { offset, .. }
+        | Inst::Bge { offset, .. }
+        | Inst::Bgeu { offset, .. } = inst
+        {
+            targets.insert(pc.wrapping_add(offset as u64));
+        }
+
+        pc += step as u64;
+    }
+
+    targets
+}
+
+// emits an op made up of several instructions, see `ir::Op`
+fn compile_fused(
+    ops: &mut Assembler,
+    regs: &RegAlloc,
+    dynamic_labels: &HashMap<u64, DynamicLabel>,
+    pc: u64,
+    op: Op,
+) {
+    match op {
+        Op::Inst(..) => unreachable!("single instructions are compiled directly"),
+        Op::Nop(_) => {}
+        Op::Li { rd, imm, .. } => {
+            my_dynasm!(ops
+                ; mov r9, QWORD imm as i64
+                ;; store_reg!(ops, regs, r9 => rd)
+            );
+        }
+        Op::LoadGlobal {
+            base,
+            upper,
+            rd,
+            addr,
+            ..
+        } => {
+            my_dynasm!(ops
+                ; mov r9, QWORD upper as i64
+                ;; store_reg!(ops, regs, r9 => base)
+
+                ; mov rsi, QWORD addr as i64
+                ;; call_extern!(ops, load_u64)
+                ;; store_reg!(ops, regs, rax => rd)
+            );
+        }
+        Op::ZeroExtend { rd, rs1, mask, .. } => {
+            my_dynasm!(ops
+                ;; load_reg!(ops, regs, r9 <= rs1)
+                ; mov r10, QWORD mask as i64
+                ; and r9, r10
+                ;; store_reg!(ops, regs, r9 => rd)
+            );
+        }
+        Op::CmpBranch {
+            rd,
+            rs1,
+            rs2,
+            signed,
+            branch_if,
+            offset,
+            cmp_len,
+            ..
+        } => {
+            load_reg!(ops, regs, r9 <= rs1);
+            match rs2 {
+                Operand::Reg(rs2) => load_reg!(ops, regs, r10 <= rs2),
+                Operand::Imm(imm) => my_dynasm!(ops
+                    ; mov r10, QWORD imm as i64
+                ),
+            }
+
+            my_dynasm!(ops
+                ; xor eax, eax
+                ; cmp r9, r10
+            );
+            if signed {
+                my_dynasm!(ops ; setl al);
+            } else {
+                my_dynasm!(ops ; setb al);
+            }
+            store_reg!(ops, regs, rax => rd);
+
+            let target = (pc + cmp_len as u64).wrapping_add(offset as u64);
+            let not_taken = ops.new_dynamic_label();
+
+            my_dynasm!(ops
+                ; test rax, rax
+            );
+            if branch_if {
+                my_dynasm!(ops ; jz =>not_taken);
+            } else {
+                my_dynasm!(ops ; jnz =>not_taken);
+            }
+
+            my_dynasm!(ops
+                ; add QWORD [a_pc], target.wrapping_sub(pc) as i32
+
+                ; mov r9, a_emu => Emulator.inst_counter
+                ; add r9, 2
+                ; mov a_emu => Emulator.inst_counter, r9
+
+                ; jmp =>dynamic_labels[&target]
+                ;=>not_taken
+            );
+        }
+    }
+
+    my_dynasm!(ops
+        ; add QWORD [a_pc], op.len() as _
+
+        ; mov r9, a_emu => Emulator.inst_counter
+        ; add r9, op.inst_count() as _
+        ; mov a_emu => Emulator.inst_counter, r9
+    );
+}
+
+// guest registers worth keeping in host registers
+const ALLOCATABLE: [Reg; 9] = [SP, A0, A1, A2, A3, A4, A5, S0, S1];
+
+// rbx, rbp, r12-r15: callee saved, so they survive calls to extern functions
+const HOST_REGISTERS: [u8; 6] = [3, 5, 12, 13, 14, 15];
+
+// the integer registers read or written by the instructions the jit can compile, on their own or
+// as part of an op
+fn registers(inst: Inst) -> Vec<Reg> {
+    match inst {
+        Inst::Lui { rd, .. } | Inst::Auipc { rd, .. } | Inst::Jal { rd, .. } => vec![rd],
+        Inst::Ld { rd, rs1, .. }
+        | Inst::Slli { rd, rs1, .. }
+        | Inst::Srli { rd, rs1, .. }
+        | Inst::Slti { rd, rs1, .. }
+        | Inst::Sltiu { rd, rs1, .. }
+        | Inst::Addi { rd, rs1, .. }
+        | Inst::Addiw { rd, rs1, .. }
+        | Inst::Jalr { rd, rs1, .. } => vec![rd, rs1],
+        Inst::Sd { rs1, rs2, .. }
+        | Inst::Beq { rs1, rs2, .. }
+        | Inst::Bne { rs1, rs2, .. }
+        | Inst::Blt { rs1, rs2, .. }
+        | Inst::Bltu { rs1, rs2, .. }
+        | Inst::Bge { rs1, rs2, .. }
+        | Inst::Bgeu { rs1, rs2, .. } => vec![rs1, rs2],
+        Inst::Add { rd, rs1, rs2 }
+        | Inst::Addw { rd, rs1, rs2 }
+        | Inst::Slt { rd, rs1, rs2 }
+        | Inst::Sltu { rd, rs1, rs2 } => vec![rd, rs1, rs2],
+        _ => vec![],
+    }
+}
+
+/// Keeps the most used of [`ALLOCATABLE`] in host registers for a whole function, instead of
+/// going through memory on every access. They are loaded on entry, and written back on exit and
+/// around extern calls that touch guest registers.
+struct RegAlloc {
+    // (guest, host)
+    allocated: Vec<(Reg, u8)>,
+}
+
+impl RegAlloc {
+    fn new(instructions: &[(Inst, u8)]) -> RegAlloc {
+        let mut uses = [0usize; 32];
+        for (inst, _) in instructions {
+            for reg in registers(*inst) {
+                uses[reg.0 as usize] += 1;
+            }
+        }
+
+        let mut candidates: Vec<Reg> = ALLOCATABLE
+            .into_iter()
+            .filter(|reg| uses[reg.0 as usize] > 0)
+            .collect();
+        // stable, so ties keep the order of ALLOCATABLE
+        candidates.sort_by_key(|reg| Reverse(uses[reg.0 as usize]));
+
+        RegAlloc {
+            allocated: candidates.into_iter().zip(HOST_REGISTERS).collect(),
+        }
+    }
+
+    fn host(&self, reg: Reg) -> Option<u8> {
+        self.allocated
+            .iter()
+            .find(|(guest, _)| *guest == reg)
+            .map(|(_, host)| *host)
+    }
+
+    fn spill(&self, ops: &mut Assembler) {
+        for &(reg, host) in &self.allocated {
+            my_dynasm!(ops
+                ; mov QWORD [a_registers + (8 * reg.0 as i32)], Rq(host)
+            );
+        }
+    }
+
+    fn reload(&self, ops: &mut Assembler) {
+        for &(reg, host) in &self.allocated {
+            my_dynasm!(ops
+                ; mov Rq(host), QWORD [a_registers + (8 * reg.0 as i32)]
+            );
+        }
+    }
+}
+
 /// stores a jit recompiled version of a RISC-V function
 ///
 /// the jit compilation block is given 3 arguments:
-/// - rcx/emu: *mut Emulator
-/// - rdx/pc: *mut u64
-/// - r8x/registers: *mut u64
+/// - rdi/emu: *mut Emulator
+/// - rsi/pc: *mut u64
+/// - rdx/registers: *mut u64
 pub struct RVFunction {
     code: ExecutableBuffer,
     start: AssemblyOffset,
 }
 
 impl RVFunction {
+    /// The number of bytes of host code
+    pub fn code_size(&self) -> usize {
+        self.code.len()
+    }
+
     pub fn run(&self, emulator: &mut Emulator) {
         // arguments: emulator, pc, x registers
-        let func: extern "sysv64" fn(*mut Emulator, *mut u64, *mut u64) =
-            unsafe { mem::transmute(self.code.ptr(self.start)) };
+        let func: CompiledFn = unsafe { mem::transmute(self.code.ptr(self.start)) };
 
         // emulator
         let emu = emulator as *mut Emulator;
@@ -219,25 +500,30 @@ impl RVFunction {
 
         func(emu, pc, x);
     }
+}
 
-    /// compiles function starting at current pc, until the `ret` instruction is reached
-    pub fn compile(emulator: &mut Emulator, profile: bool) -> RVFunction {
-        log::debug!("COMPILING FUNCTION {:x}", emulator.pc);
-
-        let mut ops = Assembler::new().expect("Failed to create assembler");
-        let start = ops.offset();
+/// Everything needed to compile a function, so it can be done away from the emulator.
+pub struct CompileJob {
+    pub pc: u64,
+    instructions: Vec<(Inst, u8)>,
+    profile: bool,
+    profile_start_point: Option<NonZeroU64>,
+    roi_start_point: Option<NonZeroU64>,
+    // see `JitFunctions::invalidate`
+    pub generation: u64,
+}
 
+impl CompileJob {
+    /// reads the function starting at current pc, until the `ret` instruction is reached. Returns
+    /// None if it contains an invalid instruction, or can never be compiled.
+    pub fn new(emulator: &Emulator, profile: bool) -> Option<CompileJob> {
         let mut pc = emulator.pc;
         let mut instructions = Vec::new();
-        let mut dynamic_labels = HashMap::new();
 
         // prepass
         let mut done = false;
         while !done {
-            let inst_data = emulator
-                .memory
-                .load::<u32>(pc)
-                .expect("Failed to load instruction");
+            let inst_data = emulator.memory.load::<u32>(pc).ok()?;
             let (inst, step) = Inst::decode(inst_data);
 
             match inst {
@@ -246,10 +532,18 @@ impl RVFunction {
                     if inst == 0 {
                         break;
                     } else {
-                        panic!("Invalid instruction: {inst}");
+                        log::warn!(
+                            "Invalid instruction in function at {:x}: {inst}",
+                            emulator.pc
+                        );
+                        return None;
                     }
                 }
 
+                // compiled code can't throw itself away, so these functions are always
+                // interpreted
+                Inst::FenceI => return None,
+
                 // technically JALR could be used for an intra-function jump, but in practice no
                 // code generator will do this (or at least I hope)
                 Inst::Jalr { rd, rs1, offset } => {
@@ -262,26 +556,86 @@ impl RVFunction {
                 _ => {}
             }
 
-            // create dynamic label for each instruction to allow branches to work
             instructions.push((inst, step));
-            dynamic_labels.insert(pc, ops.new_dynamic_label());
-
             pc += step as u64;
         }
 
+        let body = function_body(emulator.pc, &instructions, profile);
+
+        // branches can only jump to the start of an op in the same function
+        let mut op_pc = emulator.pc;
+        let starts: HashSet<u64> = body
+            .iter()
+            .map(|op| {
+                let start = op_pc;
+                op_pc += op.len() as u64;
+                start
+            })
+            .collect();
+        if !branch_targets(emulator.pc, &instructions).is_subset(&starts) {
+            log::debug!("Function at {:x} branches out of itself", emulator.pc);
+            return None;
+        }
+
+        Some(CompileJob {
+            pc: emulator.pc,
+            instructions,
+            profile,
+            profile_start_point: emulator.profile_start_point,
+            roi_start_point: emulator.roi_start_point,
+            generation: 0,
+        })
+    }
+
+    /// Returns None if the function uses an instruction the jit can't compile yet
+    pub fn compile(self) -> Option<RVFunction> {
+        let CompileJob {
+            pc,
+            instructions,
+            profile,
+            profile_start_point,
+            roi_start_point,
+            ..
+        } = self;
+
+        log::debug!("COMPILING FUNCTION {pc:x}");
+
+        let mut ops = Assembler::new().expect("Failed to create assembler");
+        let start = ops.offset();
+
+        let regs = RegAlloc::new(&instructions);
+
+        let body = function_body(pc, &instructions, profile);
+
+        // create dynamic label for each op to allow branches to work
+        let mut dynamic_labels = HashMap::new();
+        let mut label_pc = pc;
+        for op in &body {
+            dynamic_labels.insert(label_pc, ops.new_dynamic_label());
+            label_pc += op.len() as u64;
+        }
+
         my_dynasm!(ops
+            ; push rbx
+            ; push rbp
+            ; push r12
+            ; push r13
+            ; push r14
+            ; push r15
             ; sub rsp, 0x28
             ; mov [rsp + 0x8], rdi
             ; mov [rsp + 0x10], rsi
             ; mov [rsp + 0x20], rdx
         );
 
-        let mut started_profile = false;
+        regs.reload(&mut ops);
 
-        let mut pc = emulator.pc;
+        let exit = ops.new_dynamic_label();
+        let mut started_profile = false;
+        let mut pc = pc;
 
-        for (inst, step) in instructions {
-            log::debug!("{pc:16x} {}", inst.fmt(pc));
+        for op in body {
+            log::debug!("{pc:16x} {op:?}");
 
             let current_label = *dynamic_labels
                 .get(&pc)
@@ -293,21 +647,48 @@ impl RVFunction {
                 // ;; call_extern!(ops, debug_print_registers)
             );
 
-            if NonZeroU64::new(pc) == emulator.profile_start_point {
+            if NonZeroU64::new(pc) == roi_start_point {
+                call_extern!(ops, start_region_of_interest);
+            }
+
+            if NonZeroU64::new(pc) == profile_start_point {
                 started_profile = true;
                 call_extern!(ops, start_profile);
             }
 
+            let (inst, step) = match op {
+                Op::Inst(inst, step) => (inst, step),
+                op => {
+                    compile_fused(&mut ops, &regs, &dynamic_labels, pc, op);
+                    pc += op.len() as u64;
+                    continue;
+                }
+            };
+
             match inst {
                 Inst::Fence => {} // noop
+                Inst::FenceI => unreachable!("functions with fence.i aren't compiled"),
                 Inst::Ecall => {
                     if profile {
                         call_extern!(ops, profiler_tick);
                     }
 
-                    call_extern!(ops, syscall);
+                    let running = ops.new_dynamic_label();
+                    call_extern_spilled!(ops, regs, syscall);
+                    my_dynasm!(ops
+                        ; test al, al
+                        ; jz =>running
+                        ; add QWORD [a_pc], step as _
+                        ; mov r9, a_emu => Emulator.inst_counter
+                        ; add r9, 1
+                        ; mov a_emu => Emulator.inst_counter, r9
+                        ; jmp =>exit
+                        ;=>running
+                    );
                 }
                 Inst::Ebreak => {} // noop
+                // system mode doesn't use the jit, and these do nothing outside of it
+                Inst::Mret | Inst::Sret | Inst::Wfi | Inst::SfenceVma => {}
                 Inst::Error(e) => {
                     log::error!("{e}");
                 }
@@ -316,14 +697,14 @@ impl RVFunction {
                         ;; if profile { call_extern!(ops, profiler_tick); }
 
                         ; mov r9, imm
-                        ;; store_reg!(ops, r9 => rd)
+                        ;; store_reg!(ops, regs, r9 => rd)
                     );
                 }
                 Inst::Ld { rd, rs1, offset } => {
                     my_dynasm!(ops
                         ;; if profile {
                             my_dynasm!(ops
-                                ;; load_reg!(ops, rsi <= rs1)
+                                ;; load_reg!(ops, regs, rsi <= rs1)
                                 ; add rsi, offset
                                 ;; add_load_delay!(ops, rd)
 
@@ -331,94 +712,69 @@ impl RVFunction {
                             );
                         }
 
-                        ;; load_reg!(ops, rsi <= rs1)
+                        ;; load_reg!(ops, regs, rsi <= rs1)
                         ; add rsi, offset
 
                         ;; call_extern!(ops, load_u64)
-                        ;; store_reg!(ops, rax => rd)
+                        ;; store_reg!(ops, regs, rax => rd)
                     );
                 }
-                Inst::Lw { rd, rs1, offset } => todo!(),
-                Inst::Lwu { rd, rs1, offset } => todo!(),
-                Inst::Lhu { rd, rs1, offset } => todo!(),
-                Inst::Lb { rd, rs1, offset } => todo!(),
-                Inst::Lbu { rd, rs1, offset } => todo!(),
                 Inst::Sd { rs1, rs2, offset } => {
                     my_dynasm!(ops
-                        ;; if profile { pipeline_stall!(ops, x.rs1, x.rs2); }
+                        ;; if profile {
+                            my_dynasm!(ops
+                                ;; load_reg!(ops, regs, rsi <= rs1)
+                                ; add rsi, offset
+                                ;; call_extern!(ops, add_store)
 
-                        ;; load_reg!(ops, rsi <= rs1)
-                        ;; load_reg!(ops, rdx <= rs2)
+                                ;; pipeline_stall!(ops, x.rs1, x.rs2)
+                            );
+                        }
+
+                        ;; load_reg!(ops, regs, rsi <= rs1)
+                        ;; load_reg!(ops, regs, rdx <= rs2)
                         ; add rsi, offset
                         ;; call_extern!(ops, store_u64)
                     );
                 }
-                Inst::Sw { rs1, rs2, offset } => todo!(),
-                Inst::Sh { rs1, rs2, offset } => todo!(),
-                Inst::Sb { rs1, rs2, offset } => todo!(),
                 Inst::Add { rd, rs1, rs2 } => {
                     my_dynasm!(ops
                         ;; if profile { pipeline_stall!(ops, x.rs1, x.rs2); }
 
-                        ;; load_reg!(ops, r9 <= rs1)
-                        ;; load_reg!(ops, r10 <= rs2)
+                        ;; load_reg!(ops, regs, r9 <= rs1)
+                        ;; load_reg!(ops, regs, r10 <= rs2)
                         ; add r9, r10
-                        ;; store_reg!(ops, r9 => rd)
+                        ;; store_reg!(ops, regs, r9 => rd)
                     );
                 }
                 Inst::Addw { rd, rs1, rs2 } => {
                     my_dynasm!(ops
                         ;; if profile { pipeline_stall!(ops, x.rs1, x.rs2); }
 
-                        ;; load_reg!(ops, r9 <= rs1)
-                        ;; load_reg!(ops, r10 <= rs2)
+                        ;; load_reg!(ops, regs, r9 <= rs1)
+                        ;; load_reg!(ops, regs, r10 <= rs2)
                         ; add r9d, r10d
-                        ;; store_reg!(ops, r9 => rd)
+                        ;; store_reg!(ops, regs, r9 => rd)
                     );
                 }
                 Inst::Addi { rd, rs1, imm } => {
                     my_dynasm!(ops
                         ;; if profile { pipeline_stall!(ops, x.rs1); }
 
-                        ;; load_reg!(ops, r9 <= rs1)
+                        ;; load_reg!(ops, regs, r9 <= rs1)
                         ; add r9, imm
-                        ;; store_reg!(ops, r9 => rd)
+                        ;; store_reg!(ops, regs, r9 => rd)
                     );
                 }
                 Inst::Addiw { rd, rs1, imm } => {
                     my_dynasm!(ops
                         ;; if profile { pipeline_stall!(ops, x.rs1); }
 
-                        ;; load_reg!(ops, r9 <= rs1)
+                        ;; load_reg!(ops, regs, r9 <= rs1)
                         ; add r9d, imm
-                        ;; store_reg!(ops, r9 => rd)
+                        ;; store_reg!(ops, regs, r9 => rd)
                     );
                 }
-                Inst::Div { rd, rs1, rs2 } => todo!(),
-                Inst::Divw { rd, rs1, rs2 } => todo!(),
-                Inst::Divu { rd, rs1, rs2 } => todo!(),
-                Inst::Divuw { rd, rs1, rs2 } => todo!(),
-                Inst::And { rd, rs1, rs2 } => todo!(),
-                Inst::Andi { rd, rs1, imm } => todo!(),
-                Inst::Sub { rd, rs1, rs2 } => todo!(),
-                Inst::Subw { rd, rs1, rs2 } => todo!(),
-                Inst::Sll { rd, rs1, rs2 } => todo!(),
-                Inst::Sllw { rd, rs1, rs2 } => todo!(),
-                Inst::Slli { rd, rs1, shamt } => todo!(),
-                Inst::Slliw { rd, rs1, shamt } => todo!(),
-                Inst::Srl { rd, rs1, rs2 } => todo!(),
-                Inst::Srlw { rd, rs1, rs2 } => todo!(),
-                Inst::Srli { rd, rs1, shamt } => todo!(),
-                Inst::Srliw { rd, rs1, shamt } => todo!(),
-                Inst::Sra { rd, rs1, rs2 } => todo!(),
-                Inst::Sraw { rd, rs1, rs2 } => todo!(),
-                Inst::Srai { rd, rs1, shamt } => todo!(),
-                Inst::Sraiw { rd, rs1, shamt } => todo!(),
-                Inst::Or { rd, rs1, rs2 } => todo!(),
-                Inst::Ori { rd, rs1, imm } => todo!(),
-                Inst::Xor { rd, rs1, rs2 } => todo!(),
-                Inst::Xori { rd, rs1, imm } => todo!(),
-                Inst::Auipc { rd, imm } => todo!(),
                 Inst::Jal { rd, offset } => {
                     my_dynasm!(ops
                         ;; if profile { call_extern!(ops, profiler_tick); }
@@ -428,17 +784,21 @@ impl RVFunction {
                             my_dynasm!(ops
                                 ; mov r9, [a_pc]
                                 ; add r9, step as _
-                                ;; store_reg!(ops, r9 => rd)
+                                ;; store_reg!(ops, regs, r9 => rd)
                             );
                         }
 
                         // set pc to new address
-                        ; add [a_pc], offset as _
+                        ; add QWORD [a_pc], offset as _
 
                         // actually start executing that new function in the emulator
-                        ;; call_extern!(ops, execute_block)
+                        ;; call_extern_spilled!(ops, regs, execute_block)
+
+                        // pc is already where the guest stopped
+                        ; test al, al
+                        ; jnz =>exit
 
-                        ; sub [a_pc], step as _
+                        ; sub QWORD [a_pc], step as _
                     );
                 }
                 Inst::Jalr { rd, rs1, offset } => {
@@ -449,12 +809,12 @@ impl RVFunction {
                             my_dynasm!(ops
                                 ; mov r9, [a_pc]
                                 ; add r9, step as _
-                                ;; store_reg!(ops, r9 => rd)
+                                ;; store_reg!(ops, regs, r9 => rd)
                             );
                         }
 
                         // set pc to new address
-                        ;; load_reg!(ops, r10 <= rs1)
+                        ;; load_reg!(ops, regs, r10 <= rs1)
                         ; add r10, offset as _
                         ; sub r10, step as _
                         ; mov [a_pc], r10
@@ -462,66 +822,109 @@ impl RVFunction {
                 }
                 Inst::Beq { rs1, rs2, offset } => {
                     branch_impl!(jne :
-                        ops, profile, dynamic_labels, pc, rs1, rs2, offset);
+                        ops, regs, profile, dynamic_labels, pc, rs1, rs2, offset);
                 }
                 Inst::Bne { rs1, rs2, offset } => {
                     branch_impl!(je :
-                        ops, profile, dynamic_labels, pc, rs1, rs2, offset);
+                        ops, regs, profile, dynamic_labels, pc, rs1, rs2, offset);
                 }
                 Inst::Blt { rs1, rs2, offset } => {
                     branch_impl!(jge :
-                        ops, profile, dynamic_labels, pc, rs1, rs2, offset);
+                        ops, regs, profile, dynamic_labels, pc, rs1, rs2, offset);
                 }
                 Inst::Bltu { rs1, rs2, offset } => {
                     branch_impl!(jae :
-                        ops, profile, dynamic_labels, pc, rs1, rs2, offset);
+                        ops, regs, profile, dynamic_labels, pc, rs1, rs2, offset);
                 }
                 Inst::Bge { rs1, rs2, offset } => {
                     branch_impl!(jl :
-                        ops, profile, dynamic_labels, pc, rs1, rs2, offset);
+                        ops, regs, profile, dynamic_labels, pc, rs1, rs2, offset);
                 }
                 Inst::Bgeu { rs1, rs2, offset } => {
                     branch_impl!(jb :
-                        ops, profile, dynamic_labels, pc, rs1, rs2, offset);
+                        ops, regs, profile, dynamic_labels, pc, rs1, rs2, offset);
+                }
+                // instructions the code generator can't emit yet, so the function is interpreted
+                Inst::Lw { .. }
+                | Inst::Lwu { .. }
+                | Inst::Lhu { .. }
+                | Inst::Lb { .. }
+                | Inst::Lbu { .. }
+                | Inst::Sw { .. }
+                | Inst::Sh { .. }
+                | Inst::Sb { .. }
+                | Inst::Div { .. }
+                | Inst::Divw { .. }
+                | Inst::Divu { .. }
+                | Inst::Divuw { .. }
+                | Inst::And { .. }
+                | Inst::Andi { .. }
+                | Inst::Sub { .. }
+                | Inst::Subw { .. }
+                | Inst::Sll { .. }
+                | Inst::Sllw { .. }
+                | Inst::Slli { .. }
+                | Inst::Slliw { .. }
+                | Inst::Srl { .. }
+                | Inst::Srlw { .. }
+                | Inst::Srli { .. }
+                | Inst::Srliw { .. }
+                | Inst::Sra { .. }
+                | Inst::Sraw { .. }
+                | Inst::Srai { .. }
+                | Inst::Sraiw { .. }
+                | Inst::Or { .. }
+                | Inst::Ori { .. }
+                | Inst::Xor { .. }
+                | Inst::Xori { .. }
+                | Inst::Auipc { .. }
+                | Inst::Mul { .. }
+                | Inst::Mulhu { .. }
+                | Inst::Remw { .. }
+                | Inst::Remu { .. }
+                | Inst::Remuw { .. }
+                | Inst::Slt { .. }
+                | Inst::Sltu { .. }
+                | Inst::Slti { .. }
+                | Inst::Sltiu { .. }
+                | Inst::Amoswapw { .. }
+                | Inst::Amoswapd { .. }
+                | Inst::Amoaddw { .. }
+                | Inst::Amoaddd { .. }
+                | Inst::Amoorw { .. }
+                | Inst::Amomaxuw { .. }
+                | Inst::Amomaxud { .. }
+                | Inst::Lrw { .. }
+                | Inst::Lrd { .. }
+                | Inst::Scw { .. }
+                | Inst::Scd { .. }
+                | Inst::Fsd { .. }
+                | Inst::Fsw { .. }
+                | Inst::Fld { .. }
+                | Inst::Flw { .. }
+                | Inst::Fcvtdlu { .. }
+                | Inst::Fcvtds { .. }
+                | Inst::Fled { .. }
+                | Inst::Fdivd { .. }
+                | Inst::Csrrw { .. }
+                | Inst::Csrrs { .. }
+                | Inst::Csrrc { .. }
+                | Inst::Csrrwi { .. }
+                | Inst::Csrrsi { .. }
+                | Inst::Csrrci { .. } => {
+                    log::debug!("Can't compile {inst:?} at {pc:x}");
+                    return None;
                 }
-                Inst::Mul { rd, rs1, rs2 } => todo!(),
-                Inst::Mulhu { rd, rs1, rs2 } => todo!(),
-                Inst::Remw { rd, rs1, rs2 } => todo!(),
-                Inst::Remu { rd, rs1, rs2 } => todo!(),
-                Inst::Remuw { rd, rs1, rs2 } => todo!(),
-                Inst::Slt { rd, rs1, rs2 } => todo!(),
-                Inst::Sltu { rd, rs1, rs2 } => todo!(),
-                Inst::Slti { rd, rs1, imm } => todo!(),
-                Inst::Sltiu { rd, rs1, imm } => todo!(),
-                Inst::Amoswapw { rd, rs1, rs2 } => todo!(),
-                Inst::Amoswapd { rd, rs1, rs2 } => todo!(),
-                Inst::Amoaddw { rd, rs1, rs2 } => todo!(),
-                Inst::Amoaddd { rd, rs1, rs2 } => todo!(),
-                Inst::Amoorw { rd, rs1, rs2 } => todo!(),
-                Inst::Amomaxuw { rd, rs1, rs2 } => todo!(),
-                Inst::Amomaxud { rd, rs1, rs2 } => todo!(),
-                Inst::Lrw { rd, rs1 } => todo!(),
-                Inst::Lrd { rd, rs1 } => todo!(),
-                Inst::Scw { rd, rs1, rs2 } => todo!(),
-                Inst::Scd { rd, rs1, rs2 } => todo!(),
-                Inst::Fsd { rs1, rs2, offset } => todo!(),
-                Inst::Fsw { rs1, rs2, offset } => todo!(),
-                Inst::Fld { rd, rs1, offset } => todo!(),
-                Inst::Flw { rd, rs1, offset } => todo!(),
-                Inst::Fcvtdlu { rd, rs1, rm } => todo!(),
-                Inst::Fcvtds { rd, rs1, rm } => todo!(),
-                Inst::Fled { rd, rs1, rs2 } => todo!(),
-                Inst::Fdivd { rd, rs1, rs2 } => todo!(),
             }
 
             // increment pc
             pc += step as u64;
             my_dynasm!(ops
                 // set x0 to zero
-                ;; store_reg!(ops, ZERO => Reg(0))
+                ;; store_reg!(ops, regs, ZERO => Reg(0))
 
                 // increment program counter
-                ; add [a_pc], step as _
+                ; add QWORD [a_pc], step as _
 
                 // increment instruction counter
                 ; mov r9, a_emu => Emulator.inst_counter
@@ -530,18 +933,29 @@ impl RVFunction {
             );
         }
 
-        // end of function
+        // end of function, or the guest exiting
+        my_dynasm!(ops
+            ;=>exit
+        );
         if started_profile {
             call_extern!(ops, end_profile);
         }
 
+        regs.spill(&mut ops);
+
         my_dynasm!(ops
             ; add rsp, 0x28
+            ; pop r15
+            ; pop r14
+            ; pop r13
+            ; pop r12
+            ; pop rbp
+            ; pop rbx
             ; ret
         );
 
         let code = ops.finalize().unwrap();
 
-        RVFunction { code, start }
+        Some(RVFunction { code, start })
     }
 }
@@ -0,0 +1,306 @@
+// checking the jit against the interpreter. Every call to a compiled function is also interpreted
+// on a fork of the emulator, and the two are compared once the function returns: the pc, the
+// registers and every write to memory in the order it was made. The compiled function is the
+// unit the jit translates, so it's the smallest piece of code the two can be compared over.
+//
+// The fork makes the same syscalls as the compiled code, so output to the guest's stdout is
+// thrown away with it, but writes to host files are made twice.
+
+use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
+use core::{
+    fmt::{self, Display},
+    ops::Range,
+};
+
+use super::{jit::RVFunction, Emulator};
+use crate::{
+    error::RVError,
+    register::{FReg, Reg, SP},
+};
+
+/// A register that ended up different, with its value after the compiled function and after
+/// the interpreter
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RegisterDivergence<R> {
+    pub reg: R,
+    pub jit: u64,
+    pub interp: u64,
+}
+
+/// A write to memory made differently, or only made by one of the two
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WriteDivergence {
+    /// The index of the write among those the function made
+    pub index: usize,
+    /// The addresses written and the bytes left there by the compiled function, if it made the
+    /// write
+    pub jit: Option<(Range<u64>, Vec<u8>)>,
+    pub interp: Option<(Range<u64>, Vec<u8>)>,
+}
+
+/// Where a compiled function first did something the interpreter didn't, see
+/// [`Emulator::set_jit_verification_enabled`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JitDivergence {
+    /// The address of the function
+    pub function: u64,
+    /// The integer registers it was called with
+    pub entry_registers: [u64; 32],
+    pub jit_pc: u64,
+    pub interp_pc: u64,
+    pub registers: Vec<RegisterDivergence<Reg>>,
+    /// The bits of the floating point registers that ended up different
+    pub float_registers: Vec<RegisterDivergence<FReg>>,
+    pub write: Option<WriteDivergence>,
+}
+
+impl Display for JitDivergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "the jit diverged from the interpreter in the function at {:x}",
+            self.function
+        )?;
+        if self.jit_pc != self.interp_pc {
+            writeln!(
+                f,
+                "    returned to {:x}, interpreter returned to {:x}",
+                self.jit_pc, self.interp_pc
+            )?;
+        }
+        for reg in &self.registers {
+            writeln!(
+                f,
+                "    {}: {:x}, interpreter {:x}",
+                reg.reg, reg.jit, reg.interp
+            )?;
+        }
+        for reg in &self.float_registers {
+            writeln!(
+                f,
+                "    {}: {:x}, interpreter {:x}",
+                reg.reg, reg.jit, reg.interp
+            )?;
+        }
+        if let Some(ref write) = self.write {
+            let describe = |write: &Option<(Range<u64>, Vec<u8>)>| match write {
+                Some((range, bytes)) => {
+                    alloc::format!("{:x}-{:x} {bytes:02x?}", range.start, range.end)
+                }
+                None => "nothing".into(),
+            };
+            writeln!(
+                f,
+                "    write {}: {}, interpreter {}",
+                write.index,
+                describe(&write.jit),
+                describe(&write.interp)
+            )?;
+        }
+
+        write!(f, "    called with")?;
+        for (i, value) in self.entry_registers.iter().enumerate().skip(1) {
+            write!(f, " {}={value:x}", Reg(i as u8))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Emulator {
+    /// Runs every call to a compiled function on the interpreter too, and stops execution with
+    /// [`RVError::JitDivergence`] when the two end up with different registers or memory writes,
+    /// which is described by [`Emulator::jit_divergence`]. Doubles the work of every call, and
+    /// copies all of memory for each one unless it's [`MemoryLayout::Cow`]. Disabled by default.
+    ///
+    /// [`MemoryLayout::Cow`]: crate::memory::MemoryLayout::Cow
+    pub fn set_jit_verification_enabled(&mut self, enabled: bool) {
+        self.jit_verification = enabled;
+        self.jit_divergence = None;
+    }
+
+    /// The divergence that stopped execution, if jit verification caught one
+    pub fn jit_divergence(&self) -> Option<&JitDivergence> {
+        self.jit_divergence.as_deref()
+    }
+
+    // runs `function`, and the interpreter on a fork from the same state. Calls the function
+    // makes aren't checked on their own, they're part of what's compared.
+    pub(super) fn run_verified(&mut self, function: &RVFunction) -> Result<(), RVError> {
+        if self.memory.is_logging_writes() {
+            function.run(self);
+            return Ok(());
+        }
+
+        let (entry_pc, entry_registers) = (self.pc, self.x);
+
+        let mut interp = self.fork();
+        interp.exit_hooks.clear();
+        // the jit only delivers interrupts between functions
+        interp.interrupts = BTreeMap::new();
+        interp.next_interrupt = u64::MAX;
+
+        self.memory.start_write_log();
+        function.run(self);
+        let jit_writes = self.memory.take_write_log();
+
+        interp.memory.start_write_log();
+        interp.interp_until_return()?;
+        let interp_writes = interp.memory.take_write_log();
+
+        let divergence = JitDivergence {
+            function: entry_pc,
+            entry_registers,
+            jit_pc: self.pc,
+            interp_pc: interp.pc,
+            registers: (0..32)
+                .map(Reg)
+                .filter(|&reg| self.x[reg] != interp.x[reg])
+                .map(|reg| RegisterDivergence {
+                    reg,
+                    jit: self.x[reg],
+                    interp: interp.x[reg],
+                })
+                .collect(),
+            float_registers: (0..32)
+                .map(FReg)
+                .filter(|&reg| self.f[reg].to_bits() != interp.f[reg].to_bits())
+                .map(|reg| RegisterDivergence {
+                    reg,
+                    jit: self.f[reg].to_bits(),
+                    interp: interp.f[reg].to_bits(),
+                })
+                .collect(),
+            write: self.compare_writes(&jit_writes, &interp, &interp_writes),
+        };
+
+        let diverged = divergence.jit_pc != divergence.interp_pc
+            || !divergence.registers.is_empty()
+            || !divergence.float_registers.is_empty()
+            || divergence.write.is_some();
+        if diverged {
+            log::error!("{divergence}");
+            self.jit_divergence = Some(Box::new(divergence));
+            return Err(RVError::JitDivergence { pc: entry_pc });
+        }
+
+        Ok(())
+    }
+
+    // the first write either made differently, comparing what's left at the addresses written
+    fn compare_writes(
+        &self,
+        jit_writes: &[Range<u64>],
+        interp: &Emulator,
+        interp_writes: &[Range<u64>],
+    ) -> Option<WriteDivergence> {
+        let written = |emulator: &Emulator, writes: &[Range<u64>], index: usize| {
+            let range = writes.get(index)?.clone();
+            let bytes = emulator
+                .memory
+                .read_n(range.start, range.end - range.start)
+                .unwrap_or_default();
+            Some((range, bytes))
+        };
+
+        (0..jit_writes.len().max(interp_writes.len())).find_map(|index| {
+            let jit = written(self, jit_writes, index);
+            let interp = written(interp, interp_writes, index);
+            (jit != interp).then_some(WriteDivergence { index, jit, interp })
+        })
+    }
+
+    // interprets the function at pc until it returns, including the functions it calls
+    fn interp_until_return(&mut self) -> Result<(), RVError> {
+        let (return_addr, sp) = (self.x[crate::register::RA], self.x[SP]);
+
+        while self.execute_next()?.is_none() {
+            if self.pc == return_addr && self.x[SP] == sp {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::{assembler::assemble, memory::Memory, register::A0};
+
+    // calls a function that increments a0 and stores it until a0 is 10, then exits with it
+    fn program() -> Vec<u8> {
+        let mut data = Vec::new();
+        for line in [
+            "li a0, 0",
+            "li a1, 10",
+            "jal ra, 0x1c",
+            "blt a0, a1, 0x8",
+            "li a7, 93",
+            "ecall",
+            // keeps the loop from being compiled, so only the function is
+            "fence.i",
+            // 0x1c
+            "addi a0, a0, 1",
+            "sd a0, 0x80(zero)",
+            "ret",
+        ] {
+            data.extend(assemble(line, data.len() as u64).unwrap());
+        }
+
+        data.resize(0x100, 0);
+        data
+    }
+
+    #[test]
+    fn jit_verification() -> Result<(), RVError> {
+        let mut emulator = Emulator::new(Memory::from_raw(&program()));
+        emulator.set_jit_verification_enabled(true);
+        let mut verified = emulator.clone();
+        let mut wrong_register = emulator.clone();
+        let mut wrong_write = emulator.clone();
+
+        assert_eq!(emulator.run(true)?, 10);
+        emulator.jit_functions.wait();
+
+        // clones share the compiled function, which now runs next to the interpreter
+        assert_eq!(verified.run(true)?, 10);
+        assert!(verified.jit_divergence().is_none());
+
+        // changing the code without flushing the jit leaves the compiled function stale
+        wrong_register.memory.store(0x1c, 0x00250513u32)?; // addi a0, a0, 2
+        assert!(matches!(
+            wrong_register.run(true),
+            Err(RVError::JitDivergence { pc: 0x1c })
+        ));
+        let divergence = wrong_register.jit_divergence().unwrap();
+        assert_eq!(
+            divergence.registers,
+            [RegisterDivergence {
+                reg: A0,
+                jit: 1,
+                interp: 2,
+            }]
+        );
+        assert_eq!(divergence.jit_pc, divergence.interp_pc);
+        assert_eq!(divergence.write.as_ref().unwrap().index, 0);
+
+        wrong_write.memory.store(0x20, 0x08a03423u32)?; // sd a0, 0x88(zero)
+        assert!(wrong_write.run(true).is_err());
+        let divergence = wrong_write.jit_divergence().unwrap();
+        assert!(divergence.registers.is_empty());
+        assert_eq!(
+            divergence.write,
+            Some(WriteDivergence {
+                index: 0,
+                jit: Some((0x80..0x88, [1, 0, 0, 0, 0, 0, 0, 0].into())),
+                interp: Some((0x88..0x90, [1, 0, 0, 0, 0, 0, 0, 0].into())),
+            })
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,177 @@
+// compiles jit functions on background threads, so execution doesn't pause while large functions
+// are compiled. Until a function is ready it is run by the interpreter.
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::jit::{CompileJob, RVFunction};
+
+#[derive(Clone)]
+pub enum JitState {
+    /// queued or being compiled on a worker thread
+    Pending,
+    Ready(Arc<RVFunction>),
+    /// the function can't be compiled, and is always interpreted
+    Failed,
+}
+
+/// Counters describing how much work the jit is doing, see [`Emulator::jit_stats`].
+///
+/// [`Emulator::jit_stats`]: super::Emulator::jit_stats
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct JitStats {
+    /// Functions compiled to host code
+    pub functions_compiled: u64,
+    /// Functions that use instructions the jit can't compile yet, which are always interpreted
+    pub functions_failed: u64,
+    /// Bytes of host code emitted
+    pub code_bytes: u64,
+    /// Time spent compiling, summed over every worker thread
+    pub compile_time: Duration,
+    /// Calls to functions that were interpreted, because they failed or weren't compiled yet
+    pub interpreted_calls: u64,
+}
+
+type FunctionMap = Arc<Mutex<BTreeMap<u64, JitState>>>;
+
+/// Jit compiled functions keyed by their start address. The map is shared with the worker
+/// threads, which install functions as soon as they are compiled.
+#[derive(Clone, Default)]
+pub struct JitFunctions {
+    functions: FunctionMap,
+    stats: Arc<Mutex<JitStats>>,
+    // bumped by `invalidate`, jobs submitted before that are thrown away once compiled
+    generation: Arc<AtomicU64>,
+    // started on the first submitted job
+    jobs: Option<Sender<CompileJob>>,
+}
+
+impl JitFunctions {
+    pub fn get(&self, pc: u64) -> Option<JitState> {
+        self.functions.lock().unwrap().get(&pc).cloned()
+    }
+
+    pub fn stats(&self) -> JitStats {
+        *self.stats.lock().unwrap()
+    }
+
+    /// Counts a call to a function run by the interpreter instead of compiled code.
+    pub fn count_interpreted_call(&self) {
+        self.stats.lock().unwrap().interpreted_calls += 1;
+    }
+
+    /// Queues `job` to be compiled on a worker thread, or marks its function as failed if there
+    /// is no job.
+    pub fn submit(&mut self, pc: u64, job: Option<CompileJob>) {
+        let Some(mut job) = job else {
+            self.functions.lock().unwrap().insert(pc, JitState::Failed);
+            self.stats.lock().unwrap().functions_failed += 1;
+            return;
+        };
+
+        self.functions.lock().unwrap().insert(pc, JitState::Pending);
+        job.generation = self.generation.load(Ordering::Acquire);
+
+        let jobs = self
+            .jobs
+            .get_or_insert_with(|| spawn_workers(&self.functions, &self.stats, &self.generation));
+        jobs.send(job).expect("jit worker threads exited");
+    }
+
+    /// Forgets every function, including ones still being compiled, so they are compiled again
+    /// from the current contents of memory.
+    pub fn invalidate(&self) {
+        let mut functions = self.functions.lock().unwrap();
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        functions.clear();
+    }
+
+    /// Blocks until every submitted function has finished compiling.
+    #[cfg(test)]
+    pub fn wait(&self) {
+        let pending = || {
+            let functions = self.functions.lock().unwrap();
+            functions
+                .values()
+                .any(|state| matches!(state, JitState::Pending))
+        };
+
+        while pending() {
+            thread::yield_now();
+        }
+    }
+}
+
+fn spawn_workers(
+    functions: &FunctionMap,
+    stats: &Arc<Mutex<JitStats>>,
+    generation: &Arc<AtomicU64>,
+) -> Sender<CompileJob> {
+    let (sender, receiver) = mpsc::channel::<CompileJob>();
+    let receiver = Arc::new(Mutex::new(receiver));
+
+    let worker_count = thread::available_parallelism().map_or(1, |n| n.get());
+    for i in 0..worker_count {
+        let receiver = receiver.clone();
+        let functions = functions.clone();
+        let stats = stats.clone();
+        let generation = generation.clone();
+
+        thread::Builder::new()
+            .name(format!("jit-worker-{i}"))
+            .spawn(move || worker(&receiver, &functions, &stats, &generation))
+            .expect("failed to spawn jit worker thread");
+    }
+
+    sender
+}
+
+// exits once every emulator sharing the job queue is dropped
+fn worker(
+    receiver: &Mutex<Receiver<CompileJob>>,
+    functions: &FunctionMap,
+    stats: &Mutex<JitStats>,
+    generation: &AtomicU64,
+) {
+    loop {
+        let Ok(job) = receiver.lock().unwrap().recv() else {
+            return;
+        };
+
+        let pc = job.pc;
+        let job_generation = job.generation;
+        let start = Instant::now();
+
+        let function = job.compile();
+
+        let mut stats = stats.lock().unwrap();
+        stats.compile_time += start.elapsed();
+        let state = match function {
+            Some(function) => {
+                stats.functions_compiled += 1;
+                stats.code_bytes += function.code_size() as u64;
+                JitState::Ready(Arc::new(function))
+            }
+            // it uses an instruction the jit can't compile yet
+            None => {
+                stats.functions_failed += 1;
+                JitState::Failed
+            }
+        };
+        drop(stats);
+
+        // the function may have been compiled from code that was since overwritten
+        let mut functions = functions.lock().unwrap();
+        if generation.load(Ordering::Acquire) == job_generation {
+            functions.insert(pc, state);
+        }
+    }
+}
@@ -0,0 +1,337 @@
+// running in lockstep with a trace from a reference simulator, spike's commit log or QEMU's exec
+// log, and stopping at the first instruction where the two differ. The emulator only steps for
+// the trace's instructions in the privilege mode it started in, so the proxy kernel or firmware
+// handling a trap in spike is skipped over the way remu emulates the trap in one step.
+
+use alloc::{string::ToString, vec::Vec};
+use core::fmt::{self, Display};
+
+use super::Emulator;
+use crate::{error::RVError, register::Reg};
+
+/// An instruction executed by the reference simulator, parsed from a line of its trace
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TraceStep {
+    pub pc: u64,
+    /// The bits of the instruction, if the trace has them
+    pub inst: Option<u32>,
+    /// The privilege mode it ran in, if the trace has it
+    pub privilege: Option<u8>,
+    /// The integer registers it wrote and their values, for traces that log them
+    pub writes: Vec<(Reg, u64)>,
+}
+
+impl TraceStep {
+    /// Parses a line of spike's `--log-commits` or `-l` output, like
+    /// `core   0: 3 0x0000000080000000 (0x00000297) x5  0x0000000080000000`, or of QEMU's
+    /// `-d exec -one-insn-per-tb` output. Returns `None` for lines that aren't an instruction,
+    /// like spike's exception messages.
+    pub fn parse(line: &str) -> Option<TraceStep> {
+        if let Some(rest) = line.strip_prefix("Trace ") {
+            // Trace 0: 0x7f1c [00000000/0000000000010078/00000000/ff020000] _start
+            let fields = rest.split_once('[')?.1.split_once(']')?.0;
+            let pc = fields.split('/').nth(1)?;
+
+            return Some(TraceStep {
+                pc: u64::from_str_radix(pc, 16).ok()?,
+                ..TraceStep::default()
+            });
+        }
+
+        let mut tokens = line
+            .strip_prefix("core")?
+            .split_once(':')?
+            .1
+            .split_whitespace()
+            .peekable();
+
+        // commit logs have the privilege mode before the pc
+        let privilege = match tokens.peek() {
+            Some(token) if token.len() == 1 => tokens.next()?.parse().ok(),
+            _ => None,
+        };
+        let pc = hex(tokens.next()?)?;
+        let inst = tokens
+            .next()
+            .and_then(|inst| inst.strip_prefix('(')?.strip_suffix(')'))
+            .and_then(hex)
+            .map(|inst| inst as u32);
+
+        let mut writes = Vec::new();
+        if privilege.is_some() {
+            while let Some(token) = tokens.next() {
+                let Some(reg) = token.strip_prefix('x').and_then(|n| n.parse().ok()) else {
+                    continue;
+                };
+                if let Some(value) = tokens.next().and_then(hex) {
+                    writes.push((Reg(reg), value));
+                }
+            }
+        }
+
+        Some(TraceStep {
+            pc,
+            inst,
+            privilege,
+            writes,
+        })
+    }
+}
+
+fn hex(token: &str) -> Option<u64> {
+    u64::from_str_radix(token.strip_prefix("0x")?, 16).ok()
+}
+
+/// How far [`Emulator::run_lockstep`] got
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LockstepReport {
+    /// The instructions of the trace that matched
+    pub steps: u64,
+    pub divergence: Option<Divergence>,
+}
+
+/// The first instruction the emulator and the trace differed at, and the emulator's registers
+/// when they did
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    /// The index of the instruction among those compared
+    pub step: u64,
+    /// The pc of the instruction
+    pub pc: u64,
+    pub kind: DivergenceKind,
+    pub registers: [u64; 32],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DivergenceKind {
+    /// The emulator went to a different instruction, at `Divergence::pc`
+    Pc { expected: u64 },
+    /// The bits of the instruction at the pc are different, like code that was loaded or
+    /// patched differently
+    Instruction { expected: u32, actual: u32 },
+    /// The instruction left a different value in a register
+    Register {
+        reg: Reg,
+        expected: u64,
+        actual: u64,
+    },
+    /// The program exited with this code before the trace ended
+    Exited(u64),
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "diverged from the trace at step {}: ", self.step)?;
+        match self.kind {
+            DivergenceKind::Pc { expected } => {
+                writeln!(f, "pc {:x}, expected {expected:x}", self.pc)?
+            }
+            DivergenceKind::Instruction { expected, actual } => writeln!(
+                f,
+                "instruction {actual:08x} at {:x}, expected {expected:08x}",
+                self.pc
+            )?,
+            DivergenceKind::Register {
+                reg,
+                expected,
+                actual,
+            } => writeln!(
+                f,
+                "{reg} = {actual:x} after {:x}, expected {expected:x}",
+                self.pc
+            )?,
+            DivergenceKind::Exited(code) => writeln!(f, "exited with code {code}")?,
+        }
+
+        for (i, value) in self.registers.iter().enumerate() {
+            let separator = if i % 4 == 3 { "\n" } else { "  " };
+            write!(
+                f,
+                "{:>4}: {value:016x}{separator}",
+                Reg(i as u8).to_string()
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Emulator {
+    /// Executes an instruction for each step of `trace` from a reference simulator, comparing
+    /// the pc, the instruction and the registers it wrote, until they differ or the trace ends.
+    /// Steps before the trace reaches the pc are skipped, which drops the boot rom and the
+    /// loader. Only interprets, and errors executing an instruction are returned.
+    pub fn run_lockstep(
+        &mut self,
+        trace: impl IntoIterator<Item = TraceStep>,
+    ) -> Result<LockstepReport, RVError> {
+        let mut steps = 0;
+        let mut privilege = None;
+        let mut synced = false;
+
+        for expected in trace {
+            if !synced {
+                if expected.pc != self.pc {
+                    continue;
+                }
+                synced = true;
+                privilege = expected.privilege;
+            } else if expected.privilege != privilege {
+                continue;
+            }
+
+            if let Some(kind) = self.compare_step(&expected)? {
+                return Ok(LockstepReport {
+                    steps,
+                    divergence: Some(Divergence {
+                        step: steps,
+                        pc: match kind {
+                            DivergenceKind::Register { .. } => expected.pc,
+                            _ => self.pc,
+                        },
+                        kind,
+                        registers: self.x,
+                    }),
+                });
+            }
+            steps += 1;
+        }
+
+        Ok(LockstepReport {
+            steps,
+            divergence: None,
+        })
+    }
+
+    // executes the instruction of `expected`, returning how it differed if it did
+    fn compare_step(&mut self, expected: &TraceStep) -> Result<Option<DivergenceKind>, RVError> {
+        if let Some(code) = self.exit_code {
+            return Ok(Some(DivergenceKind::Exited(code)));
+        }
+        if self.pc != expected.pc {
+            return Ok(Some(DivergenceKind::Pc {
+                expected: expected.pc,
+            }));
+        }
+
+        if let Some(bits) = expected.inst {
+            let (_, len) = self.fetch()?;
+            let actual = match len {
+                2 => self.memory.load::<u16>(self.pc)? as u32,
+                _ => self.memory.load::<u32>(self.pc)?,
+            };
+            if actual != bits {
+                return Ok(Some(DivergenceKind::Instruction {
+                    expected: bits,
+                    actual,
+                }));
+            }
+        }
+
+        self.execute_next()?;
+
+        for &(reg, value) in &expected.writes {
+            if reg.0 != 0 && self.x[reg] != value {
+                return Ok(Some(DivergenceKind::Register {
+                    reg,
+                    expected: value,
+                    actual: self.x[reg],
+                }));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::{memory::Memory, register::*};
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            TraceStep::parse("core   0: 3 0x0000000080000000 (0x00000297) x5  0x0000000080000000"),
+            Some(TraceStep {
+                pc: 0x8000_0000,
+                inst: Some(0x297),
+                privilege: Some(3),
+                writes: vec![(Reg(5), 0x8000_0000)],
+            })
+        );
+        assert_eq!(
+            TraceStep::parse("core   0: 0x0000000000010078 (0x00001197) auipc   gp, 0x1"),
+            Some(TraceStep {
+                pc: 0x10078,
+                inst: Some(0x1197),
+                ..TraceStep::default()
+            })
+        );
+        assert_eq!(
+            TraceStep::parse(
+                "Trace 0: 0x7f1c [00000000/0000000000010078/00000000/ff020000] _start"
+            )
+            .map(|step| step.pc),
+            Some(0x10078)
+        );
+        assert_eq!(
+            TraceStep::parse("core   0: exception trap_user_ecall, epc 0x0000000000010080"),
+            None
+        );
+    }
+
+    #[test]
+    fn lockstep() -> Result<(), RVError> {
+        let mut data = [0u8; 0x20];
+        data[0..4].copy_from_slice(&0x00100513u32.to_le_bytes()); // li a0, 1
+        data[4..8].copy_from_slice(&0x00250513u32.to_le_bytes()); // addi a0, a0, 2
+        data[8..12].copy_from_slice(&0x00000013u32.to_le_bytes()); // nop
+
+        // a boot rom line before the program, and a trap handled in another mode
+        let trace = [
+            "core   0: 3 0x0000000000001000 (0x00000297) x5  0x0000000000001000",
+            "core   0: 0 0x0000000000000000 (0x00100513) x10 0x0000000000000001",
+            "core   0: 1 0x0000000080000000 (0x00000013)",
+            "core   0: 0 0x0000000000000004 (0x00250513) x10 0x0000000000000004",
+            "core   0: 0 0x0000000000000008 (0x00000013)",
+        ];
+        let steps = || trace.iter().filter_map(|line| TraceStep::parse(line));
+
+        let mut emulator = Emulator::new(Memory::from_raw(&data));
+        let report = emulator.run_lockstep(steps())?;
+        assert_eq!(report.steps, 1);
+
+        let divergence = report.divergence.unwrap();
+        assert_eq!(divergence.pc, 4);
+        assert_eq!(
+            divergence.kind,
+            DivergenceKind::Register {
+                reg: A0,
+                expected: 4,
+                actual: 3
+            }
+        );
+        assert_eq!(divergence.registers[10], 3);
+
+        // the same trace with the right value matches to the end
+        let fixed = steps().map(|mut step| {
+            if step.pc == 4 {
+                step.writes[0].1 = 3;
+            }
+            step
+        });
+        let report = Emulator::new(Memory::from_raw(&data)).run_lockstep(fixed)?;
+        assert_eq!(
+            report,
+            LockstepReport {
+                steps: 3,
+                divergence: None
+            }
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,186 @@
+use alloc::{vec, vec::Vec};
+
+use crate::error::RVError;
+
+use super::Emulator;
+
+// registers of a hart that is not currently scheduled
+#[derive(Clone)]
+struct HartContext {
+    pc: u64,
+    x: [u64; 32],
+    f: [f64; 32],
+    reservation: Option<(u64, u64)>,
+    running: bool,
+}
+
+/// A multi-hart machine. Every hart shares the memory, file descriptors, and profiler of a single
+/// [`Emulator`], and only the registers of the scheduled hart are loaded into it at a time.
+///
+/// Harts are scheduled round-robin, switching every `quantum` instructions, so a run is entirely
+/// deterministic. All harts start at the entry point and can tell themselves apart by reading the
+/// mhartid csr.
+#[derive(Clone)]
+pub struct Machine {
+    pub emulator: Emulator,
+    harts: Vec<HartContext>,
+    current: usize,
+    quantum: u64,
+    remaining: u64,
+}
+
+impl Machine {
+    pub fn new(mut emulator: Emulator, hart_count: usize, quantum: u64) -> Machine {
+        assert!(hart_count > 0, "a machine needs at least one hart");
+        assert!(quantum > 0, "the scheduling quantum must be non-zero");
+
+        emulator.hart_id = 0;
+        emulator.hart_count = hart_count as u64;
+
+        let context = HartContext {
+            pc: emulator.pc,
+            x: emulator.x,
+            f: emulator.f,
+            reservation: None,
+            running: true,
+        };
+
+        Machine {
+            emulator,
+            harts: vec![context; hart_count],
+            current: 0,
+            quantum,
+            remaining: quantum,
+        }
+    }
+
+    /// The id of the hart currently loaded into `emulator`
+    pub fn current_hart(&self) -> usize {
+        self.current
+    }
+
+    /// Executes a single instruction on the current hart, returning the exit code once every hart
+    /// has exited or any hart calls exit_group.
+    pub fn step(&mut self) -> Result<Option<u64>, RVError> {
+        if let Some(exit_code) = self.emulator.fetch_and_execute()? {
+            return Ok(Some(exit_code));
+        }
+
+        if let Some(exit_code) = self.emulator.hart_exit_code.take() {
+            self.harts[self.current].running = false;
+
+            if self.harts.iter().all(|hart| !hart.running) {
+                self.emulator.exit(exit_code);
+                return Ok(Some(exit_code));
+            }
+
+            self.switch_hart();
+        } else {
+            self.remaining -= 1;
+
+            if self.remaining == 0 {
+                self.switch_hart();
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub fn run(&mut self) -> Result<u64, RVError> {
+        loop {
+            if let Some(exit_code) = self.step()? {
+                return Ok(exit_code);
+            }
+        }
+    }
+
+    fn switch_hart(&mut self) {
+        self.remaining = self.quantum;
+
+        let next = (1..=self.harts.len())
+            .map(|i| (self.current + i) % self.harts.len())
+            .find(|&i| self.harts[i].running)
+            .expect("at least one hart is running");
+
+        if next == self.current {
+            return;
+        }
+
+        let outgoing = &mut self.harts[self.current];
+        outgoing.pc = self.emulator.pc;
+        outgoing.x = self.emulator.x;
+        outgoing.f = self.emulator.f;
+        outgoing.reservation = self.emulator.reservation;
+
+        let incoming = &self.harts[next];
+        self.emulator.pc = incoming.pc;
+        self.emulator.x = incoming.x;
+        self.emulator.f = incoming.f;
+        self.emulator.reservation = incoming.reservation;
+        self.emulator.hart_id = next as u64;
+
+        self.current = next;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{memory::Memory, register::*};
+
+    fn program(insts: &[u32]) -> Memory {
+        let mut data = vec![0u8; 0x200];
+        for (i, inst) in insts.iter().enumerate() {
+            data[i * 4..i * 4 + 4].copy_from_slice(&inst.to_le_bytes());
+        }
+
+        Memory::from_raw(&data)
+    }
+
+    #[test]
+    fn harts_share_memory() -> Result<(), RVError> {
+        let memory = program(&[
+            0xf1402573, // csrr     a0, mhartid
+            0x00100593, // li       a1, 1
+            0x10000613, // li       a2, 0x100
+            0x00b626af, // amoadd.w a3, a1, (a2)
+            0x05d00893, // li       a7, 93
+            0x00000073, // ecall
+        ]);
+
+        let mut machine = Machine::new(Emulator::new(memory), 4, 1);
+
+        // the last hart to exit returns its id
+        assert_eq!(machine.run()?, 3);
+        assert_eq!(machine.emulator.memory.load::<u32>(0x100)?, 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn sc_fails_after_foreign_store() -> Result<(), RVError> {
+        let memory = program(&[
+            0x10000613, // li     a2, 0x100
+            0x1006272f, // lr.w   a4, (a2)
+            0x00170713, // addi   a4, a4, 1
+            0x18e627af, // sc.w   a5, a4, (a2)
+            0x05d00893, // li     a7, 93
+            0x00000073, // ecall
+        ]);
+
+        // with a quantum of 2, both harts execute lr.w before either reaches sc.w
+        let mut machine = Machine::new(Emulator::new(memory), 2, 2);
+
+        for _ in 0..8 {
+            machine.step()?;
+        }
+
+        // hart 0 succeeded, so hart 1's reservation was broken
+        assert_eq!(machine.emulator.memory.load::<u32>(0x100)?, 1);
+        assert_eq!(machine.emulator.hart_id, 0);
+        assert_eq!(machine.emulator.x[A5], 0);
+        assert_eq!(machine.harts[1].x[A5], 1);
+
+        Ok(())
+    }
+}
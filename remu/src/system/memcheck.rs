@@ -0,0 +1,107 @@
+// a lightweight take on valgrind's memcheck. Every load and store is checked against the shadow
+// memory kept by `Memory` before it runs, while the heap routines tell it which blocks are live.
+
+use alloc::vec::Vec;
+
+use super::Emulator;
+use crate::{instruction::Inst, memory::Violation, register::Reg};
+
+// the deepest stack a report is attributed to
+const MEMCHECK_FRAME_LIMIT: usize = 32;
+
+/// An instruction that made an invalid access, see [`Emulator::set_memcheck_enabled`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemcheckReport {
+    pub violation: Violation,
+    /// The first access made by the instruction
+    pub addr: u64,
+    pub len: u64,
+    /// The instruction's pc followed by the return address of each caller, innermost first
+    pub stack: Vec<u64>,
+    /// How many times the instruction made this kind of access
+    pub count: u64,
+}
+
+impl Emulator {
+    /// Reports loads of bytes returned by `malloc` that were never written, and accesses to blocks
+    /// that were freed or regions that were unmapped, see [`Emulator::memcheck_reports`]. The heap
+    /// routines are found like with [`Emulator::set_heap_profiling_enabled`]. Every instruction
+    /// has to be checked, so the jit isn't used while it's enabled. Disabled by default.
+    pub fn set_memcheck_enabled(&mut self, enabled: bool) {
+        self.memory.set_memcheck_enabled(enabled);
+        self.memcheck_reports.clear();
+        self.find_heap_routines();
+    }
+
+    /// The instructions that made invalid accesses so far, in the order they first did
+    pub fn memcheck_reports(&self) -> &[MemcheckReport] {
+        &self.memcheck_reports
+    }
+
+    // checks the access `inst` is about to make, before it's executed
+    pub(super) fn check_memory_access(&mut self, inst: Inst) {
+        let Some((base, offset, len, is_load)) = memory_access(inst) else {
+            return;
+        };
+
+        let addr = self.x[base].wrapping_add(offset as u64);
+        let Some(violation) = self.memory.check_access(addr, len, is_load) else {
+            return;
+        };
+
+        let pc = self.pc;
+        if let Some(report) = self
+            .memcheck_reports
+            .iter_mut()
+            .find(|report| report.stack[0] == pc && report.violation == violation)
+        {
+            report.count += 1;
+            return;
+        }
+
+        log::warn!(
+            "{} of {len} bytes at {addr:#x} by {pc:x}",
+            violation.description()
+        );
+
+        let stack = self.backtrace(MEMCHECK_FRAME_LIMIT);
+        self.memcheck_reports.push(MemcheckReport {
+            violation,
+            addr,
+            len,
+            stack,
+            count: 1,
+        });
+    }
+}
+
+// the base register, offset, length and whether it reads memory, of the access made by `inst`.
+// Atomics count as loads.
+pub(super) fn memory_access(inst: Inst) -> Option<(Reg, i32, u64, bool)> {
+    let access = match inst {
+        Inst::Ld { rs1, offset, .. } | Inst::Fld { rs1, offset, .. } => (rs1, offset, 8, true),
+        Inst::Lw { rs1, offset, .. }
+        | Inst::Lwu { rs1, offset, .. }
+        | Inst::Flw { rs1, offset, .. } => (rs1, offset, 4, true),
+        Inst::Lhu { rs1, offset, .. } => (rs1, offset, 2, true),
+        Inst::Lb { rs1, offset, .. } | Inst::Lbu { rs1, offset, .. } => (rs1, offset, 1, true),
+        Inst::Sd { rs1, offset, .. } | Inst::Fsd { rs1, offset, .. } => (rs1, offset, 8, false),
+        Inst::Sw { rs1, offset, .. } | Inst::Fsw { rs1, offset, .. } => (rs1, offset, 4, false),
+        Inst::Sh { rs1, offset, .. } => (rs1, offset, 2, false),
+        Inst::Sb { rs1, offset, .. } => (rs1, offset, 1, false),
+        Inst::Lrw { rs1, .. }
+        | Inst::Amoswapw { rs1, .. }
+        | Inst::Amoaddw { rs1, .. }
+        | Inst::Amoorw { rs1, .. }
+        | Inst::Amomaxuw { rs1, .. } => (rs1, 0, 4, true),
+        Inst::Lrd { rs1, .. }
+        | Inst::Amoswapd { rs1, .. }
+        | Inst::Amoaddd { rs1, .. }
+        | Inst::Amomaxud { rs1, .. } => (rs1, 0, 8, true),
+        Inst::Scw { rs1, .. } => (rs1, 0, 4, false),
+        Inst::Scd { rs1, .. } => (rs1, 0, 8, false),
+        _ => return None,
+    };
+
+    Some(access)
+}
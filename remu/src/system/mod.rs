@@ -1,30 +1,117 @@
-use std::{
-    collections::{BTreeMap, HashMap},
-    num::NonZeroU64,
-    path::Path,
-    rc::Rc,
-};
-
-use elf::{endian::AnyEndian, ElfBytes};
+use alloc::{boxed::Box, collections::BTreeMap, format, string::String, sync::Arc, vec, vec::Vec};
+use core::num::NonZeroU64;
+#[cfg(feature = "std")]
+use std::path::Path;
 
 use crate::{
     auxvec::{AuxPair, Auxv, RANDOM_BYTES},
     error::RVError,
-    files::FileDescriptor,
     instruction::Inst,
-    memory::{Memory, PAGE_SIZE},
-    profiler::Profiler,
+    memory::{LoadOptions, Memory, PAGE_SIZE},
+    profiler::{HpmEvent, Profiler},
     register::*,
 };
 
-use self::jit::RVFunction;
+#[cfg(jit)]
+use self::{
+    jit::CompileJob,
+    jit_pool::{JitFunctions, JitState},
+};
+
+pub use self::{
+    builder::EmulatorBuilder,
+    clock::DEFAULT_CLOCK_RATE,
+    conformance::{run_isa_test, IsaTestResult},
+    controller::{Controller, Resume, StopReason},
+    core_dump::{CoreDump, CoreDumpError},
+    events::{Event, EventCategory, EventFilter, EventRecord},
+    exit::{ExitHook, ExitReason, ExitSummary, RunResult},
+    faults::{BitFlip, FaultKind, FaultPlan, FlipTarget, InjectedFault},
+    frame_check::FrameViolation,
+    heap::{AllocationSite, HeapProfile, HeapSummary},
+    identity::{MachineIdentity, DEFAULT_TOTAL_RAM},
+    intercept::InterceptHandler,
+    interrupt::InterruptHandler,
+    lockstep::{Divergence, DivergenceKind, LockstepReport, TraceStep},
+    machine::Machine,
+    memcheck::MemcheckReport,
+    output::{OutputLimit, OutputOverflow, OutputSink, OutputStream, TRUNCATION_MARKER},
+    plt::PltBinding,
+    privileged::Privilege,
+    sandbox::{Extension, Extensions, SandboxPolicy, SandboxViolation},
+    segfault::{Access, AccessKind, Segfault},
+    strace::StraceSink,
+    syscall::{Syscall, SyscallRecord},
+    syscall_handler::SyscallHandler,
+    taint::{TaintSet, TaintSource, TaintTracker, TaintedBranch, TaintedFault, TaintedOutput},
+};
+#[cfg(jit)]
+pub use self::{
+    jit_check::{JitDivergence, RegisterDivergence, WriteDivergence},
+    jit_pool::JitStats,
+};
+pub use crate::auxvec::AuxvConfig;
+pub use crate::files::{
+    Device, DirEntry, FdTable, FileDescriptor, FileKind, OpenFile, Sysroot, Vfs, VfsNode,
+    DEFAULT_INTERPRETER,
+};
+pub use crate::profiler::{
+    Baseline, BaselineDiff, BaselineError, CacheConfig, CountDelta, CpuModel, FunctionCounts,
+    FunctionDiff, Prefetcher, ProfileSnapshot, StackSample, Trace, TraceEvent,
+};
+
+use self::{
+    block_cache::BlockCache, clock::GuestClock, controller::StopPoints, faults::FaultState,
+    frame_check::FrameCheck, heap::HeapRoutine, history::InstHistory, hle::Routine,
+    inst_cache::InstCache, intercept::Intercept, privileged::SystemState, reset::StartState,
+    taint::BranchInputLog,
+};
 
+mod block_cache;
+mod builder;
+mod clock;
+mod conformance;
+mod controller;
+mod core_dump;
+mod csr;
+mod events;
+mod exit;
+mod faults;
+mod frame_check;
+mod heap;
+mod history;
+mod hle;
+mod identity;
+mod inst_cache;
+mod intercept;
 mod interp;
+mod interrupt;
+#[cfg(jit)]
 mod jit;
+#[cfg(jit)]
+mod jit_check;
+#[cfg(jit)]
+mod jit_pool;
+mod lockstep;
+mod machine;
+mod memcheck;
+mod output;
+mod plt;
+mod privileged;
+mod process;
+mod reset;
+mod sandbox;
+mod segfault;
+mod strace;
 mod syscall;
+mod syscall_handler;
+mod taint;
 
 pub const STACK_START: u64 = -1i64 as u64;
 
+// the deepest stack recorded by `sample_stacks`, deeper recursion is cut off
+const SAMPLE_FRAME_LIMIT: usize = 128;
+
 // https://sifive.cdn.prismic.io/sifive/1a82e600-1f93-4f41-b2d8-86ed8b16acba_fu740-c000-manual-v1p6.pdf
 // The latency of DIV, DIVU, REM, and REMU instructions can be determined by calculating:
 // Latency = 2 cycles + log2(dividend) - log2(divisor) + 1 cycle
@@ -38,6 +125,22 @@ macro_rules! div_cycle_count {
     };
 }
 
+// calls a profiler method from `Emulator::execute`, compiled out entirely when it is executing
+// without profiling
+macro_rules! profile {
+    ($self:ident.$method:ident($($arg:expr),* $(,)?)) => {
+        if PROFILE {
+            $self.profiler.$method($($arg),*);
+        }
+    };
+}
+
+/// A RISC-V hart running a program, with its memory, open files and profiler.
+///
+/// Emulators are [`Send`], so they can be moved to another thread or between async tasks, but not
+/// [`Sync`]: one is only ever run by one thread at a time. Clones share immutable data like
+/// decoded blocks and file contents, and share callbacks, devices and the branch input log, which
+/// are required to be `Send + Sync` themselves.
 #[derive(Clone)]
 pub struct Emulator {
     pub pc: u64,
@@ -46,82 +149,269 @@ pub struct Emulator {
     f: [f64; 32],
 
     pub memory: Memory,
-    file_descriptors: HashMap<i64, FileDescriptor>,
+    inst_cache: InstCache,
+    block_cache: BlockCache,
+    // entry points of intercepted library routines, see `set_hle_enabled`
+    hle_routines: BTreeMap<u64, Routine>,
+    // entry points of guest functions replaced by closures, see `intercept`
+    intercepts: BTreeMap<u64, Intercept>,
+    // see `set_heap_profiling_enabled`
+    heap_profile: Option<HeapProfile>,
+    // entry points of malloc and friends, while heap profiling or memcheck need them
+    heap_routines: BTreeMap<u64, HeapRoutine>,
+    in_heap_call: bool,
+    // see `set_memcheck_enabled`
+    memcheck_reports: Vec<MemcheckReport>,
+    // see `set_frame_checking_enabled`
+    frame_check: Option<FrameCheck>,
+    frame_violation: Option<FrameViolation>,
+    // see `set_taint_sources`
+    taint: Option<TaintTracker>,
+    // see `set_instruction_history_len`
+    inst_history: Option<InstHistory>,
+    // see `set_plt_tracking`
+    plt_bindings: Option<Vec<PltBinding>>,
+    branch_input_log: Option<BranchInputLog>,
+    fds: FdTable,
+    // files the guest can open and execute
+    vfs: Vfs,
+    // see `sysroot`
+    sysroot: Sysroot,
+    // the pids getpid and getppid return, and the one the next child gets
+    pid: u64,
+    ppid: u64,
+    next_pid: u64,
+    // exited children that weren't waited for, pid -> status
+    children: BTreeMap<u64, u64>,
+    // see `EmulatorBuilder`, kept for execve
+    auxv: AuxvConfig,
+    // see `set_machine_identity`
+    identity: MachineIdentity,
+    args: Vec<String>,
+    env: Vec<String>,
+    // see `run_controlled`
+    stop_points: StopPoints,
+    // see `enable_system_mode`
+    system: Option<Box<SystemState>>,
+    // see `register_syscall` and `set_fallback_syscall_handler`
+    syscall_handlers: BTreeMap<u64, Arc<dyn SyscallHandler>>,
+    fallback_syscall_handler: Option<Arc<dyn SyscallHandler>>,
+    // see `set_sandbox_policy`
+    sandbox: Option<Box<SandboxPolicy>>,
+    // see `set_fault_plan`
+    faults: Option<Box<FaultState>>,
+    // see `set_strace`
+    strace: Option<StraceSink>,
+    // see `set_event_filter`
+    event_filter: EventFilter,
+    events: Vec<EventRecord>,
 
     pub stdout: String,
     pub stderr: String,
+    // see `set_output_limit`
+    output_limit: Option<OutputLimit>,
+    output_truncated: [bool; 2],
 
     profile_start_point: Option<NonZeroU64>,
     profile_end_point: Option<NonZeroU64>,
+    // see `set_region_of_interest`
+    roi_start_point: Option<NonZeroU64>,
+    roi_start: Option<ProfileSnapshot>,
     pub profiler: Profiler,
+    // the event counted by each of hpmcounter3 through hpmcounter31
+    hpm_events: [HpmEvent; csr::HPM_COUNTERS],
 
     /// The number of instructions executed over the lifecycle of the emulator.
     pub inst_counter: u64,
+    // see `set_clock_rate` and `set_real_time_pacing`
+    clock: GuestClock,
+
+    /// The id of the hart whose registers are currently loaded, readable through mhartid.
+    pub hart_id: u64,
+    /// The number of harts in the machine, see [`Machine`].
+    pub hart_count: u64,
+    // set instead of exit_code when a single hart of a multi-hart machine calls exit
+    hart_exit_code: Option<u64>,
+    // lr/sc reservation: (address, value observed by lr)
+    reservation: Option<(u64, u64)>,
+
+    // pending interrupts, keyed by the inst_counter value they fire at
+    interrupts: BTreeMap<u64, Vec<InterruptHandler>>,
+    next_interrupt: u64,
+    // see `on_exit` and `set_exit_summary_enabled`
+    exit_hooks: Vec<ExitHook>,
+    exit_summary_enabled: bool,
+    exit_summary: Option<ExitSummary>,
+    // the signal that terminated the guest, see `term_signal`
+    term_signal: Option<u64>,
+    // the inst_counter value the guest stops at, see `set_fuel`
+    fuel_limit: Option<u64>,
+    // see `reset`
+    // boxed rather than shared, since the memory's TLB isn't Sync with the `mmu` feature
+    start: Option<Box<StartState>>,
     pub max_memory: u64,
 
-    jit_functions: BTreeMap<u64, Rc<RVFunction>>,
+    #[cfg(jit)]
+    jit_functions: JitFunctions,
+    // bumped by execve, so compiled code can tell the program it was running is gone
+    #[cfg(jit)]
+    exec_count: u64,
+    // see `set_jit_verification_enabled`
+    #[cfg(jit)]
+    jit_verification: bool,
+    #[cfg(jit)]
+    jit_divergence: Option<Box<JitDivergence>>,
 
     // Similar to fuel_counter, but also takes into account intruction level parallelism and cache misses.
     // performance_counter: u64,
     pub exit_code: Option<u64>,
 }
 
+// embedders move emulators between threads, so losing Send is a breaking change
+const _: () = {
+    fn assert_send<T: Send>() {}
+    let _ = assert_send::<Emulator>;
+    let _ = assert_send::<Machine>;
+};
+
 impl Emulator {
+    /// Runs the program loaded into `memory` with the defaults, see [`EmulatorBuilder`] to
+    /// configure it
     pub fn new(memory: Memory) -> Self {
+        EmulatorBuilder::new(memory)
+            .build()
+            .expect("the defaults are always valid")
+    }
+
+    /// Like [`Emulator::new`], but with the auxiliary vector described by `auxv`, so guests that
+    /// detect features at runtime can be shown a different machine.
+    #[deprecated(note = "use `EmulatorBuilder::auxv`")]
+    pub fn new_with_auxv(memory: Memory, auxv: &AuxvConfig) -> Self {
+        EmulatorBuilder::new(memory)
+            .auxv(auxv.clone())
+            .build()
+            .expect("the defaults are always valid")
+    }
+
+    // an emulator at the entry point of the program in `memory`, with the stack set up for it
+    fn with_stack(
+        memory: Memory,
+        auxv: AuxvConfig,
+        args: Vec<String>,
+        env: Vec<String>,
+    ) -> Result<Self, RVError> {
         let mut em = Self {
             pc: memory.entry,
             // fscr: 0,
             x: [0; 32],
             f: [0.0; 32],
 
-            file_descriptors: HashMap::default(),
+            fds: FdTable::new(),
+            vfs: Vfs::default(),
+            sysroot: Sysroot::default(),
+            pid: 1,
+            ppid: 0,
+            next_pid: 2,
+            children: BTreeMap::new(),
+            auxv,
+            identity: MachineIdentity::default(),
+            args,
+            env,
+            stop_points: StopPoints::default(),
+            system: None,
+            syscall_handlers: BTreeMap::new(),
+            fallback_syscall_handler: None,
+            sandbox: None,
+            faults: None,
+            strace: None,
+            event_filter: EventFilter::NONE,
+            events: Vec::new(),
             stdout: String::new(),
             stderr: String::new(),
+            output_limit: None,
+            output_truncated: [false; 2],
 
             // if set, only count cycles when profile_start_point
             // then stop when return profile_end_point is reached
             // (automatically set from RA when profile_start_point is reached)
             profile_start_point: None,
             profile_end_point: None,
+            roi_start_point: None,
+            roi_start: None,
             profiler: Profiler::new(),
+            hpm_events: csr::DEFAULT_HPM_EVENTS,
 
-            jit_functions: BTreeMap::new(),
+            #[cfg(jit)]
+            jit_functions: JitFunctions::default(),
+            #[cfg(jit)]
+            exec_count: 0,
+            #[cfg(jit)]
+            jit_verification: false,
+            #[cfg(jit)]
+            jit_divergence: None,
 
             memory,
+            inst_cache: InstCache::new(),
+            block_cache: BlockCache::new(),
+            hle_routines: BTreeMap::new(),
+            intercepts: BTreeMap::new(),
+            heap_profile: None,
+            heap_routines: BTreeMap::new(),
+            in_heap_call: false,
+            memcheck_reports: Vec::new(),
+            frame_check: None,
+            frame_violation: None,
+            taint: None,
+            inst_history: None,
+            plt_bindings: None,
+            branch_input_log: None,
             exit_code: None,
             inst_counter: 0,
+            clock: GuestClock::default(),
             max_memory: 0,
-        };
 
-        em.x[SP] = STACK_START;
+            hart_id: 0,
+            hart_count: 1,
+            hart_exit_code: None,
+            reservation: None,
+
+            interrupts: BTreeMap::new(),
+            next_interrupt: u64::MAX,
+            exit_hooks: Vec::new(),
+            exit_summary_enabled: false,
+            exit_summary: None,
+            term_signal: None,
+            fuel_limit: None,
+            start: None,
+        };
 
-        // this can never fail
-        em.init_auxv_stack()
-            .expect("Failed to initialize aux vector");
+        em.x[SP] = em.memory.stack_top();
+        em.profiler
+            .set_dynamic_linker(em.memory.dynamic_linker.clone());
 
-        em
+        em.init_auxv_stack()?;
+        Ok(em)
     }
 
+    #[cfg(feature = "std")]
     pub fn from_file<P>(path: P) -> Result<Emulator, anyhow::Error>
     where
         P: AsRef<Path>,
     {
-        let file_data = std::fs::read(path).expect("Could not read file.");
-        let slice = file_data.as_slice();
-        let file = ElfBytes::<AnyEndian>::minimal_parse(slice)?;
-
-        match (file.ehdr.class, file.ehdr.e_type, file.ehdr.e_machine) {
-            // (64 bit, executable, risc_v arch)
-            (elf::file::Class::ELF64, 0x03 | 0x02, 0xF3) => log::info!("Parsing executable."),
-            _ => return Err(RVError::InvalidFileType.into()),
-        }
-
-        let memory = Memory::load_elf(file);
-        let emulator = Emulator::new(memory);
+        let file_data = std::fs::read(path)?;
+        Ok(Emulator::from_elf_bytes(&file_data)?)
+    }
 
-        Ok(emulator)
+    /// Loads the executable `data` with the defaults, failing with [`RVError::InvalidFileType`]
+    /// if it isn't a 64-bit RISC-V executable. See [`EmulatorBuilder::from_elf`] to configure it.
+    pub fn from_elf_bytes(data: &[u8]) -> Result<Emulator, RVError> {
+        Ok(Emulator::new(Memory::load_elf_bytes(
+            data,
+            &LoadOptions::default(),
+        )?))
     }
 
+    #[deprecated(note = "use `EmulatorBuilder::profile_label`")]
     pub fn profile_label(&mut self, label: &str) -> Result<(), RVError> {
         self.profile_start_point = NonZeroU64::new(
             self.memory
@@ -133,129 +423,347 @@ impl Emulator {
         Ok(())
     }
 
-    pub fn set_stdin(&mut self, data: &[u8]) {
-        self.file_descriptors.insert(
-            0,
-            FileDescriptor {
-                offset: 0,
-                data: data.into(),
-            },
-        );
+    /// Records the guest's call stack every `interval` instructions from now on, into
+    /// `profiler.samples`. Stacks are walked like [`Emulator::backtrace`].
+    pub fn sample_stacks(&mut self, interval: u64) {
+        self.schedule_interrupt(interval, move |emulator| {
+            let sample = StackSample {
+                inst_count: emulator.inst_counter,
+                cycle_count: emulator.profiler.cycle_count,
+                stack: emulator.backtrace(SAMPLE_FRAME_LIMIT),
+            };
+            emulator.profiler.samples.push(sample);
+
+            emulator.sample_stacks(interval);
+        });
     }
 
-    // https://github.com/torvalds/linux/blob/master/fs/binfmt_elf.c#L175
-    // https://github.com/lattera/glibc/blob/895ef79e04a953cac1493863bcae29ad85657ee1/elf/dl-support.c#L228
-    fn init_auxv_stack(&mut self) -> Result<(), RVError> {
-        self.x[SP] -= RANDOM_BYTES;
+    /// Makes `data` the guest's standard input. It isn't copied if it's already an `Arc`, so
+    /// the same input can be given to many emulators without copying it for each.
+    #[deprecated(note = "use `EmulatorBuilder::stdin`")]
+    pub fn set_stdin(&mut self, data: impl Into<Arc<[u8]>>) {
+        self.fds
+            .insert(0, OpenFile::File(FileDescriptor::new(data)));
+    }
 
-        let at_random_addr = self.x[SP];
+    /// Starts the guest with the file descriptors in `fds` open, instead of only stdout and
+    /// stderr, like a test harness passing extra descriptors to a child process
+    #[deprecated(note = "use `EmulatorBuilder::fds`")]
+    pub fn with_fds(mut self, fds: FdTable) -> Self {
+        self.fds = fds;
+        self
+    }
 
-        // initialize random bytes to 0..16
-        for i in 0..16 {
-            self.memory.store::<u8>(at_random_addr + i, i as u8)?;
-        }
+    pub fn fds(&self) -> &FdTable {
+        &self.fds
+    }
 
-        self.x[SP] -= 8; // for alignment
-        let program_name_addr = self.x[SP];
-        self.memory.write_n(b"/prog\0", program_name_addr, 8)?;
+    pub fn fds_mut(&mut self) -> &mut FdTable {
+        &mut self.fds
+    }
 
-        self.x[SP] -= 16;
-        let envp1_addr = self.x[SP];
-        self.memory.write_n(b"LD_DEBUG=all\0", envp1_addr, 13)?;
+    /// An independent copy of the emulator, to explore a different branch of the execution from
+    /// here and throw it away. With [`MemoryLayout::Cow`] memory is shared until either copy writes
+    /// to a page, so forking is cheap, while other layouts copy all of it.
+    ///
+    /// The contents of open files are shared, but each copy reads from its own offset. The
+    /// profiler, heap profile and check reports are copied, so the fork's results cover what ran
+    /// before it too. Breakpoints, exit hooks and pending interrupts carry over and the jit's
+    /// compiled functions are shared, but a branch input log stays with the original.
+    ///
+    /// [`MemoryLayout::Cow`]: crate::memory::MemoryLayout::Cow
+    pub fn fork(&self) -> Emulator {
+        let mut fork = self.clone();
+        fork.branch_input_log = None;
+        fork
+    }
 
-        // argc
-        self.x[SP] -= 8;
-        self.memory.store(self.x[SP], 1u32)?; // one argument
+    // https://github.com/torvalds/linux/blob/master/fs/binfmt_elf.c#L175
+    // https://github.com/lattera/glibc/blob/895ef79e04a953cac1493863bcae29ad85657ee1/elf/dl-support.c#L228
+    //
+    // from sp upwards: argc, the argv and envp pointers each ending with a null pointer, then the
+    // auxv pairs ending with AT_NULL. The strings they point to are above them.
+    pub(super) fn init_auxv_stack(&mut self) -> Result<(), RVError> {
+        let auxv = &self.auxv;
 
-        // argv
-        self.x[SP] -= 8; // argv[0]
-        self.memory.store(self.x[SP], program_name_addr)?;
+        self.x[SP] -= RANDOM_BYTES;
+        let at_random_addr = self.x[SP];
+        self.memory
+            .write_n(&auxv.random, at_random_addr, RANDOM_BYTES)?;
 
-        log::trace!("Writing argv to addr=0x{:x}", self.x[SP]);
+        let platform_addr = match auxv.platform {
+            Some(ref platform) => Some(push_string(&mut self.memory, &mut self.x[SP], platform)?),
+            None => None,
+        };
 
-        // envp
-        // self.x[SP] -= 8; // envp[0]
-        // self.memory.store_u64(self.x[SP], envp1_addr);
-        self.x[SP] -= 8;
+        let mut strings = |strings: &[String]| {
+            strings
+                .iter()
+                .map(|string| push_string(&mut self.memory, &mut self.x[SP], string))
+                .collect::<Result<Vec<u64>, RVError>>()
+        };
+        let args = strings(&self.args)?;
+        let env = strings(&self.env)?;
+        let program_name_addr = match args.first() {
+            Some(&addr) => addr,
+            None => push_string(&mut self.memory, &mut self.x[SP], DEFAULT_PROGRAM_NAME)?,
+        };
 
-        // minimal auxv
-        let aux_values = [
+        let mut aux_values = vec![
             AuxPair(Auxv::Entry, self.memory.program_header.entry), // The address of the entry of the executable
             AuxPair(Auxv::Phdr, self.memory.program_header.address), // The address of the program header of the executable
             AuxPair(Auxv::Phent, self.memory.program_header.size), // The size of the program header entry
             AuxPair(Auxv::Phnum, self.memory.program_header.number), // The number of the program headers
-            AuxPair(Auxv::Uid, 0),
-            AuxPair(Auxv::Euid, 0),
-            AuxPair(Auxv::Gid, 0),
-            AuxPair(Auxv::Egid, 0),
+            AuxPair(Auxv::Hwcap, auxv.hwcap),
+            AuxPair(Auxv::Clktlk, auxv.clock_tick),
+            AuxPair(Auxv::Uid, auxv.uid),
+            AuxPair(Auxv::Euid, auxv.euid),
+            AuxPair(Auxv::Gid, auxv.gid),
+            AuxPair(Auxv::Egid, auxv.egid),
             AuxPair(Auxv::Secure, 0),
             AuxPair(Auxv::Pagesz, PAGE_SIZE),
             AuxPair(Auxv::Random, at_random_addr),
             AuxPair(Auxv::Execfn, program_name_addr),
-            AuxPair(Auxv::Null, 0),
         ];
-
-        for AuxPair(key, val) in aux_values.into_iter() {
-            self.x[SP] -= 16;
-            log::trace!("Writing {:?}=0x{:x} at 0x{:x}", key, val, self.x[SP]);
-            // self.memory.store_u64(self.x[SP], key as u64);
-            self.memory.store(self.x[SP], key as u64)?;
-            self.memory.store(self.x[SP] + 8, val)?;
+        if let Some(platform_addr) = platform_addr {
+            aux_values.push(AuxPair(Auxv::Platform, platform_addr));
+        }
+        aux_values.push(AuxPair(Auxv::Null, 0));
+
+        let mut words = vec![args.len() as u64];
+        words.extend(&args);
+        words.push(0);
+        words.extend(&env);
+        words.push(0);
+        for AuxPair(key, val) in aux_values {
+            log::trace!("Writing {:?}=0x{:x}", key, val);
+            words.extend([key as u64, val]);
         }
 
-        // padding or smthn
-        self.x[SP] -= 8;
+        // the ABI wants sp 16 byte aligned
+        self.x[SP] = (self.x[SP] - words.len() as u64 * 8) & !15;
+        for (i, word) in words.into_iter().enumerate() {
+            self.memory.store(self.x[SP] + i as u64 * 8, word)?;
+        }
 
         Ok(())
     }
 
-    pub fn fetch(&self) -> Result<(Inst, u8), RVError> {
+    pub fn fetch(&mut self) -> Result<(Inst, u8), RVError> {
+        // the same pc can be other code after the page table changes, so translated fetches
+        // aren't cached
+        #[cfg(feature = "mmu")]
+        if self.memory.is_translating() {
+            return Ok(Inst::decode(self.memory.fetch(self.pc)?));
+        }
+
+        if let Some(decoded) = self.inst_cache.get(self.pc, &self.memory) {
+            return Ok(decoded);
+        }
+
         let inst_data = self.memory.load::<u32>(self.pc)?;
-        Ok(Inst::decode(inst_data))
+        let decoded = Inst::decode(inst_data);
+        self.inst_cache.insert(self.pc, decoded, &mut self.memory);
+
+        Ok(decoded)
+    }
+
+    /// The decoded instruction and basic block caches are enabled by default
+    pub fn set_inst_cache_enabled(&mut self, enabled: bool) {
+        self.inst_cache.enabled = enabled;
+        self.inst_cache.invalidate();
+        self.block_cache.invalidate();
+    }
+
+    // makes every instruction be fetched and compiled again, after fence.i or
+    // riscv_flush_icache
+    pub(super) fn flush_icache(&mut self) {
+        self.memory.flush_code();
+        #[cfg(jit)]
+        self.jit_functions.invalidate();
+    }
+
+    /// The profiler's counters so far. Only counted while profiling, which is enabled by
+    /// [`Emulator::profile_label`] or by setting `profiler.running`.
+    pub fn profile_snapshot(&self) -> ProfileSnapshot {
+        self.profiler.snapshot(self.inst_counter)
+    }
+
+    /// Starts the region of interest when the guest first reaches `label`, like `main` to leave
+    /// out libc's startup or a function called after the program initializes. The profile of the
+    /// region is counted from there alongside the profile of the whole run, see
+    /// [`Emulator::region_of_interest_profile`].
+    pub fn set_region_of_interest(&mut self, label: &str) -> Result<(), RVError> {
+        self.roi_start_point = NonZeroU64::new(
+            self.memory
+                .disassembler
+                .get_symbol_addr(label)
+                .ok_or(RVError::InvalidLabel)?,
+        );
+        self.roi_start = None;
+
+        Ok(())
+    }
+
+    /// The profiler's counters since the region of interest started, or `None` if it hasn't
+    pub fn region_of_interest_profile(&self) -> Option<ProfileSnapshot> {
+        Some(self.profile_snapshot() - self.roi_start?)
     }
 
+    // called when the guest reaches the start of the region of interest, which only starts it the
+    // first time
+    pub(super) fn start_region_of_interest(&mut self) {
+        if self.roi_start_point.take().is_some() {
+            self.roi_start = Some(self.profile_snapshot());
+        }
+    }
+
+    /// Statistics about the jit compiler, shared between clones of this emulator.
+    #[cfg(jit)]
+    pub fn jit_stats(&self) -> JitStats {
+        self.jit_functions.stats()
+    }
+
+    #[cfg(jit)]
     fn execute_block(&mut self) -> Result<Option<u64>, RVError> {
-        if let Some(stored) = self.jit_functions.get(&self.pc) {
-            stored.clone().run(self);
-        } else {
-            let profile = self.profile_start_point.is_some();
-            let newfunc = Rc::new(RVFunction::compile(self, profile));
-            self.jit_functions.insert(self.pc, newfunc.clone());
-            newfunc.run(self);
+        if self.try_hle()? {
+            return Ok(self.exit_code);
         }
 
+        self.profiler.trace_call(self.pc, true);
+
+        match self.jit_functions.get(self.pc) {
+            Some(JitState::Ready(function)) => {
+                self.profiler.trace_jit_ready(self.pc);
+                if self.jit_verification {
+                    self.run_verified(&function)?;
+                } else {
+                    function.run(self);
+                }
+            }
+            Some(JitState::Pending | JitState::Failed) => {
+                self.jit_functions.count_interpreted_call();
+                self.interp_function()?;
+            }
+            None => {
+                let profile = self.profile_start_point.is_some() || self.profiler.running;
+                let job = CompileJob::new(self, profile);
+                if job.is_some() {
+                    self.profiler.trace_jit_queued(self.pc);
+                }
+                self.jit_functions.submit(self.pc, job);
+
+                self.jit_functions.count_interpreted_call();
+                self.interp_function()?;
+            }
+        }
+
+        self.profiler.trace_return(true);
+
         Ok(self.exit_code)
     }
 
+    // interprets the function at pc until it returns, while the jit compiles it. Calls it makes
+    // go back through the jit.
+    #[cfg(jit)]
+    fn interp_function(&mut self) -> Result<(), RVError> {
+        let (return_addr, sp) = (self.x[RA], self.x[SP]);
+        let exec_count = self.exec_count;
+
+        loop {
+            let (inst, incr) = self.fetch()?;
+            let link = self.pc.wrapping_add(incr as u64);
+
+            if self.execute_next()?.is_some() || self.exec_count != exec_count {
+                return Ok(());
+            }
+
+            let call = matches!(inst, Inst::Jal { rd: RA, .. } | Inst::Jalr { rd: RA, .. });
+            if call
+                && self.x[RA] == link
+                && (self.execute_block()?.is_some() || self.exec_count != exec_count)
+            {
+                return Ok(());
+            }
+
+            if self.pc == return_addr && self.x[SP] == sp {
+                return Ok(());
+            }
+        }
+    }
+
     pub fn run(&mut self, jit: bool) -> Result<u64, RVError> {
-        if jit {
-            // jit
+        let result = self.run_blocks(jit);
+        self.record_fault(result)
+    }
+
+    fn run_blocks(&mut self, jit: bool) -> Result<u64, RVError> {
+        #[cfg(jit)]
+        if jit && self.checks_every_instruction() {
+            log::warn!("instructions are being checked, falling back to the interpreter");
+        } else if jit && self.sandbox.is_some() {
+            // compiled code can't stop at a syscall the policy doesn't allow
+            log::warn!("a sandbox policy is set, falling back to the interpreter");
+        } else if jit {
+            self.profiler.calls_by_block = true;
+
             loop {
+                // interrupts can only be delivered between blocks
+                if self.next_interrupt <= self.inst_counter {
+                    self.deliver_interrupts();
+                    self.check_fuel()?;
+                }
+
                 if let Some(exit_code) = self.execute_block()? {
                     return Ok(exit_code);
                 }
             }
-        } else {
-            // interp
-            loop {
-                if let Some(exit_code) = self.fetch_and_execute()? {
-                    return Ok(exit_code);
-                }
+        }
+
+        #[cfg(not(jit))]
+        if jit {
+            log::warn!(
+                "the jit needs the `jit` feature and an x86_64 host, falling back to the interpreter"
+            );
+        }
+
+        // interp
+        loop {
+            if let Some(exit_code) = self.execute_interp_block()? {
+                return Ok(exit_code);
             }
         }
     }
 
     pub fn fetch_and_execute(&mut self) -> Result<Option<u64>, RVError> {
+        let result = self.execute_next();
+        self.record_fault(result)
+    }
+
+    // `fetch_and_execute`, without recording a fault event, for callers that record their own
+    pub(super) fn execute_next(&mut self) -> Result<Option<u64>, RVError> {
         if self.exit_code.is_some() {
             return Ok(self.exit_code);
         }
 
-        let (inst, incr) = self.fetch()?;
+        if self.next_interrupt <= self.inst_counter {
+            self.deliver_interrupts();
+
+            if self.exit_code.is_some() {
+                return Ok(self.exit_code);
+            }
+            self.check_fuel()?;
+        }
+
+        if NonZeroU64::new(self.pc) == self.roi_start_point {
+            self.start_region_of_interest();
+        }
 
         // if we reach the end
         if NonZeroU64::new(self.pc) == self.profile_start_point {
             self.profile_end_point = NonZeroU64::new(self.x[RA]);
             self.profiler.running = true;
+            self.profiler.start_function(self.pc);
         }
         // save final_cycle_count
         else if NonZeroU64::new(self.pc) == self.profile_end_point {
@@ -264,25 +772,159 @@ impl Emulator {
             self.profiler.running = false;
         }
 
+        if self.try_hle()? {
+            return Ok(self.exit_code);
+        }
+
+        let (inst, incr) = match self.fetch() {
+            Ok(fetched) => fetched,
+            Err(e) => {
+                if let Some(handler) = self.fault_trap(None, &e) {
+                    self.pc = handler;
+                    return Ok(self.exit_code);
+                }
+
+                self.record_taint_fault(None);
+                return Err(e);
+            }
+        };
+
+        if let Some((cause, tval)) = self.privilege_violation(inst) {
+            self.pc = self.trap_or_stop(cause, tval)?;
+            return Ok(self.exit_code);
+        }
+        self.check_sandbox_inst(inst, incr)?;
+
+        if let Some(ref mut history) = self.inst_history {
+            history.push(self.pc, inst);
+        }
+
+        if !self.in_heap_call && self.memory.is_memcheck_enabled() {
+            self.check_memory_access(inst);
+        }
+
+        if self.frame_check.is_some() {
+            self.check_frame(inst, incr as u64)?;
+        }
+
+        if self.taint.is_some() {
+            self.propagate_taint(inst);
+        }
+
+        let plt_store = match self.plt_bindings {
+            Some(_) => self.plt_store(inst),
+            None => None,
+        };
+
         // this log statement is nice but it is super slow even when not printing unfortunately
         // log::debug!("{:16x} {}", self.pc, inst.fmt(self.pc));
 
-        self.execute(inst, incr as u64)?;
+        let result = if self.profiler.running {
+            self.execute::<true>(inst, incr as u64)
+        } else {
+            self.execute::<false>(inst, incr as u64)
+        };
+
+        if let Err(ref e) = result {
+            if let Some(handler) = self.fault_trap(Some(inst), e) {
+                self.pc = handler;
+                return Ok(self.exit_code);
+            }
+
+            self.record_taint_fault(Some(inst));
+        }
+        result?;
+
+        if let Some(slot) = plt_store {
+            self.record_plt_binding(slot);
+        }
 
-        self.max_memory = self.max_memory.max(self.memory.usage());
+        self.update_memory_usage()?;
 
         Ok(self.exit_code)
     }
 
+    // records the peak memory usage, stopping once it's past the memory limit
+    fn update_memory_usage(&mut self) -> Result<(), RVError> {
+        let usage = self.memory.usage();
+        self.max_memory = self.max_memory.max(usage);
+
+        match self.memory.memory_limit() {
+            Some(limit) if usage > limit => Err(RVError::MemoryLimitExceeded { usage, limit }),
+            _ => Ok(()),
+        }
+    }
+
     #[cfg(test)]
     fn execute_raw(&mut self, inst_data: u32) -> Result<(), RVError> {
         let (inst, incr) = Inst::decode(inst_data);
-        self.execute(inst, incr as u64)?;
+        self.execute::<true>(inst, incr as u64)?;
         self.print_registers();
 
         Ok(())
     }
 
+    // whether memcheck, frame checking, taint tracking, the instruction history, system mode or
+    // the dual issue model need to see each instruction before it runs, which only execute_next
+    // does
+    pub(super) fn checks_every_instruction(&self) -> bool {
+        self.memory.is_memcheck_enabled()
+            || self.frame_check.is_some()
+            || self.taint.is_some()
+            || self.inst_history.is_some()
+            || self.plt_bindings.is_some()
+            || self.system.is_some()
+            || self.profiler.model().dual_issue
+            || self.restricts_extensions()
+    }
+
+    pub fn reg(&self, reg: Reg) -> u64 {
+        self.x[reg]
+    }
+
+    pub fn freg(&self, reg: FReg) -> f64 {
+        self.f[reg]
+    }
+
+    /// writes to x0 are ignored
+    pub fn set_reg(&mut self, reg: Reg, value: u64) {
+        if reg.0 != 0 {
+            self.x[reg] = value;
+        }
+    }
+
+    /// The pc followed by the return address of each caller, innermost first. Callers are found
+    /// by following saved frame pointers, so the walk stops at the first function compiled
+    /// without them, and can miss a frame in a function's prologue.
+    pub fn backtrace(&self, max_frames: usize) -> Vec<u64> {
+        let mut frames = vec![self.pc];
+        let mut fp = self.x[S0];
+
+        while frames.len() < max_frames && fp != 0 {
+            // ra and the caller's frame pointer are saved just below the frame pointer
+            let (Ok(ra), Ok(caller_fp)) = (
+                self.memory.load::<u64>(fp.wrapping_sub(8)),
+                self.memory.load::<u64>(fp.wrapping_sub(16)),
+            ) else {
+                break;
+            };
+
+            if ra == 0 {
+                break;
+            }
+
+            frames.push(ra);
+
+            // frames are further up the stack the further out they are
+            if caller_fp <= fp {
+                break;
+            }
+            fp = caller_fp;
+        }
+
+        frames
+    }
+
     pub fn print_registers(&self) -> String {
         let mut output = String::new();
 
@@ -298,15 +940,32 @@ impl Emulator {
         output
     }
 
-    fn execute(&mut self, inst: Inst, incr: u64) -> Result<(), RVError> {
+    // PROFILE is only needed while the profiler is running. Without it every profiler call is
+    // compiled out, which took fib(35) in the interpreter from ~4.4s to ~3.4s.
+    fn execute<const PROFILE: bool>(&mut self, inst: Inst, incr: u64) -> Result<(), RVError> {
+        profile!(self.issue(inst, self.pc));
+
         match inst {
             Inst::Fence => {} // noop currently, to do with concurrency I think
+            Inst::FenceI => self.flush_icache(),
             Inst::Ebreak => {}
+            Inst::Ecall if self.system.is_some() => {
+                if let Some(handler) = self.system_ecall()? {
+                    self.pc = handler.wrapping_sub(incr);
+                }
+            }
             Inst::Ecall => {
-                self.profiler.pipeline_stall_x(A7, self.pc);
+                profile!(self.pipeline_stall_x(A7, self.pc));
 
                 self.syscall()?;
             }
+            Inst::Mret => self.pc = self.trap_return(Privilege::Machine).wrapping_sub(incr),
+            Inst::Sret => self.pc = self.trap_return(Privilege::Supervisor).wrapping_sub(incr),
+            Inst::Wfi => {} // there are no interrupts to wait for
+            Inst::SfenceVma => {
+                #[cfg(feature = "mmu")]
+                self.memory.flush_tlb();
+            }
             Inst::Error(e) => {
                 log::error!("unknown instruction: {e:x}");
             }
@@ -314,222 +973,234 @@ impl Emulator {
                 self.x[rd] = imm as u64;
             }
             Inst::Ld { rd, rs1, offset } => {
-                self.profiler.pipeline_stall_x(rs1, self.pc);
+                profile!(self.pipeline_stall_x(rs1, self.pc));
 
                 let addr = self.x[rs1].wrapping_add(offset as u64);
-                self.profiler.add_load_delay_x(rd, addr, self.pc);
+                profile!(self.add_load_delay_x(rd, addr, self.pc));
 
                 self.x[rd] = self.memory.load(addr)?;
             }
             Inst::Fld { rd, rs1, offset } => {
-                self.profiler.pipeline_stall_x(rs1, self.pc);
+                profile!(self.pipeline_stall_x(rs1, self.pc));
 
                 let addr = self.x[rs1].wrapping_add(offset as u64);
-                self.profiler.add_load_delay_f(rd, addr, self.pc);
+                profile!(self.add_load_delay_f(rd, addr, self.pc));
 
                 self.f[rd] = f64::from_bits(self.memory.load(addr)?);
             }
             Inst::Flw { rd, rs1, offset } => {
-                self.profiler.pipeline_stall_x(rs1, self.pc);
+                profile!(self.pipeline_stall_x(rs1, self.pc));
 
                 let addr = self.x[rs1].wrapping_add(offset as u64);
-                self.profiler.add_load_delay_f(rd, addr, self.pc);
+                profile!(self.add_load_delay_f(rd, addr, self.pc));
 
                 self.f[rd] = f32::from_bits(self.memory.load(addr)?) as f64;
             }
             Inst::Lw { rd, rs1, offset } => {
-                self.profiler.pipeline_stall_x(rs1, self.pc);
+                profile!(self.pipeline_stall_x(rs1, self.pc));
 
                 let addr = self.x[rs1].wrapping_add(offset as u64);
-                self.profiler.add_load_delay_x(rd, addr, self.pc);
+                profile!(self.add_load_delay_x(rd, addr, self.pc));
 
                 self.x[rd] = self.memory.load::<i32>(addr)? as u64;
             }
             Inst::Lwu { rd, rs1, offset } => {
-                self.profiler.pipeline_stall_x(rs1, self.pc);
+                profile!(self.pipeline_stall_x(rs1, self.pc));
 
                 let addr = self.x[rs1].wrapping_add(offset as u64);
-                self.profiler.add_load_delay_x(rd, addr, self.pc);
+                profile!(self.add_load_delay_x(rd, addr, self.pc));
 
                 self.x[rd] = self.memory.load::<u32>(addr)? as u64;
             }
             Inst::Lhu { rd, rs1, offset } => {
-                self.profiler.pipeline_stall_x(rs1, self.pc);
+                profile!(self.pipeline_stall_x(rs1, self.pc));
 
                 let addr = self.x[rs1].wrapping_add(offset as u64);
-                self.profiler.add_load_delay_x(rd, addr, self.pc);
+                profile!(self.add_load_delay_x(rd, addr, self.pc));
 
                 self.x[rd] = self.memory.load::<u16>(addr)? as u64;
             }
             Inst::Lb { rd, rs1, offset } => {
-                self.profiler.pipeline_stall_x(rs1, self.pc);
+                profile!(self.pipeline_stall_x(rs1, self.pc));
 
                 let addr = self.x[rs1].wrapping_add(offset as u64);
-                self.profiler.add_load_delay_x(rd, addr, self.pc);
+                profile!(self.add_load_delay_x(rd, addr, self.pc));
 
                 self.x[rd] = self.memory.load::<i8>(addr)? as u64;
             }
             Inst::Lbu { rd, rs1, offset } => {
-                self.profiler.pipeline_stall_x(rs1, self.pc);
+                profile!(self.pipeline_stall_x(rs1, self.pc));
 
                 let addr = self.x[rs1].wrapping_add(offset as u64);
-                self.profiler.add_load_delay_x(rd, addr, self.pc);
+                profile!(self.add_load_delay_x(rd, addr, self.pc));
 
                 self.x[rd] = self.memory.load::<u8>(addr)? as u64;
             }
             Inst::Sd { rs1, rs2, offset } => {
-                self.profiler.pipeline_stall_xx(rs1, rs2, self.pc);
+                profile!(self.pipeline_stall_xx(rs1, rs2, self.pc));
 
                 let addr = self.x[rs1].wrapping_add(offset as u64);
+                profile!(self.add_store(addr, self.pc));
+
                 self.memory.store(addr, self.x[rs2])?;
             }
             Inst::Fsd { rs1, rs2, offset } => {
-                self.profiler.pipeline_stall_xf(rs1, rs2, self.pc);
+                profile!(self.pipeline_stall_xf(rs1, rs2, self.pc));
 
                 let addr = self.x[rs1].wrapping_add(offset as u64);
+                profile!(self.add_store(addr, self.pc));
+
                 self.memory.store(addr, self.f[rs2].to_bits())?;
             }
             Inst::Fsw { rs1, rs2, offset } => {
-                self.profiler.pipeline_stall_xf(rs1, rs2, self.pc);
+                profile!(self.pipeline_stall_xf(rs1, rs2, self.pc));
 
                 let addr = self.x[rs1].wrapping_add(offset as u64);
+                profile!(self.add_store(addr, self.pc));
+
                 self.memory.store(addr, (self.f[rs2] as f32).to_bits())?;
             }
             Inst::Sw { rs1, rs2, offset } => {
-                self.profiler.pipeline_stall_xx(rs1, rs2, self.pc);
+                profile!(self.pipeline_stall_xx(rs1, rs2, self.pc));
 
                 let addr = self.x[rs1].wrapping_add(offset as u64);
+                profile!(self.add_store(addr, self.pc));
+
                 self.memory.store(addr, self.x[rs2] as u32)?;
             }
             Inst::Sh { rs1, rs2, offset } => {
-                self.profiler.pipeline_stall_xx(rs1, rs2, self.pc);
+                profile!(self.pipeline_stall_xx(rs1, rs2, self.pc));
 
                 let addr = self.x[rs1].wrapping_add(offset as u64);
+                profile!(self.add_store(addr, self.pc));
+
                 self.memory.store(addr, self.x[rs2] as u16)?;
             }
             Inst::Sb { rs1, rs2, offset } => {
-                self.profiler.pipeline_stall_xx(rs1, rs2, self.pc);
+                profile!(self.pipeline_stall_xx(rs1, rs2, self.pc));
 
                 let addr = self.x[rs1].wrapping_add(offset as u64);
+                profile!(self.add_store(addr, self.pc));
+
                 self.memory.store(addr, self.x[rs2] as u8)?;
             }
             Inst::Add { rd, rs1, rs2 } => {
-                self.profiler.pipeline_stall_xx(rs1, rs2, self.pc);
+                profile!(self.pipeline_stall_xx(rs1, rs2, self.pc));
 
                 self.x[rd] = self.x[rs1].wrapping_add(self.x[rs2]);
             }
             Inst::Addw { rd, rs1, rs2 } => {
-                self.profiler.pipeline_stall_xx(rs1, rs2, self.pc);
+                profile!(self.pipeline_stall_xx(rs1, rs2, self.pc));
 
                 self.x[rd] = (self.x[rs1] as i32).wrapping_add(self.x[rs2] as i32) as u64;
             }
             Inst::Addi { rd, rs1, imm } => {
-                self.profiler.pipeline_stall_x(rs1, self.pc);
+                profile!(self.pipeline_stall_x(rs1, self.pc));
 
                 self.x[rd] = self.x[rs1].wrapping_add(imm as u64);
             }
             Inst::Addiw { rd, rs1, imm } => {
-                self.profiler.pipeline_stall_x(rs1, self.pc);
+                profile!(self.pipeline_stall_x(rs1, self.pc));
 
                 self.x[rd] = (self.x[rs1] as i32).wrapping_add(imm) as u64;
             }
             Inst::And { rd, rs1, rs2 } => {
-                self.profiler.pipeline_stall_xx(rs1, rs2, self.pc);
+                profile!(self.pipeline_stall_xx(rs1, rs2, self.pc));
 
                 self.x[rd] = self.x[rs1] & self.x[rs2];
             }
             Inst::Andi { rd, rs1, imm } => {
-                self.profiler.pipeline_stall_x(rs1, self.pc);
+                profile!(self.pipeline_stall_x(rs1, self.pc));
 
                 self.x[rd] = self.x[rs1] & (imm as u64);
             }
             Inst::Sub { rd, rs1, rs2 } => {
-                self.profiler.pipeline_stall_xx(rs1, rs2, self.pc);
+                profile!(self.pipeline_stall_xx(rs1, rs2, self.pc));
 
                 self.x[rd] = self.x[rs1].wrapping_sub(self.x[rs2]);
             }
             Inst::Subw { rd, rs1, rs2 } => {
-                self.profiler.pipeline_stall_xx(rs1, rs2, self.pc);
+                profile!(self.pipeline_stall_xx(rs1, rs2, self.pc));
 
                 self.x[rd] = (self.x[rs1] as i32).wrapping_sub(self.x[rs2] as i32) as u64;
             }
             Inst::Sll { rd, rs1, rs2 } => {
-                self.profiler.pipeline_stall_xx(rs1, rs2, self.pc);
+                profile!(self.pipeline_stall_xx(rs1, rs2, self.pc));
 
-                self.x[rd] = self.x[rs1] << self.x[rs2];
+                self.x[rd] = self.x[rs1].wrapping_shl(self.x[rs2] as u32);
             }
             Inst::Sllw { rd, rs1, rs2 } => {
-                self.profiler.pipeline_stall_xx(rs1, rs2, self.pc);
+                profile!(self.pipeline_stall_xx(rs1, rs2, self.pc));
 
                 self.x[rd] = ((self.x[rs1] as u32).wrapping_shl(self.x[rs2] as u32)) as i32 as u64;
             }
             Inst::Slli { rd, rs1, shamt } => {
-                self.profiler.pipeline_stall_x(rs1, self.pc);
+                profile!(self.pipeline_stall_x(rs1, self.pc));
 
                 self.x[rd] = self.x[rs1] << shamt;
             }
             Inst::Slliw { rd, rs1, shamt } => {
-                self.profiler.pipeline_stall_x(rs1, self.pc);
+                profile!(self.pipeline_stall_x(rs1, self.pc));
 
-                self.x[rd] = ((self.x[rs1] as u32).wrapping_shl(shamt)) as u64;
+                self.x[rd] = ((self.x[rs1] as u32).wrapping_shl(shamt)) as i32 as u64;
             }
             Inst::Srl { rd, rs1, rs2 } => {
-                self.profiler.pipeline_stall_xx(rs1, rs2, self.pc);
+                profile!(self.pipeline_stall_xx(rs1, rs2, self.pc));
 
                 self.x[rd] = self.x[rs1].wrapping_shr(self.x[rs2] as u32);
             }
             Inst::Srlw { rd, rs1, rs2 } => {
-                self.profiler.pipeline_stall_xx(rs1, rs2, self.pc);
+                profile!(self.pipeline_stall_xx(rs1, rs2, self.pc));
 
                 self.x[rd] = ((self.x[rs1] as u32).wrapping_shr(self.x[rs2] as u32)) as i32 as u64;
             }
             Inst::Srli { rd, rs1, shamt } => {
-                self.profiler.pipeline_stall_x(rs1, self.pc);
+                profile!(self.pipeline_stall_x(rs1, self.pc));
 
                 self.x[rd] = self.x[rs1] >> shamt;
             }
             Inst::Srliw { rd, rs1, shamt } => {
-                self.profiler.pipeline_stall_x(rs1, self.pc);
+                profile!(self.pipeline_stall_x(rs1, self.pc));
 
-                self.x[rd] = ((self.x[rs1] as u32).wrapping_shr(shamt)) as u64;
+                self.x[rd] = ((self.x[rs1] as u32).wrapping_shr(shamt)) as i32 as u64;
             }
             Inst::Sra { rd, rs1, rs2 } => {
-                self.profiler.pipeline_stall_xx(rs1, rs2, self.pc);
+                profile!(self.pipeline_stall_xx(rs1, rs2, self.pc));
 
                 self.x[rd] = ((self.x[rs1] as i64).wrapping_shr(self.x[rs2] as u32)) as u64;
             }
             Inst::Sraw { rd, rs1, rs2 } => {
-                self.profiler.pipeline_stall_xx(rs1, rs2, self.pc);
+                profile!(self.pipeline_stall_xx(rs1, rs2, self.pc));
 
                 self.x[rd] = ((self.x[rs1] as i32).wrapping_shr(self.x[rs2] as u32)) as u64;
             }
             Inst::Srai { rd, rs1, shamt } => {
-                self.profiler.pipeline_stall_x(rs1, self.pc);
+                profile!(self.pipeline_stall_x(rs1, self.pc));
 
                 self.x[rd] = ((self.x[rs1] as i64) >> shamt) as u64;
             }
             Inst::Sraiw { rd, rs1, shamt } => {
-                self.profiler.pipeline_stall_x(rs1, self.pc);
+                profile!(self.pipeline_stall_x(rs1, self.pc));
 
                 self.x[rd] = ((self.x[rs1] as i32) >> shamt) as u64;
             }
             Inst::Or { rd, rs1, rs2 } => {
-                self.profiler.pipeline_stall_xx(rs1, rs2, self.pc);
+                profile!(self.pipeline_stall_xx(rs1, rs2, self.pc));
 
                 self.x[rd] = self.x[rs1] | self.x[rs2];
             }
             Inst::Ori { rd, rs1, imm } => {
-                self.profiler.pipeline_stall_x(rs1, self.pc);
+                profile!(self.pipeline_stall_x(rs1, self.pc));
 
                 self.x[rd] = self.x[rs1] | imm as u64;
             }
             Inst::Xor { rd, rs1, rs2 } => {
-                self.profiler.pipeline_stall_xx(rs1, rs2, self.pc);
+                profile!(self.pipeline_stall_xx(rs1, rs2, self.pc));
 
                 self.x[rd] = self.x[rs1] ^ self.x[rs2];
             }
             Inst::Xori { rd, rs1, imm } => {
-                self.profiler.pipeline_stall_x(rs1, self.pc);
+                profile!(self.pipeline_stall_x(rs1, self.pc));
 
                 self.x[rd] = self.x[rs1] ^ imm as u64;
             }
@@ -537,61 +1208,71 @@ impl Emulator {
                 self.x[rd] = self.pc.wrapping_add(imm as i64 as u64);
             }
             Inst::Jal { rd, offset } => {
+                if rd == RA {
+                    profile!(self.trace_call(self.pc.wrapping_add(offset as u64), false));
+                }
+
                 self.x[rd] = self.pc + incr as u64;
                 self.pc = self.pc.wrapping_add(offset as u64).wrapping_sub(incr);
             }
             Inst::Jalr { rd, rs1, offset } => {
-                self.profiler.pipeline_stall_x(rs1, self.pc);
+                profile!(self.pipeline_stall_x(rs1, self.pc));
+
+                if rd == RA {
+                    profile!(self.trace_call(self.x[rs1].wrapping_add(offset as u64), false));
+                } else if rd == Reg(0) && rs1 == RA && offset == 0 {
+                    profile!(self.trace_return(false));
+                }
 
                 self.x[rd] = self.pc + incr as u64;
                 self.pc = self.x[rs1].wrapping_add(offset as u64).wrapping_sub(incr);
             }
             Inst::Beq { rs1, rs2, offset } => {
-                self.profiler.pipeline_stall_xx(rs1, rs2, self.pc);
+                profile!(self.pipeline_stall_xx(rs1, rs2, self.pc));
 
                 if self.x[rs1] == self.x[rs2] {
-                    self.profiler.branch_taken(self.pc);
+                    profile!(self.branch_taken(self.pc));
 
                     self.pc = self.pc.wrapping_add(offset as u64).wrapping_sub(incr);
                 } else {
-                    self.profiler.branch_not_taken(self.pc);
+                    profile!(self.branch_not_taken(self.pc));
                 }
             }
             Inst::Bne { rs1, rs2, offset } => {
-                self.profiler.pipeline_stall_xx(rs1, rs2, self.pc);
+                profile!(self.pipeline_stall_xx(rs1, rs2, self.pc));
 
                 if self.x[rs1] != self.x[rs2] {
-                    self.profiler.branch_taken(self.pc);
+                    profile!(self.branch_taken(self.pc));
 
                     self.pc = self.pc.wrapping_add(offset as u64).wrapping_sub(incr);
                 } else {
-                    self.profiler.branch_not_taken(self.pc);
+                    profile!(self.branch_not_taken(self.pc));
                 }
             }
             Inst::Blt { rs1, rs2, offset } => {
-                self.profiler.pipeline_stall_xx(rs1, rs2, self.pc);
+                profile!(self.pipeline_stall_xx(rs1, rs2, self.pc));
 
                 if (self.x[rs1] as i64) < self.x[rs2] as i64 {
-                    self.profiler.branch_taken(self.pc);
+                    profile!(self.branch_taken(self.pc));
 
                     self.pc = self.pc.wrapping_add(offset as u64).wrapping_sub(incr);
                 } else {
-                    self.profiler.branch_not_taken(self.pc);
+                    profile!(self.branch_not_taken(self.pc));
                 }
             }
             Inst::Bltu { rs1, rs2, offset } => {
-                self.profiler.pipeline_stall_xx(rs1, rs2, self.pc);
+                profile!(self.pipeline_stall_xx(rs1, rs2, self.pc));
 
                 if self.x[rs1] < self.x[rs2] {
-                    self.profiler.branch_taken(self.pc);
+                    profile!(self.branch_taken(self.pc));
 
                     self.pc = self.pc.wrapping_add(offset as u64).wrapping_sub(incr);
                 } else {
-                    self.profiler.branch_not_taken(self.pc);
+                    profile!(self.branch_not_taken(self.pc));
                 }
             }
             Inst::Slt { rd, rs1, rs2 } => {
-                self.profiler.pipeline_stall_xx(rs1, rs2, self.pc);
+                profile!(self.pipeline_stall_xx(rs1, rs2, self.pc));
 
                 if (self.x[rs1] as i64) < (self.x[rs2] as i64) {
                     self.x[rd] = 1;
@@ -600,7 +1281,7 @@ impl Emulator {
                 }
             }
             Inst::Sltu { rd, rs1, rs2 } => {
-                self.profiler.pipeline_stall_xx(rs1, rs2, self.pc);
+                profile!(self.pipeline_stall_xx(rs1, rs2, self.pc));
 
                 if self.x[rs1] < self.x[rs2] {
                     self.x[rd] = 1;
@@ -609,7 +1290,7 @@ impl Emulator {
                 }
             }
             Inst::Slti { rd, rs1, imm } => {
-                self.profiler.pipeline_stall_x(rs1, self.pc);
+                profile!(self.pipeline_stall_x(rs1, self.pc));
 
                 if (self.x[rs1] as i64) < (imm as i64) {
                     self.x[rd] = 1;
@@ -618,7 +1299,7 @@ impl Emulator {
                 }
             }
             Inst::Sltiu { rd, rs1, imm } => {
-                self.profiler.pipeline_stall_x(rs1, self.pc);
+                profile!(self.pipeline_stall_x(rs1, self.pc));
 
                 if self.x[rs1] < imm as u64 {
                     self.x[rd] = 1;
@@ -627,89 +1308,107 @@ impl Emulator {
                 }
             }
             Inst::Bge { rs1, rs2, offset } => {
-                self.profiler.pipeline_stall_xx(rs1, rs2, self.pc);
+                profile!(self.pipeline_stall_xx(rs1, rs2, self.pc));
 
                 if (self.x[rs1] as i64) >= self.x[rs2] as i64 {
-                    self.profiler.branch_taken(self.pc);
+                    profile!(self.branch_taken(self.pc));
 
                     self.pc = self.pc.wrapping_add(offset as u64).wrapping_sub(incr);
                 } else {
-                    self.profiler.branch_not_taken(self.pc);
+                    profile!(self.branch_not_taken(self.pc));
                 }
             }
             Inst::Bgeu { rs1, rs2, offset } => {
-                self.profiler.pipeline_stall_xx(rs1, rs2, self.pc);
+                profile!(self.pipeline_stall_xx(rs1, rs2, self.pc));
 
                 if self.x[rs1] >= self.x[rs2] {
-                    self.profiler.branch_taken(self.pc);
+                    profile!(self.branch_taken(self.pc));
 
                     self.pc = self.pc.wrapping_add(offset as u64).wrapping_sub(incr);
                 } else {
-                    self.profiler.branch_not_taken(self.pc);
+                    profile!(self.branch_not_taken(self.pc));
                 }
             }
-            // TODO: Divide by zero semantics are NOT correct
+            // dividing by zero gives all ones and doesn't trap, and the quotient of the most negative
+            // number by -1 overflows back to itself
             Inst::Div { rd, rs1, rs2 } => {
-                self.profiler.pipeline_stall_xx(rs1, rs2, self.pc);
-                self.profiler.add_delay_x(
+                profile!(self.pipeline_stall_xx(rs1, rs2, self.pc));
+                profile!(self.add_delay_x(
                     rd,
-                    div_cycle_count!((self.x[rs1] as i64).abs(), (self.x[rs2] as i64).abs()),
-                );
+                    div_cycle_count!(
+                        (self.x[rs1] as i64).unsigned_abs(),
+                        (self.x[rs2] as i64).unsigned_abs()
+                    ),
+                ));
 
-                self.x[rd] = ((self.x[rs1] as i64) / (self.x[rs2] as i64)) as u64;
+                self.x[rd] = match self.x[rs2] {
+                    0 => u64::MAX,
+                    divisor => (self.x[rs1] as i64).wrapping_div(divisor as i64) as u64,
+                };
             }
             Inst::Divw { rd, rs1, rs2 } => {
-                self.profiler.pipeline_stall_xx(rs1, rs2, self.pc);
-                self.profiler.add_delay_x(
+                profile!(self.pipeline_stall_xx(rs1, rs2, self.pc));
+                profile!(self.add_delay_x(
                     rd,
-                    div_cycle_count!((self.x[rs1] as i32).abs(), (self.x[rs2] as i32).abs()),
-                );
+                    div_cycle_count!(
+                        (self.x[rs1] as i32).unsigned_abs(),
+                        (self.x[rs2] as i32).unsigned_abs()
+                    ),
+                ));
 
-                self.x[rd] = ((self.x[rs1] as i32) / (self.x[rs2] as i32)) as u64;
+                self.x[rd] = match self.x[rs2] as i32 {
+                    0 => u64::MAX,
+                    divisor => (self.x[rs1] as i32).wrapping_div(divisor) as u64,
+                };
             }
             Inst::Divu { rd, rs1, rs2 } => {
-                self.profiler.pipeline_stall_xx(rs1, rs2, self.pc);
-                self.profiler
-                    .add_delay_x(rd, div_cycle_count!(self.x[rs1], self.x[rs2]));
+                profile!(self.pipeline_stall_xx(rs1, rs2, self.pc));
+                profile!(self.add_delay_x(rd, div_cycle_count!(self.x[rs1], self.x[rs2])));
 
-                self.x[rd] = self.x[rs1] / self.x[rs2];
+                self.x[rd] = self.x[rs1].checked_div(self.x[rs2]).unwrap_or(u64::MAX);
             }
             Inst::Divuw { rd, rs1, rs2 } => {
-                self.profiler.pipeline_stall_xx(rs1, rs2, self.pc);
-                self.profiler
-                    .add_delay_x(rd, div_cycle_count!(self.x[rs1] as u32, self.x[rs2] as u32));
+                profile!(self.pipeline_stall_xx(rs1, rs2, self.pc));
+                profile!(
+                    self.add_delay_x(rd, div_cycle_count!(self.x[rs1] as u32, self.x[rs2] as u32))
+                );
 
-                self.x[rd] = ((self.x[rs1] as u32) / (self.x[rs2] as u32)) as i32 as u64;
+                self.x[rd] = match (self.x[rs1] as u32).checked_div(self.x[rs2] as u32) {
+                    Some(quotient) => quotient as i32 as u64,
+                    None => u64::MAX,
+                };
             }
             Inst::Mul { rd, rs1, rs2 } => {
-                self.profiler.pipeline_stall_xx(rs1, rs2, self.pc);
-                self.profiler.add_delay_x(rd, 3);
+                profile!(self.pipeline_stall_xx(rs1, rs2, self.pc));
+                profile!(self.add_delay_x(rd, 3));
 
                 self.x[rd] = (self.x[rs1] as i64).wrapping_mul(self.x[rs2] as i64) as u64;
             }
             Inst::Mulhu { rd, rs1, rs2 } => {
-                self.profiler.pipeline_stall_xx(rs1, rs2, self.pc);
-                self.profiler.add_delay_x(rd, 3);
+                profile!(self.pipeline_stall_xx(rs1, rs2, self.pc));
+                profile!(self.add_delay_x(rd, 3));
 
                 self.x[rd] = ((self.x[rs1] as u128).wrapping_mul(self.x[rs2] as u128) >> 64) as u64;
             }
             Inst::Remw { rd, rs1, rs2 } => {
-                self.profiler.pipeline_stall_xx(rs1, rs2, self.pc);
-                self.profiler.add_delay_x(
+                profile!(self.pipeline_stall_xx(rs1, rs2, self.pc));
+                profile!(self.add_delay_x(
                     rd,
-                    div_cycle_count!((self.x[rs1] as i32).abs(), (self.x[rs2] as i32).abs()),
-                );
+                    div_cycle_count!(
+                        (self.x[rs1] as i32).unsigned_abs(),
+                        (self.x[rs2] as i32).unsigned_abs()
+                    ),
+                ));
 
-                if self.x[rs2] == 0 {
+                if self.x[rs2] as u32 == 0 {
                     self.x[rd] = (self.x[rs1] as i32) as u64;
                 } else {
-                    self.x[rd] = ((self.x[rs1] as i32) % (self.x[rs2] as i32)) as u64;
+                    self.x[rd] = (self.x[rs1] as i32).wrapping_rem(self.x[rs2] as i32) as u64;
                 }
             }
             Inst::Remu { rd, rs1, rs2 } => {
-                self.profiler.pipeline_stall_xx(rs1, rs2, self.pc);
-                self.profiler
-                    .add_delay_x(rd, div_cycle_count!(self.x[rs1], self.x[rs2]));
+                profile!(self.pipeline_stall_xx(rs1, rs2, self.pc));
+                profile!(self.add_delay_x(rd, div_cycle_count!(self.x[rs1], self.x[rs2])));
 
                 if self.x[rs2] == 0 {
                     self.x[rd] = self.x[rs1];
@@ -718,64 +1417,95 @@ impl Emulator {
                 }
             }
             Inst::Remuw { rd, rs1, rs2 } => {
-                self.profiler.pipeline_stall_xx(rs1, rs2, self.pc);
-                self.profiler
-                    .add_delay_x(rd, div_cycle_count!(self.x[rs1] as u32, self.x[rs2] as u32));
+                profile!(self.pipeline_stall_xx(rs1, rs2, self.pc));
+                profile!(
+                    self.add_delay_x(rd, div_cycle_count!(self.x[rs1] as u32, self.x[rs2] as u32))
+                );
 
-                if self.x[rs2] == 0 {
-                    self.x[rd] = self.x[rs1] as u32 as u64;
+                if self.x[rs2] as u32 == 0 {
+                    self.x[rd] = self.x[rs1] as i32 as u64;
                 } else {
                     self.x[rd] = ((self.x[rs1] as u32) % (self.x[rs2] as u32)) as i32 as u64;
                 }
             }
+            // harts are interleaved at instruction granularity, so every AMO is atomic as long
+            // as both operands are read before rd is written
             Inst::Amoswapw { rd, rs1, rs2 } => {
-                self.x[rd] = self.memory.load::<i32>(self.x[rs1])? as u64;
-                self.memory.store(self.x[rs1], self.x[rs2] as u32)?;
+                let (addr, src) = (self.x[rs1], self.x[rs2]);
+                self.x[rd] = self.memory.load::<i32>(addr)? as u64;
+                self.memory.store(addr, src as u32)?;
             }
             Inst::Amoswapd { rd, rs1, rs2 } => {
-                self.x[rd] = self.memory.load(self.x[rs1])?;
-                self.memory.store(self.x[rs1], self.x[rs2])?;
+                let (addr, src) = (self.x[rs1], self.x[rs2]);
+                self.x[rd] = self.memory.load(addr)?;
+                self.memory.store(addr, src)?;
             }
             Inst::Amoaddw { rd, rs1, rs2 } => {
-                self.x[rd] = self.memory.load::<i32>(self.x[rs1])? as u64;
-                self.memory.store(
-                    self.x[rs1],
-                    (self.x[rs2] as u32).wrapping_add(self.x[rd] as u32),
-                )?;
+                let (addr, src) = (self.x[rs1], self.x[rs2]);
+                let value = self.memory.load::<u32>(addr)?;
+                self.memory.store(addr, (src as u32).wrapping_add(value))?;
+                self.x[rd] = value as i32 as u64;
             }
             Inst::Amoaddd { rd, rs1, rs2 } => {
-                self.x[rd] = self.memory.load(self.x[rs1])?;
-                self.memory
-                    .store(self.x[rs1], self.x[rs2].wrapping_add(self.x[rd]))?;
+                let (addr, src) = (self.x[rs1], self.x[rs2]);
+                let value = self.memory.load::<u64>(addr)?;
+                self.memory.store(addr, src.wrapping_add(value))?;
+                self.x[rd] = value;
             }
             Inst::Amoorw { rd, rs1, rs2 } => {
-                self.x[rd] = self.memory.load::<i32>(self.x[rs1])? as u64;
-                self.memory
-                    .store(self.x[rs1], (self.x[rs2] as u32) | (self.x[rd] as u32))?;
+                let (addr, src) = (self.x[rs1], self.x[rs2]);
+                let value = self.memory.load::<u32>(addr)?;
+                self.memory.store(addr, (src as u32) | value)?;
+                self.x[rd] = value as i32 as u64;
             }
             Inst::Amomaxuw { rd, rs1, rs2 } => {
-                self.x[rd] = self.memory.load::<i32>(self.x[rs1])? as u64;
-                self.memory
-                    .store(self.x[rs1], (self.x[rs2] as u32).max(self.x[rd] as u32))?;
+                let (addr, src) = (self.x[rs1], self.x[rs2]);
+                let value = self.memory.load::<u32>(addr)?;
+                self.memory.store(addr, (src as u32).max(value))?;
+                self.x[rd] = value as i32 as u64;
             }
             Inst::Amomaxud { rd, rs1, rs2 } => {
-                self.x[rd] = self.memory.load(self.x[rs1])?;
-                self.memory
-                    .store(self.x[rs1], self.x[rs2].max(self.x[rd]))?;
-            }
+                let (addr, src) = (self.x[rs1], self.x[rs2]);
+                let value = self.memory.load::<u64>(addr)?;
+                self.memory.store(addr, src.max(value))?;
+                self.x[rd] = value;
+            }
+            // reservations remember the value lr observed, and sc only succeeds if memory still
+            // holds it. this is the same approach qemu takes, and means another hart writing to
+            // the address between the lr and sc makes the sc fail.
             Inst::Lrw { rd, rs1 } => {
-                self.x[rd] = self.memory.load::<i32>(self.x[rs1])? as u64;
+                let addr = self.x[rs1];
+                let value = self.memory.load::<i32>(addr)? as u64;
+                self.reservation = Some((addr, value));
+                self.x[rd] = value;
             }
             Inst::Lrd { rd, rs1 } => {
-                self.x[rd] = self.memory.load(self.x[rs1])?;
+                let addr = self.x[rs1];
+                let value = self.memory.load(addr)?;
+                self.reservation = Some((addr, value));
+                self.x[rd] = value;
             }
             Inst::Scw { rd, rs1, rs2 } => {
-                self.x[rd] = 0;
-                self.memory.store(self.x[rs1], self.x[rs2] as u32)?;
+                let (addr, src) = (self.x[rs1], self.x[rs2]);
+                let current = self.memory.load::<i32>(addr)? as u64;
+
+                if self.reservation.take() == Some((addr, current)) {
+                    self.memory.store(addr, src as u32)?;
+                    self.x[rd] = 0;
+                } else {
+                    self.x[rd] = 1;
+                }
             }
             Inst::Scd { rd, rs1, rs2 } => {
-                self.x[rd] = 0;
-                self.memory.store(self.x[rs1], self.x[rs2])?;
+                let (addr, src) = (self.x[rs1], self.x[rs2]);
+                let current = self.memory.load::<u64>(addr)?;
+
+                if self.reservation.take() == Some((addr, current)) {
+                    self.memory.store(addr, src)?;
+                    self.x[rd] = 0;
+                } else {
+                    self.x[rd] = 1;
+                }
             }
             Inst::Fcvtdlu { rd, rs1, rm: _rm } => {
                 // ignore rounding mode for now, super incorrect
@@ -788,7 +1518,7 @@ impl Emulator {
                 self.x[rd] = self.f[rs1] as u64;
             }
             Inst::Fled { rd, rs1, rs2 } => {
-                if self.f[rs1] < self.f[rs2] {
+                if self.f[rs1] <= self.f[rs2] {
                     self.x[rd] = 1;
                 } else {
                     self.x[rd] = 0;
@@ -797,12 +1527,53 @@ impl Emulator {
             Inst::Fdivd { rd, rs1, rs2 } => {
                 self.f[rd] = self.f[rs1] / self.f[rs2];
             }
+            Inst::Csrrw { rd, rs1, csr } => {
+                let src = self.x[rs1];
+                if rd.0 != 0 {
+                    self.x[rd] = self.read_csr(csr);
+                }
+                self.write_csr(csr, src);
+            }
+            Inst::Csrrs { rd, rs1, csr } => {
+                let (old, src) = (self.read_csr(csr), self.x[rs1]);
+                if rs1.0 != 0 {
+                    self.write_csr(csr, old | src);
+                }
+                self.x[rd] = old;
+            }
+            Inst::Csrrc { rd, rs1, csr } => {
+                let (old, src) = (self.read_csr(csr), self.x[rs1]);
+                if rs1.0 != 0 {
+                    self.write_csr(csr, old & !src);
+                }
+                self.x[rd] = old;
+            }
+            Inst::Csrrwi { rd, uimm, csr } => {
+                if rd.0 != 0 {
+                    self.x[rd] = self.read_csr(csr);
+                }
+                self.write_csr(csr, uimm as u64);
+            }
+            Inst::Csrrsi { rd, uimm, csr } => {
+                let old = self.read_csr(csr);
+                if uimm != 0 {
+                    self.write_csr(csr, old | uimm as u64);
+                }
+                self.x[rd] = old;
+            }
+            Inst::Csrrci { rd, uimm, csr } => {
+                let old = self.read_csr(csr);
+                if uimm != 0 {
+                    self.write_csr(csr, old & !(uimm as u64));
+                }
+                self.x[rd] = old;
+            }
         }
 
         self.pc = self.pc.wrapping_add(incr);
 
         self.inst_counter += 1;
-        self.profiler.tick(self.pc);
+        profile!(self.tick(self.pc));
 
         // make sure x0 is zero
         self.x[0] = 0;
@@ -811,9 +1582,22 @@ impl Emulator {
     }
 }
 
+// the argv[0] and AT_EXECFN of a program started without arguments
+const DEFAULT_PROGRAM_NAME: &str = "/prog";
+
+// writes `string` null terminated below `sp`, keeping it 8 byte aligned, and returns its address
+fn push_string(memory: &mut Memory, sp: &mut u64, string: &str) -> Result<u64, RVError> {
+    let len = string.len() as u64 + 1;
+    *sp -= len.next_multiple_of(8);
+    memory.write_n(string.as_bytes(), *sp, len - 1)?;
+    memory.store::<u8>(*sp + len - 1, 0)?;
+    Ok(*sp)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::memory::{MemoryLayout, FLAT_STACK_SIZE, PAGE_SIZE};
 
     #[test]
     fn lui() -> Result<(), RVError> {
@@ -888,6 +1672,489 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn region_of_interest() -> Result<(), RVError> {
+        let mut data = [0u8; 12];
+        data[0..4].copy_from_slice(&0x00100513u32.to_le_bytes()); // li a0, 1
+        data[4..8].copy_from_slice(&0x00200593u32.to_le_bytes()); // work: li a1, 2
+        data[8..12].copy_from_slice(&0x00300613u32.to_le_bytes()); // li a2, 3
+
+        let mut emulator = Emulator::new(Memory::from_raw(&data));
+        emulator
+            .memory
+            .disassembler
+            .import_symbols("4 work", 0)
+            .unwrap();
+        emulator.set_region_of_interest("work")?;
+        emulator.profiler.running = true;
+
+        emulator.fetch_and_execute()?;
+        assert!(emulator.region_of_interest_profile().is_none());
+        emulator.fetch_and_execute()?;
+        emulator.fetch_and_execute()?;
+
+        let roi = emulator.region_of_interest_profile().unwrap();
+        assert_eq!(roi.inst_count, 2);
+        assert_eq!(roi.cycle_count, 2);
+        assert_eq!(emulator.profile_snapshot().inst_count, 3);
+        assert!(matches!(
+            emulator.set_region_of_interest("missing"),
+            Err(RVError::InvalidLabel)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn interrupts() -> Result<(), RVError> {
+        let mut data = [0u8; 12];
+        data[0..4].copy_from_slice(&0x0000006fu32.to_le_bytes()); // j .
+        data[4..8].copy_from_slice(&0x05d00893u32.to_le_bytes()); // li a7, 93
+        data[8..12].copy_from_slice(&0x00000073u32.to_le_bytes()); // ecall
+
+        let mut emulator = Emulator::new(Memory::from_raw(&data));
+
+        // break out of the infinite loop after 10 instructions
+        emulator.schedule_interrupt(10, |emu| {
+            emu.set_reg(A0, 7);
+            emu.pc = 4;
+        });
+
+        assert_eq!(emulator.pending_interrupts(), 1);
+        assert_eq!(emulator.run(false)?, 7);
+        assert_eq!(emulator.inst_counter, 12);
+        assert_eq!(emulator.pending_interrupts(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn self_modifying_code() -> Result<(), RVError> {
+        let mut data = [0u8; 8];
+        data[0..4].copy_from_slice(&0x00150513u32.to_le_bytes()); // addi a0, a0, 1
+        data[4..8].copy_from_slice(&0xffdff06fu32.to_le_bytes()); // j 0
+
+        let mut emulator = Emulator::new(Memory::from_raw(&data));
+        emulator.fetch_and_execute()?;
+        emulator.fetch_and_execute()?;
+        assert_eq!(emulator.x[A0], 1);
+
+        // addi a0, a0, 2
+        emulator.memory.store(0, 0x00250513u32)?;
+        emulator.fetch_and_execute()?;
+        assert_eq!(emulator.x[A0], 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn interp_blocks() -> Result<(), RVError> {
+        let mut data = [0u8; 24];
+        data[0..4].copy_from_slice(&0x00000513u32.to_le_bytes()); // li a0, 0
+        data[4..8].copy_from_slice(&0x00a00593u32.to_le_bytes()); // li a1, 10
+        data[8..12].copy_from_slice(&0x00150513u32.to_le_bytes()); // addi a0, a0, 1
+        data[12..16].copy_from_slice(&0xfeb54ee3u32.to_le_bytes()); // blt a0, a1, -4
+        data[16..20].copy_from_slice(&0x05d00893u32.to_le_bytes()); // li a7, 93
+        data[20..24].copy_from_slice(&0x00000073u32.to_le_bytes()); // ecall
+
+        let mut stepped = Emulator::new(Memory::from_raw(&data));
+        while stepped.fetch_and_execute()?.is_none() {}
+
+        let mut emulator = Emulator::new(Memory::from_raw(&data));
+        assert_eq!(emulator.run(false)?, 10);
+        assert_eq!(emulator.inst_counter, stepped.inst_counter);
+
+        Ok(())
+    }
+
+    #[test]
+    fn flat_memory() -> Result<(), RVError> {
+        let memory = Memory::from_raw_with_layout(
+            &[0x12, 0x23, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde],
+            MemoryLayout::Flat {
+                size: 2 * PAGE_SIZE,
+            },
+        );
+        let mut emulator = Emulator::new(memory);
+
+        // ld a0, 0(x0)
+        emulator.execute_raw(0x00003503)?;
+        assert_eq!(emulator.x[A0], 0xdebc9a7856342312);
+
+        // C.SDSP a0, 0
+        // C.LDSP a1, 0
+        emulator.execute_raw(0x0000e02a)?;
+        emulator.execute_raw(0x00006582)?;
+        assert_eq!(emulator.x[A0], emulator.x[A1]);
+
+        // the last 8 bytes of the low region are accessible, the ones straddling its end are not
+        let end = 2 * PAGE_SIZE;
+        assert!(emulator.memory.store(end - 8, 0u64).is_ok());
+        assert!(emulator.memory.store(end - 4, 0u64).is_err());
+        assert!(emulator
+            .memory
+            .load::<u8>(STACK_START - FLAT_STACK_SIZE)
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn cow_memory() -> Result<(), RVError> {
+        let memory = Memory::from_raw_with_layout(&[0; 0x2000], MemoryLayout::Cow);
+        let mut emulator = Emulator::new(memory);
+        emulator.memory.store(0x100, 0x1234u32)?;
+
+        let snapshot = emulator.clone();
+
+        // writes after a snapshot don't affect it, even when straddling a page boundary
+        emulator.memory.store(0x100, 0x5678u32)?;
+        emulator.memory.store(0xffc, 0xdebc9a7856342312u64)?;
+        assert_eq!(emulator.memory.load::<u64>(0xffc)?, 0xdebc9a7856342312);
+        assert_eq!(emulator.memory.load::<u32>(0x1000)?, 0xdebc9a78);
+
+        assert_eq!(snapshot.memory.load::<u32>(0x100)?, 0x1234);
+        assert_eq!(snapshot.memory.load::<u64>(0xffc)?, 0);
+
+        // C.SDSP a0, 0
+        // C.LDSP a1, 0
+        emulator.x[A0] = 42;
+        emulator.execute_raw(0x0000e02a)?;
+        emulator.execute_raw(0x00006582)?;
+        assert_eq!(emulator.x[A1], 42);
+
+        Ok(())
+    }
+
+    #[test]
+    fn fork() -> Result<(), RVError> {
+        let mut data = [0u8; 0x200];
+        data[0..4].copy_from_slice(&0x00000513u32.to_le_bytes()); // li a0, 0
+        data[4..8].copy_from_slice(&0x10000593u32.to_le_bytes()); // li a1, 0x100
+        data[8..12].copy_from_slice(&0x00100613u32.to_le_bytes()); // li a2, 1
+        data[12..16].copy_from_slice(&0x03f00893u32.to_le_bytes()); // li a7, 63
+        data[16..20].copy_from_slice(&0x00000073u32.to_le_bytes()); // ecall
+        data[20..24].copy_from_slice(&0x10004503u32.to_le_bytes()); // lbu a0, 0x100(zero)
+        data[24..28].copy_from_slice(&0x05d00893u32.to_le_bytes()); // li a7, 93
+        data[28..32].copy_from_slice(&0x00000073u32.to_le_bytes()); // ecall
+
+        let memory = Memory::from_raw_with_layout(&data, MemoryLayout::Cow);
+        let mut emulator = EmulatorBuilder::new(memory).stdin(*b"a").build()?;
+        for _ in 0..4 {
+            emulator.fetch_and_execute()?;
+        }
+
+        // what if the read was empty
+        let mut fork = emulator.fork();
+        fork.set_reg(A2, 0);
+
+        assert_eq!(emulator.run(false)?, b'a' as u64);
+        assert_eq!(fork.run(false)?, 0);
+        assert_eq!(emulator.fds().file(0).unwrap().offset, 1);
+        assert_eq!(fork.fds().file(0).unwrap().offset, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn auxv() -> Result<(), RVError> {
+        let config = AuxvConfig {
+            hwcap: AuxvConfig::hwcap("IMA"),
+            uid: 1000,
+            platform: Some("riscv64".into()),
+            random: [0xaa; 16],
+            ..AuxvConfig::default()
+        };
+        let mut emulator = EmulatorBuilder::new(Memory::from_raw(&[0; 0x100]))
+            .auxv(config)
+            .args(["prog", "-v"])
+            .env(["HOME=/root"])
+            .build()?;
+        assert_eq!(emulator.x[SP] % 16, 0);
+
+        // argc, then argv and envp each ending with a null pointer
+        let mut strings = Vec::new();
+        let mut addr = emulator.x[SP] + 8;
+        for _ in 0..2 {
+            loop {
+                let string: u64 = emulator.memory.load(addr)?;
+                addr += 8;
+                if string == 0 {
+                    break;
+                }
+                strings.push(emulator.memory.read_string_n(string, 16)?);
+            }
+        }
+        assert_eq!(emulator.memory.load::<u64>(emulator.x[SP])?, 2);
+        assert_eq!(strings, ["prog", "-v", "HOME=/root"]);
+
+        // then the pairs, ending with AT_NULL
+        let mut auxv = BTreeMap::new();
+        loop {
+            let key: u64 = emulator.memory.load(addr)?;
+            if key == Auxv::Null as u64 {
+                break;
+            }
+            auxv.insert(key, emulator.memory.load::<u64>(addr + 8)?);
+            addr += 16;
+        }
+
+        assert_eq!(auxv[&(Auxv::Hwcap as u64)], 1 << 12 | 1 << 8 | 1);
+        assert_eq!(auxv[&(Auxv::Uid as u64)], 1000);
+        assert_eq!(auxv[&(Auxv::Gid as u64)], 0);
+        assert_eq!(auxv[&(Auxv::Clktlk as u64)], 100);
+
+        let platform = auxv[&(Auxv::Platform as u64)];
+        assert_eq!(emulator.memory.read_string_n(platform, 16)?, "riscv64");
+        let random = auxv[&(Auxv::Random as u64)];
+        assert_eq!(
+            emulator.memory.load::<u64>(random + 8)?,
+            0xaaaa_aaaa_aaaa_aaaa
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn loads_into_x0() -> Result<(), RVError> {
+        let mut data = [0u8; 32];
+        data[0..4].copy_from_slice(&0x00000317u32.to_le_bytes()); // auipc t1, 0
+        data[4..8].copy_from_slice(&0x01833003u32.to_le_bytes()); // ld zero, 24(t1)
+        data[8..12].copy_from_slice(&0x00000513u32.to_le_bytes()); // mv a0, zero
+        data[12..16].copy_from_slice(&0x01832003u32.to_le_bytes()); // lw zero, 24(t1)
+        data[16..20].copy_from_slice(&0x00000593u32.to_le_bytes()); // mv a1, zero
+        data[20..24].copy_from_slice(&0x00008067u32.to_le_bytes()); // ret
+        data[24..32].copy_from_slice(&0x1122334455667788u64.to_le_bytes());
+
+        let mut emulator = Emulator::new(Memory::from_raw(&data));
+        emulator.x[RA] = 0x1000;
+        emulator.x[A0] = 1;
+        emulator.x[A1] = 1;
+        while emulator.pc != 0x1000 {
+            emulator.execute_interp_block()?;
+        }
+
+        assert_eq!(emulator.x[0], 0);
+        assert_eq!(emulator.x[A0], 0);
+        assert_eq!(emulator.x[A1], 0);
+        assert_eq!(emulator.inst_counter, 6);
+
+        Ok(())
+    }
+
+    // a function whose instructions are merged into ops, see `ir::optimize`
+    fn fused_ops_program() -> Memory {
+        let mut data = [0u8; 52];
+        data[0..4].copy_from_slice(&0x12345537u32.to_le_bytes()); // lui a0, 0x12345
+        data[4..8].copy_from_slice(&0x67850513u32.to_le_bytes()); // addi a0, a0, 0x678
+        data[8..12].copy_from_slice(&0x00000593u32.to_le_bytes()); // li a1, 0
+        data[12..16].copy_from_slice(&0x00158593u32.to_le_bytes()); // addi a1, a1, 1
+        data[16..20].copy_from_slice(&0x00a5a293u32.to_le_bytes()); // slti t0, a1, 10
+        data[20..24].copy_from_slice(&0xfe029ce3u32.to_le_bytes()); // bnez t0, -8
+        data[24..28].copy_from_slice(&0x00000317u32.to_le_bytes()); // auipc t1, 0
+        data[28..32].copy_from_slice(&0x01433603u32.to_le_bytes()); // ld a2, 20(t1)
+        data[32..36].copy_from_slice(&0x02851693u32.to_le_bytes()); // slli a3, a0, 40
+        data[36..40].copy_from_slice(&0x0286d693u32.to_le_bytes()); // srli a3, a3, 40
+        data[40..44].copy_from_slice(&0x00008067u32.to_le_bytes()); // ret
+        data[44..52].copy_from_slice(&0x1122334455667788u64.to_le_bytes());
+
+        Memory::from_raw(&data)
+    }
+
+    #[test]
+    fn fused_ops() -> Result<(), RVError> {
+        let mut stepped = Emulator::new(fused_ops_program());
+        stepped.x[RA] = 0x1000;
+        while stepped.pc != 0x1000 {
+            stepped.fetch_and_execute()?;
+        }
+
+        let mut emulator = Emulator::new(fused_ops_program());
+        emulator.x[RA] = 0x1000;
+        while emulator.pc != 0x1000 {
+            emulator.execute_interp_block()?;
+        }
+
+        assert_eq!(emulator.x, stepped.x);
+        assert_eq!(emulator.x[A0], 0x12345678);
+        assert_eq!(emulator.x[A1], 10);
+        assert_eq!(emulator.x[A2], 0x1122334455667788);
+        assert_eq!(emulator.x[A3], 0x345678);
+        assert_eq!(emulator.inst_counter, stepped.inst_counter);
+
+        #[cfg(jit)]
+        {
+            let mut jitted = Emulator::new(fused_ops_program());
+            jitted.x[RA] = 0x1000;
+
+            let function = jit::CompileJob::new(&jitted, false)
+                .unwrap()
+                .compile()
+                .unwrap();
+            function.run(&mut jitted);
+
+            assert_eq!(jitted.pc, 0x1000);
+            assert_eq!(jitted.x, stepped.x);
+            assert_eq!(jitted.inst_counter, stepped.inst_counter);
+        }
+
+        Ok(())
+    }
+
+    #[cfg(jit)]
+    #[test]
+    fn background_jit() -> Result<(), RVError> {
+        let mut data = [0u8; 36];
+        data[0..4].copy_from_slice(&0x00000513u32.to_le_bytes()); // li a0, 0
+        data[4..8].copy_from_slice(&0x00a00593u32.to_le_bytes()); // li a1, 10
+        data[8..12].copy_from_slice(&0x014000efu32.to_le_bytes()); // jal ra, 20
+        data[12..16].copy_from_slice(&0xfeb54ee3u32.to_le_bytes()); // blt a0, a1, -4
+        data[16..20].copy_from_slice(&0x05d00893u32.to_le_bytes()); // li a7, 93
+        data[20..24].copy_from_slice(&0x00000073u32.to_le_bytes()); // ecall
+        data[28..32].copy_from_slice(&0x00150513u32.to_le_bytes()); // addi a0, a0, 1
+        data[32..36].copy_from_slice(&0x00008067u32.to_le_bytes()); // ret
+
+        let mut interpreted = Emulator::new(Memory::from_raw(&data));
+        assert_eq!(interpreted.run(false)?, 10);
+
+        // clones share compiled functions, so the second run uses the ones the first compiled
+        let mut emulator = Emulator::new(Memory::from_raw(&data));
+        let mut compiled = emulator.clone();
+
+        assert_eq!(emulator.run(true)?, 10);
+        assert_eq!(emulator.inst_counter, interpreted.inst_counter);
+
+        emulator.jit_functions.wait();
+        let stats = emulator.jit_stats();
+        assert_eq!(stats.functions_compiled, 2);
+        assert_eq!(stats.functions_failed, 0);
+        assert!(stats.code_bytes > 0);
+        assert!(stats.interpreted_calls > 0);
+
+        assert!(matches!(
+            emulator.jit_functions.get(28),
+            Some(JitState::Ready(_))
+        ));
+
+        assert_eq!(compiled.run(true)?, 10);
+        assert_eq!(compiled.inst_counter, interpreted.inst_counter);
+
+        Ok(())
+    }
+
+    #[cfg(jit)]
+    #[test]
+    fn unsupported_instructions() -> Result<(), RVError> {
+        let mut data = [0u8; 48];
+        data[0..4].copy_from_slice(&0x00a00513u32.to_le_bytes()); // li a0, 10
+        data[4..8].copy_from_slice(&0x014000efu32.to_le_bytes()); // jal ra, 20
+        data[8..12].copy_from_slice(&0x05d00893u32.to_le_bytes()); // li a7, 93
+        data[12..16].copy_from_slice(&0x00000073u32.to_le_bytes()); // ecall
+        data[24..28].copy_from_slice(&0x40b50533u32.to_le_bytes()); // sub a0, a0, a1
+        data[28..32].copy_from_slice(&0x00008067u32.to_le_bytes()); // ret
+
+        // the function isn't compiled, and is interpreted instead
+        let mut emulator = Emulator::new(Memory::from_raw(&data));
+        emulator.x[A1] = 3;
+        assert_eq!(emulator.run(true)?, 7);
+        emulator.jit_functions.wait();
+        assert!(matches!(
+            emulator.jit_functions.get(24),
+            Some(JitState::Failed)
+        ));
+
+        Ok(())
+    }
+
+    #[cfg(jit)]
+    #[test]
+    fn jit_stats() -> Result<(), RVError> {
+        let mut data = [0u8; 40];
+        data[0..4].copy_from_slice(&0x00a00513u32.to_le_bytes()); // li a0, 10
+        data[4..8].copy_from_slice(&0x014000efu32.to_le_bytes()); // jal ra, 20
+        data[8..12].copy_from_slice(&0x018000efu32.to_le_bytes()); // jal ra, 24
+        data[12..16].copy_from_slice(&0x05d00893u32.to_le_bytes()); // li a7, 93
+        data[16..20].copy_from_slice(&0x00000073u32.to_le_bytes()); // ecall
+        data[24..28].copy_from_slice(&0x00150513u32.to_le_bytes()); // addi a0, a0, 1
+        data[28..32].copy_from_slice(&0x00008067u32.to_le_bytes()); // ret
+        data[32..36].copy_from_slice(&0x40b50533u32.to_le_bytes()); // sub a0, a0, a1
+        data[36..40].copy_from_slice(&0x00008067u32.to_le_bytes()); // ret
+
+        let mut emulator = Emulator::new(Memory::from_raw(&data));
+        emulator.x[A1] = 3;
+        let mut compiled = emulator.clone();
+        assert_eq!(emulator.jit_stats(), JitStats::default());
+
+        // every function is interpreted the first time, while it's compiled
+        assert_eq!(emulator.run(true)?, 8);
+        emulator.jit_functions.wait();
+        let stats = emulator.jit_stats();
+        assert_eq!(stats.functions_compiled, 2);
+        assert_eq!(stats.functions_failed, 1);
+        assert_eq!(stats.interpreted_calls, 3);
+        assert!(stats.code_bytes > 0);
+
+        // and only the one with sub is interpreted after that
+        assert_eq!(compiled.run(true)?, 8);
+        let stats = compiled.jit_stats();
+        assert_eq!(stats.functions_compiled, 2);
+        assert_eq!(stats.functions_failed, 1);
+        assert_eq!(stats.interpreted_calls, 4);
+
+        Ok(())
+    }
+
+    #[cfg(jit)]
+    #[test]
+    fn flush_icache() -> Result<(), RVError> {
+        let mut data = [0u8; 48];
+        data[0..4].copy_from_slice(&0x00000513u32.to_le_bytes()); // li a0, 0
+        data[4..8].copy_from_slice(&0x00a00593u32.to_le_bytes()); // li a1, 10
+        data[8..12].copy_from_slice(&0x014000efu32.to_le_bytes()); // jal ra, 20
+        data[12..16].copy_from_slice(&0xfeb54ee3u32.to_le_bytes()); // blt a0, a1, -4
+        data[16..20].copy_from_slice(&0x05d00893u32.to_le_bytes()); // li a7, 93
+        data[20..24].copy_from_slice(&0x00000073u32.to_le_bytes()); // ecall
+        data[28..32].copy_from_slice(&0x00150513u32.to_le_bytes()); // addi a0, a0, 1
+        data[32..36].copy_from_slice(&0x00008067u32.to_le_bytes()); // ret
+        assert_eq!(Inst::decode(0x0000100f), (Inst::FenceI, 4));
+
+        let mut emulator = Emulator::new(Memory::from_raw(&data));
+        let mut patched = emulator.clone();
+        assert_eq!(emulator.run(true)?, 10);
+        emulator.jit_functions.wait();
+
+        // the function is patched to add 3 and then fence.i, so it can't be compiled anymore
+        patched.memory.store(28, 0x00350513u32)?; // addi a0, a0, 3
+        patched.memory.store(32, 0x0000100fu32)?; // fence.i
+        patched.memory.store(36, 0x00008067u32)?; // ret
+        patched.flush_icache();
+        assert!(emulator.jit_functions.get(28).is_none());
+
+        assert_eq!(patched.run(true)?, 12);
+        assert!(patched.jit_stats().functions_failed > 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn self_modifying_block() -> Result<(), RVError> {
+        let mut data = [0u8; 20];
+        data[0..4].copy_from_slice(&0x00b02423u32.to_le_bytes()); // sw a1, 8(zero)
+        data[4..8].copy_from_slice(&0x00000013u32.to_le_bytes()); // nop
+        data[8..12].copy_from_slice(&0x00150513u32.to_le_bytes()); // addi a0, a0, 1
+        data[12..16].copy_from_slice(&0x05d00893u32.to_le_bytes()); // li a7, 93
+        data[16..20].copy_from_slice(&0x00000073u32.to_le_bytes()); // ecall
+
+        let mut emulator = Emulator::new(Memory::from_raw(&data));
+        // addi a0, a0, 7
+        emulator.x[A1] = 0x00750513;
+
+        // the store has to replace the addi even though it was already decoded as part of the block
+        assert_eq!(emulator.run(false)?, 7);
+
+        Ok(())
+    }
+
     #[test]
     fn sp_relative() -> Result<(), RVError> {
         let memory = Memory::from_raw(&[]);
@@ -916,4 +2183,26 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn backtrace() -> Result<(), RVError> {
+        let memory = Memory::from_raw(&[]);
+        let mut emulator = Emulator::new(memory);
+        emulator.pc = 0x1000;
+
+        // two frames below the stack pointer, the outer one ending the chain
+        let top = emulator.x[SP] & !0xf;
+        let inner = top - 64;
+        let outer = top - 32;
+        emulator.memory.store(inner - 8, 0x2000u64)?;
+        emulator.memory.store(inner - 16, outer)?;
+        emulator.memory.store(outer - 8, 0x3000u64)?;
+        emulator.memory.store(outer - 16, 0u64)?;
+        emulator.x[S0] = inner;
+
+        assert_eq!(emulator.backtrace(16), [0x1000, 0x2000, 0x3000]);
+        assert_eq!(emulator.backtrace(2), [0x1000, 0x2000]);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,172 @@
+// limiting how much of the guest's stdout and stderr is kept, since both are strings in host
+// memory that a guest printing in a loop would otherwise grow until the host runs out
+
+use alloc::{string::String, sync::Arc};
+
+use super::Emulator;
+
+/// Appended to [`Emulator::stdout`] or [`Emulator::stderr`] once output to it goes past the
+/// [`OutputLimit`]
+pub const TRUNCATION_MARKER: &str = "\n[output truncated]\n";
+
+/// Receives the output past the limit when it's [`OutputOverflow::Stream`]ed
+pub type OutputSink = Arc<dyn Fn(OutputStream, &str) + Send + Sync>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// What happens to output past the limit
+#[derive(Clone)]
+pub enum OutputOverflow {
+    /// It's dropped
+    Truncate,
+    /// It's passed to the sink as it's written
+    Stream(OutputSink),
+}
+
+/// How much of stdout and stderr is kept in the emulator, see [`Emulator::set_output_limit`]
+#[derive(Clone)]
+pub struct OutputLimit {
+    /// The bytes kept of each of stdout and stderr, not counting the [`TRUNCATION_MARKER`]
+    pub max_bytes: usize,
+    pub overflow: OutputOverflow,
+}
+
+impl OutputLimit {
+    /// Drops everything past `max_bytes`
+    pub fn truncate(max_bytes: usize) -> OutputLimit {
+        OutputLimit {
+            max_bytes,
+            overflow: OutputOverflow::Truncate,
+        }
+    }
+
+    /// Passes everything past `max_bytes` to `sink`
+    pub fn stream<F>(max_bytes: usize, sink: F) -> OutputLimit
+    where
+        F: Fn(OutputStream, &str) + Send + Sync + 'static,
+    {
+        OutputLimit {
+            max_bytes,
+            overflow: OutputOverflow::Stream(Arc::new(sink)),
+        }
+    }
+}
+
+impl Emulator {
+    /// Keeps at most `limit.max_bytes` of each of [`Emulator::stdout`] and
+    /// [`Emulator::stderr`], ending them with [`TRUNCATION_MARKER`] once the guest writes more.
+    /// Unlimited by default.
+    pub fn set_output_limit(&mut self, limit: Option<OutputLimit>) {
+        self.output_limit = limit;
+    }
+
+    /// Whether the guest wrote more to `stream` than the output limit kept
+    pub fn output_truncated(&self, stream: OutputStream) -> bool {
+        self.output_truncated[stream as usize]
+    }
+
+    // how many bytes of `s` fit under the output limit, ending on a character boundary
+    pub(super) fn output_room(&self, stream: OutputStream, s: &str) -> usize {
+        let Some(ref limit) = self.output_limit else {
+            return s.len();
+        };
+        if self.output_truncated(stream) {
+            return 0;
+        }
+
+        let mut room = limit.max_bytes.saturating_sub(self.output(stream).len());
+        if room >= s.len() {
+            return s.len();
+        }
+        while !s.is_char_boundary(room) {
+            room -= 1;
+        }
+
+        room
+    }
+
+    // appends `s` to stdout or stderr, up to the output limit
+    pub(super) fn push_output(&mut self, stream: OutputStream, s: &str) {
+        let room = self.output_room(stream, s);
+        let (kept, overflow) = s.split_at(room);
+        self.output_mut(stream).push_str(kept);
+        if overflow.is_empty() {
+            return;
+        }
+
+        if !self.output_truncated(stream) {
+            self.output_truncated[stream as usize] = true;
+            self.output_mut(stream).push_str(TRUNCATION_MARKER);
+        }
+        if let Some(OutputLimit {
+            overflow: OutputOverflow::Stream(ref sink),
+            ..
+        }) = self.output_limit
+        {
+            sink(stream, overflow);
+        }
+    }
+
+    pub(super) fn push_char(&mut self, c: char) {
+        self.push_output(OutputStream::Stdout, c.encode_utf8(&mut [0; 4]));
+    }
+
+    fn output(&self, stream: OutputStream) -> &str {
+        match stream {
+            OutputStream::Stdout => &self.stdout,
+            OutputStream::Stderr => &self.stderr,
+        }
+    }
+
+    fn output_mut(&mut self, stream: OutputStream) -> &mut String {
+        match stream {
+            OutputStream::Stdout => &mut self.stdout,
+            OutputStream::Stderr => &mut self.stderr,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::{memory::Memory, sync::Lock};
+
+    #[test]
+    fn output_limit() {
+        let mut emulator = Emulator::new(Memory::from_raw(&[0; 0x100]));
+        emulator.set_output_limit(Some(OutputLimit::truncate(4)));
+
+        emulator.push_output(OutputStream::Stdout, "ab");
+        assert!(!emulator.output_truncated(OutputStream::Stdout));
+        // the multi-byte character would go past the limit, so it's dropped whole
+        emulator.push_output(OutputStream::Stdout, "cé");
+        emulator.push_output(OutputStream::Stdout, "d");
+        assert_eq!(emulator.stdout, String::from("abc") + TRUNCATION_MARKER);
+        assert!(emulator.output_truncated(OutputStream::Stdout));
+        assert!(!emulator.output_truncated(OutputStream::Stderr));
+
+        let streamed = Arc::new(Lock::new(Vec::new()));
+        let sink = streamed.clone();
+        let mut emulator = Emulator::new(Memory::from_raw(&[0; 0x100]));
+        emulator.set_output_limit(Some(OutputLimit::stream(2, move |stream, s: &str| {
+            sink.lock().push((stream, String::from(s)))
+        })));
+
+        emulator.push_output(OutputStream::Stderr, "abc");
+        emulator.push_output(OutputStream::Stderr, "de");
+        assert_eq!(emulator.stderr, String::from("ab") + TRUNCATION_MARKER);
+        assert_eq!(
+            *streamed.lock(),
+            [
+                (OutputStream::Stderr, String::from("c")),
+                (OutputStream::Stderr, String::from("de"))
+            ]
+        );
+    }
+}
@@ -0,0 +1,128 @@
+// watching the guest's dynamic linker bind PLT entries, by catching its stores to the GOT slots
+// of each loaded object's jump slot relocations
+
+use alloc::{string::String, vec::Vec};
+
+use super::{memcheck::memory_access, Emulator};
+use crate::instruction::Inst;
+
+/// A GOT slot of a PLT entry the dynamic linker filled in with the address of its function
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PltBinding {
+    pub symbol: String,
+    /// The object the PLT entry is in
+    pub object: String,
+    pub slot: u64,
+    pub target: u64,
+    /// The object the function was found in, if it's in one
+    pub target_object: Option<String>,
+    /// The instruction count when it was bound
+    pub inst_count: u64,
+}
+
+impl Emulator {
+    /// Records each PLT entry the dynamic linker binds, see [`Emulator::plt_bindings`]. Stores
+    /// are only seen one at a time, so the jit isn't used while this is enabled. Disabled by
+    /// default.
+    pub fn set_plt_tracking(&mut self, enabled: bool) {
+        self.plt_bindings = enabled.then(Vec::new);
+    }
+
+    /// The PLT entries bound so far, in the order they were bound. Lazily bound entries show up
+    /// the first time they're called, eagerly bound ones while their object is relocated. Empty
+    /// unless enabled with [`Emulator::set_plt_tracking`].
+    pub fn plt_bindings(&self) -> &[PltBinding] {
+        self.plt_bindings.as_deref().unwrap_or_default()
+    }
+
+    // the GOT slot `inst` is about to store to, if it's a PLT entry's
+    pub(super) fn plt_store(&self, inst: Inst) -> Option<u64> {
+        let (base, offset, 8, false) = memory_access(inst)? else {
+            return None;
+        };
+        let addr = self.x[base].wrapping_add(offset as u64);
+
+        self.memory.plt_slot(addr).map(|_| addr)
+    }
+
+    // records the binding of `slot` after it was stored to. Pointing it at its object's .plt,
+    // like lazy binding does before the first call, doesn't bind it.
+    pub(super) fn record_plt_binding(&mut self, slot: u64) {
+        let Ok(target) = self.memory.load::<u64>(slot) else {
+            return;
+        };
+        let Some((object, symbol)) = self.memory.plt_slot(slot) else {
+            return;
+        };
+        if object.plt.as_ref().is_some_and(|plt| plt.contains(&target)) {
+            return;
+        }
+
+        let binding = PltBinding {
+            symbol: symbol.into(),
+            object: object.name.clone(),
+            slot,
+            target,
+            target_object: self
+                .memory
+                .object_at(target)
+                .map(|object| object.name.clone()),
+            inst_count: self.inst_counter,
+        };
+        if let Some(ref mut bindings) = self.plt_bindings {
+            bindings.push(binding);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "sysroot"))]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::{
+        assembler::assemble,
+        files::{FileDescriptor, LIBM_DATA},
+        memory::Memory,
+        register::*,
+    };
+
+    #[test]
+    fn plt_tracking() {
+        let mut data = vec![0; 0x100];
+        for (i, line) in ["sd a1, 0(a0)", "sd a2, 0(a0)"].iter().enumerate() {
+            let inst = assemble(line, i as u64 * 4).unwrap();
+            data[i * 4..i * 4 + 4].copy_from_slice(&inst);
+        }
+
+        let mut emulator = Emulator::new(Memory::from_raw(&data));
+        let libm = FileDescriptor::new(LIBM_DATA);
+        emulator.memory.mmap_file(&libm, 0, 0, 0x80000).unwrap();
+        emulator.set_plt_tracking(true);
+
+        let object = &emulator.memory.loaded_objects()[0];
+        let (slot, symbol) = object.plt_slots[0].clone();
+        let plt = object.plt.clone().unwrap();
+        let cos = emulator.memory.disassembler.get_symbol_addr("cos").unwrap();
+
+        // pointing the slot at the .plt leaves it unbound, then it's bound to cos
+        emulator.x[A0] = slot;
+        emulator.x[A1] = plt.start;
+        emulator.x[A2] = cos;
+        emulator.fetch_and_execute().unwrap();
+        assert!(emulator.plt_bindings().is_empty());
+        emulator.fetch_and_execute().unwrap();
+
+        assert_eq!(
+            emulator.plt_bindings(),
+            [PltBinding {
+                symbol,
+                object: "libm.so.6".into(),
+                slot,
+                target: cos,
+                target_object: Some("libm.so.6".into()),
+                inst_count: 2,
+            }]
+        );
+    }
+}
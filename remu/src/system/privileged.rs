@@ -0,0 +1,682 @@
+// machine and supervisor mode, for kernels and other bare-metal programs that handle their own
+// traps instead of making syscalls to the emulator. The emulator can also act as the firmware
+// below a supervisor mode kernel, answering its ecalls like OpenSBI does.
+// https://five-embeddev.com/riscv-priv-isa-manual/Priv-v1.12/machine.html
+// https://github.com/riscv-non-isa/riscv-sbi-doc
+//
+// Interrupts aren't emulated, so mie and mip don't do anything. Paging needs the mmu feature,
+// without it satp can only be set to bare mode.
+
+use alloc::boxed::Box;
+
+use super::{memcheck::memory_access, AccessKind, Emulator};
+use crate::{error::RVError, instruction::Inst, register::*};
+
+/// A privilege level, see [`Emulator::enable_system_mode`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Privilege {
+    User = 0,
+    Supervisor = 1,
+    Machine = 3,
+}
+
+impl Privilege {
+    // the reserved level 2 is never stored, so it can't come back out of mpp
+    fn from_bits(bits: u64) -> Privilege {
+        match bits & 3 {
+            3 => Privilege::Machine,
+            1 => Privilege::Supervisor,
+            _ => Privilege::User,
+        }
+    }
+}
+
+// exception causes
+pub const ILLEGAL_INSTRUCTION: u64 = 2;
+pub const ECALL_FROM_U: u64 = 8;
+pub const ECALL_FROM_S: u64 = 9;
+pub const ECALL_FROM_M: u64 = 11;
+const INSTRUCTION_ACCESS_FAULT: u64 = 1;
+const BREAKPOINT: u64 = 3;
+const LOAD_ACCESS_FAULT: u64 = 5;
+const STORE_ACCESS_FAULT: u64 = 7;
+const INSTRUCTION_PAGE_FAULT: u64 = 12;
+const LOAD_PAGE_FAULT: u64 = 13;
+const STORE_PAGE_FAULT: u64 = 15;
+
+// privileged csrs
+const SSTATUS: u16 = 0x100;
+const SIE: u16 = 0x104;
+const STVEC: u16 = 0x105;
+const SCOUNTEREN: u16 = 0x106;
+const SSCRATCH: u16 = 0x140;
+const SEPC: u16 = 0x141;
+const SCAUSE: u16 = 0x142;
+const STVAL: u16 = 0x143;
+const SIP: u16 = 0x144;
+const SATP: u16 = 0x180;
+// satp's mode field, which turns on paging
+const SATP_MODE_SHIFT: u64 = 60;
+const SATP_MODE_BARE: u64 = 0;
+#[cfg(feature = "mmu")]
+const SATP_MODE_SV39: u64 = 8;
+const MSTATUS: u16 = 0x300;
+const MISA: u16 = 0x301;
+const MEDELEG: u16 = 0x302;
+const MIDELEG: u16 = 0x303;
+const MIE: u16 = 0x304;
+const MTVEC: u16 = 0x305;
+const MCOUNTEREN: u16 = 0x306;
+const MSCRATCH: u16 = 0x340;
+const MEPC: u16 = 0x341;
+const MCAUSE: u16 = 0x342;
+const MTVAL: u16 = 0x343;
+const MIP: u16 = 0x344;
+// physical memory protection, which is accepted and ignored
+const PMPCFG0: u16 = 0x3A0;
+const PMPADDR63: u16 = 0x3EF;
+const MVENDORID: u16 = 0xF11;
+const MIMPID: u16 = 0xF13;
+
+// mstatus fields
+const STATUS_SIE: u64 = 1 << 1;
+const STATUS_MIE: u64 = 1 << 3;
+const STATUS_SPIE: u64 = 1 << 5;
+const STATUS_MPIE: u64 = 1 << 7;
+const STATUS_SPP: u64 = 1 << 8;
+const STATUS_MPP_SHIFT: u64 = 11;
+const STATUS_MPP: u64 = 3 << STATUS_MPP_SHIFT;
+const STATUS_FS: u64 = 3 << 13;
+const STATUS_SUM: u64 = 1 << 18;
+const STATUS_MXR: u64 = 1 << 19;
+// uxl and sxl, which always say 64 bits
+const STATUS_XLEN: u64 = 2 << 32 | 2 << 34;
+const MSTATUS_WRITABLE: u64 = STATUS_SIE
+    | STATUS_MIE
+    | STATUS_SPIE
+    | STATUS_MPIE
+    | STATUS_SPP
+    | STATUS_MPP
+    | STATUS_FS
+    | STATUS_SUM
+    | STATUS_MXR;
+const SSTATUS_MASK: u64 =
+    STATUS_SIE | STATUS_SPIE | STATUS_SPP | STATUS_FS | STATUS_SUM | STATUS_MXR;
+
+// rv64 with the extensions remu implements, a bit per letter from a: imafdc, and supervisor and
+// user mode
+const MISA_VALUE: u64 = 2 << 62 | 0x14112d;
+
+// the exceptions the firmware leaves to the kernel, which is everything but its own ecalls
+const SBI_MEDELEG: u64 = 0xffff & !(1 << ECALL_FROM_S | 1 << ECALL_FROM_M);
+
+// sbi extensions, by the id the guest puts in a7
+const SBI_LEGACY_PUTCHAR: u64 = 0x01;
+const SBI_LEGACY_GETCHAR: u64 = 0x02;
+const SBI_LEGACY_SHUTDOWN: u64 = 0x08;
+const SBI_BASE: u64 = 0x10;
+const SBI_TIME: u64 = 0x54494D45;
+const SBI_DBCN: u64 = 0x4442434E;
+const SBI_SRST: u64 = 0x53525354;
+const SBI_EXTENSIONS: [u64; 7] = [
+    SBI_LEGACY_PUTCHAR,
+    SBI_LEGACY_GETCHAR,
+    SBI_LEGACY_SHUTDOWN,
+    SBI_BASE,
+    SBI_TIME,
+    SBI_DBCN,
+    SBI_SRST,
+];
+
+// sbi errors
+const SBI_ERR_FAILED: i64 = -1;
+const SBI_ERR_NOT_SUPPORTED: i64 = -2;
+
+// version 2.0
+const SBI_SPEC_VERSION: u64 = 2 << 24;
+
+// the privileged state of the hart, while system mode is enabled
+#[derive(Clone, Debug)]
+pub(super) struct SystemState {
+    privilege: Privilege,
+    // whether the emulator answers supervisor mode ecalls as the sbi firmware
+    sbi: bool,
+    mstatus: u64,
+    medeleg: u64,
+    mideleg: u64,
+    mie: u64,
+    mip: u64,
+    mtvec: u64,
+    mscratch: u64,
+    mepc: u64,
+    mcause: u64,
+    mtval: u64,
+    stvec: u64,
+    sscratch: u64,
+    sepc: u64,
+    scause: u64,
+    stval: u64,
+    satp: u64,
+}
+
+impl Emulator {
+    /// Runs the guest as a kernel or other bare-metal program at `privilege`, instead of
+    /// answering its ecalls as linux syscalls. Exceptions trap to the guest's handlers in mtvec
+    /// or stvec, and stop execution with [`RVError::UnhandledTrap`] if there isn't one.
+    ///
+    /// Starting in supervisor mode makes the emulator the machine mode firmware, which answers
+    /// the supervisor's ecalls through the SBI like OpenSBI does, for the console, timer and
+    /// shutdown. Everything else is delegated to the supervisor.
+    ///
+    /// Instructions are checked one at a time, so the jit isn't used in system mode.
+    pub fn enable_system_mode(&mut self, privilege: Privilege) {
+        let sbi = privilege < Privilege::Machine;
+        self.system = Some(Box::new(SystemState {
+            privilege,
+            sbi,
+            mstatus: 0,
+            medeleg: if sbi { SBI_MEDELEG } else { 0 },
+            mideleg: 0,
+            mie: 0,
+            mip: 0,
+            mtvec: 0,
+            mscratch: 0,
+            mepc: 0,
+            mcause: 0,
+            mtval: 0,
+            stvec: 0,
+            sscratch: 0,
+            sepc: 0,
+            scause: 0,
+            stval: 0,
+            satp: 0,
+        }));
+        self.update_translation();
+    }
+
+    /// The privilege level the guest is running at, or `None` outside of system mode
+    pub fn privilege(&self) -> Option<Privilege> {
+        self.system.as_ref().map(|system| system.privilege)
+    }
+
+    // goes back to answering ecalls as syscalls, for programs that set themselves up in machine
+    // mode before running as a normal one
+    pub(super) fn disable_system_mode(&mut self) {
+        self.system = None;
+        self.update_translation();
+    }
+
+    // the trap an instruction raises before it runs, because the current privilege level isn't
+    // allowed to run it, as (cause, tval)
+    pub(super) fn privilege_violation(&self, inst: Inst) -> Option<(u64, u64)> {
+        let privilege = self.system.as_ref()?.privilege;
+
+        // the address's bits 9:8 are the lowest privilege that can access it, and bits 11:10
+        // are 0b11 for read-only csrs
+        let csr_allowed = |csr: u16, write: bool| {
+            privilege as u16 >= (csr >> 8) & 3 && !(write && csr >> 10 == 0b11)
+        };
+
+        let allowed = match inst {
+            Inst::Mret => privilege == Privilege::Machine,
+            Inst::Sret | Inst::SfenceVma => privilege >= Privilege::Supervisor,
+            Inst::Csrrw { csr, .. } | Inst::Csrrwi { csr, .. } => csr_allowed(csr, true),
+            Inst::Csrrs { rs1, csr, .. } | Inst::Csrrc { rs1, csr, .. } => {
+                csr_allowed(csr, rs1.0 != 0)
+            }
+            Inst::Csrrsi { uimm, csr, .. } | Inst::Csrrci { uimm, csr, .. } => {
+                csr_allowed(csr, uimm != 0)
+            }
+            Inst::Error(data) => return Some((ILLEGAL_INSTRUCTION, data as u64)),
+            Inst::Ebreak => return Some((BREAKPOINT, self.pc)),
+            _ => true,
+        };
+
+        (!allowed).then_some((ILLEGAL_INSTRUCTION, 0))
+    }
+
+    // the trap a failed instruction raises instead of stopping the guest, if there's a handler
+    // for it. Returns the handler's address.
+    pub(super) fn fault_trap(&mut self, inst: Option<Inst>, e: &RVError) -> Option<u64> {
+        let (cause, tval) = match (e, inst) {
+            (&RVError::PageFault { addr, kind }, _) => match kind {
+                AccessKind::Read => (LOAD_PAGE_FAULT, addr),
+                AccessKind::Write => (STORE_PAGE_FAULT, addr),
+                AccessKind::Execute => (INSTRUCTION_PAGE_FAULT, addr),
+            },
+            (RVError::SegmentationFault, None) => (INSTRUCTION_ACCESS_FAULT, self.pc),
+            (RVError::SegmentationFault, Some(inst)) => {
+                let (base, offset, _, load) = memory_access(inst)?;
+                let addr = self.x[base].wrapping_add(offset as u64);
+                (
+                    if load {
+                        LOAD_ACCESS_FAULT
+                    } else {
+                        STORE_ACCESS_FAULT
+                    },
+                    addr,
+                )
+            }
+            _ => return None,
+        };
+
+        self.trap(cause, tval)
+    }
+
+    // takes the trap `cause` for the instruction at pc, to supervisor mode if it's delegated
+    // there and machine mode otherwise. Returns the handler's address, or `None` without
+    // changing anything if it has none.
+    pub(super) fn trap(&mut self, cause: u64, tval: u64) -> Option<u64> {
+        let pc = self.pc;
+        let system = self.system.as_mut()?;
+
+        let delegated =
+            system.privilege <= Privilege::Supervisor && system.medeleg & (1 << cause) != 0;
+
+        let handler = if delegated {
+            if system.stvec & !3 == 0 {
+                return None;
+            }
+
+            system.sepc = pc;
+            system.scause = cause;
+            system.stval = tval;
+            let sie = system.mstatus & STATUS_SIE != 0;
+            system.mstatus &= !(STATUS_SIE | STATUS_SPIE | STATUS_SPP);
+            if sie {
+                system.mstatus |= STATUS_SPIE;
+            }
+            if system.privilege == Privilege::Supervisor {
+                system.mstatus |= STATUS_SPP;
+            }
+            system.privilege = Privilege::Supervisor;
+
+            // exceptions go to the base address even in vectored mode
+            system.stvec & !3
+        } else {
+            if system.mtvec & !3 == 0 {
+                return None;
+            }
+
+            system.mepc = pc;
+            system.mcause = cause;
+            system.mtval = tval;
+            let mie = system.mstatus & STATUS_MIE != 0;
+            system.mstatus &= !(STATUS_MIE | STATUS_MPIE | STATUS_MPP);
+            if mie {
+                system.mstatus |= STATUS_MPIE;
+            }
+            system.mstatus |= (system.privilege as u64) << STATUS_MPP_SHIFT;
+            system.privilege = Privilege::Machine;
+
+            system.mtvec & !3
+        };
+
+        self.update_translation();
+        Some(handler)
+    }
+
+    // takes the trap `cause`, stopping the guest if it has no handler for it
+    pub(super) fn trap_or_stop(&mut self, cause: u64, tval: u64) -> Result<u64, RVError> {
+        self.trap(cause, tval)
+            .ok_or(RVError::UnhandledTrap { cause, pc: self.pc })
+    }
+
+    // an ecall in system mode, which traps or is answered by the sbi. Returns where to jump to,
+    // or `None` to continue with the next instruction.
+    pub(super) fn system_ecall(&mut self) -> Result<Option<u64>, RVError> {
+        let Some(ref system) = self.system else {
+            return Ok(None);
+        };
+
+        let cause = match system.privilege {
+            Privilege::Supervisor if system.sbi => {
+                self.sbi_call()?;
+                return Ok(None);
+            }
+            Privilege::User => ECALL_FROM_U,
+            Privilege::Supervisor => ECALL_FROM_S,
+            Privilege::Machine => ECALL_FROM_M,
+        };
+
+        self.trap_or_stop(cause, 0).map(Some)
+    }
+
+    // mret or sret, returning to the privilege level and pc saved when the trap was taken.
+    // Returns the pc.
+    pub(super) fn trap_return(&mut self, from: Privilege) -> u64 {
+        let Some(ref mut system) = self.system else {
+            return self.pc;
+        };
+
+        let pc = if from == Privilege::Machine {
+            system.privilege = Privilege::from_bits(system.mstatus >> STATUS_MPP_SHIFT);
+            let mpie = system.mstatus & STATUS_MPIE != 0;
+            system.mstatus &= !(STATUS_MIE | STATUS_MPP);
+            if mpie {
+                system.mstatus |= STATUS_MIE;
+            }
+            system.mstatus |= STATUS_MPIE;
+            system.mepc
+        } else {
+            system.privilege = if system.mstatus & STATUS_SPP != 0 {
+                Privilege::Supervisor
+            } else {
+                Privilege::User
+            };
+            let spie = system.mstatus & STATUS_SPIE != 0;
+            system.mstatus &= !(STATUS_SIE | STATUS_SPP);
+            if spie {
+                system.mstatus |= STATUS_SIE;
+            }
+            system.mstatus |= STATUS_SPIE;
+            system.sepc
+        };
+
+        self.update_translation();
+        pc
+    }
+
+    // points the memory's address translation at satp, unless in machine mode or satp is bare
+    fn update_translation(&mut self) {
+        #[cfg(feature = "mmu")]
+        {
+            use crate::memory::Translation;
+
+            let translation = self.system.as_ref().and_then(|system| {
+                let paging = system.satp >> SATP_MODE_SHIFT == SATP_MODE_SV39;
+                (paging && system.privilege != Privilege::Machine).then(|| Translation {
+                    satp: system.satp,
+                    user: system.privilege == Privilege::User,
+                    sum: system.mstatus & STATUS_SUM != 0,
+                    mxr: system.mstatus & STATUS_MXR != 0,
+                })
+            });
+            self.memory.set_translation(translation);
+        }
+    }
+
+    // a privileged csr, or `None` if it isn't one or system mode isn't enabled
+    pub(super) fn read_system_csr(&self, csr: u16) -> Option<u64> {
+        let system = self.system.as_ref()?;
+
+        Some(match csr {
+            SSTATUS => (system.mstatus | STATUS_XLEN) & (SSTATUS_MASK | 3 << 32),
+            SIE => system.mie & system.mideleg,
+            STVEC => system.stvec,
+            SSCRATCH => system.sscratch,
+            SEPC => system.sepc,
+            SCAUSE => system.scause,
+            STVAL => system.stval,
+            SIP => system.mip & system.mideleg,
+            SATP => system.satp,
+            MSTATUS => system.mstatus | STATUS_XLEN,
+            MISA => MISA_VALUE,
+            MEDELEG => system.medeleg,
+            MIDELEG => system.mideleg,
+            MIE => system.mie,
+            MTVEC => system.mtvec,
+            MSCRATCH => system.mscratch,
+            MEPC => system.mepc,
+            MCAUSE => system.mcause,
+            MTVAL => system.mtval,
+            MIP => system.mip,
+            SCOUNTEREN | MCOUNTEREN | PMPCFG0..=PMPADDR63 | MVENDORID..=MIMPID => 0,
+            _ => return None,
+        })
+    }
+
+    // writes a privileged csr, returning false if it isn't one or system mode isn't enabled
+    pub(super) fn write_system_csr(&mut self, csr: u16, value: u64) -> bool {
+        let Some(ref mut system) = self.system else {
+            return false;
+        };
+
+        // pcs are at least 2 byte aligned with compressed instructions
+        let epc = value & !1;
+
+        match csr {
+            SSTATUS => {
+                system.mstatus = system.mstatus & !SSTATUS_MASK | value & SSTATUS_MASK;
+            }
+            SIE => system.mie = system.mie & !system.mideleg | value & system.mideleg,
+            STVEC => system.stvec = value,
+            SSCRATCH => system.sscratch = value,
+            SEPC => system.sepc = epc,
+            SCAUSE => system.scause = value,
+            STVAL => system.stval = value,
+            SIP => system.mip = system.mip & !system.mideleg | value & system.mideleg,
+            // writing a mode that isn't supported leaves satp unchanged
+            SATP => {
+                let mode = value >> SATP_MODE_SHIFT;
+                #[cfg(feature = "mmu")]
+                let supported = mode == SATP_MODE_BARE || mode == SATP_MODE_SV39;
+                #[cfg(not(feature = "mmu"))]
+                let supported = mode == SATP_MODE_BARE;
+
+                if supported {
+                    system.satp = value;
+                }
+            }
+            MSTATUS => {
+                let mut mstatus = value & MSTATUS_WRITABLE;
+                // mpp can't hold the reserved level 2
+                if mstatus & STATUS_MPP == 2 << STATUS_MPP_SHIFT {
+                    mstatus &= !STATUS_MPP;
+                }
+                system.mstatus = mstatus;
+            }
+            MEDELEG => system.medeleg = value,
+            MIDELEG => system.mideleg = value,
+            MIE => system.mie = value,
+            MTVEC => system.mtvec = value,
+            MSCRATCH => system.mscratch = value,
+            MEPC => system.mepc = epc,
+            MCAUSE => system.mcause = value,
+            MTVAL => system.mtval = value,
+            MIP => system.mip = value,
+            // misa can't turn extensions off
+            MISA | SCOUNTEREN | MCOUNTEREN | PMPCFG0..=PMPADDR63 => {}
+            _ => return false,
+        }
+
+        if matches!(csr, SATP | SSTATUS | MSTATUS) {
+            self.update_translation();
+        }
+        true
+    }
+
+    // answers an sbi call, with the extension in a7 and the function in a6. Returns the error in
+    // a0 and the value in a1, or just a value in a0 for the legacy extensions.
+    fn sbi_call(&mut self) -> Result<(), RVError> {
+        let (eid, fid) = (self.x[A7], self.x[A6]);
+
+        let legacy = |value: i64| Ok::<_, RVError>((value, None));
+        let ok = |value: u64| Ok::<_, RVError>((0, Some(value)));
+        let not_supported = Ok::<_, RVError>((SBI_ERR_NOT_SUPPORTED, Some(0)));
+
+        let (error, value) = match (eid, fid) {
+            (SBI_LEGACY_PUTCHAR, _) => {
+                self.push_char(self.x[A0] as u8 as char);
+                legacy(0)
+            }
+            (SBI_LEGACY_GETCHAR, _) => legacy(self.read_stdin_byte().map_or(-1, i64::from)),
+            (SBI_LEGACY_SHUTDOWN, _) => {
+                self.exit_code = Some(0);
+                legacy(0)
+            }
+
+            (SBI_BASE, 0) => ok(SBI_SPEC_VERSION),
+            // implementation id and version, where 0 would be berkeley's bbl
+            (SBI_BASE, 1 | 2) => ok(0),
+            (SBI_BASE, 3) => ok(SBI_EXTENSIONS.contains(&self.x[A0]) as u64),
+            // mvendorid, marchid and mimpid
+            (SBI_BASE, 4..=6) => ok(0),
+
+            // there are no timer interrupts, so there's nothing to set
+            (SBI_TIME, 0) => ok(0),
+
+            // write, read and write_byte, with the high half of the address in a2 ignored
+            (SBI_DBCN, 0) => {
+                let (len, addr) = (self.x[A0], self.x[A1]);
+                match self.write_fd(1, addr, len)? {
+                    true => ok(len),
+                    false => Ok((SBI_ERR_FAILED, Some(0))),
+                }
+            }
+            (SBI_DBCN, 1) => {
+                let (len, addr) = (self.x[A0], self.x[A1]);
+                match self.fds.file_mut(0) {
+                    Some(stdin) => ok(self.memory.read_file(stdin, addr, len)? as u64),
+                    None => ok(0),
+                }
+            }
+            (SBI_DBCN, 2) => {
+                self.push_char(self.x[A0] as u8 as char);
+                ok(0)
+            }
+
+            // system reset, which exits with the reason as the code, so 0 is a normal shutdown
+            (SBI_SRST, 0) => {
+                self.exit_code = Some(self.x[A1]);
+                ok(0)
+            }
+
+            _ => {
+                log::warn!("{:16x} unsupported sbi call {eid:#x} {fid}", self.pc);
+                not_supported
+            }
+        }?;
+
+        self.x[A0] = error as u64;
+        if let Some(value) = value {
+            self.x[A1] = value;
+        }
+
+        Ok(())
+    }
+
+    fn read_stdin_byte(&mut self) -> Option<u8> {
+        let stdin = self.fds.file_mut(0)?;
+        let byte = *stdin.data.get(stdin.offset as usize)?;
+        stdin.offset += 1;
+        Some(byte)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::Memory;
+
+    fn from_code(code: &[u32]) -> Emulator {
+        let data: alloc::vec::Vec<u8> = code.iter().flat_map(|inst| inst.to_le_bytes()).collect();
+        Emulator::new(Memory::from_raw(&data))
+    }
+
+    #[test]
+    fn traps() {
+        let mut emulator = from_code(&[
+            0x01000293, // li t0, 16
+            0x30529073, // csrw mtvec, t0
+            0x00000073, // ecall
+            0x00000013, // nop
+            0x34102373, // csrr t1, mepc
+            0x00430313, // addi t1, t1, 4
+            0x34131073, // csrw mepc, t1
+            0x30200073, // mret
+        ]);
+        emulator.enable_system_mode(Privilege::Machine);
+
+        for _ in 0..3 {
+            emulator.fetch_and_execute().unwrap();
+        }
+        assert_eq!(emulator.pc, 16);
+        assert_eq!(emulator.read_csr(MCAUSE), ECALL_FROM_M);
+        assert_eq!(emulator.read_csr(MEPC), 8);
+        assert_eq!(emulator.read_csr(MSTATUS) & STATUS_MPP, STATUS_MPP);
+
+        for _ in 0..4 {
+            emulator.fetch_and_execute().unwrap();
+        }
+        assert_eq!(emulator.pc, 12);
+        assert_eq!(emulator.privilege(), Some(Privilege::Machine));
+
+        // mret drops to the level in mpp, which is user mode after the first one
+        emulator.pc = 28;
+        emulator.fetch_and_execute().unwrap();
+        assert_eq!(emulator.privilege(), Some(Privilege::User));
+
+        // where reading mtvec is illegal
+        emulator.pc = 4;
+        emulator.fetch_and_execute().unwrap();
+        assert_eq!(emulator.pc, 16);
+        assert_eq!(emulator.read_csr(MCAUSE), ILLEGAL_INSTRUCTION);
+
+        // without a handler the guest stops
+        let mut emulator = from_code(&[0x00000073]);
+        emulator.enable_system_mode(Privilege::Machine);
+        let e = emulator.fetch_and_execute().unwrap_err();
+        assert!(matches!(
+            e,
+            RVError::UnhandledTrap {
+                cause: ECALL_FROM_M,
+                pc: 0
+            }
+        ));
+    }
+
+    #[test]
+    fn sbi() {
+        let mut emulator = from_code(&[
+            0x00000073, // ecall
+            0x00000073, // ecall
+            0x00000073, // ecall
+        ]);
+        emulator.enable_system_mode(Privilege::Supervisor);
+
+        emulator.x[A7] = SBI_LEGACY_PUTCHAR;
+        emulator.x[A0] = b'h' as u64;
+        emulator.fetch_and_execute().unwrap();
+
+        emulator.x[A7] = SBI_BASE;
+        emulator.x[A6] = 3;
+        emulator.x[A0] = SBI_DBCN;
+        emulator.fetch_and_execute().unwrap();
+        assert_eq!((emulator.x[A0], emulator.x[A1]), (0, 1));
+
+        emulator.x[A7] = SBI_SRST;
+        emulator.x[A6] = 0;
+        emulator.x[A1] = 0;
+        assert_eq!(emulator.fetch_and_execute().unwrap(), Some(0));
+        assert_eq!(emulator.stdout, "h");
+    }
+
+    #[cfg(feature = "mmu")]
+    #[test]
+    fn page_faults() {
+        let mut data = [0u8; 0x4000];
+        data[0..4].copy_from_slice(&0x0005b503u32.to_le_bytes()); // ld a0, 0(a1)
+                                                                  // tables at 0x1000, 0x2000 and 0x3000 mapping the first page to itself
+        data[0x1000..0x1008].copy_from_slice(&(0x2u64 << 10 | 1).to_le_bytes());
+        data[0x2000..0x2008].copy_from_slice(&(0x3u64 << 10 | 1).to_le_bytes());
+        data[0x3000..0x3008].copy_from_slice(&0b1011u64.to_le_bytes()); // valid, read, execute
+
+        let mut emulator = Emulator::new(Memory::from_raw(&data));
+        emulator.enable_system_mode(Privilege::Supervisor);
+        emulator.write_csr(STVEC, 8);
+        emulator.write_csr(SATP, SATP_MODE_SV39 << SATP_MODE_SHIFT | 1);
+        emulator.x[A1] = 0x1000;
+
+        emulator.fetch_and_execute().unwrap();
+        assert_eq!(emulator.pc, 8);
+        assert_eq!(emulator.read_csr(SCAUSE), LOAD_PAGE_FAULT);
+        assert_eq!(emulator.read_csr(STVAL), 0x1000);
+
+        // the page tables are only mapped while paging is off
+        emulator.write_csr(SATP, 0);
+        emulator.pc = 0;
+        emulator.fetch_and_execute().unwrap();
+        assert_eq!(emulator.x[A0], 0x2 << 10 | 1);
+    }
+}
@@ -0,0 +1,360 @@
+// execve, and a fork that runs the child to completion before the parent continues. That's enough
+// for launchers that spawn a program and wait for it, without scheduling several processes.
+
+use alloc::{sync::Arc, vec::Vec};
+
+use super::{Emulator, FrameCheck, HeapProfile};
+use crate::{
+    error::RVError,
+    files::{OpenFile, Sysroot, Vfs},
+    memory::Memory,
+    register::{A0, SP},
+};
+
+// the signal a child that faulted is reported as killed by
+const SIGSEGV: u64 = 11;
+
+impl Emulator {
+    /// Makes `data` available to the guest at `path`, to open or execve. It isn't copied if it's
+    /// already an `Arc`, and clones of the emulator share it.
+    pub fn add_file(&mut self, path: &str, data: impl Into<Arc<[u8]>>) {
+        self.vfs.add_file(path, data);
+    }
+
+    /// The files the guest can open, list and execute
+    pub fn vfs(&self) -> &Vfs {
+        &self.vfs
+    }
+
+    pub fn vfs_mut(&mut self) -> &mut Vfs {
+        &mut self.vfs
+    }
+
+    /// Where dynamically linked programs started by execve get their dynamic linker and shared
+    /// libraries, and where the guest's shared libraries are opened from
+    pub fn sysroot(&self) -> &Sysroot {
+        &self.sysroot
+    }
+
+    pub fn sysroot_mut(&mut self) -> &mut Sysroot {
+        &mut self.sysroot
+    }
+
+    /// Replaces the program with the executable `data`, like execve. Memory, registers and the
+    /// auxiliary vector start over, while open files, output and the profiler are kept. Heap
+    /// profiling and the checks restart with the new program.
+    pub fn exec(&mut self, data: &[u8]) -> Result<(), RVError> {
+        let elf = Memory::parse_elf(data)?;
+        let memcheck = self.memory.is_memcheck_enabled();
+        let options = self.memory.load_options();
+        self.memory = Memory::load_elf_with_sysroot(elf, &options, &self.sysroot);
+        self.memory.set_memcheck_enabled(memcheck);
+        self.memcheck_reports.clear();
+
+        self.pc = self.memory.entry;
+        self.x = [0; 32];
+        self.f = [0.0; 32];
+        self.reservation = None;
+
+        self.inst_cache.invalidate();
+        self.block_cache.invalidate();
+        #[cfg(jit)]
+        {
+            self.jit_functions = Default::default();
+            self.exec_count += 1;
+        }
+
+        self.set_hle_enabled(!self.hle_routines.is_empty());
+        self.find_intercepts();
+        self.find_malloc_fault();
+        if self.heap_profile.is_some() {
+            self.heap_profile = Some(HeapProfile::default());
+        }
+        self.in_heap_call = false;
+        self.find_heap_routines();
+        if self.frame_check.is_some() {
+            self.frame_check = Some(FrameCheck::default());
+        }
+        self.frame_violation = None;
+        if let Some(ref mut taint) = self.taint {
+            taint.clear_labels();
+        }
+
+        self.x[SP] = self.memory.stack_top();
+        self.init_auxv_stack()?;
+        self.save_start();
+
+        Ok(())
+    }
+
+    /// The exit status of each child that exited but wasn't waited for yet, by pid, encoded like
+    /// wait4 does
+    pub fn children(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.children.iter().map(|(&pid, &status)| (pid, status))
+    }
+
+    // runs a copy of the emulator as a child process until it exits, returning its pid. The
+    // child starts after the current ecall, on `stack` if it isn't 0. Output and sinks the child
+    // writes to are shared with the parent, while exit hooks aren't run for it.
+    pub(super) fn run_child(&mut self, stack: u64) -> u64 {
+        let pid = self.next_pid;
+
+        let mut child = self.fork();
+        child.pid = pid;
+        child.ppid = self.pid;
+        child.next_pid = pid + 1;
+        child.children.clear();
+        child.exit_hooks.clear();
+        child.exit_summary_enabled = false;
+        child.x[A0] = 0;
+        if stack != 0 {
+            child.x[SP] = stack;
+        }
+        child.pc += 4;
+
+        log::info!("Running child process {pid}");
+        let status = match child.run(false) {
+            Ok(exit_code) => match child.term_signal {
+                Some(sig) => sig,
+                None => (exit_code & 0xff) << 8,
+            },
+            Err(e) => {
+                log::warn!("Child process {pid} faulted: {e}");
+                SIGSEGV
+            }
+        };
+
+        self.stdout.push_str(&child.stdout[self.stdout.len()..]);
+        self.stderr.push_str(&child.stderr[self.stderr.len()..]);
+        // the child was limited the same way, starting from the same output
+        self.output_truncated = child.output_truncated;
+        let sinks: Vec<(i64, Vec<u8>)> = child
+            .fds
+            .iter()
+            .filter_map(|(fd, file)| match file {
+                OpenFile::Sink(data) => Some((fd, data.clone())),
+                _ => None,
+            })
+            .collect();
+        for (fd, data) in sinks {
+            if let Some(OpenFile::Sink(parent_data)) = self.fds.get_mut(fd) {
+                *parent_data = data;
+            }
+        }
+
+        self.next_pid = child.next_pid;
+        self.children.insert(pid, status);
+        pid
+    }
+
+    // whether `pid` is a child that exited but wasn't waited for
+    pub(super) fn is_zombie(&self, pid: u64) -> bool {
+        self.children.contains_key(&pid)
+    }
+
+    // the pid and status of an exited child, `pid` or any of them if it's -1
+    pub(super) fn reap_child(&mut self, pid: i64) -> Option<(u64, u64)> {
+        if pid == -1 {
+            self.children.pop_first()
+        } else {
+            let status = self.children.remove(&(pid as u64))?;
+            Some((pid as u64, status))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use elf::{endian::AnyEndian, ElfBytes};
+
+    use super::*;
+    use crate::{
+        memory::{LoadOptions, PAGE_MASK},
+        register::A7,
+        system::{EmulatorBuilder, STACK_START},
+    };
+
+    #[test]
+    fn fork_and_wait() -> Result<(), RVError> {
+        let mut data = [0u8; 12];
+        data[4..8].copy_from_slice(&0x00300513u32.to_le_bytes()); // li a0, 3
+        data[8..12].copy_from_slice(&0x00000073u32.to_le_bytes()); // ecall
+
+        // the child starts after the clone at 0, and exits with 3
+        let mut emulator = Emulator::new(Memory::from_raw(&data));
+        emulator.x[A7] = 93;
+        let pid = emulator.run_child(0);
+
+        assert_eq!(pid, 2);
+        assert_eq!(emulator.pc, 0);
+        assert_eq!(emulator.exit_code, None);
+        assert_eq!(emulator.children().collect::<Vec<_>>(), [(2, 3 << 8)]);
+        assert_eq!(emulator.reap_child(-1), Some((2, 3 << 8)));
+        assert_eq!(emulator.reap_child(-1), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn execve() -> Result<(), RVError> {
+        let mut data = [0u8; 0x200];
+        data[0..4].copy_from_slice(&0x10000513u32.to_le_bytes()); // li a0, 0x100
+        data[4..8].copy_from_slice(&0x00000593u32.to_le_bytes()); // li a1, 0
+        data[8..12].copy_from_slice(&0x00000613u32.to_le_bytes()); // li a2, 0
+        data[12..16].copy_from_slice(&0x0dd00893u32.to_le_bytes()); // li a7, 221
+        data[16..20].copy_from_slice(&0x00000073u32.to_le_bytes()); // ecall
+        data[20..24].copy_from_slice(&0x05d00893u32.to_le_bytes()); // li a7, 93
+        data[24..28].copy_from_slice(&0x00000073u32.to_le_bytes()); // ecall
+        data[0x100..0x10b].copy_from_slice(b"/bin/child\0");
+
+        let mut emulator = EmulatorBuilder::new(Memory::from_raw(&data))
+            .stdin(*b"input")
+            .build()?;
+        let mut missing = emulator.fork();
+        assert_eq!(missing.run(false)?, -2i64 as u64); // ENOENT
+
+        emulator.add_file("/bin/child", exit_elf(5));
+        assert_eq!(emulator.run(false)?, 5);
+        assert!(emulator.fds().file(0).is_some());
+
+        assert!(matches!(
+            emulator.exec(b"#!/bin/sh"),
+            Err(RVError::InvalidFileType)
+        ));
+
+        Ok(())
+    }
+
+    #[cfg(jit)]
+    #[test]
+    fn execve_from_jit() -> Result<(), RVError> {
+        let mut data = [0u8; 0x200];
+        data[0..4].copy_from_slice(&0x010000efu32.to_le_bytes()); // jal ra, 16
+        data[4..8].copy_from_slice(&0x00100513u32.to_le_bytes()); // li a0, 1
+        data[8..12].copy_from_slice(&0x05d00893u32.to_le_bytes()); // li a7, 93
+        data[12..16].copy_from_slice(&0x00000073u32.to_le_bytes()); // ecall
+        data[16..20].copy_from_slice(&0x10000513u32.to_le_bytes()); // li a0, 0x100
+        data[20..24].copy_from_slice(&0x00000593u32.to_le_bytes()); // li a1, 0
+        data[24..28].copy_from_slice(&0x00000613u32.to_le_bytes()); // li a2, 0
+        data[28..32].copy_from_slice(&0x0dd00893u32.to_le_bytes()); // li a7, 221
+        data[32..36].copy_from_slice(&0x00000073u32.to_le_bytes()); // ecall
+        data[36..40].copy_from_slice(&0x00200513u32.to_le_bytes()); // li a0, 2
+        data[40..44].copy_from_slice(&0x00008067u32.to_le_bytes()); // ret
+        data[0x100..0x10b].copy_from_slice(b"/bin/child\0");
+
+        let mut emulator = Emulator::new(Memory::from_raw(&data));
+        emulator.add_file("/bin/child", exit_elf(5));
+        let mut compiled = emulator.clone();
+        assert_eq!(emulator.run(true)?, 5);
+
+        // neither the function calling execve nor its caller keep running the old program
+        compiled.jit_functions.wait();
+        assert_eq!(compiled.jit_stats().functions_compiled, 2);
+        assert_eq!(compiled.run(true)?, 5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn elf_bytes() -> Result<(), RVError> {
+        assert_eq!(Emulator::from_elf_bytes(&exit_elf(6))?.run(false)?, 6);
+
+        let mut riscv32 = exit_elf(6);
+        riscv32[4] = 1; // ELFCLASS32
+        let mut x86 = exit_elf(6);
+        x86[18] = 0x3e; // EM_X86_64
+        let mut object = exit_elf(6);
+        object[16] = 1; // ET_REL
+        for data in [&riscv32[..], &x86, &object, b"#!/bin/sh"] {
+            assert!(matches!(
+                Memory::load_elf_bytes(data, &LoadOptions::default()),
+                Err(RVError::InvalidFileType)
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn load_options() -> Result<(), RVError> {
+        const ENTRY: u64 = 0x10000 + 64 + 56;
+
+        let mut pie = exit_elf(4);
+        pie[16] = 3; // ET_DYN
+        let load = |data: &[u8], options: LoadOptions| {
+            let elf = ElfBytes::<AnyEndian>::minimal_parse(data).unwrap();
+            Memory::load_elf_with_options(elf, &options)
+        };
+
+        let based = LoadOptions {
+            program_base: Some(0x400000),
+            ..LoadOptions::default()
+        };
+        assert_eq!(load(&exit_elf(4), based).entry, ENTRY);
+        let memory = load(&pie, based);
+        assert_eq!(memory.entry, 0x400000 + ENTRY);
+        assert_eq!(Emulator::new(memory).run(false)?, 4);
+
+        let aslr = |seed| LoadOptions {
+            aslr_seed: Some(seed),
+            ..LoadOptions::default()
+        };
+        let mut memory = load(&pie, aslr(1));
+        let same = load(&pie, aslr(1));
+        let other = load(&pie, aslr(2));
+        assert_eq!(
+            (memory.entry, memory.stack_top()),
+            (same.entry, same.stack_top())
+        );
+        assert_ne!(
+            (memory.entry, memory.stack_top()),
+            (other.entry, other.stack_top())
+        );
+        assert_eq!(memory.entry & PAGE_MASK, ENTRY & PAGE_MASK);
+        assert_eq!(memory.stack_top() % 16, 0xf);
+        assert_eq!(memory.mmap(0, 0x1000) as u64 & PAGE_MASK, 0);
+
+        let mut emulator = Emulator::new(memory);
+        assert!(emulator.x[SP] < STACK_START);
+        assert_eq!(emulator.run(false)?, 4);
+
+        Ok(())
+    }
+
+    // a static executable that exits with `exit_code`
+    fn exit_elf(exit_code: u8) -> Vec<u8> {
+        const BASE: u64 = 0x10000;
+        const CODE: u64 = 64 + 56;
+        let code = [
+            0x00000513 | (exit_code as u32) << 20,
+            0x05d00893,
+            0x00000073,
+        ];
+
+        let mut elf = Vec::new();
+        elf.extend(b"\x7fELF\x02\x01\x01");
+        elf.resize(16, 0);
+        elf.extend(2u16.to_le_bytes()); // executable
+        elf.extend(0xf3u16.to_le_bytes()); // risc-v
+        elf.extend(1u32.to_le_bytes());
+        elf.extend((BASE + CODE).to_le_bytes()); // entry
+        elf.extend(64u64.to_le_bytes()); // program headers
+        elf.extend(0u64.to_le_bytes()); // section headers
+        elf.extend(0u32.to_le_bytes());
+        for size in [64u16, 56, 1, 64, 0, 0] {
+            elf.extend(size.to_le_bytes());
+        }
+
+        let len = CODE + 4 * code.len() as u64;
+        elf.extend(1u32.to_le_bytes()); // PT_LOAD
+        elf.extend(5u32.to_le_bytes()); // r-x
+        for field in [0, BASE, BASE, len, len, 0x1000] {
+            elf.extend(field.to_le_bytes());
+        }
+
+        for inst in code {
+            elf.extend(inst.to_le_bytes());
+        }
+        elf
+    }
+}
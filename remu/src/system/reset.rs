@@ -0,0 +1,144 @@
+// rewinding the guest to the start of its program without loading it again, for running the same
+// program over and over, like a fuzzer's persistent mode. The start is kept once the stack and
+// auxiliary vector are set up, when the emulator is built and after each execve.
+
+use alloc::boxed::Box;
+
+use super::{clock::GuestClock, Emulator, FrameCheck, HeapProfile};
+use crate::{files::FdTable, memory::Memory};
+
+#[derive(Clone)]
+pub(super) struct StartState {
+    pc: u64,
+    x: [u64; 32],
+    f: [f64; 32],
+    memory: Memory,
+    fds: FdTable,
+    clock: GuestClock,
+}
+
+impl Emulator {
+    /// Rewinds the guest to the start of the program, as it was when the emulator was built or
+    /// the program was last replaced by execve. Registers, memory, open files, output and the
+    /// instruction count start over, while settings like intercepts, breakpoints and the
+    /// profiler are kept. Memcheck and the memory limit stay as they are now. Only the pages of
+    /// memory written since are copied back, unless memory was mapped or unmapped, see
+    /// [`Memory::restore`].
+    pub fn reset(&mut self) {
+        let Some(start) = self.start.take() else {
+            return;
+        };
+
+        let memcheck = self.memory.is_memcheck_enabled();
+        let memory_limit = self.memory.memory_limit();
+        self.memory.restore(&start.memory);
+        self.memory.set_memcheck_enabled(memcheck);
+        self.memory.set_memory_limit(memory_limit);
+        self.memcheck_reports.clear();
+
+        self.pc = start.pc;
+        self.x = start.x;
+        self.f = start.f;
+        self.fds = start.fds.clone();
+        self.clock = start.clock.clone();
+        self.reservation = None;
+
+        self.inst_cache.invalidate();
+        self.block_cache.invalidate();
+        #[cfg(jit)]
+        {
+            self.jit_functions = Default::default();
+        }
+
+        if self.heap_profile.is_some() {
+            self.heap_profile = Some(HeapProfile::default());
+        }
+        self.in_heap_call = false;
+        if self.frame_check.is_some() {
+            self.frame_check = Some(FrameCheck::default());
+        }
+        self.frame_violation = None;
+        if let Some(ref mut taint) = self.taint {
+            taint.clear_labels();
+        }
+
+        self.stdout.clear();
+        self.stderr.clear();
+        self.output_truncated = [false; 2];
+        self.children.clear();
+        self.next_pid = self.pid + 1;
+
+        self.inst_counter = 0;
+        self.max_memory = 0;
+        self.exit_code = None;
+        self.hart_exit_code = None;
+        self.term_signal = None;
+        self.exit_summary = None;
+        self.update_next_interrupt();
+        self.start = Some(start);
+    }
+
+    // makes the current state the one `reset` goes back to
+    pub(super) fn save_start(&mut self) {
+        self.memory.clear_dirty_pages();
+        self.start = Some(Box::new(StartState {
+            pc: self.pc,
+            x: self.x,
+            f: self.f,
+            memory: self.memory.clone(),
+            fds: self.fds.clone(),
+            clock: self.clock.clone(),
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::{assembler::assemble, error::RVError};
+
+    // increments the byte at 0x80, writes it to stdout and exits with it
+    fn program() -> Vec<u8> {
+        let mut data = Vec::new();
+        for line in [
+            "lbu a0, 128(zero)",
+            "addi a0, a0, 1",
+            "sb a0, 128(zero)",
+            "li a0, 1",
+            "li a1, 0x80",
+            "li a2, 1",
+            "li a7, 64",
+            "ecall",
+            "lbu a0, 128(zero)",
+            "li a7, 93",
+            "ecall",
+        ] {
+            data.extend(assemble(line, data.len() as u64).unwrap());
+        }
+
+        data.resize(0x100, 0);
+        data[0x80] = b'a';
+        data
+    }
+
+    #[test]
+    fn reset() -> Result<(), RVError> {
+        let mut emulator = Emulator::new(Memory::from_raw(&program()));
+        assert_eq!(emulator.run(false)?, b'b' as u64);
+        let inst_count = emulator.inst_counter;
+
+        emulator.reset();
+        assert_eq!((emulator.pc, emulator.inst_counter), (0, 0));
+        assert_eq!(emulator.exit_code, None);
+        assert!(emulator.stdout.is_empty());
+
+        // memory was restored too, so it counts from 'a' again
+        assert_eq!(emulator.run(false)?, b'b' as u64);
+        assert_eq!(emulator.stdout, "b");
+        assert_eq!(emulator.inst_counter, inst_count);
+
+        Ok(())
+    }
+}
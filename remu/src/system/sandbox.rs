@@ -0,0 +1,406 @@
+// limiting what the guest is allowed to do on top of what the emulator implements: which
+// syscalls it can make, whether it can read host files through mounted directories, and which
+// extensions' instructions it can run. Anything else stops the emulator with
+// `RVError::SandboxViolation` instead of being carried out.
+
+use alloc::{boxed::Box, collections::BTreeSet, string::String};
+use core::fmt::{self, Display};
+
+use super::{syscall::AT_FDCWD, Emulator};
+use crate::{
+    error::RVError,
+    instruction::Inst,
+    register::{Reg, A0},
+};
+
+/// An extension to RV64I
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Extension {
+    M,
+    A,
+    F,
+    D,
+    C,
+    Zicsr,
+    Zifencei,
+}
+
+impl Extension {
+    const ALL: [Extension; 7] = [
+        Extension::M,
+        Extension::A,
+        Extension::F,
+        Extension::D,
+        Extension::C,
+        Extension::Zicsr,
+        Extension::Zifencei,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Extension::M => "m",
+            Extension::A => "a",
+            Extension::F => "f",
+            Extension::D => "d",
+            Extension::C => "c",
+            Extension::Zicsr => "zicsr",
+            Extension::Zifencei => "zifencei",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Extension> {
+        Extension::ALL.into_iter().find(|ext| ext.name() == name)
+    }
+
+    // the extension `inst` is part of, if it isn't in RV64I. Compressed instructions are also
+    // part of C.
+    fn of(inst: Inst) -> Option<Extension> {
+        Some(match inst {
+            Inst::Mul { .. }
+            | Inst::Mulhu { .. }
+            | Inst::Div { .. }
+            | Inst::Divw { .. }
+            | Inst::Divu { .. }
+            | Inst::Divuw { .. }
+            | Inst::Remw { .. }
+            | Inst::Remu { .. }
+            | Inst::Remuw { .. } => Extension::M,
+            Inst::Amoswapw { .. }
+            | Inst::Amoswapd { .. }
+            | Inst::Amoaddw { .. }
+            | Inst::Amoaddd { .. }
+            | Inst::Amoorw { .. }
+            | Inst::Amomaxuw { .. }
+            | Inst::Amomaxud { .. }
+            | Inst::Lrw { .. }
+            | Inst::Lrd { .. }
+            | Inst::Scw { .. }
+            | Inst::Scd { .. } => Extension::A,
+            Inst::Flw { .. } | Inst::Fsw { .. } => Extension::F,
+            Inst::Fld { .. }
+            | Inst::Fsd { .. }
+            | Inst::Fcvtdlu { .. }
+            | Inst::Fcvtds { .. }
+            | Inst::Fled { .. }
+            | Inst::Fdivd { .. } => Extension::D,
+            Inst::Csrrw { .. }
+            | Inst::Csrrs { .. }
+            | Inst::Csrrc { .. }
+            | Inst::Csrrwi { .. }
+            | Inst::Csrrsi { .. }
+            | Inst::Csrrci { .. } => Extension::Zicsr,
+            Inst::FenceI => Extension::Zifencei,
+            _ => return None,
+        })
+    }
+}
+
+/// A set of [`Extension`]s
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Extensions(u8);
+
+impl Extensions {
+    /// Only RV64I
+    pub const NONE: Extensions = Extensions(0);
+    /// RV64GC
+    pub const ALL: Extensions = Extensions(0b1111111);
+
+    pub fn with(self, extension: Extension) -> Extensions {
+        Extensions(self.0 | 1 << extension as u8)
+    }
+
+    pub fn contains(self, extension: Extension) -> bool {
+        self.0 & 1 << extension as u8 != 0
+    }
+
+    /// Parses a comma separated list of extension names, like `m,a,c`, or `all`
+    pub fn parse(list: &str) -> Option<Extensions> {
+        if list == "all" {
+            return Some(Extensions::ALL);
+        }
+
+        list.split(',')
+            .try_fold(Extensions::NONE, |extensions, name| {
+                Some(extensions.with(Extension::from_name(name.trim())?))
+            })
+    }
+}
+
+/// What the guest is allowed to do, see [`Emulator::set_sandbox_policy`]. By default it allows
+/// every syscall and instruction the emulator implements, but not reading host files.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SandboxPolicy {
+    // every syscall is allowed if this is None
+    allowed_syscalls: Option<BTreeSet<u64>>,
+    denied_syscalls: BTreeSet<u64>,
+    host_files: bool,
+    extensions: Extensions,
+}
+
+impl Default for SandboxPolicy {
+    fn default() -> Self {
+        SandboxPolicy {
+            allowed_syscalls: None,
+            denied_syscalls: BTreeSet::new(),
+            host_files: false,
+            extensions: Extensions::ALL,
+        }
+    }
+}
+
+impl SandboxPolicy {
+    /// Only allows the syscalls numbered in `nrs`, along with any allowed before
+    pub fn allow_syscalls(mut self, nrs: impl IntoIterator<Item = u64>) -> Self {
+        self.allowed_syscalls
+            .get_or_insert_with(BTreeSet::new)
+            .extend(nrs);
+        self
+    }
+
+    /// Denies the syscalls numbered in `nrs`, even if they're allowed
+    pub fn deny_syscalls(mut self, nrs: impl IntoIterator<Item = u64>) -> Self {
+        self.denied_syscalls.extend(nrs);
+        self
+    }
+
+    /// Whether syscalls can be made with paths in host directories mounted with
+    /// [`Vfs::mount`](crate::files::Vfs::mount)
+    pub fn host_files(mut self, allowed: bool) -> Self {
+        self.host_files = allowed;
+        self
+    }
+
+    /// The extensions whose instructions can run. Restricting them makes every instruction be
+    /// checked, which is as slow as running with memcheck.
+    pub fn extensions(mut self, extensions: Extensions) -> Self {
+        self.extensions = extensions;
+        self
+    }
+
+    pub fn allows_syscall(&self, nr: u64) -> bool {
+        let allowed = match self.allowed_syscalls {
+            Some(ref allowed) => allowed.contains(&nr),
+            None => true,
+        };
+        allowed && !self.denied_syscalls.contains(&nr)
+    }
+
+    pub fn allows_host_files(&self) -> bool {
+        self.host_files
+    }
+
+    pub fn allowed_extensions(&self) -> Extensions {
+        self.extensions
+    }
+}
+
+/// Something the guest tried to do that its [`SandboxPolicy`] doesn't allow
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SandboxViolation {
+    Syscall {
+        pc: u64,
+        nr: u64,
+    },
+    /// A syscall with a path in a mounted host directory
+    HostFile {
+        pc: u64,
+        path: String,
+    },
+    Instruction {
+        pc: u64,
+        inst: Inst,
+        extension: Extension,
+    },
+}
+
+impl Display for SandboxViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SandboxViolation::Syscall { pc, nr } => write!(f, "syscall {nr} at pc {pc:#x}"),
+            SandboxViolation::HostFile { pc, path } => {
+                write!(f, "host file {path:?} at pc {pc:#x}")
+            }
+            SandboxViolation::Instruction {
+                pc,
+                inst,
+                extension,
+            } => write!(
+                f,
+                "`{}` from the {} extension at pc {pc:#x}",
+                inst.fmt(*pc),
+                extension.name()
+            ),
+        }
+    }
+}
+
+impl Emulator {
+    /// Stops the emulator with [`RVError::SandboxViolation`] when the guest does something
+    /// `policy` doesn't allow, instead of doing it. The jit isn't used while there's a policy.
+    /// Unrestricted by default.
+    pub fn set_sandbox_policy(&mut self, policy: Option<SandboxPolicy>) {
+        self.sandbox = policy.map(Box::new);
+    }
+
+    pub fn sandbox_policy(&self) -> Option<&SandboxPolicy> {
+        self.sandbox.as_deref()
+    }
+
+    // whether there's a policy that needs every instruction to be checked
+    pub(super) fn restricts_extensions(&self) -> bool {
+        self.sandbox
+            .as_ref()
+            .is_some_and(|policy| policy.extensions != Extensions::ALL)
+    }
+
+    // checks the instruction at pc, `len` bytes long, before it runs
+    pub(super) fn check_sandbox_inst(&self, inst: Inst, len: u8) -> Result<(), RVError> {
+        let Some(ref policy) = self.sandbox else {
+            return Ok(());
+        };
+
+        let compressed = (len == 2).then_some(Extension::C);
+        let missing = [Extension::of(inst), compressed]
+            .into_iter()
+            .flatten()
+            .find(|&extension| !policy.extensions.contains(extension));
+
+        match missing {
+            Some(extension) => Err(self.sandbox_violation(SandboxViolation::Instruction {
+                pc: self.pc,
+                inst,
+                extension,
+            })),
+            None => Ok(()),
+        }
+    }
+
+    // checks syscall `nr` before it's made, along with the path it's given if it takes one
+    pub(super) fn check_sandbox_syscall(
+        &mut self,
+        nr: u64,
+        path_arg: Option<usize>,
+    ) -> Result<(), RVError> {
+        let Some(ref policy) = self.sandbox else {
+            return Ok(());
+        };
+
+        if !policy.allows_syscall(nr) {
+            return Err(self.sandbox_violation(SandboxViolation::Syscall { pc: self.pc, nr }));
+        }
+
+        if let (false, Some(arg)) = (policy.host_files, path_arg) {
+            // the path is relative to the directory in a0 for the *at syscalls
+            let dirfd = match arg {
+                0 => AT_FDCWD,
+                _ => self.x[A0] as i64,
+            };
+            let path = self
+                .memory
+                .read_string_n(self.x[Reg(A0.0 + arg as u8)], 512)?;
+
+            if let Some(path) = self.resolve_path(dirfd, &path) {
+                if self.vfs.is_host_path(&path) {
+                    return Err(
+                        self.sandbox_violation(SandboxViolation::HostFile { pc: self.pc, path })
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn sandbox_violation(&self, violation: SandboxViolation) -> RVError {
+        log::error!("the sandbox policy doesn't allow {violation}");
+        RVError::SandboxViolation(Box::new(violation))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::{assembler::assemble, memory::Memory};
+
+    fn program(lines: &[&str]) -> Memory {
+        let mut data = Vec::new();
+        for line in lines {
+            data.extend(assemble(line, data.len() as u64).unwrap());
+        }
+
+        data.resize(0x100, 0);
+        data[0x80..0x91].copy_from_slice(b"/host/Cargo.toml\0");
+        Memory::from_raw(&data)
+    }
+
+    fn violation(emulator: &mut Emulator) -> SandboxViolation {
+        match emulator.run(false) {
+            Err(RVError::SandboxViolation(violation)) => *violation,
+            result => panic!("expected a sandbox violation, got {result:?}"),
+        }
+    }
+
+    #[test]
+    fn sandbox_policy() {
+        let exit = ["li a7, 93", "ecall"];
+        let mul = program(&["li a0, 6", "mul a0, a0, a0", exit[0], exit[1]]);
+
+        let mut emulator = Emulator::new(mul.clone());
+        emulator.set_sandbox_policy(Some(SandboxPolicy::default()));
+        assert_eq!(emulator.run(false).unwrap(), 36);
+
+        let mut emulator = Emulator::new(mul.clone());
+        let policy = SandboxPolicy::default().extensions(Extensions::parse("a,c").unwrap());
+        emulator.set_sandbox_policy(Some(policy));
+        assert_eq!(
+            violation(&mut emulator),
+            SandboxViolation::Instruction {
+                pc: 4,
+                inst: Inst::decode(0x02a50533).0,
+                extension: Extension::M
+            }
+        );
+
+        // only exit is allowed
+        let mut emulator = Emulator::new(mul);
+        let policy = SandboxPolicy::default().allow_syscalls([94]);
+        emulator.set_sandbox_policy(Some(policy));
+        assert_eq!(
+            violation(&mut emulator),
+            SandboxViolation::Syscall { pc: 0xc, nr: 93 }
+        );
+
+        // openat(AT_FDCWD, "/host/Cargo.toml", O_RDONLY)
+        let open = program(&[
+            "li a0, -100",
+            "li a1, 0x80",
+            "li a2, 0",
+            "li a7, 56",
+            "ecall",
+            "li a0, 0",
+            exit[0],
+            exit[1],
+        ]);
+        let mut emulator = Emulator::new(open.clone());
+        emulator
+            .vfs_mut()
+            .mount("/host", env!("CARGO_MANIFEST_DIR"));
+        emulator.set_sandbox_policy(Some(SandboxPolicy::default()));
+        assert_eq!(
+            violation(&mut emulator),
+            SandboxViolation::HostFile {
+                pc: 0x10,
+                path: "/host/Cargo.toml".into()
+            }
+        );
+
+        let mut emulator = Emulator::new(open);
+        emulator
+            .vfs_mut()
+            .mount("/host", env!("CARGO_MANIFEST_DIR"));
+        emulator.set_sandbox_policy(Some(SandboxPolicy::default().host_files(true)));
+        assert_eq!(emulator.run(false).unwrap(), 0);
+    }
+}
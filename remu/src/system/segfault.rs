@@ -0,0 +1,226 @@
+use alloc::{boxed::Box, format, string::String};
+use core::fmt::{self, Display};
+
+use super::{memcheck::memory_access, Emulator};
+use crate::{error::RVError, instruction::Inst, memory::Mapping};
+
+/// The kind of access that faulted
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+    /// Fetching the instruction at the pc
+    Execute,
+}
+
+/// A memory access of the faulting instruction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Access {
+    pub kind: AccessKind,
+    pub addr: u64,
+    /// The number of bytes accessed
+    pub size: u64,
+}
+
+/// Where and why the guest faulted, see [`RVError::Segfault`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Segfault {
+    pub pc: u64,
+    /// The faulting instruction, disassembled. `None` if it couldn't be fetched.
+    pub inst: Option<String>,
+    /// The symbol containing the pc and the offset into it
+    pub symbol: Option<(String, u64)>,
+    /// The access that faulted, or `None` if it wasn't made by a load or store, like the memory
+    /// a syscall reads
+    pub access: Option<Access>,
+    /// The symbol closest below the faulting address and the offset into it
+    pub addr_symbol: Option<(String, u64)>,
+    /// The mapping containing the faulting address, which the access wasn't allowed into
+    pub mapping: Option<Mapping>,
+    /// The closest mappings below and above the faulting address
+    pub below: Option<Mapping>,
+    pub above: Option<Mapping>,
+}
+
+impl Emulator {
+    // adds where and how the guest faulted to a segmentation fault. Other errors are returned
+    // unchanged, as are faults that already have it.
+    pub(super) fn diagnose(&self, e: RVError) -> RVError {
+        let RVError::SegmentationFault = e else {
+            return e;
+        };
+
+        let disassembler = &self.memory.disassembler;
+        let symbol = |addr| {
+            disassembler
+                .get_symbol_containing(addr)
+                .map(|(name, offset)| (String::from(name), offset))
+        };
+
+        // registers aren't written by an instruction that faults, so the address can be found
+        // again from them
+        let inst = self
+            .memory
+            .load::<u32>(self.pc)
+            .ok()
+            .map(|data| Inst::decode(data).0);
+        let access = match inst {
+            None => Some(Access {
+                kind: AccessKind::Execute,
+                addr: self.pc,
+                size: 4,
+            }),
+            Some(inst) => memory_access(inst).map(|(base, offset, size, load)| Access {
+                kind: if load {
+                    AccessKind::Read
+                } else {
+                    AccessKind::Write
+                },
+                addr: self.x[base].wrapping_add(offset as u64),
+                size,
+            }),
+        };
+
+        let mut segfault = Segfault {
+            pc: self.pc,
+            inst: inst.map(|inst| inst.fmt(self.pc)),
+            symbol: symbol(self.pc),
+            access,
+            addr_symbol: None,
+            mapping: None,
+            below: None,
+            above: None,
+        };
+
+        if let Some(access) = access {
+            let mappings = self.memory.mappings();
+            let addr = access.addr;
+
+            segfault.addr_symbol = symbol(addr);
+            segfault.mapping = mappings.iter().find(|m| m.contains(addr)).copied();
+            segfault.below = mappings.iter().rev().find(|m| m.end <= addr).copied();
+            segfault.above = mappings.iter().find(|m| m.start > addr).copied();
+        }
+
+        RVError::Segfault(Box::new(segfault))
+    }
+}
+
+impl Display for AccessKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AccessKind::Read => "read",
+            AccessKind::Write => "write",
+            AccessKind::Execute => "execute",
+        })
+    }
+}
+
+// `addr <symbol+offset>`, or just the address without a symbol
+fn location(addr: u64, symbol: &Option<(String, u64)>) -> String {
+    match symbol {
+        Some((name, offset)) => format!("{addr:#x} <{name}+{offset:#x}>"),
+        None => format!("{addr:#x}"),
+    }
+}
+
+/// A report of several lines, ending in a newline
+impl Display for Segfault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.access {
+            Some(access) => writeln!(
+                f,
+                "segmentation fault: {} of {} bytes at {:#x}",
+                access.kind, access.size, access.addr
+            )?,
+            None => writeln!(f, "segmentation fault")?,
+        }
+
+        write!(f, "  pc:      {}", location(self.pc, &self.symbol))?;
+        match self.inst {
+            Some(ref inst) => writeln!(f, ": {inst}")?,
+            None => writeln!(f)?,
+        }
+
+        let Some(access) = self.access else {
+            return Ok(());
+        };
+
+        writeln!(f, "  address: {}", location(access.addr, &self.addr_symbol))?;
+        match self.mapping {
+            Some(mapping) => writeln!(f, "  in:      {mapping}")?,
+            None => writeln!(f, "  in:      nothing mapped")?,
+        }
+        if let Some(below) = self.below {
+            writeln!(
+                f,
+                "  below:   {below} ({:#x} bytes before)",
+                access.addr - below.end
+            )?;
+        }
+        if let Some(above) = self.above {
+            writeln!(
+                f,
+                "  above:   {above} ({:#x} bytes after)",
+                above.start - access.addr
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        memory::{MappingKind, Memory},
+        register::A1,
+    };
+
+    #[test]
+    fn segfault() {
+        let mut data = [0u8; 8];
+        data[0..4].copy_from_slice(&0x00000593u32.to_le_bytes()); // li a1, 0
+        data[4..8].copy_from_slice(&0x00a5b423u32.to_le_bytes()); // sd a0, 8(a1)
+
+        let mut emulator = Emulator::new(Memory::from_raw(&data));
+        let addr = emulator.memory.mmap(0, 0x1000) as u64;
+        emulator.memory.protect(addr, 0x1000, 1).unwrap();
+        emulator.x[A1] = addr;
+        emulator.pc = 4;
+
+        let e = emulator.diagnose(RVError::SegmentationFault);
+        let RVError::Segfault(segfault) = e else {
+            panic!("not diagnosed: {e}");
+        };
+
+        assert_eq!(segfault.pc, 4);
+        assert_eq!(segfault.inst.as_deref(), Some("sd    a0, 8(a1)"));
+        assert_eq!(
+            segfault.access,
+            Some(Access {
+                kind: AccessKind::Write,
+                addr: addr + 8,
+                size: 8,
+            })
+        );
+        assert_eq!(segfault.mapping.map(|m| m.kind), Some(MappingKind::Mmap));
+        assert_eq!(segfault.above.map(|m| m.kind), Some(MappingKind::Stack));
+
+        let report = segfault.to_string();
+        assert!(report.starts_with("segmentation fault: write of 8 bytes at "));
+        assert!(report.contains("r--p mmap"));
+
+        // a jump to nowhere faults on the fetch
+        emulator.pc = 0x10000;
+        let RVError::Segfault(segfault) = emulator.diagnose(RVError::SegmentationFault) else {
+            panic!("not diagnosed");
+        };
+        assert_eq!(
+            segfault.access.map(|access| access.kind),
+            Some(AccessKind::Execute)
+        );
+        assert_eq!(segfault.inst, None);
+    }
+}
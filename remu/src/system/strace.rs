@@ -0,0 +1,313 @@
+// printing syscalls like strace, with their arguments decoded: paths and buffers as strings, flags
+// by name, and errors as the errno they are
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+
+use super::{syscall::AT_FDCWD, Emulator, Syscall, SyscallRecord};
+use crate::register::*;
+
+/// Receives each line of the syscall trace, see [`Emulator::set_strace`]
+pub type StraceSink = Arc<dyn Fn(&str) + Send + Sync>;
+
+// the bytes of a buffer shown, like strace's default -s 32
+const BUFFER_PREVIEW_LEN: u64 = 32;
+
+const OPEN_FLAGS: &[(u64, &str)] = &[
+    (0o100, "O_CREAT"),
+    (0o200, "O_EXCL"),
+    (0o400, "O_NOCTTY"),
+    (0o1000, "O_TRUNC"),
+    (0o2000, "O_APPEND"),
+    (0o4000, "O_NONBLOCK"),
+    (0o200000, "O_DIRECTORY"),
+    (0o400000, "O_NOFOLLOW"),
+    (0o2000000, "O_CLOEXEC"),
+];
+
+const PROT_FLAGS: &[(u64, &str)] = &[(1, "PROT_READ"), (2, "PROT_WRITE"), (4, "PROT_EXEC")];
+
+const MAP_FLAGS: &[(u64, &str)] = &[
+    (0x1, "MAP_SHARED"),
+    (0x2, "MAP_PRIVATE"),
+    (0x10, "MAP_FIXED"),
+    (0x20, "MAP_ANONYMOUS"),
+    (0x100, "MAP_GROWSDOWN"),
+    (0x4000, "MAP_NORESERVE"),
+    (0x8000, "MAP_POPULATE"),
+    (0x20000, "MAP_STACK"),
+    (0x100000, "MAP_FIXED_NOREPLACE"),
+];
+
+const ERRNOS: &[(i64, &str, &str)] = &[
+    (1, "EPERM", "Operation not permitted"),
+    (2, "ENOENT", "No such file or directory"),
+    (3, "ESRCH", "No such process"),
+    (4, "EINTR", "Interrupted system call"),
+    (5, "EIO", "Input/output error"),
+    (9, "EBADF", "Bad file descriptor"),
+    (10, "ECHILD", "No child processes"),
+    (11, "EAGAIN", "Resource temporarily unavailable"),
+    (12, "ENOMEM", "Cannot allocate memory"),
+    (13, "EACCES", "Permission denied"),
+    (14, "EFAULT", "Bad address"),
+    (17, "EEXIST", "File exists"),
+    (20, "ENOTDIR", "Not a directory"),
+    (21, "EISDIR", "Is a directory"),
+    (22, "EINVAL", "Invalid argument"),
+    (24, "EMFILE", "Too many open files"),
+    (25, "ENOTTY", "Inappropriate ioctl for device"),
+    (29, "ESPIPE", "Illegal seek"),
+    (34, "ERANGE", "Numerical result out of range"),
+    (36, "ENAMETOOLONG", "File name too long"),
+    (38, "ENOSYS", "Function not implemented"),
+];
+
+// how an argument is shown
+#[derive(Clone, Copy)]
+enum Arg {
+    Hex,
+    Int,
+    Fd,
+    DirFd,
+    Path,
+    // a buffer the syscall reads, or writes as many bytes of as it returns
+    InBuf,
+    OutBuf,
+    OpenFlags,
+    Prot,
+    MapFlags,
+    Octal,
+}
+
+impl Syscall {
+    // how each argument of the syscall is shown, past those it's hex
+    fn strace_args(self) -> &'static [Arg] {
+        use Arg::*;
+
+        match self {
+            Syscall::Openat => &[DirFd, Path, OpenFlags, Octal],
+            Syscall::Faccessat => &[DirFd, Path, Int, Hex],
+            Syscall::Readlinkat | Syscall::Newfstatat | Syscall::Statx => &[DirFd, Path],
+            Syscall::Execve => &[Path],
+            Syscall::Read => &[Fd, OutBuf, Int],
+            Syscall::Write => &[Fd, InBuf, Int],
+            Syscall::Readv | Syscall::Writev => &[Fd, Hex, Int],
+            Syscall::Close | Syscall::Ioctl | Syscall::Getdents64 => &[Fd],
+            Syscall::Lseek => &[Fd, Int, Int],
+            Syscall::Mmap => &[Hex, Int, Prot, MapFlags, Fd, Hex],
+            Syscall::Mprotect => &[Hex, Int, Prot],
+            Syscall::Munmap => &[Hex, Int],
+            Syscall::Exit | Syscall::ExitGroup => &[Int],
+            Syscall::Kill => &[Int, Int],
+            Syscall::Tgkill => &[Int, Int, Int],
+            _ => &[],
+        }
+    }
+
+    // whether the syscall returns an address rather than a number
+    fn returns_address(self) -> bool {
+        matches!(self, Syscall::Brk | Syscall::Mmap | Syscall::Mremap)
+    }
+}
+
+impl Emulator {
+    /// Passes every syscall the guest makes to `sink` as a line like strace's, after it returns.
+    /// Syscalls handled by a [`SyscallHandler`](super::SyscallHandler) aren't traced. Disabled by
+    /// default.
+    pub fn set_strace(&mut self, sink: Option<StraceSink>) {
+        self.strace = sink;
+    }
+
+    pub(super) fn is_strace_enabled(&self) -> bool {
+        self.strace.is_some()
+    }
+
+    // traces a syscall that returned `record.ret`
+    pub(super) fn strace(&self, record: &SyscallRecord) {
+        let Some(ref sink) = self.strace else {
+            return;
+        };
+
+        let ret = match record.syscall {
+            Syscall::Exit | Syscall::ExitGroup => "?".to_string(),
+            sc => format_ret(record.ret, sc.returns_address()),
+        };
+        sink(&format!(
+            "{}({}) = {ret}",
+            record.syscall.name(),
+            self.strace_args(record).join(", ")
+        ));
+    }
+
+    // traces a syscall remu doesn't know, before it stops the guest
+    pub(super) fn strace_unknown(&self, id: u64) {
+        let Some(ref sink) = self.strace else {
+            return;
+        };
+
+        let args: Vec<String> = [A0, A1, A2, A3, A4, A5]
+            .iter()
+            .map(|&reg| format!("{:#x}", self.x[reg]))
+            .collect();
+        sink(&format!("syscall_{id}({}) = ?", args.join(", ")));
+    }
+
+    fn strace_args(&self, record: &SyscallRecord) -> Vec<String> {
+        let (arg_count, _) = record.syscall.signature();
+        let kinds = record.syscall.strace_args();
+
+        record.args[..arg_count]
+            .iter()
+            .enumerate()
+            .map(
+                |(i, &arg)| match kinds.get(i).copied().unwrap_or(Arg::Hex) {
+                    Arg::Hex => format!("{arg:#x}"),
+                    Arg::Int => (arg as i64).to_string(),
+                    Arg::Fd => (arg as i32).to_string(),
+                    Arg::DirFd if arg as i64 == AT_FDCWD => "AT_FDCWD".to_string(),
+                    Arg::DirFd => (arg as i32).to_string(),
+                    Arg::Path => match record.path {
+                        Some(ref path) => format!("{path:?}"),
+                        None => format!("{arg:#x}"),
+                    },
+                    Arg::InBuf => self.buffer_preview(arg, record.args[i + 1]),
+                    Arg::OutBuf if (record.ret as i64) < 0 => format!("{arg:#x}"),
+                    Arg::OutBuf => self.buffer_preview(arg, record.ret),
+                    Arg::OpenFlags => {
+                        let access =
+                            ["O_RDONLY", "O_WRONLY", "O_RDWR", "O_ACCMODE"][arg as usize & 3];
+                        match format_flags(arg & !3, OPEN_FLAGS) {
+                            flags if flags == "0" => access.to_string(),
+                            flags => format!("{access}|{flags}"),
+                        }
+                    }
+                    Arg::Prot if arg == 0 => "PROT_NONE".to_string(),
+                    Arg::Prot => format_flags(arg, PROT_FLAGS),
+                    Arg::MapFlags => format_flags(arg, MAP_FLAGS),
+                    Arg::Octal => format!("0{arg:o}"),
+                },
+            )
+            .collect()
+    }
+
+    // the start of the `len` bytes at `addr` as an escaped string
+    fn buffer_preview(&self, addr: u64, len: u64) -> String {
+        let Ok(data) = self.memory.read_n(addr, len.min(BUFFER_PREVIEW_LEN)) else {
+            return format!("{addr:#x}");
+        };
+
+        let mut preview = String::from("\"");
+        for byte in data {
+            match byte {
+                b'\n' => preview.push_str("\\n"),
+                b'\t' => preview.push_str("\\t"),
+                b'\r' => preview.push_str("\\r"),
+                b'"' => preview.push_str("\\\""),
+                b'\\' => preview.push_str("\\\\"),
+                0x20..=0x7e => preview.push(byte as char),
+                _ => preview.push_str(&format!("\\x{byte:02x}")),
+            }
+        }
+        preview.push('"');
+        if len > BUFFER_PREVIEW_LEN {
+            preview.push_str("...");
+        }
+
+        preview
+    }
+}
+
+// the names of the bits of `value` in `names`, with any others in hex
+fn format_flags(value: u64, names: &[(u64, &str)]) -> String {
+    if value == 0 {
+        return "0".to_string();
+    }
+
+    let mut rest = value;
+    let mut parts = Vec::new();
+    for &(bit, name) in names {
+        if value & bit != 0 {
+            rest &= !bit;
+            parts.push(name.to_string());
+        }
+    }
+    if rest != 0 {
+        parts.push(format!("{rest:#x}"));
+    }
+
+    parts.join("|")
+}
+
+// errors are small negative numbers, shown like `-1 ENOENT (No such file or directory)`
+fn format_ret(ret: u64, address: bool) -> String {
+    let ret = ret as i64;
+    if (-4095..0).contains(&ret) {
+        return match ERRNOS.iter().find(|&&(errno, ..)| errno == -ret) {
+            Some((_, name, description)) => format!("-1 {name} ({description})"),
+            None => format!("-1 errno {}", -ret),
+        };
+    }
+
+    if address {
+        format!("{ret:#x}")
+    } else {
+        ret.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{memory::Memory, sync::Lock};
+
+    #[test]
+    fn strace() {
+        let lines = Arc::new(Lock::new(Vec::new()));
+        let sink = lines.clone();
+        let mut emulator = Emulator::new(Memory::from_raw(&[0; 0x400]));
+        emulator.set_strace(Some(Arc::new(move |line: &str| {
+            sink.lock().push(line.to_string())
+        })));
+        emulator.memory.write_n(b"hi\n\0", 0x100, 4).unwrap();
+        emulator
+            .memory
+            .write_n(b"/etc/passwd\0", 0x200, 12)
+            .unwrap();
+
+        let record = |syscall, args: [u64; 6], path: Option<&str>, ret: i64| SyscallRecord {
+            syscall,
+            args,
+            path: path.map(String::from),
+            ret: ret as u64,
+        };
+        emulator.strace(&record(Syscall::Write, [1, 0x100, 3, 0, 0, 0], None, 3));
+        emulator.strace(&record(
+            Syscall::Openat,
+            [AT_FDCWD as u64, 0x200, 0o2000101, 0o644, 0, 0],
+            Some("/etc/passwd"),
+            -2,
+        ));
+        emulator.strace(&record(
+            Syscall::Mmap,
+            [0, 0x2000, 3, 0x22, -1i64 as u64, 0],
+            None,
+            0x4000,
+        ));
+        emulator.strace(&record(Syscall::ExitGroup, [3, 0, 0, 0, 0, 0], None, 3));
+
+        assert_eq!(
+            *lines.lock(),
+            [
+                "write(1, \"hi\\n\", 3) = 3",
+                "openat(AT_FDCWD, \"/etc/passwd\", O_WRONLY|O_CREAT|O_CLOEXEC, 0644) = -1 ENOENT (No such file or directory)",
+                "mmap(0x0, 8192, PROT_READ|PROT_WRITE, MAP_PRIVATE|MAP_ANONYMOUS, -1, 0x0) = 0x4000",
+                "exit_group(3) = ?",
+            ]
+        );
+    }
+}
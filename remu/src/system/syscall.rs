@@ -2,22 +2,32 @@
 // https://jborza.com/post/2021-05-11-riscv-linux-syscalls/
 // then some edits made for correctness from linux kernel source code
 
+use alloc::{format, string::String, vec, vec::Vec};
+use core::fmt::{self, Display};
+
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
 
-use crate::{error::RVError, files::*, register::*, system::FileDescriptor};
+use crate::{error::RVError, files::*, memory::range_end, register::*};
 
-use super::Emulator;
+use super::{
+    events::{Event, EventCategory},
+    output::OutputStream,
+    taint::TaintSource,
+    Emulator,
+};
 
-#[derive(FromPrimitive, Debug)]
+#[derive(FromPrimitive, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Syscall {
     Ioctl = 29,
     Faccessat = 48,
     Openat = 56,
     Close = 57,
+    Getdents64 = 61,
     Lseek = 62,
     Read = 63,
     Write = 64,
+    Readv = 65,
     Writev = 66,
     Readlinkat = 78,
     Newfstatat = 79,
@@ -26,33 +36,182 @@ pub enum Syscall {
     SetTidAddress = 96,
     Futex = 98,
     SetRobustList = 99,
+    Nanosleep = 101,
     ClockGettime = 113,
+    ClockNanosleep = 115,
+    SchedGetaffinity = 123,
     SchedYield = 124,
+    Kill = 129,
     Tgkill = 131,
     RtSigaction = 134,
     RtSigprocmask = 135,
+    Uname = 160,
+    Prctl = 167,
     Getpid = 172,
+    Getppid = 173,
     Gettid = 178,
+    Sysinfo = 179,
     Brk = 214,
     Munmap = 215,
+    Mremap = 216,
+    Clone = 220,
+    Execve = 221,
     Mmap = 222,
     Mprotect = 226,
+    Madvise = 233,
+    RiscvHwprobe = 258,
+    RiscvFlushIcache = 259,
+    Wait4 = 260,
     Prlimit64 = 261,
     Getrandom = 278,
+    Statx = 291,
+    Rseq = 293,
+    Clone3 = 435,
+}
+
+impl Syscall {
+    /// The name of the syscall in linux, like `exit_group`
+    pub fn name(self) -> String {
+        let mut name = String::new();
+        for (i, c) in format!("{self:?}").chars().enumerate() {
+            if c.is_ascii_uppercase() && i > 0 {
+                name.push('_');
+            }
+            name.push(c.to_ascii_lowercase());
+        }
+
+        name
+    }
+
+    // the number of arguments, and which one is a path, if any
+    pub(super) fn signature(self) -> (usize, Option<usize>) {
+        match self {
+            Syscall::Getpid | Syscall::Getppid | Syscall::Gettid | Syscall::SchedYield => (0, None),
+            Syscall::Close
+            | Syscall::Exit
+            | Syscall::ExitGroup
+            | Syscall::SetTidAddress
+            | Syscall::Brk
+            | Syscall::Uname
+            | Syscall::Sysinfo => (1, None),
+            Syscall::SetRobustList
+            | Syscall::Kill
+            | Syscall::ClockGettime
+            | Syscall::Nanosleep
+            | Syscall::Munmap
+            | Syscall::Clone3 => (2, None),
+            Syscall::Ioctl
+            | Syscall::Getdents64
+            | Syscall::Lseek
+            | Syscall::Read
+            | Syscall::Write
+            | Syscall::Readv
+            | Syscall::Writev
+            | Syscall::SchedGetaffinity
+            | Syscall::Tgkill
+            | Syscall::Mprotect
+            | Syscall::Madvise
+            | Syscall::Getrandom
+            | Syscall::RiscvFlushIcache => (3, None),
+            Syscall::Execve => (3, Some(0)),
+            Syscall::Faccessat | Syscall::Openat => (4, Some(1)),
+            Syscall::Readlinkat | Syscall::Newfstatat => (4, Some(1)),
+            Syscall::RtSigaction
+            | Syscall::RtSigprocmask
+            | Syscall::Prlimit64
+            | Syscall::Wait4
+            | Syscall::ClockNanosleep
+            | Syscall::Rseq => (4, None),
+            Syscall::Clone | Syscall::Mremap | Syscall::Prctl | Syscall::RiscvHwprobe => (5, None),
+            Syscall::Statx => (5, Some(1)),
+            Syscall::Futex | Syscall::Mmap => (6, None),
+        }
+    }
+}
+
+/// A syscall made by the guest, see [`Event::Syscall`]
+#[derive(Clone, Debug)]
+pub struct SyscallRecord {
+    pub syscall: Syscall,
+    pub args: [u64; 6],
+    // the path argument, read before the syscall ran
+    pub path: Option<String>,
+    pub ret: u64,
+}
+
+impl Display for SyscallRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (arg_count, path_arg) = self.syscall.signature();
+        write!(f, "{}(", self.syscall.name())?;
+        for (i, arg) in self.args[..arg_count].iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+
+            match self.path {
+                Some(ref path) if path_arg == Some(i) => write!(f, "{path:?}")?,
+                _ => write!(f, "{arg:#x}")?,
+            }
+        }
+
+        // errors are small negative numbers
+        let ret = self.ret as i64;
+        if (-4095..0).contains(&ret) {
+            write!(f, ") = {ret}")
+        } else {
+            write!(f, ") = {:#x}", self.ret)
+        }
+    }
 }
 
 impl Emulator {
-    // emulates linux syscalls
     pub(super) fn syscall(&mut self) -> Result<(), RVError> {
         let id = self.x[A7];
-        let arg = self.x[A0];
+        let sc: Option<Syscall> = FromPrimitive::from_u64(id);
+        self.check_sandbox_syscall(id, sc.and_then(|sc| sc.signature().1))?;
+
+        if self.handle_custom_syscall(id, sc.is_some())? {
+            return Ok(());
+        }
 
-        let sc: Syscall = FromPrimitive::from_u64(id).expect(&format!(
-            "{:16x} {} Unknown syscall: {id}",
-            self.pc, self.inst_counter
-        ));
+        let Some(sc) = sc else {
+            self.strace_unknown(id);
+            panic!(
+                "{:16x} {} Unknown syscall: {id}",
+                self.pc, self.inst_counter
+            );
+        };
 
         // log::info!("{:x}: executing syscall {sc:?}", self.pc);
+        self.profiler.trace_syscall(sc);
+
+        if !self.is_event_enabled(EventCategory::Syscall) && !self.is_strace_enabled() {
+            return self.emulate_syscall(sc);
+        }
+
+        let args = [A0, A1, A2, A3, A4, A5].map(|reg| self.x[reg]);
+        let path = sc
+            .signature()
+            .1
+            .and_then(|i| self.memory.read_string_n(args[i], 512).ok());
+
+        self.emulate_syscall(sc)?;
+
+        let record = SyscallRecord {
+            syscall: sc,
+            args,
+            path,
+            ret: self.x[A0],
+        };
+        self.strace(&record);
+        self.record_event(EventCategory::Syscall, Event::Syscall(record));
+
+        Ok(())
+    }
+
+    // emulates linux syscalls
+    fn emulate_syscall(&mut self, sc: Syscall) -> Result<(), RVError> {
+        let arg = self.x[A0];
 
         match sc {
             Syscall::Ioctl => {
@@ -72,55 +231,39 @@ impl Emulator {
                 log::info!("Opening file fd={fd}, name={filename}");
                 // log::info!("Flags={_flags:b}");
 
-                if filename == "/lib/tls/libc.so.6" {
-                    self.file_descriptors.insert(
-                        LIBC_FILE_DESCRIPTOR,
-                        FileDescriptor {
-                            offset: 0,
-                            data: LIBC_DATA.into(),
-                        },
-                    );
-
-                    self.x[A0] = LIBC_FILE_DESCRIPTOR as u64;
-                } else if filename == "/lib/tls/libstdc++.so.6" {
-                    self.file_descriptors.insert(
-                        LIBCPP_FILE_DESCRIPTOR,
-                        FileDescriptor {
-                            offset: 0,
-                            data: LIBCPP_DATA.into(),
-                        },
-                    );
-
-                    self.x[A0] = LIBCPP_FILE_DESCRIPTOR as u64;
-                } else if filename == "/lib/tls/libm.so.6" {
-                    self.file_descriptors.insert(
-                        LIBM_FILE_DESCRIPTOR,
-                        FileDescriptor {
-                            offset: 0,
-                            data: LIBM_DATA.into(),
-                        },
-                    );
-
-                    self.x[A0] = LIBM_FILE_DESCRIPTOR as u64;
-                } else if filename == "/lib/tls/libgcc_s.so.1" {
-                    self.file_descriptors.insert(
-                        LIBGCCS_FILE_DESCRIPTOR,
-                        FileDescriptor {
-                            offset: 0,
-                            data: LIBGCCS_DATA.into(),
-                        },
-                    );
-
-                    self.x[A0] = LIBGCCS_FILE_DESCRIPTOR as u64;
-                } else {
-                    self.x[A0] = (-1i64) as u64;
-                }
+                let Some(path) = self.resolve_path(fd, &filename) else {
+                    self.x[A0] = -9i64 as u64; // EBADF
+                    return Ok(());
+                };
+
+                // shared libraries come from the sysroot, unless the vfs has its own
+                let node = self
+                    .vfs
+                    .lookup(&path)
+                    .or_else(|| self.sysroot.file(&path).map(VfsNode::File));
+
+                self.x[A0] = match node {
+                    Some(VfsNode::File(data)) => {
+                        let file = FileDescriptor { offset: 0, data };
+                        self.fds.open(OpenFile::File(file)) as u64
+                    }
+                    Some(VfsNode::Device(device)) => self.fds.open(OpenFile::Device(device)) as u64,
+                    Some(VfsNode::Directory(entries)) => {
+                        let dir = OpenFile::Directory {
+                            path,
+                            entries,
+                            position: 0,
+                        };
+                        self.fds.open(dir) as u64
+                    }
+                    None => -2i64 as u64, // ENOENT
+                };
             }
 
             Syscall::Close => {
                 let fd = self.x[A0] as i64;
 
-                if self.file_descriptors.remove(&fd).is_some() {
+                if self.fds.remove(fd).is_some() {
                     self.x[A0] = 0;
                 } else {
                     self.x[A0] = -1i64 as u64;
@@ -132,7 +275,22 @@ impl Emulator {
                 let offset = self.x[A1];
                 let whence = self.x[A2];
 
-                match self.file_descriptors.get_mut(&fd) {
+                // the offset of a directory is a cookie from getdents64
+                if let Some(OpenFile::Directory { position, .. }) = self.fds.get_mut(fd) {
+                    match whence {
+                        0 => *position = offset,
+                        _ => self.x[A0] = -22i64 as u64, // EINVAL
+                    }
+                    return Ok(());
+                }
+
+                // devices don't have a position
+                if let Some(OpenFile::Device(_)) = self.fds.get(fd) {
+                    self.x[A0] = 0;
+                    return Ok(());
+                }
+
+                match self.fds.file_mut(fd) {
                     Some(descriptor) => {
                         match whence {
                             // SEEK_SET
@@ -169,47 +327,72 @@ impl Emulator {
 
                 log::info!("Reading {count} bytes from file fd={fd} to addr={buf:x}");
 
-                if let Some(entry) = self.file_descriptors.get_mut(&fd) {
-                    self.x[A0] = self.memory.read_file(entry.into(), buf, count)? as u64;
-                } else {
-                    self.x[A0] = -1i64 as u64;
+                self.x[A0] = match self.read_fd(fd, buf, count)? {
+                    Some(read) => read,
+                    None => -1i64 as u64,
+                };
+            }
+
+            Syscall::Readv => {
+                let fd = self.x[A0] as i64;
+                let iovecs = self.x[A1];
+                let iovcnt = self.x[A2];
+
+                if iovcnt > UIO_MAXIOV {
+                    self.x[A0] = -22i64 as u64; // EINVAL
+                    return Ok(());
                 }
+
+                let mut read = 0;
+                for (ptr, len) in self.read_iovecs(iovecs, iovcnt)? {
+                    let Some(n) = self.read_fd(fd, ptr, len)? else {
+                        read = -9i64 as u64; // EBADF
+                        break;
+                    };
+
+                    read += n;
+                    // the end of the file
+                    if n < len {
+                        break;
+                    }
+                }
+
+                self.x[A0] = read;
             }
 
             Syscall::Write => {
-                let fd = self.x[A0];
-                assert!(fd <= 2);
-
+                let fd = self.x[A0] as i64;
                 let ptr = self.x[A1];
                 let len = self.x[A2];
 
-                log::info!(
-                    "Writing to file={}, addr={:x}, nbytes={}",
-                    self.x[A0],
-                    self.x[A1],
-                    self.x[A2]
-                );
-
-                let s = self.memory.read_string_n(ptr, len)?;
-                self.stdout.push_str(&s);
+                log::info!("Writing to file={fd}, addr={ptr:x}, nbytes={len}");
 
-                self.x[A0] = len;
+                self.x[A0] = match self.write_fd(fd, ptr, len)? {
+                    true => len,
+                    false => -9i64 as u64, // EBADF
+                };
             }
 
             Syscall::Writev => {
-                let fd = self.x[A0];
-                assert!(fd <= 2);
-
+                let fd = self.x[A0] as i64;
                 let iovecs = self.x[A1];
                 let iovcnt = self.x[A2];
 
-                for i in 0..iovcnt {
-                    let ptr = self.memory.load(iovecs + (i * 16))?;
-                    let len = self.memory.load(iovecs + 8 + (i * 16))?;
+                if iovcnt > UIO_MAXIOV {
+                    self.x[A0] = -22i64 as u64; // EINVAL
+                    return Ok(());
+                }
 
-                    let s = self.memory.read_string_n(ptr, len)?;
-                    self.stdout.push_str(&s);
+                let mut written = 0u64;
+                for (ptr, len) in self.read_iovecs(iovecs, iovcnt)? {
+                    if !self.write_fd(fd, ptr, len)? {
+                        written = -9i64 as u64; // EBADF
+                        break;
+                    }
+                    written += len;
                 }
+
+                self.x[A0] = written;
             }
 
             Syscall::Readlinkat => {
@@ -229,13 +412,19 @@ impl Emulator {
             }
 
             Syscall::Exit => {
-                log::info!("Exiting with code {arg}");
-                self.exit_code = Some(arg);
+                log::info!("Hart {} exiting with code {arg}", self.hart_id);
+
+                // on a multi-hart machine exit only stops the calling hart
+                if self.hart_count > 1 {
+                    self.hart_exit_code = Some(arg);
+                } else {
+                    self.exit(arg);
+                }
             }
 
             Syscall::ExitGroup => {
                 log::info!("Exiting with code {arg}");
-                self.exit_code = Some(arg);
+                self.exit(arg);
             }
 
             Syscall::SetTidAddress => {
@@ -262,11 +451,29 @@ impl Emulator {
             }
 
             Syscall::ClockGettime => {
-                // noop
+                self.x[A0] = self.clock_gettime()?;
+            }
+
+            Syscall::Nanosleep => {
+                self.x[A0] = self.nanosleep(0, self.x[A0])?;
+            }
+
+            Syscall::ClockNanosleep => {
+                self.x[A0] = self.nanosleep(self.x[A1], self.x[A2])?;
             }
 
             Syscall::Tgkill => {
-                self.x[A0] = -1i64 as u64;
+                let (tgid, tid, sig) = (self.x[A0], self.x[A1], self.x[A2]);
+
+                // the main thread is the only one
+                self.x[A0] = if sig > MAX_SIGNAL {
+                    -22i64 as u64 // EINVAL
+                } else if tgid != self.pid || tid != self.pid {
+                    -3i64 as u64 // ESRCH
+                } else {
+                    self.raise(sig);
+                    0
+                };
             }
 
             Syscall::RtSigaction => {
@@ -278,11 +485,37 @@ impl Emulator {
             }
 
             Syscall::Getpid => {
-                self.x[A0] = 0;
+                self.x[A0] = self.pid;
+            }
+
+            Syscall::Getppid => {
+                self.x[A0] = self.ppid;
+            }
+
+            Syscall::Kill => {
+                let pid = self.x[A0] as i64;
+                let sig = self.x[A1];
+
+                // 0 and -1 are the process group and every process, which only contain this one
+                let is_self = pid == self.pid as i64 || pid == 0 || pid == -1;
+                let exists = is_self || pid > 0 && self.is_zombie(pid as u64);
+
+                self.x[A0] = if sig > MAX_SIGNAL {
+                    -22i64 as u64 // EINVAL
+                } else if !exists {
+                    -3i64 as u64 // ESRCH
+                } else {
+                    // signalling a zombie has no effect
+                    if is_self {
+                        self.raise(sig);
+                    }
+                    0
+                };
             }
 
+            // the main thread's tid is the pid
             Syscall::Gettid => {
-                self.x[A0] = 0;
+                self.x[A0] = self.pid;
             }
 
             Syscall::Brk => {
@@ -293,17 +526,27 @@ impl Emulator {
                     "Allocated {} bytes of memory to addr=0x{addr_before:x}",
                     self.x[A0] - addr_before
                 );
+
+                if self.x[A0] != addr_before {
+                    let end = self.x[A0];
+                    self.record_event(EventCategory::Mmap, Event::Brk { end });
+                }
             }
 
             Syscall::Munmap => {
-                // who needs to free memory
-                self.x[A0] = 0;
+                let (addr, len) = (arg, self.x[A1]);
+                if self.memory.munmap(addr, len) {
+                    self.x[A0] = 0;
+                    self.record_event(EventCategory::Mmap, Event::Munmap { addr, len });
+                } else {
+                    self.x[A0] = -22i64 as u64; // EINVAL
+                }
             }
 
             Syscall::Mmap => {
                 let addr = self.x[A0];
                 let len = self.x[A1];
-                let _prot = self.x[A2];
+                let prot = self.x[A2];
                 let flags = self.x[A3];
                 let fd = self.x[A4] as i64;
                 let offset = self.x[A5];
@@ -313,22 +556,96 @@ impl Emulator {
                     fd as i64
                 );
 
-                if fd == -1 {
-                    // Only give address if MMAP_FIXED
-                    if (flags & 0x10) != 0 {
-                        self.x[A0] = self.memory.mmap(addr, len) as u64;
+                // only give the address if it's required
+                let addr = if flags & (MAP_FIXED | MAP_FIXED_NOREPLACE) != 0 {
+                    addr
+                } else {
+                    0
+                };
+
+                // mapping /dev/zero is how anonymous memory was asked for before MAP_ANONYMOUS
+                let zero = matches!(self.fds.get(fd), Some(OpenFile::Device(Device::Zero)));
+                let mapped = if let Some(error) = self.mmap_fault() {
+                    Some(error as i64)
+                } else if flags & MAP_FIXED_NOREPLACE != 0 && self.memory.is_mapped(addr, len) {
+                    Some(-17) // EEXIST
+                } else if flags & MAP_ANONYMOUS != 0 || fd == -1 || zero {
+                    Some(self.memory.mmap(addr, len))
+                } else if let Some(descriptor) = self.fds.file(fd) {
+                    Some(self.memory.mmap_file(descriptor, addr, offset, len)?)
+                } else {
+                    None
+                };
+                self.x[A0] = match mapped {
+                    Some(-1) => -12i64 as u64, // ENOMEM
+                    Some(addr) => addr as u64,
+                    None => -9i64 as u64, // EBADF
+                };
+
+                if self.x[A0] as i64 >= 0 {
+                    let addr = self.x[A0];
+                    self.memory.protect(addr, len, prot)?;
+                    self.record_event(EventCategory::Mmap, Event::Mmap { addr, len });
+                }
+            }
+
+            Syscall::Mremap => {
+                let addr = self.x[A0];
+                let old_len = self.x[A1];
+                let new_len = self.x[A2];
+                let flags = self.x[A3];
+
+                if new_len == 0 || range_end(addr, new_len).is_none() {
+                    self.x[A0] = -22i64 as u64; // EINVAL
+                } else {
+                    let new_addr =
+                        self.memory
+                            .mremap(addr, old_len, new_len, flags & MREMAP_MAYMOVE != 0);
+                    if new_addr < 0 {
+                        self.x[A0] = -12i64 as u64; // ENOMEM
                     } else {
-                        self.x[A0] = self.memory.mmap(0, len) as u64;
+                        self.x[A0] = new_addr as u64;
+                        if new_addr as u64 != addr {
+                            self.record_event(
+                                EventCategory::Mmap,
+                                Event::Munmap { addr, len: old_len },
+                            );
+                        }
+                        self.record_event(
+                            EventCategory::Mmap,
+                            Event::Mmap {
+                                addr: new_addr as u64,
+                                len: new_len,
+                            },
+                        );
                     }
-                } else if let Some(descriptor) = self.file_descriptors.get_mut(&fd) {
-                    self.x[A0] = self.memory.mmap_file(descriptor, addr, offset, len)? as u64;
-                } else {
-                    self.x[A0] = -1i64 as u64;
                 }
             }
 
+            Syscall::Madvise => {
+                let addr = self.x[A0];
+                let len = self.x[A1];
+                let advice = self.x[A2];
+
+                // the rest is only advice
+                self.x[A0] = match advice {
+                    MADV_DONTNEED => match self.memory.discard(addr, len) {
+                        Ok(()) => 0,
+                        Err(_) => -12i64 as u64, // ENOMEM
+                    },
+                    _ => 0,
+                };
+            }
+
             Syscall::Mprotect => {
-                self.x[A0] = 0;
+                let addr = self.x[A0];
+                let len = self.x[A1];
+                let prot = self.x[A2];
+
+                self.x[A0] = match self.memory.protect(addr, len, prot) {
+                    Ok(()) => 0,
+                    Err(_) => -12i64 as u64, // ENOMEM
+                };
             }
 
             Syscall::Prlimit64 => {
@@ -343,6 +660,7 @@ impl Emulator {
                 for i in buf..(buf + buflen) {
                     self.memory.store::<u8>(i, 0xff)?;
                 }
+                self.taint_input(TaintSource::Getrandom, buf, buflen, 0);
 
                 self.x[A0] = buflen;
             }
@@ -361,10 +679,796 @@ impl Emulator {
                     self.x[A0] = 0;
                 }
             }
+            Syscall::Statx => {
+                let fd = self.x[A0] as i64;
+                let pathname = self.memory.read_string_n(self.x[A1], 512)?;
+                let flags = self.x[A2];
+                let statxbuf = self.x[A4];
+
+                let kind = if pathname.is_empty() && flags & AT_EMPTY_PATH != 0 {
+                    match self.fds.get(fd) {
+                        Some(OpenFile::File(file)) => {
+                            Some((StatKind::File, file.data.len() as u64))
+                        }
+                        Some(OpenFile::Directory { .. }) => Some((StatKind::Directory, 0)),
+                        Some(_) => Some((StatKind::CharDevice, 0)),
+                        None => {
+                            self.x[A0] = -9i64 as u64; // EBADF
+                            return Ok(());
+                        }
+                    }
+                } else {
+                    let Some(path) = self.resolve_path(fd, &pathname) else {
+                        self.x[A0] = -9i64 as u64; // EBADF
+                        return Ok(());
+                    };
+                    self.vfs.metadata(&path).map(|(kind, size)| match kind {
+                        FileKind::File => (StatKind::File, size),
+                        FileKind::Directory => (StatKind::Directory, 0),
+                        FileKind::CharDevice => (StatKind::CharDevice, 0),
+                    })
+                };
+
+                match kind {
+                    Some((kind, size)) => {
+                        self.write_statx(statxbuf, kind, size)?;
+                        self.x[A0] = 0;
+                    }
+                    None => self.x[A0] = -2i64 as u64, // ENOENT
+                }
+            }
+            Syscall::Getdents64 => {
+                let fd = self.x[A0] as i64;
+                let dirp = self.x[A1];
+                let count = self.x[A2];
+
+                self.x[A0] = match self.fds.get(fd) {
+                    Some(OpenFile::Directory { .. }) => self.write_dirents(fd, dirp, count)?,
+                    Some(_) => -20i64 as u64, // ENOTDIR
+                    None => -9i64 as u64,     // EBADF
+                };
+            }
+            Syscall::SchedGetaffinity => {
+                let _pid = self.x[A0];
+                let cpusetsize = self.x[A1];
+                let mask_ptr = self.x[A2];
+
+                if cpusetsize < 8 {
+                    self.x[A0] = -22i64 as u64; // EINVAL
+                } else {
+                    // every hart is available
+                    let mask = u64::MAX >> (64 - self.hart_count.clamp(1, 64));
+                    self.memory.store(mask_ptr, mask)?;
+                    self.x[A0] = 8;
+                }
+            }
             Syscall::SchedYield => {
                 self.x[A0] = 0;
             }
+
+            Syscall::Clone => {
+                let flags = self.x[A0];
+                let stack = self.x[A1];
+
+                // threads need a hart each, see `Machine`
+                if flags & CLONE_THREAD != 0 {
+                    self.x[A0] = -38i64 as u64; // ENOSYS
+                } else {
+                    self.x[A0] = self.run_child(stack);
+                }
+            }
+
+            // glibc falls back to clone
+            Syscall::Clone3 => {
+                self.x[A0] = -38i64 as u64; // ENOSYS
+            }
+
+            Syscall::Execve => {
+                let path = self.memory.read_string_n(self.x[A0], 512)?;
+                log::info!("Executing {path}");
+
+                let data = match self.vfs.lookup(&path) {
+                    Some(VfsNode::File(data)) => data,
+                    Some(VfsNode::Directory(_) | VfsNode::Device(_)) => {
+                        self.x[A0] = -13i64 as u64; // EACCES
+                        return Ok(());
+                    }
+                    None => {
+                        self.x[A0] = -2i64 as u64; // ENOENT
+                        return Ok(());
+                    }
+                };
+
+                match self.exec(&data) {
+                    // execution continues after the ecall, at the entry point
+                    Ok(()) => self.pc -= 4,
+                    Err(RVError::InvalidFileType) => self.x[A0] = -8i64 as u64, // ENOEXEC
+                    Err(e) => return Err(e),
+                }
+            }
+
+            Syscall::RiscvFlushIcache => {
+                let flags = self.x[A2];
+
+                // every hart shares the caches, so flushing only the local one is the same thing
+                if flags & !SYS_RISCV_FLUSH_ICACHE_LOCAL != 0 {
+                    self.x[A0] = -22i64 as u64; // EINVAL
+                } else {
+                    self.flush_icache();
+                    self.x[A0] = 0;
+                }
+            }
+
+            Syscall::Uname => {
+                self.x[A0] = self.uname()?;
+            }
+
+            Syscall::Sysinfo => {
+                self.x[A0] = self.sysinfo()?;
+            }
+
+            Syscall::Prctl => {
+                self.x[A0] = self.prctl()?;
+            }
+
+            // glibc goes without restartable sequences, and asks for the extensions through the
+            // auxv hwcaps instead
+            Syscall::Rseq | Syscall::RiscvHwprobe => {
+                self.x[A0] = -38i64 as u64; // ENOSYS
+            }
+
+            Syscall::Wait4 => {
+                let pid = self.x[A0] as i64;
+                let wstatus = self.x[A1];
+
+                match self.reap_child(pid) {
+                    Some((pid, status)) => {
+                        if wstatus != 0 {
+                            self.memory.store(wstatus, status as u32)?;
+                        }
+                        self.x[A0] = pid;
+                    }
+                    None => self.x[A0] = -10i64 as u64, // ECHILD
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// the last real-time signal
+const MAX_SIGNAL: u64 = 64;
+const CLONE_THREAD: u64 = 0x10000;
+const SYS_RISCV_FLUSH_ICACHE_LOCAL: u64 = 1;
+
+pub(super) const AT_FDCWD: i64 = -100;
+const AT_EMPTY_PATH: u64 = 0x1000;
+
+// d_type in linux_dirent64
+const DT_CHR: u8 = 2;
+const DT_DIR: u8 = 4;
+const DT_REG: u8 = 8;
+
+#[derive(Clone, Copy)]
+enum StatKind {
+    File,
+    Directory,
+    CharDevice,
+}
+
+const MAP_FIXED: u64 = 0x10;
+const MAP_ANONYMOUS: u64 = 0x20;
+const MAP_FIXED_NOREPLACE: u64 = 0x100000;
+const MREMAP_MAYMOVE: u64 = 1;
+const MADV_DONTNEED: u64 = 4;
+// the most bytes read from a device at once, which can return fewer than asked for
+const MAX_DEVICE_READ: u64 = 1 << 20;
+// the most iovecs readv and writev take
+const UIO_MAXIOV: u64 = 1024;
+
+const PR_SET_PDEATHSIG: u64 = 1;
+const PR_GET_PDEATHSIG: u64 = 2;
+const PR_GET_DUMPABLE: u64 = 3;
+const PR_SET_DUMPABLE: u64 = 4;
+const PR_SET_NAME: u64 = 15;
+const PR_GET_NAME: u64 = 16;
+const PR_SET_NO_NEW_PRIVS: u64 = 38;
+const PR_GET_NO_NEW_PRIVS: u64 = 39;
+const PR_SET_THP_DISABLE: u64 = 41;
+const PR_SET_VMA: u64 = 0x53564d41;
+// the size of a thread's name, with its nul
+const TASK_COMM_LEN: usize = 16;
+
+impl Emulator {
+    // prctl(option, arg2, ...), for what libcs and language runtimes ask at startup. Settings are
+    // accepted and ignored, and queries answer like a fresh process.
+    fn prctl(&mut self) -> Result<u64, RVError> {
+        let arg2 = self.x[A1];
+
+        match self.x[A0] {
+            PR_SET_PDEATHSIG | PR_SET_DUMPABLE | PR_SET_NAME | PR_SET_NO_NEW_PRIVS
+            | PR_SET_THP_DISABLE | PR_SET_VMA => Ok(0),
+            PR_GET_PDEATHSIG => {
+                self.memory.store(arg2, 0u32)?;
+                Ok(0)
+            }
+            PR_GET_DUMPABLE => Ok(1),
+            PR_GET_NO_NEW_PRIVS => Ok(0),
+            PR_GET_NAME => {
+                let program = self.args.first().map(String::as_str).unwrap_or_default();
+                let name = program.rsplit('/').next().unwrap_or_default().as_bytes();
+                let len = name.len().min(TASK_COMM_LEN - 1);
+
+                let mut comm = [0; TASK_COMM_LEN];
+                comm[..len].copy_from_slice(&name[..len]);
+                self.memory.write_n(&comm, arg2, TASK_COMM_LEN as u64)?;
+                Ok(0)
+            }
+            option => {
+                log::warn!("unsupported prctl option {option}");
+                Ok(-22i64 as u64) // EINVAL
+            }
+        }
+    }
+
+    // reads up to `count` bytes of `fd` to `buf`, returning how many were read, or None if it
+    // can't be read from
+    fn read_fd(&mut self, fd: i64, buf: u64, count: u64) -> Result<Option<u64>, RVError> {
+        let count = self.short_read(fd, count);
+
+        if let Some(OpenFile::Device(device)) = self.fds.get_mut(fd) {
+            let mut data = vec![0; count.min(MAX_DEVICE_READ) as usize];
+            let read = device.read(&mut data) as u64;
+            let random = matches!(device, Device::Random(_));
+
+            self.memory.write_n(&data, buf, read)?;
+            if random {
+                self.taint_input(TaintSource::Getrandom, buf, read, 0);
+            }
+            return Ok(Some(read));
+        }
+
+        let Some(entry) = self.fds.file_mut(fd) else {
+            return Ok(None);
+        };
+
+        let offset = entry.offset;
+        let read = self.memory.read_file(entry.into(), buf, count)? as u64;
+
+        let source = if fd == 0 {
+            TaintSource::Stdin
+        } else {
+            TaintSource::File
+        };
+        self.taint_input(source, buf, read, offset);
+
+        Ok(Some(read))
+    }
+
+    // the (base, len) pairs of the `iovcnt` iovecs at `iovecs`
+    fn read_iovecs(&self, iovecs: u64, iovcnt: u64) -> Result<Vec<(u64, u64)>, RVError> {
+        Ok(self
+            .memory
+            .read_u64_array(iovecs, iovcnt * 2)?
+            .chunks_exact(2)
+            .map(|iovec| (iovec[0], iovec[1]))
+            .collect())
+    }
+
+    // writes `len` bytes at `ptr` to `fd`, returning false if it can't be written to
+    pub(super) fn write_fd(&mut self, fd: i64, ptr: u64, len: u64) -> Result<bool, RVError> {
+        match self.fds.get_mut(fd) {
+            Some(OpenFile::Stdout) => {
+                let s = self.memory.read_string_n(ptr, len)?;
+                let kept = self.output_room(OutputStream::Stdout, &s);
+                self.taint_output(ptr, kept as u64);
+                self.push_output(OutputStream::Stdout, &s);
+            }
+            Some(OpenFile::Stderr) => {
+                let s = self.memory.read_string_n(ptr, len)?;
+                self.push_output(OutputStream::Stderr, &s);
+            }
+            Some(OpenFile::Sink(data)) => data.extend(self.memory.read_n(ptr, len)?),
+            Some(OpenFile::Device(_)) => {}
+            Some(OpenFile::File(_) | OpenFile::Directory { .. }) | None => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    // the absolute path `path` refers to, relative to the directory `dirfd` if it's relative.
+    // None if `dirfd` isn't a directory.
+    pub(super) fn resolve_path(&self, dirfd: i64, path: &str) -> Option<String> {
+        if path.starts_with('/') {
+            return Some(normalize(path));
+        }
+
+        // the working directory is always the root
+        let dir = match self.fds.get(dirfd) {
+            _ if dirfd == AT_FDCWD => "/",
+            Some(OpenFile::Directory { path, .. }) => path,
+            _ => return None,
+        };
+        Some(normalize(&format!("{dir}/{path}")))
+    }
+
+    // fills the linux_dirent64 records of the directory `fd` that fit in the `count` bytes at
+    // `dirp`, starting at its position, and returns how many bytes were written. `.` and `..`
+    // come first, and each record's d_off is the position after it.
+    fn write_dirents(&mut self, fd: i64, dirp: u64, count: u64) -> Result<u64, RVError> {
+        let Some(OpenFile::Directory {
+            entries, position, ..
+        }) = self.fds.get(fd)
+        else {
+            return Ok(-9i64 as u64); // EBADF
+        };
+
+        let dots = [(".", DT_DIR), ("..", DT_DIR)];
+        let records: Vec<(String, u8)> = dots
+            .into_iter()
+            .map(|(name, d_type)| (String::from(name), d_type))
+            .chain(entries.iter().map(|entry| {
+                let d_type = match entry.kind {
+                    FileKind::File => DT_REG,
+                    FileKind::Directory => DT_DIR,
+                    FileKind::CharDevice => DT_CHR,
+                };
+                (entry.name.clone(), d_type)
+            }))
+            .skip(*position as usize)
+            .collect();
+        let mut position = *position;
+
+        let mut written = 0;
+        for (name, d_type) in records {
+            // d_ino, d_off, d_reclen and d_type, then the name and its nul, 8-aligned
+            let reclen = (19 + name.len() as u64 + 1).next_multiple_of(8);
+            if written + reclen > count {
+                if written == 0 {
+                    return Ok(-22i64 as u64); // EINVAL
+                }
+                break;
+            }
+
+            let record = dirp + written;
+            position += 1;
+            self.memory.store(record, position)?; // d_ino
+            self.memory.store(record + 8, position)?;
+            self.memory.store(record + 16, reclen as u16)?;
+            self.memory.store(record + 18, d_type)?;
+            let mut name = name.into_bytes();
+            name.resize((reclen - 19) as usize, 0);
+            self.memory.write_n(&name, record + 19, name.len() as u64)?;
+
+            written += reclen;
+        }
+
+        if let Some(OpenFile::Directory { position: pos, .. }) = self.fds.get_mut(fd) {
+            *pos = position;
+        }
+        Ok(written)
+    }
+
+    // fills the 256 byte struct statx at `statxbuf`
+    fn write_statx(&mut self, statxbuf: u64, kind: StatKind, size: u64) -> Result<(), RVError> {
+        const STATX_BASIC_STATS: u32 = 0x7ff;
+        const BLOCK_SIZE: u64 = 4096;
+
+        let (mode, nlink) = match kind {
+            StatKind::File => (0o100755, 1),
+            StatKind::Directory => (0o040755, 2),
+            StatKind::CharDevice => (0o020620, 1),
+        };
+
+        self.memory.write_n(&[0; 256], statxbuf, 256)?;
+        self.memory.store(statxbuf, STATX_BASIC_STATS)?;
+        self.memory.store(statxbuf + 4, BLOCK_SIZE as u32)?;
+        self.memory.store(statxbuf + 16, nlink as u32)?;
+        self.memory.store(statxbuf + 20, self.auxv.uid as u32)?;
+        self.memory.store(statxbuf + 24, self.auxv.gid as u32)?;
+        self.memory.store(statxbuf + 28, mode as u16)?;
+        self.memory.store(statxbuf + 40, size)?;
+        self.memory.store(statxbuf + 48, size.div_ceil(512))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    use crate::{
+        memory::{Memory, MemoryLayout, PAGE_MASK, PAGE_SIZE},
+        system::{EmulatorBuilder, EventFilter},
+    };
+
+    #[test]
+    fn syscall_log() -> Result<(), RVError> {
+        let mut data = [0u8; 32];
+        data[0..4].copy_from_slice(&0x00000513u32.to_le_bytes()); // li a0, 0
+        data[4..8].copy_from_slice(&0x0ac00893u32.to_le_bytes()); // li a7, 172
+        data[8..12].copy_from_slice(&0x00000073u32.to_le_bytes()); // ecall
+        data[12..16].copy_from_slice(&0x00300513u32.to_le_bytes()); // li a0, 3
+        data[16..20].copy_from_slice(&0x05d00893u32.to_le_bytes()); // li a7, 93
+        data[20..24].copy_from_slice(&0x00000073u32.to_le_bytes()); // ecall
+
+        let mut emulator = Emulator::new(Memory::from_raw(&data));
+        emulator.set_event_filter(EventFilter::NONE.with(EventCategory::Syscall));
+        while emulator.fetch_and_execute()?.is_none() {}
+
+        let log = emulator.events();
+        assert_eq!(log.len(), 2);
+        assert!(
+            matches!(log[0].event, Event::Syscall(ref record) if record.syscall == Syscall::Getpid)
+        );
+        assert_eq!(log[0].inst_count, 2);
+        assert_eq!(log[0].to_string(), "8 getpid() = 0x1");
+        assert_eq!(log[1].to_string(), "14 exit(0x3) = 0x3");
+
+        Ok(())
+    }
+
+    fn call(emulator: &mut Emulator, sc: Syscall, args: &[u64]) -> Result<i64, RVError> {
+        for (reg, arg) in [A0, A1, A2, A3, A4, A5].into_iter().zip(args) {
+            emulator.x[reg] = *arg;
+        }
+        emulator.x[A7] = sc as u64;
+        emulator.syscall()?;
+
+        Ok(emulator.x[A0] as i64)
+    }
+
+    #[test]
+    fn mmap_flags() -> Result<(), RVError> {
+        let mut emulator = Emulator::new(Memory::from_raw(&[]));
+        let fixed = MAP_ANONYMOUS | MAP_FIXED_NOREPLACE;
+
+        let addr = call(
+            &mut emulator,
+            Syscall::Mmap,
+            &[0, 2 * PAGE_SIZE, 3, MAP_ANONYMOUS, u64::MAX],
+        )?;
+        assert!(addr > 0);
+        let addr = addr as u64;
+
+        // the end of the mapping is taken, but the page after it isn't
+        let args = [addr + PAGE_SIZE, PAGE_SIZE, 3, fixed, u64::MAX];
+        assert_eq!(call(&mut emulator, Syscall::Mmap, &args)?, -17);
+        let args = [addr + 2 * PAGE_SIZE, PAGE_SIZE, 3, fixed, u64::MAX];
+        assert_eq!(
+            call(&mut emulator, Syscall::Mmap, &args)?,
+            (addr + 2 * PAGE_SIZE) as i64
+        );
+
+        let args = [addr, 3 * PAGE_SIZE, 0, MREMAP_MAYMOVE];
+        assert_eq!(call(&mut emulator, Syscall::Mremap, &args)?, -22);
+        assert_eq!(emulator.memory.load::<u8>(addr + 3 * PAGE_SIZE - 1)?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn huge_lengths() -> Result<(), RVError> {
+        let layouts = [
+            MemoryLayout::Paged,
+            MemoryLayout::Flat {
+                size: 16 * PAGE_SIZE,
+            },
+            MemoryLayout::Cow,
+        ];
+        for layout in layouts {
+            let mut emulator = Emulator::new(Memory::from_raw_with_layout(&[], layout));
+
+            // lengths that wrap around the address space fail instead of overflowing
+            let args = [0, u64::MAX, 3, MAP_ANONYMOUS, u64::MAX];
+            assert_eq!(call(&mut emulator, Syscall::Mmap, &args)?, -12);
+            let args = [
+                u64::MAX - PAGE_MASK,
+                2 * PAGE_SIZE,
+                3,
+                MAP_ANONYMOUS | MAP_FIXED,
+                u64::MAX,
+            ];
+            assert_eq!(call(&mut emulator, Syscall::Mmap, &args)?, -12);
+
+            let args = [0, PAGE_SIZE, 3, MAP_ANONYMOUS, u64::MAX];
+            let addr = call(&mut emulator, Syscall::Mmap, &args)? as u64;
+            assert_eq!(
+                call(&mut emulator, Syscall::Munmap, &[addr, u64::MAX])?,
+                -22
+            );
+            let args = [addr, PAGE_SIZE, u64::MAX, MREMAP_MAYMOVE];
+            assert_eq!(call(&mut emulator, Syscall::Mremap, &args)?, -22);
+            let args = [addr, u64::MAX, 1];
+            assert_eq!(call(&mut emulator, Syscall::Mprotect, &args)?, -12);
+            let args = [addr, u64::MAX, MADV_DONTNEED];
+            assert_eq!(call(&mut emulator, Syscall::Madvise, &args)?, -12);
+
+            // and leave the mapping alone
+            assert_eq!(emulator.memory.load::<u8>(addr)?, 0);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn processes() -> Result<(), RVError> {
+        let mut data = [0u8; 20];
+        data[4..8].copy_from_slice(&0x0ad00893u32.to_le_bytes()); // li a7, 173
+        data[8..12].copy_from_slice(&0x00000073u32.to_le_bytes()); // ecall
+        data[12..16].copy_from_slice(&0x05d00893u32.to_le_bytes()); // li a7, 93
+        data[16..20].copy_from_slice(&0x00000073u32.to_le_bytes()); // ecall
+
+        let mut emulator = Emulator::new(Memory::from_raw(&data));
+        assert_eq!(call(&mut emulator, Syscall::Getpid, &[])?, 1);
+        assert_eq!(call(&mut emulator, Syscall::Getppid, &[])?, 0);
+
+        // signal 0 only checks that the process exists
+        assert_eq!(call(&mut emulator, Syscall::Kill, &[1, 0])?, 0);
+        assert_eq!(call(&mut emulator, Syscall::Kill, &[0, 0])?, 0);
+        assert_eq!(emulator.exit_code, None);
+        assert_eq!(call(&mut emulator, Syscall::Kill, &[2, 0])?, -3); // ESRCH
+        assert_eq!(call(&mut emulator, Syscall::Kill, &[1, 65])?, -22); // EINVAL
+
+        let any = -1i64 as u64;
+        assert_eq!(call(&mut emulator, Syscall::Wait4, &[any, 0, 0, 0])?, -10); // ECHILD
+
+        // the child exits with its parent's pid, and stays a zombie until it's waited for
+        let pid = emulator.run_child(0);
+        assert!(emulator.children().eq([(pid, 1 << 8)]));
+        assert_eq!(call(&mut emulator, Syscall::Kill, &[pid, 0])?, 0);
+        assert_eq!(
+            call(&mut emulator, Syscall::Wait4, &[any, 0, 0, 0])?,
+            pid as i64
+        );
+        assert_eq!(emulator.children().count(), 0);
+        assert_eq!(call(&mut emulator, Syscall::Kill, &[pid, 0])?, -3);
+        assert_eq!(call(&mut emulator, Syscall::Wait4, &[pid, 0, 0, 0])?, -10);
+
+        Ok(())
+    }
+
+    #[test]
+    fn preopened_fds() -> Result<(), RVError> {
+        let program: [u32; 15] = [
+            0x00300513, // li a0, 3
+            0x10000593, // li a1, 0x100
+            0x00300613, // li a2, 3
+            0x03f00893, // li a7, 63
+            0x00000073, // ecall
+            0x00400513, // li a0, 4
+            0x10000593, // li a1, 0x100
+            0x00300613, // li a2, 3
+            0x04000893, // li a7, 64
+            0x00000073, // ecall
+            0x00200513, // li a0, 2
+            0x04000893, // li a7, 64
+            0x00000073, // ecall
+            0x05d00893, // li a7, 93
+            0x00000073, // ecall
+        ];
+        let mut data = [0u8; 0x200];
+        for (i, inst) in program.iter().enumerate() {
+            data[i * 4..i * 4 + 4].copy_from_slice(&inst.to_le_bytes());
+        }
+
+        let mut fds = FdTable::new();
+        fds.insert(3, OpenFile::File(FileDescriptor::new(*b"xyz")));
+        fds.insert(4, OpenFile::Sink(Vec::new()));
+        let mut emulator = EmulatorBuilder::new(Memory::from_raw(&data))
+            .fds(fds)
+            .build()?;
+
+        assert_eq!(emulator.run(false)?, 3);
+        assert_eq!(emulator.fds().file(3).unwrap().offset, 3);
+        assert_eq!(emulator.fds().sink(4), Some(&b"xyz"[..]));
+        assert_eq!(emulator.stderr, "xyz");
+        assert_eq!(emulator.stdout, "");
+
+        Ok(())
+    }
+
+    #[test]
+    fn directories() -> Result<(), RVError> {
+        let mut emulator = Emulator::new(Memory::from_raw(&[0u8; 0x400]));
+        emulator.add_file("/etc/passwd", *b"root:x:0:0");
+        emulator.add_file("/etc/ssl/cert.pem", *b"");
+        emulator.memory.write_n(b"/etc\0", 0x100, 5)?;
+        emulator.memory.write_n(b"passwd\0", 0x110, 7)?;
+
+        let syscall = |emulator: &mut Emulator, sc: Syscall, args: [u64; 5]| {
+            for (reg, arg) in [A0, A1, A2, A3, A4].into_iter().zip(args) {
+                emulator.x[reg] = arg;
+            }
+            emulator.emulate_syscall(sc).map(|()| emulator.x[A0])
+        };
+
+        let fd = syscall(
+            &mut emulator,
+            Syscall::Openat,
+            [AT_FDCWD as u64, 0x100, 0, 0, 0],
+        )?;
+        assert!(emulator.fds().get(fd as i64).is_some());
+
+        // ".", ".." and "passwd" fit in 100 bytes, "ssl" comes on the next call
+        assert_eq!(
+            syscall(&mut emulator, Syscall::Getdents64, [fd, 0x200, 100, 0, 0])?,
+            80
+        );
+        assert_eq!(emulator.memory.load::<u64>(0x200 + 48 + 8)?, 3); // d_off
+        assert_eq!(emulator.memory.load::<u16>(0x200 + 48 + 16)?, 32); // d_reclen
+        assert_eq!(emulator.memory.load::<u8>(0x200 + 48 + 18)?, DT_REG);
+        assert_eq!(emulator.memory.read_string_n(0x200 + 48 + 19, 8)?, "passwd");
+        assert_eq!(
+            syscall(&mut emulator, Syscall::Getdents64, [fd, 0x200, 100, 0, 0])?,
+            24
+        );
+        assert_eq!(emulator.memory.load::<u8>(0x200 + 18)?, DT_DIR);
+        assert_eq!(
+            syscall(&mut emulator, Syscall::Getdents64, [fd, 0x200, 100, 0, 0])?,
+            0
+        );
+
+        // seeking to a cookie lists from there again
+        syscall(&mut emulator, Syscall::Lseek, [fd, 2, 0, 0, 0])?;
+        assert_eq!(
+            syscall(&mut emulator, Syscall::Getdents64, [fd, 0x200, 8, 0, 0])?,
+            -22i64 as u64
+        );
+        assert_eq!(
+            syscall(&mut emulator, Syscall::Getdents64, [fd, 0x200, 100, 0, 0])?,
+            56
+        );
+
+        // relative to the directory
+        assert_eq!(
+            syscall(&mut emulator, Syscall::Statx, [fd, 0x110, 0, 0x7ff, 0x300])?,
+            0
+        );
+        assert_eq!(emulator.memory.load::<u16>(0x300 + 28)?, 0o100755);
+        assert_eq!(emulator.memory.load::<u64>(0x300 + 40)?, 10);
+
+        emulator.memory.write_n(b"\0", 0x110, 1)?;
+        assert_eq!(
+            syscall(
+                &mut emulator,
+                Syscall::Statx,
+                [fd, 0x110, AT_EMPTY_PATH, 0x7ff, 0x300]
+            )?,
+            0
+        );
+        assert_eq!(emulator.memory.load::<u16>(0x300 + 28)?, 0o040755);
+
+        emulator.memory.write_n(b"/usr\0", 0x110, 5)?;
+        assert_eq!(
+            syscall(&mut emulator, Syscall::Statx, [fd, 0x110, 0, 0x7ff, 0x300])?,
+            -2i64 as u64
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn startup_syscalls() -> Result<(), RVError> {
+        let mut emulator = EmulatorBuilder::new(Memory::from_raw(&[0u8; 0x200]))
+            .args(["/usr/bin/a-long-program-name"])
+            .build()?;
+
+        let syscall = |emulator: &mut Emulator, sc: Syscall, args: [u64; 5]| {
+            for (reg, arg) in [A0, A1, A2, A3, A4].into_iter().zip(args) {
+                emulator.x[reg] = arg;
+            }
+            emulator.emulate_syscall(sc).map(|()| emulator.x[A0])
+        };
+
+        assert_eq!(
+            syscall(&mut emulator, Syscall::Rseq, [0x100, 32, 0, 0, 0])?,
+            -38i64 as u64
+        );
+        assert_eq!(
+            syscall(
+                &mut emulator,
+                Syscall::Prctl,
+                [PR_SET_VMA, 0, 0x100, 0x1000, 0]
+            )?,
+            0
+        );
+        assert_eq!(
+            syscall(&mut emulator, Syscall::Prctl, [PR_GET_DUMPABLE, 0, 0, 0, 0])?,
+            1
+        );
+        assert_eq!(
+            syscall(&mut emulator, Syscall::Prctl, [PR_GET_NAME, 0x100, 0, 0, 0])?,
+            0
+        );
+        assert_eq!(emulator.memory.read_string_n(0x100, 16)?, "a-long-program-");
+        assert_eq!(
+            syscall(&mut emulator, Syscall::Prctl, [0x1234, 0, 0, 0, 0])?,
+            -22i64 as u64
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn vectored_io() -> Result<(), RVError> {
+        let mut emulator = EmulatorBuilder::new(Memory::from_raw(&[0u8; 0x400]))
+            .stdin(*b"hello world")
+            .build()?;
+        for (i, value) in [0x200, 5, 0x210, 100].into_iter().enumerate() {
+            emulator.memory.store::<u64>(0x100 + 8 * i as u64, value)?;
+        }
+
+        let syscall = |emulator: &mut Emulator, sc: Syscall, args: [u64; 3]| {
+            for (reg, arg) in [A0, A1, A2].into_iter().zip(args) {
+                emulator.x[reg] = arg;
+            }
+            emulator.emulate_syscall(sc).map(|()| emulator.x[A0])
+        };
+
+        // the second buffer is only partly filled before the end of the file
+        assert_eq!(syscall(&mut emulator, Syscall::Readv, [0, 0x100, 2])?, 11);
+        assert_eq!(emulator.memory.read_string_n(0x200, 16)?, "hello");
+        assert_eq!(emulator.memory.read_string_n(0x210, 16)?, " world");
+        assert_eq!(syscall(&mut emulator, Syscall::Readv, [0, 0x100, 2])?, 0);
+
+        emulator.memory.store::<u64>(0x118, 6)?;
+        assert_eq!(syscall(&mut emulator, Syscall::Writev, [1, 0x100, 2])?, 11);
+        assert_eq!(emulator.stdout, "hello world");
+
+        assert_eq!(
+            syscall(&mut emulator, Syscall::Writev, [9, 0x100, 2])?,
+            -9i64 as u64
+        );
+        assert_eq!(
+            syscall(&mut emulator, Syscall::Readv, [0, 0x100, UIO_MAXIOV + 1])?,
+            -22i64 as u64
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn devices() -> Result<(), RVError> {
+        let mut emulator = Emulator::new(Memory::from_raw(&[0xffu8; 0x400]));
+        emulator.memory.write_n(b"/dev/urandom\0", 0x100, 13)?;
+        emulator.memory.write_n(b"/dev/null\0", 0x110, 10)?;
+        emulator.memory.write_n(b"/dev/zero\0", 0x120, 10)?;
+
+        let syscall = |emulator: &mut Emulator, sc: Syscall, args: [u64; 3]| {
+            for (reg, arg) in [A0, A1, A2].into_iter().zip(args) {
+                emulator.x[reg] = arg;
+            }
+            emulator.emulate_syscall(sc).map(|()| emulator.x[A0])
+        };
+        let open = |emulator: &mut Emulator, path: u64| {
+            syscall(emulator, Syscall::Openat, [AT_FDCWD as u64, path, 0])
+        };
+
+        // each open starts from the seed
+        for buf in [0x200, 0x210] {
+            let fd = open(&mut emulator, 0x100)?;
+            assert_eq!(syscall(&mut emulator, Syscall::Read, [fd, buf, 16])?, 16);
         }
+        assert_eq!(
+            emulator.memory.read_n(0x200, 16)?,
+            emulator.memory.read_n(0x210, 16)?
+        );
+
+        let null = open(&mut emulator, 0x110)?;
+        assert_eq!(
+            syscall(&mut emulator, Syscall::Write, [null, 0x200, 16])?,
+            16
+        );
+        assert_eq!(syscall(&mut emulator, Syscall::Read, [null, 0x200, 16])?, 0);
+
+        let zero = open(&mut emulator, 0x120)?;
+        assert_eq!(syscall(&mut emulator, Syscall::Read, [zero, 0x300, 8])?, 8);
+        assert_eq!(emulator.memory.load::<u64>(0x300)?, 0);
+        assert_eq!(emulator.memory.load::<u8>(0x308)?, 0xff);
 
         Ok(())
     }
@@ -0,0 +1,700 @@
+// byte granular taint tracking. Bytes read from the chosen sources are labeled with them, and the
+// labels follow the data through registers and memory, one instruction at a time. Only data flow
+// is tracked: a value chosen by a tainted branch, or loaded through a tainted pointer, is clean.
+
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec::Vec};
+use core::{
+    fmt::Write,
+    ops::{BitOr, BitOrAssign},
+};
+
+use super::{hle::Routine, memcheck::memory_access, Emulator};
+use crate::{
+    instruction::Inst,
+    memory::{PAGE_MASK, PAGE_SIZE},
+    register::*,
+    sync::Lock,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TaintSource {
+    /// Reads from standard input
+    Stdin,
+    /// Reads from any other file
+    File,
+    /// Bytes returned by the getrandom syscall, or read from a random [`Device`]
+    ///
+    /// [`Device`]: crate::files::Device
+    Getrandom,
+}
+
+impl TaintSource {
+    pub const ALL: [TaintSource; 3] = [
+        TaintSource::Stdin,
+        TaintSource::File,
+        TaintSource::Getrandom,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TaintSource::Stdin => "stdin",
+            TaintSource::File => "file",
+            TaintSource::Getrandom => "getrandom",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<TaintSource> {
+        TaintSource::ALL
+            .into_iter()
+            .find(|source| source.name() == name)
+    }
+}
+
+/// The sources a value was influenced by
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TaintSet(u8);
+
+impl TaintSet {
+    pub const EMPTY: TaintSet = TaintSet(0);
+    pub const ALL: TaintSet = TaintSet(0b111);
+
+    pub fn with(self, source: TaintSource) -> TaintSet {
+        TaintSet(self.0 | 1 << source as u8)
+    }
+
+    pub fn contains(self, source: TaintSource) -> bool {
+        self.0 & 1 << source as u8 != 0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn sources(self) -> impl Iterator<Item = TaintSource> {
+        TaintSource::ALL
+            .into_iter()
+            .filter(move |&source| self.contains(source))
+    }
+
+    /// Parses a comma separated list of source names, like `stdin,getrandom`, or `all`
+    pub fn parse(list: &str) -> Option<TaintSet> {
+        if list == "all" {
+            return Some(TaintSet::ALL);
+        }
+
+        list.split(',').try_fold(TaintSet::EMPTY, |set, name| {
+            Some(set.with(TaintSource::from_name(name.trim())?))
+        })
+    }
+}
+
+impl BitOr for TaintSet {
+    type Output = TaintSet;
+
+    fn bitor(self, rhs: TaintSet) -> TaintSet {
+        TaintSet(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for TaintSet {
+    fn bitor_assign(&mut self, rhs: TaintSet) {
+        self.0 |= rhs.0;
+    }
+}
+
+// what a byte or register was influenced by: its sources, and a range of stdin offsets covering
+// every byte of stdin it came from, which is empty if there are none
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Label {
+    set: TaintSet,
+    stdin: (u32, u32),
+}
+
+impl Label {
+    const EMPTY: Label = Label {
+        set: TaintSet::EMPTY,
+        stdin: (0, 0),
+    };
+
+    fn stdin(offset: u64) -> Label {
+        Label {
+            set: TaintSet::EMPTY.with(TaintSource::Stdin),
+            stdin: (offset as u32, offset as u32 + 1),
+        }
+    }
+}
+
+impl BitOr for Label {
+    type Output = Label;
+
+    fn bitor(self, rhs: Label) -> Label {
+        let stdin = match (self.stdin, rhs.stdin) {
+            ((start, end), other) | (other, (start, end)) if start == end => other,
+            ((a, b), (c, d)) => (a.min(c), b.max(d)),
+        };
+
+        Label {
+            set: self.set | rhs.set,
+            stdin,
+        }
+    }
+}
+
+// see `Emulator::enable_branch_input_log`
+pub(super) type BranchInputLog = Arc<Lock<dyn Write + Send>>;
+
+/// Bytes written to standard output or error that were influenced by a source
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TaintedOutput {
+    /// The offset of the first byte in [`Emulator::stdout`]
+    pub offset: u64,
+    pub len: u64,
+    pub taint: TaintSet,
+}
+
+/// A conditional branch whose operands were influenced by a source
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TaintedBranch {
+    pub pc: u64,
+    pub taint: TaintSet,
+    /// The number of times it was executed with tainted operands
+    pub count: u64,
+}
+
+/// The error that stopped execution, if it was an access through a tainted address
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TaintedFault {
+    pub pc: u64,
+    pub addr: u64,
+    pub taint: TaintSet,
+}
+
+/// See [`Emulator::set_taint_sources`]
+#[derive(Clone, Debug, Default)]
+pub struct TaintTracker {
+    sources: TaintSet,
+    x: [Label; 32],
+    f: [Label; 32],
+    // the taint of the target of the last jump, in case it faults
+    pc: Label,
+    memory: BTreeMap<u64, Box<[Label; PAGE_SIZE as usize]>>,
+
+    pub outputs: Vec<TaintedOutput>,
+    /// Keyed by pc
+    pub branches: BTreeMap<u64, TaintedBranch>,
+    pub fault: Option<TaintedFault>,
+}
+
+impl TaintTracker {
+    // forgets which registers and memory are tainted, for a new program. What was already
+    // recorded is kept.
+    pub(super) fn clear_labels(&mut self) {
+        self.x = [Label::default(); 32];
+        self.f = [Label::default(); 32];
+        self.pc = Label::default();
+        self.memory.clear();
+    }
+
+    pub fn reg(&self, reg: Reg) -> TaintSet {
+        self.reg_label(reg).set
+    }
+
+    pub fn freg(&self, reg: FReg) -> TaintSet {
+        self.freg_label(reg).set
+    }
+
+    /// The sources any of the `len` bytes at `addr` were influenced by
+    pub fn memory(&self, addr: u64, len: u64) -> TaintSet {
+        self.memory_label(addr, len).set
+    }
+
+    /// The sources the byte at `offset` in [`Emulator::stdout`] was influenced by
+    pub fn output(&self, offset: u64) -> TaintSet {
+        let i = self
+            .outputs
+            .partition_point(|output| output.offset <= offset);
+
+        match i.checked_sub(1).map(|i| self.outputs[i]) {
+            Some(output) if offset < output.offset + output.len => output.taint,
+            _ => TaintSet::EMPTY,
+        }
+    }
+
+    fn reg_label(&self, reg: Reg) -> Label {
+        self.x[reg.0 as usize]
+    }
+
+    fn freg_label(&self, reg: FReg) -> Label {
+        self.f[reg.0 as usize]
+    }
+
+    fn memory_label(&self, addr: u64, len: u64) -> Label {
+        (addr..addr.wrapping_add(len))
+            .filter_map(|addr| {
+                let page = self.memory.get(&(addr & !PAGE_MASK))?;
+                Some(page[(addr & PAGE_MASK) as usize])
+            })
+            .fold(Label::EMPTY, BitOr::bitor)
+    }
+
+    fn set_reg(&mut self, reg: Reg, label: Label) {
+        if reg.0 != 0 {
+            self.x[reg.0 as usize] = label;
+        }
+    }
+
+    fn set_memory(&mut self, addr: u64, len: u64, label: Label) {
+        for addr in addr..addr.wrapping_add(len) {
+            self.set_byte(addr, label);
+        }
+    }
+
+    fn set_byte(&mut self, addr: u64, label: Label) {
+        let page = addr & !PAGE_MASK;
+
+        if label == Label::EMPTY {
+            if let Some(page) = self.memory.get_mut(&page) {
+                page[(addr & PAGE_MASK) as usize] = label;
+            }
+        } else {
+            let page = self
+                .memory
+                .entry(page)
+                .or_insert_with(|| Box::new([Label::EMPTY; PAGE_SIZE as usize]));
+            page[(addr & PAGE_MASK) as usize] = label;
+        }
+    }
+}
+
+impl Emulator {
+    /// Labels bytes read from `sources` and tracks what they influence, see
+    /// [`Emulator::taint`]. Every instruction has to be followed, so the jit isn't used while it's
+    /// enabled. Tracking nothing, the default, disables it.
+    pub fn set_taint_sources(&mut self, sources: TaintSet) {
+        self.taint = (!sources.is_empty()).then(|| TaintTracker {
+            sources,
+            ..TaintTracker::default()
+        });
+    }
+
+    pub fn taint(&self) -> Option<&TaintTracker> {
+        self.taint.as_ref()
+    }
+
+    /// Writes a line of JSON to `writer` for every conditional branch whose operands depended on
+    /// stdin, with the range of stdin offsets they came from and whether it was taken, like
+    /// `{"inst":120,"pc":69920,"taken":true,"stdin":[0,4]}`. Flipping a branch takes changing
+    /// some of the bytes in its range. Starts tracking stdin if it isn't already.
+    pub fn enable_branch_input_log(&mut self, writer: impl Write + Send + 'static) {
+        let sources = self
+            .taint
+            .as_ref()
+            .map_or(TaintSet::EMPTY, |taint| taint.sources);
+        if !sources.contains(TaintSource::Stdin) {
+            self.set_taint_sources(sources.with(TaintSource::Stdin));
+        }
+
+        self.branch_input_log = Some(Arc::new(Lock::new(writer)));
+    }
+
+    fn log_branch_input(&mut self, taken: bool, label: Label) {
+        let (start, end) = label.stdin;
+        let Some(ref log) = self.branch_input_log else {
+            return;
+        };
+        if start == end {
+            return;
+        }
+
+        let written = writeln!(
+            log.lock(),
+            "{{\"inst\":{},\"pc\":{},\"taken\":{taken},\"stdin\":[{start},{end}]}}",
+            self.inst_counter,
+            self.pc
+        );
+        if written.is_err() {
+            log::warn!("Could not write to the branch input log, disabling it");
+            self.branch_input_log = None;
+        }
+    }
+
+    // labels `len` bytes at `addr` just written by a syscall, clearing them if the source isn't
+    // tracked. `offset` is where the bytes were read from in their file.
+    pub(super) fn taint_input(&mut self, source: TaintSource, addr: u64, len: u64, offset: u64) {
+        let Some(ref mut taint) = self.taint else {
+            return;
+        };
+
+        for i in 0..len {
+            let label = match source {
+                _ if !taint.sources.contains(source) => Label::EMPTY,
+                TaintSource::Stdin => Label::stdin(offset + i),
+                source => Label {
+                    set: TaintSet::EMPTY.with(source),
+                    ..Label::EMPTY
+                },
+            };
+
+            taint.set_byte(addr.wrapping_add(i), label);
+        }
+    }
+
+    // records the taint of `len` bytes at `addr` about to be appended to stdout
+    pub(super) fn taint_output(&mut self, addr: u64, len: u64) {
+        let offset = self.stdout.len() as u64;
+        let Some(ref mut taint) = self.taint else {
+            return;
+        };
+
+        // runs of bytes with the same taint are recorded together
+        for i in 0..len {
+            let set = taint.memory(addr + i, 1);
+            if set.is_empty() {
+                continue;
+            }
+
+            match taint.outputs.last_mut() {
+                Some(output) if output.offset + output.len == offset + i && output.taint == set => {
+                    output.len += 1
+                }
+                _ => taint.outputs.push(TaintedOutput {
+                    offset: offset + i,
+                    len: 1,
+                    taint: set,
+                }),
+            }
+        }
+    }
+
+    // the routine was performed natively, so its data flow is applied all at once
+    pub(super) fn taint_routine(&mut self, routine: Routine, dst: u64, arg: u64, len: u64) {
+        let Some(ref mut taint) = self.taint else {
+            return;
+        };
+
+        match routine {
+            Routine::Memcpy => {
+                for i in 0..len {
+                    let label = taint.memory_label(arg.wrapping_add(i), 1);
+                    taint.set_byte(dst.wrapping_add(i), label);
+                }
+            }
+            Routine::Memset => taint.set_memory(dst, len, taint.reg_label(A1)),
+            // the length depends on every byte up to the terminator
+            Routine::Strlen => {
+                let label = taint.memory_label(dst, len + 1);
+                taint.set_reg(A0, label);
+            }
+        }
+    }
+
+    // follows the data flow of `inst`, before it's executed
+    pub(super) fn propagate_taint(&mut self, inst: Inst) {
+        let pc = self.pc;
+        let access = memory_access(inst)
+            .map(|(base, offset, len, _)| (self.x[base].wrapping_add(offset as u64), len));
+        let Some(ref mut taint) = self.taint else {
+            return;
+        };
+        let (addr, len) = access.unwrap_or_default();
+
+        taint.pc = Label::EMPTY;
+
+        match inst {
+            Inst::Ld { rd, .. }
+            | Inst::Lw { rd, .. }
+            | Inst::Lwu { rd, .. }
+            | Inst::Lhu { rd, .. }
+            | Inst::Lb { rd, .. }
+            | Inst::Lbu { rd, .. }
+            | Inst::Lrw { rd, .. }
+            | Inst::Lrd { rd, .. } => taint.set_reg(rd, taint.memory_label(addr, len)),
+            Inst::Fld { rd, .. } | Inst::Flw { rd, .. } => {
+                taint.f[rd.0 as usize] = taint.memory_label(addr, len)
+            }
+            Inst::Sd { rs2, .. }
+            | Inst::Sw { rs2, .. }
+            | Inst::Sh { rs2, .. }
+            | Inst::Sb { rs2, .. } => taint.set_memory(addr, len, taint.reg_label(rs2)),
+            Inst::Fsd { rs2, .. } | Inst::Fsw { rs2, .. } => {
+                taint.set_memory(addr, len, taint.freg_label(rs2))
+            }
+            // assumes the store succeeds
+            Inst::Scw { rd, rs2, .. } | Inst::Scd { rd, rs2, .. } => {
+                taint.set_memory(addr, len, taint.reg_label(rs2));
+                taint.set_reg(rd, Label::EMPTY);
+            }
+            Inst::Amoswapw { rd, rs2, .. } | Inst::Amoswapd { rd, rs2, .. } => {
+                let old = taint.memory_label(addr, len);
+                taint.set_memory(addr, len, taint.reg_label(rs2));
+                taint.set_reg(rd, old);
+            }
+            Inst::Amoaddw { rd, rs2, .. }
+            | Inst::Amoaddd { rd, rs2, .. }
+            | Inst::Amoorw { rd, rs2, .. }
+            | Inst::Amomaxuw { rd, rs2, .. }
+            | Inst::Amomaxud { rd, rs2, .. } => {
+                let old = taint.memory_label(addr, len);
+                taint.set_memory(addr, len, old | taint.reg_label(rs2));
+                taint.set_reg(rd, old);
+            }
+
+            // x ^ x and x - x don't depend on x
+            Inst::Xor { rd, rs1, rs2 }
+            | Inst::Sub { rd, rs1, rs2 }
+            | Inst::Subw { rd, rs1, rs2 }
+                if rs1 == rs2 =>
+            {
+                taint.set_reg(rd, Label::EMPTY)
+            }
+            Inst::Add { rd, rs1, rs2 }
+            | Inst::Addw { rd, rs1, rs2 }
+            | Inst::Sub { rd, rs1, rs2 }
+            | Inst::Subw { rd, rs1, rs2 }
+            | Inst::Div { rd, rs1, rs2 }
+            | Inst::Divw { rd, rs1, rs2 }
+            | Inst::Divu { rd, rs1, rs2 }
+            | Inst::Divuw { rd, rs1, rs2 }
+            | Inst::And { rd, rs1, rs2 }
+            | Inst::Or { rd, rs1, rs2 }
+            | Inst::Xor { rd, rs1, rs2 }
+            | Inst::Sll { rd, rs1, rs2 }
+            | Inst::Sllw { rd, rs1, rs2 }
+            | Inst::Srl { rd, rs1, rs2 }
+            | Inst::Srlw { rd, rs1, rs2 }
+            | Inst::Sra { rd, rs1, rs2 }
+            | Inst::Sraw { rd, rs1, rs2 }
+            | Inst::Mul { rd, rs1, rs2 }
+            | Inst::Mulhu { rd, rs1, rs2 }
+            | Inst::Remw { rd, rs1, rs2 }
+            | Inst::Remu { rd, rs1, rs2 }
+            | Inst::Remuw { rd, rs1, rs2 }
+            | Inst::Slt { rd, rs1, rs2 }
+            | Inst::Sltu { rd, rs1, rs2 } => {
+                taint.set_reg(rd, taint.reg_label(rs1) | taint.reg_label(rs2))
+            }
+            Inst::Addi { rd, rs1, .. }
+            | Inst::Addiw { rd, rs1, .. }
+            | Inst::Andi { rd, rs1, .. }
+            | Inst::Ori { rd, rs1, .. }
+            | Inst::Xori { rd, rs1, .. }
+            | Inst::Slti { rd, rs1, .. }
+            | Inst::Sltiu { rd, rs1, .. }
+            | Inst::Slli { rd, rs1, .. }
+            | Inst::Slliw { rd, rs1, .. }
+            | Inst::Srli { rd, rs1, .. }
+            | Inst::Srliw { rd, rs1, .. }
+            | Inst::Srai { rd, rs1, .. }
+            | Inst::Sraiw { rd, rs1, .. } => taint.set_reg(rd, taint.reg_label(rs1)),
+            Inst::Fcvtdlu { rd, rs1, .. } | Inst::Fcvtds { rd, rs1, .. } => {
+                taint.set_reg(rd, taint.freg_label(rs1))
+            }
+            Inst::Fled { rd, rs1, rs2 } => {
+                taint.set_reg(rd, taint.freg_label(rs1) | taint.freg_label(rs2))
+            }
+            Inst::Fdivd { rd, rs1, rs2 } => {
+                taint.f[rd.0 as usize] = taint.freg_label(rs1) | taint.freg_label(rs2)
+            }
+
+            Inst::Jalr { rd, rs1, .. } => {
+                taint.pc = taint.reg_label(rs1);
+                taint.set_reg(rd, Label::EMPTY);
+            }
+            Inst::Lui { rd, .. }
+            | Inst::Auipc { rd, .. }
+            | Inst::Jal { rd, .. }
+            | Inst::Csrrw { rd, .. }
+            | Inst::Csrrs { rd, .. }
+            | Inst::Csrrc { rd, .. }
+            | Inst::Csrrwi { rd, .. }
+            | Inst::Csrrsi { rd, .. }
+            | Inst::Csrrci { rd, .. } => taint.set_reg(rd, Label::EMPTY),
+            // syscalls return values of their own, the ones that read input label it themselves
+            Inst::Ecall => taint.set_reg(A0, Label::EMPTY),
+
+            Inst::Beq { rs1, rs2, .. }
+            | Inst::Bne { rs1, rs2, .. }
+            | Inst::Blt { rs1, rs2, .. }
+            | Inst::Bltu { rs1, rs2, .. }
+            | Inst::Bge { rs1, rs2, .. }
+            | Inst::Bgeu { rs1, rs2, .. } => {
+                let label = taint.reg_label(rs1) | taint.reg_label(rs2);
+                if label.set.is_empty() {
+                    return;
+                }
+
+                let branch = taint.branches.entry(pc).or_insert(TaintedBranch {
+                    pc,
+                    taint: TaintSet::EMPTY,
+                    count: 0,
+                });
+                branch.taint |= label.set;
+                branch.count += 1;
+
+                let (a, b) = (self.x[rs1], self.x[rs2]);
+                let taken = match inst {
+                    Inst::Beq { .. } => a == b,
+                    Inst::Bne { .. } => a != b,
+                    Inst::Blt { .. } => (a as i64) < (b as i64),
+                    Inst::Bge { .. } => (a as i64) >= (b as i64),
+                    Inst::Bltu { .. } => a < b,
+                    _ => a >= b,
+                };
+                self.log_branch_input(taken, label);
+            }
+            Inst::Fence
+            | Inst::FenceI
+            | Inst::Ebreak
+            | Inst::Mret
+            | Inst::Sret
+            | Inst::Wfi
+            | Inst::SfenceVma
+            | Inst::Error(_) => {}
+        }
+    }
+
+    // records whether the access or jump made by `inst` faulted because of a tainted address
+    pub(super) fn record_taint_fault(&mut self, inst: Option<Inst>) {
+        let pc = self.pc;
+        let access = inst
+            .and_then(memory_access)
+            .map(|(base, offset, _, _)| (self.x[base].wrapping_add(offset as u64), base));
+        let Some(ref mut taint) = self.taint else {
+            return;
+        };
+
+        // without an instruction the fetch faulted, after jumping to pc
+        let (addr, set) = match access {
+            Some((addr, base)) => (addr, taint.reg(base)),
+            None if inst.is_none() => (pc, taint.pc.set),
+            None => return,
+        };
+
+        if !set.is_empty() {
+            taint.fault = Some(TaintedFault {
+                pc,
+                addr,
+                taint: set,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::Memory;
+
+    #[test]
+    fn propagation() {
+        let mut emulator = Emulator::new(Memory::from_raw(&[0; 0x100]));
+        emulator.set_taint_sources(TaintSet::parse("stdin, file").unwrap());
+        emulator.taint_input(TaintSource::Stdin, 0x10, 4, 0);
+        emulator.taint_input(TaintSource::Getrandom, 0x14, 4, 0);
+        emulator.x[A1] = 0x10;
+        emulator.x[A2] = 0x40;
+
+        let stdin = TaintSet::EMPTY.with(TaintSource::Stdin);
+        let taint = |emulator: &Emulator| emulator.taint().unwrap().clone();
+
+        emulator.propagate_taint(Inst::Lw {
+            rd: A0,
+            rs1: A1,
+            offset: 0,
+        });
+        emulator.propagate_taint(Inst::Lw {
+            rd: A3,
+            rs1: A1,
+            offset: 4,
+        });
+        assert_eq!(taint(&emulator).reg(A0), stdin);
+        assert_eq!(taint(&emulator).reg(A3), TaintSet::EMPTY);
+
+        emulator.propagate_taint(Inst::Add {
+            rd: A3,
+            rs1: A3,
+            rs2: A0,
+        });
+        emulator.propagate_taint(Inst::Sd {
+            rs1: A2,
+            rs2: A3,
+            offset: 0,
+        });
+        emulator.propagate_taint(Inst::Bne {
+            rs1: A3,
+            rs2: Reg(0),
+            offset: 8,
+        });
+        assert_eq!(taint(&emulator).memory(0x40, 8), stdin);
+        assert_eq!(taint(&emulator).memory(0x48, 8), TaintSet::EMPTY);
+        assert_eq!(taint(&emulator).branches[&0].count, 1);
+
+        emulator.propagate_taint(Inst::Xor {
+            rd: A3,
+            rs1: A3,
+            rs2: A3,
+        });
+        assert_eq!(taint(&emulator).reg(A3), TaintSet::EMPTY);
+
+        emulator.stdout.push_str("ab");
+        emulator.taint_output(0x3e, 4);
+        assert_eq!(taint(&emulator).output(3), TaintSet::EMPTY);
+        assert_eq!(taint(&emulator).output(4), stdin);
+        assert_eq!(taint(&emulator).output(5), stdin);
+        assert_eq!(taint(&emulator).output(6), TaintSet::EMPTY);
+    }
+
+    #[test]
+    fn branch_input_log() {
+        // a writer the test can still read from once the emulator owns it
+        #[derive(Clone, Default)]
+        struct SharedLog(Arc<Lock<alloc::string::String>>);
+
+        impl Write for SharedLog {
+            fn write_str(&mut self, s: &str) -> core::fmt::Result {
+                self.0.lock().write_str(s)
+            }
+        }
+
+        let log = SharedLog::default();
+        let mut emulator = Emulator::new(Memory::from_raw(&[0; 0x100]));
+        emulator.enable_branch_input_log(log.clone());
+        emulator.taint_input(TaintSource::Stdin, 0x10, 8, 0);
+        emulator.x[A1] = 0x10;
+        emulator.x[A2] = 5;
+
+        // byte 2 and bytes 4 through 7 are combined, the range covers everything between
+        emulator.propagate_taint(Inst::Lbu {
+            rd: A0,
+            rs1: A1,
+            offset: 2,
+        });
+        emulator.propagate_taint(Inst::Lw {
+            rd: A3,
+            rs1: A1,
+            offset: 4,
+        });
+        emulator.propagate_taint(Inst::Or {
+            rd: A0,
+            rs1: A0,
+            rs2: A3,
+        });
+        emulator.pc = 0x30;
+        emulator.propagate_taint(Inst::Bltu {
+            rs1: A0,
+            rs2: A2,
+            offset: 8,
+        });
+        // an untainted branch isn't logged
+        emulator.propagate_taint(Inst::Beq {
+            rs1: A2,
+            rs2: A2,
+            offset: 8,
+        });
+
+        assert_eq!(
+            *log.0.lock(),
+            "{\"inst\":0,\"pc\":48,\"taken\":true,\"stdin\":[2,8]}\n"
+        );
+    }
+}
@@ -1,6 +1,13 @@
-use std::collections::HashMap;
+use alloc::{boxed::Box, collections::BTreeMap, string::ToString};
+use core::mem;
 
-use crate::system::Emulator;
+use crate::{
+    error::RVError,
+    instruction::Inst,
+    memory::{Memory, MemoryLayout},
+    register::{Reg, RA, SP},
+    system::{Emulator, Resume, Segfault, StopReason},
+};
 
 // number of instructions
 const B_STATE_INTERVAL: u64 = 10000;
@@ -8,81 +15,369 @@ const B_STATE_LIMIT: usize = 250;
 
 pub struct TimeTravel {
     pub current: Emulator,
-    history: HashMap<u64, Emulator>,
+    history: BTreeMap<u64, Emulator>,
     smallest_b_state: u64,
+    // the last segfault, until it's taken
+    fault: Option<Box<Segfault>>,
+    // see `post_mortem`
+    frozen: bool,
 }
 
 impl TimeTravel {
-    pub fn new(emulator: Emulator) -> TimeTravel {
-        let mut history = HashMap::default();
+    pub fn new(mut emulator: Emulator) -> TimeTravel {
+        emulator.memory.clear_dirty_pages();
+        let mut history = BTreeMap::default();
         history.insert(0, emulator.clone());
 
         TimeTravel {
             current: emulator.clone(),
             history,
             smallest_b_state: 0,
+            fault: None,
+            frozen: false,
         }
     }
 
+    /// Looks at an emulator that can't be run, like one restored from a
+    /// [`CoreDump`](crate::system::CoreDump). Stepping and running do nothing.
+    pub fn post_mortem(emulator: Emulator) -> TimeTravel {
+        TimeTravel {
+            frozen: true,
+            ..TimeTravel::new(emulator)
+        }
+    }
+
+    pub fn is_post_mortem(&self) -> bool {
+        self.frozen
+    }
+
     pub fn step(&mut self, amount: i32) -> Option<u64> {
+        if self.frozen {
+            return None;
+        }
+
         if amount >= 0 {
-            for _ in 0..amount {
+            if amount == 0 {
+                return None;
+            }
+
+            let mut left = amount;
+            return self.run_until(|_, _| {
+                left -= 1;
+                left == 0
+            });
+        } else {
+            // find closest one
+            let new_inst_count = self.current.inst_counter as i64 + amount as i64;
+            if new_inst_count < 0 {
+                return None;
+            }
+
+            let i = new_inst_count as u64 / B_STATE_INTERVAL;
+            let r = new_inst_count as u64 % B_STATE_INTERVAL;
+
+            if !self.history.contains_key(&i) {
+                self.restore_checkpoint(self.smallest_b_state);
+                return None;
+            }
+
+            self.restore_checkpoint(i);
+            for _ in 0..r {
+                // guaranteed to not return
                 match self.current.fetch_and_execute() {
                     Ok(Some(exit_code)) => return Some(exit_code),
                     Ok(None) => {}
                     Err(e) => {
-                        self.current.stderr.push_str(&e.to_string());
+                        report(&mut self.current, &mut self.fault, e);
                         return None;
                     }
                 }
+            }
+        }
+
+        None
+    }
 
-                let i = self.current.inst_counter / B_STATE_INTERVAL;
-                let r = self.current.inst_counter % B_STATE_INTERVAL;
+    /// Steps a single instruction, but runs calls until they return to the instruction after
+    /// them. Returns the exit code if the program exited.
+    pub fn step_over(&mut self) -> Option<u64> {
+        let Some((inst, len)) = self.fetch() else {
+            return self.step(1);
+        };
 
-                // only add if greater than current latest timestamp
-                if i >= self.history.len() as u64 && r == 0 {
-                    self.history.insert(i, self.current.clone());
+        if !is_call(inst) {
+            return self.step(1);
+        }
 
-                    if self.history.len() > B_STATE_LIMIT {
-                        assert!(self.history.remove(&self.smallest_b_state).is_some());
-                        self.smallest_b_state += 1;
-                    }
-                }
+        // the stack pointer tells apart a recursive call reaching the same address
+        let return_addr = self.current.pc + len as u64;
+        let sp = self.current.reg(SP);
+
+        self.run_until(|emulator, _| emulator.pc == return_addr && emulator.reg(SP) >= sp)
+    }
 
-                debug_assert!(self.history.len() <= B_STATE_LIMIT);
+    /// Runs until the current function returns to its caller. Returns the exit code if the program
+    /// exited.
+    pub fn finish(&mut self) -> Option<u64> {
+        // calls made from the current function, which have to return before it does
+        let mut depth = 0u64;
+
+        self.run_until(|_, inst| match inst {
+            Some(inst) if is_call(inst) => {
+                depth += 1;
+                false
             }
-        } else {
-            // find closest one
-            let new_inst_count = self.current.inst_counter as i64 + amount as i64;
-            if new_inst_count < 0 {
-                return None;
+            Some(inst) if is_return(inst) => {
+                if depth == 0 {
+                    return true;
+                }
+
+                depth -= 1;
+                false
             }
+            _ => false,
+        })
+    }
 
-            let i = new_inst_count as u64 / B_STATE_INTERVAL;
-            let r = new_inst_count as u64 % B_STATE_INTERVAL;
+    /// Moves to the point after `inst_count` instructions have been executed, replaying from the
+    /// closest checkpoint before it when going backwards. Returns the exit code if the program
+    /// exits first.
+    pub fn goto(&mut self, inst_count: u64) -> Option<u64> {
+        if self.frozen {
+            return None;
+        }
+
+        if inst_count < self.current.inst_counter {
+            // checkpoints are only kept for a while, going further back starts at the oldest
+            let checkpoint = self
+                .history
+                .range(..=inst_count / B_STATE_INTERVAL)
+                .next_back()
+                .map_or(self.smallest_b_state, |(&i, _)| i);
 
-            match self.history.get(&i) {
-                Some(new_current) => {
-                    self.current = new_current.clone();
-
-                    for _ in 0..r {
-                        // guaranteed to not return
-                        match self.current.fetch_and_execute() {
-                            Ok(Some(exit_code)) => return Some(exit_code),
-                            Ok(None) => {}
-                            Err(e) => {
-                                self.current.stderr.push_str(&e.to_string());
-                                return None;
-                            }
-                        }
+            self.restore_checkpoint(checkpoint);
+        }
+
+        if self.current.inst_counter >= inst_count {
+            return None;
+        }
+
+        self.run_until(|emulator, _| emulator.inst_counter >= inst_count)
+    }
+
+    /// Runs until the program exits, returning its exit code, or a step fails.
+    pub fn run(&mut self) -> Option<u64> {
+        self.run_until(|_, _| false)
+    }
+
+    /// Runs until the pc reaches `addr`. Returns the exit code if the program exited.
+    pub fn run_to(&mut self, addr: u64) -> Option<u64> {
+        self.run_until(|emulator, _| emulator.pc == addr)
+    }
+
+    /// Runs until the pc enters a loaded object whose name starts with `name`, like `libm` for
+    /// the next call into libm.so.6. Returns the exit code if the program exited.
+    pub fn run_to_object(&mut self, name: &str) -> Option<u64> {
+        let in_object = |emulator: &Emulator| {
+            emulator
+                .memory
+                .object_at(emulator.pc)
+                .is_some_and(|object| object.name.starts_with(name))
+        };
+
+        let mut inside = in_object(&self.current);
+        self.run_until(|emulator, _| {
+            let was_inside = inside;
+            inside = in_object(emulator);
+            inside && !was_inside
+        })
+    }
+
+    /// Runs until a syscall has been made. Returns the exit code if the program exited.
+    pub fn run_to_syscall(&mut self) -> Option<u64> {
+        self.run_until(|_, inst| inst == Some(Inst::Ecall))
+    }
+
+    // steps until `done`, which is given the emulator after each step and the instruction about to
+    // be executed, returns true. Stops early if a step fails to make progress.
+    fn run_until(&mut self, mut done: impl FnMut(&Emulator, Option<Inst>) -> bool) -> Option<u64> {
+        let TimeTravel {
+            current,
+            history,
+            smallest_b_state,
+            fault,
+            frozen,
+        } = self;
+        if *frozen {
+            return None;
+        }
+
+        let mut inst = None;
+        let mut inst_counter = current.inst_counter;
+
+        current.run_controlled(&mut |emulator: &mut Emulator, reason| {
+            match reason {
+                StopReason::Step if emulator.inst_counter == inst_counter => return Resume::Detach,
+                StopReason::Step => {
+                    record_checkpoint(history, smallest_b_state, emulator);
+
+                    if done(emulator, inst) {
+                        return Resume::Detach;
                     }
                 }
-                None => {
-                    self.current = self.history[&self.smallest_b_state].clone();
+                StopReason::Signal(e) => {
+                    report(emulator, fault, e);
+                    return Resume::Detach;
                 }
+                _ => {}
             }
+
+            inst = emulator.fetch().ok().map(|(inst, _)| inst);
+            inst_counter = emulator.inst_counter;
+            Resume::Step
+        })
+    }
+
+    /// The segfault the last step or run stopped at, if it hasn't been taken yet
+    pub fn take_fault(&mut self) -> Option<Box<Segfault>> {
+        self.fault.take()
+    }
+
+    fn fetch(&mut self) -> Option<(Inst, u8)> {
+        self.current.fetch().ok()
+    }
+
+    // goes back to checkpoint `i`. Memory is restored in place, so only the pages written since
+    // are copied when it's the latest checkpoint.
+    fn restore_checkpoint(&mut self, i: u64) {
+        let Some(checkpoint) = self.history.get_mut(&i) else {
+            return;
+        };
+
+        let placeholder = || Memory::new(MemoryLayout::Paged);
+        let mut memory = mem::replace(&mut self.current.memory, placeholder());
+        memory.restore(&checkpoint.memory);
+
+        // the rest of the checkpoint is cloned without its memory
+        let checkpoint_memory = mem::replace(&mut checkpoint.memory, placeholder());
+        self.current = checkpoint.clone();
+        checkpoint.memory = checkpoint_memory;
+        self.current.memory = memory;
+    }
+}
+
+// writes why a step failed to the guest's stderr, keeping a segfault to be taken
+fn report(emulator: &mut Emulator, fault: &mut Option<Box<Segfault>>, e: RVError) {
+    match e {
+        RVError::Segfault(segfault) => {
+            emulator.stderr.push_str(&segfault.to_string());
+            *fault = Some(segfault);
         }
+        e => emulator.stderr.push_str(&e.to_string()),
+    }
+}
 
-        None
+// keeps a copy of the emulator every B_STATE_INTERVAL instructions, dropping the oldest once there
+// are B_STATE_LIMIT of them
+fn record_checkpoint(
+    history: &mut BTreeMap<u64, Emulator>,
+    smallest_b_state: &mut u64,
+    emulator: &mut Emulator,
+) {
+    let i = emulator.inst_counter / B_STATE_INTERVAL;
+    let r = emulator.inst_counter % B_STATE_INTERVAL;
+
+    // only add if greater than current latest timestamp
+    if i >= history.len() as u64 && r == 0 {
+        emulator.memory.clear_dirty_pages();
+        history.insert(i, emulator.clone());
+
+        if history.len() > B_STATE_LIMIT {
+            assert!(history.remove(smallest_b_state).is_some());
+            *smallest_b_state += 1;
+        }
+    }
+
+    debug_assert!(history.len() <= B_STATE_LIMIT);
+}
+
+fn is_call(inst: Inst) -> bool {
+    matches!(inst, Inst::Jal { rd: RA, .. } | Inst::Jalr { rd: RA, .. })
+}
+
+fn is_return(inst: Inst) -> bool {
+    matches!(
+        inst,
+        Inst::Jalr {
+            rd: Reg(0),
+            rs1: RA,
+            offset: 0
+        }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{memory::Memory, register::A1};
+
+    fn program() -> Memory {
+        let mut data = [0u8; 24];
+        data[0..4].copy_from_slice(&0x00c000efu32.to_le_bytes()); // jal ra, 12
+        data[4..8].copy_from_slice(&0x00150513u32.to_le_bytes()); // addi a0, a0, 1
+        data[12..16].copy_from_slice(&0x00158593u32.to_le_bytes()); // addi a1, a1, 1
+        data[16..20].copy_from_slice(&0x00158593u32.to_le_bytes()); // addi a1, a1, 1
+        data[20..24].copy_from_slice(&0x00008067u32.to_le_bytes()); // ret
+
+        Memory::from_raw(&data)
+    }
+
+    #[test]
+    fn step_over_finish_and_run_to() {
+        let mut time_travel = TimeTravel::new(Emulator::new(program()));
+        time_travel.step_over();
+        assert_eq!(time_travel.current.pc, 4);
+        assert_eq!(time_travel.current.reg(A1), 2);
+
+        // stepping over anything but a call is a single step
+        time_travel.step_over();
+        assert_eq!(time_travel.current.pc, 8);
+
+        let mut time_travel = TimeTravel::new(Emulator::new(program()));
+        time_travel.step(2);
+        assert_eq!(time_travel.current.pc, 16);
+        time_travel.finish();
+        assert_eq!(time_travel.current.pc, 4);
+        assert_eq!(time_travel.current.reg(A1), 2);
+
+        let mut time_travel = TimeTravel::new(Emulator::new(program()));
+        time_travel.run_to(20);
+        assert_eq!(time_travel.current.pc, 20);
+        assert_eq!(time_travel.current.reg(A1), 2);
+    }
+
+    #[test]
+    fn goto() {
+        let mut time_travel = TimeTravel::new(Emulator::new(program()));
+        time_travel.goto(3);
+        assert_eq!(time_travel.current.pc, 20);
+        assert_eq!(time_travel.current.reg(A1), 2);
+
+        time_travel.goto(1);
+        assert_eq!(time_travel.current.inst_counter, 1);
+        assert_eq!(time_travel.current.pc, 12);
+        assert_eq!(time_travel.current.reg(A1), 0);
+    }
+
+    #[test]
+    fn run_to_syscall() {
+        let mut data = [0u8; 12];
+        data[0..4].copy_from_slice(&0x0ac00893u32.to_le_bytes()); // li a7, 172
+        data[4..8].copy_from_slice(&0x00000073u32.to_le_bytes()); // ecall
+        data[8..12].copy_from_slice(&0x00000013u32.to_le_bytes()); // nop
+
+        let mut time_travel = TimeTravel::new(Emulator::new(Memory::from_raw(&data)));
+        assert_eq!(time_travel.run_to_syscall(), None);
+        assert_eq!(time_travel.current.pc, 8);
     }
 }
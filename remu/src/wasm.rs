@@ -0,0 +1,95 @@
+// wasm-bindgen wrapper around the interpreter, so the emulator can be driven from javascript
+//
+// build with:
+// cargo build -p remu --target wasm32-unknown-unknown --no-default-features --features wasm
+
+use elf::{endian::AnyEndian, ElfBytes};
+use wasm_bindgen::prelude::*;
+
+use crate::{error::RVError, memory::Memory, system::Emulator};
+
+#[wasm_bindgen]
+pub struct WasmEmulator {
+    emulator: Emulator,
+}
+
+#[wasm_bindgen]
+impl WasmEmulator {
+    /// parses and loads a 64-bit RISC-V linux executable
+    #[wasm_bindgen(constructor)]
+    pub fn new(elf_data: &[u8]) -> Result<WasmEmulator, JsError> {
+        let file = ElfBytes::<AnyEndian>::minimal_parse(elf_data)?;
+
+        match (file.ehdr.class, file.ehdr.e_type, file.ehdr.e_machine) {
+            // (64 bit, executable, risc_v arch)
+            (elf::file::Class::ELF64, 0x03 | 0x02, 0xF3) => {}
+            _ => return Err(RVError::InvalidFileType.into()),
+        }
+
+        let memory = Memory::load_elf(file);
+
+        Ok(WasmEmulator {
+            emulator: Emulator::new(memory),
+        })
+    }
+
+    // javascript sets these after construction, so they can't go through the builder
+    #[allow(deprecated)]
+    pub fn set_stdin(&mut self, data: &[u8]) {
+        self.emulator.set_stdin(data);
+    }
+
+    #[allow(deprecated)]
+    pub fn profile_label(&mut self, label: &str) -> Result<(), JsError> {
+        Ok(self.emulator.profile_label(label)?)
+    }
+
+    /// executes up to `count` instructions, returning the exit code if the program exited
+    pub fn step(&mut self, count: u32) -> Result<Option<u64>, JsError> {
+        for _ in 0..count {
+            if let Some(exit_code) = self.emulator.fetch_and_execute()? {
+                return Ok(Some(exit_code));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// runs the program to completion, returning the exit code
+    pub fn run(&mut self) -> Result<u64, JsError> {
+        Ok(self.emulator.run(false)?)
+    }
+
+    pub fn pc(&self) -> u64 {
+        self.emulator.pc
+    }
+
+    pub fn inst_counter(&self) -> u64 {
+        self.emulator.inst_counter
+    }
+
+    pub fn cycle_count(&self) -> u64 {
+        self.emulator.profiler.cycle_count
+    }
+
+    pub fn stdout(&self) -> String {
+        self.emulator.stdout.clone()
+    }
+
+    pub fn stderr(&self) -> String {
+        self.emulator.stderr.clone()
+    }
+
+    pub fn registers(&self) -> String {
+        self.emulator.print_registers()
+    }
+
+    /// disassembles ~n instructions around the current pc
+    pub fn disassemble(&self, n: u64) -> String {
+        self.emulator.memory.disassembler.disassemble_pc_relative(
+            &self.emulator.memory,
+            self.emulator.pc,
+            n,
+        )
+    }
+}